@@ -0,0 +1,148 @@
+//! The integration step and its parts, at several sizes: segments stepping
+//! their membranes, channels gating, junctions coupling neighbouring
+//! segments, synapses stepping, and whole frames of the ECS schedule over
+//! scenes of one to sixteen sample neurons.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use nb_sim::constants::{AXIAL_RESISTIVITY_OHM_CM, BODY_TEMPERATURE};
+use nb_sim::dimension::{Diameter, Interval, MilliVolts};
+use nb_sim::headless::HeadlessSimulation;
+use nb_sim::integrations::grace;
+use nb_sim::neuron::channel::common_channels::{giant_squid, rat_ca1, rat_thalamocortical};
+use nb_sim::neuron::channel::ChannelBuilder;
+use nb_sim::neuron::geometry::axial_conductance;
+use nb_sim::neuron::junction_charge;
+use nb_sim::neuron::segment::{examples, Geometry};
+use nb_sim::neuron::solution::{EXAMPLE_CYTOPLASM, INTERSTICIAL_FLUID};
+use nb_sim::neuron::synapse::examples::excitatory_synapse;
+use nb_sim::serialize;
+
+const STEP: Interval = Interval(1e-5);
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+fn segment_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("segment_step");
+    for n in SIZES {
+        let mut segments = vec![examples::giant_squid_axon(); n];
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| {
+                for segment in segments.iter_mut() {
+                    segment.step(&BODY_TEMPERATURE, &INTERSTICIAL_FLUID, &STEP);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn channel_gating(c: &mut Criterion) {
+    let mut group = c.benchmark_group("channel_gating");
+    let channels: [(&str, ChannelBuilder); 4] = [
+        ("squid_na", giant_squid::NA_CHANNEL),
+        ("squid_k", giant_squid::K_CHANNEL),
+        ("thalamocortical_na_transient", rat_thalamocortical::NA_TRANSIENT),
+        ("ca1_hcn_dendrite", rat_ca1::HCN_CHANNEL_DENDRITE),
+    ];
+    for (name, builder) in channels {
+        let n = 1_000;
+        let mut channels: Vec<_> = (0..n).map(|_| builder.clone().build(&MilliVolts(-70.0))).collect();
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for (i, channel) in channels.iter_mut().enumerate() {
+                    // Spread the voltages, so that gates move.
+                    channel.step(&MilliVolts(-80.0 + (i % 100) as f32), &STEP);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// An unbranched cable of `n` 2 µm by 10 µm compartments, coupled as
+/// `step_biophysics` couples neighbouring segments.
+fn junction_coupling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("junction_coupling");
+    let capacitance_per_square_cm = 1e-6;
+    for n in SIZES {
+        let geometries = vec![Geometry::cylinder(Diameter(2e-4), 10e-4); n];
+        let mut voltages: Vec<f32> = (0..n).map(|i| -70.0 + (i % 10) as f32).collect();
+        group.throughput(Throughput::Elements(n as u64 - 1));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| {
+                for i in 1..n {
+                    let (first, second) = (&geometries[i - 1], &geometries[i]);
+                    let capacitance1 = capacitance_per_square_cm * first.surface_area();
+                    let capacitance2 = capacitance_per_square_cm * second.surface_area();
+                    let conductance = axial_conductance(first, second, AXIAL_RESISTIVITY_OHM_CM);
+                    let charge = junction_charge(voltages[i - 1] - voltages[i], conductance, capacitance1, capacitance2, STEP.0);
+                    voltages[i - 1] -= charge / capacitance1;
+                    voltages[i] += charge / capacitance2;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn synapse_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("synapse_step");
+    for n in [10, 100, 1_000] {
+        let mut synapses = vec![excitatory_synapse(&MilliVolts(-70.0)); n];
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| {
+                for (i, synapse) in synapses.iter_mut().enumerate() {
+                    // Half the presynaptic segments are firing.
+                    let presynaptic = MilliVolts(if i % 2 == 0 { 20.0 } else { -70.0 });
+                    let mut postsynaptic = MilliVolts(-70.0);
+                    synapse.step(&BODY_TEMPERATURE, &presynaptic, &postsynaptic, &STEP);
+                    synapse.apply_current(&STEP, &BODY_TEMPERATURE, &mut postsynaptic, &EXAMPLE_CYTOPLASM);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// `neurons` copies of the sample neuron, side by side.
+fn sample_scene(neurons: usize) -> serialize::Scene {
+    let neuron = grace::sample::neuron();
+    serialize::Scene {
+        neurons: (0..neurons)
+            .map(|i| serialize::SceneNeuron {
+                neuron: neuron.clone(),
+                location: serialize::Location { x_mm: i as f32, y_mm: 0.0, z_mm: 0.0 },
+                stimulator_segments: vec![],
+                tag: None,
+                thermal_segments: vec![],
+                noise: None,
+                membrane_overrides: vec![],
+            })
+            .collect(),
+        synapses: vec![],
+        constants: None,
+        lights: vec![],
+        projectors: vec![],
+        seed: None,
+    }
+}
+
+/// One frame of ten steps of the whole schedule.
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    group.sample_size(20);
+    for neurons in [1, 4, 16] {
+        let scene = sample_scene(neurons);
+        let segments: usize = scene.neurons.iter().map(|n| n.neuron.segments.len()).sum();
+        let mut simulation = HeadlessSimulation::new(scene, STEP.0, 10).expect("sample scene should load");
+        group.throughput(Throughput::Elements(segments as u64));
+        group.bench_with_input(BenchmarkId::new("segments", segments), &segments, |b, _| b.iter(|| simulation.step()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, segment_step, channel_gating, junction_coupling, synapse_step, frame);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+//! The cost of recoloring segments per frame, when their voltages stay
+//! within their color buckets and when every one of them changes bucket.
+
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use nb_sim::dimension::{MilliVolts, SimulationStepSeconds, StepsPerFrame};
+use nb_sim::instancing::{SegmentBatch, SegmentInstances};
+use nb_sim::lod::SegmentShape;
+use nb_sim::neuron::membrane::MembraneVoltage;
+use nb_sim::neuron::voltage_material::{apply_voltage_to_materials, ColorBucket, MembraneMaterials, SpikeFlash};
+
+fn scene(segments: usize) -> (World, Schedule) {
+    let mut world = World::new();
+    world.insert_resource(MembraneMaterials::default());
+    world.insert_resource(Time::<()>::default());
+    world.insert_resource(SimulationStepSeconds(1e-5));
+    world.insert_resource(StepsPerFrame(10));
+    for batch in SegmentBatch::ALL {
+        world.spawn((batch, SegmentInstances::default()));
+    }
+    for i in 0..segments {
+        let v = MilliVolts(-70.0);
+        world.spawn((
+            GlobalTransform::from_translation(Vec3::new(i as f32, 0.0, 0.0)),
+            SegmentShape::cylinder(1.0, 10.0),
+            MembraneVoltage(v.clone()),
+            SpikeFlash { last_voltage: v, intensity: 0.0 },
+            ColorBucket::default(),
+        ));
+    }
+    let mut schedule = Schedule::default();
+    schedule.add_systems(apply_voltage_to_materials);
+    // The first run writes every instance.
+    schedule.run(&mut world);
+    (world, schedule)
+}
+
+/// Move every voltage by `dv` one frame and back the next.
+fn run_frames(world: &mut World, schedule: &mut Schedule, dv: f32, frame: &mut u64) {
+    let sign = if *frame % 2 == 0 { 1.0 } else { -1.0 };
+    *frame += 1;
+    let mut voltages = world.query::<&mut MembraneVoltage>();
+    for mut v in voltages.iter_mut(world) {
+        v.0 .0 += sign * dv;
+    }
+    schedule.run(world);
+}
+
+fn recolor(c: &mut Criterion) {
+    let mut group = c.benchmark_group("recolor_segments");
+    for segments in [1_000, 10_000, 100_000] {
+        // A bucket is 130 mV / 255, about 0.5 mV, wide.
+        for (name, dv) in [("within_bucket", 0.01), ("every_bucket_changes", 20.0)] {
+            let (mut world, mut schedule) = scene(segments);
+            let mut frame = 0;
+            group.bench_with_input(BenchmarkId::new(name, segments), &dv, |b, &dv| {
+                b.iter(|| run_frames(&mut world, &mut schedule, dv, &mut frame))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, recolor);
+criterion_main!(benches);
@@ -4,5 +4,16 @@ use vergen::EmitBuilder;
 fn main() -> Result<(), Box<dyn Error>> {
     // Emit the instructions
     EmitBuilder::builder().all_git().emit()?;
+
+    // The gRPC server is native only, like its dependencies.
+    #[cfg(feature = "grpc")]
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() != Ok("wasm32") {
+        tonic_build::configure()
+            .build_client(false)
+            // `At` holds a command, which may be another `At`.
+            .boxed(".nb_sim.v1.Command.command.at")
+            .boxed(".nb_sim.v1.At.command")
+            .compile_protos(&["proto/nb_sim.proto"], &["proto"])?;
+    }
     Ok(())
 }
@@ -0,0 +1,52 @@
+//! The actions keyboard shortcuts can be bound to, by the names the config
+//! file's `[shortcuts]` table uses; see `gui::palette`. Kept apart from the
+//! GUI so that the config parses the same in builds without it.
+
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+pub enum Action {
+    OpenPalette,
+    TogglePause,
+    StepFrame,
+    Reset,
+    LoadScene,
+    AddStimulator,
+    FocusSelection,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::OpenPalette,
+        Action::TogglePause,
+        Action::StepFrame,
+        Action::Reset,
+        Action::LoadScene,
+        Action::AddStimulator,
+        Action::FocusSelection,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::OpenPalette => "Open the command palette",
+            Action::TogglePause => "Pause or resume",
+            Action::StepFrame => "Step one frame",
+            Action::Reset => "Reset the scene",
+            Action::LoadScene => "Load scene",
+            Action::AddStimulator => "Add the current stimulator to the selection",
+            Action::FocusSelection => "Frame the selection",
+        }
+    }
+
+    pub fn default_shortcut(&self) -> Option<&'static str> {
+        match self {
+            Action::OpenPalette => Some("Ctrl+K"),
+            Action::TogglePause => Some("Space"),
+            Action::StepFrame => Some("Period"),
+            Action::Reset => Some("Ctrl+R"),
+            Action::LoadScene => Some("Ctrl+O"),
+            Action::AddStimulator => Some("S"),
+            Action::FocusSelection => Some("F"),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::dimension::MilliVolts;
+
+/// Membrane potential above which an upward crossing counts as a spike.
+pub const SPIKE_THRESHOLD: MilliVolts = MilliVolts(0.0);
+
+/// A recorded time series. Times are in simulation seconds, values in
+/// whatever unit the source produces (usually millivolts).
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub label: String,
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+/// Summary statistics of a single trace, used to compare runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceMetrics {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub spike_count: usize,
+}
+
+impl Trace {
+    pub fn new(label: String) -> Self {
+        Trace { label, times: vec![], values: vec![] }
+    }
+
+    pub fn push(&mut self, t: f32, v: f32) {
+        self.times.push(t);
+        self.values.push(v);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Times of the upward crossings of `threshold`.
+    pub fn spike_times(&self, threshold: &MilliVolts) -> Vec<f32> {
+        self.values
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] < threshold.0 && w[1] >= threshold.0)
+            .map(|(i, _)| self.times[i + 1])
+            .collect()
+    }
+
+    pub fn metrics(&self) -> Option<TraceMetrics> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let min = self.values.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = self.values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let mean = self.values.iter().sum::<f32>() / self.values.len() as f32;
+        Some(TraceMetrics {
+            min,
+            max,
+            mean,
+            spike_count: self.spike_times(&SPIKE_THRESHOLD).len(),
+        })
+    }
+
+    /// The value at `t`, interpolated linearly between samples, or `None`
+    /// outside the recorded times.
+    pub fn value_at(&self, t: f32) -> Option<f32> {
+        let i = self.times.partition_point(|sample| *sample < t);
+        match (i.checked_sub(1), self.times.get(i)) {
+            (_, Some(after)) if *after == t => Some(self.values[i]),
+            (Some(before), Some(after)) => {
+                let fraction = (t - self.times[before]) / (after - self.times[before]);
+                Some(self.values[before] + fraction * (self.values[i] - self.values[before]))
+            },
+            _ => None,
+        }
+    }
+
+    /// The one-sided power spectral density, as (frequency (Hz), power
+    /// (unit^2/Hz)) pairs from DC up to the Nyquist frequency. Samples are
+    /// taken to be evenly spaced; the mean is removed and a Hann window
+    /// applied, and the trace is zero-padded to a power of two.
+    pub fn power_spectrum(&self) -> Vec<(f32, f32)> {
+        if self.len() < 2 {
+            return vec![];
+        }
+        let interval = ((self.times[self.len() - 1] - self.times[0]) / (self.len() - 1) as f32) as f64;
+        let mean = self.values.iter().map(|v| *v as f64).sum::<f64>() / self.len() as f64;
+        let hann = |i: usize| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (self.len() - 1) as f64).cos();
+        let n = self.len().next_power_of_two();
+        let mut re = vec![0.0; n];
+        let mut im = vec![0.0; n];
+        for (i, v) in self.values.iter().enumerate() {
+            re[i] = (*v as f64 - mean) * hann(i);
+        }
+        fft(&mut re, &mut im);
+        let window_power = (0..self.len()).map(|i| hann(i).powi(2)).sum::<f64>();
+        (0..=n / 2)
+            .map(|k| {
+                let one_sided = if k == 0 || k == n / 2 { 1.0 } else { 2.0 };
+                let power = one_sided * (re[k].powi(2) + im[k].powi(2)) * interval / window_power;
+                ((k as f64 / (n as f64 * interval)) as f32, power as f32)
+            })
+            .collect()
+    }
+
+    /// Each value paired with its rate of change (unit/ms), by central
+    /// differences, for plotting a phase plane. The first and last samples
+    /// are left out.
+    pub fn phase_plane(&self) -> Vec<(f32, f32)> {
+        (1..self.len().saturating_sub(1))
+            .map(|i| {
+                let dt_ms = (self.times[i + 1] - self.times[i - 1]) * 1000.0;
+                (self.values[i], (self.values[i + 1] - self.values[i - 1]) / dt_ms)
+            })
+            .collect()
+    }
+
+    pub fn points(&self) -> Vec<[f64; 2]> {
+        self.times
+            .iter()
+            .zip(self.values.iter())
+            .map(|(t, v)| [*t as f64, *v as f64])
+            .collect()
+    }
+}
+
+/// In-place radix-2 FFT; the length must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f64).cos(), (angle * k as f64).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spiking_trace() -> Trace {
+        let mut trace = Trace::new("test".to_string());
+        for (i, v) in [-70.0, -60.0, 20.0, -80.0, -70.0, 10.0, 30.0, -70.0].iter().enumerate() {
+            trace.push(i as f32 * 0.001, *v);
+        }
+        trace
+    }
+
+    #[test]
+    fn counts_upward_threshold_crossings() {
+        let trace = spiking_trace();
+        assert_eq!(
+            trace.spike_times(&SPIKE_THRESHOLD),
+            vec![trace.times[2], trace.times[5]]
+        );
+    }
+
+    #[test]
+    fn metrics_summarize_trace() {
+        let metrics = spiking_trace().metrics().expect("non-empty trace");
+        assert_eq!(metrics.min, -80.0);
+        assert_eq!(metrics.max, 30.0);
+        assert_eq!(metrics.spike_count, 2);
+        assert!((metrics.mean - -36.25).abs() < 1e-4);
+        assert!(Trace::new("empty".to_string()).metrics().is_none());
+    }
+
+    #[test]
+    fn interpolates_between_samples() {
+        let trace = spiking_trace();
+        assert_eq!(trace.value_at(0.0), Some(-70.0));
+        assert!((trace.value_at(0.0015).unwrap() - -20.0).abs() < 1e-3);
+        assert_eq!(trace.value_at(-0.001), None);
+        assert_eq!(trace.value_at(1.0), None);
+    }
+
+    #[test]
+    fn phase_plane_pairs_values_with_their_slope() {
+        let mut trace = Trace::new("ramp".to_string());
+        for i in 0..5 {
+            trace.push(i as f32 * 0.001, -70.0 + 2.0 * (i * i) as f32);
+        }
+        let phase_plane = trace.phase_plane();
+        assert_eq!(phase_plane.iter().map(|(v, _)| *v).collect::<Vec<_>>(), vec![-68.0, -62.0, -52.0]);
+        for ((_, slope), expected) in phase_plane.iter().zip([4.0, 8.0, 12.0]) {
+            assert!((slope - expected).abs() < 1e-3, "{} mV/ms, expected {}", slope, expected);
+        }
+    }
+
+    #[test]
+    fn power_spectrum_peaks_at_the_oscillation() {
+        let mut trace = Trace::new("gamma".to_string());
+        for i in 0..2000 {
+            let t = i as f32 * 1e-4;
+            trace.push(t, -65.0 + 2.0 * (2.0 * std::f32::consts::PI * 50.0 * t).sin());
+        }
+        let spectrum = trace.power_spectrum();
+        let (peak, _) = spectrum.iter().cloned().fold((0.0, 0.0), |best, s| if s.1 > best.1 { s } else { best });
+        let resolution = spectrum[1].0;
+        assert!((peak - 50.0).abs() <= resolution, "peak at {} Hz", peak);
+        assert!((spectrum.last().unwrap().0 - 5000.0).abs() < 1.0);
+    }
+}
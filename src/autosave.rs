@@ -0,0 +1,267 @@
+//! Periodic autosave of the scene, and restoring the last session.
+//!
+//! Snapshots rotate through a fixed number of slots, so the last few are
+//! kept and a save interrupted partway never clobbers every copy. Natively
+//! the slots are files in `autosave/`; on the web they are `localStorage`
+//! entries. Each session carries on from the slot after the newest
+//! snapshot, so its first save overwrites the oldest.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::clock::{iso8601, unix_seconds_now};
+use crate::constants::SimulationConstants;
+use crate::dimension::{Interval, Timestamp};
+use crate::integrations::grace::{GraceScene, GraceSceneSender, SceneEntities};
+use crate::plugin::SimulationState;
+use crate::serialize;
+
+/// The most slots the GUI offers, and so the most read at startup.
+pub const MAX_SLOTS: usize = 10;
+
+/// Filled in from storage by `load_autosave` at startup.
+#[derive(Resource)]
+pub struct Autosave {
+    pub enabled: bool,
+    /// Wall-clock time between saves.
+    pub interval: Interval,
+    /// Number of rolling snapshots to keep.
+    pub slots: usize,
+    pub error: Option<String>,
+    /// The slot of the next save, modulo `slots`.
+    next_slot: usize,
+    since_last_save: f32,
+    /// The newest snapshot found at startup, until it is restored or
+    /// dismissed.
+    pub restorable: Option<serialize::Snapshot>,
+    /// A snapshot to load once the current scene has been despawned.
+    restoring: Option<serialize::Snapshot>,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        let slots = 3;
+        Autosave {
+            enabled: true,
+            interval: Interval(60.0),
+            slots,
+            error: None,
+            next_slot: 0,
+            since_last_save: 0.0,
+            restorable: None,
+            restoring: None,
+        }
+    }
+}
+
+impl Autosave {
+    /// Offer the newest of `snapshots`, each with its slot, for restoring,
+    /// and save next to the slot after it.
+    pub fn resume(&mut self, snapshots: &[(usize, String)]) {
+        match latest(snapshots) {
+            Some((slot, snapshot)) => {
+                self.next_slot = slot + 1;
+                self.restorable = Some(snapshot);
+            },
+            None => {
+                self.next_slot = 0;
+                self.restorable = None;
+            },
+        }
+    }
+
+    /// Advance by `elapsed` wall-clock seconds. Returns the slot to save
+    /// to, if a save is due.
+    pub fn tick(&mut self, elapsed: f32) -> Option<usize> {
+        if !self.enabled {
+            return None;
+        }
+        self.since_last_save += elapsed;
+        if self.since_last_save < self.interval.0 {
+            return None;
+        }
+        self.since_last_save = 0.0;
+        let slot = self.next_slot % self.slots.max(1);
+        self.next_slot = slot + 1;
+        Some(slot)
+    }
+}
+
+/// The most recently saved of the snapshots that could be read, with its
+/// slot.
+pub fn latest(snapshots: &[(usize, String)]) -> Option<(usize, serialize::Snapshot)> {
+    snapshots
+        .iter()
+        .filter_map(|(slot, s)| Some((*slot, serde_json::from_str::<serialize::Snapshot>(s).ok()?)))
+        .max_by(|(_, a), (_, b)| a.saved_at_unix_seconds.total_cmp(&b.saved_at_unix_seconds))
+}
+
+/// Read the last session's snapshots.
+pub fn load_autosave(mut autosave: ResMut<Autosave>) {
+    autosave.resume(&storage::read_all(MAX_SLOTS));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod storage {
+    const DIRECTORY: &str = "autosave";
+
+    fn path(slot: usize) -> String {
+        format!("{}/snapshot_{}.json", DIRECTORY, slot)
+    }
+
+    pub fn write(slot: usize, json: &str) -> Result<(), String> {
+        std::fs::create_dir_all(DIRECTORY).map_err(|e| format!("Failed to create {}: {}", DIRECTORY, e))?;
+        // Write then rename, so a crash mid-write leaves the old snapshot.
+        let temporary = format!("{}.partial", path(slot));
+        std::fs::write(&temporary, json).map_err(|e| format!("Failed to write {}: {}", temporary, e))?;
+        std::fs::rename(&temporary, path(slot)).map_err(|e| format!("Failed to save {}: {}", path(slot), e))
+    }
+
+    pub fn read_all(slots: usize) -> Vec<(usize, String)> {
+        (0..slots).filter_map(|slot| Some((slot, std::fs::read_to_string(path(slot)).ok()?))).collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod storage {
+    fn key(slot: usize) -> String {
+        format!("nb-sim-autosave-{}", slot)
+    }
+
+    fn local_storage() -> Result<web_sys::Storage, String> {
+        web_sys::window()
+            .ok_or("No window".to_string())?
+            .local_storage()
+            .map_err(|e| format!("No localStorage: {:?}", e))?
+            .ok_or("No localStorage".to_string())
+    }
+
+    pub fn write(slot: usize, json: &str) -> Result<(), String> {
+        local_storage()?
+            .set_item(&key(slot), json)
+            .map_err(|e| format!("Failed to write to localStorage: {:?}", e))
+    }
+
+    pub fn read_all(slots: usize) -> Vec<(usize, String)> {
+        let Ok(storage) = local_storage() else {
+            return vec![];
+        };
+        (0..slots).filter_map(|slot| Some((slot, storage.get_item(&key(slot)).ok().flatten()?))).collect()
+    }
+}
+
+pub fn autosave_system(
+    time: Res<Time>,
+    timestamp: Res<Timestamp>,
+    constants: Res<SimulationConstants>,
+    mut autosave: ResMut<Autosave>,
+    simulation_state: SimulationState,
+) {
+    // Don't overwrite the last session before the user has had a chance to
+    // restore it.
+    if autosave.restorable.is_some() || simulation_state.current_scene.0.neurons.is_empty() {
+        return;
+    }
+    if let Some(slot) = autosave.tick(time.delta_seconds()) {
+        let snapshot = serialize::Snapshot {
+            saved_at_unix_seconds: unix_seconds_now(),
+            simulation_time_seconds: timestamp.0,
+            scene: simulation_state.serialize(&constants),
+        };
+        autosave.error = serde_json::to_string(&snapshot)
+            .map_err(|e| e.to_string())
+            .and_then(|json| storage::write(slot, &json))
+            .err();
+    }
+}
+
+pub fn autosave_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut autosave: ResMut<Autosave>,
+    mut timestamp: ResMut<Timestamp>,
+    grace_scene_sender: Res<GraceSceneSender>,
+    scene_entities: Query<Entity, SceneEntities>,
+) {
+    let ctx = contexts.ctx_mut();
+    let autosave = &mut *autosave;
+
+    if scene_entities.is_empty() {
+        if let Some(snapshot) = autosave.restoring.take() {
+            timestamp.0 = snapshot.simulation_time_seconds;
+            grace_scene_sender.0.send(GraceScene(snapshot.scene)).expect("Send should succeed");
+        }
+    }
+
+    if let Some(snapshot) = autosave.restorable.clone() {
+        let mut answered = false;
+        egui::Window::new("Restore last session").collapsible(false).show(ctx, |ui| {
+            ui.label(format!(
+                "A scene was autosaved at {}, {:.2} ms into the simulation.",
+                iso8601(snapshot.saved_at_unix_seconds),
+                snapshot.simulation_time_seconds * 1000.0,
+            ));
+            ui.horizontal(|ui| {
+                if ui.button("Restore").clicked() {
+                    for entity in &scene_entities {
+                        commands.entity(entity).despawn();
+                    }
+                    autosave.restoring = Some(snapshot.clone());
+                    answered = true;
+                }
+                if ui.button("Dismiss").clicked() {
+                    answered = true;
+                }
+            });
+        });
+        if answered {
+            autosave.restorable = None;
+        }
+    }
+
+    egui::Window::new("Autosave").default_open(false).show(ctx, |ui| {
+        ui.checkbox(&mut autosave.enabled, "Save the scene periodically");
+        ui.add(egui::Slider::new(&mut autosave.interval.0, 5.0..=600.0).logarithmic(true).text("Interval (s)"));
+        ui.add(egui::Slider::new(&mut autosave.slots, 1..=MAX_SLOTS).text("Snapshots kept"));
+        if let Some(e) = &autosave.error {
+            ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_json(saved_at: f64) -> String {
+        serde_json::to_string(&serialize::Snapshot {
+            saved_at_unix_seconds: saved_at,
+            simulation_time_seconds: 0.0,
+            scene: serialize::Scene { neurons: vec![], synapses: vec![], constants: None, lights: vec![], projectors: vec![], seed: None },
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rotates_slots_at_the_interval() {
+        let mut autosave = Autosave { interval: Interval(10.0), slots: 2, ..Autosave::default() };
+        let slots = (0..8).map(|_| autosave.tick(5.0)).collect::<Vec<_>>();
+        assert_eq!(slots, vec![None, Some(0), None, Some(1), None, Some(0), None, Some(1)]);
+    }
+
+    #[test]
+    fn restores_the_newest_readable_snapshot() {
+        let snapshots = vec![(0, snapshot_json(10.0)), (1, "not json".to_string()), (2, snapshot_json(20.0))];
+        assert_eq!(latest(&snapshots).map(|(slot, s)| (slot, s.saved_at_unix_seconds)), Some((2, 20.0)));
+        assert!(latest(&[]).is_none());
+    }
+
+    #[test]
+    fn carries_on_after_the_newest_slot() {
+        let mut autosave = Autosave { interval: Interval(1.0), slots: 3, ..Autosave::default() };
+        autosave.resume(&[(0, snapshot_json(20.0)), (1, snapshot_json(30.0)), (2, snapshot_json(10.0))]);
+        assert!(autosave.restorable.is_some());
+        let slots = (0..3).map(|_| autosave.tick(1.0)).collect::<Vec<_>>();
+        assert_eq!(slots, vec![Some(2), Some(0), Some(1)]);
+    }
+}
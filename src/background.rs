@@ -0,0 +1,346 @@
+//! Integrating on a thread of its own, as fast as it will go, rather than
+//! `StepsPerFrame` steps per rendered frame.
+//!
+//! While `BackgroundSimulation::enabled` is set and the simulation is not
+//! paused, the biophysics state of every segment, junction and synapse is
+//! copied into a `World` owned by a worker thread, which runs
+//! `step_biophysics` in a loop. Whenever the last `VoltageSnapshot` has
+//! been taken it sends another, and the app shows the latest: voltages,
+//! simulation time and step count. Probes, recordings and the oscilloscope
+//! therefore sample once per frame rather than once per step.
+//!
+//! The worker hands its state back, and the frame-locked step takes over,
+//! when the simulation is paused, a `Command` arrives, or segments are
+//! spawned or despawned. It starts again from the edited state the next
+//! frame. Components edited in the app while the worker ran keep their
+//! edits rather than the worker's state. On the web the worker is one of
+//! the workers of `threads`, once the page has started them, and the app
+//! polls for the state rather than block the browser's main thread.
+
+use bevy::ecs::component::Tick;
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
+
+use crate::clock::unix_seconds_now;
+use crate::command::{Command, SimulationControl};
+use crate::constants::SimulationConstants;
+use crate::dimension::{MilliVolts, SimulationStepSeconds, StepCount, StepsPerFrame, Timestamp};
+use crate::extracellular::ExtracellularField;
+use crate::integrations::grace::Synapse;
+use crate::neuron::Junction;
+use crate::neuron::calcium::CalciumShell;
+use crate::neuron::extracellular_space::{ExtracellularSpace, clear_extracellular_potassium};
+use crate::neuron::ion_dynamics::{IonDynamics, diffuse_along_junctions};
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::multirate::IntegrationRate;
+use crate::neuron::segment::{Geometry, ecs::InputCurrent, ecs::Segment};
+use crate::neuron::solution::Solution;
+use crate::noise::MembraneNoise;
+use crate::optogenetics::Illumination;
+use crate::plugin::{Env, step_biophysics};
+use crate::spike_train::SpikeTrainInput;
+use crate::stimulator::Stimulator;
+use crate::thermal::ThermalStimulator;
+use crate::threads;
+
+/// The worker's voltages after some step.
+#[derive(Debug)]
+pub struct VoltageSnapshot {
+    pub timestamp: f32,
+    pub step_count: u64,
+    pub voltages: Vec<(Entity, MilliVolts)>,
+}
+
+struct Worker {
+    stop: Sender<()>,
+    snapshots: Receiver<VoltageSnapshot>,
+    /// The worker's world, once it has stopped. Disconnected if it
+    /// panicked.
+    handback: Receiver<World>,
+    /// Whether the worker has been asked to stop.
+    stopping: bool,
+    /// The app's change tick when the worker took its copy.
+    started_tick: Tick,
+    /// The step count and wall-clock time the worker started at.
+    started: (u64, f64),
+    /// The simulation time and step count last shown, for telling them
+    /// from edits.
+    shown: (f32, u64),
+}
+
+#[derive(Default, Resource)]
+pub struct BackgroundSimulation {
+    pub enabled: bool,
+    /// Steps per second the worker has integrated since it last started.
+    pub steps_per_second: Option<f64>,
+    pub error: Option<String>,
+    worker: Option<Worker>,
+}
+
+impl BackgroundSimulation {
+    pub fn available() -> bool {
+        threads::available()
+    }
+
+    pub fn running(&self) -> bool {
+        self.worker.is_some()
+    }
+}
+
+/// Run condition for the frame-locked step, which the worker replaces.
+pub fn background_running(background: Res<BackgroundSimulation>) -> bool {
+    background.running()
+}
+
+/// What tells the supervisor that the scene was edited: commands, and
+/// segments spawned or despawned.
+pub type SceneEdits = (
+    EventReader<'static, 'static, Command>,
+    Query<'static, 'static, (), Added<Segment>>,
+    RemovedComponents<'static, 'static, Segment>,
+);
+
+/// Start the worker when it is wanted, and bring its state back when it
+/// is not, or the scene was edited.
+pub fn supervise_background_simulation(world: &mut World, edits: &mut SystemState<SceneEdits>) {
+    let edited = {
+        let (mut commands, added, mut removed) = edits.get_mut(world);
+        // Read every reader, so that none carries its events over.
+        let commanded = commands.read().count() > 0;
+        let despawned = removed.read().count() > 0;
+        commanded || despawned || !added.is_empty()
+    };
+    let wanted = BackgroundSimulation::available()
+        && world.resource::<BackgroundSimulation>().enabled
+        && !world.resource::<SimulationControl>().paused;
+    let running = world.resource::<BackgroundSimulation>().running();
+    let stopping = world.resource::<BackgroundSimulation>().worker.as_ref().is_some_and(|w| w.stopping);
+    if running && (edited || !wanted || stopping) {
+        stop(world);
+    } else if !running && wanted && !edited {
+        start(world);
+    }
+}
+
+/// Show the worker's latest voltages, time and step count.
+pub fn receive_voltage_snapshots(
+    mut background: ResMut<BackgroundSimulation>,
+    mut timestamp: ResMut<Timestamp>,
+    mut step_count: ResMut<StepCount>,
+    mut voltages: Query<&mut MembraneVoltage>,
+) {
+    let background = &mut *background;
+    let Some(worker) = background.worker.as_mut() else {
+        return;
+    };
+    let Some(snapshot) = worker.snapshots.try_iter().last() else {
+        return;
+    };
+    for (entity, v) in snapshot.voltages {
+        if let Ok(mut voltage) = voltages.get_mut(entity) {
+            // Not an edit, to be kept over the worker's state.
+            voltage.bypass_change_detection().0 = v;
+        }
+    }
+    timestamp.0 = snapshot.timestamp;
+    step_count.0 = snapshot.step_count;
+    worker.shown = (snapshot.timestamp, snapshot.step_count);
+    let (started_step, started_at) = worker.started;
+    let seconds = unix_seconds_now() - started_at;
+    if seconds > 0.0 {
+        background.steps_per_second = Some(snapshot.step_count.saturating_sub(started_step) as f64 / seconds);
+    }
+}
+
+fn copy_resource<R: Resource + Clone>(from: &World, to: &mut World) {
+    if let Some(resource) = from.get_resource::<R>() {
+        to.insert_resource(resource.clone());
+    }
+}
+
+/// Copy every `C` onto the entity of the same id in `to`, so that entities
+/// referring to others (junctions, synapses) stay valid.
+fn copy_out<C: Component + Clone>(from: &mut World, to: &mut World) {
+    let mut query = from.query::<(Entity, &C)>();
+    for (entity, component) in query.iter(from) {
+        if let Some(mut target) = to.get_or_spawn(entity) {
+            target.insert(component.clone());
+        }
+    }
+}
+
+/// Copy every `C` back from the worker, except onto entities that are
+/// gone, or whose `C` was edited since `started`.
+fn copy_back<C: Component + Clone>(from: &mut World, to: &mut World, started: Tick) {
+    let this_run = to.change_tick();
+    let mut query = from.query::<(Entity, &C)>();
+    for (entity, component) in query.iter(from) {
+        let edited = to
+            .get_entity(entity)
+            .and_then(|target| target.get_ref::<C>())
+            .is_none_or(|target| target.last_changed().is_newer_than(started, this_run));
+        if edited {
+            continue;
+        }
+        if let Some(mut target) = to.get_mut::<C>(entity) {
+            *target.bypass_change_detection() = component.clone();
+        }
+    }
+}
+
+fn start(world: &mut World) {
+    let mut worker_world = World::new();
+    copy_resource::<Env>(world, &mut worker_world);
+    copy_resource::<SimulationConstants>(world, &mut worker_world);
+    copy_resource::<SimulationStepSeconds>(world, &mut worker_world);
+    copy_resource::<StepsPerFrame>(world, &mut worker_world);
+    copy_resource::<Timestamp>(world, &mut worker_world);
+    copy_resource::<StepCount>(world, &mut worker_world);
+    copy_resource::<IonDynamics>(world, &mut worker_world);
+    worker_world.insert_resource(SimulationControl::default());
+
+    copy_out::<Segment>(world, &mut worker_world);
+    copy_out::<Solution>(world, &mut worker_world);
+    copy_out::<Geometry>(world, &mut worker_world);
+    copy_out::<Membrane>(world, &mut worker_world);
+    copy_out::<MembraneVoltage>(world, &mut worker_world);
+    copy_out::<InputCurrent>(world, &mut worker_world);
+    copy_out::<Stimulator>(world, &mut worker_world);
+    copy_out::<SpikeTrainInput>(world, &mut worker_world);
+    copy_out::<IntegrationRate>(world, &mut worker_world);
+    copy_out::<ThermalStimulator>(world, &mut worker_world);
+    copy_out::<Illumination>(world, &mut worker_world);
+    copy_out::<MembraneNoise>(world, &mut worker_world);
+    copy_out::<ExtracellularSpace>(world, &mut worker_world);
+    copy_out::<CalciumShell>(world, &mut worker_world);
+    copy_out::<ExtracellularField>(world, &mut worker_world);
+    copy_out::<Junction>(world, &mut worker_world);
+    copy_out::<Synapse>(world, &mut worker_world);
+
+    let (stop_sender, stop_receiver) = unbounded();
+    let (snapshot_sender, snapshot_receiver) = bounded(1);
+    let (handback_sender, handback_receiver) = bounded(1);
+    let spawned = threads::spawn("nb-sim biophysics", move || {
+        let _ = handback_sender.send(run_worker(worker_world, stop_receiver, snapshot_sender));
+    });
+    let shown = (world.resource::<Timestamp>().0, world.resource::<StepCount>().0);
+    let started_tick = world.change_tick();
+    let mut background = world.resource_mut::<BackgroundSimulation>();
+    match spawned {
+        Ok(()) => {
+            background.error = None;
+            background.worker = Some(Worker {
+                stop: stop_sender,
+                snapshots: snapshot_receiver,
+                handback: handback_receiver,
+                stopping: false,
+                started_tick,
+                started: (shown.1, unix_seconds_now()),
+                shown,
+            });
+        },
+        Err(e) => {
+            background.enabled = false;
+            background.error = Some(format!("Could not start the simulation thread: {}", e));
+        },
+    }
+}
+
+/// Ask the worker to stop, and copy its state back once it has. A
+/// browser's main thread may not block, so there this returns until a
+/// later frame if the worker has not stopped yet.
+fn stop(world: &mut World) {
+    let mut background = world.resource_mut::<BackgroundSimulation>();
+    let Some(worker) = background.worker.as_mut() else {
+        return;
+    };
+    if !worker.stopping {
+        // The worker may already have stopped, if it panicked.
+        let _ = worker.stop.send(());
+        worker.stopping = true;
+    }
+    let handed_back = if cfg!(target_arch = "wasm32") {
+        match worker.handback.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            handed_back => handed_back.ok(),
+        }
+    } else {
+        worker.handback.recv().ok()
+    };
+    let Some(worker) = background.worker.take() else {
+        return;
+    };
+    let Some(mut worker_world) = handed_back else {
+        background.enabled = false;
+        background.error = Some("The simulation thread panicked; the scene is as it was when it started.".to_string());
+        return;
+    };
+
+    let started = worker.started_tick;
+    copy_back::<Solution>(&mut worker_world, world, started);
+    copy_back::<Membrane>(&mut worker_world, world, started);
+    copy_back::<MembraneVoltage>(&mut worker_world, world, started);
+    copy_back::<IntegrationRate>(&mut worker_world, world, started);
+    copy_back::<MembraneNoise>(&mut worker_world, world, started);
+    copy_back::<ExtracellularSpace>(&mut worker_world, world, started);
+    copy_back::<CalciumShell>(&mut worker_world, world, started);
+    copy_back::<Synapse>(&mut worker_world, world, started);
+
+    // Keep the app's time if it was set since it was last shown.
+    if world.resource::<Timestamp>().0 == worker.shown.0 && world.resource::<StepCount>().0 == worker.shown.1 {
+        copy_resource::<Timestamp>(&worker_world, world);
+        copy_resource::<StepCount>(&worker_world, world);
+    }
+}
+
+fn run_worker(mut world: World, stop: Receiver<()>, snapshots: Sender<VoltageSnapshot>) -> World {
+    let mut schedule = Schedule::default();
+    schedule.add_systems((step_biophysics, (diffuse_along_junctions, clear_extracellular_potassium)).chain());
+    let mut voltages = world.query::<(Entity, &MembraneVoltage)>();
+    while stop.try_recv() == Err(TryRecvError::Empty) {
+        schedule.run(&mut world);
+        // Only gather a snapshot once the last has been taken.
+        if snapshots.is_empty() {
+            let snapshot = VoltageSnapshot {
+                timestamp: world.resource::<Timestamp>().0,
+                step_count: world.resource::<StepCount>().0,
+                voltages: voltages.iter(&world).map(|(entity, v)| (entity, v.0.clone())).collect(),
+            };
+            if snapshots.send(snapshot).is_err() {
+                break;
+            }
+        }
+    }
+    world
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Component, Debug, PartialEq)]
+    struct Level(u32);
+
+    #[test]
+    fn state_comes_back_except_where_edited() {
+        let mut app_world = World::new();
+        let kept = app_world.spawn(Level(1)).id();
+        let edited = app_world.spawn(Level(1)).id();
+        let mut worker_world = World::new();
+        copy_out::<Level>(&mut app_world, &mut worker_world);
+        let started = app_world.change_tick();
+        app_world.increment_change_tick();
+
+        // The worker steps both; the app edits one meanwhile.
+        for mut level in worker_world.query::<&mut Level>().iter_mut(&mut worker_world) {
+            level.0 += 10;
+        }
+        app_world.get_mut::<Level>(edited).unwrap().0 = 5;
+        app_world.increment_change_tick();
+
+        copy_back::<Level>(&mut worker_world, &mut app_world, started);
+        assert_eq!(app_world.get::<Level>(kept), Some(&Level(11)));
+        assert_eq!(app_world.get::<Level>(edited), Some(&Level(5)));
+    }
+}
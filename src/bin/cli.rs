@@ -0,0 +1,149 @@
+//! Run a scene without a window and write membrane potentials to CSV.
+//!
+//! ```text
+//! nb-sim-cli SCENE --duration-ms 100 [--dt-us 0.5] [--sample-us 50]
+//!            [--all-segments] [--repair] [--out traces.csv]
+//!            [--sweep PATH=VALUES]... [--parallel]
+//! ```
+//!
+//! Records each neuron's soma, or every segment with `--all-segments`,
+//! one row per sample, with a `t_ms` column and one column per segment
+//! named `n<neuron>_<SWC id>`.
+//!
+//! `--repair` fixes defective morphologies (see `nb_sim::repair`) before
+//! running, listing each fix.
+//!
+//! Given one or more `--sweep PATH=VALUES` (see `nb_sim::sweep`), it runs
+//! every combination of the values instead, with `--parallel` spreading
+//! them over the available cores, and writes a table of each neuron's
+//! spike count and resting potential per combination, to `sweep.csv`
+//! unless `--out` says otherwise.
+
+use nb_sim::headless::HeadlessSimulation;
+use nb_sim::integrations::dhall::read_scene;
+use nb_sim::integrations::grace::soma;
+use nb_sim::serialize::Scene;
+use nb_sim::recording::csv_recorder::CsvRecorder;
+use nb_sim::repair::{diagnose, repair, RepairOptions};
+use nb_sim::sweep::{Parameter, Sweep};
+
+const USAGE: &str =
+    "Usage: nb-sim-cli SCENE --duration-ms MS [--dt-us US] [--sample-us US] [--all-segments] [--repair] [--sweep PATH=VALUES]... [--parallel] [--out PATH]";
+
+struct Args {
+    scene: String,
+    duration_ms: f32,
+    dt_us: f32,
+    sample_us: f32,
+    all_segments: bool,
+    repair: bool,
+    sweep: Vec<Parameter>,
+    parallel: bool,
+    out: String,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        scene: String::new(),
+        duration_ms: f32::NAN,
+        dt_us: 0.5,
+        sample_us: 50.0,
+        all_segments: false,
+        repair: false,
+        sweep: vec![],
+        parallel: false,
+        out: String::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| -> Result<f32, String> {
+            let value = args.next().ok_or(format!("{} needs a value", name))?;
+            value.parse().map_err(|_| format!("{} should be a number, not {}", name, value))
+        };
+        match arg.as_str() {
+            "--duration-ms" => parsed.duration_ms = number(&arg)?,
+            "--dt-us" => parsed.dt_us = number(&arg)?,
+            "--sample-us" => parsed.sample_us = number(&arg)?,
+            "--all-segments" => parsed.all_segments = true,
+            "--repair" => parsed.repair = true,
+            "--sweep" => parsed.sweep.push(Parameter::parse(&args.next().ok_or("--sweep needs PATH=VALUES".to_string())?)?),
+            "--parallel" => parsed.parallel = true,
+            "--out" => parsed.out = args.next().ok_or("--out needs a path".to_string())?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            scene if parsed.scene.is_empty() => parsed.scene = scene.to_string(),
+            extra => return Err(format!("Unexpected argument {}", extra)),
+        }
+    }
+    if parsed.scene.is_empty() || parsed.duration_ms.is_nan() {
+        return Err(USAGE.to_string());
+    }
+    if parsed.out.is_empty() {
+        parsed.out = if parsed.sweep.is_empty() { "traces.csv" } else { "sweep.csv" }.to_string();
+    }
+    if parsed.dt_us <= 0.0 || parsed.sample_us < parsed.dt_us {
+        return Err("--dt-us should be positive, and --sample-us at least as long".to_string());
+    }
+    Ok(parsed)
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let mut scene = read_scene(&args.scene)?;
+    for (n, scene_neuron) in scene.neurons.iter_mut().enumerate() {
+        if args.repair {
+            for fix in repair(&mut scene_neuron.neuron, &RepairOptions::default()) {
+                eprintln!("Neuron {}: {}", n, fix);
+            }
+        } else if !diagnose(&scene_neuron.neuron).is_empty() {
+            eprintln!("Neuron {} has morphology defects; --repair would fix them", n);
+        }
+    }
+    if !args.sweep.is_empty() {
+        return run_sweep(&args, &scene);
+    }
+    let somas = scene
+        .neurons
+        .iter()
+        .map(|n| soma(&n.neuron).map(|s| s.id))
+        .collect::<Vec<_>>();
+    let steps_per_sample = (args.sample_us / args.dt_us).round() as usize;
+    let mut simulation = HeadlessSimulation::new(scene, args.dt_us * 1e-6, steps_per_sample)?;
+    let mut recorder = CsvRecorder::create(&args.out, 1)?;
+
+    let duration_seconds = args.duration_ms * 1e-3;
+    loop {
+        let columns = simulation
+            .voltages()
+            .into_iter()
+            .filter(|(at, _)| args.all_segments || somas.get(at.neuron) == Some(&Some(at.segment)))
+            .map(|(at, v)| (format!("n{}_{}", at.neuron, at.segment), v))
+            .collect::<Vec<_>>();
+        recorder.record(simulation.time(), &columns)?;
+        if simulation.time() >= duration_seconds {
+            break;
+        }
+        simulation.step();
+    }
+    recorder.flush()?;
+    eprintln!("Wrote {:.2} ms of {} to {}", simulation.time() * 1e3, args.scene, args.out);
+    Ok(())
+}
+
+fn run_sweep(args: &Args, scene: &Scene) -> Result<(), String> {
+    let sweep = Sweep {
+        parameters: args.sweep.clone(),
+        duration_seconds: args.duration_ms * 1e-3,
+        step_seconds: args.dt_us * 1e-6,
+        sample_seconds: args.sample_us * 1e-6,
+    };
+    let rows = sweep.run(scene, args.parallel);
+    let failed = rows.iter().filter(|row| row.summaries.is_err()).count();
+    std::fs::write(&args.out, sweep.table(&rows, scene.neurons.len())).map_err(|e| format!("{}: {}", args.out, e))?;
+    eprintln!("Wrote {} combinations ({} failed) of {} to {}", rows.len(), failed, args.scene, args.out);
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = parse_args(std::env::args().skip(1)).and_then(run) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,351 @@
+//! Programmatic camera control, for automated demos and video capture.
+//!
+//! Systems (or anything holding an `EventWriter<CameraCommand>`) send
+//! `CameraCommand`s. Javascript clients send the same commands as JSON
+//! through `camera_command`, which forwards them over a channel in the
+//! same way as `gui::external_trigger::set_scene_source`.
+//!
+//! Commands move the camera immediately rather than through the orbit
+//! camera's smoothing, and animations advance by a fixed step per frame,
+//! so that a given command sequence always produces the same frames
+//! regardless of frame rate.
+//!
+//! The camera can also be switched to an orthographic projection, for
+//! comparing against 2D morphology figures. The orbit radius then sets
+//! the projection's scale, so that the view at the focus keeps its size
+//! across the switch.
+
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy_panorbit_camera::PanOrbitCamera;
+use crossbeam::channel::{Receiver, Sender};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::neuron::segment::ecs::Segment;
+use crate::selection::Selection;
+
+#[derive(Clone, Debug, Deserialize, Event)]
+#[serde(tag = "type")]
+pub enum CameraCommand {
+    SetTarget { x: f32, y: f32, z: f32 },
+    SetRadius { radius: f32 },
+    /// Set the orbit angles, in degrees.
+    SetAngles { yaw: f32, pitch: f32 },
+    /// Orbit around the target. Runs forever when `frames` is `None`.
+    Orbit { degrees_per_frame: f32, frames: Option<u32> },
+    /// Keep the target on an entity as it moves. Not available from
+    /// Javascript, which has no way of naming entities.
+    #[serde(skip)]
+    Follow(Entity),
+    /// Stop any animation and entity following.
+    Stop,
+    /// Switch between orthographic and perspective projection.
+    SetOrthographic { orthographic: bool },
+    /// Move to aim at the middle of every segment, backing off until they
+    /// are all in view.
+    FitAll,
+    /// Move to aim at the selected segments in the same way.
+    FrameSelection,
+}
+
+/// Frames `FitAll` and `FrameSelection` take to arrive.
+pub const FRAMING_FRAMES: u32 = 30;
+/// The least radius framing backs off to, so that a lone segment is seen
+/// with some of its surroundings.
+const MIN_FRAMING_RADIUS: f32 = 20.0;
+
+/// The views along the axes, as a name and the orbit angles (yaw, pitch)
+/// in degrees for `CameraCommand::SetAngles`.
+pub const STANDARD_VIEWS: [(&str, f32, f32); 3] = [("XY", 0.0, 0.0), ("XZ", 0.0, 90.0), ("YZ", 90.0, 0.0)];
+
+/// The field of view of the perspective projection, vertically.
+fn perspective_fov(projection: &Projection) -> f32 {
+    match projection {
+        Projection::Perspective(p) => p.fov,
+        Projection::Orthographic(_) => PerspectiveProjection::default().fov,
+    }
+}
+
+/// An orthographic projection as tall at a scale of `radius` as a
+/// perspective one with `fov` is at that distance from the camera.
+fn orthographic(fov: f32, radius: f32) -> Projection {
+    Projection::Orthographic(OrthographicProjection {
+        // The camera orbits at `radius`, so it may be among the segments.
+        near: -1e6,
+        far: 1e6,
+        scaling_mode: ScalingMode::FixedVertical(2.0 * (fov * 0.5).tan()),
+        scale: radius,
+        ..default()
+    })
+}
+
+/// The orbit radius at which a sphere of `radius` around the focus fills
+/// a view with vertical field of view `fov`.
+pub fn fit_radius(radius: f32, fov: f32) -> f32 {
+    radius / (fov * 0.5).sin()
+}
+
+#[derive(Debug)]
+pub struct OrbitAnimation {
+    pub degrees_per_frame: f32,
+    pub frames_remaining: Option<u32>,
+}
+
+/// A move of the focus and radius, easing in and out over
+/// `FRAMING_FRAMES`.
+#[derive(Debug)]
+pub struct FramingAnimation {
+    pub from: (Vec3, f32),
+    pub to: (Vec3, f32),
+    pub frame: u32,
+}
+
+impl FramingAnimation {
+    /// The focus and radius `frame` frames in.
+    pub fn at(&self, frame: u32) -> (Vec3, f32) {
+        let t = (frame as f32 / FRAMING_FRAMES as f32).clamp(0.0, 1.0);
+        let eased = t * t * (3.0 - 2.0 * t);
+        (self.from.0.lerp(self.to.0, eased), self.from.1 + (self.to.1 - self.from.1) * eased)
+    }
+}
+
+/// The animations started by earlier commands.
+#[derive(Debug, Default, Resource)]
+pub struct CameraAnimation {
+    pub orbit: Option<OrbitAnimation>,
+    pub follow: Option<Entity>,
+    pub framing: Option<FramingAnimation>,
+}
+
+pub struct CameraPlugin;
+
+static CAMERA_COMMAND_SENDER: OnceCell<Sender<CameraCommand>> = OnceCell::new();
+
+#[derive(Resource)]
+struct CameraCommandReceiver(Receiver<CameraCommand>);
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        let (tx, rx) = crossbeam::channel::unbounded();
+        // External commands reach the first app built in this process; a
+        // later one, as in tests or a headless run, still animates its own.
+        if CAMERA_COMMAND_SENDER.set(tx).is_err() {
+            eprintln!("CameraPlugin: another app already receives external camera commands");
+        }
+        app.insert_resource(CameraCommandReceiver(rx))
+            .init_resource::<CameraAnimation>()
+            .add_event::<CameraCommand>()
+            .add_systems(Update, (
+                forward_external_camera_commands,
+                apply_camera_commands,
+                animate_camera,
+            ).chain());
+    }
+}
+
+fn forward_external_camera_commands(
+    receiver: Res<CameraCommandReceiver>,
+    mut camera_commands: EventWriter<CameraCommand>,
+) {
+    camera_commands.send_batch(receiver.0.try_iter());
+}
+
+fn apply_camera_commands(
+    mut camera_commands: EventReader<CameraCommand>,
+    mut animation: ResMut<CameraAnimation>,
+    mut cameras: Query<(&mut PanOrbitCamera, &mut Projection)>,
+    segments: Query<(&GlobalTransform, Has<Selection>), With<Segment>>,
+) {
+    for command in camera_commands.read() {
+        match command {
+            CameraCommand::Orbit { degrees_per_frame, frames } => {
+                animation.orbit = Some(OrbitAnimation {
+                    degrees_per_frame: *degrees_per_frame,
+                    frames_remaining: *frames,
+                });
+            },
+            CameraCommand::Follow(entity) => {
+                animation.follow = Some(*entity);
+            },
+            CameraCommand::Stop => {
+                animation.orbit = None;
+                animation.follow = None;
+                animation.framing = None;
+            },
+            CameraCommand::SetTarget { x, y, z } => {
+                animation.follow = None;
+                animation.framing = None;
+                for (mut camera, _) in &mut cameras {
+                    set_focus(&mut camera, Vec3::new(*x, *y, *z));
+                }
+            },
+            CameraCommand::SetRadius { radius } => {
+                animation.framing = None;
+                for (mut camera, _) in &mut cameras {
+                    set_radius(&mut camera, *radius);
+                }
+            },
+            CameraCommand::SetAngles { yaw, pitch } => {
+                for (mut camera, _) in &mut cameras {
+                    set_angles(&mut camera, yaw.to_radians(), pitch.to_radians());
+                }
+            },
+            CameraCommand::SetOrthographic { orthographic: true } => {
+                for (mut camera, mut projection) in &mut cameras {
+                    let Projection::Perspective(p) = &*projection else { continue };
+                    let fov = p.fov;
+                    *projection = orthographic(fov, camera.radius.unwrap_or(camera.target_radius));
+                    camera.force_update = true;
+                }
+            },
+            CameraCommand::SetOrthographic { orthographic: false } => {
+                for (mut camera, mut projection) in &mut cameras {
+                    if matches!(*projection, Projection::Orthographic(_)) {
+                        *projection = Projection::Perspective(PerspectiveProjection::default());
+                        camera.force_update = true;
+                    }
+                }
+            },
+            CameraCommand::FitAll => {
+                start_framing(&mut animation, &cameras, segments.iter().map(|(transform, _)| transform.translation()));
+            },
+            CameraCommand::FrameSelection => {
+                let selected = segments.iter().filter(|(_, selected)| *selected);
+                start_framing(&mut animation, &cameras, selected.map(|(transform, _)| transform.translation()));
+            },
+        }
+    }
+}
+
+/// The least and greatest corners of the box around `points`.
+fn bounds(points: impl Iterator<Item = Vec3>) -> Option<(Vec3, Vec3)> {
+    points.fold(None, |bounds, p| Some(bounds.map_or((p, p), |(min, max): (Vec3, Vec3)| (min.min(p), max.max(p)))))
+}
+
+/// Start moving the camera to bring every one of `points` into view. Does
+/// nothing without points.
+fn start_framing(
+    animation: &mut CameraAnimation,
+    cameras: &Query<(&mut PanOrbitCamera, &mut Projection)>,
+    points: impl Iterator<Item = Vec3>,
+) {
+    let (Some((min, max)), Some((camera, projection))) = (bounds(points), cameras.iter().next()) else {
+        return;
+    };
+    // Segment positions are their centres; leave room for their extent.
+    let radius = fit_radius((max - min).length() * 0.5 * 1.1, perspective_fov(projection)).max(MIN_FRAMING_RADIUS);
+    animation.follow = None;
+    animation.framing = Some(FramingAnimation {
+        from: (camera.focus, camera.radius.unwrap_or(camera.target_radius)),
+        to: ((min + max) * 0.5, radius),
+        frame: 0,
+    });
+}
+
+fn animate_camera(
+    mut animation: ResMut<CameraAnimation>,
+    mut cameras: Query<&mut PanOrbitCamera>,
+    transforms: Query<&GlobalTransform>,
+) {
+    if let Some(entity) = animation.follow {
+        match transforms.get(entity) {
+            Ok(transform) => {
+                for mut camera in &mut cameras {
+                    set_focus(&mut camera, transform.translation());
+                }
+            },
+            // The followed entity was despawned.
+            Err(_) => animation.follow = None,
+        }
+    }
+
+    let mut orbit_finished = false;
+    if let Some(orbit) = &mut animation.orbit {
+        for mut camera in &mut cameras {
+            let yaw = camera.yaw.unwrap_or(camera.target_yaw) + orbit.degrees_per_frame.to_radians();
+            let pitch = camera.pitch.unwrap_or(camera.target_pitch);
+            set_angles(&mut camera, yaw, pitch);
+        }
+        if let Some(frames) = &mut orbit.frames_remaining {
+            *frames = frames.saturating_sub(1);
+            orbit_finished = *frames == 0;
+        }
+    }
+    if orbit_finished {
+        animation.orbit = None;
+    }
+
+    let mut framing_finished = false;
+    if let Some(framing) = &mut animation.framing {
+        framing.frame += 1;
+        let (focus, radius) = framing.at(framing.frame);
+        for mut camera in &mut cameras {
+            set_focus(&mut camera, focus);
+            set_radius(&mut camera, radius);
+        }
+        framing_finished = framing.frame >= FRAMING_FRAMES;
+    }
+    if framing_finished {
+        animation.framing = None;
+    }
+}
+
+fn set_focus(camera: &mut PanOrbitCamera, focus: Vec3) {
+    camera.focus = focus;
+    camera.target_focus = focus;
+    camera.force_update = true;
+}
+
+fn set_radius(camera: &mut PanOrbitCamera, radius: f32) {
+    camera.radius = Some(radius);
+    camera.target_radius = radius;
+    camera.force_update = true;
+}
+
+fn set_angles(camera: &mut PanOrbitCamera, yaw: f32, pitch: f32) {
+    camera.yaw = Some(yaw);
+    camera.pitch = Some(pitch);
+    camera.target_yaw = yaw;
+    camera.target_pitch = pitch;
+    camera.force_update = true;
+}
+
+/// Exported to Javascript clients, to drive the camera from scripts. The
+/// command is JSON, e.g. `{"type": "Orbit", "degrees_per_frame": 0.5, "frames": 720}`.
+#[wasm_bindgen]
+pub fn camera_command(json: String) -> Result<(), String> {
+    let command: CameraCommand = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    let sender = CAMERA_COMMAND_SENDER.get().ok_or("Camera should be initialized by start()".to_string())?;
+    sender.send(command).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fitted_views_hold_the_whole_sphere() {
+        let fov = PerspectiveProjection::default().fov;
+        let radius = fit_radius(100.0, fov);
+        // The sphere touches the edges of the view.
+        assert!(((100.0 / radius).asin() - fov * 0.5).abs() < 1e-5);
+        // An orthographic view at that radius is at least as tall as the
+        // sphere.
+        let Projection::Orthographic(o) = orthographic(fov, radius) else { panic!() };
+        let ScalingMode::FixedVertical(height) = o.scaling_mode else { panic!() };
+        assert!(height * o.scale >= 200.0);
+    }
+
+    #[test]
+    fn framing_eases_from_start_to_end() {
+        let framing = FramingAnimation { from: (Vec3::ZERO, 100.0), to: (Vec3::new(10.0, 0.0, 0.0), 50.0), frame: 0 };
+        assert_eq!(framing.at(0), (Vec3::ZERO, 100.0));
+        assert_eq!(framing.at(FRAMING_FRAMES / 2), (Vec3::new(5.0, 0.0, 0.0), 75.0));
+        assert_eq!(framing.at(FRAMING_FRAMES), (Vec3::new(10.0, 0.0, 0.0), 50.0));
+        // Slow at the ends.
+        assert!(framing.at(1).0.x < 10.0 / FRAMING_FRAMES as f32);
+        assert_eq!(bounds([Vec3::ONE, Vec3::NEG_X, Vec3::Z].into_iter()), Some((Vec3::new(-1.0, 0.0, 0.0), Vec3::ONE)));
+    }
+}
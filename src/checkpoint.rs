@@ -0,0 +1,480 @@
+//! Checkpoints of the simulation's dynamic state, so that a long run can
+//! be saved to a file and resumed after a restart.
+//!
+//! A checkpoint holds only what changes as the simulation runs: time and
+//! step count, membrane potentials, gate magnitudes, membrane noise
+//! currents, intracellular and extracellular concentrations, submembrane
+//! calcium, where each multirate segment's long step started, and each
+//! synapse's cleft transmitter concentrations (its presynaptic state),
+//! receptor gates and plastic weight. It is restored
+//! onto the scene it was taken from, with segments matched by `SegmentRef`
+//! and synapses by their position in the scene, so save the scene
+//! alongside it.
+//!
+//! Stimulator phase needs no state of its own: envelopes are functions of
+//! time, and Poisson pulses and noise draws come from streams keyed by the
+//! scene's seed and the time bin or step, so restoring time and step
+//! resumes them where they were. The internal state of mechanisms
+//! registered by other crates (see `mechanism`) is not saved.
+//!
+//! The same state can be held in memory instead, as a snapshot to roll
+//! back to while exploring interventions from one point.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::command::Command;
+use crate::dimension::{MilliVolts, Molar, StepCount, Timestamp};
+use crate::integrations::grace::{SceneSegment, Synapse};
+use crate::neuron::calcium::CalciumShell;
+use crate::neuron::channel::Channel;
+use crate::neuron::extracellular_space::ExtracellularSpace;
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::multirate::IntegrationRate;
+use crate::neuron::solution::Solution;
+use crate::neuron::synapse::SynapseMembranes;
+use crate::noise::MembraneNoise;
+use crate::serialize::{self, SegmentRef};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Checkpoint {
+    pub t_seconds: f32,
+    pub steps: u64,
+    pub segments: Vec<SegmentState>,
+    pub synapses: Vec<SynapseState>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SegmentState {
+    pub at: SegmentRef,
+    pub v_mv: f32,
+    /// One per membrane channel, in the membrane's order.
+    pub channels: Vec<GateMagnitudes>,
+    /// The background and channel currents of the segment's membrane
+    /// noise, if it has any; see `noise`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<(f32, f32)>,
+    /// Missing from checkpoints saved before segments had concentrations
+    /// of their own, which restore without touching them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<SegmentDynamics>,
+}
+
+/// What a segment's concentrations and multirate integration have
+/// reached.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SegmentDynamics {
+    pub intracellular: serialize::Solution,
+    /// The segment's own extracellular solution, while potassium
+    /// accumulates; see `neuron::extracellular_space`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extracellular: Option<serialize::Solution>,
+    /// Free Ca2+ in the submembrane shell (M); see `neuron::calcium`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calcium_molar: Option<f32>,
+    /// Where the current long step started, and the coupling deferred
+    /// since (mV); see `IntegrationRate::long_step`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_step: Option<(Option<f32>, f32)>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SynapseState {
+    /// The synapse's position in the scene's synapses, as
+    /// `plugin::serialize_simulation` writes them.
+    pub index: usize,
+    pub pre: SegmentRef,
+    pub post: SegmentRef,
+    pub glutamate_molar: f32,
+    pub gaba_molar: f32,
+    /// One per postsynaptic receptor, in order.
+    pub receptors: Vec<GateMagnitudes>,
+    /// The weight a plastic synapse has reached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GateMagnitudes {
+    pub activation: Option<f32>,
+    pub inactivation: Option<f32>,
+    /// State occupancies of a light-gated channel; see `neuron::opsin`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub opsin: Vec<f32>,
+}
+
+impl GateMagnitudes {
+    pub fn of(channel: &Channel) -> Self {
+        GateMagnitudes {
+            activation: channel.activation.as_ref().map(|g| g.magnitude),
+            inactivation: channel.inactivation.as_ref().map(|g| g.magnitude),
+            opsin: channel.opsin.as_ref().map_or(vec![], |o| o.occupancies()),
+        }
+    }
+
+    pub fn apply(&self, channel: &mut Channel) -> Result<(), String> {
+        if let (Some(gate), Some(magnitude)) = (channel.activation.as_mut(), self.activation) {
+            gate.magnitude = magnitude;
+        }
+        if let (Some(gate), Some(magnitude)) = (channel.inactivation.as_mut(), self.inactivation) {
+            gate.magnitude = magnitude;
+        }
+        match channel.opsin.as_mut() {
+            Some(opsin) if !self.opsin.is_empty() => opsin.set_occupancies(&self.opsin),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Apply saved gates to channels one for one, failing if they differ in
+/// number.
+pub fn apply_all<'a>(saved: &[GateMagnitudes], channels: impl ExactSizeIterator<Item = &'a mut Channel>) -> Result<(), String> {
+    if saved.len() != channels.len() {
+        return Err(format!("{} channels saved but {} in the scene", saved.len(), channels.len()));
+    }
+    saved.iter().zip(channels).try_for_each(|(gates, channel)| gates.apply(channel))
+}
+
+impl SegmentState {
+    pub fn capture(at: SegmentRef, membrane: &Membrane, voltage: &MembraneVoltage, noise: Option<&MembraneNoise>) -> Self {
+        SegmentState {
+            at,
+            v_mv: voltage.0.0,
+            channels: membrane.membrane_channels.iter().map(|c| GateMagnitudes::of(&c.channel)).collect(),
+            noise: noise.map(|n| (n.background, n.channel)),
+            dynamics: None,
+        }
+    }
+
+    pub fn restore(&self, membrane: &mut Membrane, voltage: &mut MembraneVoltage, noise: Option<&mut MembraneNoise>) -> Result<(), String> {
+        apply_all(&self.channels, membrane.membrane_channels.iter_mut().map(|c| &mut c.channel))?;
+        voltage.0 = MilliVolts(self.v_mv);
+        if let (Some(noise), Some((background, channel))) = (noise, self.noise) {
+            noise.background = background;
+            noise.channel = channel;
+        }
+        Ok(())
+    }
+}
+
+impl SegmentDynamics {
+    pub fn capture(
+        intracellular: &Solution,
+        extracellular: Option<&ExtracellularSpace>,
+        shell: Option<&CalciumShell>,
+        rate: Option<&IntegrationRate>,
+    ) -> Self {
+        SegmentDynamics {
+            intracellular: intracellular.serialize(),
+            extracellular: extracellular.map(|space| space.0.serialize()),
+            calcium_molar: shell.map(|shell| shell.0 .0),
+            long_step: rate.map(|rate| rate.long_step()),
+        }
+    }
+
+    /// Restore onto the parts the segment has. Those it has only while an
+    /// ion dynamics setting is on keep their state if it was off when the
+    /// checkpoint was taken.
+    pub fn restore(
+        &self,
+        intracellular: &mut Solution,
+        extracellular: Option<&mut ExtracellularSpace>,
+        shell: Option<&mut CalciumShell>,
+        rate: Option<&mut IntegrationRate>,
+    ) -> Result<(), String> {
+        *intracellular = Solution::deserialize(&self.intracellular)?;
+        if let (Some(space), Some(saved)) = (extracellular, &self.extracellular) {
+            space.0 = Solution::deserialize(saved)?;
+        }
+        if let (Some(shell), Some(calcium_molar)) = (shell, self.calcium_molar) {
+            shell.0 = Molar(calcium_molar);
+        }
+        if let (Some(rate), Some((previous, pending))) = (rate, self.long_step) {
+            rate.resume(previous, pending);
+        }
+        Ok(())
+    }
+}
+
+impl SynapseState {
+    pub fn capture(index: usize, pre: SegmentRef, post: SegmentRef, membranes: &SynapseMembranes) -> Self {
+        SynapseState {
+            index,
+            pre,
+            post,
+            glutamate_molar: membranes.transmitter_concentrations.glutamate.0,
+            gaba_molar: membranes.transmitter_concentrations.gaba.0,
+            receptors: membranes.postsynaptic_receptors.iter().map(|r| GateMagnitudes::of(&r.membrane_channel.channel)).collect(),
+            weight: membranes.plasticity.as_ref().map(|p| p.weight),
+        }
+    }
+
+    pub fn restore(&self, membranes: &mut SynapseMembranes) -> Result<(), String> {
+        membranes.transmitter_concentrations.glutamate = Molar(self.glutamate_molar);
+        membranes.transmitter_concentrations.gaba = Molar(self.gaba_molar);
+        if let (Some(plasticity), Some(weight)) = (membranes.plasticity.as_mut(), self.weight) {
+            plasticity.weight = weight;
+        }
+        apply_all(&self.receptors, membranes.postsynaptic_receptors.iter_mut().map(|r| &mut r.membrane_channel.channel))
+    }
+}
+
+/// The saved state of each of the scene's synapses, given by their
+/// segments in scene order, or why there is none.
+fn saved_synapses<'a>(saved: &'a [SynapseState], running: &[(SegmentRef, SegmentRef)]) -> Vec<Result<&'a SynapseState, String>> {
+    let by_index: HashMap<usize, &SynapseState> = saved.iter().map(|s| (s.index, s)).collect();
+    running
+        .iter()
+        .enumerate()
+        .map(|(index, (pre, post))| match by_index.get(&index) {
+            Some(state) if state.pre == *pre && state.post == *post => Ok(*state),
+            Some(state) => Err(format!(
+                "synapse {} runs from {:?} to {:?}, but was saved from {:?} to {:?}",
+                index, pre, post, state.pre, state.post
+            )),
+            None => Err(format!("no state saved for synapse {}, from {:?} to {:?}", index, pre, post)),
+        })
+        .collect()
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| format!("Failed to serialize checkpoint: {}", e))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse checkpoint: {}", e))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(path: &str, checkpoint: &Checkpoint) -> Result<(), String> {
+    // Write then rename, so a crash mid-write leaves the old checkpoint.
+    let temporary = format!("{}.partial", path);
+    std::fs::write(&temporary, checkpoint.to_json()?).map_err(|e| format!("Failed to write {}: {}", temporary, e))?;
+    std::fs::rename(&temporary, path).map_err(|e| format!("Failed to save {}: {}", path, e))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(path: &str) -> Result<Checkpoint, String> {
+    Checkpoint::from_json(&std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(_path: &str, _checkpoint: &Checkpoint) -> Result<(), String> {
+    Err("Checkpoint files require a native build.".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read(_path: &str) -> Result<Checkpoint, String> {
+    Err("Checkpoint files require a native build.".to_string())
+}
+
+/// The state held by `Command::TakeSnapshot`, for `Command::RollBack` to
+/// return to, so interventions can be tried from the same point.
+#[derive(Debug, Default, Resource)]
+pub struct SnapshotBuffer(pub Option<Checkpoint>);
+
+type SegmentsQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static SceneSegment,
+        &'static mut Membrane,
+        &'static mut MembraneVoltage,
+        Option<&'static mut MembraneNoise>,
+        (&'static mut Solution, Option<&'static mut ExtracellularSpace>, Option<&'static mut CalciumShell>, Option<&'static mut IntegrationRate>),
+    ),
+>;
+
+fn capture(t: &Timestamp, steps: &StepCount, segments: &SegmentsQuery, synapses: &Query<&mut Synapse>) -> Checkpoint {
+    let refs: HashMap<Entity, SegmentRef> = segments.iter().map(|(e, s, ..)| (e, s.0)).collect();
+    Checkpoint {
+        t_seconds: t.0,
+        steps: steps.0,
+        segments: segments
+            .iter()
+            .map(|(_, s, m, v, noise, (solution, space, shell, rate))| SegmentState {
+                dynamics: Some(SegmentDynamics::capture(solution, space, shell, rate)),
+                ..SegmentState::capture(s.0, m, v, noise)
+            })
+            .collect(),
+        // In the order, and skipping the synapses, that the scene does.
+        synapses: synapses
+            .iter()
+            .filter_map(|synapse| Some((*refs.get(&synapse.pre_segment)?, *refs.get(&synapse.post_segment)?, synapse)))
+            .enumerate()
+            .map(|(index, (pre, post, synapse))| SynapseState::capture(index, pre, post, &synapse.synapse_membranes))
+            .collect(),
+    }
+}
+
+/// Restore `checkpoint` onto the scene, reporting what does not match as
+/// `command` did.
+fn restore(
+    command: &str,
+    checkpoint: &Checkpoint,
+    t: &mut Timestamp,
+    steps: &mut StepCount,
+    segments: &mut SegmentsQuery,
+    synapses: &mut Query<&mut Synapse>,
+) {
+    let saved: HashMap<SegmentRef, &SegmentState> = checkpoint.segments.iter().map(|s| (s.at, s)).collect();
+    let mut refs = HashMap::new();
+    for (entity, SceneSegment(at), mut membrane, mut voltage, noise, (mut solution, space, shell, rate)) in segments.iter_mut() {
+        refs.insert(entity, *at);
+        let Some(state) = saved.get(at) else {
+            eprintln!("{}: no state saved for {:?}", command, at);
+            continue;
+        };
+        if let Err(e) = state.restore(&mut membrane, &mut voltage, noise.map(|n| n.into_inner())) {
+            eprintln!("{}: segment {:?}: {}", command, at, e);
+        }
+        let restored = state.dynamics.as_ref().map_or(Ok(()), |dynamics| {
+            dynamics.restore(&mut solution, space.map(|s| s.into_inner()), shell.map(|s| s.into_inner()), rate.map(|r| r.into_inner()))
+        });
+        if let Err(e) = restored {
+            eprintln!("{}: segment {:?}: {}", command, at, e);
+        }
+    }
+    let mut running = synapses
+        .iter_mut()
+        .filter_map(|synapse| Some((*refs.get(&synapse.pre_segment)?, *refs.get(&synapse.post_segment)?, synapse)))
+        .collect::<Vec<_>>();
+    let pairs = running.iter().map(|(pre, post, _)| (*pre, *post)).collect::<Vec<_>>();
+    for ((_, _, synapse), saved) in running.iter_mut().zip(saved_synapses(&checkpoint.synapses, &pairs)) {
+        if let Err(e) = saved.and_then(|state| state.restore(&mut synapse.synapse_membranes)) {
+            eprintln!("{}: {}", command, e);
+        }
+    }
+    t.0 = checkpoint.t_seconds;
+    steps.0 = checkpoint.steps;
+}
+
+/// Handle the commands that save and restore dynamic state: to files with
+/// `SaveCheckpoint` and `RestoreCheckpoint`, and in memory with
+/// `TakeSnapshot` and `RollBack`.
+pub fn checkpoint_commands(
+    mut events: EventReader<Command>,
+    mut timestamp: ResMut<Timestamp>,
+    mut step_count: ResMut<StepCount>,
+    mut buffer: ResMut<SnapshotBuffer>,
+    mut segments: SegmentsQuery,
+    mut synapses: Query<&mut Synapse>,
+) {
+    for command in events.read() {
+        match command {
+            Command::SaveCheckpoint { path } => {
+                if let Err(e) = write(path, &capture(&timestamp, &step_count, &segments, &synapses)) {
+                    eprintln!("SaveCheckpoint: {}", e);
+                }
+            },
+            Command::RestoreCheckpoint { path } => match read(path) {
+                Ok(checkpoint) => {
+                    restore("RestoreCheckpoint", &checkpoint, &mut timestamp, &mut step_count, &mut segments, &mut synapses)
+                },
+                Err(e) => eprintln!("RestoreCheckpoint: {}", e),
+            },
+            Command::TakeSnapshot => buffer.0 = Some(capture(&timestamp, &step_count, &segments, &synapses)),
+            Command::RollBack => match &buffer.0 {
+                Some(checkpoint) => {
+                    restore("RollBack", checkpoint, &mut timestamp, &mut step_count, &mut segments, &mut synapses)
+                },
+                None => eprintln!("RollBack: no snapshot taken"),
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimension::FaradsPerSquareCm;
+    use crate::neuron::channel::common_channels::giant_squid::{K_CHANNEL, NA_CHANNEL};
+    use crate::neuron::membrane::MembraneChannel;
+    use crate::neuron::solution::{EXAMPLE_CYTOPLASM, INTERSTICIAL_FLUID};
+    use crate::neuron::synapse::examples::excitatory_synapse;
+    use crate::neuron::synapse::Plasticity;
+
+    fn membrane(v: &MilliVolts) -> Membrane {
+        Membrane {
+            membrane_channels: [NA_CHANNEL, K_CHANNEL]
+                .map(|builder| MembraneChannel { channel: builder.build(v), siemens_per_square_cm: 0.1 })
+                .to_vec(),
+            capacitance: FaradsPerSquareCm(1e-6),
+        }
+    }
+
+    #[test]
+    fn restores_voltage_and_gates() {
+        let at = SegmentRef { neuron: 0, segment: 1 };
+        let state = SegmentState::capture(at, &membrane(&MilliVolts(-20.0)), &MembraneVoltage(MilliVolts(-20.0)), None);
+        let json = Checkpoint { t_seconds: 0.5, steps: 10, segments: vec![state.clone()], synapses: vec![] }.to_json().unwrap();
+        let restored = Checkpoint::from_json(&json).unwrap();
+        assert_eq!(restored.segments, vec![state.clone()]);
+
+        let mut resting = membrane(&MilliVolts(-70.0));
+        let mut voltage = MembraneVoltage(MilliVolts(-70.0));
+        restored.segments[0].restore(&mut resting, &mut voltage, None).unwrap();
+        assert_eq!(SegmentState::capture(at, &resting, &voltage, None), state);
+
+        resting.membrane_channels.pop();
+        assert!(state.restore(&mut resting, &mut voltage, None).is_err());
+    }
+
+    #[test]
+    fn restores_concentrations_and_long_steps() {
+        let mut intracellular = EXAMPLE_CYTOPLASM;
+        intracellular.na_concentration = Molar(20e-3);
+        let mut space = ExtracellularSpace(INTERSTICIAL_FLUID);
+        space.0.k_concentration = Molar(9e-3);
+        let shell = CalciumShell(Molar(2e-7));
+        let mut rate = IntegrationRate::new(4).unwrap();
+        let mut v = -60.0;
+        rate.begin_step(&mut v);
+        rate.defer(1.5);
+        let saved = SegmentDynamics::capture(&intracellular, Some(&space), Some(&shell), Some(&rate));
+        let at = SegmentRef { neuron: 0, segment: 1 };
+        let state = SegmentState {
+            dynamics: Some(saved.clone()),
+            ..SegmentState::capture(at, &membrane(&MilliVolts(-60.0)), &MembraneVoltage(MilliVolts(v)), None)
+        };
+        let json = Checkpoint { t_seconds: 0.5, steps: 10, segments: vec![state], synapses: vec![] }.to_json().unwrap();
+        let restored = Checkpoint::from_json(&json).unwrap().segments[0].dynamics.clone().unwrap();
+        assert_eq!(restored, saved);
+
+        let mut fresh = (EXAMPLE_CYTOPLASM, ExtracellularSpace(INTERSTICIAL_FLUID), CalciumShell(Molar(5e-8)), IntegrationRate::new(4).unwrap());
+        restored.restore(&mut fresh.0, Some(&mut fresh.1), Some(&mut fresh.2), Some(&mut fresh.3)).unwrap();
+        assert_eq!(fresh, (intracellular, space, shell, rate));
+
+        // Checkpoints saved before segments had concentrations still load.
+        let old = r#"{"at":{"neuron":0,"segment":1},"v_mv":-60.0,"channels":[]}"#;
+        assert_eq!(serde_json::from_str::<SegmentState>(old).unwrap().dynamics, None);
+    }
+
+    #[test]
+    fn keys_synapses_by_their_position() {
+        let (a, b) = (SegmentRef { neuron: 0, segment: 1 }, SegmentRef { neuron: 1, segment: 1 });
+        let mut first = excitatory_synapse(&MilliVolts(-70.0));
+        first.transmitter_concentrations.glutamate = Molar(1e-3);
+        first.plasticity = Some(Plasticity { weight: 1.5, ..Plasticity::default() });
+        let mut second = first.clone();
+        second.transmitter_concentrations.glutamate = Molar(2e-3);
+        let saved = vec![SynapseState::capture(0, a, b, &first), SynapseState::capture(1, a, b, &second)];
+
+        // Two synapses between the same segments keep their own state.
+        let matched = saved_synapses(&saved, &[(a, b), (a, b)]);
+        let mut restored = excitatory_synapse(&MilliVolts(-70.0));
+        restored.plasticity = Some(Plasticity::default());
+        matched[1].clone().unwrap().restore(&mut restored).unwrap();
+        assert_eq!(SynapseState::capture(1, a, b, &restored), saved[1]);
+        assert_eq!(matched[0].clone().unwrap().weight, Some(1.5));
+
+        let moved = saved_synapses(&saved, &[(b, a), (a, b), (a, b)]);
+        assert!(moved[0].is_err() && moved[1].is_ok() && moved[2].is_err());
+    }
+}
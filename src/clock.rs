@@ -0,0 +1,81 @@
+use bevy::prelude::Resource;
+
+/// Seconds since the UNIX epoch, from the system clock.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_seconds_now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn unix_seconds_now() -> f64 {
+    js_sys::Date::now() * 0.001
+}
+
+/// Format seconds since the UNIX epoch as an ISO 8601 UTC timestamp with
+/// millisecond precision.
+pub fn iso8601(unix_seconds: f64) -> String {
+    let millis_total = (unix_seconds * 1000.0).round() as i64;
+    let days = millis_total.div_euclid(86_400_000);
+    let millis_of_day = millis_total.rem_euclid(86_400_000);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis_of_day / 3_600_000,
+        millis_of_day / 60_000 % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000
+    )
+}
+
+/// The wall-clock time at which the current run started. Exports record
+/// both this and simulation-relative times, so that recordings from
+/// several processes can be aligned afterwards.
+#[derive(Clone, Debug, Resource)]
+pub struct RunClock {
+    pub started_at_unix_seconds: f64,
+}
+
+impl Default for RunClock {
+    fn default() -> Self {
+        RunClock { started_at_unix_seconds: unix_seconds_now() }
+    }
+}
+
+impl RunClock {
+    pub fn restart(&mut self) {
+        self.started_at_unix_seconds = unix_seconds_now();
+    }
+
+    /// Wall-clock seconds elapsed since the run started.
+    pub fn elapsed_seconds(&self) -> f64 {
+        unix_seconds_now() - self.started_at_unix_seconds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_iso8601() {
+        assert_eq!(iso8601(0.0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(951_782_400.5), "2000-02-29T00:00:00.500Z");
+        assert_eq!(iso8601(1_700_000_000.0), "2023-11-14T22:13:20.000Z");
+    }
+}
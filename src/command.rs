@@ -0,0 +1,444 @@
+//! Commands that control the simulation, from the GUI or from remote
+//! controllers. Javascript clients send them as JSON through
+//! `gui::external_trigger::simulation_command`.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::RunClock;
+use crate::constants::SimulationConstants;
+use crate::dimension::{StepCount, Timestamp};
+use crate::integrations::grace::{spawn_stimulation, GraceScene, GraceSceneSender, SceneEntities, SceneSegment, SceneVisuals, Synapse};
+use crate::neuron::synapse::SynapseMembranes;
+use crate::morphology::{cut_branch, reattach_branch, scale_branch};
+use crate::neuron::opsin::{express, Opsin};
+use crate::plugin::SimulationState;
+use crate::replay::{Session, SessionRecorder};
+use crate::serialize;
+use crate::validate::validate_scene;
+use crate::stimulator::{Stimulation, Stimulator};
+
+#[derive(Clone, Debug, Deserialize, Event, Serialize)]
+#[serde(tag = "type")]
+pub enum Command {
+    /// Stop advancing simulated time.
+    Pause,
+    Resume,
+    /// Pause, then integrate `steps` steps, e.g. one step or one frame's.
+    Step {
+        steps: usize,
+    },
+    /// Integrate `speed` times the steps per frame, e.g. 0.1 for slow
+    /// motion.
+    SetSpeed {
+        speed: f32,
+    },
+    /// Respawn the scene, with its edits, in its initial state at time zero.
+    Reset,
+    /// Replace the scene with another, at time zero. Invalid scenes are
+    /// ignored; see `validate::validate_scene`.
+    LoadScene {
+        scene: serialize::Scene,
+    },
+    /// Stimulate a segment, given by the index of its neuron in the scene
+    /// and its SWC id, replacing any stimulator it already has.
+    SetStimulator {
+        neuron: usize,
+        segment: i32,
+        stimulator: serialize::Stimulator,
+    },
+    /// Connect two different segments, each given by its neuron's index in
+    /// the scene and its SWC id.
+    AddSynapse {
+        pre: serialize::SegmentRef,
+        post: serialize::SegmentRef,
+        synapse_membranes: serialize::SynapseMembranes,
+    },
+    /// Remove every synapse from `pre` onto `post`.
+    RemoveSynapse {
+        pre: serialize::SegmentRef,
+        post: serialize::SegmentRef,
+    },
+    /// Save the dynamic state (time, voltages, gates, transmitter
+    /// concentrations) to a file; see `checkpoint`.
+    SaveCheckpoint {
+        path: String,
+    },
+    /// Restore dynamic state saved by `SaveCheckpoint` onto the same scene.
+    RestoreCheckpoint {
+        path: String,
+    },
+    /// Apply the membrane and synapse parameters of `scene`, which has the
+    /// running scene's segments and synapses, without respawning it. With
+    /// `reset_state`, voltages, gates and time start over too; see
+    /// `warm_restart`.
+    WarmRestart {
+        scene: serialize::Scene,
+        #[serde(default)]
+        reset_state: bool,
+    },
+    /// Hold the dynamic state in memory, replacing any snapshot held.
+    TakeSnapshot,
+    /// Return to the state held by `TakeSnapshot`, keeping the snapshot
+    /// to roll back to again.
+    RollBack,
+    /// Remove a branch (a segment and everything distal to it), with its
+    /// stimulators and synapses. Like the other morphology edits, this
+    /// respawns the edited scene at time zero; see `morphology`.
+    CutBranch {
+        at: serialize::SegmentRef,
+    },
+    /// Move the branch at `at` onto `to`, in the same neuron.
+    ReattachBranch {
+        at: serialize::SegmentRef,
+        to: serialize::SegmentRef,
+    },
+    /// Multiply the radii of the branch at `at` by `factor`.
+    ScaleBranch {
+        at: serialize::SegmentRef,
+        factor: f32,
+    },
+    /// Express a light-gated channel in the membrane of `at`'s type, in
+    /// every segment of that type in its neuron; see `neuron::opsin::express`.
+    /// Respawns the edited scene at time zero.
+    ExpressOpsin {
+        at: serialize::SegmentRef,
+        opsin: serialize::Opsin,
+        siemens_per_square_cm: f32,
+    },
+    /// Apply `command` once simulated time reaches `t_seconds`. Commands
+    /// take effect between frames, so up to a frame's steps late.
+    At {
+        t_seconds: f32,
+        command: Box<Command>,
+    },
+    /// Remove the stimulator from a segment, if it has one.
+    RemoveStimulator {
+        at: serialize::SegmentRef,
+    },
+    SetConstants {
+        constants: serialize::SimulationConstants,
+    },
+    /// Restart the scene at time zero and record the session from there;
+    /// see `replay`.
+    StartRecording,
+    /// Stop recording, keeping the session for saving or replaying, or stop
+    /// a replay.
+    StopRecording,
+    /// Load a recorded session's scene at time zero and replay its
+    /// interventions. Invalid scenes are ignored, as by `LoadScene`.
+    ReplaySession {
+        session: Session,
+    },
+    /// Inject membrane noise into every neuron tagged `tag`, or every
+    /// neuron, or stop it with `None`, without restarting; see `noise`.
+    SetNoise {
+        #[serde(default)]
+        tag: Option<String>,
+        noise: Option<serialize::MembraneNoise>,
+    },
+}
+
+#[derive(Debug, Resource)]
+pub struct SimulationControl {
+    pub paused: bool,
+    /// The fraction of the steps per frame integrated while running.
+    pub speed: f32,
+    /// Steps still to integrate while paused, from `Command::Step`.
+    pending_steps: usize,
+    /// Steps owed at speeds below one, carried between frames.
+    partial_steps: f32,
+    /// The scene to respawn once the current one has been despawned.
+    resetting: Option<serialize::Scene>,
+    /// Commands waiting for their simulation time, from `Command::At`.
+    scheduled: Vec<(f32, Command)>,
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        SimulationControl {
+            paused: false,
+            speed: 1.0,
+            pending_steps: 0,
+            partial_steps: 0.0,
+            resetting: None,
+            scheduled: vec![],
+        }
+    }
+}
+
+impl SimulationControl {
+    /// How many steps to integrate this frame, at `steps_per_frame` for
+    /// full speed.
+    pub fn take_steps(&mut self, steps_per_frame: usize) -> usize {
+        if self.paused {
+            return std::mem::take(&mut self.pending_steps);
+        }
+        self.partial_steps += self.speed * steps_per_frame as f32;
+        let steps = self.partial_steps.floor();
+        self.partial_steps -= steps;
+        steps as usize
+    }
+}
+
+/// Run condition for the integration step.
+pub fn simulation_running(control: Res<SimulationControl>) -> bool {
+    !control.paused || control.pending_steps > 0
+}
+
+/// Send the scheduled commands whose time has come, in time order.
+pub fn release_scheduled_commands(
+    mut control: ResMut<SimulationControl>,
+    timestamp: Res<Timestamp>,
+    mut events: EventWriter<Command>,
+) {
+    if control.scheduled.is_empty() {
+        return;
+    }
+    let (mut due, pending): (Vec<_>, Vec<_>) =
+        std::mem::take(&mut control.scheduled).into_iter().partition(|(t, _)| *t <= timestamp.0);
+    control.scheduled = pending;
+    due.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    events.send_batch(due.into_iter().map(|(_, command)| command));
+}
+
+/// The clocks a reset or a new scene restarts.
+#[derive(SystemParam)]
+pub struct CommandClock<'w> {
+    pub timestamp: ResMut<'w, Timestamp>,
+    pub step_count: ResMut<'w, StepCount>,
+    pub run_clock: ResMut<'w, RunClock>,
+}
+
+/// The scene commands act on, and its loader.
+#[derive(SystemParam)]
+pub struct CommandTargets<'w, 's> {
+    pub grace_scene_sender: Option<Res<'w, GraceSceneSender>>,
+    pub simulation_state: SimulationState<'w, 's>,
+    pub scene_entities: Query<'w, 's, Entity, SceneEntities>,
+    pub visuals: SceneVisuals<'w, 's>,
+    pub segments: Query<'w, 's, (Entity, &'static SceneSegment, &'static GlobalTransform)>,
+    pub stimulations: Query<'w, 's, (Entity, &'static Stimulation)>,
+    pub synapses: Query<'w, 's, (Entity, &'static Synapse)>,
+}
+
+pub fn apply_commands(
+    mut commands: Commands,
+    mut events: EventReader<Command>,
+    mut control: ResMut<SimulationControl>,
+    clock: CommandClock,
+    mut constants: ResMut<SimulationConstants>,
+    targets: CommandTargets,
+    mut recorder: ResMut<SessionRecorder>,
+) {
+    let CommandClock { mut timestamp, mut step_count, mut run_clock } = clock;
+    let CommandTargets { grace_scene_sender, simulation_state, scene_entities, mut visuals, segments, stimulations, synapses } = targets;
+    if scene_entities.is_empty() {
+        if let (Some(scene), Some(sender)) = (control.resetting.take(), &grace_scene_sender) {
+            sender.0.send(GraceScene(scene)).expect("Send should succeed");
+        }
+    }
+
+    let find_segment = |at: serialize::SegmentRef| {
+        segments.iter().find(|(_, SceneSegment(s), _)| *s == at).map(|(entity, _, _)| entity)
+    };
+    for command in events.read() {
+        recorder.record(command, &timestamp);
+        match command {
+            Command::Pause => control.paused = true,
+            Command::Resume => control.paused = false,
+            Command::Step { steps } => {
+                control.paused = true;
+                control.pending_steps += steps;
+            },
+            Command::SetSpeed { speed } => {
+                if speed.is_finite() && *speed > 0.0 {
+                    control.speed = *speed;
+                } else {
+                    eprintln!("SetSpeed: speed must be positive, not {}", speed);
+                }
+            },
+            Command::Reset
+            | Command::LoadScene { .. }
+            | Command::StartRecording
+            | Command::ReplaySession { .. }
+            | Command::CutBranch { .. }
+            | Command::ReattachBranch { .. }
+            | Command::ScaleBranch { .. }
+            | Command::ExpressOpsin { .. } => {
+                let scene = match command {
+                    Command::LoadScene { scene } | Command::ReplaySession { session: Session { scene, .. } } => {
+                        let errors = validate_scene(scene);
+                        if !errors.is_empty() {
+                            eprintln!("LoadScene: invalid scene: {}", errors.join("; "));
+                            continue;
+                        }
+                        if let Command::ReplaySession { session } = command {
+                            recorder.replay(session.clone());
+                        }
+                        Some(scene.clone())
+                    },
+                    Command::Reset => {
+                        (!simulation_state.current_scene.0.neurons.is_empty()).then(|| simulation_state.serialize(&constants))
+                    },
+                    Command::StartRecording => {
+                        let scene = simulation_state.serialize(&constants);
+                        recorder.start_recording(scene.clone(), control.paused);
+                        (!scene.neurons.is_empty()).then_some(scene)
+                    },
+                    edit => {
+                        let mut scene = simulation_state.serialize(&constants);
+                        let edited = match edit {
+                            Command::CutBranch { at } => cut_branch(&mut scene, *at),
+                            Command::ReattachBranch { at, to } => reattach_branch(&mut scene, *at, *to),
+                            Command::ScaleBranch { at, factor } => scale_branch(&mut scene, *at, *factor),
+                            Command::ExpressOpsin { at, opsin, siemens_per_square_cm } => {
+                                express(&mut scene, *at, Opsin::deserialize(opsin), *siemens_per_square_cm)
+                            },
+                            _ => unreachable!("only scene edits are left"),
+                        };
+                        if let Err(e) = edited {
+                            eprintln!("{:?}: {}", edit, e);
+                            continue;
+                        }
+                        Some(scene)
+                    },
+                };
+                timestamp.0 = 0.0;
+                step_count.0 = 0;
+                control.partial_steps = 0.0;
+                run_clock.restart();
+                // Without a scene loader, only the clock can be reset.
+                if grace_scene_sender.is_some() && scene.is_some() {
+                    control.resetting = scene;
+                    for entity in &scene_entities {
+                        commands.entity(entity).despawn();
+                    }
+                }
+            },
+            Command::SetStimulator { neuron, segment, stimulator } => {
+                let at = serialize::SegmentRef { neuron: *neuron, segment: *segment };
+                let Some(entity) = find_segment(at) else {
+                    eprintln!("SetStimulator: no segment {} in neuron {}", segment, neuron);
+                    continue;
+                };
+                if !stimulations.iter().any(|(_, s)| s.stimulation_segment == entity) {
+                    let translation = segments.get(entity).map_or(Vec3::ZERO, |(_, _, t)| t.translation());
+                    spawn_stimulation(&mut commands, &mut visuals, entity, translation);
+                }
+                commands.entity(entity).insert(Stimulator::deserialize(stimulator));
+            },
+            Command::AddSynapse { pre, post, synapse_membranes } => {
+                let (Some(pre_segment), Some(post_segment)) = (find_segment(*pre), find_segment(*post)) else {
+                    eprintln!("AddSynapse: no segment at {:?} or {:?}", pre, post);
+                    continue;
+                };
+                if pre_segment == post_segment {
+                    eprintln!("AddSynapse: segment {:?} cannot synapse onto itself", pre);
+                    continue;
+                }
+                match SynapseMembranes::deserialize(synapse_membranes) {
+                    Ok(synapse_membranes) => {
+                        commands.spawn(Synapse { pre_segment, post_segment, synapse_membranes });
+                    },
+                    Err(e) => eprintln!("AddSynapse: {}", e),
+                }
+            },
+            Command::RemoveSynapse { pre, post } => {
+                let (Some(pre_segment), Some(post_segment)) = (find_segment(*pre), find_segment(*post)) else {
+                    continue;
+                };
+                for (entity, synapse) in &synapses {
+                    if synapse.pre_segment == pre_segment && synapse.post_segment == post_segment {
+                        commands.entity(entity).despawn();
+                    }
+                }
+            },
+            Command::RemoveStimulator { at } => {
+                let Some(entity) = find_segment(*at) else {
+                    eprintln!("RemoveStimulator: no segment at {:?}", at);
+                    continue;
+                };
+                commands.entity(entity).remove::<Stimulator>();
+                for (marker, stimulation) in &stimulations {
+                    if stimulation.stimulation_segment == entity {
+                        commands.entity(marker).despawn();
+                    }
+                }
+            },
+            Command::SetConstants { constants: new_constants } => {
+                *constants = SimulationConstants::deserialize(new_constants);
+            },
+            Command::StopRecording => recorder.stop(),
+            // Handled by `checkpoint::checkpoint_commands`.
+            Command::SaveCheckpoint { .. }
+            | Command::RestoreCheckpoint { .. }
+            | Command::TakeSnapshot
+            | Command::RollBack => {},
+            // Handled by `noise::noise_commands`.
+            Command::SetNoise { .. } => {},
+            // Handled by `warm_restart::warm_restart_commands`.
+            Command::WarmRestart { .. } => {},
+            Command::At { t_seconds, command } => control.scheduled.push((*t_seconds, (**command).clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lifecycle_commands() {
+        let parsed = ["Pause", "Resume", "Reset"]
+            .map(|t| serde_json::from_str::<Command>(&format!("{{\"type\": \"{}\"}}", t)).unwrap());
+        assert!(matches!(parsed, [Command::Pause, Command::Resume, Command::Reset]));
+        assert!(serde_json::from_str::<Command>("{\"type\": \"Stop\"}").is_err());
+    }
+
+    #[test]
+    fn steps_at_speed_and_while_paused() {
+        let mut control = SimulationControl { speed: 0.1, ..default() };
+        let steps = (0..20).map(|_| control.take_steps(15)).collect::<Vec<_>>();
+        assert_eq!(steps.iter().sum::<usize>(), 30);
+        assert!(steps.iter().all(|s| *s <= 2));
+
+        control.paused = true;
+        control.pending_steps = 1;
+        assert_eq!(control.take_steps(15), 1);
+        assert_eq!(control.take_steps(15), 0);
+    }
+
+    #[test]
+    fn parses_stimulator_settings() {
+        let json = r#"{
+            "type": "SetStimulator",
+            "neuron": 0,
+            "segment": 3,
+            "stimulator": {
+                "envelope": {"period_sec": 0.1, "onset_sec": 0.0, "offset_sec": 0.05},
+                "current_shape": {
+                    "type": "SquareWave",
+                    "on_current_uamps_per_square_cm": 50.0,
+                    "off_current_uamps_per_square_cm": 0.0
+                }
+            }
+        }"#;
+        let Command::SetStimulator { neuron, segment, stimulator } = serde_json::from_str(json).unwrap() else {
+            panic!("Expected SetStimulator");
+        };
+        assert_eq!((neuron, segment), (0, 3));
+        assert_eq!(stimulator.envelope.offset_sec, 0.05);
+    }
+
+    #[test]
+    fn parses_scheduled_commands() {
+        let json = r#"{"type": "At", "t_seconds": 0.05, "command": {"type": "At", "t_seconds": 0.1, "command": {"type": "Pause"}}}"#;
+        let Command::At { t_seconds, command } = serde_json::from_str(json).unwrap() else {
+            panic!("Expected At");
+        };
+        assert_eq!(t_seconds, 0.05);
+        assert!(matches!(*command, Command::At { command: ref inner, .. } if matches!(**inner, Command::Pause)));
+    }
+}
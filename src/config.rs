@@ -0,0 +1,128 @@
+//! Startup settings read from a TOML file, so that native runs can change
+//! their step size or initial scene without a rebuild.
+//!
+//! Native builds read `nb-sim.toml` from the working directory, or the
+//! file named by `NB_SIM_CONFIG`; see `sample_data/nb-sim.toml`. Every
+//! setting is optional. Apps embedding the simulation can insert their
+//! own `Config` before adding `BiophysicsPlugin`.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::command::Command;
+use crate::action::Action;
+use crate::integrations::dhall::read_scene;
+use crate::replay::read_session;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The integration interval.
+    pub simulation_step_seconds: f32,
+    /// Integration steps per rendered frame.
+    pub steps_per_frame: usize,
+    /// A JSON or Dhall scene to load at startup.
+    pub scene: Option<String>,
+    /// A session saved from the recorder to replay at startup, in place of
+    /// `scene`; see `replay`.
+    pub replay: Option<String>,
+    /// See `notify::Notifications`; `NB_SIM_WEBHOOK_URL` takes precedence.
+    pub webhook_url: Option<String>,
+    /// See `notify::Notifications`; `NB_SIM_RUN_SECONDS` takes precedence.
+    pub run_seconds: Option<f32>,
+    /// Keyboard shortcuts replacing the defaults, by action; see
+    /// `gui::palette`. Parsed, but unused, without the `render` feature.
+    pub shortcuts: HashMap<Action, String>,
+    /// Serve `proto/nb_sim.proto` here, e.g. `127.0.0.1:50051`, in builds
+    /// with the `grpc` feature; see `grpc`.
+    pub grpc_address: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            simulation_step_seconds: 5e-7,
+            steps_per_frame: 100,
+            scene: None,
+            replay: None,
+            webhook_url: None,
+            run_seconds: None,
+            shortcuts: HashMap::new(),
+            grpc_address: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(source).map_err(|e| format!("Invalid config: {}", e))?;
+        if !(config.simulation_step_seconds.is_finite() && config.simulation_step_seconds > 0.0) {
+            return Err(format!("Invalid config: simulation_step_seconds is {}", config.simulation_step_seconds));
+        }
+        Ok(config)
+    }
+
+    /// The config file, if there is one. A missing `nb-sim.toml` is not an
+    /// error, but a missing `NB_SIM_CONFIG` is.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Result<Self, String> {
+        let (path, required) = match std::env::var("NB_SIM_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => ("nb-sim.toml".to_string(), false),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(source) => Config::parse(&source).map_err(|e| format!("{}: {}", path, e)),
+            Err(e) if required => Err(format!("Failed to read {}: {}", path, e)),
+            Err(_) => Ok(Config::default()),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Result<Self, String> {
+        Ok(Config::default())
+    }
+}
+
+/// Load the configured scene, or replay the configured session, if any,
+/// once the app starts.
+pub fn load_configured_scene(config: Res<Config>, mut commands: EventWriter<Command>) {
+    if let Some(path) = &config.replay {
+        match read_session(path) {
+            Ok(session) => {
+                commands.send(Command::ReplaySession { session });
+                return;
+            },
+            Err(e) => eprintln!("Failed to load the configured session: {}", e),
+        }
+    }
+    let Some(path) = &config.scene else {
+        return;
+    };
+    match read_scene(path) {
+        Ok(scene) => {
+            commands.send(Command::LoadScene { scene });
+        },
+        Err(e) => eprintln!("Failed to load the configured scene: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_sample_config() {
+        let config = Config::parse(include_str!("../sample_data/nb-sim.toml")).unwrap();
+        assert_eq!(config.steps_per_frame, 200);
+        assert_eq!(config.scene.as_deref(), Some("sample_data/sample_scene.json"));
+        assert_eq!(config.webhook_url, None);
+        assert_eq!(config.shortcuts.get(&Action::TogglePause).map(String::as_str), Some("Space"));
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("steps_per_frames = 10").is_err());
+        assert!(Config::parse("simulation_step_seconds = 0.0").is_err());
+        assert!(Config::parse("[shortcuts]\nFly = \"F\"").is_err());
+    }
+}
@@ -1,11 +1,83 @@
-use crate::dimension::Kelvin;
+use bevy::prelude::Resource;
+
+use crate::dimension::{Kelvin, MilliVolts, Molar};
+use crate::neuron::channel::reversal_potential_with;
+use crate::neuron::solution::Solution;
+use crate::serialize;
 
 pub const GAS_CONSTANT: f32 = 8.314;
 pub const BODY_TEMPERATURE: Kelvin = Kelvin(310.0);
-pub const INVERSE_FARADAY: f32 = 1.0 / 96485.3;
+pub const FARADAY: f32 = 96485.3;
+pub const INVERSE_FARADAY: f32 = 1.0 / FARADAY;
 
-pub const CONDUCTANCE_PER_SQUARE_CM: f32 = 10.000; // TODO: Figure this out.
+/// Resistivity of cytoplasm (Ω cm), as commonly used for mammalian
+/// neurons.
+pub const AXIAL_RESISTIVITY_OHM_CM: f32 = 100.0;
 
 pub const EPSILON: f32 = 1e-3;
 
-pub const SIMULATION_STEPS_PER_FRAME: usize = 100;
+/// Physical constants of the simulated preparation. These default to a
+/// mammalian preparation at body temperature, and are saved with the
+/// scene so that other preparations can override them.
+#[derive(Clone, Debug, Resource)]
+pub struct SimulationConstants {
+    pub temperature: Kelvin,
+    /// J / (mol K).
+    pub gas_constant: f32,
+    /// C / mol.
+    pub faraday: f32,
+    /// Resistivity of the cytoplasm (Ω cm), which sets the conductance
+    /// between neighboring segments from their geometry.
+    pub axial_resistivity_ohm_cm: f32,
+}
+
+impl Default for SimulationConstants {
+    fn default() -> Self {
+        SimulationConstants {
+            temperature: BODY_TEMPERATURE,
+            gas_constant: GAS_CONSTANT,
+            faraday: FARADAY,
+            axial_resistivity_ohm_cm: AXIAL_RESISTIVITY_OHM_CM,
+        }
+    }
+}
+
+impl SimulationConstants {
+    pub fn reversal_potential(&self, internal: &Molar, external: &Molar, valence: i8) -> MilliVolts {
+        reversal_potential_with(internal, external, &self.temperature, valence, self.gas_constant, self.faraday)
+    }
+
+    pub fn k_reversal(&self, internal: &Solution, external: &Solution) -> MilliVolts {
+        self.reversal_potential(&internal.k_concentration, &external.k_concentration, 1)
+    }
+
+    pub fn na_reversal(&self, internal: &Solution, external: &Solution) -> MilliVolts {
+        self.reversal_potential(&internal.na_concentration, &external.na_concentration, 1)
+    }
+
+    pub fn ca_reversal(&self, internal: &Solution, external: &Solution) -> MilliVolts {
+        self.reversal_potential(&internal.ca_concentration, &external.ca_concentration, 2)
+    }
+
+    pub fn cl_reversal(&self, internal: &Solution, external: &Solution) -> MilliVolts {
+        self.reversal_potential(&internal.cl_concentration, &external.cl_concentration, -1)
+    }
+
+    pub fn serialize(&self) -> serialize::SimulationConstants {
+        serialize::SimulationConstants {
+            temperature_kelvin: self.temperature.0,
+            gas_constant: self.gas_constant,
+            faraday: self.faraday,
+            axial_resistivity_ohm_cm: self.axial_resistivity_ohm_cm,
+        }
+    }
+
+    pub fn deserialize(c: &serialize::SimulationConstants) -> Self {
+        SimulationConstants {
+            temperature: Kelvin(c.temperature_kelvin),
+            gas_constant: c.gas_constant,
+            faraday: c.faraday,
+            axial_resistivity_ohm_cm: c.axial_resistivity_ohm_cm,
+        }
+    }
+}
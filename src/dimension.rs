@@ -24,10 +24,15 @@ impl P3 {
     }
 }
 
-/// Seconds since UNIX epoch.
+/// Simulated time, in seconds since the start of the run. See
+/// `clock::RunClock` for the corresponding wall-clock time.
 #[derive(Debug, Clone, Resource)]
 pub struct Timestamp(pub f32);
 
+/// Integration steps taken since the start of the run.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct StepCount(pub u64);
+
 #[derive(Debug, Clone, Resource)]
 pub struct StepsPerFrame(pub usize);
 
@@ -0,0 +1,279 @@
+//! Evolving a membrane's channel composition toward a firing phenotype.
+//!
+//! A genome says, for each candidate channel, whether the membrane has it
+//! and at what density. Each generation is scored by running every genome
+//! headless, spread over threads, and measuring the soma's firing: its
+//! rate, how much its interspike intervals lengthen (adaptation), and how
+//! many spikes follow the end of a stimulus (rebound). The next generation
+//! keeps the best genomes and breeds the rest from tournament winners by
+//! uniform crossover and mutation.
+
+use crate::analysis::{Trace, SPIKE_THRESHOLD};
+use crate::headless::soma_traces;
+use crate::rng::Rng;
+use crate::serialize::{MembraneChannel, Scene};
+use crate::sweep::par_map;
+
+/// Firing to evolve toward. Features left `None` are not scored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Phenotype {
+    pub rate_hz: Option<f32>,
+    /// Mean of (ISIₙ₊₁ − ISIₙ) / (ISIₙ₊₁ + ISIₙ): 0 for regular firing,
+    /// positive when firing slows down.
+    pub adaptation: Option<f32>,
+    /// Spikes at or after `rebound_from_seconds`.
+    pub rebound_spikes: Option<usize>,
+    pub rebound_from_seconds: f32,
+}
+
+impl Phenotype {
+    /// The features of a somatic trace. All are reported; adaptation is
+    /// zero with fewer than three spikes.
+    pub fn measure(trace: &Trace, rebound_from_seconds: f32) -> Phenotype {
+        let spikes = trace.spike_times(&SPIKE_THRESHOLD);
+        let duration = trace.times.last().copied().unwrap_or(0.0) - trace.times.first().copied().unwrap_or(0.0);
+        let intervals = spikes.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+        let ratios = intervals.windows(2).map(|w| (w[1] - w[0]) / (w[1] + w[0])).collect::<Vec<_>>();
+        Phenotype {
+            rate_hz: Some(if duration > 0.0 { spikes.len() as f32 / duration } else { 0.0 }),
+            adaptation: Some(if ratios.is_empty() { 0.0 } else { ratios.iter().sum::<f32>() / ratios.len() as f32 }),
+            rebound_spikes: Some(spikes.iter().filter(|t| **t >= rebound_from_seconds).count()),
+            rebound_from_seconds,
+        }
+    }
+
+    /// How far `measured` is from this target, summing the squared
+    /// differences of the targeted features, each on its own scale.
+    pub fn distance(&self, measured: &Phenotype) -> f64 {
+        let term = |target: Option<f32>, value: Option<f32>, scale: f32| match (target, value) {
+            (Some(target), Some(value)) => (((value - target) / scale) as f64).powi(2),
+            (Some(_), None) => f64::INFINITY,
+            (None, _) => 0.0,
+        };
+        term(self.rate_hz, measured.rate_hz, self.rate_hz.unwrap_or(0.0).max(1.0))
+            + term(self.adaptation, measured.adaptation, 0.1)
+            + term(
+                self.rebound_spikes.map(|n| n as f32),
+                measured.rebound_spikes.map(|n| n as f32),
+                self.rebound_spikes.unwrap_or(0).max(1) as f32,
+            )
+    }
+}
+
+/// Whether the membrane has one candidate channel, and at what density.
+/// An absent channel keeps its density, to come back at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gene {
+    pub present: bool,
+    /// Natural log of the density (S/cm²).
+    pub log_density: f64,
+}
+
+pub type Genome = Vec<Gene>;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub population: usize,
+    pub generations: usize,
+    /// Genomes carried over unchanged to the next generation.
+    pub elites: usize,
+    pub tournament: usize,
+    /// Chance per gene of a mutation.
+    pub mutation_rate: f64,
+    /// Standard deviation of a density mutation, in natural log units.
+    pub mutation_sigma: f64,
+    /// Chance that a mutation adds or removes the channel instead.
+    pub toggle_rate: f64,
+    pub parallel: bool,
+    pub seed: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            population: 24,
+            generations: 20,
+            elites: 2,
+            tournament: 3,
+            mutation_rate: 0.3,
+            mutation_sigma: 0.5,
+            toggle_rate: 0.1,
+            parallel: true,
+            seed: 1,
+        }
+    }
+}
+
+fn mutate(genome: &mut [Gene], settings: &Settings, rng: &mut Rng) {
+    for gene in genome.iter_mut() {
+        if rng.uniform() >= settings.mutation_rate {
+            continue;
+        }
+        if rng.uniform() < settings.toggle_rate {
+            gene.present = !gene.present;
+        } else {
+            gene.log_density += settings.mutation_sigma * rng.normal();
+        }
+    }
+}
+
+/// Evolve genomes descended from `start` to minimize `cost`, returning the
+/// best genome, its cost, and the best cost of each generation.
+pub fn evolve(
+    start: &[Gene],
+    cost: impl Fn(&Genome) -> f64 + Sync,
+    settings: &Settings,
+) -> (Genome, f64, Vec<f64>) {
+    let mut rng = Rng::new(settings.seed);
+    let population = settings.population.max(2);
+    let mut genomes = vec![start.to_vec()];
+    while genomes.len() < population {
+        let mut genome = start.to_vec();
+        mutate(&mut genome, &Settings { mutation_rate: 1.0, ..settings.clone() }, &mut rng);
+        genomes.push(genome);
+    }
+
+    let mut history = vec![];
+    let score = |genomes: Vec<Genome>| {
+        let costs = par_map(&genomes, settings.parallel, |genome| {
+            let cost = cost(genome);
+            if cost.is_nan() { f64::INFINITY } else { cost }
+        });
+        let mut scored = genomes.into_iter().zip(costs).collect::<Vec<_>>();
+        scored.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        scored
+    };
+    let mut scored = score(genomes);
+    history.push(scored[0].1);
+    for _ in 1..settings.generations.max(1) {
+        let mut next = scored.iter().take(settings.elites).map(|(g, _)| g.clone()).collect::<Vec<_>>();
+        let pick = |rng: &mut Rng| {
+            // The sample is sorted by cost, so the lowest index wins.
+            let winner = (0..settings.tournament.max(1)).map(|_| rng.below(scored.len())).min().unwrap_or(0);
+            &scored[winner].0
+        };
+        while next.len() < population {
+            let (a, b) = (pick(&mut rng).clone(), pick(&mut rng).clone());
+            let mut child: Genome = a.iter().zip(&b).map(|(a, b)| if rng.uniform() < 0.5 { *a } else { *b }).collect();
+            mutate(&mut child, settings, &mut rng);
+            next.push(child);
+        }
+        scored = score(next);
+        history.push(scored[0].1);
+    }
+    let (best, cost) = scored.swap_remove(0);
+    (best, cost, history)
+}
+
+/// Evolving one membrane of a scene.
+#[derive(Clone, Debug)]
+pub struct MembraneSearch {
+    pub scene: Scene,
+    /// The neuron whose soma is measured.
+    pub neuron: usize,
+    /// The membrane evolved, by its position in the neuron's membranes.
+    pub membrane: usize,
+    /// Channels the membrane may gain, each at the density it would be
+    /// added at. The membrane's own channels are always candidates.
+    pub extra_candidates: Vec<MembraneChannel>,
+    pub target: Phenotype,
+    pub duration_seconds: f32,
+    pub step_seconds: f32,
+    pub steps_per_sample: usize,
+}
+
+impl MembraneSearch {
+    fn candidates(&self) -> Result<Vec<MembraneChannel>, String> {
+        let membrane = self
+            .scene
+            .neurons
+            .get(self.neuron)
+            .and_then(|n| n.neuron.membranes.get(self.membrane))
+            .ok_or(format!("Neuron {} has no membrane {}", self.neuron, self.membrane))?;
+        Ok(membrane.membrane_channels.iter().chain(&self.extra_candidates).cloned().collect())
+    }
+
+    /// The membrane's own channels, followed by the absent extra ones.
+    pub fn start(&self) -> Result<Genome, String> {
+        let candidates = self.candidates()?;
+        let own = candidates.len() - self.extra_candidates.len();
+        Ok(candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Gene { present: i < own, log_density: (c.siemens_per_square_cm.max(1e-9) as f64).ln() })
+            .collect())
+    }
+
+    /// The scene with the membrane's channels set from `genome`.
+    pub fn scene(&self, genome: &[Gene]) -> Result<Scene, String> {
+        let candidates = self.candidates()?;
+        let mut scene = self.scene.clone();
+        scene.neurons[self.neuron].neuron.membranes[self.membrane].membrane_channels = candidates
+            .into_iter()
+            .zip(genome)
+            .filter(|(_, gene)| gene.present)
+            .map(|(candidate, gene)| MembraneChannel {
+                channel: candidate.channel,
+                siemens_per_square_cm: gene.log_density.exp() as f32,
+                density: None,
+            })
+            .collect();
+        Ok(scene)
+    }
+
+    pub fn measure(&self, genome: &[Gene]) -> Result<Phenotype, String> {
+        let traces = soma_traces(self.scene(genome)?, self.duration_seconds, self.step_seconds, self.steps_per_sample)?;
+        let trace = traces.get(self.neuron).filter(|t| !t.is_empty()).ok_or("The neuron has no soma".to_string())?;
+        Ok(Phenotype::measure(trace, self.target.rebound_from_seconds))
+    }
+
+    /// Evolve the membrane, returning the best scene found, its distance
+    /// from the target, and the best distance of each generation.
+    pub fn run(&self, settings: &Settings) -> Result<(Scene, f64, Vec<f64>), String> {
+        let start = self.start()?;
+        if start.is_empty() {
+            return Err("There are no candidate channels".to_string());
+        }
+        let cost = |genome: &Genome| self.measure(genome).map_or(f64::INFINITY, |m| self.target.distance(&m));
+        let (best, distance, history) = evolve(&start, cost, settings);
+        Ok((self.scene(&best)?, distance, history))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_adapting_firing() {
+        let mut trace = Trace::new("soma".to_string());
+        // Spikes at 10, 30, 60 and 100 ms, then one at 150 ms after the
+        // stimulus ends at 120 ms.
+        for i in 0..=200 {
+            let t = i as f32 * 0.001;
+            let spiking = [10, 30, 60, 100, 150].contains(&i);
+            trace.push(t, if spiking { 20.0 } else { -70.0 });
+        }
+        let measured = Phenotype::measure(&trace, 0.12);
+        assert_eq!(measured.rate_hz, Some(25.0));
+        assert!(measured.adaptation.unwrap() > 0.0);
+        assert_eq!(measured.rebound_spikes, Some(1));
+        assert_eq!(Phenotype { rebound_from_seconds: 0.12, ..measured.clone() }.distance(&measured), 0.0);
+    }
+
+    #[test]
+    fn evolves_toward_lower_cost() {
+        // Wants the first channel at density e², and the second absent.
+        let cost = |genome: &Genome| {
+            let first = if genome[0].present { (genome[0].log_density - 2.0).powi(2) } else { 10.0 };
+            first + if genome[1].present { 1.0 } else { 0.0 }
+        };
+        let start = vec![Gene { present: true, log_density: 0.0 }; 2];
+        let settings = Settings { generations: 40, parallel: false, ..Settings::default() };
+        let (best, best_cost, history) = evolve(&start, cost, &settings);
+        assert!(best_cost < 0.05, "{:?} {}", best, best_cost);
+        assert!(!best[1].present);
+        assert!(history.windows(2).all(|w| w[1] <= w[0]), "elites keep the best: {:?}", history);
+        assert_eq!(evolve(&start, cost, &settings).0, best, "same seed, same result");
+    }
+}
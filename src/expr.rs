@@ -0,0 +1,444 @@
+//! A small arithmetic expression language, shared by the model importers
+//! and by user-entered formulas in the GUI.
+//!
+//! The syntax follows NMODL's: `^` is exponentiation, comparisons yield
+//! `1.0` or `0.0`, and a parenthesized unit annotation after a numeric
+//! literal (`-65 (mV)`) is ignored.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Number(f32),
+    Ident(String),
+    Symbol(&'static str),
+    Newline,
+}
+
+const SYMBOLS: [&str; 22] = [
+    "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "^", "<", ">", "!", "=", "'", ",", "(", ")", "{", "}", "~",
+];
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            tokens.push(Token::Newline);
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let n = text.parse::<f32>().map_err(|e| format!("Invalid number {}: {}", text, e))?;
+            tokens.push(Token::Number(n));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                Some(s) => {
+                    tokens.push(Token::Symbol(s));
+                    i += s.len();
+                }
+                None => return Err(format!("Unexpected character '{}'", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    Variable(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// Where an expression looks up its variables and non-builtin functions.
+pub trait Context {
+    fn var(&self, name: &str) -> Option<f32>;
+
+    fn call(&self, name: &str, args: &[f32]) -> Result<f32, String> {
+        builtin(name, args)
+    }
+}
+
+/// A context with no variables, for constant expressions.
+pub struct Constants;
+
+impl Context for Constants {
+    fn var(&self, _name: &str) -> Option<f32> {
+        None
+    }
+}
+
+impl<F: Fn(&str) -> Option<f32>> Context for F {
+    fn var(&self, name: &str) -> Option<f32> {
+        self(name)
+    }
+}
+
+pub fn builtin(name: &str, args: &[f32]) -> Result<f32, String> {
+    let unary = |f: fn(f32) -> f32| match args {
+        [x] => Ok(f(*x)),
+        _ => Err(format!("{} takes one argument", name)),
+    };
+    let binary = |f: fn(f32, f32) -> f32| match args {
+        [x, y] => Ok(f(*x, *y)),
+        _ => Err(format!("{} takes two arguments", name)),
+    };
+    match name {
+        "exp" => unary(f32::exp),
+        "log" => unary(f32::ln),
+        "log10" => unary(f32::log10),
+        "sqrt" => unary(f32::sqrt),
+        "fabs" | "abs" => unary(f32::abs),
+        "sin" => unary(f32::sin),
+        "cos" => unary(f32::cos),
+        "tanh" => unary(f32::tanh),
+        "floor" => unary(f32::floor),
+        "pow" => binary(f32::powf),
+        "min" => binary(f32::min),
+        "max" => binary(f32::max),
+        _ => Err(format!("Unknown function {}", name)),
+    }
+}
+
+fn truth(b: bool) -> f32 {
+    if b { 1.0 } else { 0.0 }
+}
+
+impl Expr {
+    pub fn eval(&self, ctx: &dyn Context) -> Result<f32, String> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Variable(name) => ctx.var(name).ok_or(format!("Unknown variable {}", name)),
+            Expr::Neg(e) => Ok(-e.eval(ctx)?),
+            Expr::Not(e) => Ok(truth(e.eval(ctx)? == 0.0)),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(ctx)?, b.eval(ctx)?);
+                Ok(match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Pow => a.powf(b),
+                    BinOp::Lt => truth(a < b),
+                    BinOp::Le => truth(a <= b),
+                    BinOp::Gt => truth(a > b),
+                    BinOp::Ge => truth(a >= b),
+                    BinOp::Eq => truth(a == b),
+                    BinOp::Ne => truth(a != b),
+                    BinOp::And => truth(a != 0.0 && b != 0.0),
+                    BinOp::Or => truth(a != 0.0 || b != 0.0),
+                })
+            }
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|a| a.eval(ctx)).collect::<Result<Vec<_>, _>>()?;
+                ctx.call(name, &args)
+            }
+        }
+    }
+
+    /// The names of all variables referenced by the expression.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = vec![];
+        self.collect_variables(&mut names);
+        names
+    }
+
+    fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Expr::Neg(e) | Expr::Not(e) => e.collect_variables(names),
+            Expr::Binary(_, a, b) => {
+                a.collect_variables(names);
+                b.collect_variables(names);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|a| a.collect_variables(names)),
+        }
+    }
+}
+
+/// Parse a complete expression from source text.
+pub fn parse(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser::new(tokenize(source)?);
+    parser.skip_newlines();
+    let expr = parser.expr()?;
+    parser.skip_newlines();
+    match parser.peek() {
+        None => Ok(expr),
+        Some(t) => Err(format!("Unexpected {:?} after expression", t)),
+    }
+}
+
+/// A cursor over a token stream. Exposed so that languages embedding
+/// expressions (such as NMODL) can parse their own statements around them.
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser { tokens, pos: 0 }
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    pub fn advance(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    pub fn at_symbol(&self, s: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(t)) if *t == s)
+    }
+
+    pub fn at_ident(&self, s: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(t)) if t == s)
+    }
+
+    pub fn eat_symbol(&mut self, s: &str) -> bool {
+        let found = self.at_symbol(s);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn expect_symbol(&mut self, s: &str) -> Result<(), String> {
+        if self.eat_symbol(s) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}', found {:?}", s, self.peek()))
+        }
+    }
+
+    pub fn expect_ident(&mut self) -> Result<String, String> {
+        match self.advance() {
+            Some(Token::Ident(name)) => Ok(name),
+            t => Err(format!("Expected identifier, found {:?}", t)),
+        }
+    }
+
+    pub fn skip_newlines(&mut self) {
+        while let Some(Token::Newline) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip a balanced parenthesized group, such as a unit annotation.
+    pub fn skip_parens(&mut self) -> Result<(), String> {
+        self.expect_symbol("(")?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                Some(Token::Symbol("(")) => depth += 1,
+                Some(Token::Symbol(")")) => depth -= 1,
+                Some(_) => {}
+                None => return Err("Unclosed parenthesis".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and_expr()?;
+        while self.eat_symbol("||") {
+            lhs = Expr::Binary(BinOp::Or, Box::new(lhs), Box::new(self.and_expr()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.comparison()?;
+        while self.eat_symbol("&&") {
+            lhs = Expr::Binary(BinOp::And, Box::new(lhs), Box::new(self.comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let lhs = self.sum()?;
+        let op = match self.peek() {
+            Some(Token::Symbol("<")) => BinOp::Lt,
+            Some(Token::Symbol("<=")) => BinOp::Le,
+            Some(Token::Symbol(">")) => BinOp::Gt,
+            Some(Token::Symbol(">=")) => BinOp::Ge,
+            Some(Token::Symbol("==")) => BinOp::Eq,
+            Some(Token::Symbol("!=")) => BinOp::Ne,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                BinOp::Add
+            } else if self.eat_symbol("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                BinOp::Mul
+            } else if self.eat_symbol("/") {
+                BinOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat_symbol("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat_symbol("+") {
+            self.unary()
+        } else if self.eat_symbol("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.primary()?;
+        if self.eat_symbol("^") {
+            let exponent = self.unary()?;
+            Ok(Expr::Binary(BinOp::Pow, Box::new(base), Box::new(exponent)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.advance() {
+            Some(Token::Number(n)) => {
+                if self.at_symbol("(") {
+                    self.skip_parens()?;
+                }
+                Ok(Expr::Number(n))
+            }
+            Some(Token::Ident(name)) => {
+                if self.eat_symbol("(") {
+                    let mut args = vec![];
+                    self.skip_newlines();
+                    if !self.eat_symbol(")") {
+                        loop {
+                            self.skip_newlines();
+                            args.push(self.expr()?);
+                            self.skip_newlines();
+                            if self.eat_symbol(")") {
+                                break;
+                            }
+                            self.expect_symbol(",")?;
+                        }
+                    }
+                    Ok(Expr::Call(name, args))
+                } else {
+                    Ok(Expr::Variable(name))
+                }
+            }
+            Some(Token::Symbol("(")) => {
+                self.skip_newlines();
+                let e = self.expr()?;
+                self.skip_newlines();
+                self.expect_symbol(")")?;
+                Ok(e)
+            }
+            t => Err(format!("Unexpected {:?} in expression", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_and_power() {
+        let e = parse("1 + 2 * 3 ^ 2 - -4").unwrap();
+        assert_eq!(e.eval(&Constants), Ok(23.0));
+        let e = parse("-2 ^ 2").unwrap();
+        assert_eq!(e.eval(&Constants), Ok(-4.0));
+    }
+
+    #[test]
+    fn variables_functions_and_units() {
+        let vars = |name: &str| match name {
+            "v" => Some(-65.0),
+            _ => None,
+        };
+        let e = parse("4 * exp(-(v + 65 (mV)) / 18) + (v < 0)").unwrap();
+        assert_eq!(e.eval(&vars), Ok(5.0));
+        assert_eq!(e.variables(), vec!["v".to_string()]);
+        assert!(parse("w + 1").unwrap().eval(&vars).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+//! Extracellular stimulation: electrodes in the medium around the neurons
+//! passing current pulses, as in electrode and deep brain stimulation
+//! experiments.
+//!
+//! A stimulating electrode is a point current source in the medium of
+//! `lfp`, raising the extracellular potential at each segment by
+//! `I / (4 pi sigma r)`. The potential drives no current through a
+//! segment's membrane directly; it changes the axial current between
+//! neighbouring segments, which flows between their intracellular
+//! potentials `Vm + Ve`. Segments where the field bends most, near the
+//! electrode and at branch points and ends, are depolarized or
+//! hyperpolarized the most (Rattay's activating function).
+//!
+//! As with lights, once a frame every segment records the electrodes that
+//! reach it and how strongly; every step, the pulses that are on set the
+//! segment's extracellular potential.
+
+use bevy::prelude::*;
+#[cfg(feature = "render")]
+use bevy::math::prelude::Sphere;
+
+use crate::dimension::Timestamp;
+use crate::lfp::{point_source_potential, DEFAULT_CONDUCTIVITY};
+use crate::neuron::segment::ecs::Segment;
+use crate::optogenetics::PulseTrain;
+use crate::serialize;
+use crate::stimulator::Envelope;
+use crate::units::UnitScale;
+
+/// Extracellular potential (mV per µA) below which an electrode is taken
+/// not to reach a segment.
+pub const MIN_POTENTIAL_PER_MICROAMP: f32 = 1e-4;
+
+/// The current an electrode passes: square pulses, or biphasic pairs
+/// reversing halfway through each pulse so that no net charge is
+/// delivered.
+#[derive(Clone, Debug)]
+pub struct CurrentPulses {
+    /// Current (µA) of each pulse, or of its first phase; negative for
+    /// cathodic stimulation, which depolarizes the nearest segments.
+    pub current_ua: f32,
+    pub biphasic: bool,
+    pub train: PulseTrain,
+}
+
+impl CurrentPulses {
+    /// The current (µA) passed at `t`.
+    pub fn current(&self, t: &Timestamp) -> f32 {
+        if !self.train.is_on(t) {
+            return 0.0;
+        }
+        match self.train.envelope.completion(t) {
+            Some(completion) if self.biphasic && completion > 0.5 => -self.current_ua,
+            _ => self.current_ua,
+        }
+    }
+}
+
+#[derive(Clone, Component, Debug)]
+pub struct ExtracellularStimulator {
+    /// Where the tip is, as for `lfp::ExtracellularElectrode`.
+    pub position_um: Vec3,
+    /// Conductivity (S/m) of the medium.
+    pub conductivity: f32,
+    pub pulses: CurrentPulses,
+}
+
+impl Default for ExtracellularStimulator {
+    /// 100 µA biphasic, cathodic first, 200 µs pulses at 130 Hz, as used
+    /// for deep brain stimulation.
+    fn default() -> Self {
+        ExtracellularStimulator {
+            position_um: Vec3::ZERO,
+            conductivity: DEFAULT_CONDUCTIVITY,
+            pulses: CurrentPulses {
+                current_ua: -100.0,
+                biphasic: true,
+                train: PulseTrain {
+                    envelope: Envelope::deserialize(&serialize::Envelope {
+                        period_sec: 1.0 / 130.0,
+                        onset_sec: 0.0,
+                        offset_sec: 0.0002,
+                    }),
+                    pulses: None,
+                },
+            },
+        }
+    }
+}
+
+impl ExtracellularStimulator {
+    /// Extracellular potential (mV) at `point_um` per µA passed.
+    pub fn potential_per_microamp(&self, point_um: Vec3) -> f32 {
+        // `point_source_potential` is in µV per amp.
+        point_source_potential(1e-6, self.position_um, point_um, self.conductivity) * 1e-3
+    }
+}
+
+/// One electrode reaching a segment.
+#[derive(Clone, Debug)]
+pub struct Polarization {
+    /// Extracellular potential (mV) at the segment per µA passed.
+    pub potential_per_microamp: f32,
+    pub pulses: CurrentPulses,
+}
+
+/// The stimulating electrodes reaching a segment.
+#[derive(Clone, Component, Debug, Default)]
+pub struct ExtracellularField(pub Vec<Polarization>);
+
+impl ExtracellularField {
+    /// The extracellular potential (mV) at the segment at `t`.
+    pub fn potential(&self, t: &Timestamp) -> f32 {
+        self.0.iter().map(|p| p.potential_per_microamp * p.pulses.current(t)).sum()
+    }
+}
+
+/// The extracellular potential (mV) at a segment at `t`, zero outside any
+/// field.
+pub fn extracellular_potential(field: Option<&ExtracellularField>, t: &Timestamp) -> f32 {
+    field.map_or(0.0, |field| field.potential(t))
+}
+
+/// Record, for each segment, the stimulating electrodes that reach it.
+pub fn polarize_segments(
+    mut commands: Commands,
+    scale: Res<UnitScale>,
+    stimulators: Query<&ExtracellularStimulator>,
+    mut segments: Query<(Entity, &Transform, Option<&mut ExtracellularField>), With<Segment>>,
+) {
+    for (entity, transform, field) in &mut segments {
+        let point_um = transform.translation / scale.screen_per_micron();
+        let polarizations = stimulators
+            .iter()
+            .map(|stimulator| Polarization {
+                potential_per_microamp: stimulator.potential_per_microamp(point_um),
+                pulses: stimulator.pulses.clone(),
+            })
+            .filter(|p| p.potential_per_microamp.abs() > MIN_POTENTIAL_PER_MICROAMP)
+            .collect::<Vec<_>>();
+        match (field, polarizations.is_empty()) {
+            (Some(_), true) => {
+                commands.entity(entity).remove::<ExtracellularField>();
+            },
+            (Some(mut field), false) => field.0 = polarizations,
+            (None, true) => {},
+            (None, false) => {
+                commands.entity(entity).insert(ExtracellularField(polarizations));
+            },
+        }
+    }
+}
+
+/// The marker drawn at a stimulating electrode, and whether it was last
+/// drawn passing current.
+#[cfg(feature = "render")]
+#[derive(Component)]
+pub struct StimulatingElectrodeMarker {
+    on: bool,
+}
+
+#[cfg(feature = "render")]
+fn marker_material(on: bool) -> StandardMaterial {
+    StandardMaterial {
+        base_color: if on { Color::RED } else { Color::MAROON },
+        emissive: if on { Color::RED * 2.0 } else { Color::BLACK },
+        unlit: true,
+        ..default()
+    }
+}
+
+/// Draw each new stimulating electrode.
+#[cfg(feature = "render")]
+pub fn spawn_stimulating_electrode_markers(
+    mut commands: Commands,
+    scale: Res<UnitScale>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    stimulators: Query<(Entity, &ExtracellularStimulator), Added<ExtracellularStimulator>>,
+) {
+    for (entity, stimulator) in &stimulators {
+        commands.entity(entity).insert((
+            StimulatingElectrodeMarker { on: false },
+            PbrBundle {
+                mesh: meshes.add(Sphere { radius: 5.0 }),
+                material: materials.add(marker_material(false)),
+                transform: Transform::from_translation(scale.point_to_screen(stimulator.position_um)),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Keep each stimulating electrode's marker at its tip, lit while it
+/// passes current.
+#[cfg(feature = "render")]
+pub fn update_stimulating_electrode_markers(
+    scale: Res<UnitScale>,
+    timestamp: Res<Timestamp>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut stimulators: Query<(Ref<ExtracellularStimulator>, &mut StimulatingElectrodeMarker, &mut Transform, &Handle<StandardMaterial>)>,
+) {
+    for (stimulator, mut marker, mut transform, material) in &mut stimulators {
+        if stimulator.is_changed() {
+            transform.translation = scale.point_to_screen(stimulator.position_um);
+        }
+        let on = stimulator.pulses.current(&timestamp) != 0.0;
+        if on != marker.on {
+            marker.on = on;
+            if let Some(material) = materials.get_mut(material) {
+                *material = marker_material(on);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn biphasic_pulses_reverse_and_fields_fall_off() {
+        let stimulator = ExtracellularStimulator::default();
+        let pulses = &stimulator.pulses;
+        assert_eq!(pulses.current(&Timestamp(0.00005)), -100.0);
+        assert_eq!(pulses.current(&Timestamp(0.00015)), 100.0);
+        assert_eq!(pulses.current(&Timestamp(0.001)), 0.0);
+        let monophasic = CurrentPulses { biphasic: false, ..pulses.clone() };
+        assert_eq!(monophasic.current(&Timestamp(0.00015)), -100.0);
+
+        let near = stimulator.potential_per_microamp(Vec3::new(100.0, 0.0, 0.0));
+        let far = stimulator.potential_per_microamp(Vec3::new(0.0, 200.0, 0.0));
+        assert!((near - 2.6526).abs() < 1e-3, "{}", near);
+        assert!((far * 2.0 - near).abs() < 1e-4);
+
+        let field = ExtracellularField(vec![Polarization { potential_per_microamp: near, pulses: pulses.clone() }]);
+        assert!((field.potential(&Timestamp(0.00005)) + 265.26).abs() < 0.1);
+        assert_eq!(extracellular_potential(None, &Timestamp(0.00005)), 0.0);
+    }
+}
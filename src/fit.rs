@@ -0,0 +1,186 @@
+//! Fitting the channel densities of one membrane to a target voltage
+//! trace, recorded at a neuron's soma.
+//!
+//! Each candidate set of densities is run headless from time zero, and
+//! scored by the RMS difference between its somatic potential and the
+//! target at the target's sample times. The densities are searched with
+//! Nelder–Mead on their logarithms, which keeps them positive and lets a
+//! density move by the same factor whether it is large or small.
+
+use std::cell::Cell;
+
+use crate::analysis::Trace;
+use crate::headless::soma_traces;
+use crate::serialize::Scene;
+use crate::sweep::{apply, Parameter};
+
+/// The smallest density (S/cm²) searched from, so that channels absent
+/// from the starting membrane can still be fitted.
+const MIN_DENSITY: f64 = 1e-9;
+
+/// Minimize `f` from `start` with the Nelder–Mead simplex method, taking
+/// initial steps of `step` along each axis. Returns the best point, its
+/// value, and the number of evaluations of `f`.
+pub fn nelder_mead(
+    mut f: impl FnMut(&[f64]) -> f64,
+    start: &[f64],
+    step: f64,
+    max_evaluations: usize,
+) -> (Vec<f64>, f64, usize) {
+    let n = start.len();
+    let evaluations = Cell::new(0);
+    let mut evaluate = |x: &[f64]| {
+        evaluations.set(evaluations.get() + 1);
+        let value = f(x);
+        if value.is_nan() { f64::INFINITY } else { value }
+    };
+    let mut simplex = vec![(start.to_vec(), evaluate(start))];
+    for i in 0..n {
+        let mut x = start.to_vec();
+        x[i] += step;
+        let value = evaluate(&x);
+        simplex.push((x, value));
+    }
+    // Points along the line from the centroid through the worst point.
+    let along = |centroid: &[f64], worst: &[f64], t: f64| {
+        centroid.iter().zip(worst).map(|(c, w)| c + t * (w - c)).collect::<Vec<_>>()
+    };
+
+    while n > 0 && evaluations.get() < max_evaluations {
+        simplex.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        let (best, second_worst, worst) = (simplex[0].1, simplex[n - 1].1, simplex[n].1);
+        if (worst - best).abs() <= 1e-6 * best.abs().max(1e-12) {
+            break;
+        }
+        let centroid = (0..n)
+            .map(|i| simplex[..n].iter().map(|(x, _)| x[i]).sum::<f64>() / n as f64)
+            .collect::<Vec<_>>();
+        let worst_point = simplex[n].0.clone();
+
+        let reflected = along(&centroid, &worst_point, -1.0);
+        let reflected_value = evaluate(&reflected);
+        if reflected_value < best {
+            let expanded = along(&centroid, &worst_point, -2.0);
+            let expanded_value = evaluate(&expanded);
+            simplex[n] = if expanded_value < reflected_value { (expanded, expanded_value) } else { (reflected, reflected_value) };
+        } else if reflected_value < second_worst {
+            simplex[n] = (reflected, reflected_value);
+        } else {
+            let contracted = along(&centroid, &worst_point, 0.5);
+            let contracted_value = evaluate(&contracted);
+            if contracted_value < worst {
+                simplex[n] = (contracted, contracted_value);
+            } else {
+                // Shrink everything towards the best point.
+                let best_point = simplex[0].0.clone();
+                for (x, value) in simplex[1..].iter_mut() {
+                    *x = along(&best_point, x, 0.5);
+                    *value = evaluate(x);
+                }
+            }
+        }
+    }
+    simplex.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    let (x, value) = simplex.swap_remove(0);
+    (x, value, evaluations.get())
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceFit {
+    pub scene: Scene,
+    /// The neuron whose soma is compared with the target.
+    pub neuron: usize,
+    /// The membrane whose channel densities are fitted, by its position
+    /// in `serialize::Neuron::membranes` (the SWC type less one).
+    pub membrane: usize,
+    /// Somatic potential (mV) over time (s) from time zero.
+    pub target: Trace,
+    pub step_seconds: f32,
+    pub steps_per_sample: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct FitResult {
+    /// Fitted densities (S/cm²), one per channel of the membrane.
+    pub densities: Vec<f64>,
+    /// The scene with the fitted densities.
+    pub scene: Scene,
+    /// RMS difference from the target (mV).
+    pub error_mv: f64,
+    pub evaluations: usize,
+}
+
+impl TraceFit {
+    fn parameters(&self) -> Result<Vec<Parameter>, String> {
+        let membrane = self
+            .scene
+            .neurons
+            .get(self.neuron)
+            .and_then(|n| n.neuron.membranes.get(self.membrane))
+            .ok_or(format!("Neuron {} has no membrane {}", self.neuron, self.membrane))?;
+        Ok((0..membrane.membrane_channels.len())
+            .map(|i| Parameter {
+                path: format!(
+                    "/neurons/{}/neuron/membranes/{}/membrane_channels/{}/siemens_per_square_cm",
+                    self.neuron, self.membrane, i
+                ),
+                values: vec![membrane.membrane_channels[i].siemens_per_square_cm as f64],
+            })
+            .collect())
+    }
+
+    /// RMS difference (mV) between the target and the scene with
+    /// `densities`, at the target's sample times.
+    pub fn error(&self, densities: &[f64]) -> Result<f64, String> {
+        let scene = apply(&self.scene, &self.parameters()?, densities)?;
+        let duration = self.target.times.last().copied().unwrap_or(0.0);
+        let traces = soma_traces(scene, duration, self.step_seconds, self.steps_per_sample)?;
+        let trace = traces.get(self.neuron).filter(|t| !t.is_empty()).ok_or("The neuron has no soma".to_string())?;
+        let squared = self
+            .target
+            .times
+            .iter()
+            .zip(&self.target.values)
+            .map(|(t, target)| {
+                // The last sample may fall just short of the target's end.
+                let v = trace.value_at(*t).or(trace.values.last().copied()).unwrap_or(f32::NAN);
+                ((v - target) as f64).powi(2)
+            })
+            .sum::<f64>();
+        Ok((squared / self.target.len().max(1) as f64).sqrt())
+    }
+
+    /// Search for the densities that best reproduce the target, running
+    /// at most `max_evaluations` simulations.
+    pub fn run(&self, max_evaluations: usize) -> Result<FitResult, String> {
+        if self.target.is_empty() {
+            return Err("The target trace is empty".to_string());
+        }
+        let parameters = self.parameters()?;
+        let start = parameters.iter().map(|p| p.values[0].max(MIN_DENSITY).ln()).collect::<Vec<_>>();
+        let densities = |x: &[f64]| x.iter().map(|x| x.exp()).collect::<Vec<_>>();
+        let (best, error_mv, evaluations) = nelder_mead(
+            |x| self.error(&densities(x)).unwrap_or(f64::INFINITY),
+            &start,
+            // A factor of about 1.6 each way.
+            0.5,
+            max_evaluations,
+        );
+        let densities = densities(&best);
+        Ok(FitResult { scene: apply(&self.scene, &parameters, &densities)?, densities, error_mv, evaluations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimizes_a_valley() {
+        let rosenbrock = |x: &[f64]| (1.0 - x[0]).powi(2) + 100.0 * (x[1] - x[0].powi(2)).powi(2);
+        let (x, value, evaluations) = nelder_mead(rosenbrock, &[-1.0, 2.0], 0.5, 2000);
+        assert!(value < 1e-6, "{:?} {}", x, value);
+        assert!((x[0] - 1.0).abs() < 1e-2 && (x[1] - 1.0).abs() < 1e-2, "{:?}", x);
+        assert!(evaluations <= 2000 + 3);
+    }
+}
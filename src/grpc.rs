@@ -0,0 +1,427 @@
+//! A gRPC server for `proto/nb_sim.proto`, for orchestration systems that
+//! prefer typed RPCs to the JSON interface exported to Javascript.
+//!
+//! The server runs on its own thread with its own Tokio runtime, and
+//! reaches the simulation the way Javascript clients do: commands are sent
+//! through `gui::external_trigger::send_command`, and queries answer from
+//! the latest `snapshot`. It therefore needs `ExternalTriggerPlugin`, and
+//! starts once the app does if `Config::grpc_address` is set. Voltage
+//! streams subscribe to the probe registry instead, with
+//! `probe::subscribe_remotely`.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::command::Command;
+use crate::config::Config;
+use crate::gui::external_trigger::send_command;
+use crate::neuron::voltage_material::MembraneMaterials;
+use crate::probe::{self, ProbeFrame, ProbeKey, Subscription};
+use crate::serialize::{self, SegmentRef};
+use crate::snapshot::{self, Snapshot};
+use crate::validate::checked_scene;
+use crate::voltage_stream::Encoder;
+
+pub mod proto {
+    tonic::include_proto!("nb_sim.v1");
+}
+
+use proto::simulation_server::{Simulation, SimulationServer};
+
+/// How often a voltage stream with no new frames checks whether its
+/// client has gone.
+const CLIENT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Starts the server on `Config::grpc_address`, if there is one.
+pub struct GrpcPlugin;
+
+impl Plugin for GrpcPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, start_server);
+    }
+}
+
+fn start_server(config: Res<Config>) {
+    let Some(address) = &config.grpc_address else {
+        return;
+    };
+    match address.parse() {
+        Ok(address) => serve(address),
+        Err(e) => eprintln!("Invalid grpc_address {}: {}", address, e),
+    }
+}
+
+/// Serve on `address` from a new thread, until the process exits.
+pub fn serve(address: SocketAddr) {
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start the gRPC server: {}", e);
+                return;
+            },
+        };
+        let server = tonic::transport::Server::builder().add_service(SimulationServer::new(SimulationService)).serve(address);
+        if let Err(e) = runtime.block_on(server) {
+            eprintln!("gRPC server on {}: {}", address, e);
+        }
+    });
+}
+
+pub struct SimulationService;
+
+#[tonic::async_trait]
+impl Simulation for SimulationService {
+    async fn send(&self, request: Request<proto::Command>) -> Result<Response<proto::CommandReply>, Status> {
+        let errors = match Command::try_from(request.into_inner()).and_then(send_command) {
+            Ok(()) => vec![],
+            Err(e) => vec![e],
+        };
+        Ok(Response::new(proto::CommandReply { errors }))
+    }
+
+    async fn time(&self, _: Request<proto::TimeRequest>) -> Result<Response<proto::Time>, Status> {
+        let latest = snapshot::latest().map_err(Status::unavailable)?;
+        Ok(Response::new(time(&latest)))
+    }
+
+    async fn state(&self, _: Request<proto::StateRequest>) -> Result<Response<proto::State>, Status> {
+        let latest = snapshot::latest().map_err(Status::unavailable)?;
+        Ok(Response::new(state(&latest)))
+    }
+
+    async fn voltage(&self, request: Request<proto::SegmentRef>) -> Result<Response<proto::SegmentVoltage>, Status> {
+        let at = segment_ref(Some(request.into_inner())).map_err(Status::invalid_argument)?;
+        let latest = snapshot::latest().map_err(Status::unavailable)?;
+        latest
+            .voltages
+            .iter()
+            .find(|v| v.neuron == at.neuron && v.segment == at.segment)
+            .map(|v| Response::new(segment_voltage(v)))
+            .ok_or_else(|| Status::not_found(format!("No segment {} in neuron {}", at.segment, at.neuron)))
+    }
+
+    type StreamVoltagesStream = ReceiverStream<Result<proto::State, Status>>;
+
+    async fn stream_voltages(
+        &self,
+        request: Request<proto::StreamRequest>,
+    ) -> Result<Response<Self::StreamVoltagesStream>, Status> {
+        let request = request.into_inner();
+        let every_frames = request.every_frames.max(1) as usize;
+        let range = if request.v_max_mv > request.v_min_mv {
+            (request.v_min_mv, request.v_max_mv)
+        } else {
+            let (v_min, v_max) = MembraneMaterials::default().voltage_range;
+            (v_min.0, v_max.0)
+        };
+        let mut encoder = request.quantized.then(|| Encoder::new(range));
+        let segments = request
+            .segments
+            .into_iter()
+            .map(|at| segment_ref(Some(at)).map(ProbeKey::Segment))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+        let frames = probe::subscribe_remotely(if segments.is_empty() {
+            Subscription::EverySegment
+        } else {
+            Subscription::Probes(segments)
+        });
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        tokio::task::spawn_blocking(move || {
+            let mut count = 0;
+            while !sender.is_closed() {
+                let Ok(frame) = frames.recv_timeout(CLIENT_CHECK_INTERVAL) else { continue };
+                count += 1;
+                if (count - 1) % every_frames != 0 {
+                    continue;
+                }
+                let state = match &mut encoder {
+                    Some(encoder) => quantized_state(&frame, encoder),
+                    None => frame_state(&frame),
+                };
+                if sender.blocking_send(Ok(state)).is_err() {
+                    break;
+                }
+            }
+            // Dropping `frames` unsubscribes.
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+fn time(snapshot: &Snapshot) -> proto::Time {
+    proto::Time { t_ms: snapshot.t_ms, step: snapshot.step, paused: snapshot.paused }
+}
+
+fn segment_voltage(v: &snapshot::SegmentVoltage) -> proto::SegmentVoltage {
+    proto::SegmentVoltage { neuron: v.neuron as u32, segment: v.segment, v_mv: v.v_mv }
+}
+
+fn state(snapshot: &Snapshot) -> proto::State {
+    proto::State {
+        time: Some(time(snapshot)),
+        voltages: snapshot.voltages.iter().map(segment_voltage).collect(),
+        quantized_voltages: vec![],
+    }
+}
+
+/// A frame of `ProbeKey::Segment` voltages.
+fn frame_state(frame: &ProbeFrame) -> proto::State {
+    proto::State {
+        time: Some(proto::Time { t_ms: frame.t * 1000.0, step: frame.step, paused: frame.paused }),
+        voltages: frame
+            .samples
+            .iter()
+            .filter_map(|sample| match sample.key {
+                ProbeKey::Segment(at) => {
+                    Some(proto::SegmentVoltage { neuron: at.neuron as u32, segment: at.segment, v_mv: sample.value })
+                },
+                _ => None,
+            })
+            .collect(),
+        quantized_voltages: vec![],
+    }
+}
+
+/// A frame of `ProbeKey::Segment` voltages, quantized and delta-encoded
+/// from the last frame `encoder` encoded.
+fn quantized_state(frame: &ProbeFrame, encoder: &mut Encoder) -> proto::State {
+    let mut state = frame_state(frame);
+    state.voltages.sort_by_key(|v| (v.neuron, v.segment));
+    let voltages = std::mem::take(&mut state.voltages).into_iter().map(|v| v.v_mv).collect::<Vec<_>>();
+    proto::State { quantized_voltages: encoder.encode(&voltages), ..state }
+}
+
+fn segment_ref(at: Option<proto::SegmentRef>) -> Result<SegmentRef, String> {
+    let at = at.ok_or("Missing segment".to_string())?;
+    Ok(SegmentRef { neuron: at.neuron as usize, segment: at.segment })
+}
+
+fn from_json<T: DeserializeOwned>(what: &str, json: &str) -> Result<T, String> {
+    serde_json::from_str(json).map_err(|e| format!("Invalid {}: {}", what, e))
+}
+
+fn stimulator(stimulator: Option<proto::Stimulator>) -> Result<serialize::Stimulator, String> {
+    use proto::stimulator::CurrentShape as Shape;
+    let stimulator = stimulator.ok_or("Missing stimulator".to_string())?;
+    let envelope = stimulator.envelope.ok_or("Stimulator without an envelope".to_string())?;
+    let current_shape = match stimulator.current_shape.ok_or("Stimulator without a current shape".to_string())? {
+        Shape::SquareWave(s) => serialize::CurrentShape::SquareWave {
+            on_current_uamps_per_square_cm: s.on_current_uamps_per_square_cm,
+            off_current_uamps_per_square_cm: s.off_current_uamps_per_square_cm,
+        },
+        Shape::LinearRamp(s) => serialize::CurrentShape::LinearRamp {
+            start_current_uamps_per_square_cm: s.start_current_uamps_per_square_cm,
+            end_current_uamps_per_square_cm: s.end_current_uamps_per_square_cm,
+            off_current_uamps_per_square_cm: s.off_current_uamps_per_square_cm,
+        },
+        Shape::FrequencyRamp(s) => serialize::CurrentShape::FrequencyRamp {
+            on_amplitude_uamps_per_square_cm: s.on_amplitude_uamps_per_square_cm,
+            offset_current_uamps_per_square_cm: s.offset_current_uamps_per_square_cm,
+            start_frequency_hz: s.start_frequency_hz,
+            end_frequency_hz: s.end_frequency_hz,
+        },
+        Shape::Poisson(s) => serialize::CurrentShape::Poisson {
+            rate_hz: s.rate_hz,
+            pulse_current_uamps_per_square_cm: s.pulse_current_uamps_per_square_cm,
+            pulse_width_sec: s.pulse_width_sec,
+            off_current_uamps_per_square_cm: s.off_current_uamps_per_square_cm,
+        },
+        Shape::Custom(c) => serialize::CurrentShape::Custom(serialize::CustomMechanism {
+            name: c.name,
+            parameters: if c.parameters_json.is_empty() {
+                serde_json::Value::Null
+            } else {
+                from_json("custom mechanism parameters", &c.parameters_json)?
+            },
+        }),
+    };
+    Ok(serialize::Stimulator {
+        envelope: serialize::Envelope {
+            period_sec: envelope.period_sec,
+            onset_sec: envelope.onset_sec,
+            offset_sec: envelope.offset_sec,
+        },
+        current_shape,
+    })
+}
+
+impl TryFrom<proto::Command> for Command {
+    type Error = String;
+
+    fn try_from(command: proto::Command) -> Result<Self, String> {
+        use proto::command::Command as C;
+        Ok(match command.command.ok_or("Empty command".to_string())? {
+            C::Pause(_) => Command::Pause,
+            C::Resume(_) => Command::Resume,
+            C::Step(c) => Command::Step { steps: c.steps as usize },
+            C::SetSpeed(c) => Command::SetSpeed { speed: c.speed },
+            C::Reset(_) => Command::Reset,
+            C::LoadScene(c) => Command::LoadScene { scene: checked_scene(&c.source)? },
+            C::SetStimulator(c) => {
+                let at = segment_ref(c.at)?;
+                Command::SetStimulator { neuron: at.neuron, segment: at.segment, stimulator: stimulator(c.stimulator)? }
+            },
+            C::AddSynapse(c) => Command::AddSynapse {
+                pre: segment_ref(c.pre)?,
+                post: segment_ref(c.post)?,
+                synapse_membranes: from_json("synapse membranes", &c.synapse_membranes_json)?,
+            },
+            C::RemoveSynapse(c) => Command::RemoveSynapse { pre: segment_ref(c.pre)?, post: segment_ref(c.post)? },
+            C::SaveCheckpoint(c) => Command::SaveCheckpoint { path: c.path },
+            C::RestoreCheckpoint(c) => Command::RestoreCheckpoint { path: c.path },
+            C::WarmRestart(c) => Command::WarmRestart { scene: checked_scene(&c.source)?, reset_state: c.reset_state },
+            C::TakeSnapshot(_) => Command::TakeSnapshot,
+            C::RollBack(_) => Command::RollBack,
+            C::CutBranch(c) => Command::CutBranch { at: segment_ref(c.at)? },
+            C::ReattachBranch(c) => Command::ReattachBranch { at: segment_ref(c.at)?, to: segment_ref(c.to)? },
+            C::ScaleBranch(c) => Command::ScaleBranch { at: segment_ref(c.at)?, factor: c.factor },
+            C::ExpressOpsin(c) => Command::ExpressOpsin {
+                at: segment_ref(c.at)?,
+                opsin: match proto::Opsin::try_from(c.opsin).map_err(|_| format!("Unknown opsin {}", c.opsin))? {
+                    proto::Opsin::Chr2 => serialize::Opsin::ChR2,
+                    proto::Opsin::Nphr => serialize::Opsin::NpHR,
+                },
+                siemens_per_square_cm: c.siemens_per_square_cm,
+            },
+            C::At(at) => {
+                let command = at.command.ok_or("At without a command".to_string())?;
+                Command::At { t_seconds: at.t_seconds, command: Box::new(Command::try_from(*command)?) }
+            },
+            C::RemoveStimulator(c) => Command::RemoveStimulator { at: segment_ref(c.at)? },
+            C::SetConstants(c) => Command::SetConstants {
+                constants: serialize::SimulationConstants {
+                    temperature_kelvin: c.temperature_kelvin,
+                    gas_constant: c.gas_constant,
+                    faraday: c.faraday,
+                    axial_resistivity_ohm_cm: c.axial_resistivity_ohm_cm,
+                },
+            },
+            C::StartRecording(_) => Command::StartRecording,
+            C::StopRecording(_) => Command::StopRecording,
+            C::ReplaySession(c) => Command::ReplaySession { session: from_json("session", &c.session_json)? },
+            C::SetNoise(c) => Command::SetNoise {
+                tag: c.tag,
+                noise: c.noise.map(|n| serialize::MembraneNoise {
+                    mean_uamps_per_square_cm: n.mean_uamps_per_square_cm,
+                    background_sigma_uamps_per_square_cm: n.background_sigma_uamps_per_square_cm,
+                    background_tau_sec: n.background_tau_sec,
+                    channel_sigma_uamps_per_square_cm: n.channel_sigma_uamps_per_square_cm,
+                    channel_tau_sec: n.channel_tau_sec,
+                }),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::command::Command as C;
+    use crate::dimension::MilliVolts;
+    use crate::integrations::grace::sample;
+    use crate::neuron::synapse;
+    use crate::probe::ProbeSample;
+    use crate::voltage_stream::Decoder;
+    use crate::replay::Session;
+
+    #[test]
+    fn translates_every_command() {
+        let at = || Some(proto::SegmentRef { neuron: 0, segment: 1 });
+        let source = include_str!("../sample_data/sample_scene.json").to_string();
+        let synapse_membranes_json =
+            serde_json::to_string(&synapse::examples::excitatory_synapse(&MilliVolts(-80.0)).serialize()).unwrap();
+        let session = Session { scene: sample::scene2(), paused: false, steps: None, commands: vec![] };
+        let stimulator = proto::Stimulator {
+            envelope: Some(proto::Envelope { period_sec: 0.1, onset_sec: 0.0, offset_sec: 0.05 }),
+            current_shape: Some(proto::stimulator::CurrentShape::SquareWave(proto::SquareWave {
+                on_current_uamps_per_square_cm: 10.0,
+                off_current_uamps_per_square_cm: 0.0,
+            })),
+        };
+        let commands = vec![
+            C::Pause(proto::Pause {}),
+            C::Resume(proto::Resume {}),
+            C::Step(proto::Step { steps: 1 }),
+            C::SetSpeed(proto::SetSpeed { speed: 0.5 }),
+            C::Reset(proto::Reset {}),
+            C::LoadScene(proto::LoadScene { source: source.clone() }),
+            C::SetStimulator(proto::SetStimulator { at: at(), stimulator: Some(stimulator) }),
+            C::AddSynapse(proto::AddSynapse { pre: at(), post: at(), synapse_membranes_json }),
+            C::RemoveSynapse(proto::RemoveSynapse { pre: at(), post: at() }),
+            C::SaveCheckpoint(proto::SaveCheckpoint { path: "state.json".to_string() }),
+            C::RestoreCheckpoint(proto::RestoreCheckpoint { path: "state.json".to_string() }),
+            C::WarmRestart(proto::WarmRestart { source, reset_state: true }),
+            C::TakeSnapshot(proto::TakeSnapshot {}),
+            C::RollBack(proto::RollBack {}),
+            C::CutBranch(proto::CutBranch { at: at() }),
+            C::ReattachBranch(proto::ReattachBranch { at: at(), to: at() }),
+            C::ScaleBranch(proto::ScaleBranch { at: at(), factor: 2.0 }),
+            C::ExpressOpsin(proto::ExpressOpsin { at: at(), opsin: proto::Opsin::Nphr as i32, siemens_per_square_cm: 1e-3 }),
+            C::At(Box::new(proto::At {
+                t_seconds: 0.01,
+                command: Some(Box::new(proto::Command { command: Some(C::Pause(proto::Pause {})) })),
+            })),
+            C::RemoveStimulator(proto::RemoveStimulator { at: at() }),
+            C::SetConstants(proto::SetConstants {
+                temperature_kelvin: 310.0,
+                gas_constant: 8.314,
+                faraday: 96485.0,
+                axial_resistivity_ohm_cm: 100.0,
+            }),
+            C::StartRecording(proto::StartRecording {}),
+            C::StopRecording(proto::StopRecording {}),
+            C::ReplaySession(proto::ReplaySession { session_json: serde_json::to_string(&session).unwrap() }),
+            C::SetNoise(proto::SetNoise { tag: Some("pyramidal".to_string()), noise: None }),
+        ];
+        let mut types = commands
+            .into_iter()
+            .map(|command| {
+                let command = Command::try_from(proto::Command { command: Some(command) }).unwrap();
+                serde_json::to_value(command).unwrap()["type"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+        types.sort();
+        types.dedup();
+        assert_eq!(types.len(), 25, "{:?}", types);
+
+        let missing = proto::Command { command: Some(C::CutBranch(proto::CutBranch { at: None })) };
+        assert!(Command::try_from(missing).is_err());
+        let invalid = proto::Command { command: Some(C::LoadScene(proto::LoadScene { source: "not a scene".to_string() })) };
+        assert!(Command::try_from(invalid).is_err());
+    }
+
+    #[test]
+    fn streams_the_frame_segment_voltages() {
+        let at = SegmentRef { neuron: 1, segment: 1 };
+        let frame = ProbeFrame {
+            t: 0.25,
+            step: 60,
+            paused: false,
+            samples: vec![
+                ProbeSample { key: ProbeKey::Segment(at), t: 0.25, value: -70.0 },
+                ProbeSample { key: ProbeKey::Watch("v".to_string()), t: 0.25, value: 1.0 },
+            ],
+        };
+        let state = frame_state(&frame);
+        assert_eq!(state.voltages, vec![proto::SegmentVoltage { neuron: 1, segment: 1, v_mv: -70.0 }]);
+        assert_eq!(state.time, Some(proto::Time { t_ms: 250.0, step: 60, paused: false }));
+    }
+
+    #[test]
+    fn quantizes_voltages_in_segment_order() {
+        let sample = |neuron, segment, value| ProbeSample { key: ProbeKey::Segment(SegmentRef { neuron, segment }), t: 0.0, value };
+        let frame = ProbeFrame { t: 0.0, step: 0, paused: false, samples: vec![sample(1, 1, 30.0), sample(0, 2, -70.0)] };
+        let mut encoder = Encoder::new((-80.0, 50.0));
+        let state = quantized_state(&frame, &mut encoder);
+        assert!(state.voltages.is_empty());
+        let decoded = Decoder::default().decode(&state.quantized_voltages).unwrap();
+        assert!((decoded[0] - -70.0).abs() < 0.5 && (decoded[1] - 30.0).abs() < 0.5, "{:?}", decoded);
+    }
+}
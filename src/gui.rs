@@ -1,6 +1,41 @@
+pub mod bulk;
+pub mod channel_library;
+pub mod clipping;
+pub mod colorbar;
+pub mod cursors;
+pub mod download;
+pub mod electrodes;
 pub mod external_trigger;
+pub mod inspector;
+pub mod integration;
+pub mod ions;
+pub mod layout;
 pub mod load;
+pub mod membrane;
+pub mod morphology;
+pub mod noise;
+pub mod optogenetics;
 pub mod oscilloscope;
+pub mod palette;
+pub mod png;
+pub mod probes;
+pub mod raster;
+pub mod recording;
+pub mod replay;
+pub mod rollback;
+pub mod runs;
+pub mod scale;
+pub mod scene_diff;
+pub mod session_log;
+pub mod speed_test;
+pub mod spike_train;
+pub mod step_advice;
+pub mod synapses;
+pub mod thermal;
+pub mod tooltip;
+pub mod view;
+pub mod voltage_clamp;
+pub mod watch;
 
 use bevy::prelude::*;
 use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
@@ -16,7 +51,17 @@ use crate::dimension::{
     Interval
 };
 // use crate::gui::load::InterpreterUrl;
+use crate::gui::download::save_file;
 use crate::gui::oscilloscope::Oscilloscope;
+use crate::gui::runs::RunRegistry;
+use crate::gui::session_log::SessionLog;
+use crate::clock::RunClock;
+use crate::command::{Command, SimulationControl};
+use crate::constants::SimulationConstants;
+use crate::integrations::dhall::to_dhall;
+use crate::gui::colorbar::Colorbar;
+use crate::neuron::voltage_material::{Colormap, EmissiveMode, MembraneMaterials};
+use crate::plugin::SimulationState;
 use crate::stimulator::{Stimulator, Envelope, CurrentShape};
 // use crate::integrations::grace::GraceSceneSender;
 use crate::selection::Selection;
@@ -34,17 +79,45 @@ pub fn run_gui(
     mut new_stimulators: ResMut<Stimulator>,
     // is_loading: ResMut<load::IsLoading>, // TODO: surface is_loading to user with a spinner.
     // source: ResMut<load::GraceSceneSource>,
-    oscilloscope: ResMut<Oscilloscope>,
+    mut oscilloscope: ResMut<Oscilloscope>,
+    mut runs: ResMut<RunRegistry>,
+    mut session_log: ResMut<SessionLog>,
+    mut constants: ResMut<SimulationConstants>,
+    run_clock: Res<RunClock>,
+    (membrane_materials, mut colorbar): (Option<ResMut<MembraneMaterials>>, ResMut<Colorbar>),
     // neurons: Query<(Entity, &Neuron)>,
     // segments: Query<(Entity, &Segment)>,
     // junctions: Query<(Entity, &Junction)>,
     // stimulations: Query<(Entity, &Stimulation)>,
-    mut selected_stimulators: Query<&mut Stimulator, With<Selection>>,
+    // The selected stimulators, and the whole scene for saving, which also
+    // reads stimulators.
+    mut stimulators_and_scene: ParamSet<(Query<&mut Stimulator, With<Selection>>, SimulationState)>,
+    mut save_scene_error: Local<Option<String>>,
     // grace_scene_sender: Res<GraceSceneSender>,
+    (control, mut command_events): (Res<SimulationControl>, EventWriter<Command>),
 ) {
+    let run_settings = ((*timestamp).clone(), (*simulation_step).clone(), (*steps_per_frame).clone());
     egui::Window::new("NeuronBench").show(contexts.ctx_mut(), |ui| {
+        transport_controls(ui, &control, steps_per_frame.0, &mut command_events);
         runtime_stats_header(ui, diagnostics, timestamp, steps_per_frame, simulation_step);
 
+        ui.horizontal(|ui| {
+            if ui.button("Save scene").clicked() {
+                *save_scene_error = serde_json::to_string_pretty(&stimulators_and_scene.p1().serialize(&constants))
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| save_file("scene.json", "application/json", json.as_bytes()))
+                    .err();
+            }
+            if ui.button("Save as Dhall").clicked() {
+                *save_scene_error = to_dhall(&stimulators_and_scene.p1().serialize(&constants))
+                    .and_then(|dhall| save_file("scene.dhall", "text/plain", dhall.as_bytes()))
+                    .err();
+            }
+            if let Some(e) = &*save_scene_error {
+                ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+            }
+        });
+
         let id = ui.make_persistent_id("stimulator_header");
         egui::collapsing_header::CollapsingState::load_with_default_open(
             ui.ctx(), id, false
@@ -52,7 +125,7 @@ pub fn run_gui(
             ui.label("Stimulation")
         })
         .body(|ui| {
-            match selected_stimulators.get_single_mut() {
+            match stimulators_and_scene.p0().get_single_mut() {
                 Ok(mut s) => {
                     s.widget(ui);
                 },
@@ -70,23 +143,57 @@ pub fn run_gui(
         })
             .body( |ui| {
                 ui.horizontal( |h| {
-                   if h.add(egui::Button::new("1")).clicked() {
-                       *next_click = NextClickAction::SetVoltageSource(0);
+                   for (i, channel) in oscilloscope.channels.iter().enumerate() {
+                       let label = egui::RichText::new(channel.label.as_str()).color(channel.color);
+                       if h.add(egui::Button::new(label)).clicked() {
+                           *next_click = NextClickAction::SetVoltageSource(i);
+                       }
                    }
-                   if h.add(egui::Button::new("2")).clicked() {
-                       *next_click = NextClickAction::SetVoltageSource(1);
-                   }
-                   if h.add(egui::Button::new("3")).clicked() {
-                       *next_click = NextClickAction::SetVoltageSource(2);
-                   }
-                   if h.add(egui::Button::new("4")).clicked() {
-                       *next_click = NextClickAction::SetVoltageSource(3);
-                   }
-
                 } );
                 oscilloscope.plot(ui);
             } );
 
+        let id = ui.make_persistent_id("runs_header");
+        egui::collapsing_header::CollapsingState::load_with_default_open(
+            ui.ctx(), id, false
+        ).show_header(ui, |ui| {
+            ui.label("Runs")
+        })
+            .body( |ui| {
+                let (simulation_time, simulation_step, steps_per_frame) = run_settings;
+                if let Some(run) = runs.widget(ui, &oscilloscope, simulation_time, simulation_step, steps_per_frame, &run_clock) {
+                    session_log.record_run(run);
+                }
+            } );
+
+        let id = ui.make_persistent_id("session_log_header");
+        egui::collapsing_header::CollapsingState::load_with_default_open(
+            ui.ctx(), id, false
+        ).show_header(ui, |ui| {
+            ui.label("Session log")
+        })
+            .body( |ui| {
+                session_log.widget(ui, &run_clock);
+            } );
+
+        let id = ui.make_persistent_id("constants_header");
+        egui::collapsing_header::CollapsingState::load_with_default_open(
+            ui.ctx(), id, false
+        ).show_header(ui, |ui| {
+            ui.label("Constants")
+        })
+            .body( |ui| { simulation_constants_widget(ui, &mut constants); } );
+
+        if let Some(mut membrane_materials) = membrane_materials {
+            let id = ui.make_persistent_id("rendering_header");
+            egui::collapsing_header::CollapsingState::load_with_default_open(
+                ui.ctx(), id, false
+            ).show_header(ui, |ui| {
+                ui.label("Rendering")
+            })
+                .body( |ui| { rendering_widget(ui, &mut membrane_materials, &mut colorbar); } );
+        }
+
         let id = ui.make_persistent_id("build_header");
         egui::collapsing_header::CollapsingState::load_with_default_open(
             ui.ctx(), id, false
@@ -98,6 +205,99 @@ pub fn run_gui(
     });
 }
 
+/// Pause and play, single steps, and slow motion. Sent as commands, so
+/// that sessions record them.
+pub fn transport_controls(ui: &mut Ui, control: &SimulationControl, steps_per_frame: usize, events: &mut EventWriter<Command>) {
+    ui.horizontal(|ui| {
+        if control.paused {
+            if ui.button("Play").clicked() {
+                events.send(Command::Resume);
+            }
+        } else if ui.button("Pause").clicked() {
+            events.send(Command::Pause);
+        }
+        if ui.button("Step dt").on_hover_text("Pause, then integrate one step").clicked() {
+            events.send(Command::Step { steps: 1 });
+        }
+        if ui.button("Step frame").on_hover_text("Pause, then integrate one frame's steps").clicked() {
+            events.send(Command::Step { steps: steps_per_frame });
+        }
+        for speed in [0.1, 1.0] {
+            if ui.selectable_label(control.speed == speed, format!("{}x", speed)).clicked() {
+                events.send(Command::SetSpeed { speed });
+            }
+        }
+    });
+}
+
+pub fn simulation_constants_widget(ui: &mut Ui, constants: &mut SimulationConstants) {
+    ui.horizontal(|ui| {
+        ui.label("Temperature");
+        ui.add(egui::Slider::from_get_set(0.0..=45.0, |v: Option<f64>| {
+            if let Some(v) = v {
+                constants.temperature.0 = v as f32 + 273.15;
+            }
+            (constants.temperature.0 - 273.15) as f64
+        }).suffix(" C"));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Axial resistivity");
+        ui.add(egui::DragValue::new(&mut constants.axial_resistivity_ohm_cm).clamp_range(1.0..=10000.0).speed(1.0).suffix(" Ω cm"));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Gas constant");
+        ui.add(egui::DragValue::new(&mut constants.gas_constant).speed(0.001).suffix(" J/(mol K)"));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Faraday constant");
+        ui.add(egui::DragValue::new(&mut constants.faraday).speed(1.0).suffix(" C/mol"));
+    });
+    if ui.button("Reset").clicked() {
+        *constants = SimulationConstants::default();
+    }
+}
+
+pub fn rendering_widget(ui: &mut Ui, membrane_materials: &mut MembraneMaterials, colorbar: &mut Colorbar) {
+    ui.horizontal(|ui| {
+        ui.label("Colormap");
+        egui::ComboBox::from_id_source("colormap")
+            .selected_text(membrane_materials.colormap.name())
+            .show_ui(ui, |ui| {
+                for colormap in Colormap::ALL {
+                    ui.selectable_value(&mut membrane_materials.colormap, colormap, colormap.name());
+                }
+            });
+    });
+    ui.horizontal(|ui| {
+        let (v_min, v_max) = &mut membrane_materials.voltage_range;
+        ui.label("Range");
+        ui.add(egui::DragValue::new(&mut v_min.0).clamp_range(-200.0..=v_max.0 - 1.0).speed(0.5).suffix(" mV"));
+        ui.label("to");
+        ui.add(egui::DragValue::new(&mut v_max.0).clamp_range(v_min.0 + 1.0..=200.0).speed(0.5).suffix(" mV"));
+    });
+    ui.checkbox(&mut colorbar.shown, "Show colorbar");
+    let mode = &mut membrane_materials.emissive_mode;
+    ui.horizontal(|ui| {
+        ui.label("Glow");
+        if ui.selectable_label(*mode == EmissiveMode::Voltage, "Voltage").clicked() {
+            *mode = EmissiveMode::Voltage;
+        }
+        if ui.selectable_label(*mode != EmissiveMode::Voltage, "Spikes").clicked() && *mode == EmissiveMode::Voltage {
+            *mode = EmissiveMode::spiking();
+        }
+    });
+    if let EmissiveMode::Spiking { decay, full_flash_dv_dt } = mode {
+        ui.horizontal(|ui| {
+            ui.label("Decay");
+            ui.add(egui::Slider::new(&mut decay.0, 0.02..=2.0).logarithmic(true).suffix(" s"));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Full flash at");
+            ui.add(egui::Slider::new(full_flash_dv_dt, 1.0..=500.0).logarithmic(true).suffix(" mV/ms"));
+        });
+    }
+}
+
 pub fn build_info(ui: &mut Ui) {
     ui.horizontal(|ui| {
         ui.label("Version");
@@ -178,6 +378,7 @@ pub fn runtime_stats_header(
 pub enum NextClickAction {
     ModifyStimulator,
     SetVoltageSource(usize),
+    AttachProbe,
 }
 
 impl Default for NextClickAction {
@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::command::Command;
+use crate::integrations::grace::{path_to_soma_microns, swc_type_name, CurrentScene, SceneSegment};
+use crate::neuron::density;
+use crate::neuron::membrane::Membrane;
+use crate::selection::Selection;
+use crate::stimulator::Stimulator;
+
+#[derive(Resource)]
+pub struct BulkActions {
+    /// The SWC type, from 1, whose membrane "Swap membrane" gives.
+    pub membrane_type: usize,
+    pub factor: f32,
+    /// Why the last swap left some segments at their membrane's plain
+    /// densities.
+    pub error: Option<String>,
+}
+
+impl Default for BulkActions {
+    fn default() -> Self {
+        BulkActions { membrane_type: 1, factor: 0.5, error: None }
+    }
+}
+
+/// Multiply the density of every channel in `membrane` by `factor`.
+pub fn scale_conductances(membrane: &mut Membrane, factor: f32) {
+    for membrane_channel in membrane.membrane_channels.iter_mut() {
+        membrane_channel.siemens_per_square_cm *= factor;
+    }
+}
+
+/// Act on every selected segment at once.
+pub fn bulk_gui(
+    mut contexts: EguiContexts,
+    mut actions: ResMut<BulkActions>,
+    stimulator: Res<Stimulator>,
+    current_scene: Res<CurrentScene>,
+    mut selected: Query<(&SceneSegment, &mut Membrane), With<Selection>>,
+    mut events: EventWriter<Command>,
+) {
+    egui::Window::new("Apply to selection")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let count = selected.iter().count();
+            ui.label(format!("{} segments selected", count));
+            ui.add_enabled_ui(count > 0, |ui| {
+                if ui.button("Attach the current stimulator").clicked() {
+                    for (SceneSegment(at), _) in &selected {
+                        events.send(Command::SetStimulator {
+                            neuron: at.neuron,
+                            segment: at.segment,
+                            stimulator: stimulator.serialize(),
+                        });
+                    }
+                }
+
+                // Neurons without the chosen type keep their membranes.
+                let types = current_scene.0.neurons.iter().map(|n| n.neuron.membranes.len()).max().unwrap_or(0);
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("bulk_membrane_type")
+                        .selected_text(swc_type_name(actions.membrane_type))
+                        .show_ui(ui, |ui| {
+                            for type_ in 1..=types {
+                                ui.selectable_value(&mut actions.membrane_type, type_, swc_type_name(type_));
+                            }
+                        });
+                    if ui.add_enabled(actions.membrane_type <= types, egui::Button::new("Swap membrane")).clicked() {
+                        actions.error = None;
+                        for (SceneSegment(at), mut membrane) in &mut selected {
+                            let Some(neuron) = current_scene.0.neurons.get(at.neuron).map(|n| &n.neuron) else { continue };
+                            let Some(serialized) = neuron.membranes.get(actions.membrane_type - 1) else { continue };
+                            let distance = if density::has_rules(serialized) {
+                                path_to_soma_microns(neuron, at.segment).unwrap_or(0.0)
+                            } else {
+                                0.0
+                            };
+                            *membrane = density::membrane_at(serialized, distance).unwrap_or_else(|e| {
+                                eprintln!("Segment {}: {}", at.segment, e);
+                                actions.error.get_or_insert(format!("Segment {}: {}", at.segment, e));
+                                Membrane::deserialize(serialized)
+                            });
+                        }
+                    }
+                });
+                if let Some(e) = &actions.error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+                }
+
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut actions.factor).clamp_range(0.0..=100.0).speed(0.01).prefix("x"));
+                    if ui.button("Scale conductances").clicked() {
+                        for (_, mut membrane) in &mut selected {
+                            scale_conductances(&mut membrane, actions.factor);
+                        }
+                    }
+                });
+            });
+        });
+}
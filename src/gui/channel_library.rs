@@ -0,0 +1,127 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use egui_plot::{Legend, Line, Plot};
+
+use crate::dimension::MilliVolts;
+use crate::gui::membrane::channel_label;
+use crate::neuron::channel::{common_channels, ChannelBuilder, Gating};
+use crate::neuron::membrane::{Membrane, MembraneChannel, MembraneVoltage};
+use crate::selection::Selection;
+
+/// Voltages the gate curves are plotted over (mV).
+const PLOTTED_VOLTAGES: std::ops::RangeInclusive<i32> = -100..=60;
+
+#[derive(Default, Resource)]
+pub struct ChannelLibrary {
+    pub search: String,
+    /// Index into `common_channels::library`.
+    pub chosen: Option<usize>,
+}
+
+/// Whether every word of `search` appears in `name`, ignoring case.
+pub fn matches_search(name: &str, search: &str) -> bool {
+    let name = name.to_lowercase();
+    search.to_lowercase().split_whitespace().all(|word| name.contains(word))
+}
+
+/// A channel's gates, named for plotting.
+fn gates(builder: &ChannelBuilder) -> Vec<(&'static str, &Gating)> {
+    builder
+        .activation_parameters
+        .iter()
+        .map(|gating| ("activation", gating))
+        .chain(builder.inactivation_parameters.iter().map(|gating| ("inactivation", gating)))
+        .collect()
+}
+
+fn curve(f: impl Fn(&MilliVolts) -> Option<f32>) -> Vec<[f64; 2]> {
+    PLOTTED_VOLTAGES
+        .filter_map(|v| f(&MilliVolts(v as f32)).map(|y| [v as f64, y as f64]))
+        .collect()
+}
+
+/// Browse the built-in channels, see how their gates depend on voltage,
+/// and add one to the selected segments' membranes.
+pub fn channel_library_gui(
+    mut contexts: EguiContexts,
+    mut channel_library: ResMut<ChannelLibrary>,
+    mut selected: Query<(&mut Membrane, &MembraneVoltage), With<Selection>>,
+) {
+    egui::Window::new("Channel library")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let library = common_channels::library();
+            let channel_library = &mut *channel_library;
+            ui.horizontal(|ui| {
+                ui.label("Search");
+                ui.text_edit_singleline(&mut channel_library.search);
+            });
+            egui::ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+                for (i, (name, _, _)) in library.iter().enumerate() {
+                    if matches_search(name, &channel_library.search) {
+                        ui.selectable_value(&mut channel_library.chosen, Some(i), *name);
+                    }
+                }
+            });
+
+            let Some((name, builder, siemens_per_square_cm)) = channel_library.chosen.and_then(|i| library.get(i)) else {
+                return;
+            };
+            ui.separator();
+            ui.heading(*name);
+            ui.label(format!(
+                "{}, typically {:.3} mS/cm²",
+                channel_label(&builder.clone().build(&MilliVolts(0.0))),
+                siemens_per_square_cm * 1e3,
+            ));
+
+            let gates = gates(builder);
+            if gates.is_empty() {
+                ui.label("No voltage-dependent gates: always open.");
+            } else {
+                ui.label("Steady state against mV");
+                Plot::new("channel_library_steady_state")
+                    .view_aspect(2.0)
+                    .legend(Legend::default())
+                    .show(ui, |plot_ui| {
+                        for (gate, gating) in &gates {
+                            let name = format!("{} (x{})", gate, gating.gates);
+                            plot_ui.line(Line::new(curve(|v| Some(gating.steady_state_magnitude.steady_state(v)))).name(name));
+                        }
+                    });
+                ui.label("Time constant (ms) against mV");
+                Plot::new("channel_library_tau")
+                    .view_aspect(2.0)
+                    .legend(Legend::default())
+                    .show(ui, |plot_ui| {
+                        for (gate, gating) in &gates {
+                            plot_ui.line(Line::new(curve(|v| gating.time_constant.tau(v).map(|tau| tau * 1000.0))).name(*gate));
+                        }
+                    });
+            }
+
+            let count = selected.iter().count();
+            if ui.add_enabled(count > 0, egui::Button::new(format!("Add to {} selected segments", count))).clicked() {
+                for (mut membrane, voltage) in &mut selected {
+                    membrane.membrane_channels.push(MembraneChannel {
+                        channel: builder.clone().build(&voltage.0),
+                        siemens_per_square_cm: *siemens_per_square_cm,
+                    });
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_matches_every_word() {
+        assert!(matches_search("Rat CA1 HCN (soma)", ""));
+        assert!(matches_search("Rat CA1 HCN (soma)", "hcn rat"));
+        assert!(!matches_search("Rat CA1 HCN (soma)", "hcn squid"));
+        let found = common_channels::library().iter().filter(|(name, _, _)| matches_search(name, "squid")).count();
+        assert_eq!(found, 4);
+    }
+}
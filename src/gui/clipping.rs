@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::neuron::segment::ecs::Segment;
+use crate::neuron::voltage_material::{ClipPlane, MembraneMaterials};
+
+/// The least and greatest `normal · p` over the segments, which the
+/// offset slider spans.
+fn extent_along(normal: Vec3, segments: &Query<&GlobalTransform, With<Segment>>) -> Option<(f32, f32)> {
+    segments
+        .iter()
+        .map(|transform| normal.dot(transform.translation()))
+        .fold(None, |extent, d| Some(extent.map_or((d, d), |(lo, hi): (f32, f32)| (lo.min(d), hi.max(d)))))
+}
+
+/// Slice through the scene with a plane, set along an axis or the view
+/// direction and moved with a slider.
+pub fn clipping_gui(
+    mut contexts: EguiContexts,
+    membrane_materials: Option<ResMut<MembraneMaterials>>,
+    segments: Query<&GlobalTransform, With<Segment>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    let Some(mut membrane_materials) = membrane_materials else {
+        return;
+    };
+    egui::Window::new("Cross-section")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let mut enabled = membrane_materials.clip_plane.is_some();
+            if ui.checkbox(&mut enabled, "Clip the scene").changed() {
+                membrane_materials.clip_plane = enabled.then(|| {
+                    let extent = extent_along(Vec3::X, &segments).unwrap_or((0.0, 0.0));
+                    ClipPlane { normal: Vec3::X, offset: (extent.0 + extent.1) * 0.5 }
+                });
+            }
+            // Compare a copy, so that the materials are only touched when
+            // the plane moves.
+            let Some(mut plane) = membrane_materials.clip_plane else {
+                return;
+            };
+            ui.horizontal(|ui| {
+                ui.label("Facing");
+                for (name, normal) in [("+X", Vec3::X), ("+Y", Vec3::Y), ("+Z", Vec3::Z)] {
+                    if ui.selectable_label(plane.normal == normal, name).clicked() {
+                        plane.normal = normal;
+                    }
+                }
+                let view = cameras.iter().next().map(|transform| -transform.forward());
+                if ui.add_enabled(view.is_some(), egui::Button::new("Viewer")).on_hover_text("Cut away what is nearest the camera").clicked() {
+                    if let Some(view) = view {
+                        plane.normal = view.normalize();
+                    }
+                }
+                if ui.button("Flip").on_hover_text("Cut away the other side").clicked() {
+                    plane.normal = -plane.normal;
+                    plane.offset = -plane.offset;
+                }
+            });
+            let (lo, hi) = extent_along(plane.normal, &segments).unwrap_or((-1000.0, 1000.0));
+            let margin = ((hi - lo) * 0.05).max(1.0);
+            ui.add(egui::Slider::new(&mut plane.offset, lo - margin..=hi + margin).text("Offset"));
+            if membrane_materials.clip_plane != Some(plane) {
+                membrane_materials.clip_plane = Some(plane);
+            }
+        });
+}
+
+/// Outline the clip plane across the scene.
+pub fn draw_clip_plane(
+    mut gizmos: Gizmos,
+    membrane_materials: Option<Res<MembraneMaterials>>,
+    segments: Query<&GlobalTransform, With<Segment>>,
+) {
+    let Some(plane) = membrane_materials.and_then(|m| m.clip_plane) else {
+        return;
+    };
+    let Some((min, max)) = segments
+        .iter()
+        .map(|transform| transform.translation())
+        .fold(None, |bounds, p| Some(bounds.map_or((p, p), |(min, max): (Vec3, Vec3)| (min.min(p), max.max(p))))) else {
+        return;
+    };
+    // Centre the outline on the point of the plane nearest the scene's
+    // middle, and make it as wide as the scene.
+    let middle = (min + max) * 0.5;
+    let center = middle - plane.normal * (plane.normal.dot(middle) - plane.offset);
+    let size = (max - min).length().max(1.0);
+    gizmos.rect(center, Quat::from_rotation_arc(Vec3::Z, plane.normal), Vec2::splat(size), Color::rgb(1.0, 0.8, 0.2));
+}
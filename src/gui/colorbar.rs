@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::neuron::voltage_material::MembraneMaterials;
+
+/// Size of the color strip (points).
+const BAR_WIDTH: f32 = 16.0;
+const BAR_HEIGHT: f32 = 160.0;
+/// Bands the strip is drawn in.
+const BANDS: usize = 64;
+/// Labelled voltages, evenly spaced from the bottom to the top of the range.
+const TICKS: usize = 5;
+
+#[derive(Resource)]
+pub struct Colorbar {
+    pub shown: bool,
+}
+
+impl Default for Colorbar {
+    fn default() -> Self {
+        Colorbar { shown: true }
+    }
+}
+
+/// A legend at the right of the window mapping segment colors to
+/// millivolts, following the colormap and range in use.
+pub fn colorbar_gui(mut contexts: EguiContexts, colorbar: Res<Colorbar>, membrane_materials: Option<Res<MembraneMaterials>>) {
+    let Some(membrane_materials) = membrane_materials.filter(|_| colorbar.shown) else {
+        return;
+    };
+    let colormap = membrane_materials.colormap;
+    let (v_min, v_max) = (membrane_materials.voltage_range.0 .0, membrane_materials.voltage_range.1 .0);
+    let color = |t: f32| {
+        let [r, g, b, _] = colormap.color(t);
+        egui::Color32::from_rgb(r, g, b)
+    };
+
+    egui::Area::new(egui::Id::new("voltage_colorbar"))
+        .anchor(egui::Align2::RIGHT_CENTER, [-10.0, 0.0])
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let font = egui::FontId::proportional(12.0);
+            let label_width = 56.0;
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(BAR_WIDTH + label_width, BAR_HEIGHT + 24.0), egui::Sense::hover());
+            let bar = egui::Rect::from_min_size(rect.min + egui::vec2(label_width, 16.0), egui::vec2(BAR_WIDTH, BAR_HEIGHT));
+            let painter = ui.painter();
+            painter.text(bar.center_top() - egui::vec2(0.0, 4.0), egui::Align2::CENTER_BOTTOM, "mV", font.clone(), egui::Color32::WHITE);
+            // The most depolarized color at the top.
+            let band_height = BAR_HEIGHT / BANDS as f32;
+            for band in 0..BANDS {
+                let t = 1.0 - (band as f32 + 0.5) / BANDS as f32;
+                let top = bar.top() + band as f32 * band_height;
+                let band_rect = egui::Rect::from_x_y_ranges(bar.x_range(), top..=top + band_height + 0.5);
+                painter.rect_filled(band_rect, 0.0, color(t));
+            }
+            painter.rect_stroke(bar, 0.0, egui::Stroke::new(1.0, egui::Color32::GRAY));
+            for tick in 0..TICKS {
+                let t = tick as f32 / (TICKS - 1) as f32;
+                let y = bar.bottom() - t * BAR_HEIGHT;
+                painter.line_segment([egui::pos2(bar.left() - 4.0, y), egui::pos2(bar.left(), y)], egui::Stroke::new(1.0, egui::Color32::WHITE));
+                painter.text(
+                    egui::pos2(bar.left() - 6.0, y),
+                    egui::Align2::RIGHT_CENTER,
+                    format!("{:.0}", v_min + t * (v_max - v_min)),
+                    font.clone(),
+                    egui::Color32::WHITE,
+                );
+            }
+        });
+}
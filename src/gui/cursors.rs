@@ -0,0 +1,163 @@
+//! A pair of draggable measurement cursors for plots.
+//!
+//! Each cursor marks a point (a time and a value). With cursors shown,
+//! dragging near one moves it instead of panning the plot, and the readout
+//! shows the differences between them and the slope of the line joining
+//! them.
+
+use bevy_egui::egui::{Color32, Ui};
+use egui_plot::{HLine, PlotUi, VLine};
+
+/// How close to a cursor, as a fraction of the visible plot, a drag must
+/// start to pick it up.
+const GRAB_FRACTION: f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Which {
+    A,
+    B,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cursors {
+    pub shown: bool,
+    pub a: [f64; 2],
+    pub b: [f64; 2],
+    dragging: Option<Which>,
+    /// Whether the cursors have been put in view since they were shown.
+    placed: bool,
+}
+
+/// Differences between cursor B and cursor A.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Delta {
+    pub dx: f64,
+    pub dy: f64,
+    /// `dy / dx`, or `None` when the cursors share a time.
+    pub slope: Option<f64>,
+}
+
+impl Default for Cursors {
+    fn default() -> Self {
+        Cursors { shown: false, a: [0.0, 0.0], b: [1.0, 0.0], dragging: None, placed: false }
+    }
+}
+
+impl Cursors {
+    pub fn delta(&self) -> Delta {
+        let dx = self.b[0] - self.a[0];
+        let dy = self.b[1] - self.a[1];
+        Delta { dx, dy, slope: if dx == 0.0 { None } else { Some(dy / dx) } }
+    }
+
+    /// The cursor within `tolerance` (per axis) of `point`, preferring the
+    /// nearer one.
+    pub fn nearest(&self, point: [f64; 2], tolerance: [f64; 2]) -> Option<Which> {
+        let distance = |c: [f64; 2]| {
+            let (dx, dy) = ((c[0] - point[0]) / tolerance[0], (c[1] - point[1]) / tolerance[1]);
+            // A cursor is a crosshair, so it can be grabbed by either line.
+            dx.abs().min(dy.abs())
+        };
+        let (da, db) = (distance(self.a), distance(self.b));
+        match (da <= 1.0, db <= 1.0) {
+            (false, false) => None,
+            _ if da <= db => Some(Which::A),
+            _ => Some(Which::B),
+        }
+    }
+
+    /// Place both cursors inside the visible area, a third of the way in
+    /// from either side.
+    fn place(&mut self, min: [f64; 2], max: [f64; 2]) {
+        let at = |f: f64| [min[0] + f * (max[0] - min[0]), min[1] + f * (max[1] - min[1])];
+        self.a = at(1.0 / 3.0);
+        self.b = at(2.0 / 3.0);
+    }
+
+    /// Draw the cursors and handle dragging them. Call inside the plot's
+    /// `show` closure; the plot should have dragging disabled while the
+    /// cursors are shown.
+    pub fn show(&mut self, plot_ui: &mut PlotUi) {
+        if !self.shown {
+            self.placed = false;
+            return;
+        }
+        let bounds = plot_ui.plot_bounds();
+        if !self.placed {
+            self.place(bounds.min(), bounds.max());
+            self.placed = true;
+        }
+        let response = plot_ui.response().clone();
+        let pointer = plot_ui.pointer_coordinate().map(|p| [p.x, p.y]);
+
+        if response.drag_started() {
+            let tolerance = [bounds.width() * GRAB_FRACTION, bounds.height() * GRAB_FRACTION];
+            self.dragging = pointer.and_then(|p| self.nearest(p, tolerance));
+        }
+        if response.dragged() {
+            if let (Some(which), Some(p)) = (self.dragging, pointer) {
+                match which {
+                    Which::A => self.a = p,
+                    Which::B => self.b = p,
+                }
+            }
+        }
+        if response.drag_stopped() {
+            self.dragging = None;
+        }
+
+        for (cursor, name, color) in [(self.a, "A", Color32::WHITE), (self.b, "B", Color32::GRAY)] {
+            plot_ui.vline(VLine::new(cursor[0]).color(color).name(name));
+            plot_ui.hline(HLine::new(cursor[1]).color(color).name(name));
+        }
+    }
+
+    /// The show/hide toggle and the measurement readout. `to_x` converts
+    /// plotted times into `x_unit`, and `to_y` converts plotted values into
+    /// `y_unit` of the quantity `y_name` (e.g. "V").
+    pub fn readout(
+        &mut self,
+        ui: &mut Ui,
+        x_unit: &str,
+        to_x: impl Fn(f64) -> f64,
+        y_name: &str,
+        y_unit: &str,
+        to_y: impl Fn(f64) -> f64,
+    ) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.shown, "Cursors");
+            if self.shown {
+                let a = [to_x(self.a[0]), to_y(self.a[1])];
+                let b = [to_x(self.b[0]), to_y(self.b[1])];
+                let delta = Cursors { a, b, ..self.clone() }.delta();
+                ui.label(format!("Δt = {:.3} {}", delta.dx, x_unit));
+                ui.label(format!("Δ{} = {:.3} {}", y_name, delta.dy, y_unit));
+                ui.label(match delta.slope {
+                    Some(slope) => format!("slope = {:.3} {}/{}", slope, y_unit, x_unit),
+                    None => "slope = -".to_string(),
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_and_slope() {
+        let cursors = Cursors { a: [1.0, -70.0], b: [3.0, -60.0], ..Cursors::default() };
+        assert_eq!(cursors.delta(), Delta { dx: 2.0, dy: 10.0, slope: Some(5.0) });
+        let vertical = Cursors { a: [1.0, -70.0], b: [1.0, -60.0], ..Cursors::default() };
+        assert_eq!(vertical.delta().slope, None);
+    }
+
+    #[test]
+    fn grabs_the_nearer_cursor() {
+        let cursors = Cursors { a: [0.0, 0.0], b: [10.0, 10.0], ..Cursors::default() };
+        assert_eq!(cursors.nearest([0.5, 5.0], [1.0, 1.0]), Some(Which::A));
+        assert_eq!(cursors.nearest([9.8, 5.0], [1.0, 1.0]), Some(Which::B));
+        assert_eq!(cursors.nearest([5.0, 5.0], [1.0, 1.0]), None);
+    }
+}
@@ -0,0 +1,37 @@
+/// Save a file produced by the GUI. On native builds the file is written
+/// to the working directory. On the web it is offered to the user as a
+/// browser download.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_file(file_name: &str, _mime_type: &str, contents: &[u8]) -> Result<(), String> {
+    std::fs::write(file_name, contents)
+        .map_err(|e| format!("Failed to write {}: {}", file_name, e))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save_file(file_name: &str, mime_type: &str, contents: &[u8]) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+
+    let bytes = js_sys::Uint8Array::from(contents);
+    let parts = js_sys::Array::of1(&bytes);
+    let mut options = web_sys::BlobPropertyBag::new();
+    options.type_(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|e| format!("Failed to create blob: {:?}", e))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|e| format!("Failed to create object url: {:?}", e))?;
+
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or("No document".to_string())?;
+    let anchor = document
+        .create_element("a")
+        .map_err(|e| format!("Failed to create anchor: {:?}", e))?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|_| "Element is not an anchor".to_string())?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    web_sys::Url::revoke_object_url(&url)
+        .map_err(|e| format!("Failed to revoke object url: {:?}", e))
+}
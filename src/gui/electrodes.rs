@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::dimension::Timestamp;
+use crate::extracellular::ExtracellularStimulator;
+use crate::gui::oscilloscope::Oscilloscope;
+use crate::integrations::grace::SceneSegment;
+use crate::lfp::{ExtracellularElectrode, SourceModel};
+use crate::probe::ProbeSource;
+use crate::selection::Selection;
+use crate::units::UnitScale;
+
+/// How far (µm) from the selected segment a new electrode is placed.
+const PLACEMENT_OFFSET_UM: f32 = 50.0;
+
+fn position_setting(ui: &mut egui::Ui, position_um: &mut Vec3) {
+    ui.horizontal(|ui| {
+        ui.add(egui::DragValue::new(&mut position_um.x).prefix("x ").suffix(" µm"));
+        ui.add(egui::DragValue::new(&mut position_um.y).prefix("y ").suffix(" µm"));
+        ui.add(egui::DragValue::new(&mut position_um.z).prefix("z ").suffix(" µm"));
+    });
+}
+
+/// Place extracellular electrodes: recording ones, sending their local
+/// field potentials to the oscilloscope, and stimulating ones.
+pub fn electrodes_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    scale: Res<UnitScale>,
+    timestamp: Res<Timestamp>,
+    mut oscilloscope: ResMut<Oscilloscope>,
+    selected: Query<&Transform, (With<SceneSegment>, With<Selection>)>,
+    mut electrodes: Query<(Entity, &mut ExtracellularElectrode)>,
+    mut stimulators: Query<(Entity, &mut ExtracellularStimulator)>,
+) {
+    egui::Window::new("Extracellular electrodes")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let selection = selected.iter().next().map(|t| t.translation / scale.screen_per_micron());
+            // New electrodes go beside the selected segment, or at the
+            // origin.
+            let placement = selection.map_or(Vec3::ZERO, |p| p + Vec3::X * PLACEMENT_OFFSET_UM);
+
+            ui.heading("Recording");
+            if electrodes.is_empty() {
+                ui.weak("No electrodes");
+            }
+            for (entity, mut electrode) in &mut electrodes {
+                let electrode = &mut *electrode;
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut electrode.label).desired_width(100.0));
+                    ui.label(format!("{:.3} uV", electrode.potential_uv));
+                });
+                position_setting(ui, &mut electrode.position_um);
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut electrode.conductivity).clamp_range(0.01..=10.0).speed(0.01).suffix(" S/m"));
+                    ui.selectable_value(&mut electrode.model, SourceModel::PointSource, "Point sources");
+                    ui.selectable_value(&mut electrode.model, SourceModel::LineSource, "Line sources");
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Show on oscilloscope").clicked() {
+                        // The first free channel, or the last one.
+                        let free = oscilloscope.channels.iter().position(|c| c.source.is_none());
+                        let channel = free.unwrap_or(oscilloscope.channels.len() - 1);
+                        oscilloscope.accept_source(channel, entity);
+                        oscilloscope.channels[channel].quantity = ProbeSource::Lfp;
+                    }
+                    if ui.button("Remove").clicked() {
+                        commands.entity(entity).despawn();
+                    }
+                });
+            }
+            if ui.button("Add recording electrode").clicked() {
+                let label = format!("electrode_{}", electrodes.iter().len() + 1);
+                commands.spawn(ExtracellularElectrode::new(label, placement));
+            }
+
+            ui.separator();
+            ui.heading("Stimulating");
+            for (i, (entity, mut stimulator)) in stimulators.iter_mut().enumerate() {
+                let current = stimulator.pulses.current(&timestamp);
+                egui::CollapsingHeader::new(format!("Stimulator {}{}", i + 1, if current != 0.0 { " (on)" } else { "" }))
+                    .id_source(entity)
+                    .show(ui, |ui| {
+                        let stimulator = &mut *stimulator;
+                        position_setting(ui, &mut stimulator.position_um);
+                        let pulses = &mut stimulator.pulses;
+                        ui.add(egui::DragValue::new(&mut pulses.current_ua).clamp_range(-10000.0..=10000.0).suffix(" µA"));
+                        ui.checkbox(&mut pulses.biphasic, "Biphasic").on_hover_text("Reverse the current halfway through each pulse");
+                        let envelope = &mut pulses.train.envelope;
+                        let mut width_us = (envelope.offset.0 - envelope.onset.0) * 1e6;
+                        let mut rate_hz = 1.0 / envelope.period.0;
+                        ui.horizontal(|ui| {
+                            if ui.add(egui::DragValue::new(&mut width_us).clamp_range(1.0..=100_000.0).suffix(" µs wide")).changed() {
+                                envelope.offset.0 = envelope.onset.0 + width_us * 1e-6;
+                            }
+                            if ui.add(egui::DragValue::new(&mut rate_hz).clamp_range(0.1..=10_000.0).suffix(" Hz")).changed() {
+                                envelope.period.0 = 1.0 / rate_hz;
+                            }
+                        });
+                        ui.add(egui::DragValue::new(&mut stimulator.conductivity).clamp_range(0.01..=10.0).speed(0.01).suffix(" S/m"));
+                        if let Some(point_um) = selection {
+                            ui.weak(format!(
+                                "{:.3} mV per µA at the selected segment",
+                                stimulator.potential_per_microamp(point_um),
+                            ));
+                        }
+                        if ui.button("Remove").clicked() {
+                            commands.entity(entity).despawn();
+                        }
+                    });
+            }
+            if ui.button("Add stimulating electrode").clicked() {
+                commands.spawn(ExtracellularStimulator { position_um: placement, ..default() });
+            }
+        });
+}
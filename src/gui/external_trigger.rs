@@ -16,6 +16,12 @@
 //! so that the FFI function can access it. The reader end will be polled
 //! by ECS, and the polling function therfore has access to the necessary
 //! queries for doing a full load and spawn.
+//!
+//! Simulation commands (`crate::command::Command`) arrive the same way,
+//! through `simulation_command`, and are sent on as events. In the other
+//! direction, a `crate::snapshot::Snapshot` of the simulation is published
+//! for `simulation_time`, `simulation_state` and `segment_voltage` to
+//! answer from, every frame the simulation changes.
 use once_cell::sync::OnceCell; // TODO: Bump rustc and use std::cell::OnceCell when stable.
 use crossbeam::channel::{Receiver, Sender};
 use bevy::prelude::*;
@@ -27,6 +33,10 @@ use crate::neuron::ecs::Neuron;
 use crate::neuron::segment::ecs::Segment;
 use crate::gui::load::{load_ffg_scene, GraceSceneSource, InterpreterUrl, IsLoading};
 use crate::integrations::grace::GraceSceneSender;
+use crate::command::Command;
+use crate::serialize::SegmentRef;
+use crate::snapshot::{self, publish_snapshot};
+use crate::validate::checked_scene;
 
 /// The primary interface interface to this module, from nb-sim's perspective.
 /// nb-sim only needs to install this plugin, after the Neuron and Gui plugins
@@ -37,15 +47,27 @@ pub struct ExternalTriggerPlugin;
 /// The global variable holding a Sender for new
 static EXTERNAL_TRIGGER_SENDER: OnceCell<Sender<String>> = OnceCell::new();
 
+/// The same, for simulation commands.
+static COMMAND_SENDER: OnceCell<Sender<Command>> = OnceCell::new();
+
 #[derive(Resource)]
 struct ExternalTriggerReceiver (Receiver<String>);
 
+#[derive(Resource)]
+struct CommandReceiver(Receiver<Command>);
+
 impl Plugin for ExternalTriggerPlugin {
     fn build(&self, app: &mut App) {
         let (tx, rx) = crossbeam::channel::unbounded();
         EXTERNAL_TRIGGER_SENDER.set(tx).expect("Should be able to set trigger.");
         app.insert_resource(ExternalTriggerReceiver(rx));
         app.add_systems(Update, respond_to_triggers);
+
+        let (tx, rx) = crossbeam::channel::unbounded();
+        COMMAND_SENDER.set(tx).expect("Should be able to set command sender.");
+        app.insert_resource(CommandReceiver(rx));
+        app.add_systems(Update, forward_commands);
+        app.add_systems(Update, publish_snapshot);
     }
 }
 
@@ -70,6 +92,13 @@ fn respond_to_triggers(
     }
 }
 
+fn forward_commands(
+    receiver: Res<CommandReceiver>,
+    mut commands: EventWriter<Command>,
+) {
+    commands.send_batch(receiver.0.try_iter());
+}
+
 /// This function is exported via `wasm_bindgen`. It is exported to Javascript clients,
 /// so that they can trigger the loading of new scenes by calling it.
 #[wasm_bindgen]
@@ -77,3 +106,44 @@ pub fn set_scene_source(str: String) {
     let sender = EXTERNAL_TRIGGER_SENDER.get().expect("Trigger should be initialized by start()");
     sender.send(str).expect("Should be able to send source to channel.");
 }
+
+/// Apply `command` between frames, as the GUI would.
+pub fn send_command(command: Command) -> Result<(), String> {
+    let sender = COMMAND_SENDER.get().ok_or("Commands should be initialized by start()".to_string())?;
+    sender.send(command).map_err(|e| e.to_string())
+}
+
+/// Exported to Javascript clients, to control the simulation remotely. The
+/// command is JSON, e.g. `{"type": "Pause"}`.
+#[wasm_bindgen]
+pub fn simulation_command(json: String) -> Result<(), String> {
+    send_command(serde_json::from_str(&json).map_err(|e| e.to_string())?)
+}
+
+/// Exported to Javascript clients, to replace the running scene with one
+/// given as JSON or Dhall. The swap happens between frames, at time zero.
+/// Scenes that fail validation are rejected with every problem found.
+#[wasm_bindgen]
+pub fn upload_scene(source: String) -> Result<(), String> {
+    send_command(Command::LoadScene { scene: checked_scene(&source)? })
+}
+
+/// Exported to Javascript clients: the simulation time and step, as JSON.
+#[wasm_bindgen]
+pub fn simulation_time() -> Result<String, String> {
+    snapshot::time()
+}
+
+/// Exported to Javascript clients: the time and every segment's membrane
+/// potential, as JSON.
+#[wasm_bindgen]
+pub fn simulation_state() -> Result<String, String> {
+    snapshot::state()
+}
+
+/// Exported to Javascript clients: one segment's membrane potential, by
+/// its neuron's index in the scene and its SWC id, as JSON.
+#[wasm_bindgen]
+pub fn segment_voltage(neuron: usize, segment: i32) -> Result<String, String> {
+    snapshot::voltage(SegmentRef { neuron, segment })
+}
@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::constants::SimulationConstants;
+use crate::gui::membrane::channel_label;
+use crate::integrations::grace::SceneSegment;
+use crate::neuron::channel::GateState;
+use crate::neuron::extracellular_space::{local_solution, ExtracellularSpace};
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::solution::Solution;
+use crate::plugin::Env;
+use crate::selection::Selection;
+
+fn gate_label(gate: &Option<GateState>) -> String {
+    gate.as_ref().map_or("-".to_string(), |gate| format!("{:.4}", gate.magnitude))
+}
+
+/// While a segment is selected, show its gates, conductances, reversal
+/// potentials and currents as they are this frame.
+pub fn inspector_gui(
+    mut contexts: EguiContexts,
+    env: Res<Env>,
+    constants: Res<SimulationConstants>,
+    selected: Query<(&SceneSegment, &Membrane, &MembraneVoltage, &Solution, Option<&ExtracellularSpace>), With<Selection>>,
+) {
+    let Some((SceneSegment(at), membrane, voltage, solution, space)) = selected.iter().next() else {
+        return;
+    };
+    let v = &voltage.0;
+    let extracellular = local_solution(&env, space);
+    let reversals = [
+        ("K+", constants.k_reversal(solution, extracellular)),
+        ("Na+", constants.na_reversal(solution, extracellular)),
+        ("Cl-", constants.cl_reversal(solution, extracellular)),
+        ("Ca2+", constants.ca_reversal(solution, extracellular)),
+    ];
+    let [(_, e_k), (_, e_na), (_, e_cl), (_, e_ca)] = &reversals;
+
+    egui::Window::new("Segment inspector").show(contexts.ctx_mut(), |ui| {
+        ui.label(format!("Neuron {}, segment {}: {:.2} mV", at.neuron + 1, at.segment, v.0));
+
+        ui.heading("Channels");
+        egui::Grid::new("inspector_channels").striped(true).show(ui, |ui| {
+            ui.strong("Channel");
+            ui.strong("m");
+            ui.strong("h");
+            ui.strong("G (mS/cm²)");
+            ui.strong("I (µA/cm²)");
+            ui.end_row();
+            for membrane_channel in &membrane.membrane_channels {
+                let channel = &membrane_channel.channel;
+                ui.label(channel_label(channel));
+                ui.label(gate_label(&channel.activation));
+                ui.label(gate_label(&channel.inactivation));
+                ui.label(format!("{:.4}", channel.conductance_coefficient() * membrane_channel.siemens_per_square_cm * 1e3));
+                ui.label(format!("{:.3}", membrane_channel.channel_current_per_cm(e_k, e_na, e_cl, e_ca, v) * 1e6));
+                ui.end_row();
+            }
+        });
+        let total = membrane.current_per_square_cm(e_k, e_na, e_cl, e_ca, v);
+        ui.label(format!("Total membrane current: {:.3} µA/cm²", total * 1e6));
+
+        ui.heading("Ions");
+        let (g_k, g_na, g_cl, g_ca) = membrane.conductances();
+        egui::Grid::new("inspector_ions").striped(true).show(ui, |ui| {
+            ui.strong("Ion");
+            ui.strong("G (mS/cm²)");
+            ui.strong("E (mV)");
+            ui.strong("Driving force (mV)");
+            ui.end_row();
+            for ((ion, reversal), g) in reversals.iter().zip([g_k, g_na, g_cl, g_ca]) {
+                ui.label(*ion);
+                ui.label(format!("{:.4}", g * 1e3));
+                ui.label(format!("{:.2}", reversal.0));
+                ui.label(format!("{:.2}", v.0 - reversal.0));
+                ui.end_row();
+            }
+        });
+    });
+}
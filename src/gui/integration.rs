@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::dimension::SimulationStepSeconds;
+use crate::integrations::grace::{CurrentScene, SceneSegment};
+use crate::neuron::multirate::{IntegrationRate, MAX_STEP_MULTIPLE};
+use crate::selection::Selection;
+
+/// SWC types, with the regions they are named after.
+const REGIONS: [(usize, &str); 4] = [(1, "Soma"), (2, "Axon"), (3, "Basal dendrites"), (4, "Apical dendrites")];
+
+/// Step multiples chosen for each region, indexed like `REGIONS`, and
+/// for the current selection.
+#[derive(Resource)]
+pub struct IntegrationRates {
+    pub every: [u32; 4],
+    pub selected_every: u32,
+}
+
+impl Default for IntegrationRates {
+    fn default() -> Self {
+        IntegrationRates { every: [1; 4], selected_every: 1 }
+    }
+}
+
+fn set_rate(commands: &mut Commands, entity: Entity, every: u32) {
+    match every {
+        1 => {
+            commands.entity(entity).remove::<IntegrationRate>();
+        },
+        every => {
+            if let Ok(rate) = IntegrationRate::new(every) {
+                commands.entity(entity).insert(rate);
+            }
+        },
+    }
+}
+
+pub fn integration_rates_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut rates: ResMut<IntegrationRates>,
+    simulation_step: Res<SimulationStepSeconds>,
+    current_scene: Res<CurrentScene>,
+    segments: Query<(Entity, &SceneSegment)>,
+    selected: Query<Entity, With<Selection>>,
+    rated: Query<(), With<IntegrationRate>>,
+) {
+    egui::Window::new("Integration rates")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("Slow regions step once every N simulation steps.");
+            let step_us = simulation_step.0 * 1e6;
+            egui::Grid::new("integration_rates").show(ui, |ui| {
+                for ((_, name), every) in REGIONS.iter().zip(rates.every.iter_mut()) {
+                    ui.label(*name);
+                    ui.add(egui::DragValue::new(every).clamp_range(1..=MAX_STEP_MULTIPLE).prefix("every "));
+                    ui.label(format!("{:.2} µs", step_us * *every as f32));
+                    ui.end_row();
+                }
+            });
+            if ui.button("Apply to regions").clicked() {
+                let types = current_scene
+                    .0
+                    .neurons
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(n, scene_neuron)| scene_neuron.neuron.segments.iter().map(move |s| ((n, s.id), s.type_)))
+                    .collect::<HashMap<_, _>>();
+                for (entity, SceneSegment(at)) in &segments {
+                    let region = types.get(&(at.neuron, at.segment)).and_then(|t| REGIONS.iter().position(|(r, _)| r == t));
+                    // Other SWC types step with the soma.
+                    let every = rates.every[region.unwrap_or(0)];
+                    set_rate(&mut commands, entity, every);
+                }
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut rates.selected_every).clamp_range(1..=MAX_STEP_MULTIPLE).prefix("every "));
+                if ui.button("Apply to selected").clicked() {
+                    for entity in &selected {
+                        set_rate(&mut commands, entity, rates.selected_every);
+                    }
+                }
+            });
+            ui.label(format!("{} segments on slow steps.", rated.iter().count()));
+        });
+}
@@ -0,0 +1,109 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::dimension::{Interval, Molar};
+use crate::integrations::grace::Synapse;
+use crate::neuron::calcium::{CalciumBuffering, CalciumShell};
+use crate::neuron::extracellular_space::{ExtracellularSpace, PotassiumAccumulation};
+use crate::neuron::ion_dynamics::IonDynamics;
+use crate::neuron::segment::ecs::Segment;
+use crate::neuron::solution::Solution;
+use crate::plugin::Env;
+
+fn milliseconds_setting(ui: &mut egui::Ui, label: &str, interval: &mut Interval) {
+    let mut ms = interval.0 * 1000.0;
+    if ui.add(egui::DragValue::new(&mut ms).clamp_range(0.0..=100000.0).prefix(label).suffix(" ms")).changed() {
+        interval.0 = ms / 1000.0;
+    }
+}
+
+fn micromolar_setting(ui: &mut egui::Ui, label: &str, concentration: &mut Molar) {
+    let mut um = concentration.0 * 1e6;
+    if ui.add(egui::DragValue::new(&mut um).clamp_range(0.0..=10000.0).speed(0.01).prefix(label).suffix(" µM")).changed() {
+        concentration.0 = um * 1e-6;
+    }
+}
+
+/// The least, mean and largest of some concentrations, if there are any.
+fn summary(concentrations: impl Iterator<Item = f32>) -> Option<(f32, f32, f32)> {
+    let (count, min, sum, max) = concentrations
+        .fold((0, f32::MAX, 0.0, f32::MIN), |(n, min, sum, max), c| (n + 1, min.min(c), sum + c, max.max(c)));
+    (count > 0).then(|| (min, sum / count as f32, max))
+}
+
+/// Let ion concentrations change, inside segments and around them, and
+/// follow how far they have moved.
+pub fn ions_gui(
+    mut contexts: EguiContexts,
+    mut env: ResMut<Env>,
+    mut ion_dynamics: ResMut<IonDynamics>,
+    spaces: Query<&ExtracellularSpace>,
+    shells: Query<&CalciumShell>,
+    solutions: Query<&Solution, With<Segment>>,
+    synapses: Query<&Synapse>,
+) {
+    egui::Window::new("Ion concentrations")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.heading("Inside");
+            ui.checkbox(&mut ion_dynamics.intracellular, "Update from channel currents")
+                .on_hover_text("Move the ions each channel carries into or out of the segment's volume");
+            ui.checkbox(&mut ion_dynamics.longitudinal_diffusion, "Diffuse along junctions")
+                .on_hover_text("Let ions diffuse between neighbouring segments, relaxing gradients along dendrites");
+            if let Some((_, mean, max)) = summary(solutions.iter().map(|s| s.na_concentration.0 * 1000.0)) {
+                ui.label(format!("Na+: mean {:.2} mM, up to {:.2} mM", mean, max));
+            }
+            if let Some((min, mean, _)) = summary(solutions.iter().map(|s| s.k_concentration.0 * 1000.0)) {
+                ui.label(format!("K+: mean {:.2} mM, down to {:.2} mM", mean, min));
+            }
+
+            ui.separator();
+            ui.heading("Calcium");
+            let mut buffered = ion_dynamics.calcium.is_some();
+            if ui.checkbox(&mut buffered, "Buffer in a shell beneath the membrane")
+                .on_hover_text("Calcium-dependent channels and plastic synapses follow the shell")
+                .changed() {
+                ion_dynamics.calcium = buffered.then(CalciumBuffering::default);
+            }
+            if let Some(buffering) = ion_dynamics.calcium.as_mut() {
+                ui.add(egui::Slider::new(&mut buffering.shell_depth_um, 0.01..=1.0).logarithmic(true).text("shell (µm)"));
+                ui.horizontal(|ui| {
+                    micromolar_setting(ui, "rest ", &mut buffering.resting);
+                    milliseconds_setting(ui, "extrusion ", &mut buffering.extrusion_tau);
+                });
+                ui.horizontal(|ui| {
+                    micromolar_setting(ui, "buffer ", &mut buffering.buffer_total);
+                    micromolar_setting(ui, "Kd ", &mut buffering.buffer_kd);
+                });
+                ui.weak(format!("Buffer capacity at rest: {:.0}", buffering.buffer_capacity(buffering.resting.0)));
+                if let Some((_, mean, max)) = summary(shells.iter().map(|shell| shell.0.0 * 1e6)) {
+                    ui.label(format!("Free Ca2+: mean {:.3} µM, up to {:.3} µM", mean, max));
+                }
+            }
+            if let Some((min, mean, max)) = summary(synapses.iter().filter_map(|s| s.synapse_membranes.plasticity.as_ref()).map(|p| p.weight)) {
+                ui.label(format!("Plastic synapse weights: mean {:.3}, {:.3} to {:.3}", mean, min, max));
+            }
+
+            ui.separator();
+            ui.heading("Extracellular potassium");
+            let env = &mut *env;
+            let mut bath_mm = env.extracellular_solution.k_concentration.0 * 1000.0;
+            if ui.add(egui::DragValue::new(&mut bath_mm).clamp_range(0.0..=200.0).speed(0.1).prefix("Bath ").suffix(" mM")).changed() {
+                env.extracellular_solution.k_concentration.0 = bath_mm / 1000.0;
+            }
+
+            let mut accumulating = env.potassium.is_some();
+            if ui.checkbox(&mut accumulating, "Accumulate around each segment").changed() {
+                env.potassium = accumulating.then(PotassiumAccumulation::default);
+            }
+            let Some(potassium) = env.potassium.as_mut() else { return };
+            ui.add(egui::Slider::new(&mut potassium.shell_thickness_um, 0.01..=10.0).logarithmic(true).text("shell (µm)"));
+            ui.horizontal(|ui| {
+                milliseconds_setting(ui, "clearance ", &mut potassium.clearance_tau);
+                milliseconds_setting(ui, "diffusion ", &mut potassium.diffusion_tau);
+            });
+            if let Some((_, mean, max)) = summary(spaces.iter().map(|space| space.0.k_concentration.0 * 1000.0)) {
+                ui.label(format!("Around segments: mean {:.2} mM, up to {:.2} mM", mean, max));
+            }
+        });
+}
@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::gui::download::save_file;
+use crate::gui::oscilloscope::Oscilloscope;
+use crate::integrations::grace::{SceneSegment, SceneVisuals};
+use crate::layout;
+use crate::recording::csv_recorder::Recorder;
+use crate::serialize;
+use crate::stimulator::{Stimulation, Stimulator};
+
+/// The layout file being saved or loaded, and the outcome of the last
+/// attempt.
+#[derive(Resource)]
+pub struct LayoutFile {
+    pub path: String,
+    pub status: Option<Result<String, String>>,
+}
+
+impl Default for LayoutFile {
+    fn default() -> Self {
+        LayoutFile { path: "layout.json".to_string(), status: None }
+    }
+}
+
+fn read_layout(path: &str) -> Result<serialize::Layout, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+pub fn layout_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut visuals: SceneVisuals,
+    mut oscilloscope: ResMut<Oscilloscope>,
+    mut layout_file: ResMut<LayoutFile>,
+    placed: Query<(Entity, &SceneSegment, Option<&Stimulator>, Option<&Recorder>)>,
+    segments: Query<(Entity, &SceneSegment, &GlobalTransform)>,
+    stimulations: Query<(Entity, &Stimulation)>,
+) {
+    egui::Window::new("Protocol layout")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let layout_file = &mut *layout_file;
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut layout_file.path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Export").clicked() {
+                    let layout = layout::capture(placed.iter(), &oscilloscope);
+                    let summary = format!(
+                        "Saved {} stimulators, {} probes and {} recorders.",
+                        layout.stimulators.len(), layout.probes.len(), layout.recorders.len(),
+                    );
+                    layout_file.status = Some(
+                        serde_json::to_string_pretty(&layout)
+                            .map_err(|e| e.to_string())
+                            .and_then(|json| save_file(&layout_file.path, "application/json", json.as_bytes()))
+                            .map(|_| summary)
+                    );
+                }
+                if ui.button("Import").clicked() {
+                    layout_file.status = Some(read_layout(&layout_file.path).map(|layout| {
+                        let missing = layout::apply(
+                            &layout,
+                            &mut commands,
+                            &mut visuals,
+                            &mut oscilloscope,
+                            &segments,
+                            &stimulations,
+                        );
+                        if missing.is_empty() {
+                            "Applied layout.".to_string()
+                        } else {
+                            let missing = missing
+                                .iter()
+                                .map(|at| format!("{}:{}", at.neuron, at.segment))
+                                .collect::<Vec<_>>();
+                            format!("Applied layout, skipping missing segments {}.", missing.join(", "))
+                        }
+                    }));
+                }
+            });
+            match &layout_file.status {
+                Some(Ok(message)) => { ui.label(message.as_str()); },
+                Some(Err(e)) => { ui.colored_label(egui::Color32::LIGHT_RED, e.as_str()); },
+                None => {},
+            }
+        });
+}
@@ -1,20 +1,13 @@
 use bevy::prelude::*;
 use bevy_egui::egui::{self, Ui};
 use ehttp::{Request, fetch};
-use crossbeam::channel::unbounded;
 
 use crate::neuron::ecs::Neuron;
 use crate::neuron::Junction;
 use crate::neuron::segment::ecs::Segment;
 use crate::stimulator::Stimulation;
-use crate::selection::{Highlight, Selection};
-use crate::integrations::grace::{
-    GraceScene,
-    GraceSceneSender,
-    GraceSceneReceiver
-};
+use crate::integrations::grace::{GraceScene, GraceSceneSender};
 use crate::serialize;
-use crate::neuron::membrane::MembraneMaterials;
 use web_sys::window;
 
 #[derive(Resource)]
@@ -68,9 +61,6 @@ pub fn window_location_scene() -> String {
 pub fn setup(app: &mut App) {
   app.insert_resource(IsLoading(false));
   app.init_resource::<GraceSceneSource>();
-  let (tx, rx) = unbounded();
-  app.insert_resource(GraceSceneSender(tx));
-  app.insert_resource(GraceSceneReceiver(rx));
   // app.add_systems(Startup, startup_load_ffg_scene);
 }
 
@@ -164,20 +154,3 @@ pub fn run_grace_load_widget(
         load_ffg_scene(commands, interpreter_url, is_loading, source, neurons, segments, junctions, stimulations, grace_scene_sender);
     }
 }
-
-pub fn handle_loaded_neuron(
-    commands: Commands,
-    grace_scene_receiver: Res<GraceSceneReceiver>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    membrane_materials: Res<MembraneMaterials>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    selections: Query<Entity, With<Selection>>,
-    highlights: Query<Entity, With<Highlight>>,
-) {
-    match grace_scene_receiver.0.try_recv() {
-        Err(_) => {},
-        Ok(n) => {
-            n.spawn(Vec3::new(0.0, 0.0, 0.0), commands, &mut meshes, membrane_materials, &mut materials, selections, highlights);
-        }
-    }
-}
@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::integrations::grace::SceneSegment;
+use crate::neuron::channel::{common_channels, Channel};
+use crate::neuron::membrane::{Membrane, MembraneChannel, MembraneVoltage};
+use crate::selection::Selection;
+
+/// The library channel "Add" puts into the membrane, by index into
+/// `common_channels::library`.
+#[derive(Default, Resource)]
+pub struct MembraneEditor {
+    pub library_index: usize,
+}
+
+/// The ions a channel passes and the gates it has, since channels do not
+/// keep the name they were built under: "Na+ m^3 h", say.
+pub fn channel_label(channel: &Channel) -> String {
+    let s = &channel.ion_selectivity;
+    let ions = [("Na+", s.na), ("K+", s.k), ("Ca2+", s.ca), ("Cl-", s.cl)]
+        .iter()
+        .filter(|(_, selectivity)| *selectivity > 0.0)
+        .map(|(ion, _)| *ion)
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut parts = vec![if ions.is_empty() { "No ions".to_string() } else { ions }];
+    let gate = |name: &str, gates: u8| if gates == 1 { name.to_string() } else { format!("{}^{}", name, gates) };
+    if let Some(activation) = &channel.activation {
+        parts.push(gate("m", activation.parameters.gates));
+    }
+    if let Some(inactivation) = &channel.inactivation {
+        parts.push(gate("h", inactivation.parameters.gates));
+    }
+    if channel.calcium.is_some() {
+        parts.push("Ca2+-gated".to_string());
+    }
+    if channel.opsin.is_some() {
+        parts.push("light-gated".to_string());
+    }
+    if channel.custom.is_some() {
+        parts.push("custom".to_string());
+    }
+    parts.join(" ")
+}
+
+/// Edit the channels and capacitance of the selected segment's membrane
+/// while the simulation runs.
+pub fn membrane_gui(
+    mut contexts: EguiContexts,
+    mut editor: ResMut<MembraneEditor>,
+    mut selected: Query<(&SceneSegment, &mut Membrane, &MembraneVoltage), With<Selection>>,
+) {
+    let selected_count = selected.iter().count();
+    egui::Window::new("Membrane")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let Some((SceneSegment(at), mut membrane, voltage)) = selected.iter_mut().next() else {
+                ui.label("Select a segment to edit its membrane.");
+                return;
+            };
+            ui.label(format!("Neuron {}, segment {}", at.neuron + 1, at.segment));
+            if selected_count > 1 {
+                ui.weak(format!("The first of {} selected segments.", selected_count));
+            }
+
+            let mut capacitance_uf = membrane.capacitance.0 * 1e6;
+            if ui.add(egui::DragValue::new(&mut capacitance_uf).clamp_range(0.01..=100.0).speed(0.01).prefix("Capacitance ").suffix(" µF/cm²")).changed() {
+                membrane.capacitance.0 = capacitance_uf * 1e-6;
+            }
+
+            // Read through `Deref` and write back only what was edited, so
+            // that an untouched membrane is not marked changed every frame.
+            let mut edited = None;
+            let mut removed = None;
+            egui::Grid::new("membrane_channels").striped(true).show(ui, |ui| {
+                for (i, membrane_channel) in membrane.membrane_channels.iter().enumerate() {
+                    ui.label(channel_label(&membrane_channel.channel));
+                    let mut ms = membrane_channel.siemens_per_square_cm * 1e3;
+                    if ui.add(egui::DragValue::new(&mut ms).clamp_range(0.0..=1e6).speed(0.01).suffix(" mS/cm²")).changed() {
+                        edited = Some((i, ms * 1e-3));
+                    }
+                    if ui.small_button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some((i, siemens_per_square_cm)) = edited {
+                membrane.membrane_channels[i].siemens_per_square_cm = siemens_per_square_cm;
+            }
+            if let Some(i) = removed {
+                membrane.membrane_channels.remove(i);
+            }
+
+            ui.separator();
+            let library = common_channels::library();
+            editor.library_index = editor.library_index.min(library.len() - 1);
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("membrane_library")
+                    .selected_text(library[editor.library_index].0)
+                    .show_ui(ui, |ui| {
+                        for (i, (name, _, _)) in library.iter().enumerate() {
+                            ui.selectable_value(&mut editor.library_index, i, *name);
+                        }
+                    });
+                if ui.button("Add").clicked() {
+                    // Gates start at rest for the segment's present voltage.
+                    let (_, builder, siemens_per_square_cm) = library[editor.library_index].clone();
+                    membrane.membrane_channels.push(MembraneChannel {
+                        channel: builder.build(&voltage.0),
+                        siemens_per_square_cm,
+                    });
+                }
+            });
+        });
+}
@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::command::Command;
+use crate::integrations::grace::SceneSegment;
+use crate::selection::Selection;
+use crate::serialize::SegmentRef;
+
+#[derive(Resource)]
+pub struct MorphologyEditor {
+    factor: f32,
+    /// The branch waiting for its new parent to be selected.
+    moving: Option<SegmentRef>,
+}
+
+impl Default for MorphologyEditor {
+    fn default() -> Self {
+        MorphologyEditor { factor: 0.5, moving: None }
+    }
+}
+
+/// Edit the branch starting at the selected segment.
+pub fn morphology_gui(
+    mut contexts: EguiContexts,
+    mut editor: ResMut<MorphologyEditor>,
+    selected: Query<&SceneSegment, With<Selection>>,
+    mut events: EventWriter<Command>,
+) {
+    egui::Window::new("Morphology")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("Edits apply to the selected segment and everything distal to it, and restart the run.");
+            let Some(SceneSegment(at)) = selected.iter().next().copied() else {
+                ui.label("Select a segment.");
+                return;
+            };
+            ui.label(format!("Neuron {}, segment {}", at.neuron + 1, at.segment));
+            if ui.button("Cut branch").clicked() {
+                events.send(Command::CutBranch { at });
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut editor.factor).clamp_range(0.1..=10.0).speed(0.01).prefix("x"));
+                if ui.button("Scale diameters").clicked() {
+                    events.send(Command::ScaleBranch { at, factor: editor.factor });
+                }
+            });
+            match editor.moving {
+                Some(moving) => {
+                    ui.label(format!("Moving the branch at segment {}; select its new parent.", moving.segment));
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(moving != at, egui::Button::new("Attach here")).clicked() {
+                            events.send(Command::ReattachBranch { at: moving, to: at });
+                            editor.moving = None;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            editor.moving = None;
+                        }
+                    });
+                },
+                None => {
+                    if ui.button("Move branch").clicked() {
+                        editor.moving = Some(at);
+                    }
+                },
+            }
+        });
+}
@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::command::Command;
+use crate::integrations::grace::CurrentScene;
+use crate::noise::{MembraneNoise, NoisePreset};
+
+/// The population and preset chosen in the membrane noise window. `None`
+/// stands for every neuron, and for no noise.
+#[derive(Default, Resource)]
+pub struct NoiseSettings {
+    pub tag: Option<String>,
+    pub preset: Option<NoisePreset>,
+}
+
+/// Set the noise of a whole population with one preset.
+pub fn noise_gui(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<NoiseSettings>,
+    current_scene: Res<CurrentScene>,
+    noisy: Query<(), With<MembraneNoise>>,
+    mut events: EventWriter<Command>,
+) {
+    egui::Window::new("Membrane noise")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let mut tags = current_scene.0.neurons.iter().filter_map(|n| n.tag.clone()).collect::<Vec<_>>();
+            tags.sort();
+            tags.dedup();
+            let settings = &mut *settings;
+            egui::ComboBox::from_label("Population")
+                .selected_text(settings.tag.as_deref().unwrap_or("All neurons"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.tag, None, "All neurons");
+                    for tag in tags {
+                        ui.selectable_value(&mut settings.tag, Some(tag.clone()), tag);
+                    }
+                });
+            egui::ComboBox::from_label("Preset")
+                .selected_text(settings.preset.map_or("Off", |p| p.name()))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.preset, None, "Off");
+                    for preset in NoisePreset::ALL {
+                        ui.selectable_value(&mut settings.preset, Some(preset), preset.name());
+                    }
+                });
+            if let Some(noise) = settings.preset.map(|p| p.noise()) {
+                ui.weak(format!(
+                    "Background {:.2} ± {:.2} µA/cm², channel ± {:.2} µA/cm²",
+                    noise.mean_uamps_per_square_cm,
+                    noise.background_sigma_uamps_per_square_cm,
+                    noise.channel_sigma_uamps_per_square_cm,
+                ));
+            }
+            if ui.button("Apply").clicked() {
+                events.send(Command::SetNoise { tag: settings.tag.clone(), noise: settings.preset.map(|p| p.noise()) });
+            }
+            ui.label(format!("{} noisy segments", noisy.iter().count()));
+        });
+}
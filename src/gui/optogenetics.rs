@@ -0,0 +1,237 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::command::Command;
+use crate::dimension::{Interval, Timestamp};
+use crate::integrations::grace::SceneSegment;
+use crate::neuron::opsin::Opsin;
+use crate::optogenetics::{LightSource, PulseTrain};
+use crate::projector::{Pattern, Projector};
+use crate::selection::Selection;
+use crate::units::UnitScale;
+
+#[derive(Resource)]
+pub struct OptogeneticsEditor {
+    opsin: Opsin,
+    siemens_per_square_cm: f32,
+    /// A PGM image or movie to project.
+    pattern_path: String,
+    pattern_error: Option<String>,
+}
+
+impl Default for OptogeneticsEditor {
+    fn default() -> Self {
+        OptogeneticsEditor {
+            opsin: Opsin::ChR2,
+            siemens_per_square_cm: 0.002,
+            pattern_path: String::new(),
+            pattern_error: None,
+        }
+    }
+}
+
+fn milliseconds_setting(ui: &mut egui::Ui, label: &str, interval: &mut Interval) -> bool {
+    let mut ms = interval.0 * 1000.0;
+    let changed = ui.add(egui::DragValue::new(&mut ms).clamp_range(0.0..=10000.0).prefix(label).suffix(" ms")).changed();
+    if changed {
+        interval.0 = ms / 1000.0;
+    }
+    changed
+}
+
+fn point_setting(ui: &mut egui::Ui, point: &mut Vec3, suffix: &str) -> bool {
+    ui.horizontal(|ui| {
+        let x = ui.add(egui::DragValue::new(&mut point.x).prefix("x ").suffix(suffix)).changed();
+        let y = ui.add(egui::DragValue::new(&mut point.y).prefix("y ").suffix(suffix)).changed();
+        let z = ui.add(egui::DragValue::new(&mut point.z).prefix("z ").suffix(suffix)).changed();
+        x || y || z
+    })
+    .inner
+}
+
+fn train_settings(ui: &mut egui::Ui, train: &mut PulseTrain) -> bool {
+    let envelope = &mut train.envelope;
+    let mut changed = ui.horizontal(|ui| {
+        let onset = milliseconds_setting(ui, "onset ", &mut envelope.onset);
+        let offset = milliseconds_setting(ui, "offset ", &mut envelope.offset);
+        let period = milliseconds_setting(ui, "period ", &mut envelope.period);
+        onset || offset || period
+    })
+    .inner;
+    ui.horizontal(|ui| {
+        let mut limited = train.pulses.is_some();
+        changed |= ui.checkbox(&mut limited, "Limit pulses").changed();
+        match (limited, train.pulses) {
+            (true, None) => train.pulses = Some(10),
+            (false, Some(_)) => train.pulses = None,
+            _ => {},
+        }
+        if let Some(pulses) = train.pulses.as_mut() {
+            changed |= ui.add(egui::DragValue::new(pulses).clamp_range(1..=10000)).changed();
+        }
+    });
+    changed
+}
+
+fn light_settings(ui: &mut egui::Ui, light: &mut LightSource) {
+    ui.add(egui::Slider::new(&mut light.irradiance, 0.0..=50.0).logarithmic(true).text("mW/mm²"));
+    ui.add(egui::Slider::new(&mut light.spread_um, 1.0..=1000.0).logarithmic(true).text("spread (µm)"));
+    ui.add(egui::Slider::new(&mut light.wavelength_nm, 400.0..=650.0).text("wavelength (nm)"));
+    point_setting(ui, &mut light.position_um, " µm");
+    train_settings(ui, &mut light.train);
+}
+
+/// Edit a projector's settings, returning whether any changed.
+fn projector_settings(ui: &mut egui::Ui, projector: &mut Projector) -> bool {
+    let mut changed = false;
+    changed |= ui.add(egui::Slider::new(&mut projector.irradiance, 0.0..=50.0).logarithmic(true).text("mW/mm² at white")).changed();
+    changed |= ui.add(egui::Slider::new(&mut projector.wavelength_nm, 400.0..=650.0).text("wavelength (nm)")).changed();
+    ui.horizontal(|ui| {
+        changed |= ui.add(egui::DragValue::new(&mut projector.width_um).clamp_range(1.0..=10000.0).prefix("width ").suffix(" µm")).changed();
+        changed |= ui.add(egui::DragValue::new(&mut projector.height_um).clamp_range(1.0..=10000.0).prefix("height ").suffix(" µm")).changed();
+    });
+    ui.label("Center");
+    changed |= point_setting(ui, &mut projector.center_um, " µm");
+    ui.label("Light travels toward");
+    changed |= point_setting(ui, &mut projector.direction, "");
+    if projector.pattern.frames.len() > 1 {
+        ui.horizontal(|ui| {
+            changed |= milliseconds_setting(ui, "frames every ", &mut projector.frame_interval);
+            changed |= ui.checkbox(&mut projector.looping, "Loop").changed();
+        });
+    }
+    changed |= train_settings(ui, &mut projector.train);
+    changed
+}
+
+/// Place and pulse lights and projectors, and express opsins in the
+/// selected segment's membrane.
+pub fn optogenetics_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut editor: ResMut<OptogeneticsEditor>,
+    scale: Res<UnitScale>,
+    timestamp: Res<Timestamp>,
+    selected: Query<(&SceneSegment, &Transform), With<Selection>>,
+    mut lights: Query<(Entity, &mut LightSource)>,
+    mut projectors: Query<(Entity, &mut Projector)>,
+    mut events: EventWriter<Command>,
+) {
+    egui::Window::new("Optogenetics")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let selection = selected.iter().next();
+
+            ui.heading("Opsins");
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("opsin")
+                    .selected_text(editor.opsin.name())
+                    .show_ui(ui, |ui| {
+                        for opsin in Opsin::ALL {
+                            ui.selectable_value(&mut editor.opsin, opsin, opsin.name());
+                        }
+                    });
+                ui.add(
+                    egui::DragValue::new(&mut editor.siemens_per_square_cm)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.0001)
+                        .suffix(" S/cm²"),
+                );
+            });
+            match selection {
+                None => {
+                    ui.label("Select a segment to express the opsin in its membrane.");
+                },
+                Some((SceneSegment(at), _)) => {
+                    if ui.button("Express in the selected segment's membrane").clicked() {
+                        events.send(Command::ExpressOpsin {
+                            at: *at,
+                            opsin: editor.opsin.serialize(),
+                            siemens_per_square_cm: editor.siemens_per_square_cm,
+                        });
+                    }
+                    ui.weak("Expressing restarts the run. A density of zero removes the opsin.");
+                },
+            }
+
+            ui.separator();
+            ui.heading("Lights");
+            for (i, (entity, mut light)) in lights.iter_mut().enumerate() {
+                let on = light.train.is_on(&timestamp);
+                egui::CollapsingHeader::new(format!("Light {}{}", i + 1, if on { " (on)" } else { "" }))
+                    .id_source(entity)
+                    .show(ui, |ui| {
+                        // Only mark the light changed when it is edited.
+                        let mut edited = light.clone();
+                        light_settings(ui, &mut edited);
+                        if let Some((_, transform)) = selection {
+                            if ui.button("Move to the selected segment").clicked() {
+                                edited.position_um = transform.translation / scale.screen_per_micron();
+                            }
+                        }
+                        if ui.button("Remove").clicked() {
+                            commands.entity(entity).despawn();
+                        }
+                        if edited.serialize() != light.serialize() {
+                            *light = edited;
+                        }
+                    });
+            }
+            if ui.button("Add light").clicked() {
+                let position_um = selection.map_or(Vec3::ZERO, |(_, t)| t.translation / scale.screen_per_micron());
+                commands.spawn(LightSource { position_um, ..default() });
+            }
+
+            ui.separator();
+            ui.heading("Projectors");
+            for (i, (entity, mut projector)) in projectors.iter_mut().enumerate() {
+                let on = projector.train.is_on(&timestamp);
+                egui::CollapsingHeader::new(format!("Projector {}{}", i + 1, if on { " (on)" } else { "" }))
+                    .id_source(entity)
+                    .show(ui, |ui| {
+                        let pattern = &projector.pattern;
+                        let showing = match projector.frame_at(&timestamp) {
+                            Some(frame) => format!("showing frame {}", frame + 1),
+                            None => "ended".to_string(),
+                        };
+                        ui.label(format!("{}x{} pixels, {} frames, {}", pattern.width, pattern.height, pattern.frames.len(), showing));
+                        // The pattern is too big to compare each frame, so
+                        // mark the projector changed only when a setting is.
+                        let mut changed = projector_settings(ui, projector.bypass_change_detection());
+                        if let Some((_, transform)) = selection {
+                            if ui.button("Move to the selected segment").clicked() {
+                                projector.bypass_change_detection().center_um = transform.translation / scale.screen_per_micron();
+                                changed = true;
+                            }
+                        }
+                        if ui.button("Remove").clicked() {
+                            commands.entity(entity).despawn();
+                        }
+                        if changed {
+                            projector.set_changed();
+                        }
+                    });
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut editor.pattern_path).hint_text("pattern.pgm"));
+                if ui.button("Project").clicked() {
+                    let path = editor.pattern_path.clone();
+                    let pattern = std::fs::read(&path)
+                        .map_err(|e| format!("Failed to read {}: {}", path, e))
+                        .and_then(|bytes| Pattern::parse_pgm(&bytes));
+                    match pattern {
+                        Ok(pattern) => {
+                            editor.pattern_error = None;
+                            let center_um = selection.map_or(Vec3::ZERO, |(_, t)| t.translation / scale.screen_per_micron());
+                            commands.spawn(Projector { center_um, ..Projector::new(pattern) });
+                        },
+                        Err(e) => editor.pattern_error = Some(e),
+                    }
+                }
+            });
+            ui.weak("A PGM image, or a movie of several PGM images in one file.");
+            if let Some(e) = &editor.pattern_error {
+                ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+            }
+        });
+}
@@ -1,25 +1,238 @@
+use std::collections::VecDeque;
+
 use bevy::prelude::*;
-use bevy_egui::egui::Ui;
+use bevy_egui::egui::{self, Ui};
 use bevy_egui::egui::Color32;
+use crossbeam::channel::Receiver;
 use egui_plot::{Plot, Line};
 
+use crate::analysis::Trace;
+use crate::gui::cursors::Cursors;
+use crate::gui::download::save_file;
+use crate::gui::png::Canvas;
 use crate::gui::{NextClickAction, SimulationStepSeconds};
 use crate::dimension::StepsPerFrame;
 
-use crate::neuron::membrane::MembraneVoltage;
+use crate::neuron::synapse::Transmitter;
+use crate::probe::{ProbeFrame, ProbeKey, ProbeRegistry, ProbeSource, Subscription};
+
+const DEFAULT_CHANNELS: usize = 4;
+const MAX_CHANNELS: usize = 16;
+const DEFAULT_SAMPLE_DEPTH: usize = 2000;
+const MAX_SAMPLE_DEPTH: usize = 100_000;
+/// The plot spans this many vertical divisions, centered on zero.
+const N_DIVISIONS: f32 = 10.0;
+/// Channel colors, in order, repeating past the last.
+const COLORS: [Color32; 8] = [
+    Color32::YELLOW, Color32::LIGHT_GREEN, Color32::LIGHT_RED, Color32::LIGHT_BLUE,
+    Color32::GOLD, Color32::KHAKI, Color32::from_rgb(255, 128, 255), Color32::WHITE,
+];
+
+/// How a channel's vertical scale is chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RangeMode {
+    /// Fit the whole trace, spikes included.
+    Coarse,
+    /// Zoom in on the baseline, letting spikes run off the screen.
+    Fine,
+    /// Use the channel's `mv_per_division` and `offset_mv` as set.
+    Manual,
+}
+
+/// The vertical scale of one oscilloscope channel. Samples are drawn at
+/// `(v - offset_mv) / mv_per_division` divisions from the center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelRange {
+    pub mode: RangeMode,
+    pub mv_per_division: f32,
+    pub offset_mv: f32,
+}
+
+impl Default for ChannelRange {
+    fn default() -> Self {
+        ChannelRange { mode: RangeMode::Coarse, mv_per_division: 20.0, offset_mv: -20.0 }
+    }
+}
+
+impl ChannelRange {
+    /// Recompute the scale from recent samples, unless in manual mode.
+    pub fn auto_range(&mut self, samples: &[f32]) {
+        if samples.is_empty() {
+            return;
+        }
+        let (low, high, min_span) = match self.mode {
+            RangeMode::Manual => return,
+            RangeMode::Coarse => {
+                let low = samples.iter().cloned().fold(f32::INFINITY, f32::min);
+                let high = samples.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                (low, high, 10.0)
+            },
+            RangeMode::Fine => {
+                // Spikes occupy a small fraction of the buffer, so the middle
+                // 80% of samples describes the subthreshold activity.
+                let mut sorted = samples.to_vec();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p) as usize];
+                (percentile(0.1), percentile(0.9), 0.5)
+            },
+        };
+        let span = (high - low).max(min_span);
+        self.mv_per_division = nice_division(span / (N_DIVISIONS - 2.0));
+        self.offset_mv = ((low + high) / 2.0 / self.mv_per_division).round() * self.mv_per_division;
+    }
+
+    fn to_divisions(&self, v: f32) -> f32 {
+        (v - self.offset_mv) / self.mv_per_division
+    }
+}
+
+/// The smallest 1-2-5 step at least as large as `x`, so that the scale only
+/// changes when the signal grows or shrinks appreciably.
+fn nice_division(x: f32) -> f32 {
+    let decade = 10f32.powf(x.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter().map(|m| m * decade).find(|step| *step >= x).unwrap_or(10.0 * decade)
+}
+
+/// What the oscilloscope plots.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Display {
+    /// Every channel against time.
+    Traces,
+    /// One channel's power spectrum.
+    Spectrum,
+    /// One channel against its rate of change, an XY display.
+    PhasePlane,
+}
+
+/// Bound on the samples kept for rewinding, whatever the time it spans.
+const MAX_REWIND_SAMPLES: usize = 1_000_000;
+
+/// The values of the probed segments over the last `seconds` of
+/// simulated time, one sample per frame, so that the oscilloscope can
+/// scrub back through more than its live sweep.
+#[derive(Debug)]
+pub struct RewindBuffer {
+    pub seconds: f32,
+    samples: VecDeque<(f32, Vec<Option<f32>>)>,
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        RewindBuffer { seconds: 1.0, samples: VecDeque::new() }
+    }
+}
+
+impl RewindBuffer {
+    /// Record the values at `t`, one per channel. Nothing is recorded
+    /// while time stands still, and the history is dropped when time goes
+    /// back, as on a reset.
+    pub fn push(&mut self, t: f32, values: Vec<Option<f32>>) {
+        match self.samples.back() {
+            Some((last, _)) if t == *last => return,
+            Some((last, _)) if t < *last => self.samples.clear(),
+            _ => {},
+        }
+        self.samples.push_back((t, values));
+        while self.samples.len() > MAX_REWIND_SAMPLES || self.samples.front().is_some_and(|(first, _)| *first < t - self.seconds) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// The oldest and newest times held.
+    pub fn span(&self) -> Option<(f32, f32)> {
+        Some((self.samples.front()?.0, self.samples.back()?.0))
+    }
+
+    /// One channel's samples from `start` to `end`, with times measured
+    /// from `start`.
+    pub fn window(&self, source_index: usize, start: f32, end: f32) -> Vec<(f32, f32)> {
+        let first = self.samples.partition_point(|(t, _)| *t < start);
+        self.samples
+            .range(first..)
+            .take_while(|(t, _)| *t <= end)
+            .filter_map(|(t, values)| Some((t - start, (*values.get(source_index)?)?)))
+            .collect()
+    }
 
-const N_SOURCES: usize = 4;
-const N_SAMPLES: usize = 2000;
+    /// Forget one channel's history, when it is given a new source.
+    pub fn forget(&mut self, source_index: usize) {
+        for (_, values) in self.samples.iter_mut() {
+            if let Some(value) = values.get_mut(source_index) {
+                *value = None;
+            }
+        }
+    }
+}
+
+/// One oscilloscope channel: its source, what it shows of it, and how.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub source: Option<Entity>,
+    /// What the channel shows of its source.
+    pub quantity: ProbeSource,
+    pub range: ChannelRange,
+    /// Disabled channels keep their source but are neither sampled nor
+    /// drawn.
+    pub enabled: bool,
+    pub label: String,
+    pub color: Color32,
+    /// Samples of the source's quantity, a ring buffer written at
+    /// `Oscilloscope::write_offset`.
+    pub buffer: Vec<f32>,
+    /// Stimulator current density (uA/cm^2) injected at the source.
+    pub currents: Vec<f32>,
+}
+
+impl Channel {
+    fn new(index: usize, sample_depth: usize) -> Self {
+        Channel {
+            source: None,
+            quantity: ProbeSource::Voltage,
+            range: ChannelRange::default(),
+            enabled: true,
+            label: (index + 1).to_string(),
+            color: COLORS[index % COLORS.len()],
+            buffer: vec![0.0; sample_depth],
+            currents: vec![0.0; sample_depth],
+        }
+    }
+
+    /// The channel's source, if it is shown.
+    pub fn shown_source(&self) -> Option<Entity> {
+        self.source.filter(|_| self.enabled)
+    }
+
+    fn rgb(&self) -> [u8; 3] {
+        [self.color.r(), self.color.g(), self.color.b()]
+    }
+}
 
 #[derive(Debug, Resource)]
 pub struct Oscilloscope {
-    pub buffers: [ [f32; N_SAMPLES]; N_SOURCES],
-    pub sources: [Option<Entity>; N_SOURCES],
-    pub times: [ f32; N_SAMPLES ],
+    pub channels: Vec<Channel>,
+    /// Samples held per channel.
+    pub sample_depth: usize,
+    /// Take a sample every this many frames.
+    pub decimation: usize,
+    /// The time of each sample from the start of the buffer.
+    pub times: Vec<f32>,
     pub write_offset: usize,
+    frames_since_sample: usize,
     pub trigger_setting: Option<TriggerSetting>,
     pub trigger_sample: Option<usize>,
-    pub last_known_simulation_step_seconds: SimulationStepSeconds,
+    /// The time between samples the buffers were laid out for.
+    sample_seconds: f32,
+    pub cursors: Cursors,
+    pub rewind: RewindBuffer,
+    /// While scrubbing, the time at the right edge of the plot.
+    pub scrub: Option<f32>,
+    pub display: Display,
+    /// The channel shown by the spectrum and phase plane.
+    pub display_channel: usize,
+    pub max_frequency_hz: f32,
+    pub export_error: Option<String>,
+    /// The channels' samples, from the probe registry.
+    samples: Option<Receiver<ProbeFrame>>,
 }
 
 #[derive(Debug)]
@@ -31,21 +244,39 @@ pub struct TriggerSetting {
 impl Oscilloscope {
     pub fn init() -> Self {
         Oscilloscope {
-            buffers: [ [ 0.0; N_SAMPLES ]; N_SOURCES ],
-            sources: [ None; N_SOURCES ],
-            times: [ 0.0; N_SAMPLES ],
+            channels: (0..DEFAULT_CHANNELS).map(|i| Channel::new(i, DEFAULT_SAMPLE_DEPTH)).collect(),
+            sample_depth: DEFAULT_SAMPLE_DEPTH,
+            decimation: 1,
+            times: vec![0.0; DEFAULT_SAMPLE_DEPTH],
             write_offset: 0,
+            frames_since_sample: 0,
             trigger_setting: None,
             trigger_sample: None,
-            last_known_simulation_step_seconds: SimulationStepSeconds(0.0),
+            sample_seconds: 0.0,
+            cursors: Cursors::default(),
+            rewind: RewindBuffer::default(),
+            scrub: None,
+            display: Display::Traces,
+            display_channel: 0,
+            max_frequency_hz: 200.0,
+            export_error: None,
+            samples: None,
         }
     }
 
+    /// The source of channel `index`, if it has one.
+    pub fn source(&self, index: usize) -> Option<Entity> {
+        self.channels.get(index)?.source
+    }
+
     pub fn accept_source(
         &mut self,
         ind: usize,
         new_source: Entity) {
-            self.sources[ind] = Some(new_source);
+            if let Some(channel) = self.channels.get_mut(ind) {
+                channel.source = Some(new_source);
+                self.rewind.forget(ind);
+            }
     }
 
     pub fn accept_source_if_available_slot(
@@ -53,28 +284,323 @@ impl Oscilloscope {
         _next_click: ResMut<NextClickAction>,
         new_source: Entity
     ) {
-        for source in self.sources.iter_mut() {
-            if source.is_none() {
-                *source = Some(new_source);
+        for (i, channel) in self.channels.iter_mut().enumerate() {
+            if channel.source.is_none() {
+                channel.source = Some(new_source);
+                self.rewind.forget(i);
                 break;
             }
         }
     }
 
-    pub fn plot(&self, ui: &mut Ui) {
+    /// Add or remove channels from the end. Removed channels lose their
+    /// sources.
+    pub fn set_channel_count(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_CHANNELS);
+        while self.channels.len() > count {
+            self.channels.pop();
+            self.rewind.forget(self.channels.len());
+        }
+        while self.channels.len() < count {
+            self.channels.push(Channel::new(self.channels.len(), self.times.len()));
+        }
+        self.display_channel = self.display_channel.min(count - 1);
+    }
+
+    /// Start the buffers over, `sample_depth` samples long, sampled every
+    /// `sample_seconds`.
+    fn clear_buffers(&mut self, sample_seconds: f32) {
+        self.sample_depth = self.sample_depth.clamp(2, MAX_SAMPLE_DEPTH);
+        self.sample_seconds = sample_seconds;
+        self.write_offset = 0;
+        self.frames_since_sample = 0;
+        self.times = (0..self.sample_depth).map(|i| i as f32 * sample_seconds).collect();
+        for channel in &mut self.channels {
+            channel.buffer = vec![0.0; self.sample_depth];
+            channel.currents = vec![0.0; self.sample_depth];
+        }
+    }
+
+    /// The time the buffers span.
+    fn sweep(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    /// The samples of one channel, oldest first, with times measured from
+    /// the start of the buffer.
+    pub fn trace(&self, source_index: usize) -> Option<Trace> {
+        self.chronological(source_index, |channel| &channel.buffer)
+    }
+
+    /// The stimulator current injected at one channel's source, oldest
+    /// first, on the same time base as `trace`.
+    pub fn current_trace(&self, source_index: usize) -> Option<Trace> {
+        self.chronological(source_index, |channel| &channel.currents)
+    }
+
+    fn chronological(&self, source_index: usize, buffer: impl Fn(&Channel) -> &Vec<f32>) -> Option<Trace> {
+        let channel = self.channels.get(source_index)?;
+        channel.shown_source()?;
+        let buffer = buffer(channel);
+        let mut trace = Trace::new(channel.label.clone());
+        let chronological = (self.write_offset..buffer.len()).chain(0..self.write_offset);
+        for (k, i) in chronological.enumerate() {
+            trace.push(k as f32 * self.sample_seconds, buffer[i]);
+        }
+        Some(trace)
+    }
+
+    /// The buffers of every channel in use, oldest first, as CSV with a
+    /// `t_ms` column and each channel's quantity and stimulator current.
+    pub fn to_csv(&self) -> Result<String, String> {
+        let channels = (0..self.channels.len())
+            .filter_map(|i| Some((&self.channels[i], self.trace(i)?, self.current_trace(i)?)))
+            .collect::<Vec<_>>();
+        let (_, first, _) = channels.first().ok_or("No channels to export".to_string())?;
+        let mut writer = csv::Writer::from_writer(vec![]);
+        let mut header = vec!["t_ms".to_string()];
+        for (channel, _, _) in &channels {
+            header.push(format!("{} {} ({})", channel.label, channel.quantity.name(), channel.quantity.unit()));
+            header.push(format!("{} stimulus (uA/cm^2)", channel.label));
+        }
+        writer.write_record(&header).map_err(|e| e.to_string())?;
+        for (k, t) in first.times.iter().enumerate() {
+            let mut row = vec![format!("{:.4}", t * 1000.0)];
+            for (_, values, current) in &channels {
+                row.push(values.values[k].to_string());
+                row.push(current.values[k].to_string());
+            }
+            writer.write_record(&row).map_err(|e| e.to_string())?;
+        }
+        let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+
+    /// The traces as drawn, with a grid line at every division, as a PNG.
+    pub fn to_png(&self, width: usize, height: usize) -> Vec<u8> {
+        let mut canvas = Canvas::new(width, height, [16, 16, 16]);
+        let sweep = self.sweep().max(f32::MIN_POSITIVE);
+        let x = |t: f32| t / sweep * (width - 1) as f32;
+        let y = |divisions: f32| (0.5 - divisions / N_DIVISIONS) * (height - 1) as f32;
+        for d in 0..=N_DIVISIONS as usize {
+            let fraction = d as f32 / N_DIVISIONS;
+            let grid = if 2 * d == N_DIVISIONS as usize { [96, 96, 96] } else { [48, 48, 48] };
+            canvas.line((0.0, fraction * (height - 1) as f32), ((width - 1) as f32, fraction * (height - 1) as f32), grid);
+            canvas.line((fraction * (width - 1) as f32, 0.0), (fraction * (width - 1) as f32, (height - 1) as f32), grid);
+        }
+        for (channel, lines) in self.channels.iter().zip(self.shown_lines()) {
+            if channel.shown_source().is_none() {
+                continue;
+            }
+            for line in lines {
+                let points = line.iter().map(|(t, v)| (x(*t), y(channel.range.to_divisions(*v)))).collect::<Vec<_>>();
+                for pair in points.windows(2) {
+                    canvas.line(pair[0], pair[1], channel.rgb());
+                }
+            }
+        }
+        canvas.encode()
+    }
+
+    /// The samples drawn for each channel, at times from the left edge of
+    /// the plot: the live sweep in two parts, either side of the write
+    /// position, or the rewound history ending at the scrubbed time.
+    fn shown_lines(&self) -> Vec<Vec<Vec<(f32, f32)>>> {
+        let sweep = self.sweep();
+        self.channels
+            .iter()
+            .enumerate()
+            .map(|(i, channel)| match self.scrub {
+                Some(end) => vec![self.rewind.window(i, end - sweep, end)],
+                None => {
+                    let point = |(x, y): (usize, &f32)| (self.times[x], *y);
+                    vec![
+                        channel.buffer.iter().enumerate().take(self.write_offset.saturating_sub(1)).map(point).collect(),
+                        channel.buffer.iter().enumerate().skip(self.write_offset).map(point).collect(),
+                    ]
+                },
+            })
+            .collect()
+    }
+
+    pub fn plot(&mut self, ui: &mut Ui) {
+        self.display_controls(ui);
+        match self.display {
+            Display::Traces => {},
+            Display::Spectrum => return self.plot_spectrum(ui, self.display_channel),
+            Display::PhasePlane => return self.plot_phase_plane(ui, self.display_channel),
+        }
+        let lines = self.shown_lines();
+        for (channel, lines) in self.channels.iter_mut().zip(&lines) {
+            if channel.shown_source().is_some() {
+                let samples = lines.iter().flatten().map(|(_, v)| *v).collect::<Vec<_>>();
+                channel.range.auto_range(&samples);
+            }
+        }
         Plot::new("oscilloscope")
             .view_aspect(2.0)
+            .include_y(-N_DIVISIONS / 2.0)
+            .include_y(N_DIVISIONS / 2.0)
+            .y_axis_label("divisions")
+            .allow_drag(!self.cursors.shown)
             .show(ui, |plot_ui| {
-                for i in 0..4 {
-                    let _name = (i+1).to_string(); // TODO: Use name?
-                    let color = [Color32::YELLOW, Color32::LIGHT_GREEN, Color32::LIGHT_RED, Color32::LIGHT_BLUE][i];
-                    let line_before_break = self.buffers[i].iter().enumerate().take(self.write_offset - 1).map(|(x,y)| [self.times[x] as f64, *y as f64]).collect::<Vec<_>>();
-                    let line_after_break = self.buffers[i].iter().enumerate().skip(self.write_offset).map(|(x,y)| [self.times[x] as f64, *y as f64]).collect::<Vec<_>>();
-                    plot_ui.line( Line::new(line_before_break).name(i.to_string()).color(color) );
-                    plot_ui.line( Line::new(line_after_break).name(i.to_string()).color(color) );
+                for (channel, lines) in self.channels.iter().zip(&lines) {
+                    if !channel.enabled {
+                        continue;
+                    }
+                    let range = channel.range;
+                    let name = format!("{} {}: {} {}/div", channel.label, channel.quantity.name(), range.mv_per_division, channel.quantity.unit());
+                    for line in lines {
+                        let points = line.iter().map(|(t, v)| [*t as f64, range.to_divisions(*v) as f64]).collect::<Vec<_>>();
+                        plot_ui.line( Line::new(points).name(&name).color(channel.color) );
+                    }
+                }
+                self.cursors.show(plot_ui);
+            });
+        self.rewind_controls(ui);
+        // Cursors are read in the units of the first channel in use.
+        let reference = self.channels.iter().find(|c| c.shown_source().is_some()).unwrap_or(&self.channels[0]);
+        let range = reference.range;
+        self.cursors.readout(
+            ui,
+            "ms", |t| t * 1000.0,
+            &format!("{} {}", reference.quantity.name(), reference.label), reference.quantity.unit(), |d| d * range.mv_per_division as f64 + range.offset_mv as f64,
+        );
+        for (i, channel) in self.channels.iter_mut().enumerate() {
+            let mut quantity_changed = false;
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut channel.enabled, "");
+                egui::color_picker::color_edit_button_srgba(ui, &mut channel.color, egui::color_picker::Alpha::Opaque);
+                ui.add(egui::TextEdit::singleline(&mut channel.label).desired_width(40.0));
+                quantity_changed = quantity_selector(ui, ("oscilloscope_quantity", i), &mut channel.quantity);
+                let range = &mut channel.range;
+                for (mode, label) in [(RangeMode::Coarse, "Coarse"), (RangeMode::Fine, "Fine"), (RangeMode::Manual, "Manual")] {
+                    if ui.selectable_label(range.mode == mode, label).clicked() {
+                        range.mode = mode;
+                    }
                 }
+                let manual = range.mode == RangeMode::Manual;
+                let unit = channel.quantity.unit();
+                ui.add_enabled(manual, egui::DragValue::new(&mut range.mv_per_division).speed(0.1).clamp_range(0.0001..=100.0).suffix(format!(" {}/div", unit)));
+                ui.add_enabled(manual, egui::DragValue::new(&mut range.offset_mv).speed(0.5).prefix("offset ").suffix(format!(" {}", unit)));
+            });
+            if quantity_changed {
+                self.rewind.forget(i);
+            }
+        }
+    }
+
+    /// Choose between the traces and one channel's power spectrum or
+    /// phase plane, and set the channel count and sampling.
+    fn display_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            for (display, label) in [(Display::Traces, "Traces"), (Display::Spectrum, "Spectrum"), (Display::PhasePlane, "Phase plane")] {
+                ui.selectable_value(&mut self.display, display, label);
+            }
+            if self.display != Display::Traces {
+                for (i, channel) in self.channels.iter().enumerate() {
+                    let label = egui::RichText::new(channel.label.as_str()).color(channel.color);
+                    if ui.add_enabled(channel.shown_source().is_some(), egui::SelectableLabel::new(self.display_channel == i, label)).clicked() {
+                        self.display_channel = i;
+                    }
+                }
+            }
+            if self.display == Display::Spectrum {
+                ui.add(egui::DragValue::new(&mut self.max_frequency_hz).speed(1.0).clamp_range(1.0..=10000.0).prefix("up to ").suffix(" Hz"));
+            }
+            ui.separator();
+            if ui.button("Export CSV").clicked() {
+                self.export_error = self
+                    .to_csv()
+                    .and_then(|csv| save_file("oscilloscope.csv", "text/csv", csv.as_bytes()))
+                    .err();
+            }
+            if ui.button("Export PNG").on_hover_text("The traces, as shown").clicked() {
+                self.export_error = save_file("oscilloscope.png", "image/png", &self.to_png(1000, 500)).err();
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut count = self.channels.len();
+            if ui.add(egui::DragValue::new(&mut count).clamp_range(1..=MAX_CHANNELS).suffix(" channels")).changed() {
+                self.set_channel_count(count);
+            }
+            // A new depth or decimation starts the buffers over.
+            let depth = ui.add(egui::DragValue::new(&mut self.sample_depth).speed(10.0).clamp_range(2..=MAX_SAMPLE_DEPTH).suffix(" samples"));
+            let decimation = ui.add(egui::DragValue::new(&mut self.decimation).clamp_range(1..=1000).prefix("every ").suffix(" frames"));
+            if depth.changed() || decimation.changed() {
+                self.sample_seconds = 0.0;
+            }
+        });
+        if let Some(e) = &self.export_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+        }
+    }
+
+    /// The power spectral density of one channel's buffer, on a log scale,
+    /// for finding subthreshold oscillations and resonances.
+    fn plot_spectrum(&self, ui: &mut Ui, source_index: usize) {
+        let Some(trace) = self.trace(source_index) else {
+            ui.weak("No source on this channel");
+            return;
+        };
+        let channel = &self.channels[source_index];
+        let spectrum = trace.power_spectrum();
+        let resolution = spectrum.get(1).map_or(0.0, |(f, _)| *f);
+        let points = spectrum
+            .iter()
+            .skip(1)
+            .take_while(|(f, _)| *f <= self.max_frequency_hz)
+            .map(|(f, power)| [*f as f64, power.max(f32::MIN_POSITIVE).log10() as f64])
+            .collect::<Vec<_>>();
+        Plot::new("oscilloscope_spectrum")
+            .view_aspect(2.0)
+            .x_axis_label("Hz")
+            .y_axis_label(format!("log10 ({})^2/Hz", channel.quantity.unit()))
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(points).name(&channel.label).color(channel.color));
+            });
+        ui.weak(format!("Resolution {:.1} Hz over the {:.0} ms buffer", resolution, trace.times.last().map_or(0.0, |t| t * 1000.0)));
+    }
+
+    /// One channel against its rate of change, updating live. For
+    /// voltage this shows the kink at spike threshold.
+    fn plot_phase_plane(&self, ui: &mut Ui, source_index: usize) {
+        let Some(trace) = self.trace(source_index) else {
+            ui.weak("No source on this channel");
+            return;
+        };
+        let channel = &self.channels[source_index];
+        let points = trace.phase_plane().iter().map(|(v, dv)| [*v as f64, *dv as f64]).collect::<Vec<_>>();
+        let unit = channel.quantity.unit();
+        Plot::new("oscilloscope_phase_plane")
+            .view_aspect(2.0)
+            .x_axis_label(unit)
+            .y_axis_label(format!("{}/ms", unit))
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(points).name(&channel.label).color(channel.color));
             });
     }
+
+    /// Scrub back through the rewind buffer, or return to the live sweep.
+    fn rewind_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let Some((oldest, newest)) = self.rewind.span() else {
+                self.scrub = None;
+                ui.weak("Nothing to rewind yet");
+                return;
+            };
+            let mut scrubbing = self.scrub.is_some();
+            if ui.checkbox(&mut scrubbing, "Rewind").changed() {
+                self.scrub = scrubbing.then_some(newest);
+            }
+            if let Some(end) = self.scrub.as_mut() {
+                let mut end_ms = end.clamp(oldest, newest) * 1000.0;
+                ui.add(egui::Slider::new(&mut end_ms, oldest * 1000.0..=newest * 1000.0).suffix(" ms"));
+                *end = end_ms / 1000.0;
+            }
+            ui.add(egui::DragValue::new(&mut self.rewind.seconds).speed(0.1).clamp_range(0.01..=60.0).prefix("keep ").suffix(" s"));
+        });
+    }
 }
 
 impl Default for Oscilloscope {
@@ -83,32 +609,67 @@ impl Default for Oscilloscope {
     }
 }
 
+/// Choose what a channel shows: voltage, stimulator current, one
+/// channel's conductance, a transmitter concentration, extracellular
+/// potassium or submembrane calcium.
+pub fn quantity_selector(ui: &mut Ui, id: impl std::hash::Hash, quantity: &mut ProbeSource) -> bool {
+    let before = *quantity;
+    egui::ComboBox::from_id_source(id)
+        .selected_text(quantity.name())
+        .show_ui(ui, |ui| {
+            ui.selectable_value(quantity, ProbeSource::Voltage, "Vm");
+            ui.selectable_value(quantity, ProbeSource::StimulatorCurrent, "Stimulator current");
+            ui.selectable_value(quantity, ProbeSource::Transmitter(Transmitter::Glutamate), "Glutamate");
+            ui.selectable_value(quantity, ProbeSource::Transmitter(Transmitter::Gaba), "GABA");
+            ui.selectable_value(quantity, ProbeSource::ExtracellularK, "Extracellular K+");
+            ui.selectable_value(quantity, ProbeSource::ShellCalcium, "Submembrane Ca2+");
+        });
+    if let ProbeSource::Conductance(index) = quantity {
+        ui.add(egui::DragValue::new(index).clamp_range(0..=63).prefix("channel "));
+    } else if ui.small_button("g").on_hover_text("Conductance of one of the membrane's channels").clicked() {
+        *quantity = ProbeSource::Conductance(0);
+    }
+    *quantity != before
+}
+
 pub fn step_oscilloscope_system(
     simulation_step_seconds: Res<SimulationStepSeconds>,
     mut oscilloscope: ResMut<Oscilloscope>,
     steps_per_frame: Res<StepsPerFrame>,
-    membrane_voltages: Query<&MembraneVoltage>
+    mut registry: ResMut<ProbeRegistry>,
 ) {
-    if simulation_step_seconds.0 != oscilloscope.last_known_simulation_step_seconds.0 {
-        oscilloscope.last_known_simulation_step_seconds.0 = simulation_step_seconds.0;
-        oscilloscope.write_offset = 0;
-        oscilloscope.buffers = [ [0.0; N_SAMPLES]; N_SOURCES ];
-        for i in 0..N_SAMPLES {
-            oscilloscope.times[i] = (i as f32) * simulation_step_seconds.0 * steps_per_frame.0 as f32;
-        }
+    let oscilloscope = &mut *oscilloscope;
+    let sample_seconds = simulation_step_seconds.0 * steps_per_frame.0 as f32 * oscilloscope.decimation as f32;
+    if sample_seconds != oscilloscope.sample_seconds {
+        oscilloscope.clear_buffers(sample_seconds);
     }
-    let sources = oscilloscope.sources.clone();
-    for (source_index, source) in sources.iter().enumerate() {
-        if let Some(entity) = source {
-            if let Ok(voltage) = membrane_voltages.get(*entity) {
-                let write_offset = oscilloscope.write_offset;
-                oscilloscope.buffers[source_index][write_offset] = voltage.0.0;
+    let samples = oscilloscope.samples.get_or_insert_with(|| {
+        let keys = (1..=MAX_CHANNELS).flat_map(|i| [ProbeKey::Oscilloscope(i), ProbeKey::OscilloscopeCurrent(i)]);
+        registry.subscribe(Subscription::Probes(keys.collect()))
+    });
+    let frames = samples.try_iter().collect::<Vec<_>>();
+    for frame in frames {
+        let read = |i: usize, key: fn(usize) -> ProbeKey, channel: &Channel| {
+            channel.shown_source()?;
+            let key = key(i + 1);
+            frame.samples.iter().find(|sample| sample.key == key).map(|sample| sample.value)
+        };
+        let probed = oscilloscope.channels.iter().enumerate().map(|(i, c)| read(i, ProbeKey::Oscilloscope, c)).collect();
+        oscilloscope.rewind.push(frame.t, probed);
+
+        oscilloscope.frames_since_sample += 1;
+        if oscilloscope.frames_since_sample < oscilloscope.decimation {
+            continue;
+        }
+        oscilloscope.frames_since_sample = 0;
+        let write_offset = oscilloscope.write_offset;
+        for (i, channel) in oscilloscope.channels.iter_mut().enumerate() {
+            if let Some(value) = read(i, ProbeKey::Oscilloscope, channel) {
+                channel.buffer[write_offset] = value;
+                channel.currents[write_offset] = read(i, ProbeKey::OscilloscopeCurrent, channel).unwrap_or(0.0);
             }
         }
-    }
-    oscilloscope.write_offset += 1;
-    if oscilloscope.write_offset >= N_SAMPLES {
-        oscilloscope.write_offset = 0;
+        oscilloscope.write_offset = (write_offset + 1) % oscilloscope.sample_depth;
     }
 }
 
@@ -117,3 +678,55 @@ pub fn print_oscilloscope_system(
 ) {
     eprintln!("{:?}", oscilloscope);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A -70 mV baseline with 1 mV wiggles and one 100 mV spike.
+    fn epsp_and_spike() -> Vec<f32> {
+        let mut samples = (0..1000).map(|i| -70.0 + (i as f32 / 50.0).sin()).collect::<Vec<_>>();
+        samples[500] = 30.0;
+        samples
+    }
+
+    #[test]
+    fn coarse_range_fits_the_spike() {
+        let mut range = ChannelRange::default();
+        range.auto_range(&epsp_and_spike());
+        assert!(range.to_divisions(30.0) <= N_DIVISIONS / 2.0);
+        assert!(range.to_divisions(-71.0) >= -N_DIVISIONS / 2.0);
+    }
+
+    #[test]
+    fn fine_range_resolves_the_baseline() {
+        let mut range = ChannelRange { mode: RangeMode::Fine, ..ChannelRange::default() };
+        range.auto_range(&epsp_and_spike());
+        assert!(range.mv_per_division < 1.0);
+        assert!(range.to_divisions(-70.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn rewind_keeps_recent_history() {
+        let mut rewind = RewindBuffer { seconds: 0.5, ..RewindBuffer::default() };
+        for frame in 0..=128 {
+            rewind.push(frame as f32 / 128.0, vec![Some(frame as f32), None]);
+        }
+        rewind.push(1.0, vec![Some(0.0); 2]);
+        assert_eq!(rewind.span(), Some((0.5, 1.0)), "old and repeated samples are dropped");
+        let window = rewind.window(0, 0.75, 0.8125);
+        assert_eq!(window.iter().map(|(_, v)| *v).collect::<Vec<_>>(), (96..=104).map(|f| f as f32).collect::<Vec<_>>());
+        assert_eq!(window[0].0, 0.0);
+        assert!(rewind.window(1, 0.5, 1.0).is_empty());
+
+        rewind.push(0.0, vec![None; 2]);
+        assert_eq!(rewind.span(), Some((0.0, 0.0)), "going back in time starts over");
+    }
+
+    #[test]
+    fn manual_range_is_left_alone() {
+        let mut range = ChannelRange { mode: RangeMode::Manual, mv_per_division: 3.0, offset_mv: 1.0 };
+        range.auto_range(&epsp_and_spike());
+        assert_eq!(range, ChannelRange { mode: RangeMode::Manual, mv_per_division: 3.0, offset_mv: 1.0 });
+    }
+}
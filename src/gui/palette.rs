@@ -0,0 +1,342 @@
+//! A command palette and keyboard shortcuts, so that the common actions do
+//! not each need a trip to the mouse.
+//!
+//! Shortcuts are set by action name in the `[shortcuts]` table of the
+//! config file, as `TogglePause = "P"` or `FocusSelection = "Ctrl+F"`;
+//! actions left out keep the defaults from `Action::default_shortcut`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+pub use crate::action::Action;
+use crate::camera::CameraCommand;
+use crate::command::{Command, SimulationControl};
+use crate::config::Config;
+use crate::dimension::StepsPerFrame;
+use crate::gui::channel_library::matches_search;
+use crate::integrations::dhall::read_scene;
+use crate::integrations::grace::SceneSegment;
+use crate::selection::Selection;
+use crate::stimulator::Stimulator;
+
+/// The key names shortcuts are written with, besides letters and digits.
+const NAMED_KEYS: [(&str, KeyCode); 21] = [
+    ("Space", KeyCode::Space),
+    ("Enter", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Period", KeyCode::Period),
+    ("Comma", KeyCode::Comma),
+    ("Slash", KeyCode::Slash),
+    ("Minus", KeyCode::Minus),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+];
+
+const LETTER_KEYS: [KeyCode; 26] = [
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF, KeyCode::KeyG,
+    KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN,
+    KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU,
+    KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY, KeyCode::KeyZ,
+];
+
+const DIGIT_KEYS: [KeyCode; 10] = [
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+];
+
+fn key_code(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        let c = c.to_ascii_uppercase();
+        if c.is_ascii_uppercase() {
+            return Some(LETTER_KEYS[(c as u8 - b'A') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(DIGIT_KEYS[(c as u8 - b'0') as usize]);
+        }
+    }
+    NAMED_KEYS.iter().find(|(key_name, _)| key_name.eq_ignore_ascii_case(name)).map(|(_, key)| *key)
+}
+
+fn key_name(key: KeyCode) -> String {
+    if let Some(i) = LETTER_KEYS.iter().position(|k| *k == key) {
+        return ((b'A' + i as u8) as char).to_string();
+    }
+    if let Some(i) = DIGIT_KEYS.iter().position(|k| *k == key) {
+        return i.to_string();
+    }
+    NAMED_KEYS.iter().find(|(_, k)| *k == key).map_or(format!("{:?}", key), |(name, _)| name.to_string())
+}
+
+/// A key with the modifiers that must be held with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shortcut {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub key: KeyCode,
+}
+
+impl Shortcut {
+    /// Parse a shortcut written as modifiers and a key joined with `+`:
+    /// "Ctrl+Shift+K", "Space", "F5".
+    pub fn parse(s: &str) -> Result<Shortcut, String> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty()).ok_or(format!("Empty shortcut \"{}\"", s))?;
+        let mut shortcut = Shortcut {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            key: key_code(key).ok_or(format!("Unknown key \"{}\" in shortcut \"{}\"", key, s))?,
+        };
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" | "cmd" => shortcut.ctrl = true,
+                "shift" => shortcut.shift = true,
+                "alt" => shortcut.alt = true,
+                _ => return Err(format!("Unknown modifier \"{}\" in shortcut \"{}\"", modifier, s)),
+            }
+        }
+        Ok(shortcut)
+    }
+
+    /// Whether the key went down this frame with exactly these modifiers
+    /// held, so that "S" does not also fire on Ctrl+S.
+    pub fn just_pressed(&self, keys: &ButtonInput<KeyCode>) -> bool {
+        keys.just_pressed(self.key)
+            && self.ctrl == keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight, KeyCode::SuperLeft, KeyCode::SuperRight])
+            && self.shift == keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+            && self.alt == keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight])
+    }
+}
+
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (held, name) in [(self.ctrl, "Ctrl+"), (self.shift, "Shift+"), (self.alt, "Alt+")] {
+            if held {
+                write!(f, "{}", name)?;
+            }
+        }
+        write!(f, "{}", key_name(self.key))
+    }
+}
+
+/// The shortcut bound to each action, if any.
+#[derive(Resource)]
+pub struct Shortcuts(pub HashMap<Action, Shortcut>);
+
+impl Shortcuts {
+    /// The default shortcuts, overridden by `overrides`. An empty override
+    /// unbinds the action. Invalid overrides are returned and the default
+    /// kept.
+    pub fn new(overrides: &HashMap<Action, String>) -> (Shortcuts, Vec<String>) {
+        let mut shortcuts = HashMap::new();
+        let mut errors = Vec::new();
+        for action in Action::ALL {
+            let default = action.default_shortcut().map(|s| Shortcut::parse(s).expect("default shortcuts parse"));
+            let shortcut = match overrides.get(&action) {
+                Some(s) if s.trim().is_empty() => None,
+                Some(s) => Shortcut::parse(s).map(Some).unwrap_or_else(|e| {
+                    errors.push(format!("{:?}: {}", action, e));
+                    default
+                }),
+                None => default,
+            };
+            if let Some(shortcut) = shortcut {
+                shortcuts.insert(action, shortcut);
+            }
+        }
+        (Shortcuts(shortcuts), errors)
+    }
+}
+
+impl FromWorld for Shortcuts {
+    fn from_world(world: &mut World) -> Self {
+        let overrides = world.get_resource::<Config>().map(|config| config.shortcuts.clone()).unwrap_or_default();
+        let (shortcuts, errors) = Shortcuts::new(&overrides);
+        for e in errors {
+            eprintln!("Invalid shortcut {}; using the default", e);
+        }
+        shortcuts
+    }
+}
+
+#[derive(Resource)]
+pub struct CommandPalette {
+    pub open: bool,
+    pub search: String,
+    /// The scene "Load scene" reads.
+    pub scene_path: String,
+    pub error: Option<String>,
+}
+
+impl FromWorld for CommandPalette {
+    fn from_world(world: &mut World) -> Self {
+        let scene_path = world.get_resource::<Config>().and_then(|config| config.scene.clone()).unwrap_or_default();
+        CommandPalette { open: false, search: String::new(), scene_path, error: None }
+    }
+}
+
+/// What the actions act on.
+#[derive(SystemParam)]
+pub struct ActionTargets<'w, 's> {
+    pub control: Res<'w, SimulationControl>,
+    pub steps_per_frame: Res<'w, StepsPerFrame>,
+    pub stimulator: Res<'w, Stimulator>,
+    pub selected: Query<'w, 's, &'static SceneSegment, With<Selection>>,
+    pub commands: EventWriter<'w, Command>,
+    pub camera_commands: EventWriter<'w, CameraCommand>,
+}
+
+fn perform(action: Action, palette: &mut CommandPalette, targets: &mut ActionTargets) {
+    match action {
+        Action::OpenPalette => {
+            palette.open = !palette.open;
+            palette.search.clear();
+        }
+        Action::TogglePause => {
+            targets.commands.send(if targets.control.paused { Command::Resume } else { Command::Pause });
+        }
+        Action::StepFrame => {
+            targets.commands.send(Command::Step { steps: targets.steps_per_frame.0 });
+        }
+        Action::Reset => {
+            targets.commands.send(Command::Reset);
+        }
+        Action::LoadScene => match read_scene(&palette.scene_path) {
+            Ok(scene) => {
+                targets.commands.send(Command::LoadScene { scene });
+                palette.error = None;
+            }
+            Err(e) => {
+                palette.error = Some(e);
+                palette.open = true;
+            }
+        },
+        Action::AddStimulator => {
+            for SceneSegment(at) in &targets.selected {
+                targets.commands.send(Command::SetStimulator {
+                    neuron: at.neuron,
+                    segment: at.segment,
+                    stimulator: targets.stimulator.serialize(),
+                });
+            }
+        }
+        Action::FocusSelection => {
+            targets.camera_commands.send(CameraCommand::FrameSelection);
+        }
+    }
+}
+
+/// Run the actions whose shortcuts were pressed, and show the palette
+/// while it is open.
+pub fn palette_gui(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    shortcuts: Res<Shortcuts>,
+    mut palette: ResMut<CommandPalette>,
+    mut targets: ActionTargets,
+) {
+    let ctx = contexts.ctx_mut();
+    // Keys typed into a text field are not shortcuts, except the one that
+    // closes the palette again.
+    let typing = ctx.wants_keyboard_input();
+    let mut chosen: Vec<Action> = Action::ALL
+        .into_iter()
+        .filter(|action| !typing || *action == Action::OpenPalette)
+        .filter(|action| shortcuts.0.get(action).is_some_and(|shortcut| shortcut.just_pressed(&keys)))
+        .collect();
+
+    if palette.open {
+        let palette = &mut *palette;
+        egui::Window::new("Commands")
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                let search = ui.text_edit_singleline(&mut palette.search);
+                search.request_focus();
+                let enter = search.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                let matching: Vec<Action> = Action::ALL
+                    .into_iter()
+                    .filter(|action| *action != Action::OpenPalette && matches_search(action.name(), &palette.search))
+                    .collect();
+                for action in &matching {
+                    ui.horizontal(|ui| {
+                        if ui.button(action.name()).clicked() {
+                            chosen.push(*action);
+                        }
+                        if let Some(shortcut) = shortcuts.0.get(action) {
+                            ui.weak(shortcut.to_string());
+                        }
+                    });
+                }
+                if enter {
+                    chosen.extend(matching.first());
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Scene file");
+                    ui.text_edit_singleline(&mut palette.scene_path);
+                });
+                if let Some(e) = &palette.error {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+            });
+    }
+
+    for action in chosen {
+        // Running anything from the palette closes it.
+        if palette.open && action != Action::OpenPalette {
+            palette.open = false;
+        }
+        perform(action, &mut palette, &mut targets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcuts_parse_and_print() {
+        let shortcut = Shortcut::parse("ctrl + shift+k").unwrap();
+        assert_eq!(shortcut, Shortcut { ctrl: true, shift: true, alt: false, key: KeyCode::KeyK });
+        assert_eq!(shortcut.to_string(), "Ctrl+Shift+K");
+        assert_eq!(Shortcut::parse("7").unwrap().key, KeyCode::Digit7);
+        assert_eq!(Shortcut::parse("f5").unwrap().to_string(), "F5");
+        assert!(Shortcut::parse("Ctrl+").is_err());
+        assert!(Shortcut::parse("Hyper+K").is_err());
+        assert!(Shortcut::parse("Ctrl+Banana").is_err());
+    }
+
+    #[test]
+    fn configured_shortcuts_replace_the_defaults() {
+        let overrides = HashMap::from([
+            (Action::TogglePause, "P".to_string()),
+            (Action::AddStimulator, "".to_string()),
+            (Action::Reset, "Ctrl+Nope".to_string()),
+        ]);
+        let (shortcuts, errors) = Shortcuts::new(&overrides);
+        assert_eq!(shortcuts.0[&Action::TogglePause].key, KeyCode::KeyP);
+        assert!(!shortcuts.0.contains_key(&Action::AddStimulator));
+        assert_eq!(shortcuts.0[&Action::Reset].to_string(), "Ctrl+R");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(shortcuts.0[&Action::OpenPalette].to_string(), "Ctrl+K");
+    }
+}
@@ -0,0 +1,134 @@
+//! A small RGB canvas that can draw lines and encode itself as a PNG, for
+//! exporting plots without a screenshot of the window. The image data is
+//! stored uncompressed; plots are small enough for that not to matter.
+
+#[derive(Clone, Debug)]
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, background: [u8; 3]) -> Self {
+        Canvas { width, height, pixels: vec![background; width * height] }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Set a pixel, ignoring those off the canvas.
+    pub fn set(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    /// Draw a one-pixel line between two points, clipped to the canvas.
+    pub fn line(&mut self, (x0, y0): (f32, f32), (x1, y1): (f32, f32), color: [u8; 3]) {
+        if ![x0, y0, x1, y1].iter().all(|c| c.is_finite()) {
+            return;
+        }
+        // Points far off the canvas would make for very long loops.
+        let limit = 4.0 * self.width.max(self.height) as f32;
+        let (x0, y0, x1, y1) = (x0.clamp(-limit, limit), y0.clamp(-limit, limit), x1.clamp(-limit, limit), y1.clamp(-limit, limit));
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+        for i in 0..=steps {
+            let f = i as f32 / steps as f32;
+            self.set((x0 + f * (x1 - x0)).round() as i64, (y0 + f * (y1 - y0)).round() as i64, color);
+        }
+    }
+
+    /// The canvas as an 8-bit RGB PNG file.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(self.height * (1 + 3 * self.width));
+        for row in self.pixels.chunks(self.width) {
+            // Filter type 0: none.
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+        let mut header = vec![];
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits per channel, truecolor, default compression, filtering
+        // and no interlacing.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks = data.chunks(u16::MAX as usize).collect::<Vec<_>>();
+    if blocks.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        out.push((i + 1 == blocks.len()) as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn encodes_a_line() {
+        let mut canvas = Canvas::new(4, 3, [0, 0, 0]);
+        canvas.line((0.0, 0.0), (3.0, 2.0), [255, 255, 0]);
+        assert_eq!(canvas.pixel(0, 0), [255, 255, 0]);
+        assert_eq!(canvas.pixel(3, 2), [255, 255, 0]);
+        assert_eq!(canvas.pixel(3, 0), [0, 0, 0]);
+        let png = canvas.encode();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+    }
+}
@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::analysis::SPIKE_THRESHOLD;
+use crate::gui::oscilloscope::{quantity_selector, Oscilloscope};
+use crate::gui::NextClickAction;
+use crate::probe::Probe;
+use crate::recording::Recorded;
+
+/// Place probes by clicking segments, and send them to the oscilloscope
+/// and to recordings.
+pub fn probes_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut next_click: ResMut<NextClickAction>,
+    mut oscilloscope: ResMut<Oscilloscope>,
+    mut probes: Query<(Entity, &mut Probe, Option<&Recorded>)>,
+) {
+    egui::Window::new("Probes")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let placing = matches!(*next_click, NextClickAction::AttachProbe);
+            if ui.selectable_label(placing, "Place a probe on the next segment clicked").clicked() {
+                *next_click = if placing { NextClickAction::ModifyStimulator } else { NextClickAction::AttachProbe };
+            }
+            if probes.is_empty() {
+                ui.weak("No probes");
+            }
+            for (segment, mut probe, recorded) in &mut probes {
+                ui.separator();
+                let probe = &mut *probe;
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut probe.label).desired_width(100.0));
+                    if quantity_selector(ui, ("probe_quantity", segment), &mut probe.quantity) {
+                        probe.history.clear();
+                    }
+                    if let Some((_, value)) = probe.history.back() {
+                        ui.label(format!("{:.3} {}", value, probe.quantity.unit()));
+                    }
+                });
+                if let Some(metrics) = probe.trace().metrics() {
+                    ui.weak(format!(
+                        "min {:.3}, mean {:.3}, max {:.3}, {} crossings of {} mV",
+                        metrics.min, metrics.mean, metrics.max, metrics.spike_count, SPIKE_THRESHOLD.0,
+                    ));
+                }
+                ui.horizontal(|ui| {
+                    // The first free channel, or the last one.
+                    let free = oscilloscope.channels.iter().position(|c| c.source.is_none());
+                    if ui.button("Show on oscilloscope").clicked() {
+                        let channel = free.unwrap_or(oscilloscope.channels.len() - 1);
+                        oscilloscope.accept_source(channel, segment);
+                        oscilloscope.channels[channel].quantity = probe.quantity;
+                    }
+                    let mut record = recorded.is_some();
+                    if ui.checkbox(&mut record, "Record").on_hover_text("Include in the next HDF5 or Arrow recording").changed() {
+                        if record {
+                            commands.entity(segment).insert(Recorded { label: probe.label.clone() });
+                        } else {
+                            commands.entity(segment).remove::<Recorded>();
+                        }
+                    }
+                    if ui.button("Remove").clicked() {
+                        commands.entity(segment).remove::<Probe>();
+                    }
+                });
+            }
+        });
+}
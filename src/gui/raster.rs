@@ -0,0 +1,281 @@
+//! Spike raster of every neuron in the scene.
+//!
+//! Each neuron is a row, with a mark at each upward crossing of
+//! `SPIKE_THRESHOLD` at its soma. Rows are colored by the neuron's tag (its
+//! population) and can be sorted by firing rate or first-spike latency, or
+//! reordered by hand.
+
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Color32, Ui};
+use bevy_egui::EguiContexts;
+use egui_plot::{Legend, MarkerShape, Plot, Points};
+
+use crate::analysis::SPIKE_THRESHOLD;
+use crate::dimension::Timestamp;
+use crate::integrations::grace::{soma, CurrentScene, SceneSegment};
+use crate::neuron::membrane::MembraneVoltage;
+
+const TAG_COLORS: [Color32; 6] = [
+    Color32::LIGHT_BLUE,
+    Color32::LIGHT_RED,
+    Color32::LIGHT_GREEN,
+    Color32::GOLD,
+    Color32::from_rgb(200, 140, 255),
+    Color32::from_rgb(255, 170, 90),
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct RasterRow {
+    pub label: String,
+    pub tag: Option<String>,
+    /// Spike times (s).
+    pub spikes: Vec<f32>,
+    /// SWC id of the segment whose voltage is watched.
+    soma: Option<i32>,
+    last_voltage: Option<f32>,
+}
+
+impl RasterRow {
+    /// Spikes per second in `[from, to)`.
+    pub fn firing_rate(&self, from: f32, to: f32) -> f32 {
+        if to <= from {
+            return 0.0;
+        }
+        let count = self.spikes.iter().filter(|t| **t >= from && **t < to).count();
+        count as f32 / (to - from)
+    }
+
+    /// Time from `from` to the first spike at or after it.
+    pub fn first_spike_latency(&self, from: f32) -> Option<f32> {
+        self.spikes.iter().find(|t| **t >= from).map(|t| t - from)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowOrder {
+    /// Neurons in the order they appear in the scene.
+    Scene,
+    /// Fastest-firing neurons first.
+    FiringRate,
+    /// Earliest-firing neurons first; silent neurons last.
+    FirstSpikeLatency,
+    /// The order in `SpikeRaster::manual_order`.
+    Manual,
+}
+
+#[derive(Resource)]
+pub struct SpikeRaster {
+    /// One row per scene neuron, indexed like `CurrentScene`'s neurons.
+    pub rows: Vec<RasterRow>,
+    pub order: RowOrder,
+    pub manual_order: Vec<usize>,
+    /// Length of the displayed window, ending now (s).
+    pub window_seconds: f32,
+    last_time: f32,
+}
+
+impl Default for SpikeRaster {
+    fn default() -> Self {
+        SpikeRaster {
+            rows: vec![],
+            order: RowOrder::Scene,
+            manual_order: vec![],
+            window_seconds: 0.5,
+            last_time: 0.0,
+        }
+    }
+}
+
+impl SpikeRaster {
+    /// Rebuild the rows for a new scene, forgetting all spikes.
+    pub fn set_neurons(&mut self, scene: &CurrentScene) {
+        self.rows = scene
+            .0
+            .neurons
+            .iter()
+            .enumerate()
+            .map(|(i, scene_neuron)| RasterRow {
+                label: format!("{}", i + 1),
+                tag: scene_neuron.tag.clone(),
+                soma: soma(&scene_neuron.neuron).map(|s| s.id),
+                ..RasterRow::default()
+            })
+            .collect();
+        self.manual_order = (0..self.rows.len()).collect();
+    }
+
+    /// Note a neuron's voltage at time `t`, recording a spike on an upward
+    /// threshold crossing.
+    pub fn record(&mut self, neuron: usize, t: f32, v: f32) {
+        if let Some(row) = self.rows.get_mut(neuron) {
+            if let Some(last) = row.last_voltage {
+                if last < SPIKE_THRESHOLD.0 && v >= SPIKE_THRESHOLD.0 {
+                    row.spikes.push(t);
+                }
+            }
+            row.last_voltage = Some(v);
+        }
+    }
+
+    /// Row indices, top to bottom, for the window ending at `now`.
+    pub fn row_order(&self, now: f32) -> Vec<usize> {
+        let from = now - self.window_seconds;
+        let mut order = (0..self.rows.len()).collect::<Vec<_>>();
+        match self.order {
+            RowOrder::Scene => {},
+            RowOrder::FiringRate => order.sort_by(|a, b| {
+                let rate = |i: &usize| self.rows[*i].firing_rate(from, now);
+                rate(b).total_cmp(&rate(a))
+            }),
+            RowOrder::FirstSpikeLatency => order.sort_by(|a, b| {
+                let latency = |i: &usize| self.rows[*i].first_spike_latency(from).unwrap_or(f32::INFINITY);
+                latency(a).total_cmp(&latency(b))
+            }),
+            RowOrder::Manual => return self.manual_order.clone(),
+        }
+        order
+    }
+
+    /// Tags are colored in order of first appearance. Untagged neurons
+    /// are white.
+    fn tag_color(&self, tag: &Option<String>) -> Color32 {
+        let Some(tag) = tag else {
+            return Color32::WHITE;
+        };
+        let mut tags: Vec<&String> = vec![];
+        for t in self.rows.iter().filter_map(|r| r.tag.as_ref()) {
+            if !tags.contains(&t) {
+                tags.push(t);
+            }
+        }
+        let i = tags.iter().position(|t| *t == tag).unwrap_or(0);
+        TAG_COLORS[i % TAG_COLORS.len()]
+    }
+
+    fn order_widget(&mut self, ui: &mut Ui, now: f32) {
+        ui.horizontal(|ui| {
+            ui.label("Sort");
+            for (order, label) in [
+                (RowOrder::Scene, "Scene"),
+                (RowOrder::FiringRate, "Firing rate"),
+                (RowOrder::FirstSpikeLatency, "Latency"),
+                (RowOrder::Manual, "Manual"),
+            ] {
+                if ui.selectable_label(self.order == order, label).clicked() && self.order != order {
+                    if order == RowOrder::Manual {
+                        // Start from whatever is on screen.
+                        self.manual_order = self.row_order(now);
+                    }
+                    self.order = order;
+                }
+            }
+        });
+        ui.add(egui::Slider::new(&mut self.window_seconds, 0.01..=10.0).logarithmic(true).text("Window (s)"));
+        if self.order == RowOrder::Manual {
+            let mut swap = None;
+            for (position, row) in self.manual_order.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(position > 0, egui::Button::new("⏶")).clicked() {
+                        swap = Some(position - 1);
+                    }
+                    if ui.add_enabled(position + 1 < self.manual_order.len(), egui::Button::new("⏷")).clicked() {
+                        swap = Some(position);
+                    }
+                    ui.colored_label(self.tag_color(&self.rows[*row].tag), self.rows[*row].label.as_str());
+                });
+            }
+            if let Some(i) = swap {
+                self.manual_order.swap(i, i + 1);
+            }
+        }
+    }
+}
+
+pub fn record_spikes(
+    timestamp: Res<Timestamp>,
+    current_scene: Res<CurrentScene>,
+    mut raster: ResMut<SpikeRaster>,
+    segments: Query<(&SceneSegment, &MembraneVoltage)>,
+) {
+    if current_scene.is_changed() || timestamp.0 < raster.last_time {
+        raster.set_neurons(&current_scene);
+    }
+    raster.last_time = timestamp.0;
+    for (SceneSegment(at), voltage) in &segments {
+        let is_soma = raster.rows.get(at.neuron).is_some_and(|row| row.soma == Some(at.segment));
+        if is_soma {
+            raster.record(at.neuron, timestamp.0, voltage.0.0);
+        }
+    }
+}
+
+pub fn raster_gui(
+    mut contexts: EguiContexts,
+    timestamp: Res<Timestamp>,
+    mut raster: ResMut<SpikeRaster>,
+) {
+    egui::Window::new("Raster")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let now = timestamp.0;
+            raster.order_widget(ui, now);
+            let order = raster.row_order(now);
+            let from = now - raster.window_seconds;
+            Plot::new("spike_raster")
+                .view_aspect(2.0)
+                .legend(Legend::default())
+                .include_x(from as f64)
+                .include_x(now as f64)
+                .show_y(false)
+                .show(ui, |plot_ui| {
+                    for (position, i) in order.iter().enumerate() {
+                        let row = &raster.rows[*i];
+                        let y = (order.len() - position) as f64;
+                        let points = row
+                            .spikes
+                            .iter()
+                            .filter(|t| **t >= from)
+                            .map(|t| [*t as f64, y])
+                            .collect::<Vec<_>>();
+                        let name = row.tag.clone().unwrap_or_else(|| "untagged".to_string());
+                        plot_ui.points(
+                            Points::new(points)
+                                .shape(MarkerShape::Square)
+                                .radius(1.5)
+                                .color(raster.tag_color(&row.tag))
+                                .name(name)
+                        );
+                    }
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster_with(spikes: Vec<Vec<f32>>) -> SpikeRaster {
+        let rows = spikes.into_iter().map(|spikes| RasterRow { spikes, ..RasterRow::default() }).collect();
+        SpikeRaster { rows, window_seconds: 1.0, ..SpikeRaster::default() }
+    }
+
+    #[test]
+    fn detects_upward_crossings_only() {
+        let mut raster = raster_with(vec![vec![]]);
+        for (i, v) in [-70.0, 10.0, 20.0, -60.0, 5.0].iter().enumerate() {
+            raster.record(0, i as f32, *v);
+        }
+        assert_eq!(raster.rows[0].spikes, vec![1.0, 4.0]);
+    }
+
+    #[test]
+    fn sorts_by_rate_and_latency() {
+        let mut raster = raster_with(vec![vec![0.5], vec![0.1, 0.2, 0.3], vec![]]);
+        raster.order = RowOrder::FiringRate;
+        assert_eq!(raster.row_order(1.0), vec![1, 0, 2]);
+        raster.order = RowOrder::FirstSpikeLatency;
+        assert_eq!(raster.row_order(1.0), vec![1, 0, 2]);
+        raster.rows[0].spikes = vec![0.05];
+        assert_eq!(raster.row_order(1.0), vec![0, 1, 2]);
+    }
+}
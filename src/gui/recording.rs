@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::clock::RunClock;
+use crate::recording::{Recorded, Recording};
+use crate::recording::csv_recorder::{Quantity, Recorder};
+use crate::selection::Selection;
+
+pub fn recording_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    run_clock: Res<RunClock>,
+    mut recording: ResMut<Recording>,
+    selected: Query<Entity, With<Selection>>,
+    recorded: Query<(Entity, &Recorded)>,
+) {
+    egui::Window::new("Recording")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let recording = &mut *recording;
+            let is_active = recording.active.is_some();
+            ui.add_enabled_ui(!is_active, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut recording.path);
+                });
+                ui.label("HDF5, or Arrow IPC for paths ending in .arrow or .feather.");
+                ui.add(egui::DragValue::new(&mut recording.decimation)
+                    .clamp_range(1..=10000)
+                    .prefix("Keep every ")
+                    .suffix(" frames"));
+                ui.horizontal(|ui| {
+                    if ui.button("Record selected segments").clicked() {
+                        for entity in &selected {
+                            commands.entity(entity).insert(Recorded { label: format!("segment_{}", entity.index()) });
+                        }
+                    }
+                    if ui.button("Clear").clicked() {
+                        for (entity, _) in &recorded {
+                            commands.entity(entity).remove::<Recorded>();
+                        }
+                    }
+                });
+            });
+            ui.label(format!("{} segments marked for recording.", recorded.iter().count()));
+
+            ui.separator();
+            if ui.button("Attach CSV recorder to selected segments").clicked() {
+                for entity in &selected {
+                    commands.entity(entity).insert(Recorder::new(
+                        format!("segment_{}.csv", entity.index()),
+                        vec![Quantity::Voltage, Quantity::GateMagnitudes],
+                        recording.decimation,
+                    ));
+                }
+            }
+
+            if is_active {
+                if ui.button("Stop").clicked() {
+                    recording.stop();
+                }
+            } else if ui.add_enabled(!recorded.is_empty(), egui::Button::new("Start")).clicked() {
+                let channels = recorded.iter().map(|(e, r)| (e, r.label.clone())).collect();
+                recording.start(channels, &run_clock);
+            }
+            if let Some(e) = &recording.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+            }
+        });
+}
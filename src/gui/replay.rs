@@ -0,0 +1,53 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::command::Command;
+use crate::gui::download::save_file;
+use crate::replay::SessionRecorder;
+
+/// Record the session, and save or replay the last recording.
+pub fn replay_gui(
+    mut contexts: EguiContexts,
+    recorder: Res<SessionRecorder>,
+    mut events: EventWriter<Command>,
+    mut save_error: Local<Option<String>>,
+) {
+    egui::Window::new("Session recording")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(recorder.status());
+            ui.horizontal(|ui| {
+                if recorder.is_recording() {
+                    if ui.button("Stop recording").clicked() {
+                        events.send(Command::StopRecording);
+                    }
+                } else if ui.button("Start recording").clicked() {
+                    events.send(Command::StartRecording);
+                }
+                if recorder.is_replaying() && ui.button("Stop replay").clicked() {
+                    events.send(Command::StopRecording);
+                }
+            });
+            ui.weak("Recording restarts the scene at time zero.");
+
+            let Some(session) = &recorder.last else {
+                return;
+            };
+            ui.separator();
+            ui.label(format!("Last session: {} interventions", session.commands.len()));
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!recorder.is_recording(), egui::Button::new("Replay")).clicked() {
+                    events.send(Command::ReplaySession { session: session.clone() });
+                }
+                if ui.button("Save session").clicked() {
+                    *save_error = serde_json::to_string_pretty(session)
+                        .map_err(|e| e.to_string())
+                        .and_then(|json| save_file("session.json", "application/json", json.as_bytes()))
+                        .err();
+                }
+            });
+            if let Some(e) = &*save_error {
+                ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+            }
+        });
+}
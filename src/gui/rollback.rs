@@ -0,0 +1,37 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::checkpoint::SnapshotBuffer;
+use crate::command::Command;
+use crate::dimension::Timestamp;
+
+/// Take a snapshot of the running simulation and roll back to it, to try
+/// several interventions from the same point.
+pub fn rollback_gui(
+    mut contexts: EguiContexts,
+    buffer: Res<SnapshotBuffer>,
+    timestamp: Res<Timestamp>,
+    mut events: EventWriter<Command>,
+) {
+    egui::Window::new("Snapshot")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            match &buffer.0 {
+                Some(snapshot) => ui.label(format!(
+                    "Snapshot at {:.2} ms, {:.2} ms ago",
+                    snapshot.t_seconds * 1000.0,
+                    (timestamp.0 - snapshot.t_seconds) * 1000.0,
+                )),
+                None => ui.label("No snapshot"),
+            };
+            ui.horizontal(|ui| {
+                if ui.button("Take snapshot").clicked() {
+                    events.send(Command::TakeSnapshot);
+                }
+                if ui.add_enabled(buffer.0.is_some(), egui::Button::new("Roll back")).clicked() {
+                    events.send(Command::RollBack);
+                }
+            });
+            ui.weak("Rolling back restores voltages, gates and synapses, not edits to the scene.");
+        });
+}
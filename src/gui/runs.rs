@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Ui};
+use egui_plot::{Legend, Line, LineStyle, Plot};
+
+use crate::analysis::{Trace, TraceMetrics};
+use crate::clock::{unix_seconds_now, RunClock};
+use crate::dimension::{SimulationStepSeconds, StepsPerFrame, Timestamp};
+use crate::gui::cursors::Cursors;
+use crate::gui::oscilloscope::Oscilloscope;
+use crate::integrations::nwb;
+
+/// A saved simulation run: the settings it ran with, and the oscilloscope
+/// traces as they were when it was saved.
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub name: String,
+    /// Wall-clock time at which the run was saved.
+    pub saved_at_unix_seconds: f64,
+    pub simulation_time: Timestamp,
+    pub simulation_step: SimulationStepSeconds,
+    pub steps_per_frame: StepsPerFrame,
+    pub traces: Vec<Trace>,
+    /// Stimulator currents (uA/cm^2) at each traced source, labelled like
+    /// the voltage trace they belong to.
+    pub stimuli: Vec<Trace>,
+}
+
+impl Run {
+    pub fn capture(
+        name: String,
+        oscilloscope: &Oscilloscope,
+        simulation_time: Timestamp,
+        simulation_step: SimulationStepSeconds,
+        steps_per_frame: StepsPerFrame,
+    ) -> Self {
+        let traces = (0..oscilloscope.channels.len())
+            .filter_map(|i| oscilloscope.trace(i))
+            .collect();
+        let stimuli = (0..oscilloscope.channels.len())
+            .filter_map(|i| oscilloscope.current_trace(i))
+            .collect();
+        Run {
+            name,
+            saved_at_unix_seconds: unix_seconds_now(),
+            simulation_time,
+            simulation_step,
+            steps_per_frame,
+            traces,
+            stimuli,
+        }
+    }
+
+    pub fn trace(&self, label: &str) -> Option<&Trace> {
+        self.traces.iter().find(|t| t.label == label)
+    }
+}
+
+/// The runs saved during this session, and the pair currently being
+/// compared.
+#[derive(Default, Resource)]
+pub struct RunRegistry {
+    pub runs: Vec<Run>,
+    pub baseline: Option<usize>,
+    pub current: Option<usize>,
+    pub export_error: Option<String>,
+    pub cursors: Cursors,
+}
+
+impl RunRegistry {
+    /// Save a run. It becomes the current run, and the previously current
+    /// run becomes the baseline.
+    pub fn save(&mut self, run: Run) {
+        self.runs.push(run);
+        if self.current.is_some() {
+            self.baseline = self.current;
+        }
+        self.current = Some(self.runs.len() - 1);
+    }
+
+    /// Draw the run controls and comparison. Returns the run that was
+    /// saved this frame, if any.
+    pub fn widget(
+        &mut self,
+        ui: &mut Ui,
+        oscilloscope: &Oscilloscope,
+        simulation_time: Timestamp,
+        simulation_step: SimulationStepSeconds,
+        steps_per_frame: StepsPerFrame,
+        run_clock: &RunClock,
+    ) -> Option<&Run> {
+        let mut saved = false;
+        ui.horizontal(|ui| {
+            saved = ui.button("Save run").clicked();
+            let current = self.current.and_then(|i| self.runs.get(i));
+            if ui.add_enabled(current.is_some(), egui::Button::new("Export NWB")).clicked() {
+                if let Some(run) = current {
+                    let path = format!("{}.nwb", run.name.replace(' ', "_").to_lowercase());
+                    self.export_error = nwb::write(&path, run, run_clock.started_at_unix_seconds).err();
+                }
+            }
+        });
+        if saved {
+            let name = format!("Run {}", self.runs.len() + 1);
+            self.save(Run::capture(name, oscilloscope, simulation_time, simulation_step, steps_per_frame));
+        }
+        if let Some(e) = &self.export_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+        }
+
+        run_selector(ui, "Baseline", &self.runs, &mut self.baseline);
+        run_selector(ui, "Current", &self.runs, &mut self.current);
+
+        let baseline = self.baseline.and_then(|i| self.runs.get(i));
+        let current = self.current.and_then(|i| self.runs.get(i));
+        match (baseline, current) {
+            (Some(baseline), Some(current)) => compare(ui, baseline, current, &mut self.cursors),
+            _ => { ui.label("Save two runs to compare them."); },
+        }
+
+        if saved { self.runs.last() } else { None }
+    }
+}
+
+fn run_selector(ui: &mut Ui, label: &str, runs: &[Run], selection: &mut Option<usize>) {
+    let selected_text = selection
+        .and_then(|i| runs.get(i))
+        .map_or("None".to_string(), |run| run.name.clone());
+    egui::ComboBox::from_label(label)
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            for (i, run) in runs.iter().enumerate() {
+                ui.selectable_value(selection, Some(i), run.name.as_str());
+            }
+        });
+}
+
+/// Overlay the traces of two runs (the baseline dashed) and tabulate how
+/// their metrics changed.
+fn compare(ui: &mut Ui, baseline: &Run, current: &Run, cursors: &mut Cursors) {
+    ui.label(format!(
+        "{}: dt = {:.2} us, {} steps/frame. {}: dt = {:.2} us, {} steps/frame.",
+        baseline.name, baseline.simulation_step.0 * 1e6, baseline.steps_per_frame.0,
+        current.name, current.simulation_step.0 * 1e6, current.steps_per_frame.0,
+    ));
+
+    Plot::new("run_comparison")
+        .view_aspect(2.0)
+        .legend(Legend::default())
+        .allow_drag(!cursors.shown)
+        .show(ui, |plot_ui| {
+            for trace in baseline.traces.iter() {
+                plot_ui.line(
+                    Line::new(trace.points())
+                        .name(format!("{} {}", baseline.name, trace.label))
+                        .style(LineStyle::dashed_loose())
+                );
+            }
+            for trace in current.traces.iter() {
+                plot_ui.line(
+                    Line::new(trace.points())
+                        .name(format!("{} {}", current.name, trace.label))
+                );
+            }
+            cursors.show(plot_ui);
+        });
+    cursors.readout(ui, "ms", |t| t * 1000.0, "V", "mV", |v| v);
+
+    egui::Grid::new("run_metrics").striped(true).show(ui, |ui| {
+        ui.label("Channel");
+        ui.label("Min (mV)");
+        ui.label("Max (mV)");
+        ui.label("Mean (mV)");
+        ui.label("Spikes");
+        ui.end_row();
+        for trace in current.traces.iter() {
+            let current_metrics = trace.metrics();
+            let baseline_metrics = baseline.trace(&trace.label).and_then(|t| t.metrics());
+            ui.label(trace.label.as_str());
+            metric_cell(ui, &baseline_metrics, &current_metrics, |m| m.min);
+            metric_cell(ui, &baseline_metrics, &current_metrics, |m| m.max);
+            metric_cell(ui, &baseline_metrics, &current_metrics, |m| m.mean);
+            metric_cell(ui, &baseline_metrics, &current_metrics, |m| m.spike_count as f32);
+            ui.end_row();
+        }
+    });
+}
+
+fn metric_cell(
+    ui: &mut Ui,
+    baseline: &Option<TraceMetrics>,
+    current: &Option<TraceMetrics>,
+    metric: impl Fn(&TraceMetrics) -> f32,
+) {
+    match (baseline, current) {
+        (Some(b), Some(c)) => {
+            let (b, c) = (metric(b), metric(c));
+            ui.label(format!("{:.1} -> {:.1} ({:+.1})", b, c, c - b));
+        },
+        (None, Some(c)) => { ui.label(format!("{:.1}", metric(c))); },
+        _ => { ui.label("-"); },
+    }
+}
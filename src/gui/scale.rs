@@ -0,0 +1,193 @@
+//! Real-world scale and orientation of the 3D view: a scale bar drawn in
+//! the scene, XYZ axes in the corner of the window, and a window for the
+//! `UnitScale` used when spawning neurons.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::units::{format_microns, scale_bar_microns, UnitScale};
+
+/// Fraction of the view width the scale bar aims to fill.
+const SCALE_BAR_FRACTION: f32 = 0.2;
+
+/// Length of the orientation axes on screen (points).
+const AXIS_LENGTH: f32 = 30.0;
+
+#[derive(Resource)]
+pub struct ScaleBar {
+    pub shown: bool,
+    /// Length of the bar drawn last frame (µm).
+    pub length_microns: f32,
+    /// Width of the view at the camera's focus (µm).
+    pub view_width_microns: f32,
+    /// Whether the world axes are drawn in the corner of the window.
+    pub show_axes: bool,
+    /// Where the bar's label goes, in window coordinates.
+    label_position: Option<Vec2>,
+    /// The camera's rotation last frame, for the axes.
+    camera_rotation: Option<Quat>,
+}
+
+impl Default for ScaleBar {
+    fn default() -> Self {
+        ScaleBar {
+            shown: true,
+            length_microns: 0.0,
+            view_width_microns: 0.0,
+            show_axes: true,
+            label_position: None,
+            camera_rotation: None,
+        }
+    }
+}
+
+/// Draw the scale bar across the lower left of the view, in the plane
+/// through the camera's focus.
+pub fn draw_scale_bar(
+    mut gizmos: Gizmos,
+    scale: Res<UnitScale>,
+    mut bar: ResMut<ScaleBar>,
+    cameras: Query<(&Camera, &GlobalTransform, &Projection, Option<&PanOrbitCamera>), With<Camera3d>>,
+) {
+    bar.label_position = None;
+    bar.camera_rotation = None;
+    let Some((camera, camera_transform, projection, orbit)) = cameras.iter().next() else {
+        return;
+    };
+    bar.camera_rotation = Some(camera_transform.to_scale_rotation_translation().1);
+    let focus = orbit.map_or(Vec3::ZERO, |o| o.focus);
+    let (width, height) = match projection {
+        Projection::Perspective(p) => {
+            let distance = (focus - camera_transform.translation()).dot(camera_transform.forward()).max(f32::EPSILON);
+            let height = 2.0 * distance * (p.fov * 0.5).tan();
+            (height * p.aspect_ratio, height)
+        },
+        Projection::Orthographic(o) => (o.area.width(), o.area.height()),
+    };
+    bar.view_width_microns = scale.screen_to_microns(width);
+    bar.length_microns = scale_bar_microns(bar.view_width_microns * SCALE_BAR_FRACTION);
+    if !bar.shown {
+        return;
+    }
+
+    let right = camera_transform.right();
+    let up = camera_transform.up();
+    let start = focus - right * width * 0.4 - up * height * 0.4;
+    let end = start + right * scale.microns_to_screen(bar.length_microns);
+    let tick = up * height * 0.01;
+    gizmos.line(start, end, Color::WHITE);
+    gizmos.line(start - tick, start + tick, Color::WHITE);
+    gizmos.line(end - tick, end + tick, Color::WHITE);
+    bar.label_position = camera.world_to_viewport(camera_transform, (start + end) * 0.5 + tick * 2.0);
+}
+
+/// The world axes as a camera with `rotation` sees them: each axis's name,
+/// its direction on screen (y down, at most unit length), and how far it
+/// points toward the camera. Sorted back to front, so that nearer axes
+/// are drawn over farther ones.
+pub fn orientation_axes(rotation: Quat) -> Vec<(&'static str, Vec2, f32)> {
+    let mut axes: Vec<_> = [("X", Vec3::X), ("Y", Vec3::Y), ("Z", Vec3::Z)]
+        .into_iter()
+        .map(|(name, axis)| {
+            let seen = rotation.inverse() * axis;
+            (name, Vec2::new(seen.x, -seen.y), seen.z)
+        })
+        .collect();
+    axes.sort_by(|a, b| a.2.total_cmp(&b.2));
+    axes
+}
+
+fn draw_axes(ctx: &egui::Context, rotation: Quat) {
+    egui::Area::new(egui::Id::new("orientation_axes"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+        .interactable(false)
+        .show(ctx, |ui| {
+            let (rect, _) = ui.allocate_exact_size(egui::Vec2::splat(AXIS_LENGTH * 2.8), egui::Sense::hover());
+            let center = rect.center();
+            for (name, direction, _) in orientation_axes(rotation) {
+                let color = match name {
+                    "X" => egui::Color32::from_rgb(230, 80, 80),
+                    "Y" => egui::Color32::from_rgb(80, 210, 80),
+                    _ => egui::Color32::from_rgb(90, 140, 250),
+                };
+                let direction = egui::vec2(direction.x, direction.y);
+                ui.painter().line_segment([center, center + direction * AXIS_LENGTH], egui::Stroke::new(2.0, color));
+                ui.painter().text(
+                    center + direction * (AXIS_LENGTH + 8.0),
+                    egui::Align2::CENTER_CENTER,
+                    name,
+                    egui::FontId::proportional(12.0),
+                    color,
+                );
+            }
+        });
+}
+
+pub fn scale_gui(
+    mut contexts: EguiContexts,
+    mut scale: ResMut<UnitScale>,
+    mut bar: ResMut<ScaleBar>,
+    mut draft: Local<Option<(f32, f32, Option<String>)>>,
+) {
+    let ctx = contexts.ctx_mut();
+    if let Some(position) = bar.label_position {
+        egui::Area::new(egui::Id::new("scale_bar_label"))
+            .fixed_pos(egui::pos2(position.x, position.y))
+            .pivot(egui::Align2::CENTER_BOTTOM)
+            .interactable(false)
+            .show(ctx, |ui| {
+                ui.label(egui::RichText::new(format_microns(bar.length_microns)).color(egui::Color32::WHITE));
+            });
+    }
+
+    if let Some(rotation) = bar.camera_rotation.filter(|_| bar.show_axes) {
+        draw_axes(ctx, rotation);
+    }
+
+    egui::Window::new("Scale").default_open(false).show(ctx, |ui| {
+        ui.checkbox(&mut bar.shown, "Show scale bar");
+        ui.checkbox(&mut bar.show_axes, "Show orientation axes");
+        ui.label(format!("The view is {} across.", format_microns(bar.view_width_microns)));
+
+        let (screen_per_micron, radius_exaggeration, error) =
+            draft.get_or_insert_with(|| (scale.screen_per_micron(), scale.radius_exaggeration(), None));
+        ui.add(egui::DragValue::new(screen_per_micron).speed(0.01).prefix("Screen units per µm: "));
+        ui.add(egui::DragValue::new(radius_exaggeration).speed(0.1).prefix("Neurite radius ×"));
+        if ui.button("Apply").on_hover_text("Takes effect for scenes loaded afterwards.").clicked() {
+            match UnitScale::new(*screen_per_micron, *radius_exaggeration) {
+                Ok(new_scale) => {
+                    *scale = new_scale;
+                    *error = None;
+                },
+                Err(e) => *error = Some(e),
+            }
+        }
+        if let Some(e) = error {
+            ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axes_turn_with_the_camera() {
+        let close = |a: Vec2, b: Vec2| a.distance(b) < 1e-5;
+        let axes = orientation_axes(Quat::IDENTITY);
+        assert_eq!(axes.iter().map(|(name, _, _)| *name).collect::<Vec<_>>(), ["X", "Y", "Z"]);
+        assert!(close(axes[0].1, Vec2::X) && close(axes[1].1, Vec2::NEG_Y));
+
+        // Looking down the X axis from its positive end, Z is to the left
+        // and X points at the camera, so it is drawn last.
+        let camera = Transform::from_xyz(10.0, 0.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y);
+        let axes = orientation_axes(camera.rotation);
+        let (name, direction, _) = axes[2];
+        assert_eq!(name, "X");
+        assert!(direction.length() < 1e-5);
+        let z = axes.iter().find(|(name, _, _)| *name == "Z").unwrap();
+        assert!(close(z.1, Vec2::NEG_X));
+    }
+}
@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::constants::SimulationConstants;
+use crate::integrations::dhall::read_scene;
+use crate::plugin::SimulationState;
+use crate::scene_diff::{diff, Change};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Baseline {
+    /// The scene as it was loaded, before any edits.
+    Loaded,
+    /// A scene saved to a JSON or Dhall file.
+    File,
+}
+
+#[derive(Resource)]
+pub struct SceneDiffView {
+    pub baseline: Baseline,
+    pub path: String,
+    pub changes: Option<Result<Vec<Change>, String>>,
+}
+
+impl Default for SceneDiffView {
+    fn default() -> Self {
+        SceneDiffView { baseline: Baseline::Loaded, path: "scene.json".to_string(), changes: None }
+    }
+}
+
+pub fn scene_diff_gui(
+    mut contexts: EguiContexts,
+    constants: Res<SimulationConstants>,
+    mut view: ResMut<SceneDiffView>,
+    simulation_state: SimulationState,
+) {
+    egui::Window::new("Scene changes")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let view = &mut *view;
+            ui.horizontal(|ui| {
+                ui.label("Compare against");
+                ui.selectable_value(&mut view.baseline, Baseline::Loaded, "Scene as loaded");
+                ui.selectable_value(&mut view.baseline, Baseline::File, "File");
+            });
+            if view.baseline == Baseline::File {
+                ui.text_edit_singleline(&mut view.path);
+            }
+            if ui.button("Compare").clicked() {
+                let baseline = match view.baseline {
+                    Baseline::Loaded => {
+                        let mut loaded = simulation_state.current_scene.0.clone();
+                        // A scene without constants was loaded with the defaults.
+                        loaded.constants.get_or_insert_with(|| SimulationConstants::default().serialize());
+                        Ok(loaded)
+                    },
+                    Baseline::File => read_scene(&view.path),
+                };
+                let current = simulation_state.serialize(&constants);
+                view.changes = Some(baseline.map(|baseline| diff(&baseline, &current)));
+            }
+
+            match &view.changes {
+                None => {},
+                Some(Err(e)) => {
+                    ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+                },
+                Some(Ok(changes)) if changes.is_empty() => {
+                    ui.label("No changes.");
+                },
+                Some(Ok(changes)) => {
+                    ui.label(format!("{} changes:", changes.len()));
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        for change in changes {
+                            ui.label(change.to_string());
+                        }
+                    });
+                },
+            }
+        });
+}
@@ -0,0 +1,228 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Ui};
+use egui_plot::{Line, Plot};
+
+use crate::analysis::Trace;
+use crate::clock::{iso8601, unix_seconds_now, RunClock};
+use crate::dimension::Timestamp;
+use crate::gui::download::save_file;
+use crate::gui::runs::Run;
+
+const SVG_WIDTH: f32 = 600.0;
+const SVG_HEIGHT: f32 = 200.0;
+const SVG_COLORS: [&str; 4] = ["#d4a017", "#4caf50", "#e57373", "#64b5f6"];
+
+/// One entry in the session log: the parameters a protocol ran with and
+/// the traces it produced, stamped with both the simulation time and the
+/// wall-clock time at which it was recorded.
+#[derive(Clone, Debug)]
+pub struct SessionLogEntry {
+    pub title: String,
+    pub simulation_time: Timestamp,
+    pub recorded_at_unix_seconds: f64,
+    pub parameters: Vec<(String, String)>,
+    pub traces: Vec<Trace>,
+}
+
+impl SessionLogEntry {
+    pub fn new(title: String, simulation_time: Timestamp, parameters: Vec<(String, String)>, traces: Vec<Trace>) -> Self {
+        SessionLogEntry {
+            title,
+            simulation_time,
+            recorded_at_unix_seconds: unix_seconds_now(),
+            parameters,
+            traces,
+        }
+    }
+
+    fn timestamps(&self, clock: &RunClock) -> Vec<(String, String)> {
+        vec![
+            ("Simulation time".to_string(), format!("{:.3} ms", self.simulation_time.0 * 1000.0)),
+            ("Wall clock".to_string(), format!(
+                "{} (run +{:.3} s)",
+                iso8601(self.recorded_at_unix_seconds),
+                self.recorded_at_unix_seconds - clock.started_at_unix_seconds
+            )),
+        ]
+    }
+}
+
+/// A running lab notebook. Each executed protocol appends an entry, and
+/// the whole log can be exported as Markdown or HTML.
+#[derive(Default, Resource)]
+pub struct SessionLog {
+    pub entries: Vec<SessionLogEntry>,
+    pub export_error: Option<String>,
+}
+
+impl SessionLog {
+    pub fn push(&mut self, entry: SessionLogEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn record_run(&mut self, run: &Run) {
+        let mut entry = SessionLogEntry::new(
+            run.name.clone(),
+            run.simulation_time.clone(),
+            vec![
+                ("Step size".to_string(), format!("{:.2} us", run.simulation_step.0 * 1e6)),
+                ("Steps per frame".to_string(), run.steps_per_frame.0.to_string()),
+            ],
+            run.traces.clone(),
+        );
+        entry.recorded_at_unix_seconds = run.saved_at_unix_seconds;
+        self.push(entry);
+    }
+
+    pub fn to_markdown(&self, clock: &RunClock) -> String {
+        let mut out = "# NeuronBench session log\n".to_string();
+        out.push_str(&format!("\nRun started {}\n", iso8601(clock.started_at_unix_seconds)));
+        for entry in self.entries.iter() {
+            out.push_str(&format!("\n## {}\n\n", entry.title));
+            out.push_str("| Parameter | Value |\n|---|---|\n");
+            for (name, value) in entry.timestamps(clock).iter().chain(entry.parameters.iter()) {
+                out.push_str(&format!("| {} | {} |\n", name, value));
+            }
+            if !entry.traces.is_empty() {
+                out.push_str("\n| Trace | Min | Max | Mean | Spikes |\n|---|---|---|---|---|\n");
+                for trace in entry.traces.iter() {
+                    if let Some(m) = trace.metrics() {
+                        out.push_str(&format!(
+                            "| {} | {:.1} | {:.1} | {:.1} | {} |\n",
+                            trace.label, m.min, m.max, m.mean, m.spike_count
+                        ));
+                    }
+                }
+                out.push('\n');
+                out.push_str(&svg_plot(&entry.traces));
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    pub fn to_html(&self, clock: &RunClock) -> String {
+        let mut out = "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>NeuronBench session log</title></head>\n<body>\n<h1>NeuronBench session log</h1>\n".to_string();
+        out.push_str(&format!("<p>Run started {}</p>\n", iso8601(clock.started_at_unix_seconds)));
+        for entry in self.entries.iter() {
+            out.push_str(&format!("<h2>{}</h2>\n<table>\n", escape_html(&entry.title)));
+            for (name, value) in entry.timestamps(clock).iter().chain(entry.parameters.iter()) {
+                out.push_str(&format!(
+                    "<tr><th>{}</th><td>{}</td></tr>\n",
+                    escape_html(name),
+                    escape_html(value)
+                ));
+            }
+            out.push_str("</table>\n");
+            if !entry.traces.is_empty() {
+                out.push_str("<table>\n<tr><th>Trace</th><th>Min</th><th>Max</th><th>Mean</th><th>Spikes</th></tr>\n");
+                for trace in entry.traces.iter() {
+                    if let Some(m) = trace.metrics() {
+                        out.push_str(&format!(
+                            "<tr><td>{}</td><td>{:.1}</td><td>{:.1}</td><td>{:.1}</td><td>{}</td></tr>\n",
+                            escape_html(&trace.label), m.min, m.max, m.mean, m.spike_count
+                        ));
+                    }
+                }
+                out.push_str("</table>\n");
+                out.push_str(&svg_plot(&entry.traces));
+            }
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    pub fn widget(&mut self, ui: &mut Ui, clock: &RunClock) {
+        ui.horizontal(|ui| {
+            if ui.button("Export Markdown").clicked() {
+                let contents = self.to_markdown(clock);
+                self.export_error = save_file("session_log.md", "text/markdown", contents.as_bytes()).err();
+            }
+            if ui.button("Export HTML").clicked() {
+                let contents = self.to_html(clock);
+                self.export_error = save_file("session_log.html", "text/html", contents.as_bytes()).err();
+            }
+            if ui.button("Clear").clicked() {
+                self.entries.clear();
+            }
+        });
+        if let Some(e) = &self.export_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+        }
+
+        if self.entries.is_empty() {
+            ui.label("Saved runs and protocol results will appear here.");
+        }
+        for (i, entry) in self.entries.iter().enumerate() {
+            egui::CollapsingHeader::new(entry.title.as_str())
+                .id_source(("session_log_entry", i))
+                .show(ui, |ui| {
+                    egui::Grid::new(("session_log_parameters", i)).show(ui, |ui| {
+                        for (name, value) in entry.timestamps(clock).iter().chain(entry.parameters.iter()) {
+                            ui.label(name.as_str());
+                            ui.label(value.as_str());
+                            ui.end_row();
+                        }
+                    });
+                    Plot::new(("session_log_plot", i))
+                        .view_aspect(3.0)
+                        .show(ui, |plot_ui| {
+                            for trace in entry.traces.iter() {
+                                plot_ui.line(Line::new(trace.points()).name(trace.label.as_str()));
+                            }
+                        });
+                });
+        }
+    }
+}
+
+/// Render traces as a standalone SVG line plot, so that exported logs
+/// carry their figures inline.
+fn svg_plot(traces: &[Trace]) -> String {
+    let all_times = traces.iter().flat_map(|t| t.times.iter().cloned());
+    let all_values = traces.iter().flat_map(|t| t.values.iter().cloned());
+    let (t_min, t_max) = all_times.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), t| (lo.min(t), hi.max(t)));
+    let (v_min, v_max) = all_values.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let t_span = (t_max - t_min).max(f32::EPSILON);
+    let v_span = (v_max - v_min).max(f32::EPSILON);
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = SVG_WIDTH,
+        h = SVG_HEIGHT
+    );
+    out.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"#1b1b1b\"/>\n", SVG_WIDTH, SVG_HEIGHT));
+    for (i, trace) in traces.iter().enumerate() {
+        let points = trace
+            .times
+            .iter()
+            .zip(trace.values.iter())
+            .map(|(t, v)| {
+                let x = (t - t_min) / t_span * SVG_WIDTH;
+                let y = SVG_HEIGHT - (v - v_min) / v_span * SVG_HEIGHT;
+                format!("{:.1},{:.1}", x, y)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        out.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1\" points=\"{}\"/>\n",
+            SVG_COLORS[i % SVG_COLORS.len()],
+            points
+        ));
+    }
+    out.push_str(&format!(
+        "<text x=\"4\" y=\"12\" fill=\"#cccccc\" font-size=\"10\">{:.1}</text>\n<text x=\"4\" y=\"{}\" fill=\"#cccccc\" font-size=\"10\">{:.1}</text>\n",
+        v_max,
+        SVG_HEIGHT - 4.0,
+        v_min
+    ));
+    out.push_str("</svg>\n");
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
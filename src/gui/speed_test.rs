@@ -0,0 +1,124 @@
+use bevy::ecs::system::System;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::background::BackgroundSimulation;
+use crate::clock::unix_seconds_now;
+use crate::dimension::{StepCount, StepsPerFrame, Timestamp};
+use crate::plugin::step_biophysics;
+
+/// Wall-clock seconds to run the test for.
+const TEST_SECONDS: f64 = 3.0;
+
+#[derive(Clone, Debug)]
+pub struct SpeedTestResult {
+    pub steps: u64,
+    pub wall_seconds: f64,
+    pub simulated_seconds: f32,
+}
+
+impl SpeedTestResult {
+    pub fn steps_per_second(&self) -> f64 {
+        self.steps as f64 / self.wall_seconds.max(f64::EPSILON)
+    }
+
+    /// Simulated seconds per wall-clock second.
+    pub fn realtime_ratio(&self) -> f64 {
+        self.simulated_seconds as f64 / self.wall_seconds.max(f64::EPSILON)
+    }
+}
+
+#[derive(Default, Resource)]
+pub struct SpeedTest {
+    requested: bool,
+    pub result: Option<SpeedTestResult>,
+}
+
+/// Run the integration step flat out, with no rendering or pacing, for
+/// `TEST_SECONDS`. The app is unresponsive meanwhile, and the run moves
+/// on by however much time was simulated.
+pub fn run_speed_test(world: &mut World) {
+    if !world.resource::<SpeedTest>().requested {
+        return;
+    }
+    world.resource_mut::<SpeedTest>().requested = false;
+
+    let mut step = IntoSystem::into_system(step_biophysics);
+    step.initialize(world);
+    let steps_before = world.resource::<StepCount>().0;
+    let time_before = world.resource::<Timestamp>().0;
+    let started = unix_seconds_now();
+    let mut wall_seconds = 0.0;
+    while wall_seconds < TEST_SECONDS {
+        step.run((), world);
+        wall_seconds = unix_seconds_now() - started;
+    }
+    let result = SpeedTestResult {
+        steps: world.resource::<StepCount>().0 - steps_before,
+        wall_seconds,
+        simulated_seconds: world.resource::<Timestamp>().0 - time_before,
+    };
+    world.resource_mut::<SpeedTest>().result = Some(result);
+}
+
+pub fn speed_test_gui(
+    mut contexts: EguiContexts,
+    mut speed_test: ResMut<SpeedTest>,
+    mut background: ResMut<BackgroundSimulation>,
+    steps_per_frame: Res<StepsPerFrame>,
+) {
+    egui::Window::new("Speed test")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!(
+                "Runs the current scene as fast as possible for {} s, without rendering. The run advances meanwhile.",
+                TEST_SECONDS,
+            ));
+            // The worker has the scene while it runs.
+            if ui.add_enabled(!background.running(), egui::Button::new("Run")).clicked() {
+                speed_test.requested = true;
+            }
+            background_widget(ui, &mut background);
+            let Some(result) = &speed_test.result else {
+                return;
+            };
+            egui::Grid::new("speed_test").show(ui, |ui| {
+                ui.label("Steps per second");
+                ui.label(format!("{:.0}", result.steps_per_second()));
+                ui.end_row();
+                ui.label("Real time");
+                ui.label(format!("{:.4}x", result.realtime_ratio()));
+                ui.end_row();
+                ui.label("At 60 frames per second");
+                ui.label(format!("at most {:.0} steps per frame", result.steps_per_second() / 60.0));
+                ui.end_row();
+            });
+            if (steps_per_frame.0 as f64) * 60.0 > result.steps_per_second() {
+                ui.colored_label(
+                    egui::Color32::GOLD,
+                    format!("{} steps per frame will not keep up with 60 frames per second.", steps_per_frame.0),
+                );
+            }
+        });
+}
+
+/// Integrate on a thread of its own, as fast as it will go; see
+/// `background`.
+fn background_widget(ui: &mut egui::Ui, background: &mut BackgroundSimulation) {
+    ui.separator();
+    ui.add_enabled(
+        BackgroundSimulation::available(),
+        egui::Checkbox::new(&mut background.enabled, "Integrate on a background thread"),
+    )
+    .on_hover_text("Steps as fast as possible rather than a fixed number per frame. Edits pause it for a frame.")
+    .on_disabled_hover_text("On the web, needs a build with the wasm-threads feature on a cross-origin isolated page.");
+    if background.running() {
+        if let Some(steps_per_second) = background.steps_per_second {
+            ui.label(format!("{:.0} steps per second", steps_per_second));
+        }
+    }
+    if let Some(error) = &background.error {
+        ui.colored_label(egui::Color32::LIGHT_RED, error);
+    }
+    ui.separator();
+}
@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::dimension::{Interval, MicroAmpsPerSquareCm};
+use crate::integrations::grace::Synapse;
+use crate::selection::Selection;
+use crate::spike_train::{parse, Drive, SpikeTrainInput, SpikeTrains, TimeUnit, TrainLayout};
+
+#[derive(Resource)]
+pub struct SpikeTrainReplay {
+    pub path: String,
+    pub layout: TrainLayout,
+    pub time_unit: TimeUnit,
+    pub trains: Option<SpikeTrains>,
+    /// The unit applied to selected segments.
+    pub unit: usize,
+    pub pulse_current: MicroAmpsPerSquareCm,
+    pub pulse_duration: Interval,
+    pub error: Option<String>,
+}
+
+impl Default for SpikeTrainReplay {
+    fn default() -> Self {
+        SpikeTrainReplay {
+            path: "spikes.csv".to_string(),
+            layout: TrainLayout::Rows,
+            time_unit: TimeUnit::Milliseconds,
+            trains: None,
+            unit: 0,
+            pulse_current: MicroAmpsPerSquareCm(100.0),
+            pulse_duration: Interval(0.001),
+            error: None,
+        }
+    }
+}
+
+pub fn spike_train_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut replay: ResMut<SpikeTrainReplay>,
+    selected: Query<Entity, With<Selection>>,
+    synapses: Query<Entity, With<Synapse>>,
+    driven: Query<Entity, With<SpikeTrainInput>>,
+) {
+    egui::Window::new("Spike train replay")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let replay = &mut *replay;
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut replay.path);
+            });
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut replay.layout, TrainLayout::Rows, "Unit per row");
+                ui.selectable_value(&mut replay.layout, TrainLayout::Columns, "Unit per column");
+            });
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut replay.time_unit, TimeUnit::Milliseconds, "ms");
+                ui.selectable_value(&mut replay.time_unit, TimeUnit::Seconds, "s");
+            });
+            if ui.button("Load").clicked() {
+                let loaded = std::fs::read_to_string(&replay.path)
+                    .map_err(|e| format!("Failed to read {}: {}", replay.path, e))
+                    .and_then(|source| parse(&source, replay.layout, replay.time_unit));
+                match loaded {
+                    Ok(trains) => {
+                        replay.trains = Some(trains);
+                        replay.unit = 0;
+                        replay.error = None;
+                    },
+                    Err(e) => replay.error = Some(e),
+                }
+            }
+            if let Some(e) = &replay.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, e.as_str());
+            }
+
+            let duration_ms = |ui: &mut egui::Ui, duration: &mut Interval| {
+                ui.add(egui::Slider::from_get_set(0.1..=10.0, |v: Option<f64>| {
+                    if let Some(v) = v {
+                        duration.0 = v as f32 * 0.001;
+                    }
+                    (duration.0 * 1000.0) as f64
+                }).suffix(" ms").text("Pulse"));
+            };
+
+            let Some(trains) = &replay.trains else {
+                return;
+            };
+            let n_units = trains.units.len();
+            let n_spikes = trains.units.iter().map(|u| u.len()).sum::<usize>();
+            ui.label(format!("{} units, {} spikes.", n_units, n_spikes));
+            if n_units == 0 {
+                return;
+            }
+
+            ui.separator();
+            ui.add(egui::Slider::new(&mut replay.unit, 0..=n_units - 1).text("Unit"));
+            ui.add(egui::DragValue::new(&mut replay.pulse_current.0).speed(1.0).suffix(" uA/cm^2"));
+            duration_ms(ui, &mut replay.pulse_duration);
+            if ui.button("Drive selected segments").clicked() {
+                for entity in &selected {
+                    commands.entity(entity).insert(SpikeTrainInput {
+                        times: trains.units[replay.unit].clone(),
+                        drive: Drive::Pulse { current: replay.pulse_current.clone(), duration: replay.pulse_duration.clone() },
+                    });
+                }
+            }
+            if ui.button("Drive synapses, one unit each").clicked() {
+                for (entity, times) in synapses.iter().zip(trains.units.iter()) {
+                    commands.entity(entity).insert(SpikeTrainInput {
+                        times: times.clone(),
+                        drive: Drive::Presynaptic { duration: replay.pulse_duration.clone() },
+                    });
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.label(format!("{} inputs replaying.", driven.iter().count()));
+                if ui.button("Clear").clicked() {
+                    for entity in &driven {
+                        commands.entity(entity).remove::<SpikeTrainInput>();
+                    }
+                }
+            });
+        });
+}
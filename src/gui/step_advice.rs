@@ -0,0 +1,65 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::dimension::SimulationStepSeconds;
+use crate::neuron::membrane::Membrane;
+use crate::neuron::multirate::IntegrationRate;
+use crate::step_advisor::{advise, Advice, Compartment};
+
+/// Wall-clock seconds between re-analyses of the scene.
+const REFRESH_SECONDS: f32 = 2.0;
+
+#[derive(Default, Resource)]
+pub struct StepAdvice {
+    pub advice: Option<Advice>,
+    since_refresh: Option<f32>,
+}
+
+pub fn advise_step(
+    time: Res<Time>,
+    mut step_advice: ResMut<StepAdvice>,
+    segments: Query<(&Membrane, Option<&IntegrationRate>)>,
+) {
+    let elapsed = step_advice.since_refresh.map_or(REFRESH_SECONDS, |t| t + time.delta_seconds());
+    if elapsed < REFRESH_SECONDS {
+        step_advice.since_refresh = Some(elapsed);
+        return;
+    }
+    step_advice.since_refresh = Some(0.0);
+
+    step_advice.advice = advise(segments.iter().map(|(membrane, rate)| Compartment {
+        membrane,
+        step_multiple: rate.map_or(1, |r| r.every()),
+    }));
+}
+
+pub fn step_advice_gui(
+    mut contexts: EguiContexts,
+    step_advice: Res<StepAdvice>,
+    mut simulation_step: ResMut<SimulationStepSeconds>,
+) {
+    let Some(advice) = step_advice.advice else {
+        return;
+    };
+    if simulation_step.0 <= advice.max_step {
+        return;
+    }
+    egui::Window::new("Simulation step too long")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.colored_label(
+                egui::Color32::GOLD,
+                format!(
+                    "The step, {:.3} µs, is longer than the {:.3} µs this scene is stable at, limited by {}.",
+                    simulation_step.0 * 1e6,
+                    advice.max_step * 1e6,
+                    advice.limit,
+                ),
+            );
+            if ui.button(format!("Use {:.3} µs", advice.max_step * 1e6)).clicked() {
+                simulation_step.0 = advice.max_step;
+            }
+        });
+}
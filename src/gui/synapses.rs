@@ -0,0 +1,200 @@
+//! Connecting segments with synapses from the GUI, and drawing the
+//! synapses in the scene.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::command::Command;
+use crate::dimension::{AreaSquareMillimeters, Molar};
+use crate::integrations::grace::{SceneSegment, Synapse, INITIAL_VOLTAGE};
+use crate::neuron::segment::ecs::Segment;
+use crate::neuron::synapse::{examples, SynapseMembranes, Transmitter};
+use crate::selection::Selection;
+use crate::serialize::SegmentRef;
+
+/// The synapse "Connect" adds, and the segments it goes between.
+#[derive(Resource)]
+pub struct SynapseEditor {
+    pub pre: Option<SegmentRef>,
+    pub post: Option<SegmentRef>,
+    pub transmitter: Transmitter,
+    pub ampa: bool,
+    pub nmda: bool,
+    /// Cleft concentration at which the receptors are half open.
+    pub concentration_at_half_max: Molar,
+    /// Steepness of the receptors' response (1/M).
+    pub slope: f32,
+    pub surface_area_square_microns: f32,
+    /// Whether synapses are drawn as lines from pre- to postsynaptic
+    /// segment.
+    pub shown: bool,
+}
+
+impl Default for SynapseEditor {
+    fn default() -> Self {
+        SynapseEditor {
+            pre: None,
+            post: None,
+            transmitter: Transmitter::Glutamate,
+            ampa: true,
+            nmda: false,
+            concentration_at_half_max: Molar(3e-3),
+            slope: 10000.0,
+            surface_area_square_microns: 1.0,
+            shown: true,
+        }
+    }
+}
+
+impl SynapseEditor {
+    /// A synapse releasing the chosen transmitter onto the chosen
+    /// receptors, all tuned alike, from the `examples` templates.
+    pub fn synapse_membranes(&self) -> SynapseMembranes {
+        let v = INITIAL_VOLTAGE;
+        let mut synapse = examples::excitatory_synapse(&v);
+        synapse.presynaptic_pumps = vec![match self.transmitter {
+            Transmitter::Glutamate => examples::glutamate_release(),
+            Transmitter::Gaba => examples::gaba_release(),
+        }];
+        synapse.postsynaptic_receptors = [
+            self.ampa.then(|| examples::ampa_receptor(&v)),
+            self.nmda.then(|| examples::nmda_receptor(&v)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|mut receptor| {
+            let sensitivity = &mut receptor.neurotransmitter_sensitivity;
+            sensitivity.transmitter = self.transmitter;
+            sensitivity.concentration_at_half_max = self.concentration_at_half_max.clone();
+            sensitivity.slope = self.slope;
+            receptor
+        })
+        .collect();
+        synapse.surface_area = AreaSquareMillimeters(self.surface_area_square_microns * 1e-6);
+        synapse
+    }
+}
+
+fn segment_label(at: &Option<SegmentRef>) -> String {
+    at.map_or("none".to_string(), |at| format!("neuron {}, segment {}", at.neuron + 1, at.segment))
+}
+
+/// Connect the segments chosen through the selection, and list the
+/// synapses for removal.
+pub fn synapses_gui(
+    mut contexts: EguiContexts,
+    mut editor: ResMut<SynapseEditor>,
+    selected: Query<&SceneSegment, With<Selection>>,
+    scene_segments: Query<&SceneSegment>,
+    synapses: Query<&Synapse>,
+    mut events: EventWriter<Command>,
+) {
+    egui::Window::new("Synapses")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let editor = &mut *editor;
+            let selection = selected.iter().next().map(|SceneSegment(at)| *at);
+            egui::Grid::new("synapse_ends").show(ui, |ui| {
+                for (name, end) in [("Presynaptic", &mut editor.pre), ("Postsynaptic", &mut editor.post)] {
+                    ui.label(name);
+                    ui.label(segment_label(end));
+                    if ui.add_enabled(selection.is_some(), egui::Button::new("Use selected")).clicked() {
+                        *end = selection;
+                    }
+                    ui.end_row();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Releases");
+                ui.selectable_value(&mut editor.transmitter, Transmitter::Glutamate, "Glutamate");
+                ui.selectable_value(&mut editor.transmitter, Transmitter::Gaba, "GABA");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Receptors");
+                ui.checkbox(&mut editor.ampa, "AMPA");
+                ui.checkbox(&mut editor.nmda, "NMDA");
+            });
+            ui.horizontal(|ui| {
+                let mut half_max_mm = editor.concentration_at_half_max.0 * 1e3;
+                if ui.add(egui::DragValue::new(&mut half_max_mm).clamp_range(0.001..=100.0).speed(0.01).prefix("half open at ").suffix(" mM")).changed() {
+                    editor.concentration_at_half_max.0 = half_max_mm * 1e-3;
+                }
+                ui.add(egui::DragValue::new(&mut editor.slope).clamp_range(1.0..=1e6).speed(10.0).prefix("slope ").suffix(" /M"));
+            });
+            ui.add(egui::DragValue::new(&mut editor.surface_area_square_microns).clamp_range(0.01..=1000.0).speed(0.01).prefix("Area ").suffix(" µm²"));
+
+            let connectable = editor.pre.zip(editor.post).filter(|_| editor.ampa || editor.nmda);
+            if ui.add_enabled(connectable.is_some(), egui::Button::new("Connect")).clicked() {
+                if let Some((pre, post)) = connectable {
+                    events.send(Command::AddSynapse { pre, post, synapse_membranes: editor.synapse_membranes().serialize() });
+                }
+            }
+
+            ui.separator();
+            ui.checkbox(&mut editor.shown, "Show synapses in the scene");
+            if synapses.is_empty() {
+                ui.weak("No synapses");
+            }
+            egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                for synapse in &synapses {
+                    let (Ok(SceneSegment(pre)), Ok(SceneSegment(post))) =
+                        (scene_segments.get(synapse.pre_segment), scene_segments.get(synapse.post_segment)) else { continue };
+                    let membranes = &synapse.synapse_membranes;
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} → {}: {} receptors, {:.2} µm²",
+                            segment_label(&Some(*pre)),
+                            segment_label(&Some(*post)),
+                            membranes.postsynaptic_receptors.len(),
+                            membranes.surface_area.0 * 1e6,
+                        ));
+                        if ui.small_button("Delete").on_hover_text("Removes every synapse between these segments").clicked() {
+                            events.send(Command::RemoveSynapse { pre: *pre, post: *post });
+                        }
+                    });
+                }
+            });
+        });
+}
+
+/// Draw each synapse as a line from its presynaptic segment to its
+/// postsynaptic one: green for glutamate, red for GABA.
+pub fn draw_synapses(
+    mut gizmos: Gizmos,
+    editor: Res<SynapseEditor>,
+    synapses: Query<&Synapse>,
+    segments: Query<&GlobalTransform, With<Segment>>,
+) {
+    if !editor.shown {
+        return;
+    }
+    for synapse in &synapses {
+        let (Ok(pre), Ok(post)) = (segments.get(synapse.pre_segment), segments.get(synapse.post_segment)) else { continue };
+        let inhibitory = synapse
+            .synapse_membranes
+            .presynaptic_pumps
+            .iter()
+            .any(|pump| pump.transmitter == Transmitter::Gaba);
+        let color = if inhibitory { Color::rgb(0.9, 0.3, 0.3) } else { Color::rgb(0.3, 0.9, 0.4) };
+        gizmos.line(pre.translation(), post.translation(), color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_synapses_survive_serialization() {
+        let editor = SynapseEditor { transmitter: Transmitter::Gaba, nmda: true, surface_area_square_microns: 2.0, ..default() };
+        let synapse = SynapseMembranes::deserialize(&editor.synapse_membranes().serialize()).unwrap();
+        assert_eq!(synapse.postsynaptic_receptors.len(), 2);
+        assert!(synapse
+            .postsynaptic_receptors
+            .iter()
+            .all(|r| r.neurotransmitter_sensitivity.transmitter == Transmitter::Gaba));
+        assert_eq!(synapse.presynaptic_pumps[0].transmitter, Transmitter::Gaba);
+        assert!((synapse.surface_area.0 - 2e-6).abs() < 1e-12);
+    }
+}
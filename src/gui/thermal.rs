@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::dimension::{Interval, Timestamp};
+use crate::neuron::segment::ecs::Segment;
+use crate::selection::Selection;
+use crate::thermal::{q10_factor, ThermalStimulator};
+
+/// The stimulus applied by "Heat selected segments".
+#[derive(Default, Resource)]
+pub struct ThermalSettings(pub ThermalStimulator);
+
+fn milliseconds_setting(ui: &mut egui::Ui, label: &str, interval: &mut Interval) {
+    let mut ms = interval.0 * 1000.0;
+    if ui.add(egui::DragValue::new(&mut ms).clamp_range(0.0..=10000.0).prefix(label).suffix(" ms")).changed() {
+        interval.0 = ms / 1000.0;
+    }
+}
+
+/// Heat the selected segments, as an infrared laser would.
+pub fn thermal_gui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut settings: ResMut<ThermalSettings>,
+    timestamp: Res<Timestamp>,
+    selected: Query<(Entity, Option<&ThermalStimulator>), (With<Selection>, With<Segment>)>,
+    heated: Query<&ThermalStimulator>,
+) {
+    egui::Window::new("Thermal stimulation")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let ThermalStimulator { envelope, rise } = &mut settings.0;
+            ui.add(egui::Slider::new(&mut rise.0, 0.0..=20.0).text("Rise (K)"));
+            ui.horizontal(|ui| {
+                milliseconds_setting(ui, "onset ", &mut envelope.onset);
+                milliseconds_setting(ui, "offset ", &mut envelope.offset);
+                milliseconds_setting(ui, "period ", &mut envelope.period);
+            });
+            ui.label(format!("Gating runs {:.2}x faster while heated", q10_factor(rise.0)));
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!selected.is_empty(), egui::Button::new("Heat selected segments")).clicked() {
+                    for (entity, _) in &selected {
+                        commands.entity(entity).insert(settings.0.clone());
+                    }
+                }
+                if ui.add_enabled(selected.iter().any(|(_, t)| t.is_some()), egui::Button::new("Stop heating")).clicked() {
+                    for (entity, _) in &selected {
+                        commands.entity(entity).remove::<ThermalStimulator>();
+                    }
+                }
+            });
+
+            let hottest = heated.iter().map(|t| t.rise_at(&timestamp)).fold(0.0, f32::max);
+            ui.label(format!("{} segments heated, now up to {:.1} K", heated.iter().count(), hottest));
+        });
+}
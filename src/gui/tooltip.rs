@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::integrations::grace::{path_to_soma_microns, swc_type_name, CurrentScene, HoveredSegment, SceneSegment};
+use crate::neuron::membrane::MembraneVoltage;
+use crate::neuron::segment::Geometry;
+use crate::units::UnitScale;
+
+/// Describe the segment under the pointer: its SWC id and type, voltage,
+/// size, and distance from the soma along the neurite.
+pub fn segment_tooltip(
+    mut contexts: EguiContexts,
+    hovered: Res<HoveredSegment>,
+    current_scene: Res<CurrentScene>,
+    segments: Query<(&SceneSegment, &MembraneVoltage, &Geometry)>,
+) {
+    let Some((SceneSegment(at), voltage, geometry)) = hovered.0.and_then(|entity| segments.get(entity).ok()) else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    // Windows drawn over the segment hide it.
+    if ctx.is_pointer_over_area() {
+        return;
+    }
+    let neuron = current_scene.0.neurons.get(at.neuron).map(|n| &n.neuron);
+    let swc = neuron.and_then(|neuron| neuron.segments.iter().find(|s| s.id == at.segment));
+    egui::show_tooltip_at_pointer(ctx, egui::Id::new("segment_tooltip"), |ui| {
+        ui.strong(format!("Neuron {}, SWC id {}", at.neuron + 1, at.segment));
+        if let Some(swc) = swc {
+            ui.label(swc_type_name(swc.type_));
+        }
+        ui.label(format!("{:.2} mV", voltage.0 .0));
+        let (_, end_diameter) = geometry.end_diameters();
+        ui.label(format!(
+            "Radius {:.2} µm, length {:.2} µm",
+            UnitScale::cm_to_microns(end_diameter * 0.5),
+            UnitScale::cm_to_microns(geometry.length()),
+        ));
+        if let Some(distance) = neuron.and_then(|neuron| path_to_soma_microns(neuron, at.segment)) {
+            ui.label(format!("{:.1} µm from the soma", distance));
+        }
+    });
+}
@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::camera::{CameraCommand, STANDARD_VIEWS};
+use crate::lod::LodSettings;
+
+/// Switch the camera's projection, look along the axes or at the whole
+/// scene, and set how soon segments are drawn coarse.
+pub fn view_gui(
+    mut contexts: EguiContexts,
+    mut lod: ResMut<LodSettings>,
+    cameras: Query<&Projection, With<PanOrbitCamera>>,
+    mut camera_commands: EventWriter<CameraCommand>,
+) {
+    egui::Window::new("View")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let mut orthographic = cameras.iter().any(|projection| matches!(projection, Projection::Orthographic(_)));
+            if ui.checkbox(&mut orthographic, "Orthographic").changed() {
+                camera_commands.send(CameraCommand::SetOrthographic { orthographic });
+            }
+            ui.horizontal(|ui| {
+                for (name, yaw, pitch) in STANDARD_VIEWS {
+                    if ui.button(name).on_hover_text(format!("Look at the {} plane", name)).clicked() {
+                        camera_commands.send(CameraCommand::SetAngles { yaw, pitch });
+                    }
+                }
+                if ui.button("Fit all").clicked() {
+                    camera_commands.send(CameraCommand::FitAll);
+                }
+                if ui.button("Frame selection").clicked() {
+                    camera_commands.send(CameraCommand::FrameSelection);
+                }
+            });
+            ui.separator();
+            ui.checkbox(&mut lod.enabled, "Coarse meshes for distant segments");
+            ui.add_enabled(
+                lod.enabled,
+                egui::Slider::new(&mut lod.coarse_beyond_radii, 10.0..=5000.0).logarithmic(true).text("radii away"),
+            );
+        });
+}
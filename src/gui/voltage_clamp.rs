@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+use bevy_egui::{egui::{self, Ui}, EguiContexts};
+use egui_plot::{Legend, Line, Plot, Points};
+
+use crate::dimension::{Interval, MilliVolts, Timestamp};
+use crate::gui::cursors::Cursors;
+use crate::gui::session_log::{SessionLog, SessionLogEntry};
+use crate::neuron::channel::common_channels;
+use crate::neuron::clamp::{ClampResult, VoltageClampProtocol};
+use crate::neuron::solution::EXAMPLE_CYTOPLASM;
+use crate::constants::SimulationConstants;
+use crate::plugin::Env;
+
+#[derive(Default, Resource)]
+pub struct VoltageClamp {
+    pub channel_index: usize,
+    pub protocol: VoltageClampProtocol,
+    pub result: Option<ClampResult>,
+    pub cursors: Cursors,
+}
+
+pub fn voltage_clamp_gui(
+    mut contexts: EguiContexts,
+    env: Res<Env>,
+    constants: Res<SimulationConstants>,
+    timestamp: Res<Timestamp>,
+    mut clamp: ResMut<VoltageClamp>,
+    mut session_log: ResMut<SessionLog>,
+) {
+    egui::Window::new("Voltage clamp")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let channels = common_channels::library();
+            let clamp = &mut *clamp;
+            egui::ComboBox::from_label("Channel")
+                .selected_text(channels[clamp.channel_index].0)
+                .show_ui(ui, |ui| {
+                    for (i, (name, _, _)) in channels.iter().enumerate() {
+                        ui.selectable_value(&mut clamp.channel_index, i, *name);
+                    }
+                });
+            protocol_settings(ui, &mut clamp.protocol);
+
+            if ui.button("Run").clicked() {
+                let (name, builder, siemens_per_square_cm) = &channels[clamp.channel_index];
+                let result = clamp.protocol.run(
+                    builder,
+                    *siemens_per_square_cm,
+                    &EXAMPLE_CYTOPLASM,
+                    &env.extracellular_solution,
+                    &constants.temperature,
+                );
+                session_log.push(log_entry(name, timestamp.clone(), &clamp.protocol, &result));
+                clamp.result = Some(result);
+            }
+
+            if let Some(result) = &clamp.result {
+                result_plots(ui, result, &mut clamp.cursors);
+            }
+        });
+}
+
+fn protocol_settings(ui: &mut Ui, protocol: &mut VoltageClampProtocol) {
+    millivolts_setting(ui, "Holding", &mut protocol.holding_potential);
+    millivolts_setting(ui, "First step", &mut protocol.first_step);
+    millivolts_setting(ui, "Last step", &mut protocol.last_step);
+    millivolts_setting(ui, "Increment", &mut protocol.step_increment);
+    protocol.step_increment.0 = protocol.step_increment.0.max(1.0);
+    milliseconds_setting(ui, "Step duration", &mut protocol.step_duration);
+    milliseconds_setting(ui, "Time step", &mut protocol.simulation_step);
+}
+
+fn millivolts_setting(ui: &mut Ui, label: &str, v: &mut MilliVolts) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(egui::DragValue::new(&mut v.0).suffix(" mV").speed(1.0));
+    });
+}
+
+fn milliseconds_setting(ui: &mut Ui, label: &str, interval: &mut Interval) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(egui::Slider::from_get_set(0.001..=100.0, |v: Option<f64>| {
+            if let Some(v) = v {
+                interval.0 = v as f32 * 0.001;
+            }
+            (interval.0 * 1000.0) as f64
+        }).logarithmic(true).suffix(" ms"));
+    });
+}
+
+fn result_plots(ui: &mut Ui, result: &ClampResult, cursors: &mut Cursors) {
+    ui.label("Clamp currents (uA/cm^2)");
+    Plot::new("voltage_clamp_currents")
+        .view_aspect(2.0)
+        .allow_drag(!cursors.shown)
+        .show(ui, |plot_ui| {
+            for sweep in result.sweeps.iter() {
+                let points: Vec<[f64; 2]> = sweep
+                    .current
+                    .points()
+                    .into_iter()
+                    .map(|[t, i]| [t * 1000.0, i])
+                    .collect();
+                plot_ui.line(Line::new(points).name(sweep.current.label.as_str()));
+            }
+            cursors.show(plot_ui);
+        });
+    cursors.readout(ui, "ms", |t| t, "I", "uA/cm^2", |i| i);
+
+    ui.label("I-V (uA/cm^2 against mV)");
+    let to_points = |samples: Vec<(f32, f32)>| -> Vec<[f64; 2]> {
+        samples.into_iter().map(|(v, i)| [v as f64, i as f64]).collect()
+    };
+    Plot::new("voltage_clamp_iv")
+        .view_aspect(2.0)
+        .legend(Legend::default())
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(to_points(result.peak_iv())).name("Peak"));
+            plot_ui.line(Line::new(to_points(result.steady_state_iv())).name("Steady state"));
+        });
+
+    ui.label(format!("G-V (normalized), E_rev = {:.1} mV", result.reversal.0));
+    Plot::new("voltage_clamp_gv")
+        .view_aspect(2.0)
+        .legend(Legend::default())
+        .show(ui, |plot_ui| {
+            plot_ui.points(Points::new(to_points(result.conductance.clone())).radius(3.0).name("Peak G"));
+            if let Some(fit) = &result.fit {
+                let fitted: Vec<[f64; 2]> = result
+                    .conductance
+                    .iter()
+                    .map(|(v, _)| [*v as f64, fit.steady_state(&MilliVolts(*v)) as f64])
+                    .collect();
+                plot_ui.line(Line::new(fitted).name("Boltzmann fit"));
+            }
+        });
+    match &result.fit {
+        Some(fit) => ui.label(format!("V1/2 = {:.1} mV, slope = {:.1} mV", fit.v_at_half_max.0, fit.slope)),
+        None => ui.label("No Boltzmann fit (conductance is not voltage dependent)."),
+    };
+}
+
+fn log_entry(
+    channel_name: &str,
+    simulation_time: Timestamp,
+    protocol: &VoltageClampProtocol,
+    result: &ClampResult,
+) -> SessionLogEntry {
+    let mut parameters = vec![
+        ("Channel".to_string(), channel_name.to_string()),
+        ("Holding".to_string(), format!("{:.1} mV", protocol.holding_potential.0)),
+        ("Steps".to_string(), format!(
+            "{:.1} to {:.1} mV by {:.1} mV",
+            protocol.first_step.0, protocol.last_step.0, protocol.step_increment.0
+        )),
+        ("Step duration".to_string(), format!("{:.2} ms", protocol.step_duration.0 * 1000.0)),
+        ("Reversal".to_string(), format!("{:.1} mV", result.reversal.0)),
+    ];
+    if let Some(fit) = &result.fit {
+        parameters.push(("G-V V1/2".to_string(), format!("{:.1} mV", fit.v_at_half_max.0)));
+        parameters.push(("G-V slope".to_string(), format!("{:.1} mV", fit.slope)));
+    }
+    SessionLogEntry::new(
+        format!("Voltage clamp: {}", channel_name),
+        simulation_time,
+        parameters,
+        result.sweeps.iter().map(|s| s.current.clone()).collect(),
+    )
+}
@@ -0,0 +1,337 @@
+//! Watch expressions: define derived quantities (see `crate::watch`), plot
+//! them, record them to CSV, and pause the simulation when one rises
+//! through its threshold. Alarms on conditions over them can also pause
+//! the simulation, mark the session log, or call a webhook.
+
+use std::fs::File;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use egui_plot::{Legend, Line, Plot};
+
+use crate::command::Command;
+use crate::constants::SimulationConstants;
+use crate::dimension::Timestamp;
+use crate::gui::oscilloscope::Oscilloscope;
+use crate::gui::session_log::{SessionLog, SessionLogEntry};
+use crate::integrations::grace::{soma, CurrentScene, SceneSegment};
+use crate::notify::Notifications;
+use crate::neuron::extracellular_space::{local_solution, ExtracellularSpace};
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::solution::Solution;
+use crate::plugin::Env;
+use crate::recording::csv_recorder::CsvRecorder;
+use crate::selection::Selection;
+use crate::serialize::SegmentRef;
+use crate::watch::{Alarm, Ion, Location, Quantity, Watch};
+use crate::webhook;
+
+pub struct WatchEntry {
+    pub watch: Watch,
+    pub error: Option<String>,
+    /// Times (ms) the threshold was crossed.
+    pub fired: Vec<f32>,
+    recorder: Option<CsvRecorder<File>>,
+}
+
+pub struct AlarmEntry {
+    pub alarm: Alarm,
+    pub error: Option<String>,
+}
+
+#[derive(Default, Resource)]
+pub struct WatchList {
+    pub entries: Vec<WatchEntry>,
+    pub alarms: Vec<AlarmEntry>,
+    pub pause_on_trigger: bool,
+    new_name: String,
+    new_source: String,
+    new_alarm_name: String,
+    new_alarm_condition: String,
+    error: Option<String>,
+    /// Simulation time of the last sample (s).
+    last_sampled: Option<f32>,
+}
+
+impl WatchList {
+    pub fn add(&mut self, name: &str, source: &str) -> Result<(), String> {
+        let watch = Watch::new(name, source)?;
+        self.entries.push(WatchEntry { watch, error: None, fired: vec![], recorder: None });
+        Ok(())
+    }
+
+    pub fn add_alarm(&mut self, name: &str, condition: &str) -> Result<(), String> {
+        let names = self.entries.iter().map(|e| e.watch.name.clone()).collect::<Vec<_>>();
+        let alarm = Alarm::new(name, condition, &names)?;
+        self.alarms.push(AlarmEntry { alarm, error: None });
+        Ok(())
+    }
+}
+
+/// The concentration (M) of `ion` in `solution`.
+fn concentration(solution: &Solution, ion: Ion) -> f32 {
+    match ion {
+        Ion::K => solution.k_concentration.0,
+        Ion::Na => solution.na_concentration.0,
+        Ion::Ca => solution.ca_concentration.0,
+        Ion::Cl => solution.cl_concentration.0,
+    }
+}
+
+fn sample(
+    quantity: Quantity,
+    (voltage, membrane, solution, space): (&MembraneVoltage, &Membrane, &Solution, Option<&ExtracellularSpace>),
+    constants: &SimulationConstants,
+    env: &Env,
+) -> f32 {
+    let extracellular = local_solution(env, space);
+    let (k, na, cl, ca) = membrane.conductances();
+    match quantity {
+        Quantity::Voltage => voltage.0.0,
+        Quantity::Conductance(Ion::K) => k,
+        Quantity::Conductance(Ion::Na) => na,
+        Quantity::Conductance(Ion::Ca) => ca,
+        Quantity::Conductance(Ion::Cl) => cl,
+        Quantity::Reversal(Ion::K) => constants.k_reversal(solution, extracellular).0,
+        Quantity::Reversal(Ion::Na) => constants.na_reversal(solution, extracellular).0,
+        Quantity::Reversal(Ion::Ca) => constants.ca_reversal(solution, extracellular).0,
+        Quantity::Reversal(Ion::Cl) => constants.cl_reversal(solution, extracellular).0,
+        Quantity::Intracellular(ion) => concentration(solution, ion) * 1000.0,
+        Quantity::Extracellular(ion) => concentration(extracellular, ion) * 1000.0,
+    }
+}
+
+pub fn sample_watches(
+    timestamp: Res<Timestamp>,
+    env: Res<Env>,
+    constants: Res<SimulationConstants>,
+    oscilloscope: Res<Oscilloscope>,
+    current_scene: Res<CurrentScene>,
+    mut watch_list: ResMut<WatchList>,
+    mut session_log: ResMut<SessionLog>,
+    notifications: Res<Notifications>,
+    mut command_events: EventWriter<Command>,
+    scene_segments: Query<(Entity, &SceneSegment)>,
+    segments: Query<(&MembraneVoltage, &Membrane, &Solution, Option<&ExtracellularSpace>)>,
+    selected: Query<Entity, With<Selection>>,
+) {
+    if (watch_list.entries.is_empty() && watch_list.alarms.is_empty()) || watch_list.last_sampled == Some(timestamp.0) {
+        return;
+    }
+    watch_list.last_sampled = Some(timestamp.0);
+
+    let find_segment = |at: SegmentRef| scene_segments.iter().find(|(_, SceneSegment(s))| *s == at).map(|(e, _)| e);
+    let entity_at = |location: Location| match location {
+        Location::Probe(n) => oscilloscope.source(n - 1),
+        Location::Soma => {
+            let scene_neuron = current_scene.0.neurons.first()?;
+            find_segment(SegmentRef { neuron: 0, segment: soma(&scene_neuron.neuron)?.id })
+        },
+        Location::Selected => selected.iter().next(),
+        Location::Segment(at) => find_segment(at),
+    };
+
+    let sample_all = |watch: &Watch| {
+        watch
+            .samples()
+            .iter()
+            .map(|(quantity, location)| {
+                let segment = entity_at(*location).and_then(|e| segments.get(e).ok());
+                segment.map_or(f32::NAN, |s| sample(*quantity, s, &constants, &env))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let t_ms = timestamp.0 * 1000.0;
+    let watch_list = &mut *watch_list;
+    let mut pause = false;
+    for entry in &mut watch_list.entries {
+        let values = sample_all(&entry.watch);
+        match entry.watch.eval(t_ms, &values, &[]) {
+            Ok(value) => {
+                entry.error = None;
+                if entry.watch.push(t_ms, value) {
+                    entry.fired.push(t_ms);
+                    pause |= watch_list.pause_on_trigger;
+                }
+                if let Some(recorder) = &mut entry.recorder {
+                    if let Err(e) = recorder.record(timestamp.0, &[(entry.watch.name.clone(), value)]) {
+                        entry.error = Some(e);
+                        entry.recorder = None;
+                    }
+                }
+            },
+            Err(e) => entry.error = Some(e),
+        }
+    }
+
+    let watches = watch_list.entries.iter().map(|e| &e.watch).collect::<Vec<_>>();
+    for AlarmEntry { alarm, error } in &mut watch_list.alarms {
+        let values = sample_all(&alarm.condition);
+        let value = match alarm.condition.eval(t_ms, &values, &watches) {
+            Ok(value) => value,
+            Err(e) => {
+                *error = Some(e);
+                continue;
+            },
+        };
+        *error = None;
+        if !alarm.update(t_ms, value) {
+            continue;
+        }
+        let Watch { name, source, .. } = &alarm.condition;
+        pause |= alarm.pause;
+        if alarm.log {
+            let parameters = vec![("Condition".to_string(), source.clone())];
+            session_log.push(SessionLogEntry::new(format!("Alarm: {}", name), timestamp.clone(), parameters, vec![]));
+        }
+        // Alarms without a URL of their own use the run's.
+        let url = match alarm.webhook.as_deref() {
+            Some("") => notifications.url(),
+            url => url,
+        };
+        if let Some(url) = url {
+            webhook::post_json(url, &webhook::alarm_body(name, source, t_ms));
+        }
+    }
+    if pause {
+        command_events.send(Command::Pause);
+    }
+}
+
+pub fn watch_gui(mut contexts: EguiContexts, mut watch_list: ResMut<WatchList>) {
+    egui::Window::new("Watch expressions")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let watch_list = &mut *watch_list;
+            ui.label("e.g. g_Na(soma) * (Vm - E_Na), or Vm(probe2) - Vm(probe1)");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut watch_list.new_name).hint_text("name").desired_width(80.0));
+                ui.add(egui::TextEdit::singleline(&mut watch_list.new_source).hint_text("formula"));
+                if ui.button("Add").clicked() {
+                    let name = match watch_list.new_name.trim() {
+                        "" => format!("w{}", watch_list.entries.len() + 1),
+                        name => name.to_string(),
+                    };
+                    let source = watch_list.new_source.clone();
+                    watch_list.error = watch_list.add(&name, &source).err();
+                    if watch_list.error.is_none() {
+                        watch_list.new_name.clear();
+                        watch_list.new_source.clear();
+                    }
+                }
+            });
+            if let Some(error) = &watch_list.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            let mut removed = None;
+            for (i, entry) in watch_list.entries.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let latest = entry.watch.history.back().map_or("-".to_string(), |(_, v)| format!("{:.4}", v));
+                    ui.label(format!("{} = {}  [{}]", entry.watch.name, entry.watch.source, latest));
+                    if ui.small_button("✖").clicked() {
+                        removed = Some(i);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut triggered = entry.watch.threshold.is_some();
+                    if ui.checkbox(&mut triggered, "Trigger above").changed() {
+                        entry.watch.threshold = triggered.then_some(0.0);
+                    }
+                    if let Some(threshold) = &mut entry.watch.threshold {
+                        ui.add(egui::DragValue::new(threshold).speed(0.1));
+                    }
+                    if let Some(t) = entry.fired.last() {
+                        ui.label(format!("fired {} times, last at {:.2} ms", entry.fired.len(), t));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut recording = entry.recorder.is_some();
+                    if ui.checkbox(&mut recording, format!("Record to {}.csv", entry.watch.name)).changed() {
+                        entry.recorder = None;
+                        if recording {
+                            match CsvRecorder::create(&format!("{}.csv", entry.watch.name), 1) {
+                                Ok(recorder) => entry.recorder = Some(recorder),
+                                Err(e) => entry.error = Some(e),
+                            }
+                        }
+                    }
+                });
+                if let Some(error) = &entry.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            }
+            if let Some(i) = removed {
+                watch_list.entries.remove(i);
+            }
+
+            ui.separator();
+            ui.checkbox(&mut watch_list.pause_on_trigger, "Pause when a trigger fires");
+            alarms_widget(ui, watch_list);
+            Plot::new("watch_expressions")
+                .view_aspect(2.0)
+                .x_axis_label("ms")
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    for entry in &watch_list.entries {
+                        let points = entry.watch.history.iter().map(|(t, v)| [*t as f64, *v as f64]).collect::<Vec<_>>();
+                        plot_ui.line(Line::new(points).name(&entry.watch.name));
+                    }
+                });
+        });
+}
+
+fn alarms_widget(ui: &mut egui::Ui, watch_list: &mut WatchList) {
+    ui.collapsing("Alarms", |ui| {
+        ui.label("e.g. Vm(n0_42) > -20, or rate(v1) > 50 for a watch named v1");
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut watch_list.new_alarm_name).hint_text("name").desired_width(80.0));
+            ui.add(egui::TextEdit::singleline(&mut watch_list.new_alarm_condition).hint_text("condition"));
+            if ui.button("Add").clicked() {
+                let name = match watch_list.new_alarm_name.trim() {
+                    "" => format!("a{}", watch_list.alarms.len() + 1),
+                    name => name.to_string(),
+                };
+                let condition = watch_list.new_alarm_condition.clone();
+                watch_list.error = watch_list.add_alarm(&name, &condition).err();
+                if watch_list.error.is_none() {
+                    watch_list.new_alarm_name.clear();
+                    watch_list.new_alarm_condition.clear();
+                }
+            }
+        });
+
+        let mut removed = None;
+        for (i, AlarmEntry { alarm, error }) in watch_list.alarms.iter_mut().enumerate() {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: {}", alarm.condition.name, alarm.condition.source));
+                if let Some(t) = alarm.fired.last() {
+                    ui.label(format!("fired {} times, last at {:.2} ms", alarm.fired.len(), t));
+                }
+                if ui.small_button("✖").clicked() {
+                    removed = Some(i);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut alarm.pause, "Pause");
+                ui.checkbox(&mut alarm.log, "Log");
+                let mut notify = alarm.webhook.is_some();
+                if ui.checkbox(&mut notify, "Webhook").changed() {
+                    alarm.webhook = notify.then(String::new);
+                }
+                if let Some(url) = &mut alarm.webhook {
+                    ui.add(egui::TextEdit::singleline(url).hint_text("https://, or NB_SIM_WEBHOOK_URL"));
+                }
+            });
+            if let Some(error) = error {
+                ui.colored_label(egui::Color32::RED, error.as_str());
+            }
+        }
+        if let Some(i) = removed {
+            watch_list.alarms.remove(i);
+        }
+    });
+}
@@ -0,0 +1,127 @@
+//! Running a scene without a window or renderer, for scripted experiments
+//! and batch jobs. The simulation is the same ECS schedule the GUI runs,
+//! on bevy's minimal plugins, advanced one frame at a time by the caller.
+
+use bevy::prelude::*;
+
+use crate::analysis::Trace;
+use crate::command::Command;
+use crate::config::Config;
+use crate::dimension::Timestamp;
+use crate::integrations::grace::{soma, SceneSegment};
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::multirate::IntegrationRate;
+use crate::plugin::NbSimPlugin;
+use crate::serialize::{self, SegmentRef};
+use crate::step_advisor::{advise, Advice, Compartment};
+use crate::validate::validate_scene;
+
+/// Frames to wait for a scene to spawn before giving up.
+const LOAD_FRAMES: usize = 10;
+
+pub struct HeadlessSimulation {
+    app: App,
+}
+
+impl HeadlessSimulation {
+    /// Load `scene`, to be advanced `steps_per_frame` steps of
+    /// `step_seconds` at a time, starting at time zero.
+    pub fn new(scene: serialize::Scene, step_seconds: f32, steps_per_frame: usize) -> Result<Self, String> {
+        let errors = validate_scene(&scene);
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        // Scenes spawn their markers' meshes even with nothing to draw them.
+        #[cfg(feature = "render")]
+        app.add_plugins(AssetPlugin::default())
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>();
+        app.insert_resource(Config {
+                simulation_step_seconds: step_seconds,
+                steps_per_frame: steps_per_frame.max(1),
+                ..default()
+            })
+            .add_plugins(NbSimPlugin::headless());
+        let mut simulation = HeadlessSimulation { app };
+        simulation.send(Command::Pause);
+        simulation.send(Command::LoadScene { scene });
+        for _ in 0..LOAD_FRAMES {
+            simulation.app.update();
+            if !simulation.voltages().is_empty() {
+                simulation.send(Command::Resume);
+                return Ok(simulation);
+            }
+        }
+        Err("The scene did not spawn".to_string())
+    }
+
+    pub fn send(&mut self, command: Command) {
+        self.app.world.send_event(command);
+    }
+
+    /// Advance by one frame of steps.
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    /// Simulated time, in seconds.
+    pub fn time(&self) -> f32 {
+        self.app.world.resource::<Timestamp>().0
+    }
+
+    /// Every segment's membrane potential (mV), in scene order.
+    pub fn voltages(&mut self) -> Vec<(SegmentRef, f32)> {
+        let mut voltages = self
+            .app
+            .world
+            .query::<(&SceneSegment, &MembraneVoltage)>()
+            .iter(&self.app.world)
+            .map(|(SceneSegment(at), v)| (*at, v.0.0))
+            .collect::<Vec<_>>();
+        voltages.sort_by_key(|(at, _)| (at.neuron, at.segment));
+        voltages
+    }
+
+    /// The longest step the spawned scene integrates stably at, as the
+    /// GUI's step advice computes it.
+    pub fn step_advice(&mut self) -> Option<Advice> {
+        let world = &mut self.app.world;
+        let mut segments = world.query::<(&Membrane, Option<&IntegrationRate>)>();
+        advise(segments.iter(world).map(|(membrane, rate)| Compartment {
+            membrane,
+            step_multiple: rate.map_or(1, |r| r.every()),
+        }))
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+}
+
+/// Run `scene` from time zero for `duration_seconds`, recording each
+/// neuron's soma at the start and after every frame. Neurons without a
+/// soma get empty traces.
+pub fn soma_traces(
+    scene: serialize::Scene,
+    duration_seconds: f32,
+    step_seconds: f32,
+    steps_per_frame: usize,
+) -> Result<Vec<Trace>, String> {
+    let somas = scene.neurons.iter().map(|n| soma(&n.neuron).map(|s| s.id)).collect::<Vec<_>>();
+    let mut traces = (0..somas.len()).map(|n| Trace::new(format!("n{}_soma", n))).collect::<Vec<_>>();
+    let mut simulation = HeadlessSimulation::new(scene, step_seconds, steps_per_frame)?;
+    loop {
+        let t = simulation.time();
+        for (at, v) in simulation.voltages() {
+            if somas[at.neuron] == Some(at.segment) {
+                traces[at.neuron].push(t, v);
+            }
+        }
+        if t >= duration_seconds {
+            return Ok(traces);
+        }
+        simulation.step();
+    }
+}
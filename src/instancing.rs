@@ -0,0 +1,322 @@
+//! Drawing every segment with a handful of draw calls.
+//!
+//! Segments share four unit meshes: a detailed and a coarse cylinder, and a
+//! detailed and a coarse sphere. Each mesh has one `SegmentBatch` entity,
+//! whose `SegmentInstances` hold the transform, color, glow and clip plane
+//! of every segment drawn with it. The batches are copied into per-instance
+//! vertex buffers in the render world and drawn in one instanced call each,
+//! so a scene costs the same four draws and four meshes however many
+//! segments it has. A batch's buffer is kept from frame to frame and only
+//! rewritten on frames the batch changed.
+//!
+//! Segments are lit by a headlight rather than the scene's lights, which is
+//! all a voltage map needs.
+
+use bevy::asset::load_internal_asset;
+use bevy::core::cast_slice;
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::ecs::system::{lifetimeless::*, SystemParamItem};
+use bevy::pbr::{MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup};
+use bevy::prelude::*;
+use bevy::render::mesh::{GpuBufferInfo, MeshVertexBufferLayout};
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+    TrackedRenderPass,
+};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::{ExtractedView, NoFrustumCulling};
+use bevy::render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+use bevy::utils::HashMap;
+
+use crate::lod::{insert_unit_meshes, unit_mesh};
+
+const SEGMENT_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(0x6e62_7369_6d5f_696e_7374_616e_6365_31);
+
+/// A model matrix and three vec4s: color, emissive and clip plane.
+pub const INSTANCE_FLOATS: usize = 28;
+/// The shader location of the first per-instance attribute, after the
+/// mesh's positions, normals and UVs.
+const FIRST_INSTANCE_LOCATION: u32 = 3;
+
+/// Which of the shared meshes a batch draws.
+#[derive(Clone, Copy, Component, Debug, PartialEq)]
+pub struct SegmentBatch {
+    pub sphere: bool,
+    pub detailed: bool,
+}
+
+impl SegmentBatch {
+    pub const ALL: [SegmentBatch; 4] = [
+        SegmentBatch { sphere: false, detailed: true },
+        SegmentBatch { sphere: false, detailed: false },
+        SegmentBatch { sphere: true, detailed: true },
+        SegmentBatch { sphere: true, detailed: false },
+    ];
+}
+
+/// The per-instance data of one batch, `INSTANCE_FLOATS` per segment.
+#[derive(Clone, Component, Debug, Default)]
+pub struct SegmentInstances(pub Vec<f32>);
+
+impl SegmentInstances {
+    pub fn push(&mut self, model: Mat4, color: [f32; 4], emissive: [f32; 4], clip_plane: Vec4) {
+        self.0.extend_from_slice(&model.to_cols_array());
+        self.0.extend_from_slice(&color);
+        self.0.extend_from_slice(&emissive);
+        self.0.extend_from_slice(&clip_plane.to_array());
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / INSTANCE_FLOATS
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+pub struct SegmentInstancingPlugin;
+
+impl Plugin for SegmentInstancingPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, SEGMENT_SHADER_HANDLE, "segment_instancing.wgsl", Shader::from_wgsl);
+        app.add_systems(Startup, (insert_unit_meshes, spawn_segment_batches));
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .add_render_command::<Transparent3d, DrawSegments>()
+                .init_resource::<SpecializedMeshPipelines<SegmentPipeline>>()
+                .init_resource::<InstanceBuffers>()
+                .add_systems(ExtractSchedule, extract_segment_instances)
+                .add_systems(
+                    Render,
+                    (
+                        queue_segments.in_set(RenderSet::QueueMeshes),
+                        prepare_instance_buffers.in_set(RenderSet::PrepareResources),
+                    ),
+                );
+        }
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<SegmentPipeline>();
+        }
+    }
+}
+
+fn spawn_segment_batches(mut commands: Commands) {
+    for batch in SegmentBatch::ALL {
+        commands.spawn((
+            batch,
+            unit_mesh(batch.sphere, batch.detailed),
+            SpatialBundle::INHERITED_IDENTITY,
+            SegmentInstances::default(),
+            // The instances are spread over the scene, not at the batch's
+            // origin.
+            NoFrustumCulling,
+        ));
+    }
+}
+
+/// A batch's instance count, and its data on the frames it changed.
+#[derive(Component)]
+struct ExtractedInstances {
+    data: Option<Vec<f32>>,
+    len: usize,
+}
+
+fn extract_segment_instances(mut commands: Commands, batches: Extract<Query<(Entity, Ref<SegmentInstances>)>>) {
+    for (entity, instances) in &batches {
+        let data = instances.is_changed().then(|| instances.0.clone());
+        commands.get_or_spawn(entity).insert(ExtractedInstances { data, len: instances.len() });
+    }
+}
+
+struct InstanceBuffer {
+    buffer: Buffer,
+    length: usize,
+}
+
+/// Each batch's buffer, kept from frame to frame.
+#[derive(Default, Resource)]
+struct InstanceBuffers(HashMap<Entity, InstanceBuffer>);
+
+fn prepare_instance_buffers(
+    mut buffers: ResMut<InstanceBuffers>,
+    batches: Query<(Entity, &ExtractedInstances)>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    for (entity, instances) in &batches {
+        let Some(data) = &instances.data else {
+            continue;
+        };
+        if data.is_empty() {
+            buffers.0.remove(&entity);
+            continue;
+        }
+        let contents: &[u8] = cast_slice(data.as_slice());
+        match buffers.0.get_mut(&entity) {
+            Some(buffer) if buffer.buffer.size() == contents.len() as u64 => {
+                render_queue.write_buffer(&buffer.buffer, 0, contents);
+            }
+            _ => {
+                let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("segment instance buffer"),
+                    contents,
+                    usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                });
+                buffers.0.insert(entity, InstanceBuffer { buffer, length: instances.len });
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_segments(
+    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    segment_pipeline: Res<SegmentPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<SegmentPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    batches: Query<(Entity, &ExtractedInstances)>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_segments = transparent_3d_draw_functions.read().id::<DrawSegments>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+    for (view, mut transparent_phase) in &mut views {
+        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        for (entity, instances) in &batches {
+            if instances.len == 0 {
+                continue;
+            }
+            let Some(mesh_instance) = render_mesh_instances.get(&entity) else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline = match pipelines.specialize(&pipeline_cache, &segment_pipeline, key, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Failed to specialize the segment pipeline: {}", err);
+                    continue;
+                }
+            };
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function: draw_segments,
+                // The batches are opaque and write depth, so their order
+                // does not matter.
+                distance: 0.0,
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+struct SegmentPipeline {
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for SegmentPipeline {
+    fn from_world(world: &mut World) -> Self {
+        SegmentPipeline { mesh_pipeline: world.resource::<MeshPipeline>().clone() }
+    }
+}
+
+impl SpecializedMeshPipeline for SegmentPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.vertex.shader = SEGMENT_SHADER_HANDLE;
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: (INSTANCE_FLOATS * std::mem::size_of::<f32>()) as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..(INSTANCE_FLOATS / 4) as u32)
+                .map(|i| VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: i as u64 * VertexFormat::Float32x4.size(),
+                    shader_location: FIRST_INSTANCE_LOCATION + i,
+                })
+                .collect(),
+        });
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = SEGMENT_SHADER_HANDLE;
+        }
+        Ok(descriptor)
+    }
+}
+
+type DrawSegments = (SetItemPipeline, SetMeshViewBindGroup<0>, SetMeshBindGroup<1>, DrawSegmentInstances);
+
+struct DrawSegmentInstances;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawSegmentInstances {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>, SRes<InstanceBuffers>);
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        _entity: Option<()>,
+        (meshes, render_mesh_instances, buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(mesh_instance) = render_mesh_instances.into_inner().get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(instance_buffer) = buffers.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let instances = 0..instance_buffer.length as u32;
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, instances);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, instances);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_are_laid_out_as_the_shader_reads_them() {
+        let mut instances = SegmentInstances::default();
+        let model = Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0));
+        instances.push(model, [0.1, 0.2, 0.3, 1.0], [0.0; 4], Vec4::new(0.0, 0.0, 1.0, 5.0));
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances.0.len(), INSTANCE_FLOATS);
+        // Column-major, so the translation is the fourth column.
+        assert_eq!(&instances.0[12..16], &[1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(&instances.0[16..20], &[0.1, 0.2, 0.3, 1.0]);
+        assert_eq!(&instances.0[24..28], &[0.0, 0.0, 1.0, 5.0]);
+    }
+}
@@ -0,0 +1,320 @@
+//! Fetch cells from the Allen Cell Types Database.
+//!
+//! Given a specimen ID, `fetch` downloads the cell's reconstructed
+//! morphology (SWC) and the parameters of its perisomatic biophysical
+//! model (`fit.json`), and builds a `serialize::SceneNeuron` from them.
+//!
+//! The Allen models use mechanisms from their own channel library. Each
+//! is mapped onto the closest channel in `common_channels`, keeping the
+//! fitted peak conductance; mechanisms with no counterpart here (e.g.
+//! `SK`, `Im`, calcium dynamics) are listed in `AllenModel::skipped`.
+//!
+//! Reconstructions are passed through `repair::repair`, with the fixes
+//! listed in `AllenModel::repairs`.
+
+use std::collections::BTreeSet;
+
+use ehttp::{fetch as http_fetch, Request};
+use serde::Deserialize;
+
+use crate::constants::SimulationConstants;
+use crate::dimension::{Kelvin, MilliVolts};
+use crate::integrations::grace::{GraceScene, GraceSceneSender};
+use crate::integrations::swc;
+use crate::neuron::channel::{common_channels, ChannelBuilder};
+use crate::repair::{repair, RepairOptions};
+use crate::serialize;
+
+pub const API_URL: &str = "https://api.brain-map.org";
+
+/// SWC types, in the order of `serialize::Neuron::membranes`.
+const SECTIONS: [&str; 4] = ["soma", "axon", "dend", "apic"];
+
+pub struct AllenModel {
+    pub specimen_id: u64,
+    pub scene_neuron: serialize::SceneNeuron,
+    /// Temperature the model was fitted at.
+    pub celsius: Option<f32>,
+    /// Mechanisms in the fit that have no equivalent channel.
+    pub skipped: Vec<String>,
+    /// Defects fixed in the reconstruction.
+    pub repairs: Vec<String>,
+}
+
+impl AllenModel {
+    /// A scene holding just this neuron, at the temperature of the fit.
+    pub fn scene(&self) -> serialize::Scene {
+        let constants = self.celsius.map(|celsius| {
+            SimulationConstants { temperature: Kelvin(celsius + 273.15), ..SimulationConstants::default() }.serialize()
+        });
+        serialize::Scene { neurons: vec![self.scene_neuron.clone()], synapses: vec![], constants, lights: vec![], projectors: vec![], seed: None }
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryResponse<T> {
+    success: bool,
+    msg: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct WellKnownFile {
+    download_link: String,
+}
+
+#[derive(Deserialize)]
+struct NeuronalModel {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Fit {
+    genome: Vec<GenomeEntry>,
+    #[serde(default)]
+    passive: Vec<Passive>,
+    #[serde(default)]
+    conditions: Vec<Conditions>,
+}
+
+#[derive(Deserialize)]
+struct GenomeEntry {
+    section: String,
+    name: String,
+    value: f32,
+    #[serde(default)]
+    mechanism: String,
+}
+
+#[derive(Deserialize)]
+struct Passive {
+    #[serde(default)]
+    cm: Vec<SectionCapacitance>,
+}
+
+#[derive(Deserialize)]
+struct SectionCapacitance {
+    section: String,
+    cm: f32,
+}
+
+#[derive(Deserialize)]
+struct Conditions {
+    celsius: Option<f32>,
+}
+
+fn query_url(criteria: &str) -> String {
+    format!("{}/api/v2/data/query.json?criteria={}", API_URL, criteria)
+}
+
+pub fn morphology_query_url(specimen_id: u64) -> String {
+    query_url(&format!(
+        "model::WellKnownFile,rma::criteria,well_known_file_type[name$eq'3DNeuronReconstruction'],[attachable_id$eq{}]",
+        specimen_id
+    ))
+}
+
+pub fn model_query_url(specimen_id: u64) -> String {
+    query_url(&format!(
+        "model::NeuronalModel,rma::criteria,[specimen_id$eq{}],neuronal_model_template[name$il'*Biophysical - perisomatic*']",
+        specimen_id
+    ))
+}
+
+pub fn fit_query_url(model_id: u64) -> String {
+    query_url(&format!(
+        "model::WellKnownFile,rma::criteria,well_known_file_type[name$eq'NeuronalModelParameters'],[attachable_id$eq{}]",
+        model_id
+    ))
+}
+
+fn first<T: for<'de> Deserialize<'de>>(json: &str, what: &str) -> Result<T, String> {
+    let response: QueryResponse<T> = serde_json::from_str(json).map_err(|e| format!("Bad {} response: {}", what, e))?;
+    if !response.success {
+        return Err(format!("The Allen API could not find the {}.", what));
+    }
+    response.msg.into_iter().next().ok_or_else(|| format!("No {} for this specimen.", what))
+}
+
+fn download_url(json: &str, what: &str) -> Result<String, String> {
+    first::<WellKnownFile>(json, what).map(|f| format!("{}{}", API_URL, f.download_link))
+}
+
+/// The channel standing in for an Allen mechanism in a given section.
+fn channel_for(mechanism: &str, name: &str, section: &str) -> Option<ChannelBuilder> {
+    use common_channels::*;
+    match mechanism {
+        "NaTs" | "NaTs2_t" | "NaTa_t" | "NaV" => Some(rat_thalamocortical::NA_TRANSIENT),
+        "Kv3_1" => Some(giant_squid::K_CHANNEL),
+        "K_P" | "K_T" | "Kd" | "Kv2like" => Some(rat_thalamocortical::K_SLOW),
+        "Ih" if section == "soma" => Some(rat_ca1::HCN_CHANNEL_SOMA),
+        "Ih" => Some(rat_ca1::HCN_CHANNEL_DENDRITE),
+        "Ca_HVA" | "Ca_LVA" => Some(giant_squid::CA_CHANNEL),
+        "" if name == "g_pas" => Some(giant_squid::LEAK_CHANNEL),
+        _ => None,
+    }
+}
+
+/// Build a neuron from an Allen SWC file and the text of its `fit.json`.
+pub fn model(specimen_id: u64, swc_source: &str, fit_json: &str) -> Result<AllenModel, String> {
+    let fit: Fit = serde_json::from_str(fit_json).map_err(|e| format!("Bad fit.json: {}", e))?;
+    let mut neuron = serialize::Neuron { segments: swc::segments(swc_source)?, membranes: vec![] };
+    let repairs = repair(&mut neuron, &RepairOptions::default());
+    let v0 = MilliVolts(-70.0);
+
+    let mut skipped = BTreeSet::new();
+    neuron.membranes = SECTIONS
+        .iter()
+        .map(|section| {
+            let capacitance_uf = fit
+                .passive
+                .iter()
+                .flat_map(|p| p.cm.iter())
+                .find(|c| c.section == *section)
+                .map_or(1.0, |c| c.cm);
+            let membrane_channels = fit
+                .genome
+                .iter()
+                .filter(|entry| entry.section == *section)
+                .filter_map(|entry| match channel_for(&entry.mechanism, &entry.name, section) {
+                    Some(builder) => Some(serialize::MembraneChannel {
+                        channel: builder.build(&v0).serialize(),
+                        siemens_per_square_cm: entry.value,
+                        density: None,
+                    }),
+                    None => {
+                        skipped.insert(if entry.mechanism.is_empty() { entry.name.clone() } else { entry.mechanism.clone() });
+                        None
+                    },
+                })
+                .collect();
+            serialize::Membrane {
+                membrane_channels,
+                capacitance_farads_per_square_cm: capacitance_uf * 1e-6,
+            }
+        })
+        .collect();
+
+    Ok(AllenModel {
+        specimen_id,
+        scene_neuron: serialize::SceneNeuron {
+            neuron,
+            location: serialize::Location { x_mm: 0.0, y_mm: 0.0, z_mm: 0.0 },
+            stimulator_segments: vec![],
+            tag: None,
+            thermal_segments: vec![],
+            noise: None,
+            membrane_overrides: vec![],
+        },
+        celsius: fit.conditions.iter().find_map(|c| c.celsius),
+        skipped: skipped.into_iter().collect(),
+        repairs,
+    })
+}
+
+fn get(url: String, then: impl 'static + Send + FnOnce(Result<String, String>)) {
+    http_fetch(Request::get(&url), move |response| {
+        let text = response.and_then(|r| {
+            if r.ok {
+                r.text().map(|t| t.to_string()).ok_or_else(|| format!("{} returned no text", url))
+            } else {
+                Err(format!("{} returned {} {}", url, r.status, r.status_text))
+            }
+        });
+        then(text)
+    });
+}
+
+/// Download a cell's morphology and model, and pass the built neuron to
+/// `on_done`:
+///
+/// ```ignore
+/// allen::fetch(488695444, |model| println!("{:?}", model.map(|m| m.skipped)));
+/// ```
+pub fn fetch(specimen_id: u64, on_done: impl 'static + Send + FnOnce(Result<AllenModel, String>)) {
+    get(morphology_query_url(specimen_id), move |morphology_query| {
+        let swc_url = match morphology_query.and_then(|json| download_url(&json, "reconstruction")) {
+            Ok(url) => url,
+            Err(e) => return on_done(Err(e)),
+        };
+        get(swc_url, move |swc_source| {
+            let swc_source = match swc_source {
+                Ok(s) => s,
+                Err(e) => return on_done(Err(e)),
+            };
+            get(model_query_url(specimen_id), move |model_query| {
+                let model_id = match model_query.and_then(|json| first::<NeuronalModel>(&json, "perisomatic model")) {
+                    Ok(m) => m.id,
+                    Err(e) => return on_done(Err(e)),
+                };
+                get(fit_query_url(model_id), move |fit_query| {
+                    let fit_url = match fit_query.and_then(|json| download_url(&json, "model parameters")) {
+                        Ok(url) => url,
+                        Err(e) => return on_done(Err(e)),
+                    };
+                    get(fit_url, move |fit_json| {
+                        on_done(fit_json.and_then(|fit_json| model(specimen_id, &swc_source, &fit_json)))
+                    });
+                });
+            });
+        });
+    });
+}
+
+/// Fetch a cell and spawn it through the scene loader.
+pub fn load(specimen_id: u64, sender: &GraceSceneSender) {
+    let sender = sender.clone();
+    fetch(specimen_id, move |model| match model {
+        Ok(model) => {
+            if !model.skipped.is_empty() {
+                eprintln!("Allen specimen {}: skipped mechanisms {}", specimen_id, model.skipped.join(", "));
+            }
+            for fix in model.repairs.iter() {
+                eprintln!("Allen specimen {}: {}", specimen_id, fix);
+            }
+            sender.0.send(GraceScene(model.scene())).expect("Send should succeed");
+        },
+        Err(e) => eprintln!("Allen specimen {}: {}", specimen_id, e),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SWC: &str = "1 1 0 0 0 5 -1\n2 3 10 0 0 1 1\n3 2 -10 0 0 0.5 1\n";
+
+    const FIT: &str = r#"{
+        "passive": [{"ra": 100.0, "cm": [{"section": "soma", "cm": 1.5}], "e_pas": -90.0}],
+        "conditions": [{"celsius": 34.0, "v_init": -90.0}],
+        "genome": [
+            {"section": "soma", "name": "gbar_NaTs", "value": 0.5, "mechanism": "NaTs"},
+            {"section": "soma", "name": "gbar_SK", "value": 0.01, "mechanism": "SK"},
+            {"section": "dend", "name": "g_pas", "value": 0.0001, "mechanism": ""}
+        ]
+    }"#;
+
+    #[test]
+    fn builds_membranes_by_section() {
+        let model = model(1, SWC, FIT).expect("should build");
+        let membranes = &model.scene_neuron.neuron.membranes;
+        assert_eq!(membranes.len(), 4);
+        assert_eq!(membranes[0].membrane_channels.len(), 1);
+        assert_eq!(membranes[0].membrane_channels[0].siemens_per_square_cm, 0.5);
+        assert!((membranes[0].capacitance_farads_per_square_cm - 1.5e-6).abs() < 1e-12);
+        assert_eq!(membranes[2].membrane_channels.len(), 1);
+        assert_eq!(model.celsius, Some(34.0));
+        assert_eq!(model.skipped, vec!["SK".to_string()]);
+        assert!(model.repairs.is_empty());
+        assert!(model.scene().constants.is_some());
+    }
+
+    #[test]
+    fn reads_download_links() {
+        let json = r#"{"success": true, "msg": [{"download_link": "/api/v2/well_known_file_download/1"}]}"#;
+        assert_eq!(
+            download_url(json, "reconstruction").unwrap(),
+            "https://api.brain-map.org/api/v2/well_known_file_download/1"
+        );
+        assert!(download_url(r#"{"success": true, "msg": []}"#, "reconstruction").is_err());
+    }
+}
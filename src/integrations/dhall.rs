@@ -0,0 +1,317 @@
+//! Scenes as Dhall, so that they can be written with functions, `let`
+//! bindings and imports.
+//!
+//! Writing goes through the scene's JSON form. Dhall lists must be
+//! homogeneous and empty lists and `None` need a type, so the type of each
+//! value is inferred from every value at the same place in the scene:
+//! fields that are null or absent in some records become `Optional`. That
+//! includes the fields of tagged enums like `CurrentShape`, whose variants
+//! become one record type with the other variants' fields set to `None`.
+//!
+//! Reading evaluates the Dhall into a JSON value, drops the `None`s, and
+//! parses the scene from that, so hand-written scenes may use either plain
+//! or `Optional` fields. Evaluating Dhall needs the `dhall` feature in a
+//! native build.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::serialize;
+
+#[derive(Clone, Debug, PartialEq)]
+enum DhallType {
+    /// Only ever seen empty or null; any type will do.
+    Unknown,
+    Bool,
+    Natural,
+    Integer,
+    Double,
+    Text,
+    List(Box<DhallType>),
+    Optional(Box<DhallType>),
+    Record(BTreeMap<String, DhallType>),
+}
+
+impl DhallType {
+    fn optional(self) -> DhallType {
+        match self {
+            DhallType::Optional(_) => self,
+            t => DhallType::Optional(Box::new(t)),
+        }
+    }
+
+    fn render(&self) -> String {
+        match self {
+            DhallType::Unknown => "{}".to_string(),
+            DhallType::Bool => "Bool".to_string(),
+            DhallType::Natural => "Natural".to_string(),
+            DhallType::Integer => "Integer".to_string(),
+            DhallType::Double => "Double".to_string(),
+            DhallType::Text => "Text".to_string(),
+            DhallType::List(t) => format!("List {}", t.render_argument()),
+            DhallType::Optional(t) => format!("Optional {}", t.render_argument()),
+            DhallType::Record(fields) => {
+                let fields = fields.iter().map(|(k, t)| format!("{} : {}", label(k), t.render())).collect::<Vec<_>>();
+                format!("{{ {} }}", fields.join(", "))
+            },
+        }
+    }
+
+    fn render_argument(&self) -> String {
+        match self {
+            DhallType::List(_) | DhallType::Optional(_) => format!("({})", self.render()),
+            _ => self.render(),
+        }
+    }
+}
+
+fn infer(value: &Value) -> DhallType {
+    match value {
+        Value::Null => DhallType::Optional(Box::new(DhallType::Unknown)),
+        Value::Bool(_) => DhallType::Bool,
+        Value::Number(n) if n.is_u64() => DhallType::Natural,
+        Value::Number(n) if n.is_i64() => DhallType::Integer,
+        Value::Number(_) => DhallType::Double,
+        Value::String(_) => DhallType::Text,
+        Value::Array(items) => DhallType::List(Box::new(
+            // Errors surface when the list is rendered.
+            items.iter().map(infer).reduce(|a, b| unify(&a, &b).unwrap_or(a)).unwrap_or(DhallType::Unknown),
+        )),
+        Value::Object(fields) => DhallType::Record(fields.iter().map(|(k, v)| (k.clone(), infer(v))).collect()),
+    }
+}
+
+/// The type that values of types `a` and `b` can both be written as.
+fn unify(a: &DhallType, b: &DhallType) -> Result<DhallType, String> {
+    use DhallType::*;
+    Ok(match (a, b) {
+        (Unknown, t) | (t, Unknown) => t.clone(),
+        (Optional(a), Optional(b)) => unify(a, b)?.optional(),
+        (Optional(a), t) | (t, Optional(a)) => unify(a, t)?.optional(),
+        (Natural, Integer) | (Integer, Natural) => Integer,
+        (List(a), List(b)) => List(Box::new(unify(a, b)?)),
+        (Record(a), Record(b)) => {
+            let mut fields = BTreeMap::new();
+            for key in a.keys().chain(b.keys()) {
+                let t = match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => unify(x, y).map_err(|e| format!("{}: {}", key, e))?,
+                    (Some(t), None) | (None, Some(t)) => t.clone().optional(),
+                    (None, None) => unreachable!(),
+                };
+                fields.insert(key.clone(), t);
+            }
+            Record(fields)
+        },
+        (a, b) if a == b => a.clone(),
+        (a, b) => return Err(format!("cannot mix {} and {}", a.render(), b.render())),
+    })
+}
+
+const KEYWORDS: [&str; 19] = [
+    "if", "then", "else", "let", "in", "using", "missing", "assert", "as", "Infinity", "NaN", "merge", "Some",
+    "toMap", "forall", "with", "showConstructor", "Type", "None",
+];
+
+fn label(key: &str) -> String {
+    let simple = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '/');
+    if simple && !KEYWORDS.contains(&key) {
+        key.to_string()
+    } else {
+        format!("`{}`", key)
+    }
+}
+
+fn double(x: f64) -> String {
+    if x.is_nan() {
+        "NaN".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        // Debug formatting always includes a decimal point or exponent.
+        format!("{:?}", x)
+    }
+}
+
+fn render(value: &Value, t: &DhallType, out: &mut String) -> Result<(), String> {
+    match (t, value) {
+        (DhallType::Optional(inner), Value::Null) => out.push_str(&format!("None {}", inner.render_argument())),
+        (DhallType::Optional(inner), v) => {
+            out.push_str("Some (");
+            render(v, inner, out)?;
+            out.push(')');
+        },
+        (DhallType::Bool, Value::Bool(b)) => out.push_str(if *b { "True" } else { "False" }),
+        (DhallType::Natural, Value::Number(n)) => out.push_str(&n.to_string()),
+        (DhallType::Integer, Value::Number(n)) => {
+            let n = n.as_i64().ok_or(format!("{} is not an integer", n))?;
+            out.push_str(&format!("{}{}", if n < 0 { "-" } else { "+" }, n.unsigned_abs()));
+        },
+        (DhallType::Double, Value::Number(n)) => out.push_str(&double(n.as_f64().unwrap_or(f64::NAN))),
+        (DhallType::Text, Value::String(s)) => {
+            // JSON string escapes are valid in Dhall, apart from `$`.
+            let quoted = serde_json::to_string(s).map_err(|e| e.to_string())?;
+            out.push_str(&quoted.replace('$', "\\u0024"));
+        },
+        (DhallType::List(inner), Value::Array(items)) if items.is_empty() => {
+            out.push_str(&format!("[] : List {}", inner.render_argument()));
+        },
+        (DhallType::List(inner), Value::Array(items)) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                render(item, inner, out)?;
+            }
+            out.push(']');
+        },
+        (DhallType::Record(fields), Value::Object(values)) if fields.is_empty() => {
+            debug_assert!(values.is_empty());
+            out.push_str("{=}");
+        },
+        (DhallType::Record(fields), Value::Object(values)) => {
+            out.push_str("{ ");
+            for (i, (key, field_type)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&label(key));
+                out.push_str(" = ");
+                render(values.get(key).unwrap_or(&Value::Null), field_type, out)
+                    .map_err(|e| format!("{}: {}", key, e))?;
+            }
+            out.push_str(" }");
+        },
+        (t, v) => return Err(format!("cannot write {} as {}", v, t.render())),
+    }
+    Ok(())
+}
+
+/// Check that every list's elements can share one type.
+fn check_lists(value: &Value) -> Result<(), String> {
+    match value {
+        Value::Array(items) => {
+            let mut t = DhallType::Unknown;
+            for item in items {
+                t = unify(&t, &infer(item))?;
+                check_lists(item)?;
+            }
+            Ok(())
+        },
+        Value::Object(fields) => {
+            fields.iter().try_for_each(|(k, v)| check_lists(v).map_err(|e| format!("{}: {}", k, e)))
+        },
+        _ => Ok(()),
+    }
+}
+
+pub fn to_dhall(scene: &serialize::Scene) -> Result<String, String> {
+    let value = serde_json::to_value(scene).map_err(|e| e.to_string())?;
+    check_lists(&value).map_err(|e| format!("Scene cannot be written as Dhall: {}", e))?;
+    let mut out = String::new();
+    render(&value, &infer(&value), &mut out).map_err(|e| format!("Scene cannot be written as Dhall: {}", e))?;
+    out.push('\n');
+    Ok(out)
+}
+
+/// Remove null fields, so that `None`s read like absent fields.
+#[cfg(all(feature = "dhall", not(target_arch = "wasm32")))]
+fn drop_nulls(value: Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(drop_nulls).collect()),
+        Value::Object(fields) => {
+            Value::Object(fields.into_iter().filter(|(_, v)| !v.is_null()).map(|(k, v)| (k, drop_nulls(v))).collect())
+        },
+        v => v,
+    }
+}
+
+#[cfg(all(feature = "dhall", not(target_arch = "wasm32")))]
+fn scene_from_value(value: Value) -> Result<serialize::Scene, String> {
+    serde_json::from_value(drop_nulls(value)).map_err(|e| format!("Not a scene: {}", e))
+}
+
+const UNAVAILABLE: &str = "Reading Dhall requires a native build with the `dhall` feature.";
+
+/// Evaluate a Dhall scene. Relative imports are resolved against the
+/// working directory.
+#[cfg(all(feature = "dhall", not(target_arch = "wasm32")))]
+pub fn from_dhall(source: &str) -> Result<serialize::Scene, String> {
+    let value = serde_dhall::from_str(source).parse::<Value>().map_err(|e| format!("Dhall error: {}", e))?;
+    scene_from_value(value)
+}
+
+#[cfg(not(all(feature = "dhall", not(target_arch = "wasm32"))))]
+pub fn from_dhall(_source: &str) -> Result<serialize::Scene, String> {
+    Err(UNAVAILABLE.to_string())
+}
+
+#[cfg(all(feature = "dhall", not(target_arch = "wasm32")))]
+fn read_dhall_file(path: &str) -> Result<serialize::Scene, String> {
+    let value = serde_dhall::from_file(path).parse::<Value>().map_err(|e| format!("{}: {}", path, e))?;
+    scene_from_value(value)
+}
+
+#[cfg(not(all(feature = "dhall", not(target_arch = "wasm32"))))]
+fn read_dhall_file(_path: &str) -> Result<serialize::Scene, String> {
+    Err(UNAVAILABLE.to_string())
+}
+
+/// Read a scene file, as Dhall if its name ends in `.dhall` and as JSON
+/// otherwise. Relative imports are resolved against the file's directory.
+pub fn read_scene(path: &str) -> Result<serialize::Scene, String> {
+    if path.ends_with(".dhall") {
+        return read_dhall_file(path);
+    }
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// Parse a scene given as JSON or, failing that, as Dhall.
+pub fn parse_scene(source: &str) -> Result<serialize::Scene, String> {
+    let json_error = match serde_json::from_str(source) {
+        Ok(scene) => return Ok(scene),
+        Err(e) => e,
+    };
+    from_dhall(source).map_err(|dhall_error| format!("Not a JSON scene ({}), nor a Dhall one ({})", json_error, dhall_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::sample;
+
+    #[test]
+    fn infers_optional_fields_and_empty_lists() {
+        let value = serde_json::json!([
+            {"type": "SquareWave", "on": 1.5, "ids": []},
+            {"type": "Ramp", "start": -2.0, "ids": [-1, 3]},
+        ]);
+        check_lists(&value).unwrap();
+        let mut out = String::new();
+        render(&value, &infer(&value), &mut out).unwrap();
+        assert_eq!(
+            out,
+            "[{ ids = [] : List Integer, on = Some (1.5), start = None Double, type = \"SquareWave\" }, \
+             { ids = [-1, +3], on = None Double, start = Some (-2.0), type = \"Ramp\" }]"
+        );
+        assert!(check_lists(&serde_json::json!([1.5, "x"])).is_err());
+    }
+
+    #[test]
+    fn writes_the_sample_scene() {
+        let dhall = to_dhall(&sample::scene2()).unwrap();
+        assert!(dhall.starts_with("{ constants = None "));
+        assert!(dhall.contains("stimulator_segments = [] : List "));
+    }
+
+    #[cfg(all(feature = "dhall", not(target_arch = "wasm32")))]
+    #[test]
+    fn round_trips_through_dhall() {
+        let scene = sample::scene2();
+        let read = from_dhall(&to_dhall(&scene).unwrap()).unwrap();
+        assert!(crate::scene_diff::diff(&scene, &read).is_empty());
+    }
+}
@@ -1,26 +1,44 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::math::prelude::{Cylinder, Sphere};
+#[cfg(feature = "render")]
+use bevy::math::prelude::Sphere;
+#[cfg(feature = "render")]
 use bevy_mod_picking::{
     prelude::{Listener, On, Pointer},
     PickableBundle,
-    events::Click
+    events::{Click, Out, Over}
 };
 use crossbeam::channel::{Sender, Receiver};
 // use std::sync::mpsc::{channel, Sender, Receiver};
 use std::collections::{HashMap, HashSet};
 
+use crate::constants::SimulationConstants;
 use crate::dimension::{MilliVolts, Diameter, MicroAmpsPerSquareCm};
+#[cfg(feature = "render")]
 use crate::gui::NextClickAction;
+#[cfg(feature = "render")]
 use crate::gui::oscilloscope::Oscilloscope;
 use crate::neuron::Junction;
-use crate::neuron::membrane::{Membrane, MembraneVoltage, MembraneMaterials};
+use crate::neuron::density;
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
 use crate::neuron::solution::EXAMPLE_CYTOPLASM;
 use crate::neuron::segment::{ecs::Segment, ecs::InputCurrent, Geometry};
 use crate::neuron::synapse::SynapseMembranes;
 use crate::stimulator;
+use crate::thermal::{HeatOverlay, ThermalStimulator};
+use crate::noise::MembraneNoise;
+use crate::optogenetics::LightSource;
+#[cfg(feature = "render")]
+use crate::probe::Probe;
+use crate::projector::Projector;
+#[cfg(feature = "render")]
+use crate::lod::{SegmentShape, unit_mesh};
+use crate::rng::SimulationRng;
 use crate::serialize;
-use crate::selection::{Selection, Highlight, spawn_highlight};
+#[cfg(feature = "render")]
+use crate::selection::{Selection, Highlight, spawn_highlight, toggle_on_shift_click};
 use crate::neuron::ecs::Neuron;
+use crate::units::UnitScale;
 
 #[derive(Clone)]
 pub struct GraceScene( pub serialize::Scene );
@@ -31,24 +49,92 @@ pub struct GraceSceneSender(pub Sender<GraceScene>);
 #[derive(Resource)]
 pub struct GraceSceneReceiver(pub Receiver<GraceScene>);
 
+/// Every scene spawned since the world was last cleared, merged into one.
+/// Segment entities refer back into it through `SceneSegment`.
+#[derive(Resource)]
+pub struct CurrentScene(pub serialize::Scene);
+
+impl Default for CurrentScene {
+    fn default() -> Self {
+        CurrentScene(serialize::Scene { neurons: vec![], synapses: vec![], constants: None, lights: vec![], projectors: vec![], seed: None })
+    }
+}
+
+impl CurrentScene {
+    /// Merge in a scene about to be spawned, returning the index its first
+    /// neuron will have.
+    pub fn append(&mut self, scene: &serialize::Scene) -> usize {
+        let first_neuron = self.0.neurons.len();
+        self.0.neurons.extend(scene.neurons.iter().cloned());
+        self.0.synapses.extend(scene.synapses.iter().map(|synapse| serialize::Synapse {
+            pre_neuron: synapse.pre_neuron + first_neuron,
+            post_neuron: synapse.post_neuron + first_neuron,
+            ..synapse.clone()
+        }));
+        if scene.constants.is_some() {
+            self.0.constants = scene.constants.clone();
+        }
+        self.0.lights.extend(scene.lights.iter().cloned());
+        self.0.projectors.extend(scene.projectors.iter().cloned());
+        if scene.seed.is_some() {
+            self.0.seed = scene.seed;
+        }
+        first_neuron
+    }
+
+    pub fn clear(&mut self) {
+        *self = CurrentScene::default();
+    }
+}
+
+/// What spawning a scene needs beyond the simulation: meshes and
+/// materials for its markers, and the selection it clears. Empty without
+/// the `render` feature.
+#[cfg(feature = "render")]
+#[derive(SystemParam)]
+pub struct SceneVisuals<'w, 's> {
+    pub meshes: ResMut<'w, Assets<Mesh>>,
+    pub materials: ResMut<'w, Assets<StandardMaterial>>,
+    pub selections: Query<'w, 's, Entity, With<Selection>>,
+    pub highlights: Query<'w, 's, Entity, With<Highlight>>,
+}
+
+#[cfg(not(feature = "render"))]
+#[derive(SystemParam)]
+pub struct SceneVisuals<'w, 's> {
+    _marker: std::marker::PhantomData<(&'w (), &'s ())>,
+}
+
 impl GraceScene {
 
+    /// Spawn the scene's neurons, numbering them from `first_neuron` (see
+    /// `CurrentScene::append`).
     pub fn spawn(
         &self,
         soma_location_cm: Vec3,
+        first_neuron: usize,
+        scale: &UnitScale,
         mut commands: Commands,
-        mut meshes: &mut ResMut<Assets<Mesh>>,
-        membrane_materials: Res<MembraneMaterials>,
-        materials: &mut ResMut<Assets<StandardMaterial>>,
-        selections: Query<Entity, With<Selection>>,
-        highlights: Query<Entity, With<Highlight>>,
+        visuals: &mut SceneVisuals,
     ) -> Vec<(Entity, Vec<Entity>)> {
-        let neuron_entities = self.0.neurons.iter().map(|scene_neuron| {
-            spawn_neuron(&scene_neuron, soma_location_cm, &mut commands, &mut meshes, &membrane_materials, materials, &selections, &highlights)
+        if let Some(constants) = &self.0.constants {
+            commands.insert_resource(SimulationConstants::deserialize(constants));
+        }
+        if let Some(seed) = self.0.seed {
+            commands.insert_resource(SimulationRng { seed });
+        }
+        let neuron_entities = self.0.neurons.iter().enumerate().map(|(neuron_index, scene_neuron)| {
+            spawn_neuron(scene_neuron, first_neuron + neuron_index, soma_location_cm, scale, &mut commands, visuals)
         }).collect();
 
         for synapse in &self.0.synapses {
-            spawn_synapse(&mut commands, &synapse, &neuron_entities, meshes, materials);
+            spawn_synapse(&mut commands, synapse, &neuron_entities);
+        }
+        for light in &self.0.lights {
+            commands.spawn(LightSource::deserialize(light));
+        }
+        for projector in &self.0.projectors {
+            commands.spawn(Projector::deserialize(projector));
         }
         neuron_entities
 
@@ -56,6 +142,30 @@ impl GraceScene {
 
 }
 
+/// Spawn each scene sent through `GraceSceneSender`, whether by the GUI's
+/// loader or by `Command::LoadScene`, so that headless simulations load
+/// multi-neuron scenes the same way the GUI does.
+pub fn handle_loaded_neuron(
+    commands: Commands,
+    grace_scene_receiver: Res<GraceSceneReceiver>,
+    mut current_scene: ResMut<CurrentScene>,
+    scale: Res<UnitScale>,
+    neurons: Query<(), With<Neuron>>,
+    mut visuals: SceneVisuals,
+) {
+    match grace_scene_receiver.0.try_recv() {
+        Err(_) => {},
+        Ok(n) => {
+            // Loading despawns the previous scene before the new one arrives.
+            if neurons.is_empty() {
+                current_scene.clear();
+            }
+            let first_neuron = current_scene.append(&n.0);
+            n.spawn(Vec3::new(0.0, 0.0, 0.0), first_neuron, &scale, commands, &mut visuals);
+        }
+    }
+}
+
 pub fn soma(neuron: &serialize::Neuron) -> Option<&serialize::Segment> {
     neuron.segments.iter().find(|s| s.parent == -1 && s.type_ == 1)
 }
@@ -123,34 +233,190 @@ pub fn distance_to_segment_cm(source: &serialize::Segment, dest: &serialize::Seg
             (source.y - dest.y).powi(2) +
             (source.z - dest.z).powi(2)
     ).sqrt();
-    dist_microns * 0.0001
+    UnitScale::microns_to_cm(dist_microns)
 }
 
+/// The least length or diameter (µm) a segment is given, so that points
+/// an SWC file places on top of each other, or with zero radius, still
+/// have membrane to charge.
+pub const MIN_SEGMENT_MICRONS: f32 = 0.1;
+
+/// A segment's shape, in cm, from its SWC entry: a sphere for the soma,
+/// and otherwise a frustum tapering from its parent's diameter to its
+/// own, as long as the distance to its parent. Neurites leaving the soma
+/// start at their own diameter.
+pub fn segment_geometry(segment: &serialize::Segment, parent: Option<&serialize::Segment>) -> Geometry {
+    let diameter = |r: f32| Diameter(UnitScale::microns_to_cm((2.0 * r).max(MIN_SEGMENT_MICRONS)));
+    let min_length = UnitScale::microns_to_cm(MIN_SEGMENT_MICRONS);
+    match (segment.type_, parent) {
+        (1, _) => Geometry::Sphere { diameter: diameter(segment.r) },
+        (_, Some(parent)) if parent.type_ != 1 => Geometry::Frustum {
+            start_diameter: diameter(parent.r),
+            end_diameter: diameter(segment.r),
+            length: distance_to_segment_cm(segment, parent).max(min_length),
+        },
+        (_, Some(parent)) => Geometry::cylinder(diameter(segment.r), distance_to_segment_cm(segment, parent).max(min_length)),
+        (_, None) => Geometry::cylinder(diameter(segment.r), UnitScale::microns_to_cm(2.0 * segment.r).max(min_length)),
+    }
+}
+
+/// Names of the first SWC types.
+const SWC_TYPE_NAMES: [&str; 4] = ["Soma", "Axon", "Basal dendrite", "Apical dendrite"];
+
+/// The name of SWC type `type_`, counted from 1.
+pub fn swc_type_name(type_: usize) -> String {
+    type_
+        .checked_sub(1)
+        .and_then(|i| SWC_TYPE_NAMES.get(i))
+        .map_or(format!("Type {}", type_), |name| name.to_string())
+}
+
+/// The distance (µm) from the segment with SWC id `id` to the soma, along
+/// the neurite through its ancestors. `None` if the segment, or one of
+/// its ancestors, is missing.
+pub fn path_to_soma_microns(neuron: &serialize::Neuron, id: i32) -> Option<f32> {
+    let entry_map = segments_as_map(neuron);
+    let mut segment = *entry_map.get(&id)?;
+    let mut distance = 0.0;
+    // Bounded by the segment count, in case the parents form a cycle.
+    for _ in 0..neuron.segments.len() {
+        if segment.type_ == 1 || segment.parent == -1 {
+            return Some(distance);
+        }
+        let parent = *entry_map.get(&segment.parent)?;
+        distance += UnitScale::cm_to_microns(distance_to_segment_cm(segment, parent));
+        segment = parent;
+    }
+    None
+}
+
+/// `path_to_soma_microns` for every segment of the neuron at once,
+/// sharing the walk along each neurite. Segments without a path to the
+/// soma are left out.
+pub fn paths_to_soma_microns(neuron: &serialize::Neuron) -> HashMap<i32, f32> {
+    let entry_map = segments_as_map(neuron);
+    let mut distances = HashMap::new();
+    for segment in &neuron.segments {
+        // Walk up to the soma or to an ancestor already measured, then
+        // measure the segments passed on the way back down.
+        let mut chain = vec![];
+        let mut current = segment;
+        let mut base = None;
+        for _ in 0..neuron.segments.len() {
+            if let Some(d) = distances.get(&current.id) {
+                base = Some(*d);
+                break;
+            }
+            if current.type_ == 1 || current.parent == -1 {
+                distances.insert(current.id, 0.0);
+                base = Some(0.0);
+                break;
+            }
+            let Some(&parent) = entry_map.get(&current.parent) else { break };
+            chain.push((current.id, UnitScale::cm_to_microns(distance_to_segment_cm(current, parent))));
+            current = parent;
+        }
+        let Some(mut distance) = base else { continue };
+        for (id, length) in chain.into_iter().rev() {
+            distance += length;
+            distances.insert(id, distance);
+        }
+    }
+    distances
+}
+
+/// Whether two membranes have the same channels, densities and
+/// capacitance, whatever density rules they carry.
+fn same_membrane(a: &serialize::Membrane, b: &serialize::Membrane) -> bool {
+    let without_rules = |m: &serialize::Membrane| {
+        let mut m = m.clone();
+        m.membrane_channels.iter_mut().for_each(|c| c.density = None);
+        serde_json::to_value(m).ok()
+    };
+    without_rules(a) == without_rules(b)
+}
+
+/// The membrane to save for an SWC type of `neuron`, given the loaded one
+/// and the current membrane of each segment of the type, by SWC id, with
+/// a `MembraneOverride` for each segment that it does not describe.
+///
+/// The loaded membrane, with its density rules, stays as long as it still
+/// describes some segment. Otherwise every segment was edited, and the
+/// first segment's membrane takes its place.
+pub fn type_membranes(
+    neuron: &serialize::Neuron,
+    loaded: &serialize::Membrane,
+    segments: Vec<(i32, serialize::Membrane)>,
+) -> (serialize::Membrane, Vec<serialize::MembraneOverride>) {
+    let distances = density::has_rules(loaded).then(|| paths_to_soma_microns(neuron));
+    let described_by_loaded = |id: i32, membrane: &serialize::Membrane| match &distances {
+        None => same_membrane(loaded, membrane),
+        Some(distances) => {
+            let distance = distances.get(&id).copied().unwrap_or(0.0);
+            density::membrane_at(loaded, distance).is_ok_and(|m| same_membrane(&m.serialize(), membrane))
+        },
+    };
+    let keep_loaded = segments.iter().any(|(id, membrane)| described_by_loaded(*id, membrane));
+    let type_membrane = match (keep_loaded, segments.first()) {
+        (false, Some((_, first))) => first.clone(),
+        _ => loaded.clone(),
+    };
+    let overrides = segments
+        .into_iter()
+        .filter(|(id, membrane)| {
+            if keep_loaded {
+                !described_by_loaded(*id, membrane)
+            } else {
+                !same_membrane(&type_membrane, membrane)
+            }
+        })
+        .map(|(id, membrane)| serialize::MembraneOverride { segment: id as u32, membrane })
+        .collect();
+    (type_membrane, overrides)
+}
+
+/// Which scene neuron, and which of its SWC segments, a segment entity was
+/// spawned from.
+#[derive(Clone, Copy, Component, Debug)]
+pub struct SceneSegment(pub serialize::SegmentRef);
+
+/// An undrawn mesh in the shape of a segment, which picking hits in its
+/// place since segments are drawn instanced.
+#[cfg(feature = "render")]
+#[derive(Component)]
+pub struct PickingProxy;
+
+/// The membrane potential segments start at.
+pub const INITIAL_VOLTAGE: MilliVolts = MilliVolts(-88.0);
+
 pub fn spawn_neuron(
     scene_neuron: &serialize::SceneNeuron,
+    neuron_index: usize,
     soma_location_cm: Vec3,
+    scale: &UnitScale,
     commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    membrane_materials: &MembraneMaterials,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    selections:  &Query<Entity, With<Selection>>,
-    highlights:  &Query<Entity, With<Highlight>>,
+    visuals: &mut SceneVisuals,
 ) -> (Entity, Vec<Entity>) {
     let neuron = &scene_neuron.neuron;
     let serialize::Location { x_mm, y_mm, z_mm } = &scene_neuron.location;
-    let v0 = MilliVolts(-88.0);
-    let microns_to_screen = 1.0;
-    let entry_map = segments_as_map(neuron);
+    let v0 = INITIAL_VOLTAGE;
     let soma = soma(neuron).expect("should have soma");
+    let offset_microns = Vec3::new(
+        UnitScale::mm_to_microns(*x_mm) - soma.x,
+        UnitScale::mm_to_microns(*y_mm) - soma.y,
+        UnitScale::mm_to_microns(*z_mm) - soma.z,
+    );
+    let entry_map = segments_as_map(neuron);
+    let distances = neuron.membranes.iter().any(density::has_rules).then(|| paths_to_soma_microns(neuron));
+    let overrides: HashMap<i32, &serialize::Membrane> =
+        scene_neuron.membrane_overrides.iter().map(|o| (o.segment as i32, &o.membrane)).collect();
     let mut entities_and_parents : HashMap<i32, (Entity, i32, Diameter, Transform)> = HashMap::new();
     let neuron_entity = commands.spawn(
         (Neuron,
-            Transform::from_translation(soma_location_cm),
-            GlobalTransform::default(),
-            Visibility::default(),
-            InheritedVisibility::default(),
-            ViewVisibility::default(),
+            TransformBundle::from_transform(Transform::from_translation(soma_location_cm)),
         )).id();
+    #[cfg(feature = "render")]
+    commands.entity(neuron_entity).insert(VisibilityBundle::default());
 
     // Spawn segments.
     let segment_entities : Vec<Entity> = neuron.segments.iter().map(|segment|  {
@@ -163,27 +429,14 @@ pub fn spawn_neuron(
                 r,
                 parent
                 } = segment;
-        let x_screen = (x - soma.x + x_mm*1000.0) * microns_to_screen;
-        let y_screen = (y - soma.y + y_mm*1000.0) * microns_to_screen;
-        let z_screen = (z - soma.z + z_mm*1000.0) * microns_to_screen;
-        let default_length_cm = 2.0 * r * 0.0001;
+        let position_screen = scale.point_to_screen(Vec3::new(*x, *y, *z) + offset_microns);
+        let default_length_cm = UnitScale::microns_to_cm(2.0 * r);
         let length_cm = match (type_, entry_map.get(&parent)) {
             (1, _) => default_length_cm,
             (_, None) => default_length_cm,
             (_, Some(parent_segment)) => distance_to_segment_cm(&segment, parent_segment),
         };
-        let length_screen = length_cm * 10000.0 * microns_to_screen;
-        let radius_cm = r * 0.0001;
-        let radius_screen = radius_cm * 10000.0 * microns_to_screen;
-        let shape : Mesh = match segment.type_ {
-            1 => Sphere {
-                radius: length_screen * 0.5,
-            }.into(),
-            _ => Cylinder {
-                        radius: radius_screen * 5.0,
-                        half_height: length_screen * 0.5,
-                    }.into(),
-        };
+        let length_screen = scale.cm_to_screen(length_cm);
 
         let membrane_serialized =
             neuron
@@ -194,20 +447,25 @@ pub fn spawn_neuron(
                         segment.type_,
                         neuron.membranes.len()
                 ));
-        let membrane = Membrane::deserialize(membrane_serialized);
+        // Segments cut off from the soma take the densities at the soma.
+        let distance = distances.as_ref().and_then(|d| d.get(id)).copied().unwrap_or(0.0);
+        let membrane = match overrides.get(id) {
+            Some(membrane) => Membrane::deserialize(membrane),
+            None => density::membrane_at(membrane_serialized, distance).unwrap_or_else(|e| {
+                eprintln!("Segment {}: {}", id, e);
+                Membrane::deserialize(membrane_serialized)
+            }),
+        };
+        let geometry = segment_geometry(segment, entry_map.get(parent).copied());
+        let (_, end_diameter) = geometry.end_diameters();
         let look_target = match entry_map.get(parent) {
             None => {
                 Vec3::ZERO
             },
-            Some(p) => {
-                let p_x = (p.x - soma.x + x_mm*1000.0) * microns_to_screen;
-                let p_y = (p.y - soma.y + y_mm*1000.0) * microns_to_screen;
-                let p_z = (p.z - soma.z + z_mm*1000.0) * microns_to_screen;
-                Vec3::new(p_x, p_y, p_z)
-            }
+            Some(p) => scale.point_to_screen(Vec3::new(p.x, p.y, p.z) + offset_microns),
         };
 
-        let mut transform = Transform::from_xyz(x_screen, y_screen, z_screen);
+        let mut transform = Transform::from_translation(position_screen);
         transform.look_at(look_target, Vec3::Y);
         transform.rotate_local_x(std::f32::consts::PI / 2.0);
         transform.translation -= transform.local_y() * length_screen * 0.5;
@@ -222,25 +480,39 @@ pub fn spawn_neuron(
                 EXAMPLE_CYTOPLASM,
                 membrane,
                 MembraneVoltage(v0.clone()),
-                Geometry::Cylinder {
-                    diameter: Diameter(1.0),
-                    length: 1.0,
-                }, // TODO use real geometry. But be careful not to get units wrong,
-                // which has caused the model to become unstable
+                geometry,
 
                 InputCurrent(input_current),
-                PbrBundle {
-                    mesh: meshes.add(shape),
-                    material: membrane_materials.from_voltage(&v0),
-                    transform: transform,
-                    ..default()
-                },
-                PickableBundle::default(),
-                On::<Pointer<Click>>::run( add_stimulation ),
+                SceneSegment(serialize::SegmentRef { neuron: neuron_index, segment: *id }),
+                TransformBundle::from_transform(transform),
             )
         ).id();
+        #[cfg(feature = "render")]
+        {
+            let radius_screen = scale.microns_to_screen(*r);
+            let shape = match segment.type_ {
+                1 => SegmentShape::sphere(length_screen * 0.5),
+                _ => SegmentShape::cylinder(radius_screen * scale.radius_exaggeration(), length_screen),
+            };
+            // Its pointer events bubble up to the segment.
+            let picking_proxy = commands.spawn((
+                PickingProxy,
+                unit_mesh(shape.sphere, true),
+                SpatialBundle::from_transform(Transform::from_scale(shape.scale)),
+                PickableBundle::default(),
+            )).id();
+            commands.entity(segment_entity).insert((
+                // Drawn by VoltageMaterialPlugin.
+                VisibilityBundle::default(),
+                On::<Pointer<Click>>::run( add_stimulation ),
+                On::<Pointer<Over>>::run( hover_segment ),
+                On::<Pointer<Out>>::run( unhover_segment ),
+                shape,
+            ));
+            commands.entity(segment_entity).push_children(&[picking_proxy]);
+        }
         commands.entity(neuron_entity).push_children(&[segment_entity]);
-        entities_and_parents.insert(id.clone(), (segment_entity, segment.parent, Diameter(1.0), transform));
+        entities_and_parents.insert(*id, (segment_entity, segment.parent, Diameter(end_diameter), transform));
         segment_entity
     }).into_iter().collect();
 
@@ -267,22 +539,10 @@ pub fn spawn_neuron(
             Some((entity,_,_,transform)) => {
                 let stim = stimulator::Stimulator::deserialize(stimulator);
                 println!("INSERTING A STIMULATOR");
-                commands.spawn(
-                    (stimulator::Stimulation { stimulation_segment: entity.clone() },
-                     PbrBundle {
-                        mesh: meshes.add(Sphere{
-                            radius: 7.5,
-                        }),
-                        material: materials.add(Color::rgb(0.5,0.5,0.5)),
-                        transform: Transform::from_translation(transform.translation),
-                        ..default()
-                     },
-                     PickableBundle::default(),
-                     On::<Pointer::<Click>>::run(handle_click_stimulator),
-                    )
-                );
+                spawn_stimulation(commands, visuals, *entity, transform.translation);
                 commands.entity(*entity).insert(stim);
-                deselect_all(commands, &selections, highlights);
+                #[cfg(feature = "render")]
+                deselect_all(commands, &visuals.selections, &visuals.highlights);
                 // commands.entity(*entity).insert(Selection);
                 // spawn_highlight(commands, meshes, materials, entity.clone());
 
@@ -290,9 +550,27 @@ pub fn spawn_neuron(
         }
     } 
 
+    // Heat segments.
+    for thermal in scene_neuron.thermal_segments.iter() {
+        match entities_and_parents.get(&(thermal.segment as i32)) {
+            None => { println!("Failed to look up segment id {:?}", thermal.segment) },
+            Some((entity,_,_,_)) => {
+                commands.entity(*entity).insert(ThermalStimulator::deserialize(thermal));
+            }
+        }
+    }
+
+    // Add membrane noise.
+    if let Some(noise) = &scene_neuron.noise {
+        for entity in segment_entities.iter() {
+            commands.entity(*entity).insert(MembraneNoise::new(noise.clone()));
+        }
+    }
+
     (neuron_entity, segment_entities)
 }
 
+#[cfg(feature = "render")]
 fn deselect_all(
     commands: &mut Commands,
     selections: &Query<Entity, With<Selection>>,
@@ -307,7 +585,14 @@ fn deselect_all(
     }
 }
 
-#[derive(Component)]
+/// Every entity spawned for a scene, for despawning it.
+#[cfg(feature = "render")]
+pub type SceneEntities = Or<(With<Neuron>, With<Segment>, With<PickingProxy>, With<Junction>, With<stimulator::Stimulation>, With<Synapse>, With<HeatOverlay>, With<LightSource>, With<Projector>)>;
+/// Every entity spawned for a scene, for despawning it.
+#[cfg(not(feature = "render"))]
+pub type SceneEntities = Or<(With<Neuron>, With<Segment>, With<Junction>, With<stimulator::Stimulation>, With<Synapse>, With<HeatOverlay>, With<LightSource>, With<Projector>)>;
+
+#[derive(Clone, Component)]
 pub struct Synapse {
     pub pre_segment: Entity,
     pub post_segment: Entity,
@@ -319,8 +604,6 @@ pub fn spawn_synapse(
     commands: &mut Commands,
     synapse: &serialize::Synapse,
     neurons_and_segments: &Vec<(Entity, Vec<Entity>)>,
-    _meshes: &mut ResMut<Assets<Mesh>>,
-    _materials: &mut ResMut<Assets<StandardMaterial>>
 ) {
     if let Ok(parsed_synapse_membranes) = SynapseMembranes::deserialize(&synapse.synapse_membranes) {
         let pre_segment = neurons_and_segments[synapse.pre_neuron].1[synapse.pre_segment];
@@ -332,19 +615,41 @@ pub fn spawn_synapse(
     }
 }
 
+/// The segment under the pointer, if any.
+#[derive(Default, Resource)]
+pub struct HoveredSegment(pub Option<Entity>);
+
+#[cfg(feature = "render")]
+pub fn hover_segment(event: Listener<Pointer<Over>>, mut hovered: ResMut<HoveredSegment>) {
+    hovered.0 = Some(event.listener());
+}
+
+#[cfg(feature = "render")]
+pub fn unhover_segment(event: Listener<Pointer<Out>>, mut hovered: ResMut<HoveredSegment>) {
+    if hovered.0 == Some(event.listener()) {
+        hovered.0 = None;
+    }
+}
+
+#[cfg(feature = "render")]
 pub fn add_stimulation(
     event: Listener<Pointer<Click>>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut visuals: SceneVisuals,
     mut oscilloscope: ResMut<Oscilloscope>,
     mut next_click: ResMut<NextClickAction>,
-    selections: Query<Entity, With<Selection>>,
-    highlights: Query<Entity, With<Highlight>>,
+    highlight_parents: Query<(Entity, &Parent), With<Highlight>>,
+    keys: Res<ButtonInput<KeyCode>>,
     new_stimulators: Res<stimulator::Stimulator>,
     segments_query: Query<(Entity, &Segment, &GlobalTransform)>
 ) {
-    match segments_query.get(event.target) {
+    // The listener rather than the target, which is the segment's picking
+    // proxy or highlight.
+    let segment = event.listener();
+    if toggle_on_shift_click(&mut commands, &keys, &mut visuals.meshes, &mut visuals.materials, &visuals.selections, &highlight_parents, segment) {
+        return;
+    }
+    match segments_query.get(segment) {
         Ok((entity, _, segment_transform)) => {
 
           match *next_click {
@@ -352,22 +657,15 @@ pub fn add_stimulation(
                 oscilloscope.accept_source(i, entity);
                 *next_click = NextClickAction::ModifyStimulator;
               },
+              NextClickAction::AttachProbe => {
+                commands.entity(entity).insert(Probe::new(format!("probe_{}", entity.index())));
+                *next_click = NextClickAction::ModifyStimulator;
+              },
               NextClickAction::ModifyStimulator => {
-                commands.spawn(
-                    (stimulator::Stimulation { stimulation_segment: event.target },
-                    PbrBundle {
-                        mesh: meshes.add(Sphere{ radius: 7.5 }),
-                        material: materials.add(Color::rgb(0.5,0.5,0.5)),
-                        transform: Transform::from_translation(segment_transform.translation()),
-                        ..default()
-                    },
-                    PickableBundle::default(),
-                    On::<Pointer::<Click>>::run(handle_click_stimulator),
-                    )
-                );
-                eprintln!("Inserting stimulator into entity {}", event.target.to_bits());
-                commands.entity(event.target).insert(new_stimulators.clone());
-                select_stimulator(event.target, commands, selections, highlights, meshes, materials);
+                spawn_stimulation(&mut commands, &mut visuals, segment, segment_transform.translation());
+                eprintln!("Inserting stimulator into entity {}", segment.to_bits());
+                commands.entity(segment).insert(new_stimulators.clone());
+                select_stimulator(segment, commands, visuals);
               }
           }
         },
@@ -377,44 +675,59 @@ pub fn add_stimulation(
     }
 }
 
+/// Spawn the clickable marker for a stimulator on `segment`.
+pub fn spawn_stimulation(
+    commands: &mut Commands,
+    #[cfg_attr(not(feature = "render"), allow(unused_variables))]
+    visuals: &mut SceneVisuals,
+    segment: Entity,
+    translation: Vec3,
+) -> Entity {
+    #[cfg_attr(not(feature = "render"), allow(unused_mut))]
+    let mut stimulation = commands.spawn(
+        (stimulator::Stimulation { stimulation_segment: segment },
+         TransformBundle::from_transform(Transform::from_translation(translation)),
+        )
+    );
+    #[cfg(feature = "render")]
+    stimulation.insert(
+        (visuals.meshes.add(Sphere{ radius: 7.5 }),
+         visuals.materials.add(Color::rgb(0.5,0.5,0.5)),
+         VisibilityBundle::default(),
+         PickableBundle::default(),
+         On::<Pointer::<Click>>::run(handle_click_stimulator),
+        )
+    );
+    stimulation.id()
+}
+
+#[cfg(feature = "render")]
 pub fn select_stimulator(
     segment_entity: Entity,
     mut commands: Commands,
-    selections: Query<Entity, With<Selection>>,
-    highlights: Query<Entity, With<Highlight>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut visuals: SceneVisuals,
 ) {
-    deselect_all(&mut commands, &selections, &highlights);
-    spawn_highlight(&mut commands, &mut meshes, &mut materials, segment_entity.clone());
+    deselect_all(&mut commands, &visuals.selections, &visuals.highlights);
+    spawn_highlight(&mut commands, &mut visuals.meshes, &mut visuals.materials, segment_entity.clone());
     commands.entity(segment_entity).insert(Selection);
     eprintln!("inserting Selection into entity {}", segment_entity.to_bits());
     commands.entity(segment_entity).insert(Selection);
 }
 
+#[cfg(feature = "render")]
 pub fn handle_click_stimulator(
     event: Listener<Pointer<Click>>,
     commands: Commands,
     mut stimulations_query: Query<&stimulator::Stimulation>,
     segments_query: Query<(&Segment, Entity, &stimulator::Stimulator)>,
-    selections: Query<Entity, With<Selection>>,
-    highlights: Query<Entity, With<Highlight>>,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
+    visuals: SceneVisuals,
 ) {
     if let Ok(stimulator::Stimulation { stimulation_segment }) = stimulations_query.get_mut(event.target) {
         let results = segments_query.get(stimulation_segment.clone());
         match results {
             Ok((_, segment_entity, _)) => {
                 eprintln!("Ok, seeing a stimulator. Selecting its entity.");
-                select_stimulator(
-                    segment_entity,
-                    commands,
-                    selections,
-                    highlights,
-                    meshes,
-                    materials,
-                );
+                select_stimulator(segment_entity, commands, visuals);
             },
             Err(e) => {
                 eprintln!("Error in select_stimulator: {:?}", e);
@@ -423,6 +736,7 @@ pub fn handle_click_stimulator(
     }
 }
 
+#[cfg(feature = "render")]
 pub fn delete_stimulations(
     In(event): In<Pointer<Click>>,
     mut commands: Commands,
@@ -485,14 +799,22 @@ pub mod sample {
                         stimulator: stimulator.clone(),
                         segment: 100,
                     }
-                ]
+                ],
+                tag: None,
+                thermal_segments: vec![],
+                noise: None,
+                membrane_overrides: vec![],
             }
             , serialize::SceneNeuron {
                 neuron: n.clone(),
                 location: serialize::Location {
                     x_mm: -0.4, y_mm: 0.5, z_mm: 0.0
                 },
-                stimulator_segments: vec![]
+                stimulator_segments: vec![],
+                tag: None,
+                thermal_segments: vec![],
+                noise: None,
+                membrane_overrides: vec![],
             }
             ],
 
@@ -503,6 +825,10 @@ pub mod sample {
                 post_segment: 333,
                 synapse_membranes: synapse::examples::excitatory_synapse(&MilliVolts(-80.0)).serialize(),
             }],
+            constants: None,
+            lights: vec![],
+            projectors: vec![],
+            seed: None,
         }
 
     }
@@ -532,4 +858,80 @@ pub mod tests {
         let neuron : serialize::Neuron = sample::neuron();
     }
 
+    #[test]
+    fn segments_take_their_swc_geometry() {
+        let entry = |id, type_, x, r, parent| serialize::Segment { id, type_, x, y: 0.0, z: 0.0, r, parent };
+        let soma = entry(1, 1, 0.0, 10.0, -1);
+        let trunk = entry(2, 3, 20.0, 2.0, 1);
+        let branch = entry(3, 3, 30.0, 1.0, 2);
+        let stacked = entry(4, 3, 30.0, 0.0, 3);
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6 * b;
+        assert!(close(segment_geometry(&soma, None).surface_area(), std::f32::consts::PI * 20e-4f32.powi(2)));
+        // 20 µm from the soma, leaving it at its own diameter.
+        let (start, end) = segment_geometry(&trunk, Some(&soma)).end_diameters();
+        assert!(close(start, 4e-4) && close(end, 4e-4));
+        assert!(close(segment_geometry(&trunk, Some(&soma)).length(), 20e-4));
+        // Tapering from 4 µm to 2 µm over 10 µm.
+        let tapered = segment_geometry(&branch, Some(&trunk));
+        let (start, end) = tapered.end_diameters();
+        assert!(close(start, 4e-4) && close(end, 2e-4));
+        assert!(close(tapered.length(), 10e-4));
+        // A point on top of its parent still has membrane.
+        assert!(segment_geometry(&stacked, Some(&branch)).surface_area() > 0.0);
+    }
+
+    #[test]
+    fn path_distance_follows_the_parents() {
+        let entry = |id, type_, x, y, parent| serialize::Segment { id, type_, x, y, z: 0.0, r: 1.0, parent };
+        let neuron = serialize::Neuron {
+            segments: vec![entry(1, 1, 0.0, 0.0, -1), entry(2, 3, 3.0, 4.0, 1), entry(3, 3, 3.0, 10.0, 2), entry(4, 3, 0.0, 0.0, 9)],
+            membranes: vec![],
+        };
+        let close = |a: Option<f32>, b: f32| a.is_some_and(|a| (a - b).abs() < 1e-4);
+        assert!(close(path_to_soma_microns(&neuron, 1), 0.0));
+        assert!(close(path_to_soma_microns(&neuron, 3), 11.0));
+        assert_eq!(path_to_soma_microns(&neuron, 4), None);
+        let all = paths_to_soma_microns(&neuron);
+        for id in 1..=3 {
+            assert!(close(all.get(&id).copied(), path_to_soma_microns(&neuron, id).unwrap()));
+        }
+        assert!(!all.contains_key(&4));
+        assert_eq!(swc_type_name(3), "Basal dendrite");
+        assert_eq!(swc_type_name(7), "Type 7");
+    }
+
+    #[test]
+    fn edited_segments_become_overrides() {
+        use crate::neuron::channel::common_channels::rat_ca1::HCN_CHANNEL_DENDRITE;
+        let entry = |id, type_, x, parent| serialize::Segment { id, type_, x, y: 0.0, z: 0.0, r: 1.0, parent };
+        let loaded = serialize::Membrane {
+            membrane_channels: vec![serialize::MembraneChannel {
+                channel: HCN_CHANNEL_DENDRITE.build(&MilliVolts(-70.0)).serialize(),
+                siemens_per_square_cm: 0.0,
+                density: Some("2e-5 * exp(d / 100)".to_string()),
+            }],
+            capacitance_farads_per_square_cm: 1e-6,
+        };
+        let neuron = serialize::Neuron {
+            segments: vec![entry(1, 1, 0.0, -1), entry(2, 3, 50.0, 1), entry(3, 3, 100.0, 2)],
+            membranes: vec![loaded.clone(), loaded.clone(), loaded.clone()],
+        };
+        let at = |d| density::membrane_at(&loaded, d).unwrap().serialize();
+        let mut edited = at(100.0);
+        edited.capacitance_farads_per_square_cm = 2e-6;
+
+        // Segment 2 still follows the rule, so it stays and only 3 is saved.
+        let (membrane, overrides) = type_membranes(&neuron, &loaded, vec![(2, at(50.0)), (3, edited.clone())]);
+        assert!(membrane.membrane_channels[0].density.is_some());
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[0].segment, 3);
+        assert!(same_membrane(&overrides[0].membrane, &edited));
+
+        // With every segment edited, the first one's membrane stands in.
+        let (membrane, overrides) = type_membranes(&neuron, &loaded, vec![(2, edited.clone()), (3, edited.clone())]);
+        assert!(same_membrane(&membrane, &edited));
+        assert!(overrides.is_empty());
+    }
+
 }
@@ -0,0 +1,456 @@
+//! Import of NEURON hoc morphologies.
+//!
+//! Reads the `create`, `connect`, `access`, `pt3dclear` and `pt3dadd`
+//! statements of a hoc file (including the `sec { ... }` and
+//! `sec connect ...` forms written by NEURON's Import3D exporter) into the
+//! same SWC-style segment list used for SWC neurons. Everything else in
+//! the file is skipped.
+
+use std::collections::HashMap;
+
+use crate::serialize;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f32),
+    Symbol(char),
+}
+
+/// A 3D point with its diameter, in microns.
+#[derive(Clone, Debug)]
+struct Point {
+    x: f32,
+    y: f32,
+    z: f32,
+    d: f32,
+}
+
+#[derive(Clone, Debug)]
+struct Connection {
+    child: String,
+    child_x: f32,
+    parent: String,
+    parent_x: f32,
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut out = String::new();
+    let mut rest = source;
+    while !rest.is_empty() {
+        if rest.starts_with("//") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            rest = &rest[end..];
+        } else if rest.starts_with("/*") {
+            let end = rest.find("*/").map_or(rest.len(), |i| i + 2);
+            rest = &rest[end..];
+        } else {
+            let c = rest.chars().next().expect("non-empty");
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = strip_comments(source).chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || chars[i] == '.'
+                    || ((chars[i] == 'e' || chars[i] == 'E') && i + 1 < chars.len())
+                    || ((chars[i] == '-' || chars[i] == '+') && (chars[i - 1] == 'e' || chars[i - 1] == 'E')))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            match text.parse::<f32>() {
+                Ok(n) => tokens.push(Token::Number(n)),
+                Err(_) => tokens.push(Token::Ident(text)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(Token::Symbol(c));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+struct Scanner {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Scanner {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}', found {:?}", c, self.peek()))
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        let sign = if self.eat('-') { -1.0 } else { self.eat('+'); 1.0 };
+        match self.next() {
+            Some(Token::Number(n)) => Ok(sign * n),
+            t => Err(format!("Expected number, found {:?}", t)),
+        }
+    }
+
+    /// An optional `[n]` index.
+    fn index(&mut self) -> Result<Option<usize>, String> {
+        if self.eat('[') {
+            let n = self.number()?;
+            self.expect(']')?;
+            Ok(Some(n as usize))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// A section reference followed by its `(x)` position.
+    fn section_at(&mut self, sections: &Sections) -> Result<(String, f32), String> {
+        let name = match self.next() {
+            Some(Token::Ident(name)) => name,
+            t => return Err(format!("Expected section name, found {:?}", t)),
+        };
+        let index = self.index()?;
+        let section = sections.resolve(&name, index)?;
+        let x = if self.eat('(') {
+            let x = self.number()?;
+            self.expect(')')?;
+            x
+        } else {
+            1.0
+        };
+        Ok((section, x))
+    }
+}
+
+/// Declared sections, in declaration order, with their 3D points.
+#[derive(Default)]
+struct Sections {
+    arrays: HashMap<String, usize>,
+    order: Vec<String>,
+    points: HashMap<String, Vec<Point>>,
+}
+
+impl Sections {
+    fn declare(&mut self, name: String, size: Option<usize>) {
+        match size {
+            None => {
+                self.order.push(name.clone());
+                self.points.insert(name, vec![]);
+            }
+            Some(n) => {
+                for i in 0..n {
+                    let full_name = format!("{}[{}]", name, i);
+                    self.order.push(full_name.clone());
+                    self.points.insert(full_name, vec![]);
+                }
+                self.arrays.insert(name, n);
+            }
+        }
+    }
+
+    fn is_section(&self, name: &str) -> bool {
+        self.arrays.contains_key(name) || self.points.contains_key(name)
+    }
+
+    fn resolve(&self, name: &str, index: Option<usize>) -> Result<String, String> {
+        let full_name = match (index, self.arrays.get(name)) {
+            (Some(i), Some(n)) if i < *n => format!("{}[{}]", name, i),
+            (Some(i), _) => return Err(format!("No section {}[{}]", name, i)),
+            (None, Some(_)) => format!("{}[0]", name),
+            (None, None) => name.to_string(),
+        };
+        if self.points.contains_key(&full_name) {
+            Ok(full_name)
+        } else {
+            Err(format!("No section {}", full_name))
+        }
+    }
+}
+
+/// The SWC type of a section, from NEURON's naming conventions.
+fn swc_type(section: &str) -> usize {
+    let base = section.split('[').next().unwrap_or(section).to_lowercase();
+    if base.starts_with("soma") {
+        1
+    } else if base.starts_with("axon") || base.starts_with("node") || base.starts_with("myelin") {
+        2
+    } else if base.starts_with("apic") {
+        4
+    } else {
+        3
+    }
+}
+
+/// Read a hoc morphology as SWC-style segments: one segment per 3D point,
+/// with each section's first point attached to the point of its parent
+/// section nearest the connection position.
+pub fn segments(source: &str) -> Result<Vec<serialize::Segment>, String> {
+    let mut scanner = Scanner { tokens: tokenize(source), pos: 0 };
+    let mut sections = Sections::default();
+    let mut connections: Vec<Connection> = vec![];
+    let mut accessed: Option<String> = None;
+    // The section made current by each open brace, if any.
+    let mut stack: Vec<Option<String>> = vec![];
+    // A section prefixing the next single statement.
+    let mut prefix: Option<String> = None;
+
+    while let Some(token) = scanner.next() {
+        let current = prefix
+            .clone()
+            .or(stack.iter().rev().find_map(|s| s.clone()))
+            .or(accessed.clone());
+        match token {
+            Token::Ident(word) if word == "create" => {
+                loop {
+                    match scanner.next() {
+                        Some(Token::Ident(name)) => {
+                            let size = scanner.index()?;
+                            sections.declare(name, size);
+                        }
+                        t => return Err(format!("Expected section name after create, found {:?}", t)),
+                    }
+                    if !scanner.eat(',') {
+                        break;
+                    }
+                }
+            }
+            Token::Ident(word) if word == "access" => {
+                accessed = Some(scanner.section_at(&sections)?.0);
+            }
+            Token::Ident(word) if word == "connect" => {
+                let (child, child_x) = scanner.section_at(&sections)?;
+                scanner.expect(',')?;
+                let (parent, parent_x) = match scanner.peek() {
+                    Some(Token::Ident(_)) => scanner.section_at(&sections)?,
+                    _ => {
+                        // `parent connect child(x), y`
+                        let y = scanner.number()?;
+                        let parent = current.clone().ok_or("connect without a parent section".to_string())?;
+                        (parent, y)
+                    }
+                };
+                connections.push(Connection { child, child_x, parent, parent_x });
+                prefix = None;
+            }
+            Token::Ident(word) if word == "pt3dclear" => {
+                if scanner.eat('(') {
+                    while !scanner.eat(')') {
+                        scanner.next().ok_or("Unclosed pt3dclear(".to_string())?;
+                    }
+                }
+                let section = current.ok_or("pt3dclear outside of a section".to_string())?;
+                sections.points.insert(section, vec![]);
+                prefix = None;
+            }
+            Token::Ident(word) if word == "pt3dadd" => {
+                scanner.expect('(')?;
+                let x = scanner.number()?;
+                scanner.expect(',')?;
+                let y = scanner.number()?;
+                scanner.expect(',')?;
+                let z = scanner.number()?;
+                scanner.expect(',')?;
+                let d = scanner.number()?;
+                scanner.expect(')')?;
+                let section = current.ok_or("pt3dadd outside of a section".to_string())?;
+                sections.points.get_mut(&section).expect("resolved section").push(Point { x, y, z, d });
+                prefix = None;
+            }
+            Token::Ident(word) if sections.is_section(&word) => {
+                let index = scanner.index()?;
+                let section = sections.resolve(&word, index)?;
+                if scanner.eat('{') {
+                    stack.push(Some(section));
+                } else {
+                    prefix = Some(section);
+                }
+            }
+            Token::Symbol('{') => stack.push(None),
+            Token::Symbol('}') => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    to_segments(&sections, &connections)
+}
+
+fn to_segments(sections: &Sections, connections: &[Connection]) -> Result<Vec<serialize::Segment>, String> {
+    let empty: Vec<&String> = sections.order.iter().filter(|s| sections.points[*s].is_empty()).collect();
+    if !empty.is_empty() {
+        return Err(format!("Sections without 3D points: {:?}", empty));
+    }
+
+    let parent_of: HashMap<&str, &Connection> =
+        connections.iter().map(|c| (c.child.as_str(), c)).collect();
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    for c in connections.iter() {
+        children.entry(c.parent.as_str()).or_default().push(c.child.as_str());
+    }
+
+    // Roots first (the soma, if there is one), then breadth-first, so that
+    // every parent point is numbered before its children.
+    let mut roots: Vec<&str> = sections
+        .order
+        .iter()
+        .map(|s| s.as_str())
+        .filter(|s| !parent_of.contains_key(s))
+        .collect();
+    roots.sort_by_key(|s| swc_type(s) != 1);
+
+    let mut segments: Vec<serialize::Segment> = vec![];
+    let mut point_ids: HashMap<&str, Vec<i32>> = HashMap::new();
+    let mut queue: std::collections::VecDeque<&str> = roots.into_iter().collect();
+    while let Some(section) = queue.pop_front() {
+        if point_ids.contains_key(section) {
+            return Err(format!("Section {} is connected more than once", section));
+        }
+        let points = &sections.points[section];
+        let mut parent_id = match parent_of.get(section) {
+            None => -1,
+            Some(connection) => {
+                let parent_ids = &point_ids[connection.parent.as_str()];
+                let i = (connection.parent_x.clamp(0.0, 1.0) * (parent_ids.len() - 1) as f32).round() as usize;
+                parent_ids[i]
+            }
+        };
+        let reversed = parent_of.get(section).is_some_and(|c| c.child_x > 0.5);
+        let ordered: Vec<&Point> = if reversed {
+            points.iter().rev().collect()
+        } else {
+            points.iter().collect()
+        };
+
+        let mut ids = vec![];
+        for (i, p) in ordered.iter().enumerate() {
+            // A child's first point usually repeats the parent's
+            // attachment point; SWC shares that point instead.
+            if i == 0 && parent_id != -1 && ordered.len() > 1 {
+                let parent = &segments[(parent_id - 1) as usize];
+                let gap = ((p.x - parent.x).powi(2) + (p.y - parent.y).powi(2) + (p.z - parent.z).powi(2)).sqrt();
+                if gap < 1e-3 {
+                    ids.push(parent_id);
+                    continue;
+                }
+            }
+            let id = segments.len() as i32 + 1;
+            segments.push(serialize::Segment {
+                id,
+                type_: swc_type(section),
+                x: p.x,
+                y: p.y,
+                z: p.z,
+                r: p.d / 2.0,
+                parent: parent_id,
+            });
+            ids.push(id);
+            parent_id = id;
+        }
+        if reversed {
+            ids.reverse();
+        }
+        point_ids.insert(section, ids);
+        queue.extend(children.get(section).cloned().unwrap_or_default());
+    }
+
+    if let Some(unreached) = sections.order.iter().find(|s| !point_ids.contains_key(s.as_str())) {
+        return Err(format!("Section {} is part of a connection cycle", unreached));
+    }
+    Ok(segments)
+}
+
+/// Read a hoc morphology as a neuron, using `membranes` (indexed by SWC
+/// type, as for SWC neurons) for its segments.
+pub fn neuron(source: &str, membranes: Vec<serialize::Membrane>) -> Result<serialize::Neuron, String> {
+    Ok(serialize::Neuron { segments: segments(source)?, membranes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL: &str = r#"
+/* A soma with an axon and two dendrites. */
+create soma, axon, dend[2]
+access soma
+
+soma {
+    pt3dclear()
+    pt3dadd(0, 0, 0, 10)
+    pt3dadd(10, 0, 0, 10)
+}
+axon { pt3dclear() pt3dadd(0, 0, 0, 1) pt3dadd(-50, 0, 0, 1) }
+dend[0] pt3dadd(10, 0, 0, 2)
+dend[0] pt3dadd(30, 5, 0, 2)
+dend[0] pt3dadd(50, 10, 0, 1.5)
+dend[1] {pt3dclear() pt3dadd(50, 10, 0, 1) pt3dadd(60, 30, 0, 1)}
+
+connect axon(0), soma(0)
+soma connect dend[0](0), 1
+connect dend[1](0), dend[0](1) // branch off the end of dend[0]
+"#;
+
+    #[test]
+    fn reads_sections_into_segments() {
+        let segments = segments(CELL).expect("should parse");
+        // 2 soma points, 1 new axon point, 2 new dend[0] points and 1 new
+        // dend[1] point.
+        assert_eq!(segments.len(), 6);
+        assert_eq!(segments[0].type_, 1);
+        assert_eq!(segments[0].parent, -1);
+
+        let axon = segments.iter().find(|s| s.type_ == 2).expect("axon");
+        assert_eq!(axon.parent, 1);
+        assert_eq!(axon.r, 0.5);
+
+        let dend_tip = segments.last().expect("dend[1]");
+        assert_eq!((dend_tip.x, dend_tip.y), (60.0, 30.0));
+        let dend_base = segments.iter().find(|s| s.id == dend_tip.parent).expect("parent");
+        assert_eq!((dend_base.x, dend_base.y), (50.0, 10.0));
+    }
+
+    #[test]
+    fn rejects_sections_without_points() {
+        assert!(segments("create soma, dend\nsoma pt3dadd(0,0,0,1)").is_err());
+    }
+}
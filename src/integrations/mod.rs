@@ -1 +1,8 @@
+pub mod allen;
+pub mod dhall;
 pub mod grace;
+pub mod hoc;
+// Exports runs from the GUI's run registry.
+#[cfg(feature = "render")]
+pub mod nwb;
+pub mod swc;
@@ -0,0 +1,208 @@
+//! Export saved runs as Neurodata Without Borders (NWB 2.x) files.
+//!
+//! Each voltage trace becomes a `CurrentClampSeries` under `/acquisition`,
+//! each stimulator current a `TimeSeries` under `/stimulus/presentation`,
+//! and the spike times of every trace a row of the `/units` table. The
+//! oscilloscope samples neither absolute current nor pipette properties,
+//! so stimuli keep their density units and the electrode is nominal.
+//!
+//! NWB is built on HDF5, so the exporter is only available in native
+//! builds with the `nwb` feature enabled.
+
+use crate::analysis::SPIKE_THRESHOLD;
+use crate::clock::iso8601;
+use crate::gui::runs::Run;
+
+pub const NWB_VERSION: &str = "2.6.0";
+
+/// Write `run` to an NWB file at `path`. `session_start_unix_seconds` is
+/// the wall-clock time at which the simulation run began.
+#[cfg(all(feature = "nwb", not(target_arch = "wasm32")))]
+pub fn write(path: &str, run: &Run, session_start_unix_seconds: f64) -> Result<(), String> {
+    hdf5_writer::write(path, run, session_start_unix_seconds)
+        .map_err(|e| format!("Could not write NWB file {}: {}", path, e))
+}
+
+#[cfg(not(all(feature = "nwb", not(target_arch = "wasm32"))))]
+pub fn write(_path: &str, _run: &Run, _session_start_unix_seconds: f64) -> Result<(), String> {
+    Err("NWB export requires a native build with the `nwb` feature.".to_string())
+}
+
+/// Offset (seconds) from the start of the simulation to the first sample
+/// of the run's traces. Trace times start at zero at the oldest sample in
+/// the oscilloscope buffer, and the newest sample is the time of saving.
+fn first_sample_time(run: &Run) -> f64 {
+    let buffer_span = run
+        .traces
+        .iter()
+        .filter_map(|t| t.times.last())
+        .cloned()
+        .fold(0.0, f32::max);
+    (run.simulation_time.0 - buffer_span).max(0.0) as f64
+}
+
+/// Spike times of each voltage trace, in seconds since the start of the
+/// simulation.
+fn spike_times(run: &Run) -> Vec<(String, Vec<f64>)> {
+    let offset = first_sample_time(run);
+    run.traces
+        .iter()
+        .map(|trace| {
+            let times = trace
+                .spike_times(&SPIKE_THRESHOLD)
+                .into_iter()
+                .map(|t| t as f64 + offset)
+                .collect();
+            (trace.label.clone(), times)
+        })
+        .collect()
+}
+
+#[cfg(all(feature = "nwb", not(target_arch = "wasm32")))]
+mod hdf5_writer {
+    use hdf5::types::VarLenUnicode;
+    use hdf5::{File, Group, Location, Result};
+
+    use super::{first_sample_time, iso8601, spike_times, Run, NWB_VERSION};
+    use crate::analysis::Trace;
+    use crate::clock::unix_seconds_now;
+
+    const DEVICE: &str = "/general/devices/nb-sim";
+    const ELECTRODE: &str = "/general/intracellular_ephys/electrode";
+
+    pub fn write(path: &str, run: &Run, session_start_unix_seconds: f64) -> Result<()> {
+        let file = File::create(path)?;
+        neurodata_type(&file, "NWBFile")?;
+        string_attr(&file, "nwb_version", NWB_VERSION)?;
+
+        string_dataset(&file, "identifier", &uuid::Uuid::new_v4().to_string())?;
+        string_dataset(&file, "session_description", &run.name)?;
+        string_dataset(&file, "session_start_time", &iso8601(session_start_unix_seconds))?;
+        string_dataset(&file, "timestamps_reference_time", &iso8601(session_start_unix_seconds))?;
+        file.new_dataset_builder()
+            .with_data(&[unicode(&iso8601(unix_seconds_now()))?][..])
+            .create("file_create_date")?;
+
+        let general = file.create_group("general")?;
+        let devices = general.create_group("devices")?;
+        let device = devices.create_group("nb-sim")?;
+        neurodata_type(&device, "Device")?;
+        string_attr(&device, "description", "NeuronBench simulator")?;
+        let intracellular_ephys = general.create_group("intracellular_ephys")?;
+        let electrode = intracellular_ephys.create_group("electrode")?;
+        neurodata_type(&electrode, "IntracellularElectrode")?;
+        string_dataset(&electrode, "description", "Simulated ideal electrode")?;
+        electrode.link_soft(DEVICE, "device")?;
+
+        let offset = first_sample_time(run);
+        let acquisition = file.create_group("acquisition")?;
+        for trace in run.traces.iter() {
+            let series = time_series(&acquisition, &format!("voltage_{}", trace.label), trace, offset, "volts", 1e-3)?;
+            neurodata_type(&series, "CurrentClampSeries")?;
+            series.link_soft(ELECTRODE, "electrode")?;
+        }
+
+        let stimulus = file.create_group("stimulus")?;
+        let presentation = stimulus.create_group("presentation")?;
+        stimulus.create_group("templates")?;
+        for trace in run.stimuli.iter() {
+            let series = time_series(&presentation, &format!("stimulus_{}", trace.label), trace, offset, "uA/cm^2", 1.0)?;
+            neurodata_type(&series, "TimeSeries")?;
+        }
+
+        file.create_group("analysis")?;
+        file.create_group("processing")?;
+        write_units(&file, &spike_times(run))?;
+        Ok(())
+    }
+
+    fn time_series(parent: &Group, name: &str, trace: &Trace, offset: f64, unit: &str, conversion: f32) -> Result<Group> {
+        let series = parent.create_group(name)?;
+        string_attr(&series, "description", &format!("Oscilloscope channel {}", trace.label))?;
+        let data = series.new_dataset_builder().with_data(trace.values.as_slice()).create("data")?;
+        string_attr(&data, "unit", unit)?;
+        data.new_attr::<f32>().create("conversion")?.write_scalar(&conversion)?;
+        data.new_attr::<f32>().create("resolution")?.write_scalar(&-1.0f32)?;
+        let timestamps = trace.times.iter().map(|t| *t as f64 + offset).collect::<Vec<_>>();
+        let timestamps = series.new_dataset_builder().with_data(timestamps.as_slice()).create("timestamps")?;
+        timestamps.new_attr::<i32>().create("interval")?.write_scalar(&1)?;
+        string_attr(&timestamps, "unit", "seconds")?;
+        Ok(series)
+    }
+
+    /// The `/units` dynamic table: one row per traced source, with its spike
+    /// times stored ragged in `spike_times` and indexed by `spike_times_index`.
+    fn write_units(file: &File, spikes: &[(String, Vec<f64>)]) -> Result<()> {
+        let units = file.create_group("units")?;
+        neurodata_type(&units, "Units")?;
+        string_attr(&units, "description", "Threshold crossings of each oscilloscope channel")?;
+        units.new_attr_builder()
+            .with_data(&[unicode("spike_times")?][..])
+            .create("colnames")?;
+
+        let ids = (0..spikes.len() as i64).collect::<Vec<_>>();
+        let id = units.new_dataset_builder().with_data(ids.as_slice()).create("id")?;
+        neurodata_type_in(&id, "ElementIdentifiers", "hdmf-common")?;
+
+        let all_times = spikes.iter().flat_map(|(_, t)| t.iter().cloned()).collect::<Vec<_>>();
+        let spike_times = units.new_dataset_builder().with_data(all_times.as_slice()).create("spike_times")?;
+        neurodata_type_in(&spike_times, "VectorData", "hdmf-common")?;
+        string_attr(&spike_times, "description", "Spike times, in seconds")?;
+
+        let ends = spikes
+            .iter()
+            .scan(0u64, |end, (_, t)| { *end += t.len() as u64; Some(*end) })
+            .collect::<Vec<_>>();
+        let index = units.new_dataset_builder().with_data(ends.as_slice()).create("spike_times_index")?;
+        neurodata_type_in(&index, "VectorIndex", "hdmf-common")?;
+        string_attr(&index, "description", "Index into spike_times")?;
+        string_attr(&index, "target", "/units/spike_times")
+    }
+
+    fn neurodata_type(location: &Location, name: &str) -> Result<()> {
+        neurodata_type_in(location, name, "core")
+    }
+
+    fn neurodata_type_in(location: &Location, name: &str, namespace: &str) -> Result<()> {
+        string_attr(location, "neurodata_type", name)?;
+        string_attr(location, "namespace", namespace)?;
+        string_attr(location, "object_id", &uuid::Uuid::new_v4().to_string())
+    }
+
+    fn string_attr(location: &Location, name: &str, value: &str) -> Result<()> {
+        location.new_attr::<VarLenUnicode>().create(name)?.write_scalar(&unicode(value)?)
+    }
+
+    fn string_dataset(group: &Group, name: &str, value: &str) -> Result<()> {
+        group.new_dataset::<VarLenUnicode>().create(name)?.write_scalar(&unicode(value)?)
+    }
+
+    fn unicode(s: &str) -> Result<VarLenUnicode> {
+        s.parse::<VarLenUnicode>().map_err(|e| e.to_string().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Trace;
+    use crate::dimension::{SimulationStepSeconds, StepsPerFrame, Timestamp};
+
+    #[test]
+    fn spike_times_are_relative_to_simulation_start() {
+        let mut trace = Trace::new("1".to_string());
+        for (t, v) in [(0.0, -70.0), (0.5, 20.0), (1.0, -70.0), (1.5, 20.0)] {
+            trace.push(t, v);
+        }
+        let run = Run {
+            name: "Run 1".to_string(),
+            saved_at_unix_seconds: 0.0,
+            simulation_time: Timestamp(11.5),
+            simulation_step: SimulationStepSeconds(5e-7),
+            steps_per_frame: StepsPerFrame(100),
+            traces: vec![trace],
+            stimuli: vec![],
+        };
+        assert_eq!(spike_times(&run), vec![("1".to_string(), vec![10.5, 11.5])]);
+    }
+}
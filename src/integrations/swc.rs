@@ -0,0 +1,103 @@
+//! Reading and writing SWC morphologies.
+//!
+//! SWC is one line per point: `id type x y z radius parent`, with `#`
+//! comments. Writing renumbers the segments from 1 in depth-first order so
+//! that every parent precedes its children, which gaps left by
+//! `grace::simplify` would otherwise break in some readers.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::integrations::grace::get_children;
+use crate::serialize;
+
+/// Parse SWC text into segments.
+pub fn segments(source: &str) -> Result<Vec<serialize::Segment>, String> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() < 7 {
+                return Err(format!("Line {}: expected 7 fields, found {}", i + 1, fields.len()));
+            }
+            let int = |s: &str| s.parse::<i32>().map_err(|e| format!("Line {}: {}: {}", i + 1, s, e));
+            let float = |s: &str| s.parse::<f32>().map_err(|e| format!("Line {}: {}: {}", i + 1, s, e));
+            Ok(serialize::Segment {
+                id: int(fields[0])?,
+                type_: int(fields[1])?.max(0) as usize,
+                x: float(fields[2])?,
+                y: float(fields[3])?,
+                z: float(fields[4])?,
+                r: float(fields[5])?,
+                parent: int(fields[6])?,
+            })
+        })
+        .collect()
+}
+
+/// Write a neuron's segments as SWC text.
+pub fn write(neuron: &serialize::Neuron) -> String {
+    let children = get_children(neuron);
+    let by_id = neuron.segments.iter().map(|s| (s.id, s)).collect::<HashMap<_, _>>();
+
+    // Depth-first from every root, so parents are written first.
+    let mut order = vec![];
+    let mut stack = children.get(&-1).cloned().unwrap_or_default();
+    stack.reverse();
+    while let Some(id) = stack.pop() {
+        order.push(id);
+        if let Some(ids) = children.get(&id) {
+            stack.extend(ids.iter().rev());
+        }
+    }
+    let new_ids = order
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i as i32 + 1))
+        .collect::<HashMap<_, _>>();
+
+    let mut out = "# Exported by NeuronBench\n# id type x y z r parent\n".to_string();
+    for id in order.iter() {
+        let s = by_id[id];
+        let parent = new_ids.get(&s.parent).cloned().unwrap_or(-1);
+        writeln!(out, "{} {} {} {} {} {} {}", new_ids[id], s.type_, s.x, s.y, s.z, s.r, parent)
+            .expect("writing to a String");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::simplify;
+
+    const SWC: &str = "# id,type,x,y,z,r,pid
+1 1 0 0 0 5 -1
+2 3 10 0 0 1 1
+3 3 20 0 0 1 2
+4 3 30 0 0 1 3
+5 2 -10 0 0 0.5 1
+";
+
+    #[test]
+    fn simplified_morphology_round_trips() {
+        let neuron = serialize::Neuron { segments: segments(SWC).unwrap(), membranes: vec![] };
+        let simplified = simplify(neuron);
+        let written = segments(&write(&simplified)).unwrap();
+
+        assert_eq!(written.len(), simplified.segments.len());
+        let ids = written.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=written.len() as i32).collect::<Vec<_>>());
+        for s in written.iter() {
+            assert!(s.parent < s.id);
+        }
+    }
+
+    #[test]
+    fn rejects_short_lines() {
+        assert!(segments("1 1 0 0 0 5").is_err());
+    }
+}
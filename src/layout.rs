@@ -0,0 +1,139 @@
+//! Experimental protocol layouts: the stimulators, oscilloscope probes and
+//! recorders placed on a scene, saved apart from its morphology.
+//!
+//! Layouts refer to segments by neuron index and SWC id (`SegmentRef`), so
+//! a protocol set up on one scene can be applied to another whose neurons
+//! share those segment ids.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::gui::oscilloscope::Oscilloscope;
+use crate::integrations::grace::{spawn_stimulation, SceneSegment, SceneVisuals};
+use crate::probe::ProbeSource;
+use crate::recording::csv_recorder::{Quantity, Recorder};
+use crate::serialize::{self, SegmentRef};
+use crate::stimulator::{Stimulation, Stimulator};
+
+/// The layout currently placed on the scene.
+pub fn capture<'a>(
+    segments: impl Iterator<Item = (Entity, &'a SceneSegment, Option<&'a Stimulator>, Option<&'a Recorder>)>,
+    oscilloscope: &Oscilloscope,
+) -> serialize::Layout {
+    let mut layout = serialize::Layout::default();
+    let mut refs = HashMap::new();
+    for (entity, scene_segment, stimulator, recorder) in segments {
+        let at = scene_segment.0;
+        refs.insert(entity, at);
+        if let Some(stimulator) = stimulator {
+            layout.stimulators.push(serialize::LayoutStimulator { at, stimulator: stimulator.serialize() });
+        }
+        if let Some(recorder) = recorder {
+            layout.recorders.push(serialize::LayoutRecorder {
+                at,
+                path: recorder.path.clone(),
+                quantities: recorder.quantities.iter().map(|q| q.serialize()).collect(),
+                decimation: recorder.decimation,
+            });
+        }
+    }
+    for (index, channel) in oscilloscope.channels.iter().enumerate() {
+        if let Some(at) = channel.source.and_then(|entity| refs.get(&entity)) {
+            layout.probes.push(serialize::LayoutProbe { channel: index, at: *at, quantity: channel.quantity.serialize() });
+        }
+    }
+    layout
+}
+
+/// The segments a layout refers to that are not in the scene.
+pub fn missing_segments<T>(layout: &serialize::Layout, scene: &HashMap<SegmentRef, T>) -> Vec<SegmentRef> {
+    let mut missing = layout
+        .stimulators.iter().map(|s| s.at)
+        .chain(layout.probes.iter().map(|p| p.at))
+        .chain(layout.recorders.iter().map(|r| r.at))
+        .filter(|at| !scene.contains_key(at))
+        .collect::<Vec<_>>();
+    missing.sort_by_key(|at| (at.neuron, at.segment));
+    missing.dedup();
+    missing
+}
+
+/// Replace the scene's stimulators, probes and recorders with those of
+/// `layout`. Entries on segments missing from the scene are skipped; the
+/// missing segments are returned.
+pub fn apply(
+    layout: &serialize::Layout,
+    commands: &mut Commands,
+    visuals: &mut SceneVisuals,
+    oscilloscope: &mut Oscilloscope,
+    segments: &Query<(Entity, &SceneSegment, &GlobalTransform)>,
+    stimulations: &Query<(Entity, &Stimulation)>,
+) -> Vec<SegmentRef> {
+    let scene = segments
+        .iter()
+        .map(|(entity, scene_segment, transform)| (scene_segment.0, (entity, transform.translation())))
+        .collect::<HashMap<_, _>>();
+
+    for (stimulation_entity, stimulation) in stimulations.iter() {
+        commands.entity(stimulation.stimulation_segment).remove::<Stimulator>();
+        commands.entity(stimulation_entity).despawn();
+    }
+    for (entity, _, _) in segments.iter() {
+        commands.entity(entity).remove::<Recorder>();
+    }
+    for channel in oscilloscope.channels.iter_mut() {
+        channel.source = None;
+    }
+
+    for entry in layout.stimulators.iter() {
+        if let Some((entity, translation)) = scene.get(&entry.at) {
+            spawn_stimulation(commands, visuals, *entity, *translation);
+            commands.entity(*entity).insert(Stimulator::deserialize(&entry.stimulator));
+        }
+    }
+    for probe in layout.probes.iter() {
+        if let Some((entity, _)) = scene.get(&probe.at) {
+            if probe.channel >= oscilloscope.channels.len() {
+                oscilloscope.set_channel_count(probe.channel + 1);
+            }
+            if probe.channel < oscilloscope.channels.len() {
+                oscilloscope.accept_source(probe.channel, *entity);
+                oscilloscope.channels[probe.channel].quantity = ProbeSource::deserialize(&probe.quantity);
+            }
+        }
+    }
+    for entry in layout.recorders.iter() {
+        if let Some((entity, _)) = scene.get(&entry.at) {
+            let quantities = entry.quantities.iter().map(Quantity::deserialize).collect();
+            commands.entity(*entity).insert(Recorder::new(entry.path.clone(), quantities, entry.decimation));
+        }
+    }
+
+    missing_segments(layout, &scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_missing_segment_once() {
+        let at = |neuron, segment| SegmentRef { neuron, segment };
+        let layout = serialize::Layout {
+            stimulators: vec![],
+            probes: vec![
+                serialize::LayoutProbe { channel: 0, at: at(0, 1), quantity: serialize::ProbedQuantity::Voltage },
+                serialize::LayoutProbe { channel: 1, at: at(0, 7), quantity: serialize::ProbedQuantity::Voltage },
+            ],
+            recorders: vec![serialize::LayoutRecorder {
+                at: at(0, 7),
+                path: "soma.csv".to_string(),
+                quantities: vec![serialize::RecordedQuantity::Voltage],
+                decimation: 1,
+            }],
+        };
+        let scene = [(at(0, 1), ())].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(missing_segments(&layout, &scene), vec![at(0, 7)]);
+    }
+}
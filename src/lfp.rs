@@ -0,0 +1,290 @@
+//! Local field potentials: the extracellular potential an electrode
+//! records from the transmembrane currents of the segments around it.
+//!
+//! The extracellular medium is taken to be infinite, homogeneous and
+//! purely resistive, so each segment's current contributes
+//! `I / (4 pi sigma r)`. A segment is either a point source at its center
+//! or, for cylinders with a known extent, a line source with its current
+//! spread evenly along its axis (Holt & Koch, 1999). Distances are kept
+//! above `MIN_DISTANCE_UM`, so that an electrode inside a segment reads a
+//! large but finite potential.
+//!
+//! The transmembrane current is the ionic current plus the capacitive
+//! current `C dV/dt`; currents injected by stimulators enter the cell
+//! through the pipette rather than the membrane and are left out.
+
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+#[cfg(feature = "render")]
+use bevy::render::primitives::Aabb;
+
+use crate::analysis::Trace;
+use crate::constants::SimulationConstants;
+use crate::dimension::Timestamp;
+use crate::neuron::extracellular_space::{local_solution, ExtracellularSpace};
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::segment::ecs::Segment;
+use crate::neuron::segment::Geometry;
+use crate::neuron::solution::Solution;
+use crate::plugin::Env;
+use crate::units::UnitScale;
+
+/// The conductivity (S/m) of cortical tissue, roughly.
+pub const DEFAULT_CONDUCTIVITY: f32 = 0.3;
+/// The closest (µm) a source is taken to be to an electrode.
+pub const MIN_DISTANCE_UM: f32 = 1.0;
+/// Samples each electrode keeps for analysis.
+pub const ELECTRODE_HISTORY_LEN: usize = 2000;
+
+/// How a segment's current is placed in space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceModel {
+    /// All of the current at the segment's center.
+    PointSource,
+    /// The current spread along the axis of cylindrical segments; spheres
+    /// remain point sources.
+    LineSource,
+}
+
+/// An electrode in the extracellular space, recording the local field
+/// potential.
+#[derive(Clone, Component, Debug)]
+pub struct ExtracellularElectrode {
+    pub label: String,
+    /// The tip of the electrode, in the scene frame; see `units`.
+    pub position_um: Vec3,
+    /// Conductivity (S/m) of the medium.
+    pub conductivity: f32,
+    pub model: SourceModel,
+    /// The potential (µV) at the last sample.
+    pub potential_uv: f32,
+    /// `(t, potential)` pairs, oldest first; times in seconds, potentials
+    /// in µV.
+    pub history: VecDeque<(f32, f32)>,
+}
+
+impl ExtracellularElectrode {
+    pub fn new(label: String, position_um: Vec3) -> Self {
+        ExtracellularElectrode {
+            label,
+            position_um,
+            conductivity: DEFAULT_CONDUCTIVITY,
+            model: SourceModel::LineSource,
+            potential_uv: 0.0,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, t: f32, potential_uv: f32) {
+        if self.history.back().is_some_and(|(last, _)| t < *last) {
+            self.history.clear();
+        }
+        self.potential_uv = potential_uv;
+        self.history.push_back((t, potential_uv));
+        if self.history.len() > ELECTRODE_HISTORY_LEN {
+            self.history.pop_front();
+        }
+    }
+
+    /// The recent samples, for `crate::analysis`.
+    pub fn trace(&self) -> Trace {
+        let mut trace = Trace::new(self.label.clone());
+        for (t, potential) in &self.history {
+            trace.push(*t, *potential);
+        }
+        trace
+    }
+}
+
+/// The potential (µV) at `electrode_um` from `current` amps leaving the
+/// membrane at `source_um`, in a medium of `conductivity` S/m.
+pub fn point_source_potential(current: f32, source_um: Vec3, electrode_um: Vec3, conductivity: f32) -> f32 {
+    let r_um = source_um.distance(electrode_um).max(MIN_DISTANCE_UM);
+    current / (4.0 * PI * conductivity * r_um * 1e-6) * 1e6
+}
+
+/// The potential (µV) at `electrode_um` from `current` amps leaving the
+/// membrane evenly along the line from `start_um` to `end_um`.
+pub fn line_source_potential(current: f32, start_um: Vec3, end_um: Vec3, electrode_um: Vec3, conductivity: f32) -> f32 {
+    let length_um = start_um.distance(end_um);
+    if length_um < MIN_DISTANCE_UM {
+        return point_source_potential(current, (start_um + end_um) * 0.5, electrode_um, conductivity);
+    }
+    let axis = (end_um - start_um) / length_um;
+    let offset = electrode_um - start_um;
+    // Distance along the axis to the electrode's foot, and from the axis.
+    let along = offset.dot(axis);
+    let r_um = (offset.length_squared() - along * along).max(0.0).sqrt().max(MIN_DISTANCE_UM);
+    // The integral of 1/distance along the segment.
+    let integral = ((length_um - along) / r_um).asinh() + (along / r_um).asinh();
+    current / (4.0 * PI * conductivity * length_um * 1e-6) * integral * 1e6
+}
+
+/// The segments whose currents an electrode records.
+pub type LfpSegments<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Solution,
+        &'static Geometry,
+        &'static Membrane,
+        &'static MembraneVoltage,
+        &'static Transform,
+        Option<&'static ExtracellularSpace>,
+    ),
+    With<Segment>,
+>;
+
+/// What the segment currents and positions depend on.
+#[derive(SystemParam)]
+pub struct LfpMedium<'w> {
+    pub env: Res<'w, Env>,
+    pub constants: Res<'w, SimulationConstants>,
+    pub scale: Res<'w, UnitScale>,
+}
+
+/// Refresh each electrode's potential from the currents of every segment,
+/// once per simulated frame.
+pub fn compute_lfp(
+    medium: LfpMedium,
+    timestamp: Res<Timestamp>,
+    mut last_sampled: Local<Option<f32>>,
+    mut last_voltages: Local<HashMap<Entity, (f32, f32)>>,
+    mut electrodes: Query<&mut ExtracellularElectrode>,
+    segments: LfpSegments,
+    #[cfg(feature = "render")]
+    bounds: Query<&Aabb>,
+) {
+    if electrodes.is_empty() {
+        last_voltages.clear();
+        return;
+    }
+    let t = timestamp.0;
+    if *last_sampled == Some(t) {
+        return;
+    }
+    *last_sampled = Some(t);
+
+    let LfpMedium { env, constants, scale } = &medium;
+    let mut potentials = vec![0.0; electrodes.iter().len()];
+    for (entity, solution, geometry, membrane, voltage, transform, space) in &segments {
+        let extracellular = local_solution(env, space);
+        let ionic = membrane.current_per_square_cm(
+            &constants.k_reversal(solution, extracellular),
+            &constants.na_reversal(solution, extracellular),
+            &constants.cl_reversal(solution, extracellular),
+            &constants.ca_reversal(solution, extracellular),
+            &voltage.0,
+        );
+        // dV/dt (V/s) since the last sample, or zero for a new segment.
+        let dv_dt = match last_voltages.insert(entity, (t, voltage.0.0)) {
+            Some((last_t, last_v)) if t > last_t => (voltage.0.0 - last_v) * 1e-3 / (t - last_t),
+            _ => 0.0,
+        };
+        let current = (ionic + membrane.capacitance.0 * dv_dt) * geometry.surface_area();
+
+        let to_um = |screen: Vec3| screen / scale.screen_per_micron();
+        let center_um = to_um(transform.translation);
+        #[cfg(feature = "render")]
+        let axis = match (geometry, bounds.get(entity)) {
+            (Geometry::Frustum { .. }, Ok(aabb)) => {
+                let center = Vec3::from(aabb.center);
+                let half = Vec3::Y * aabb.half_extents.y;
+                Some((to_um(transform.transform_point(center - half)), to_um(transform.transform_point(center + half))))
+            },
+            _ => None,
+        };
+        // Without meshes there are no bounds to find a frustum's axis by,
+        // and every segment is a point source.
+        #[cfg(not(feature = "render"))]
+        let axis: Option<(Vec3, Vec3)> = None;
+        for (potential, electrode) in potentials.iter_mut().zip(electrodes.iter()) {
+            *potential += match (electrode.model, axis) {
+                (SourceModel::LineSource, Some((start, end))) => {
+                    line_source_potential(current, start, end, electrode.position_um, electrode.conductivity)
+                },
+                _ => point_source_potential(current, center_um, electrode.position_um, electrode.conductivity),
+            };
+        }
+    }
+    for (potential, mut electrode) in potentials.into_iter().zip(electrodes.iter_mut()) {
+        electrode.push(t, potential);
+    }
+}
+
+/// The marker drawn at an electrode's tip.
+#[cfg(feature = "render")]
+#[derive(Component)]
+pub struct ElectrodeMarker;
+
+/// Draw each new electrode.
+#[cfg(feature = "render")]
+pub fn spawn_electrode_markers(
+    mut commands: Commands,
+    scale: Res<UnitScale>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    electrodes: Query<(Entity, &ExtracellularElectrode), Added<ExtracellularElectrode>>,
+) {
+    for (entity, electrode) in &electrodes {
+        commands.entity(entity).insert((
+            ElectrodeMarker,
+            PbrBundle {
+                mesh: meshes.add(Sphere { radius: 4.0 }),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::ORANGE,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_translation(scale.point_to_screen(electrode.position_um)),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Keep each electrode's marker at its tip.
+#[cfg(feature = "render")]
+pub fn update_electrode_markers(
+    scale: Res<UnitScale>,
+    mut electrodes: Query<(&ExtracellularElectrode, &mut Transform), (With<ElectrodeMarker>, Changed<ExtracellularElectrode>)>,
+) {
+    for (electrode, mut transform) in &mut electrodes {
+        let translation = scale.point_to_screen(electrode.position_um);
+        if transform.translation != translation {
+            transform.translation = translation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn potentials_fall_off_with_distance() {
+        // 1 nA at 100 µm in 0.3 S/m: about 2.65 µV.
+        let near = point_source_potential(1e-9, Vec3::ZERO, Vec3::new(100.0, 0.0, 0.0), 0.3);
+        assert!((near - 2.6526).abs() < 1e-3, "{}", near);
+        let far = point_source_potential(1e-9, Vec3::ZERO, Vec3::new(200.0, 0.0, 0.0), 0.3);
+        assert!((far * 2.0 - near).abs() < 1e-4);
+        let inside = point_source_potential(1e-9, Vec3::ZERO, Vec3::ZERO, 0.3);
+        assert!(inside.is_finite());
+
+        // A short line looks like a point from afar.
+        let (start, end) = (Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 5.0, 0.0));
+        let line = line_source_potential(1e-9, start, end, Vec3::new(500.0, 0.0, 0.0), 0.3);
+        let point = point_source_potential(1e-9, Vec3::ZERO, Vec3::new(500.0, 0.0, 0.0), 0.3);
+        assert!((line / point - 1.0).abs() < 1e-3);
+        // Beside a long line, the current is spread out and the potential
+        // is lower than that of a point at its center.
+        let (start, end) = (Vec3::new(0.0, -100.0, 0.0), Vec3::new(0.0, 100.0, 0.0));
+        let beside = line_source_potential(1e-9, start, end, Vec3::new(20.0, 0.0, 0.0), 0.3);
+        let point = point_source_potential(1e-9, Vec3::ZERO, Vec3::new(20.0, 0.0, 0.0), 0.3);
+        assert!(beside > 0.0 && beside < point);
+    }
+}
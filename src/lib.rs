@@ -1,10 +1,62 @@
+pub mod action;
+pub mod analysis;
+#[cfg(feature = "render")]
+pub mod autosave;
+pub mod background;
+#[cfg(feature = "render")]
+pub mod camera;
+pub mod checkpoint;
+pub mod clock;
+pub mod command;
+pub mod config;
 pub mod constants;
 pub mod dimension;
+pub mod evolve;
+pub mod expr;
+pub mod extracellular;
+pub mod fit;
+#[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+pub mod grpc;
+#[cfg(feature = "render")]
 pub mod gui;
+pub mod headless;
+#[cfg(feature = "render")]
+pub mod instancing;
+#[cfg(feature = "render")]
+pub mod layout;
+pub mod lfp;
+#[cfg(feature = "render")]
+pub mod lod;
+pub mod mechanism;
+pub mod morphology;
 pub mod neuron;
+pub mod noise;
+pub mod notify;
+pub mod optogenetics;
 pub mod plugin;
+pub mod probe;
+pub mod projector;
+pub mod recording;
+pub mod repair;
+pub mod replay;
+pub mod rng;
 pub mod integrations;
+pub mod scene_diff;
 pub mod serialize;
+pub mod snapshot;
+#[cfg(feature = "render")]
 pub mod selection;
+pub mod spike_train;
+#[cfg(feature = "render")]
 pub mod start;
+pub mod step_advisor;
 pub mod stimulator;
+pub mod sweep;
+pub mod thermal;
+pub mod threads;
+pub mod units;
+pub mod validate;
+pub mod voltage_stream;
+pub mod warm_restart;
+pub mod watch;
+pub mod webhook;
@@ -0,0 +1,130 @@
+//! Level of detail for segment meshes, so that neurons with thousands of
+//! segments stay interactive.
+//!
+//! Segments are drawn from shared unit meshes, scaled per segment (see
+//! `instancing`): a detailed cylinder or sphere up close, and a coarse one
+//! with a handful of sides. A segment switches to the coarse mesh once the
+//! camera is more than `LodSettings::coarse_beyond_radii` of its own radii
+//! away, where the sides it loses would cover a pixel or two anyway. Thin
+//! neurites therefore go coarse sooner than the soma.
+
+use bevy::prelude::*;
+use bevy::math::prelude::{Cylinder, Sphere};
+
+use crate::instancing::SegmentBatch;
+
+/// Sides of a coarse neurite, and sectors and stacks of a coarse soma.
+const COARSE_CYLINDER_RESOLUTION: u32 = 5;
+const COARSE_SPHERE_SECTORS: usize = 8;
+const COARSE_SPHERE_STACKS: usize = 6;
+
+/// A cylinder along Y of radius 1 and height 1, and a sphere of radius 1.
+const UNIT_CYLINDER: Handle<Mesh> = Handle::weak_from_u128(0x6e62_7369_6d5f_6379_6c69_6e64_6572_31);
+const COARSE_UNIT_CYLINDER: Handle<Mesh> = Handle::weak_from_u128(0x6e62_7369_6d5f_6379_6c69_6e64_6572_30);
+const UNIT_SPHERE: Handle<Mesh> = Handle::weak_from_u128(0x6e62_7369_6d5f_7370_6865_7265_31);
+const COARSE_UNIT_SPHERE: Handle<Mesh> = Handle::weak_from_u128(0x6e62_7369_6d5f_7370_6865_7265_30);
+
+/// The fraction either side of the threshold within which segments keep
+/// their present mesh, so that they do not flicker as the camera moves
+/// along it.
+const HYSTERESIS: f32 = 0.1;
+
+#[derive(Clone, Debug, Resource)]
+pub struct LodSettings {
+    pub enabled: bool,
+    pub coarse_beyond_radii: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings { enabled: true, coarse_beyond_radii: 300.0 }
+    }
+}
+
+/// The shared unit mesh for a sphere or cylinder.
+pub fn unit_mesh(sphere: bool, detailed: bool) -> Handle<Mesh> {
+    match (sphere, detailed) {
+        (false, true) => UNIT_CYLINDER,
+        (false, false) => COARSE_UNIT_CYLINDER,
+        (true, true) => UNIT_SPHERE,
+        (true, false) => COARSE_UNIT_SPHERE,
+    }
+}
+
+pub fn insert_unit_meshes(mut meshes: ResMut<Assets<Mesh>>) {
+    let cylinder = Cylinder { radius: 1.0, half_height: 0.5 };
+    let sphere = Sphere { radius: 1.0 };
+    meshes.insert(UNIT_CYLINDER.id(), Mesh::from(cylinder));
+    meshes.insert(COARSE_UNIT_CYLINDER.id(), cylinder.mesh().resolution(COARSE_CYLINDER_RESOLUTION).build());
+    meshes.insert(UNIT_SPHERE.id(), Mesh::from(sphere));
+    meshes.insert(COARSE_UNIT_SPHERE.id(), sphere.mesh().uv(COARSE_SPHERE_SECTORS, COARSE_SPHERE_STACKS));
+}
+
+/// How a segment is drawn: which unit mesh, and how it is scaled to the
+/// segment's size in screen units.
+#[derive(Component, Debug)]
+pub struct SegmentShape {
+    pub sphere: bool,
+    pub scale: Vec3,
+    pub detailed: bool,
+}
+
+impl SegmentShape {
+    pub fn sphere(radius: f32) -> SegmentShape {
+        SegmentShape { sphere: true, scale: Vec3::splat(radius), detailed: true }
+    }
+
+    /// A cylinder along Y.
+    pub fn cylinder(radius: f32, length: f32) -> SegmentShape {
+        SegmentShape { sphere: false, scale: Vec3::new(radius, length, radius), detailed: true }
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.scale.x
+    }
+
+    pub fn batch(&self) -> SegmentBatch {
+        SegmentBatch { sphere: self.sphere, detailed: self.detailed }
+    }
+}
+
+/// Whether a segment of `radius` at `distance` from the camera should be
+/// drawn detailed, given whether it is now.
+pub fn wants_detail(detailed: bool, distance: f32, radius: f32, coarse_beyond_radii: f32) -> bool {
+    let threshold = radius * coarse_beyond_radii * if detailed { 1.0 + HYSTERESIS } else { 1.0 - HYSTERESIS };
+    distance < threshold
+}
+
+/// Give each segment the mesh for its distance from the camera.
+pub fn choose_segment_meshes(
+    settings: Res<LodSettings>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    mut segments: Query<(&GlobalTransform, &mut SegmentShape)>,
+) {
+    let Some(camera) = cameras.iter().next().map(GlobalTransform::translation) else {
+        return;
+    };
+    for (transform, mut shape) in &mut segments {
+        let wanted = !settings.enabled
+            || wants_detail(shape.detailed, transform.translation().distance(camera), shape.radius(), settings.coarse_beyond_radii);
+        // Only write on a switch, so that unchanged shapes are not marked
+        // changed.
+        if wanted != shape.detailed {
+            shape.detailed = wanted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_switch_detail_with_hysteresis() {
+        // The threshold for a radius of 2 is 600.
+        assert!(wants_detail(false, 500.0, 2.0, 300.0));
+        assert!(!wants_detail(false, 580.0, 2.0, 300.0));
+        assert!(wants_detail(true, 620.0, 2.0, 300.0));
+        assert!(!wants_detail(true, 700.0, 2.0, 300.0));
+    }
+}
@@ -0,0 +1,209 @@
+//! Extension points for channels, synapses and stimuli defined in other
+//! crates, without forking this one.
+//!
+//! A crate implements one of the traits below and registers a constructor
+//! for it under a name, before any scene is loaded:
+//!
+//! ```ignore
+//! nb_sim::mechanism::register_stimulus_source("my_crate::Noise", |parameters| {
+//!     Ok(Box::new(Noise::from_json(parameters)?))
+//! });
+//! ```
+//!
+//! Scenes then refer to the mechanism by that name, with its parameters as
+//! arbitrary JSON, through `serialize::CustomMechanism`: the `custom` field
+//! of a `Channel` or `SynapseMembranes`, or the `Custom` `CurrentShape`. A
+//! scene naming an unregistered mechanism still loads and saves intact, but
+//! the mechanism passes no current until it is registered.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use crate::dimension::{Interval, MicroAmps, MicroAmpsPerSquareCm, MilliVolts, Timestamp};
+use crate::serialize::CustomMechanism;
+
+/// Gating for a membrane channel. The channel's peak conductance and ion
+/// selectivity come from the scene; the model supplies the open fraction.
+pub trait ChannelModel: Debug + Send + Sync {
+    /// Advance the model by `interval` at `membrane_potential`.
+    fn step(&mut self, membrane_potential: &MilliVolts, interval: &Interval);
+    /// The fraction of the peak conductance that is open, from 0 to 1.
+    fn conductance_coefficient(&self) -> f32;
+    fn clone_box(&self) -> Box<dyn ChannelModel>;
+}
+
+/// A synaptic mechanism alongside a synapse's receptors.
+pub trait SynapticMechanism: Debug + Send + Sync {
+    fn step(&mut self, presynaptic_potential: &MilliVolts, postsynaptic_potential: &MilliVolts, interval: &Interval);
+    /// The current it passes into the postsynaptic segment.
+    fn current(&self, postsynaptic_potential: &MilliVolts) -> MicroAmps;
+    fn clone_box(&self) -> Box<dyn SynapticMechanism>;
+}
+
+/// A stimulator's current over time. Custom sources replace the
+/// stimulator's envelope, and are given the simulation time directly.
+pub trait StimulusSource: Debug + Send + Sync {
+    fn current(&self, t: &Timestamp) -> MicroAmpsPerSquareCm;
+}
+
+/// Builds a mechanism from the parameters in a scene.
+pub type Constructor<T> = fn(&serde_json::Value) -> Result<Box<T>, String>;
+
+type Registry<T> = RwLock<BTreeMap<String, Constructor<T>>>;
+
+static CHANNEL_MODELS: Registry<dyn ChannelModel> = RwLock::new(BTreeMap::new());
+static SYNAPTIC_MECHANISMS: Registry<dyn SynapticMechanism> = RwLock::new(BTreeMap::new());
+static STIMULUS_SOURCES: Registry<dyn StimulusSource> = RwLock::new(BTreeMap::new());
+
+fn register<T: ?Sized>(registry: &Registry<T>, name: &str, constructor: Constructor<T>) {
+    registry
+        .write()
+        .expect("Mechanism registry should not be poisoned")
+        .insert(name.to_string(), constructor);
+}
+
+fn build<T: ?Sized>(registry: &Registry<T>, spec: &CustomMechanism) -> Option<Box<T>> {
+    let constructor = registry.read().ok()?.get(&spec.name).copied();
+    let Some(constructor) = constructor else {
+        eprintln!("No mechanism is registered as {}; it will pass no current", spec.name);
+        return None;
+    };
+    constructor(&spec.parameters)
+        .map_err(|e| eprintln!("Invalid parameters for {}: {}", spec.name, e))
+        .ok()
+}
+
+fn names<T: ?Sized>(registry: &Registry<T>) -> Vec<String> {
+    registry.read().map_or(vec![], |r| r.keys().cloned().collect())
+}
+
+pub fn register_channel_model(name: &str, constructor: Constructor<dyn ChannelModel>) {
+    register(&CHANNEL_MODELS, name, constructor);
+}
+
+pub fn register_synaptic_mechanism(name: &str, constructor: Constructor<dyn SynapticMechanism>) {
+    register(&SYNAPTIC_MECHANISMS, name, constructor);
+}
+
+pub fn register_stimulus_source(name: &str, constructor: Constructor<dyn StimulusSource>) {
+    register(&STIMULUS_SOURCES, name, constructor);
+}
+
+/// The registered names, for channels, synaptic mechanisms and stimulus
+/// sources in turn.
+pub fn registered() -> (Vec<String>, Vec<String>, Vec<String>) {
+    (names(&CHANNEL_MODELS), names(&SYNAPTIC_MECHANISMS), names(&STIMULUS_SOURCES))
+}
+
+/// A custom channel model, with the spec it was built from so that it
+/// serializes unchanged.
+#[derive(Debug)]
+pub struct CustomChannel {
+    pub spec: CustomMechanism,
+    pub model: Option<Box<dyn ChannelModel>>,
+}
+
+impl CustomChannel {
+    pub fn new(spec: &CustomMechanism) -> Self {
+        CustomChannel { spec: spec.clone(), model: build(&CHANNEL_MODELS, spec) }
+    }
+
+    pub fn conductance_coefficient(&self) -> f32 {
+        self.model.as_ref().map_or(0.0, |m| m.conductance_coefficient())
+    }
+}
+
+impl Clone for CustomChannel {
+    fn clone(&self) -> Self {
+        CustomChannel { spec: self.spec.clone(), model: self.model.as_ref().map(|m| m.clone_box()) }
+    }
+}
+
+#[derive(Debug)]
+pub struct CustomSynapse {
+    pub spec: CustomMechanism,
+    pub mechanism: Option<Box<dyn SynapticMechanism>>,
+}
+
+impl CustomSynapse {
+    pub fn new(spec: &CustomMechanism) -> Self {
+        CustomSynapse { spec: spec.clone(), mechanism: build(&SYNAPTIC_MECHANISMS, spec) }
+    }
+
+    pub fn current(&self, postsynaptic_potential: &MilliVolts) -> MicroAmps {
+        self.mechanism.as_ref().map_or(MicroAmps(0.0), |m| m.current(postsynaptic_potential))
+    }
+}
+
+impl Clone for CustomSynapse {
+    fn clone(&self) -> Self {
+        CustomSynapse { spec: self.spec.clone(), mechanism: self.mechanism.as_ref().map(|m| m.clone_box()) }
+    }
+}
+
+/// Stimulus sources are stateless, so stimulators share them.
+#[derive(Clone, Debug)]
+pub struct CustomStimulus {
+    pub spec: CustomMechanism,
+    pub source: Option<Arc<dyn StimulusSource>>,
+}
+
+impl CustomStimulus {
+    pub fn new(spec: &CustomMechanism) -> Self {
+        CustomStimulus { spec: spec.clone(), source: build(&STIMULUS_SOURCES, spec).map(Arc::from) }
+    }
+
+    pub fn current(&self, t: &Timestamp) -> MicroAmpsPerSquareCm {
+        self.source.as_ref().map_or(MicroAmpsPerSquareCm(0.0), |s| s.current(t))
+    }
+}
+
+impl PartialEq for CustomStimulus {
+    fn eq(&self, other: &Self) -> bool {
+        self.spec == other.spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Clone, Debug)]
+    struct HalfOpen {
+        fraction: f32,
+    }
+
+    impl ChannelModel for HalfOpen {
+        fn step(&mut self, _membrane_potential: &MilliVolts, _interval: &Interval) {}
+
+        fn conductance_coefficient(&self) -> f32 {
+            self.fraction
+        }
+
+        fn clone_box(&self) -> Box<dyn ChannelModel> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn builds_registered_models() {
+        register_channel_model("tests::HalfOpen", |parameters| {
+            let fraction = parameters["fraction"].as_f64().ok_or("fraction should be a number")?;
+            Ok(Box::new(HalfOpen { fraction: fraction as f32 }))
+        });
+        assert!(registered().0.contains(&"tests::HalfOpen".to_string()));
+
+        let spec = CustomMechanism { name: "tests::HalfOpen".to_string(), parameters: json!({"fraction": 0.5}) };
+        let channel = CustomChannel::new(&spec).clone();
+        assert_eq!(channel.conductance_coefficient(), 0.5);
+
+        let invalid = CustomMechanism { parameters: json!({}), ..spec.clone() };
+        assert_eq!(CustomChannel::new(&invalid).conductance_coefficient(), 0.0);
+        let unregistered = CustomMechanism { name: "tests::Missing".to_string(), ..spec };
+        let channel = CustomChannel::new(&unregistered);
+        assert_eq!(channel.conductance_coefficient(), 0.0);
+        assert_eq!(channel.spec, unregistered);
+    }
+}
@@ -0,0 +1,158 @@
+//! Editing a neuron's morphology within a scene: cutting a branch off,
+//! moving it to another parent, or scaling its diameters. A branch is a
+//! segment and everything distal to it.
+//!
+//! Edits keep the rest of the scene consistent. Synapses refer to segments
+//! by their position in the segment list, so they are renumbered when a
+//! cut shifts those positions, and synapses and stimulators on a cut
+//! branch go with it.
+
+use std::collections::HashSet;
+
+use crate::serialize::{Neuron, Scene, SegmentRef};
+
+/// The SWC ids of the branch starting at `id`.
+pub fn branch(neuron: &Neuron, id: i32) -> HashSet<i32> {
+    let mut ids = HashSet::from([id]);
+    // Parents may come after their children, so repeat until nothing is
+    // added.
+    loop {
+        let before = ids.len();
+        for segment in neuron.segments.iter() {
+            if ids.contains(&segment.parent) {
+                ids.insert(segment.id);
+            }
+        }
+        if ids.len() == before {
+            return ids;
+        }
+    }
+}
+
+fn neuron_mut(scene: &mut Scene, at: SegmentRef) -> Result<&mut Neuron, String> {
+    let neuron = &mut scene.neurons.get_mut(at.neuron).ok_or(format!("No neuron {}", at.neuron))?.neuron;
+    if !neuron.segments.iter().any(|s| s.id == at.segment) {
+        return Err(format!("No segment {} in neuron {}", at.segment, at.neuron));
+    }
+    Ok(neuron)
+}
+
+/// Remove the branch at `at`, with its stimulators, membrane overrides and
+/// synapses.
+pub fn cut_branch(scene: &mut Scene, at: SegmentRef) -> Result<(), String> {
+    let neuron = neuron_mut(scene, at)?;
+    if neuron.segments.iter().any(|s| s.id == at.segment && s.parent == -1) {
+        return Err(format!("Segment {} is a root; cutting it would remove the neuron", at.segment));
+    }
+    let cut = branch(neuron, at.segment);
+
+    // Each remaining segment's new position, for renumbering synapses.
+    let mut new_index = vec![None; neuron.segments.len()];
+    let mut kept = 0;
+    for (i, segment) in neuron.segments.iter().enumerate() {
+        if !cut.contains(&segment.id) {
+            new_index[i] = Some(kept);
+            kept += 1;
+        }
+    }
+    neuron.segments.retain(|s| !cut.contains(&s.id));
+    scene.neurons[at.neuron].stimulator_segments.retain(|s| !cut.contains(&(s.segment as i32)));
+    scene.neurons[at.neuron].thermal_segments.retain(|s| !cut.contains(&(s.segment as i32)));
+    scene.neurons[at.neuron].membrane_overrides.retain(|o| !cut.contains(&(o.segment as i32)));
+    scene.synapses.retain_mut(|synapse| {
+        for (neuron, segment) in [(synapse.pre_neuron, &mut synapse.pre_segment), (synapse.post_neuron, &mut synapse.post_segment)] {
+            if neuron == at.neuron {
+                match new_index.get(*segment).copied().flatten() {
+                    Some(index) => *segment = index,
+                    None => return false,
+                }
+            }
+        }
+        true
+    });
+    Ok(())
+}
+
+/// Move the branch at `at` onto `to`, a segment of the same neuron outside
+/// the branch. The branch is translated with its attachment point, so it
+/// keeps its shape and its offset from its parent.
+pub fn reattach_branch(scene: &mut Scene, at: SegmentRef, to: SegmentRef) -> Result<(), String> {
+    if to.neuron != at.neuron {
+        return Err("A branch can only be reattached within its own neuron".to_string());
+    }
+    let neuron = neuron_mut(scene, at)?;
+    let moved = branch(neuron, at.segment);
+    if moved.contains(&to.segment) {
+        return Err(format!("Segment {} is on the branch being moved", to.segment));
+    }
+    let position = |id: i32| neuron.segments.iter().find(|s| s.id == id).map(|s| (s.x, s.y, s.z));
+    let new_parent = position(to.segment).ok_or(format!("No segment {} in neuron {}", to.segment, to.neuron))?;
+    let old_parent = neuron
+        .segments
+        .iter()
+        .find(|s| s.id == at.segment)
+        .and_then(|s| position(s.parent))
+        .ok_or(format!("Segment {} is a root, not a branch", at.segment))?;
+    let offset = (new_parent.0 - old_parent.0, new_parent.1 - old_parent.1, new_parent.2 - old_parent.2);
+    for segment in neuron.segments.iter_mut().filter(|s| moved.contains(&s.id)) {
+        segment.x += offset.0;
+        segment.y += offset.1;
+        segment.z += offset.2;
+        if segment.id == at.segment {
+            segment.parent = to.segment;
+        }
+    }
+    Ok(())
+}
+
+/// Multiply the radius of every segment on the branch at `at` by `factor`.
+pub fn scale_branch(scene: &mut Scene, at: SegmentRef, factor: f32) -> Result<(), String> {
+    if !(factor.is_finite() && factor > 0.0) {
+        return Err(format!("Cannot scale diameters by {}", factor));
+    }
+    let neuron = neuron_mut(scene, at)?;
+    let scaled = branch(neuron, at.segment);
+    for segment in neuron.segments.iter_mut().filter(|s| scaled.contains(&s.id)) {
+        segment.r *= factor;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::sample;
+    use crate::validate::validate_scene;
+
+    #[test]
+    fn edits_keep_the_scene_valid() {
+        let scene = sample::scene2();
+        let neuron = &scene.neurons[0].neuron;
+        // Some branch with segments after it in the list.
+        let start = neuron.segments[1..]
+            .iter()
+            .map(|s| s.id)
+            .find(|id| (2..neuron.segments.len() / 2).contains(&branch(neuron, *id).len()))
+            .expect("the sample neuron should branch");
+        let at = SegmentRef { neuron: 0, segment: start };
+        let size = branch(neuron, start).len();
+
+        let mut cut = scene.clone();
+        cut_branch(&mut cut, at).unwrap();
+        assert_eq!(cut.neurons[0].neuron.segments.len(), neuron.segments.len() - size);
+        assert_eq!(validate_scene(&cut), Vec::<String>::new());
+        let root = SegmentRef { neuron: 0, segment: neuron.segments.iter().find(|s| s.parent == -1).unwrap().id };
+        assert!(cut_branch(&mut cut, root).is_err());
+
+        let mut scaled = scene.clone();
+        scale_branch(&mut scaled, at, 2.0).unwrap();
+        let radius = |scene: &Scene| scene.neurons[0].neuron.segments.iter().find(|s| s.id == start).unwrap().r;
+        assert_eq!(radius(&scaled), 2.0 * radius(&scene));
+
+        let mut moved = scene.clone();
+        assert!(reattach_branch(&mut moved, at, at).is_err());
+        reattach_branch(&mut moved, at, root).unwrap();
+        assert_eq!(moved.neurons[0].neuron.segments.iter().find(|s| s.id == start).unwrap().parent, root.segment);
+        assert_eq!(validate_scene(&moved), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,253 @@
+//! Calcium microdomains beneath the membrane.
+//!
+//! Calcium entering through channels does not spread through a segment's
+//! whole volume at once. It lands in a thin submembrane shell, where most
+//! of it is bound by buffers, and pumps and exchangers extrude it again
+//! within about a hundred milliseconds. The free calcium in that shell is what
+//! calcium-dependent channels and synaptic plasticity respond to, and it
+//! swings from tens of nanomolar to micromolar while the bulk cytoplasm
+//! barely moves.
+//!
+//! With `IonDynamics.calcium` set, each segment gets a `CalciumShell`
+//! holding its free submembrane Ca2+. The buffer is taken to be fast
+//! (the rapid buffer approximation of Wagner and Keizer), so that only
+//! `1 / (1 + kappa)` of the calcium entering stays free, where the buffer
+//! capacity `kappa` falls as the buffer fills. Without a shell, channels
+//! see the segment's bulk calcium.
+
+use bevy::prelude::*;
+
+use crate::dimension::{Interval, MilliVolts, Molar};
+use crate::neuron::channel::{Channel, ChannelBuilder, K};
+use crate::neuron::ion_dynamics::IonDynamics;
+use crate::neuron::membrane::Membrane;
+use crate::neuron::segment::ecs::Segment;
+use crate::serialize;
+
+/// The buffering and extrusion shared by every segment's shell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalciumBuffering {
+    /// Depth (µm) of the shell beneath the membrane.
+    pub shell_depth_um: f32,
+    /// Free Ca2+ at rest, which extrusion restores.
+    pub resting: Molar,
+    /// Total concentration of the endogenous buffer.
+    pub buffer_total: Molar,
+    /// Dissociation constant of the buffer.
+    pub buffer_kd: Molar,
+    /// Time constant of extrusion back to rest.
+    pub extrusion_tau: Interval,
+}
+
+impl Default for CalciumBuffering {
+    /// A 100 nm shell whose buffer binds about 90 of every 91 ions at
+    /// rest. Extrusion acts on the free calcium alone, so with the buffer
+    /// a rise decays over about 100 ms (Helmchen et al., 1996).
+    fn default() -> Self {
+        CalciumBuffering {
+            shell_depth_um: 0.1,
+            resting: Molar(50e-9),
+            buffer_total: Molar(100e-6),
+            buffer_kd: Molar(1e-6),
+            extrusion_tau: Interval(0.001),
+        }
+    }
+}
+
+impl CalciumBuffering {
+    /// Buffer-bound calcium added per unit of free calcium added, at a
+    /// free concentration of `free` (M).
+    pub fn buffer_capacity(&self, free: f32) -> f32 {
+        let kd = self.buffer_kd.0;
+        self.buffer_total.0 * kd / ((kd + free) * (kd + free))
+    }
+
+    /// The rate (M/s) at which an outward `ca_current` (A/cm^2) would
+    /// raise the shell's calcium, before buffering.
+    fn influx_rate(&self, ca_current: f32, faraday: f32) -> f32 {
+        // A shell under 1 cm^2 of membrane holds depth (cm) mL.
+        let liters_per_square_cm = self.shell_depth_um * 1e-4 * 1e-3;
+        -ca_current / (2.0 * faraday * liters_per_square_cm)
+    }
+
+    /// Free calcium after `ca_current` (A/cm^2, outward positive) flows
+    /// for `interval` seconds, with no extrusion; for currents entering
+    /// beside the membrane's own, such as through synapses.
+    pub fn load(&self, free: f32, ca_current: f32, interval: f32, faraday: f32) -> f32 {
+        let rate = self.influx_rate(ca_current, faraday) / (1.0 + self.buffer_capacity(free));
+        (free + rate * interval).max(0.0)
+    }
+
+    /// Free calcium after `ca_current` (A/cm^2, outward positive) flows
+    /// for `interval` seconds while the pumps extrude it.
+    pub fn step(&self, free: f32, ca_current: f32, interval: f32, faraday: f32) -> f32 {
+        let extrusion_rate = if self.extrusion_tau.0 > 0.0 {
+            (free - self.resting.0) / self.extrusion_tau.0
+        } else {
+            0.0
+        };
+        let rate = (self.influx_rate(ca_current, faraday) - extrusion_rate) / (1.0 + self.buffer_capacity(free));
+        (free + rate * interval).max(0.0)
+    }
+}
+
+/// The free Ca2+ in a segment's submembrane shell, present while
+/// `IonDynamics.calcium` is set.
+#[derive(Clone, Component, Debug, PartialEq)]
+pub struct CalciumShell(pub Molar);
+
+/// How a channel's opening depends on the calcium it sees, as for SK and
+/// BK potassium channels: a Hill function of the free concentration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalciumSensitivity {
+    /// Free Ca2+ at which the channel is half open.
+    pub half_activation: Molar,
+    /// Hill coefficient: how many ions bind cooperatively.
+    pub hill: f32,
+}
+
+impl CalciumSensitivity {
+    pub fn open_fraction(&self, free: f32) -> f32 {
+        let x = (free.max(0.0) / self.half_activation.0).powf(self.hill);
+        x / (1.0 + x)
+    }
+
+    pub fn serialize(&self) -> serialize::CalciumSensitivity {
+        serialize::CalciumSensitivity {
+            half_activation_molar: self.half_activation.0,
+            hill: self.hill,
+        }
+    }
+
+    pub fn deserialize(s: &serialize::CalciumSensitivity) -> Self {
+        CalciumSensitivity {
+            half_activation: Molar(s.half_activation_molar),
+            hill: s.hill,
+        }
+    }
+}
+
+/// Small-conductance calcium-activated K+ channels (Hirschberg et al.,
+/// 1998), which open in about a millisecond and so are taken to follow
+/// the shell instantly.
+pub const SK_SENSITIVITY: CalciumSensitivity = CalciumSensitivity {
+    half_activation: Molar(0.5e-6),
+    hill: 4.0,
+};
+
+/// A channel's calcium dependence, and the free calcium it last saw.
+#[derive(Clone, Debug)]
+pub struct CalciumGate {
+    pub sensitivity: CalciumSensitivity,
+    pub free: Molar,
+}
+
+impl CalciumGate {
+    /// A gate that has seen no calcium yet.
+    pub fn closed(sensitivity: CalciumSensitivity) -> Self {
+        CalciumGate { sensitivity, free: Molar(0.0) }
+    }
+
+    pub fn conductance_coefficient(&self) -> f32 {
+        self.sensitivity.open_fraction(self.free.0)
+    }
+}
+
+/// An SK channel: a K+ conductance gated by calcium alone, which follows
+/// each burst of spikes with an afterhyperpolarization.
+pub fn sk_channel() -> Channel {
+    let channel = ChannelBuilder {
+        activation_parameters: None,
+        inactivation_parameters: None,
+        ion_selectivity: K,
+    }
+    .build(&MilliVolts(0.0));
+    Channel { calcium: Some(CalciumGate::closed(SK_SENSITIVITY)), ..channel }
+}
+
+/// Show each calcium-dependent channel of `membrane` the free calcium
+/// under it.
+pub fn sense_calcium(membrane: &mut Membrane, free: &Molar) {
+    for membrane_channel in membrane.membrane_channels.iter_mut() {
+        if let Some(gate) = membrane_channel.channel.calcium.as_mut() {
+            gate.free = free.clone();
+        }
+    }
+}
+
+/// Give each segment a `CalciumShell` when calcium is buffered, starting
+/// at rest, and take them away when it is not.
+pub fn track_calcium_shells(
+    mut commands: Commands,
+    ion_dynamics: Res<IonDynamics>,
+    segments: Query<(Entity, Has<CalciumShell>), With<Segment>>,
+) {
+    for (segment, has_shell) in &segments {
+        match (ion_dynamics.calcium.as_ref(), has_shell) {
+            (Some(buffering), false) => {
+                commands.entity(segment).insert(CalciumShell(buffering.resting.clone()));
+            },
+            (None, true) => {
+                commands.entity(segment).remove::<CalciumShell>();
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::FARADAY;
+
+    #[test]
+    fn buffered_influx_is_extruded() {
+        let buffering = CalciumBuffering::default();
+        let rest = buffering.resting.0;
+        assert!((buffering.buffer_capacity(rest) - 90.7).abs() < 0.1);
+        assert_eq!(buffering.step(rest, 0.0, 1e-3, FARADAY), rest);
+
+        // 10 µA/cm^2 of inward Ca2+ current for 1 ms fills a 100 nm shell
+        // with about 5 µM, of which about 60 nM stays free.
+        let mut free = rest;
+        for _ in 0..100 {
+            free = buffering.load(free, -10e-6, 1e-5, FARADAY);
+        }
+        assert!((free - 0.1097e-6).abs() < 0.001e-6, "{}", free);
+
+        // Pumps bring it back toward rest over about 100 ms, slowed by
+        // the buffer giving calcium back up.
+        let peak = free;
+        for _ in 0..1000 {
+            free = buffering.step(free, 0.0, 1e-4, FARADAY);
+        }
+        let remaining = (free - rest) / (peak - rest);
+        assert!(remaining > 0.25 && remaining < 0.4, "{}", remaining);
+
+        assert!((SK_SENSITIVITY.open_fraction(0.5e-6) - 0.5).abs() < 1e-6);
+        assert!(SK_SENSITIVITY.open_fraction(rest) < 0.001);
+        assert!(SK_SENSITIVITY.open_fraction(2e-6) > 0.99);
+    }
+
+    #[test]
+    fn depolarized_nmda_calcium_potentiates() {
+        use crate::neuron::synapse::{MagnesiumBlock, Plasticity};
+
+        // Magnesium blocks nearly all NMDA receptors at rest, and few once
+        // the spine is depolarized.
+        let block = MagnesiumBlock::default();
+        assert!(block.unblocked_fraction(&MilliVolts(-70.0)) < 0.05);
+        assert!(block.unblocked_fraction(&MilliVolts(0.0)) > 0.75);
+
+        let interval = Interval(0.1);
+        let mut plasticity = Plasticity::default();
+        plasticity.step(&Molar(0.1e-6), &interval);
+        assert_eq!(plasticity.weight, 1.0);
+        plasticity.step(&Molar(0.4e-6), &interval);
+        assert!((plasticity.weight - 0.9).abs() < 1e-6);
+        for _ in 0..100 {
+            plasticity.step(&Molar(1e-6), &interval);
+        }
+        assert_eq!(plasticity.weight, plasticity.max_weight);
+    }
+}
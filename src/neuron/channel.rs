@@ -1,5 +1,8 @@
-use crate::constants::{GAS_CONSTANT, INVERSE_FARADAY};
+use crate::constants::{FARADAY, GAS_CONSTANT};
 use crate::dimension::{Interval, Kelvin, MilliVolts, Molar};
+use crate::mechanism::CustomChannel;
+use crate::neuron::calcium::{CalciumGate, CalciumSensitivity};
+use crate::neuron::opsin::{Opsin, OpsinState};
 use crate::neuron::solution::Solution;
 use crate::serialize;
 
@@ -69,7 +72,27 @@ pub fn reversal_potential(
     temperature: &Kelvin,
     valence: i8,
 ) -> MilliVolts {
-    let v = GAS_CONSTANT * INVERSE_FARADAY * temperature.0 / valence as f32
+    reversal_potential_with(
+        internal_concentration,
+        external_concentration,
+        temperature,
+        valence,
+        GAS_CONSTANT,
+        FARADAY,
+    )
+}
+
+/// The reversal potential for one ion species, with explicit gas and
+/// Faraday constants.
+pub fn reversal_potential_with(
+    internal_concentration: &Molar,
+    external_concentration: &Molar,
+    temperature: &Kelvin,
+    valence: i8,
+    gas_constant: f32,
+    faraday: f32,
+) -> MilliVolts {
+    let v = gas_constant / faraday * temperature.0 / valence as f32
         * (external_concentration.0 / internal_concentration.0).ln();
     MilliVolts(v * 1000.0)
 }
@@ -148,6 +171,13 @@ pub struct Channel {
     pub inactivation: Option<GateState>,
     /// The ion this channel is permeable to.
     pub ion_selectivity: IonSelectivity,
+    /// Gating by a model from another crate, multiplying any gates above.
+    pub custom: Option<CustomChannel>,
+    /// Gating by light, multiplying any gates above.
+    pub opsin: Option<OpsinState>,
+    /// Gating by the calcium under the membrane, multiplying any gates
+    /// above; see `neuron::calcium`.
+    pub calcium: Option<CalciumGate>,
 }
 
 impl Channel {
@@ -160,6 +190,12 @@ impl Channel {
         self.inactivation
             .iter_mut()
             .for_each(|inactivation| inactivation.step(membrane_potential, interval));
+        if let Some(model) = self.custom.as_mut().and_then(|c| c.model.as_mut()) {
+            model.step(membrane_potential, interval);
+        }
+        if let Some(opsin) = self.opsin.as_mut() {
+            opsin.step(interval);
+        }
     }
 
     /// The product of the various gates in the channel.
@@ -174,7 +210,10 @@ impl Channel {
                 .magnitude
                 .powi(gate_state.parameters.gates as i32)
         });
-        activation_coefficient * inactivation_coefficient
+        let custom_coefficient = self.custom.as_ref().map_or(1.0, |c| c.conductance_coefficient());
+        let opsin_coefficient = self.opsin.as_ref().map_or(1.0, |o| o.conductance_coefficient());
+        let calcium_coefficient = self.calcium.as_ref().map_or(1.0, |c| c.conductance_coefficient());
+        activation_coefficient * inactivation_coefficient * custom_coefficient * opsin_coefficient * calcium_coefficient
     }
 
     pub fn serialize(&self) -> serialize::Channel {
@@ -182,6 +221,9 @@ impl Channel {
             activation: self.activation.clone().map(|a| a.serialize()),
             inactivation: self.activation.clone().map(|ia| ia.serialize()),
             ion_selectivity: self.ion_selectivity.serialize(),
+            custom: self.custom.as_ref().map(|c| c.spec.clone()),
+            opsin: self.opsin.as_ref().map(|o| o.opsin().serialize()),
+            calcium: self.calcium.as_ref().map(|c| c.sensitivity.serialize()),
         }
     }
 
@@ -190,6 +232,9 @@ impl Channel {
             activation: channel.activation.as_ref().map(|a| GateState::deserialize(a)),
             inactivation: channel.inactivation.as_ref().map(|i| GateState::deserialize(i)),
             ion_selectivity: IonSelectivity::deserialize(&channel.ion_selectivity),
+            custom: channel.custom.as_ref().map(CustomChannel::new),
+            opsin: channel.opsin.as_ref().map(|o| OpsinState::dark(Opsin::deserialize(o))),
+            calcium: channel.calcium.as_ref().map(|c| CalciumGate::closed(CalciumSensitivity::deserialize(c))),
         }
     }
 }
@@ -228,6 +273,9 @@ impl ChannelBuilder {
             activation,
             inactivation,
             ion_selectivity: self.ion_selectivity.normalize(),
+            custom: None,
+            opsin: None,
+            calcium: None,
         }
     }
 }
@@ -336,6 +384,35 @@ impl Magnitude {
     pub fn steady_state(&self, v: &MilliVolts) -> f32 {
         1.0 / (1.0 + ((self.v_at_half_max.0 - v.0) / self.slope).exp())
     }
+
+    /// Fit a Boltzmann curve to `(millivolts, magnitude)` samples, by
+    /// linear regression on the logit of the magnitude. Samples too close
+    /// to 0 or 1 to be informative are ignored.
+    pub fn fit(samples: &[(f32, f32)]) -> Option<Magnitude> {
+        let points: Vec<(f32, f32)> = samples
+            .iter()
+            .filter(|(_, m)| *m > 0.01 && *m < 0.99)
+            .map(|(v, m)| (*v, (1.0 / m - 1.0).ln()))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f32;
+        let mean_v = points.iter().map(|(v, _)| v).sum::<f32>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / n;
+        let covariance: f32 = points.iter().map(|(v, y)| (v - mean_v) * (y - mean_y)).sum();
+        let variance: f32 = points.iter().map(|(v, _)| (v - mean_v).powi(2)).sum();
+        if variance == 0.0 || covariance == 0.0 {
+            return None;
+        }
+        let gradient = covariance / variance;
+        let intercept = mean_y - gradient * mean_v;
+        let slope = -1.0 / gradient;
+        Some(Magnitude {
+            v_at_half_max: MilliVolts(intercept * slope),
+            slope,
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -361,6 +438,43 @@ impl TimeConstant {
             }
         }
     }
+
+    /// Fit a Gaussian time constant to `(millivolts, seconds)` samples.
+    /// The peak and baseline are taken directly from the samples, and the
+    /// width from the average of the widths implied by each sample.
+    pub fn fit_gaussian(samples: &[(f32, f32)]) -> TimeConstant {
+        let (v_at_max, max) = samples
+            .iter()
+            .cloned()
+            .fold((0.0, f32::NEG_INFINITY), |acc, (v, tau)| if tau > acc.1 { (v, tau) } else { acc });
+        let min = samples.iter().map(|(_, tau)| *tau).fold(f32::INFINITY, f32::min);
+        if samples.is_empty() || max <= 0.0 {
+            return TimeConstant::Instantaneous;
+        }
+        let c_amp = max - min;
+        let sigma_squares: Vec<f32> = samples
+            .iter()
+            .filter_map(|(v, tau)| {
+                let y = (tau - min) / c_amp;
+                if y > 0.05 && y < 0.95 {
+                    Some((v - v_at_max).powi(2) / -y.ln())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let sigma = if sigma_squares.is_empty() {
+            30.0
+        } else {
+            (sigma_squares.iter().sum::<f32>() / sigma_squares.len() as f32).sqrt()
+        };
+        TimeConstant::Gaussian {
+            v_at_max_tau: MilliVolts(v_at_max),
+            c_base: min,
+            c_amp,
+            sigma,
+        }
+    }
 }
 
 pub mod common_channels {
@@ -573,6 +687,34 @@ pub mod common_channels {
         activation_parameters: None,
         inactivation_parameters: None,
     };
+
+    /// NMDA receptors pass about a tenth of their current as Ca2+.
+    pub const NMDA_CHANNEL: ChannelBuilder = ChannelBuilder {
+        ion_selectivity: IonSelectivity {
+            na: 0.45,
+            k: 0.45,
+            cl: 0.0,
+            ca: 0.10,
+        },
+        activation_parameters: None,
+        inactivation_parameters: None,
+    };
+
+    /// The channels above by name, with a typical peak conductance
+    /// (S/cm^2) for each, for choosing from in the GUI.
+    pub fn library() -> Vec<(&'static str, ChannelBuilder, f32)> {
+        vec![
+            ("Giant squid Na+", giant_squid::NA_CHANNEL, 0.12),
+            ("Giant squid K+", giant_squid::K_CHANNEL, 0.036),
+            ("Giant squid Ca2+", giant_squid::CA_CHANNEL, 0.01),
+            ("Giant squid leak", giant_squid::LEAK_CHANNEL, 0.3e-3),
+            ("Rat thalamocortical Na+ transient", rat_thalamocortical::NA_TRANSIENT, 0.12),
+            ("Rat thalamocortical K+ slow", rat_thalamocortical::K_SLOW, 0.036),
+            ("Rat CA1 HCN (soma)", rat_ca1::HCN_CHANNEL_SOMA, 0.001),
+            ("Rat CA1 HCN (dendrite)", rat_ca1::HCN_CHANNEL_DENDRITE, 0.001),
+            ("AMPA", AMPA_CHANNEL, 0.3e-3),
+        ]
+    }
 }
 
 #[cfg(test)]
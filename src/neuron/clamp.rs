@@ -0,0 +1,182 @@
+use crate::analysis::Trace;
+use crate::dimension::{Interval, Kelvin, MilliVolts};
+use crate::neuron::channel::{
+    ca_reversal, cl_reversal, k_reversal, na_reversal, ChannelBuilder, Magnitude,
+};
+use crate::neuron::membrane::MembraneChannel;
+use crate::neuron::solution::Solution;
+
+/// Samples recorded per sweep, regardless of the simulation step.
+const SAMPLES_PER_SWEEP: usize = 400;
+
+/// A family of voltage steps from a common holding potential, as used to
+/// characterize a channel's voltage dependence.
+#[derive(Clone, Debug)]
+pub struct VoltageClampProtocol {
+    pub holding_potential: MilliVolts,
+    pub first_step: MilliVolts,
+    pub last_step: MilliVolts,
+    pub step_increment: MilliVolts,
+    pub step_duration: Interval,
+    pub simulation_step: Interval,
+}
+
+impl Default for VoltageClampProtocol {
+    fn default() -> Self {
+        VoltageClampProtocol {
+            holding_potential: MilliVolts(-90.0),
+            first_step: MilliVolts(-80.0),
+            last_step: MilliVolts(40.0),
+            step_increment: MilliVolts(10.0),
+            step_duration: Interval(0.02),
+            simulation_step: Interval(5e-6),
+        }
+    }
+}
+
+/// The clamp current (uA/cm^2) during one voltage step.
+#[derive(Clone, Debug)]
+pub struct ClampSweep {
+    pub command: MilliVolts,
+    pub current: Trace,
+    /// The current of largest magnitude during the step.
+    pub peak: f32,
+    /// The current at the end of the step.
+    pub steady_state: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ClampResult {
+    pub sweeps: Vec<ClampSweep>,
+    /// The channel's reversal potential, used to convert currents to
+    /// conductances.
+    pub reversal: MilliVolts,
+    /// Peak conductance against command potential, normalized to its
+    /// maximum.
+    pub conductance: Vec<(f32, f32)>,
+    /// A Boltzmann fit to `conductance`.
+    pub fit: Option<Magnitude>,
+}
+
+impl ClampResult {
+    pub fn peak_iv(&self) -> Vec<(f32, f32)> {
+        self.sweeps.iter().map(|s| (s.command.0, s.peak)).collect()
+    }
+
+    pub fn steady_state_iv(&self) -> Vec<(f32, f32)> {
+        self.sweeps.iter().map(|s| (s.command.0, s.steady_state)).collect()
+    }
+}
+
+impl VoltageClampProtocol {
+    pub fn commands(&self) -> Vec<MilliVolts> {
+        if self.step_increment.0 <= 0.0 {
+            return vec![self.first_step.clone()];
+        }
+        let n = ((self.last_step.0 - self.first_step.0) / self.step_increment.0).floor() as i32;
+        (0..=n.max(0))
+            .map(|i| MilliVolts(self.first_step.0 + i as f32 * self.step_increment.0))
+            .collect()
+    }
+
+    /// Clamp a patch of membrane containing a single channel type through
+    /// every step of the protocol. The channel starts each sweep at its
+    /// steady state for the holding potential.
+    pub fn run(
+        &self,
+        channel: &ChannelBuilder,
+        siemens_per_square_cm: f32,
+        internal_solution: &Solution,
+        external_solution: &Solution,
+        temperature: &Kelvin,
+    ) -> ClampResult {
+        let e_k = k_reversal(internal_solution, external_solution, temperature);
+        let e_na = na_reversal(internal_solution, external_solution, temperature);
+        let e_cl = cl_reversal(internal_solution, external_solution, temperature);
+        let e_ca = ca_reversal(internal_solution, external_solution, temperature);
+        let selectivity = channel.ion_selectivity.normalize();
+        let reversal = MilliVolts(
+            selectivity.k * e_k.0 + selectivity.na * e_na.0 + selectivity.cl * e_cl.0 + selectivity.ca * e_ca.0,
+        );
+
+        let n_steps = (self.step_duration.0 / self.simulation_step.0).round().max(1.0) as usize;
+        let record_every = (n_steps / SAMPLES_PER_SWEEP).max(1);
+
+        let sweeps: Vec<ClampSweep> = self
+            .commands()
+            .into_iter()
+            .map(|command| {
+                let mut membrane_channel = MembraneChannel {
+                    channel: channel.clone().build(&self.holding_potential),
+                    siemens_per_square_cm,
+                };
+                let mut current = Trace::new(format!("{:.0} mV", command.0));
+                let mut peak: f32 = 0.0;
+                let mut last = 0.0;
+                for step in 0..=n_steps {
+                    let i = membrane_channel.channel_current_per_cm(&e_k, &e_na, &e_cl, &e_ca, &command) * 1e6;
+                    if i.abs() > peak.abs() {
+                        peak = i;
+                    }
+                    if step % record_every == 0 {
+                        current.push(step as f32 * self.simulation_step.0, i);
+                    }
+                    last = i;
+                    membrane_channel.channel.step(&command, &self.simulation_step);
+                }
+                ClampSweep { command, current, peak, steady_state: last }
+            })
+            .collect();
+
+        let raw_conductance: Vec<(f32, f32)> = sweeps
+            .iter()
+            .filter(|s| (s.command.0 - reversal.0).abs() > 1.0)
+            .map(|s| (s.command.0, s.peak / (s.command.0 - reversal.0)))
+            .collect();
+        let g_max = raw_conductance.iter().map(|(_, g)| *g).fold(0.0, f32::max);
+        let conductance: Vec<(f32, f32)> = if g_max > 0.0 {
+            raw_conductance.iter().map(|(v, g)| (*v, g / g_max)).collect()
+        } else {
+            vec![]
+        };
+        let fit = Magnitude::fit(&conductance);
+
+        ClampResult { sweeps, reversal, conductance, fit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::BODY_TEMPERATURE;
+    use crate::neuron::channel::{Gating, TimeConstant, K};
+    use crate::neuron::solution::{EXAMPLE_CYTOPLASM, INTERSTICIAL_FLUID};
+
+    #[test]
+    fn recovers_boltzmann_of_single_gate_channel() {
+        let channel = ChannelBuilder {
+            ion_selectivity: K,
+            activation_parameters: Some(Gating {
+                gates: 1,
+                steady_state_magnitude: Magnitude {
+                    v_at_half_max: MilliVolts(-30.0),
+                    slope: 10.0,
+                },
+                time_constant: TimeConstant::Instantaneous,
+            }),
+            inactivation_parameters: None,
+        };
+        let protocol = VoltageClampProtocol {
+            step_duration: Interval(0.001),
+            simulation_step: Interval(1e-4),
+            ..VoltageClampProtocol::default()
+        };
+        let result = protocol.run(&channel, 0.036, &EXAMPLE_CYTOPLASM, &INTERSTICIAL_FLUID, &BODY_TEMPERATURE);
+
+        assert_eq!(result.sweeps.len(), 13);
+        assert!(result.sweeps.iter().all(|s| s.peak > 0.0));
+        let fit = result.fit.expect("fit");
+        assert!((fit.v_at_half_max.0 - -30.0).abs() < 0.5);
+        assert!((fit.slope - 10.0).abs() < 0.5);
+    }
+}
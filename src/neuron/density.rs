@@ -0,0 +1,87 @@
+//! Channel densities that vary along the neuron.
+//!
+//! A membrane is shared by every segment of its SWC type, but many
+//! channels are not spread evenly over it: HCN, for one, grows several
+//! times denser along the apical dendrite of a pyramidal cell. A channel
+//! in the scene may therefore give its conductance as a formula in `d`,
+//! the segment's path distance (µm) from the soma, such as
+//! `2e-5 * exp(d / 300)`. The formula is evaluated for each segment as it
+//! is spawned, and stays in the scene in place of a single density.
+
+use crate::expr::{self, Expr};
+use crate::neuron::membrane::Membrane;
+use crate::serialize;
+
+/// The variable holding the path distance from the soma.
+pub const DISTANCE: &str = "d";
+
+/// A parsed density formula.
+#[derive(Clone, Debug)]
+pub struct DensityRule(Expr);
+
+impl DensityRule {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let expr = expr::parse(source)?;
+        match expr.variables().into_iter().find(|v| v != DISTANCE) {
+            Some(v) => Err(format!("Unknown variable {} in density {}; only {} is defined", v, source, DISTANCE)),
+            None => Ok(DensityRule(expr)),
+        }
+    }
+
+    /// The conductance (S/cm²) at `distance_microns` from the soma.
+    pub fn at(&self, distance_microns: f32) -> Result<f32, String> {
+        let siemens_per_square_cm = self.0.eval(&|name: &str| (name == DISTANCE).then_some(distance_microns))?;
+        if siemens_per_square_cm.is_finite() && siemens_per_square_cm >= 0.0 {
+            Ok(siemens_per_square_cm)
+        } else {
+            Err(format!("Density {} at {} µm", siemens_per_square_cm, distance_microns))
+        }
+    }
+}
+
+/// Whether any channel of the membrane has a density rule, so that its
+/// segments need their distances from the soma.
+pub fn has_rules(membrane: &serialize::Membrane) -> bool {
+    membrane.membrane_channels.iter().any(|c| c.density.is_some())
+}
+
+/// The membrane of a segment `distance_microns` from the soma, with each
+/// density rule evaluated there.
+pub fn membrane_at(membrane: &serialize::Membrane, distance_microns: f32) -> Result<Membrane, String> {
+    let mut deserialized = Membrane::deserialize(membrane);
+    for (channel, serialized) in deserialized.membrane_channels.iter_mut().zip(&membrane.membrane_channels) {
+        if let Some(source) = &serialized.density {
+            channel.siemens_per_square_cm = DensityRule::parse(source)?.at(distance_microns)?;
+        }
+    }
+    Ok(deserialized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimension::MilliVolts;
+    use crate::neuron::channel::common_channels::rat_ca1::HCN_CHANNEL_DENDRITE;
+
+    #[test]
+    fn hcn_grows_along_the_dendrite() {
+        let membrane = serialize::Membrane {
+            membrane_channels: vec![serialize::MembraneChannel {
+                channel: HCN_CHANNEL_DENDRITE.build(&MilliVolts(-70.0)).serialize(),
+                siemens_per_square_cm: 0.0,
+                density: Some("2e-5 * exp(d / 100)".to_string()),
+            }],
+            capacitance_farads_per_square_cm: 1e-6,
+        };
+        let density = |d| membrane_at(&membrane, d).unwrap().membrane_channels[0].siemens_per_square_cm;
+        assert!((density(0.0) - 2e-5).abs() < 1e-9);
+        assert!((density(100.0) / density(0.0) - std::f32::consts::E).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        assert!(DensityRule::parse("1e-3 * x").is_err());
+        assert!(DensityRule::parse("1e-3 * (d").is_err());
+        assert!(DensityRule::parse("-1e-3 * d").unwrap().at(10.0).is_err());
+    }
+}
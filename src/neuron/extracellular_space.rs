@@ -0,0 +1,141 @@
+//! Potassium accumulation in the extracellular space.
+//!
+//! By default every segment sits in the same fixed bath,
+//! `Env.extracellular_solution`. With `Env.potassium` set, each segment
+//! has its own thin extracellular shell (`ExtracellularSpace`, after
+//! Frankenhaeuser and Hodgkin) whose potassium rises with the segment's
+//! outward K+ current, relaxes toward the bath as glia and the vasculature
+//! clear it, and diffuses to the shells of neighbouring segments. The
+//! segment's reversal potentials follow its shell, so that sustained
+//! firing depolarizes E_K.
+//!
+//! Efflux is added every step, with the current; clearance and diffusion
+//! are slower, and are applied once a frame for the steps taken.
+
+use bevy::prelude::*;
+
+use crate::dimension::{Interval, SimulationStepSeconds, StepCount};
+use crate::neuron::segment::ecs::Segment;
+use crate::neuron::solution::Solution;
+use crate::neuron::Junction;
+use crate::plugin::Env;
+
+/// How extracellular potassium accumulates, when it does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PotassiumAccumulation {
+    /// Thickness (µm) of the shell around each segment that K+ flows into.
+    pub shell_thickness_um: f32,
+    /// Time constant of clearance toward the bath concentration.
+    pub clearance_tau: Interval,
+    /// Time constant of equilibration between the shells of neighbouring
+    /// segments.
+    pub diffusion_tau: Interval,
+}
+
+impl Default for PotassiumAccumulation {
+    fn default() -> Self {
+        PotassiumAccumulation {
+            shell_thickness_um: 0.07,
+            clearance_tau: Interval(0.1),
+            diffusion_tau: Interval(0.05),
+        }
+    }
+}
+
+impl PotassiumAccumulation {
+    /// The rate (M/s) at which `k_current` (A/cm^2, outward positive)
+    /// raises the shell's potassium.
+    pub fn efflux_rate(&self, k_current: f32, faraday: f32) -> f32 {
+        // A shell of 1 cm^2 holds thickness (cm) mL, 1e-3 as many liters.
+        let liters_per_square_cm = self.shell_thickness_um * 1e-4 * 1e-3;
+        k_current / (faraday * liters_per_square_cm)
+    }
+}
+
+/// A segment's own extracellular solution, present while potassium
+/// accumulates.
+#[derive(Clone, Component, Debug, PartialEq)]
+pub struct ExtracellularSpace(pub Solution);
+
+/// The extracellular solution a segment sees.
+pub fn local_solution<'a>(env: &'a Env, space: Option<&'a ExtracellularSpace>) -> &'a Solution {
+    space.map_or(&env.extracellular_solution, |space| &space.0)
+}
+
+/// Relax `k` (M) toward `target` for `seconds`, with time constant `tau`.
+fn relax(k: f32, target: f32, seconds: f32, tau: &Interval) -> f32 {
+    if tau.0 <= 0.0 {
+        return target;
+    }
+    target + (k - target) * (-seconds / tau.0).exp()
+}
+
+/// Give each segment an `ExtracellularSpace` when accumulation is turned
+/// on, starting from the bath, and take them away when it is turned off.
+pub fn track_extracellular_spaces(
+    mut commands: Commands,
+    env: Res<Env>,
+    segments: Query<(Entity, Has<ExtracellularSpace>), With<Segment>>,
+) {
+    for (segment, has_space) in &segments {
+        match (env.potassium.is_some(), has_space) {
+            (true, false) => {
+                commands.entity(segment).insert(ExtracellularSpace(env.extracellular_solution.clone()));
+            },
+            (false, true) => {
+                commands.entity(segment).remove::<ExtracellularSpace>();
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Clear and diffuse extracellular potassium over the steps taken since
+/// the last frame.
+pub fn clear_extracellular_potassium(
+    env: Res<Env>,
+    step_count: Res<StepCount>,
+    simulation_step: Res<SimulationStepSeconds>,
+    mut last_step: Local<Option<u64>>,
+    junctions: Query<&Junction>,
+    mut spaces: Query<&mut ExtracellularSpace>,
+) {
+    let steps = last_step.map_or(0, |last| step_count.0.saturating_sub(last));
+    *last_step = Some(step_count.0);
+    let Some(settings) = env.potassium.as_ref() else { return };
+    if steps == 0 {
+        return;
+    }
+    let seconds = steps as f32 * simulation_step.0;
+    let bath = env.extracellular_solution.k_concentration.0;
+    for mut space in &mut spaces {
+        let k = &mut space.0.k_concentration.0;
+        *k = relax(*k, bath, seconds, &settings.clearance_tau);
+    }
+    for junction in &junctions {
+        let Ok([mut first, mut second]) = spaces.get_many_mut([junction.first_segment, junction.second_segment]) else { continue };
+        let (k1, k2) = (first.0.k_concentration.0, second.0.k_concentration.0);
+        let mean = 0.5 * (k1 + k2);
+        first.0.k_concentration.0 = relax(k1, mean, seconds, &settings.diffusion_tau);
+        second.0.k_concentration.0 = relax(k2, mean, seconds, &settings.diffusion_tau);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::FARADAY;
+
+    #[test]
+    fn efflux_accumulates_and_clears() {
+        let settings = PotassiumAccumulation::default();
+        // 1 mA/cm^2 of K+ current for 1 ms raises a 70 nm shell by about
+        // 1.5 mM.
+        let rise = settings.efflux_rate(1e-3, FARADAY) * 1e-3;
+        assert!((rise - 1.48e-3).abs() < 0.01e-3, "{}", rise);
+
+        let k = relax(10e-3, 5e-3, settings.clearance_tau.0, &settings.clearance_tau);
+        assert!((k - (5e-3 + 5e-3 * (-1.0f32).exp())).abs() < 1e-7);
+        assert_eq!(relax(10e-3, 5e-3, 1.0, &Interval(0.0)), 5e-3);
+    }
+}
@@ -0,0 +1,221 @@
+//! The shapes of segments, in cm, and the membrane areas and volumes the
+//! biophysics draws on.
+//!
+//! A neurite segment is a frustum: a cylinder tapering linearly from the
+//! diameter at its start to the diameter at its end, a cylinder being a
+//! frustum with equal ends. Its membrane is the lateral surface alone; the
+//! flat ends are where it joins its neighbours. A soma may instead be a
+//! sphere, all of whose surface is membrane.
+//!
+//! Neighbouring segments exchange current through the cytoplasm between
+//! their centres, with the resistance of the half of each segment on the
+//! way (`axial_conductance`).
+
+use bevy::prelude::Component;
+use std::f32::consts::PI;
+
+use crate::dimension::Diameter;
+use crate::serialize;
+
+#[derive(Clone, Component, Debug)]
+pub enum Geometry {
+    Frustum {
+        start_diameter: Diameter,
+        end_diameter: Diameter,
+        length: f32,
+    },
+    Sphere {
+        diameter: Diameter,
+    },
+}
+
+impl Geometry {
+    pub fn cylinder(diameter: Diameter, length: f32) -> Geometry {
+        Geometry::Frustum {
+            start_diameter: diameter.clone(),
+            end_diameter: diameter,
+            length,
+        }
+    }
+
+    /// The area of membrane.
+    pub fn surface_area(&self) -> f32 {
+        match self {
+            Geometry::Frustum { start_diameter, end_diameter, length } => {
+                let (r1, r2) = (start_diameter.0 * 0.5, end_diameter.0 * 0.5);
+                let slant = ((r1 - r2).powi(2) + length.powi(2)).sqrt();
+                PI * (r1 + r2) * slant
+            },
+            Geometry::Sphere { diameter } => PI * diameter.0.powi(2),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        match self {
+            Geometry::Frustum { start_diameter, end_diameter, length } => {
+                let (r1, r2) = (start_diameter.0 * 0.5, end_diameter.0 * 0.5);
+                PI * length / 3.0 * (r1 * r1 + r1 * r2 + r2 * r2)
+            },
+            Geometry::Sphere { diameter } => PI / 6.0 * diameter.0.powi(3),
+        }
+    }
+
+    /// The length along the segment's axis; a sphere's diameter.
+    pub fn length(&self) -> f32 {
+        match self {
+            Geometry::Frustum { length, .. } => *length,
+            Geometry::Sphere { diameter } => diameter.0,
+        }
+    }
+
+    /// The diameters at the start and end of the segment.
+    pub fn end_diameters(&self) -> (f32, f32) {
+        match self {
+            Geometry::Frustum { start_diameter, end_diameter, .. } => (start_diameter.0, end_diameter.0),
+            Geometry::Sphere { diameter } => (diameter.0, diameter.0),
+        }
+    }
+
+    /// The diameter `fraction` of the way from the start to the end.
+    fn diameter_at(&self, fraction: f32) -> f32 {
+        let (start, end) = self.end_diameters();
+        start + (end - start) * fraction
+    }
+
+    /// The resistance (Ω) of cytoplasm of `resistivity` (Ω cm) along the
+    /// axis, between fractions `from` and `to` of the length: for a
+    /// frustum, 4 ρ l / (π d_from d_to). A sphere is taken as a cylinder
+    /// as long as it is wide.
+    pub fn axial_resistance(&self, from: f32, to: f32, resistivity: f32) -> f32 {
+        let length = (to - from).abs() * self.length();
+        4.0 * resistivity * length / (PI * self.diameter_at(from) * self.diameter_at(to))
+    }
+
+    pub fn serialize(&self) -> serialize::Geometry {
+        match self {
+            Geometry::Frustum { start_diameter, end_diameter, length } => serialize::Geometry::Frustum {
+                start_diameter_cm: start_diameter.0,
+                end_diameter_cm: end_diameter.0,
+                length_cm: *length,
+            },
+            Geometry::Sphere { diameter } => serialize::Geometry::Sphere { diameter_cm: diameter.0 },
+        }
+    }
+
+    pub fn deserialize(s: &serialize::Geometry) -> Result<Geometry, String> {
+        let non_negative = |name: &str, value: f32| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(value)
+            } else {
+                Err(format!("Segment {name} must be a non-negative number of cm, not {value}"))
+            }
+        };
+        match s {
+            serialize::Geometry::Frustum { start_diameter_cm, end_diameter_cm, length_cm } => Ok(Geometry::Frustum {
+                start_diameter: Diameter(non_negative("start diameter", *start_diameter_cm)?),
+                end_diameter: Diameter(non_negative("end diameter", *end_diameter_cm)?),
+                length: non_negative("length", *length_cm)?,
+            }),
+            serialize::Geometry::Sphere { diameter_cm } => Ok(Geometry::Sphere {
+                diameter: Diameter(non_negative("diameter", *diameter_cm)?),
+            }),
+        }
+    }
+}
+
+/// The conductance (S) between the centres of two segments joined end
+/// to start, as a parent is to its child, through cytoplasm of
+/// `resistivity` (Ω cm). Zero if a segment narrows to a point.
+pub fn axial_conductance(first: &Geometry, second: &Geometry, resistivity: f32) -> f32 {
+    let resistance = first.axial_resistance(0.5, 1.0, resistivity) + second.axial_resistance(0.0, 0.5, resistivity);
+    if resistance.is_nan() {
+        0.0
+    } else {
+        1.0 / resistance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn frustum(d1: f32, d2: f32, length: f32) -> Geometry {
+        Geometry::Frustum { start_diameter: Diameter(d1), end_diameter: Diameter(d2), length }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-5 * a.abs().max(b.abs())
+    }
+
+    #[test]
+    fn cylinders_and_spheres() {
+        let cylinder = Geometry::cylinder(Diameter(2.0), 3.0);
+        assert!(close(cylinder.surface_area(), 6.0 * PI));
+        assert!(close(cylinder.volume(), 3.0 * PI));
+        let sphere = Geometry::Sphere { diameter: Diameter(2.0) };
+        assert!(close(sphere.surface_area(), 4.0 * PI));
+        assert!(close(sphere.volume(), 4.0 / 3.0 * PI));
+        // A cone holds a third of its cylinder.
+        assert!(close(frustum(2.0, 0.0, 3.0).volume(), PI));
+        assert!(close(frustum(2.0, 0.0, 3.0).surface_area(), PI * 10.0f32.sqrt()));
+    }
+
+    #[test]
+    fn frustum_properties() {
+        let mut rng = Rng::new(1584);
+        let mut draw = || (rng.uniform() * 10.0 + 0.01) as f32;
+        for _ in 0..1000 {
+            let (d1, d2, length) = (draw(), draw(), draw());
+            let segment = frustum(d1, d2, length);
+            let (area, volume) = (segment.surface_area(), segment.volume());
+
+            // Reversing a segment changes nothing.
+            let reversed = frustum(d2, d1, length);
+            assert!(close(area, reversed.surface_area()));
+            assert!(close(volume, reversed.volume()));
+
+            // It lies between the cylinders of its narrow and wide ends,
+            // and its slant is at least its length.
+            let (narrow, wide) = (d1.min(d2), d1.max(d2));
+            let thin = Geometry::cylinder(Diameter(narrow), length);
+            let thick = Geometry::cylinder(Diameter(wide), length);
+            assert!(thin.volume() <= volume * (1.0 + 1e-5) && volume <= thick.volume() * (1.0 + 1e-5));
+            assert!(area >= PI * 0.5 * (d1 + d2) * length * (1.0 - 1e-5));
+
+            // Areas scale as the square, volumes as the cube.
+            let scaled = frustum(2.0 * d1, 2.0 * d2, 2.0 * length);
+            assert!(close(scaled.surface_area(), 4.0 * area));
+            assert!(close(scaled.volume(), 8.0 * volume));
+
+            // Cutting it in two keeps its volume and membrane.
+            let middle = 0.5 * (d1 + d2);
+            let halves = [frustum(d1, middle, 0.5 * length), frustum(middle, d2, 0.5 * length)];
+            assert!(close(halves.iter().map(Geometry::volume).sum(), volume));
+            assert!(close(halves.iter().map(Geometry::surface_area).sum(), area));
+
+            let round_trip = Geometry::deserialize(&segment.serialize()).unwrap();
+            assert!(close(round_trip.volume(), volume));
+        }
+        assert!(Geometry::deserialize(&serialize::Geometry::Sphere { diameter_cm: -1.0 }).is_err());
+    }
+
+    #[test]
+    fn axial_conductance_follows_the_cytoplasm() {
+        // Two 1 µm-wide, 10 µm-long dendritic segments at 100 Ω cm are
+        // 12.7 MΩ apart, centre to centre.
+        let segment = Geometry::cylinder(Diameter(1e-4), 1e-3);
+        let conductance = axial_conductance(&segment, &segment, 100.0);
+        assert!(close(1.0 / conductance, 4.0 * 100.0 * 1e-3 / (PI * 1e-8)));
+
+        // Widening the join lowers the resistance; a tip cuts it off.
+        let widening = frustum(1e-4, 2e-4, 1e-3);
+        assert!(axial_conductance(&segment, &widening, 100.0) > conductance);
+        assert_eq!(axial_conductance(&segment, &frustum(0.0, 1e-4, 1e-3), 100.0), 0.0);
+
+        // Resistance adds along the segment.
+        let whole = widening.axial_resistance(0.0, 1.0, 100.0);
+        let halves = widening.axial_resistance(0.0, 0.5, 100.0) + widening.axial_resistance(0.5, 1.0, 100.0);
+        assert!(close(whole, halves));
+    }
+}
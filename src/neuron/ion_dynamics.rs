@@ -0,0 +1,177 @@
+//! Intracellular ion concentrations that follow the currents through the
+//! membrane.
+//!
+//! By default a segment's `Solution` is fixed, which is a fine
+//! approximation over a few spikes. With `IonDynamics.intracellular` on,
+//! every step moves the charge each ion carries across the membrane into
+//! or out of the segment's volume, so that over seconds of firing Na+
+//! loads and K+ depletes, shifting the reversal potentials. Thin segments,
+//! with more membrane per volume, change fastest.
+//!
+//! With `IonDynamics.longitudinal_diffusion` on, ions also diffuse along
+//! each `Junction`, through its pore, between the centres of the segments
+//! it joins, so that a gradient built up along a dendrite relaxes. Like
+//! the clearance of extracellular potassium, this is slow beside the
+//! membrane currents, and is applied once a frame for the steps taken.
+//!
+//! Calcium is different: it acts where it enters, in a shell beneath the
+//! membrane; see `neuron::calcium`.
+
+use bevy::prelude::*;
+
+use crate::dimension::{Diameter, SimulationStepSeconds, StepCount};
+use crate::neuron::calcium::CalciumBuffering;
+use crate::neuron::membrane::IonCurrents;
+use crate::neuron::segment::ecs::Segment;
+use crate::neuron::segment::Geometry;
+use crate::neuron::solution::Solution;
+use crate::neuron::Junction;
+
+/// Diffusion coefficients (cm^2/s) in cytoplasm, about half those in
+/// water. Calcium's is that of free Ca2+, which buffers slow further.
+pub const K_DIFFUSION: f32 = 1.0e-5;
+pub const NA_DIFFUSION: f32 = 0.7e-5;
+pub const CL_DIFFUSION: f32 = 1.0e-5;
+pub const CA_DIFFUSION: f32 = 0.3e-5;
+
+#[derive(Clone, Debug, Default, Resource)]
+pub struct IonDynamics {
+    /// Whether segments' intracellular concentrations change.
+    pub intracellular: bool,
+    /// Whether, and how, calcium is buffered in a shell beneath each
+    /// segment's membrane.
+    pub calcium: Option<CalciumBuffering>,
+    /// Whether ions diffuse between segments joined by a junction.
+    pub longitudinal_diffusion: bool,
+}
+
+/// The rate (M/s) at which an outward `current` (A/cm^2) of an ion of
+/// `valence` changes its concentration inside `geometry`.
+pub fn concentration_rate(current: f32, valence: f32, geometry: &Geometry, faraday: f32) -> f32 {
+    // Geometry is in cm, so the volume is in mL.
+    let liters = geometry.volume() * 1e-3;
+    -current * geometry.surface_area() / (valence * faraday * liters)
+}
+
+/// Move the ions carried by `currents` over `interval` seconds into or out
+/// of `solution`, never below zero.
+pub fn update_intracellular(solution: &mut Solution, currents: &IonCurrents, geometry: &Geometry, interval: f32, faraday: f32) {
+    let update = |concentration: &mut f32, current: f32, valence: f32| {
+        *concentration = (*concentration + concentration_rate(current, valence, geometry, faraday) * interval).max(0.0);
+    };
+    update(&mut solution.k_concentration.0, currents.k, 1.0);
+    update(&mut solution.na_concentration.0, currents.na, 1.0);
+    update(&mut solution.cl_concentration.0, currents.cl, -1.0);
+    update(&mut solution.ca_concentration.0, currents.ca, 2.0);
+}
+
+/// Let ions diffuse for `seconds` between two segments joined through a
+/// pore of `pore_diameter`, conserving the amount of each.
+///
+/// Between two compartments the difference in concentration decays
+/// exponentially, so this is exact for a pair however long the interval.
+pub fn diffuse(
+    (first, first_geometry): (&mut Solution, &Geometry),
+    (second, second_geometry): (&mut Solution, &Geometry),
+    pore_diameter: &Diameter,
+    seconds: f32,
+) {
+    let cross_section = std::f32::consts::PI * (pore_diameter.0 * 0.5).powi(2);
+    let distance = 0.5 * (first_geometry.length() + second_geometry.length());
+    // Geometry is in cm, so volumes are in mL.
+    let (inverse_first, inverse_second) = (1e3 / first_geometry.volume(), 1e3 / second_geometry.volume());
+    if distance <= 0.0 || !inverse_first.is_finite() || !inverse_second.is_finite() {
+        return;
+    }
+    let exchange = |c1: &mut f32, c2: &mut f32, coefficient: f32| {
+        // Liters per second carried across at unit concentration difference.
+        let flow = coefficient * cross_section / distance * 1e-3;
+        let difference = *c1 - *c2;
+        let remaining = difference * (-flow * (inverse_first + inverse_second) * seconds).exp();
+        let moles = (difference - remaining) / (inverse_first + inverse_second);
+        *c1 -= moles * inverse_first;
+        *c2 += moles * inverse_second;
+    };
+    exchange(&mut first.k_concentration.0, &mut second.k_concentration.0, K_DIFFUSION);
+    exchange(&mut first.na_concentration.0, &mut second.na_concentration.0, NA_DIFFUSION);
+    exchange(&mut first.cl_concentration.0, &mut second.cl_concentration.0, CL_DIFFUSION);
+    exchange(&mut first.ca_concentration.0, &mut second.ca_concentration.0, CA_DIFFUSION);
+}
+
+/// Diffuse intracellular ions along every junction over the steps taken
+/// since the last frame.
+pub fn diffuse_along_junctions(
+    ion_dynamics: Res<IonDynamics>,
+    step_count: Res<StepCount>,
+    simulation_step: Res<SimulationStepSeconds>,
+    mut last_step: Local<Option<u64>>,
+    junctions: Query<&Junction>,
+    mut segments: Query<(&mut Solution, &Geometry), With<Segment>>,
+) {
+    let steps = last_step.map_or(0, |last| step_count.0.saturating_sub(last));
+    *last_step = Some(step_count.0);
+    if !ion_dynamics.longitudinal_diffusion || steps == 0 {
+        return;
+    }
+    let seconds = steps as f32 * simulation_step.0;
+    for junction in &junctions {
+        let Ok([(mut first, first_geometry), (mut second, second_geometry)]) =
+            segments.get_many_mut([junction.first_segment, junction.second_segment]) else { continue };
+        diffuse((&mut first, first_geometry), (&mut second, second_geometry), &junction.pore_diameter, seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::FARADAY;
+    use crate::neuron::solution::EXAMPLE_CYTOPLASM;
+
+    #[test]
+    fn inward_currents_load_thin_segments_fastest() {
+        // 1 µm across and 10 µm long, in cm.
+        let thin = Geometry::cylinder(Diameter(1e-4), 1e-3);
+        let thick = Geometry::cylinder(Diameter(1e-3), 1e-3);
+        let inward_na = IonCurrents { na: -1e-3, ..IonCurrents::default() };
+
+        let mut solution = EXAMPLE_CYTOPLASM;
+        update_intracellular(&mut solution, &inward_na, &thin, 1e-3, FARADAY);
+        // 4 / diameter of surface per volume: 1 mA/cm^2 for 1 ms adds
+        // 4e4 * 1e-6 / F * 1e3 M, about 0.41 mM.
+        let loaded = solution.na_concentration.0 - EXAMPLE_CYTOPLASM.na_concentration.0;
+        assert!((loaded - 0.4146e-3).abs() < 0.001e-3, "{}", loaded);
+        assert_eq!(solution.k_concentration, EXAMPLE_CYTOPLASM.k_concentration);
+
+        let rate = concentration_rate(-1e-3, 1.0, &thick, FARADAY);
+        assert!((rate * 1e-3 * 10.0 - loaded).abs() < 1e-7);
+
+        // Outward Cl- current is chloride coming in.
+        assert!(concentration_rate(1e-3, -1.0, &thin, FARADAY) > 0.0);
+    }
+
+    #[test]
+    fn diffusion_relaxes_gradients_and_conserves_ions() {
+        // Two 1 µm-wide, 10 µm-long compartments, joined along their
+        // full width.
+        let geometry = Geometry::cylinder(Diameter(1e-4), 1e-3);
+        let small = Geometry::cylinder(Diameter(1e-4), 0.5e-3);
+        let mut first = EXAMPLE_CYTOPLASM;
+        first.na_concentration.0 += 10e-3;
+        let mut second = EXAMPLE_CYTOPLASM;
+        let before = first.na_concentration.0 * geometry.volume() + second.na_concentration.0 * small.volume();
+
+        // The difference decays at D A / L (1/V1 + 1/V2), 28 per second.
+        diffuse((&mut first, &geometry), (&mut second, &small), &Diameter(1e-4), 0.05);
+        let difference = first.na_concentration.0 - second.na_concentration.0;
+        assert!((difference - 10e-3 * (-1.4f32).exp()).abs() < 0.01e-3, "{}", difference);
+        let after = first.na_concentration.0 * geometry.volume() + second.na_concentration.0 * small.volume();
+        assert!((after - before).abs() / before < 1e-5);
+        assert!((first.k_concentration.0 - second.k_concentration.0).abs() < 1e-9);
+
+        // Long enough, the two meet at the volume-weighted mean.
+        diffuse((&mut first, &geometry), (&mut second, &small), &Diameter(1e-4), 1.0);
+        assert!((first.na_concentration.0 - second.na_concentration.0).abs() < 1e-7);
+        let mean = before / (geometry.volume() + small.volume());
+        assert!((first.na_concentration.0 - mean).abs() < 1e-6);
+    }
+}
@@ -1,5 +1,5 @@
 // use crate::constants::{gas_constant, inverse_faraday};
-use bevy::prelude::{Assets, Color, Component, FromWorld, Handle, Resource, StandardMaterial, World};
+use bevy::prelude::Component;
 // use uuid::Uuid;
 // use std::hash::Hash;
 
@@ -17,9 +17,19 @@ pub struct Membrane {
     pub capacitance: FaradsPerSquareCm,
 }
 
-#[derive(Component)]
+#[derive(Clone, Component)]
 pub struct MembraneVoltage(pub MilliVolts);
 
+/// Current densities (A/cm^2, outward positive) through a membrane, by
+/// ion.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IonCurrents {
+    pub k: f32,
+    pub na: f32,
+    pub cl: f32,
+    pub ca: f32,
+}
+
 impl Membrane {
     pub fn current_per_square_cm(
         &self,
@@ -43,6 +53,30 @@ impl Membrane {
             .sum()
     }
 
+    /// `current_per_square_cm`, split by the ion carrying it.
+    pub fn ion_currents_per_square_cm(
+        &self,
+        k_reversal: &MilliVolts,
+        na_reversal: &MilliVolts,
+        cl_reversal: &MilliVolts,
+        ca_reversal: &MilliVolts,
+        membrane_potential: &MilliVolts,
+    ) -> IonCurrents {
+        let mut currents = IonCurrents::default();
+        for membrane_channel in &self.membrane_channels {
+            let selectivity = &membrane_channel.channel.ion_selectivity;
+            let conductance = membrane_channel.channel.conductance_coefficient() * membrane_channel.siemens_per_square_cm;
+            let current = |selectivity: f32, reversal: &MilliVolts| {
+                selectivity * conductance * (membrane_potential.0 - reversal.0) * 0.001
+            };
+            currents.k += current(selectivity.k, k_reversal);
+            currents.na += current(selectivity.na, na_reversal);
+            currents.cl += current(selectivity.cl, cl_reversal);
+            currents.ca += current(selectivity.ca, ca_reversal);
+        }
+        currents
+    }
+
     /// A quick snapshot of the per_square_cm conductances of each
     /// ion.
     pub fn conductances(&self) -> (f32, f32, f32, f32) {
@@ -83,6 +117,7 @@ impl Membrane {
                 }| serialize::MembraneChannel {
                     channel: channel.serialize(),
                     siemens_per_square_cm: siemens_per_square_cm.clone(),
+                    density: None,
                 }).collect(),
             capacitance_farads_per_square_cm: self.capacitance.0,
         }
@@ -144,12 +179,13 @@ impl MembraneChannel {
     pub fn serialize(&self) -> serialize::MembraneChannel {
         serialize::MembraneChannel {
             channel: self.channel.serialize(),
-            siemens_per_square_cm: self.siemens_per_square_cm
+            siemens_per_square_cm: self.siemens_per_square_cm,
+            density: None,
         }
     }
 
     pub fn deserialize(c: &serialize::MembraneChannel) -> Self {
-        let serialize::MembraneChannel { channel, siemens_per_square_cm } = c;
+        let serialize::MembraneChannel { channel, siemens_per_square_cm, .. } = c;
         MembraneChannel {
             siemens_per_square_cm: siemens_per_square_cm.clone(),
             channel: Channel::deserialize(channel),
@@ -157,46 +193,6 @@ impl MembraneChannel {
     }
 }
 
-/// A collection of segment PBR materials for Bevy rendering.
-#[derive(Resource)]
-pub struct MembraneMaterials {
-    pub handles: Vec<Handle<StandardMaterial>>,
-    pub voltage_range: (MilliVolts,MilliVolts),
-    pub len: usize,
-}
-
-impl FromWorld for MembraneMaterials {
-  fn from_world(world: &mut World) -> Self {
-      let mut material_assets = world.get_resource_mut::<Assets<StandardMaterial>>().expect("Can get Assets");
-      let len = 500;
-      let voltage_range = (MilliVolts(-80.0), MilliVolts(50.0));
-      let handles = (0..len).map(|i| {
-          let intensity_range = 1.0;
-          let intensity = i as f32 / len as f32 * intensity_range;
-          let color = Color::rgb(intensity * 0.0, intensity * 0.83, intensity * 0.48);
-          let mut material : StandardMaterial = color.clone().into();
-          material.emissive = Color::rgb_linear(
-              intensity * 100000.0 * 0.0,
-              intensity * 100000.0 * 0.83,
-              intensity * 100000.0 * 0.48
-          );
-          // material.metallic = intensity;
-          let handle = material_assets.add(material);
-          handle
-      }).collect();
-      MembraneMaterials { handles, voltage_range, len}
-  }
-}
-
-impl MembraneMaterials {
-
-    pub fn from_voltage(&self, v: &MilliVolts) -> Handle<StandardMaterial> {
-        let v_min = self.voltage_range.0.0;
-        let v_max = self.voltage_range.1.0;
-        let index = (((v.0 - v_min) / (v_max - v_min)) * self.len as f32) as usize;
-        self.handles[index.min(self.len - 1)].clone()
-    }
-}
 #[cfg(test)]
 mod tests {
     use super::*;
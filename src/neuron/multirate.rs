@@ -0,0 +1,129 @@
+//! Multi-rate integration: segments in slow regions, such as a large
+//! passive arbor, take one long step every few simulation steps while
+//! small fast compartments step every time.
+//!
+//! A slow segment jumps to the end of its long step at the start of it, and
+//! its neighbours see a voltage interpolated between the two ends while the
+//! step is under way. Current flowing into a slow segment through junctions
+//! and synapses is deferred to the start of its next long step, so no
+//! charge is lost across a rate boundary.
+
+use bevy::prelude::Component;
+
+pub const MAX_STEP_MULTIPLE: u32 = 1000;
+
+#[derive(Clone, Component, Debug, PartialEq)]
+pub struct IntegrationRate {
+    every: u32,
+    /// Voltage at the start of the current long step (mV), once one has
+    /// started.
+    previous: Option<f32>,
+    /// Voltage change from coupling since the start of the current long
+    /// step (mV).
+    pending: f32,
+}
+
+impl IntegrationRate {
+    /// Step every `every` simulation steps.
+    pub fn new(every: u32) -> Result<Self, String> {
+        if every == 0 || every > MAX_STEP_MULTIPLE {
+            return Err(format!("Step multiple must be between 1 and {}, got {}", MAX_STEP_MULTIPLE, every));
+        }
+        Ok(IntegrationRate { every, previous: None, pending: 0.0 })
+    }
+
+    pub fn every(&self) -> u32 {
+        self.every
+    }
+
+    /// Whether a long step starts at simulation step `step`.
+    pub fn is_due(&self, step: u64) -> bool {
+        step.is_multiple_of(self.every as u64)
+    }
+
+    /// The length of a long step, given the simulation step.
+    pub fn interval(&self, simulation_step: f32) -> f32 {
+        simulation_step * self.every as f32
+    }
+
+    /// Start a long step from voltage `v` (mV): apply the deferred coupling
+    /// and remember where the step started.
+    pub fn begin_step(&mut self, v: &mut f32) {
+        *v += self.pending;
+        self.pending = 0.0;
+        self.previous = Some(*v);
+    }
+
+    /// The voltage neighbours see at `step`, given the voltage `v` at the
+    /// end of the current long step.
+    pub fn seen(&self, v: f32, step: u64) -> f32 {
+        let Some(previous) = self.previous else {
+            return v;
+        };
+        let phase = (step % self.every as u64) as f32 / self.every as f32;
+        previous + (v - previous) * phase
+    }
+
+    /// Defer a voltage change from coupling to the next long step.
+    pub fn defer(&mut self, dv: f32) {
+        self.pending += dv;
+    }
+
+    /// The voltage the current long step started at, if one has, and the
+    /// coupling deferred since (mV).
+    pub fn long_step(&self) -> (Option<f32>, f32) {
+        (self.previous, self.pending)
+    }
+
+    /// Resume a long step saved with `long_step`.
+    pub fn resume(&mut self, previous: Option<f32>, pending: f32) {
+        self.previous = previous;
+        self.pending = pending;
+    }
+}
+
+/// The voltage a segment's neighbours see at `step`.
+pub fn seen_voltage(v: f32, rate: Option<&IntegrationRate>, step: u64) -> f32 {
+    rate.map_or(v, |r| r.seen(v, step))
+}
+
+/// Apply a voltage change from coupling, now or at the segment's next
+/// long step.
+pub fn couple(v: &mut f32, rate: Option<&mut IntegrationRate>, dv: f32) {
+    match rate {
+        Some(r) if r.every > 1 => r.defer(dv),
+        _ => *v += dv,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_across_a_long_step() {
+        let mut rate = IntegrationRate::new(4).unwrap();
+        assert!(IntegrationRate::new(0).is_err());
+        assert!(rate.is_due(8) && !rate.is_due(9));
+        let mut v = -70.0;
+        assert_eq!(rate.seen(v, 9), -70.0);
+        rate.begin_step(&mut v);
+        v = -62.0;
+        assert_eq!(rate.seen(v, 8), -70.0);
+        assert_eq!(rate.seen(v, 10), -66.0);
+        assert_eq!(seen_voltage(v, None, 10), -62.0);
+    }
+
+    #[test]
+    fn defers_coupling_to_the_next_long_step() {
+        let mut rate = IntegrationRate::new(2).unwrap();
+        let mut v = -70.0;
+        couple(&mut v, Some(&mut rate), 1.5);
+        couple(&mut v, Some(&mut rate), 0.5);
+        assert_eq!(v, -70.0);
+        rate.begin_step(&mut v);
+        assert_eq!(v, -68.0);
+        couple(&mut v, None, 1.0);
+        assert_eq!(v, -67.0);
+    }
+}
@@ -0,0 +1,721 @@
+//! Import of Hodgkin-Huxley style channels from NEURON's NMODL (`.mod`)
+//! files.
+//!
+//! Only the common density-mechanism subset is understood: `PARAMETER`,
+//! `STATE`, `BREAKPOINT`, a `DERIVATIVE` block, and the `PROCEDURE`s and
+//! `FUNCTION`s it calls. Rates may be written as alpha/beta expressions or
+//! as inf/tau pairs; `TABLE` statements are only a caching hint to NEURON,
+//! so they are skipped and the underlying expressions evaluated directly.
+//!
+//! Rather than translating the expressions, the mechanism is interpreted
+//! across a range of membrane potentials and each gate's steady state and
+//! time constant are fitted to the forms in [`crate::neuron::channel`].
+
+use std::collections::HashMap;
+
+use crate::dimension::Kelvin;
+use crate::expr::{self, Context, Expr, Parser, Token};
+use crate::neuron::channel::{
+    ChannelBuilder, Gating, IonSelectivity, Magnitude, TimeConstant, CA, CL, K, NA,
+};
+
+/// Membrane potentials (mV) at which gates are sampled for fitting.
+const SAMPLE_VOLTAGES: std::ops::RangeInclusive<i32> = -100..=60;
+
+/// One channel recovered from a `.mod` file. A file with several
+/// currents (such as `hh.mod`) produces one channel per current.
+#[derive(Clone, Debug)]
+pub struct ImportedChannel {
+    pub name: String,
+    pub builder: ChannelBuilder,
+    pub siemens_per_square_cm: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Current {
+    pub variable: String,
+    /// The ion carrying the current, or `None` for a
+    /// `NONSPECIFIC_CURRENT`.
+    pub ion: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Stmt {
+    Assign(String, Expr),
+    Derivative(String, Expr),
+    Call(String, Vec<Expr>),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    Local(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+struct Routine {
+    args: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+/// A parsed NMODL density mechanism.
+#[derive(Clone, Debug, Default)]
+pub struct Mechanism {
+    pub suffix: String,
+    pub currents: Vec<Current>,
+    pub parameters: Vec<(String, f32)>,
+    pub states: Vec<String>,
+    breakpoint: Vec<Stmt>,
+    derivative: Vec<Stmt>,
+    functions: HashMap<String, Routine>,
+    procedures: HashMap<String, Routine>,
+}
+
+/// Parse a `.mod` file and fit its currents at the given temperature.
+pub fn import(source: &str, temperature: &Kelvin) -> Result<Vec<ImportedChannel>, String> {
+    Mechanism::parse(source)?.channels(temperature.0 - 273.15)
+}
+
+/// Remove `:` and `?` line comments, `COMMENT`/`VERBATIM` blocks and the
+/// free text of `TITLE` lines.
+fn strip_comments(source: &str) -> String {
+    let mut out = String::new();
+    let mut skipping_until: Option<&str> = None;
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if let Some(end) = skipping_until {
+            if trimmed.starts_with(end) {
+                skipping_until = None;
+            }
+            out.push('\n');
+            continue;
+        }
+        if trimmed.starts_with("COMMENT") {
+            skipping_until = Some("ENDCOMMENT");
+        } else if trimmed.starts_with("VERBATIM") {
+            skipping_until = Some("ENDVERBATIM");
+        } else if !trimmed.starts_with("TITLE") {
+            let code = line.split([':', '?']).next().unwrap_or("");
+            out.push_str(code);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn skip_line(p: &mut Parser) {
+    while let Some(t) = p.peek() {
+        if *t == Token::Newline {
+            break;
+        }
+        p.advance();
+    }
+}
+
+/// Skip a `{ ... }` block, including nested blocks.
+fn skip_block(p: &mut Parser) -> Result<(), String> {
+    p.skip_newlines();
+    p.expect_symbol("{")?;
+    let mut depth = 1;
+    while depth > 0 {
+        match p.advance() {
+            Some(Token::Symbol("{")) => depth += 1,
+            Some(Token::Symbol("}")) => depth -= 1,
+            Some(_) => {}
+            None => return Err("Unclosed block".to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn skip_units(p: &mut Parser) -> Result<(), String> {
+    if p.at_symbol("(") {
+        p.skip_parens()?;
+    }
+    Ok(())
+}
+
+/// Parse a signed numeric literal.
+fn number(p: &mut Parser) -> Result<f32, String> {
+    let sign = if p.eat_symbol("-") { -1.0 } else { 1.0 };
+    match p.advance() {
+        Some(Token::Number(n)) => Ok(sign * n),
+        t => Err(format!("Expected number, found {:?}", t)),
+    }
+}
+
+/// Parse `(a (units), b)` argument lists of procedures and functions.
+fn arguments(p: &mut Parser) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    if !p.eat_symbol("(") {
+        return Ok(args);
+    }
+    if p.eat_symbol(")") {
+        return Ok(args);
+    }
+    loop {
+        args.push(p.expect_ident()?);
+        skip_units(p)?;
+        if p.eat_symbol(")") {
+            return Ok(args);
+        }
+        p.expect_symbol(",")?;
+    }
+}
+
+fn statements(p: &mut Parser) -> Result<Vec<Stmt>, String> {
+    p.skip_newlines();
+    p.expect_symbol("{")?;
+    let mut body = vec![];
+    loop {
+        p.skip_newlines();
+        if p.eat_symbol("}") {
+            return Ok(body);
+        }
+        let name = match p.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            Some(Token::Symbol("~")) => return Err("Kinetic schemes are not supported".to_string()),
+            t => return Err(format!("Unexpected {:?} in statement", t)),
+        };
+        match name.as_str() {
+            "LOCAL" => {
+                p.advance();
+                let mut names = vec![p.expect_ident()?];
+                while p.eat_symbol(",") {
+                    names.push(p.expect_ident()?);
+                }
+                body.push(Stmt::Local(names));
+            }
+            "TABLE" | "UNITSOFF" | "UNITSON" | "SOLVE" | "DEPEND" | "CONSERVE" => skip_line(p),
+            "if" => body.push(if_statement(p)?),
+            _ => {
+                p.advance();
+                if p.eat_symbol("'") {
+                    p.expect_symbol("=")?;
+                    body.push(Stmt::Derivative(name, p.expr()?));
+                } else if p.eat_symbol("=") {
+                    body.push(Stmt::Assign(name, p.expr()?));
+                } else if p.eat_symbol("(") {
+                    let mut args = vec![];
+                    if !p.eat_symbol(")") {
+                        loop {
+                            args.push(p.expr()?);
+                            if p.eat_symbol(")") {
+                                break;
+                            }
+                            p.expect_symbol(",")?;
+                        }
+                    }
+                    body.push(Stmt::Call(name, args));
+                } else {
+                    return Err(format!("Unexpected statement starting with {}", name));
+                }
+            }
+        }
+    }
+}
+
+fn if_statement(p: &mut Parser) -> Result<Stmt, String> {
+    p.advance();
+    p.expect_symbol("(")?;
+    let condition = p.expr()?;
+    p.expect_symbol(")")?;
+    let then = statements(p)?;
+    p.skip_newlines();
+    let otherwise = if p.at_ident("else") {
+        p.advance();
+        if p.at_ident("if") {
+            vec![if_statement(p)?]
+        } else {
+            statements(p)?
+        }
+    } else {
+        vec![]
+    };
+    Ok(Stmt::If(condition, then, otherwise))
+}
+
+/// Variables visible while interpreting an expression.
+struct Scope<'a> {
+    mechanism: &'a Mechanism,
+    globals: &'a HashMap<String, f32>,
+    locals: &'a HashMap<String, f32>,
+}
+
+impl<'a> Context for Scope<'a> {
+    fn var(&self, name: &str) -> Option<f32> {
+        self.locals.get(name).or(self.globals.get(name)).cloned()
+    }
+
+    fn call(&self, name: &str, args: &[f32]) -> Result<f32, String> {
+        match self.mechanism.functions.get(name) {
+            Some(_) => self.mechanism.call_function(name, args, self.globals),
+            None => expr::builtin(name, args),
+        }
+    }
+}
+
+impl Mechanism {
+    pub fn parse(source: &str) -> Result<Mechanism, String> {
+        let mut p = Parser::new(expr::tokenize(&strip_comments(source))?);
+        let mut mechanism = Mechanism::default();
+        loop {
+            p.skip_newlines();
+            let keyword = match p.advance() {
+                None => break,
+                Some(Token::Ident(k)) => k,
+                Some(t) => return Err(format!("Unexpected {:?} at top level", t)),
+            };
+            match keyword.as_str() {
+                "UNITSOFF" | "UNITSON" => skip_line(&mut p),
+                "NEURON" => mechanism.parse_neuron_block(&mut p)?,
+                "PARAMETER" | "CONSTANT" => mechanism.parse_parameters(&mut p)?,
+                "STATE" => mechanism.parse_states(&mut p)?,
+                "BREAKPOINT" => mechanism.breakpoint = statements(&mut p)?,
+                "DERIVATIVE" => {
+                    p.expect_ident()?;
+                    mechanism.derivative = statements(&mut p)?;
+                }
+                "PROCEDURE" | "FUNCTION" => {
+                    let name = p.expect_ident()?;
+                    let args = arguments(&mut p)?;
+                    skip_units(&mut p)?;
+                    let routine = Routine { args, body: statements(&mut p)? };
+                    if keyword == "PROCEDURE" {
+                        mechanism.procedures.insert(name, routine);
+                    } else {
+                        mechanism.functions.insert(name, routine);
+                    }
+                }
+                "KINETIC" => return Err("Kinetic schemes are not supported".to_string()),
+                _ => {
+                    // UNITS, ASSIGNED, INITIAL, INDEPENDENT and so on carry
+                    // nothing the fit needs.
+                    if let Some(Token::Ident(_)) = p.peek() {
+                        p.advance();
+                    }
+                    skip_block(&mut p)?;
+                }
+            }
+        }
+        if mechanism.currents.is_empty() {
+            return Err("Mechanism writes no currents".to_string());
+        }
+        Ok(mechanism)
+    }
+
+    fn parse_neuron_block(&mut self, p: &mut Parser) -> Result<(), String> {
+        p.skip_newlines();
+        p.expect_symbol("{")?;
+        loop {
+            p.skip_newlines();
+            if p.eat_symbol("}") {
+                return Ok(());
+            }
+            match p.expect_ident()?.as_str() {
+                "SUFFIX" => self.suffix = p.expect_ident()?,
+                "POINT_PROCESS" | "ARTIFICIAL_CELL" => {
+                    return Err("Only density mechanisms are supported".to_string())
+                }
+                "USEION" => {
+                    let ion = p.expect_ident()?;
+                    let mut writing = false;
+                    while let Some(Token::Ident(word)) = p.peek().cloned() {
+                        p.advance();
+                        match word.as_str() {
+                            "WRITE" => writing = true,
+                            "READ" | "VALENCE" => writing = false,
+                            w if writing && w == format!("i{}", ion) => self.currents.push(Current {
+                                variable: w.to_string(),
+                                ion: Some(ion.clone()),
+                            }),
+                            _ => {}
+                        }
+                        p.eat_symbol(",");
+                        if p.at_symbol("-") || matches!(p.peek(), Some(Token::Number(_))) {
+                            number(p)?;
+                        }
+                    }
+                }
+                "NONSPECIFIC_CURRENT" => {
+                    self.currents.push(Current { variable: p.expect_ident()?, ion: None });
+                    while p.eat_symbol(",") {
+                        self.currents.push(Current { variable: p.expect_ident()?, ion: None });
+                    }
+                }
+                _ => skip_line(p),
+            }
+        }
+    }
+
+    fn parse_parameters(&mut self, p: &mut Parser) -> Result<(), String> {
+        p.skip_newlines();
+        p.expect_symbol("{")?;
+        loop {
+            p.skip_newlines();
+            if p.eat_symbol("}") {
+                return Ok(());
+            }
+            let name = p.expect_ident()?;
+            if p.eat_symbol("=") {
+                self.parameters.push((name, number(p)?));
+            }
+            // Units and `<low, high>` ranges.
+            skip_line(p);
+        }
+    }
+
+    fn parse_states(&mut self, p: &mut Parser) -> Result<(), String> {
+        p.skip_newlines();
+        p.expect_symbol("{")?;
+        loop {
+            p.skip_newlines();
+            match p.advance() {
+                Some(Token::Symbol("}")) => return Ok(()),
+                Some(Token::Ident(name)) if name != "FROM" && name != "TO" => {
+                    self.states.push(name);
+                    skip_units(p)?;
+                }
+                Some(_) => {}
+                None => return Err("Unclosed STATE block".to_string()),
+            }
+        }
+    }
+
+    fn call_function(&self, name: &str, args: &[f32], globals: &HashMap<String, f32>) -> Result<f32, String> {
+        let routine = self.functions.get(name).ok_or(format!("Unknown function {}", name))?;
+        if routine.args.len() != args.len() {
+            return Err(format!("{} takes {} arguments", name, routine.args.len()));
+        }
+        let mut globals = globals.clone();
+        let mut locals: HashMap<String, f32> =
+            routine.args.iter().cloned().zip(args.iter().cloned()).collect();
+        locals.insert(name.to_string(), 0.0);
+        self.exec(&routine.body, &mut globals, &mut locals)?;
+        Ok(locals[name])
+    }
+
+    fn exec(
+        &self,
+        body: &[Stmt],
+        globals: &mut HashMap<String, f32>,
+        locals: &mut HashMap<String, f32>,
+    ) -> Result<(), String> {
+        for stmt in body {
+            let eval = |e: &Expr, globals: &HashMap<String, f32>, locals: &HashMap<String, f32>| {
+                e.eval(&Scope { mechanism: self, globals, locals })
+            };
+            match stmt {
+                Stmt::Local(names) => names.iter().for_each(|n| {
+                    locals.insert(n.clone(), 0.0);
+                }),
+                Stmt::Assign(name, e) => {
+                    let value = eval(e, globals, locals)?;
+                    if locals.contains_key(name) {
+                        locals.insert(name.clone(), value);
+                    } else {
+                        globals.insert(name.clone(), value);
+                    }
+                }
+                Stmt::Derivative(name, e) => {
+                    let value = eval(e, globals, locals)?;
+                    globals.insert(format!("{}'", name), value);
+                }
+                Stmt::If(condition, then, otherwise) => {
+                    if eval(condition, globals, locals)? != 0.0 {
+                        self.exec(then, globals, locals)?;
+                    } else {
+                        self.exec(otherwise, globals, locals)?;
+                    }
+                }
+                Stmt::Call(name, args) => {
+                    let args = args
+                        .iter()
+                        .map(|a| eval(a, globals, locals))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    match self.procedures.get(name) {
+                        Some(routine) => {
+                            let mut procedure_locals: HashMap<String, f32> =
+                                routine.args.iter().cloned().zip(args).collect();
+                            self.exec(&routine.body, globals, &mut procedure_locals)?;
+                        }
+                        None => {
+                            self.call_function(name, &args, globals)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Global variables for one evaluation: parameters, temperature,
+    /// membrane potential, reversal potentials and the given states.
+    fn globals(&self, celsius: f32, v: f32, reversal: f32, states: &[(String, f32)]) -> HashMap<String, f32> {
+        let mut globals: HashMap<String, f32> = self.parameters.iter().cloned().collect();
+        globals.insert("celsius".to_string(), celsius);
+        globals.insert("v".to_string(), v);
+        for current in self.currents.iter() {
+            if let Some(ion) = &current.ion {
+                globals.insert(format!("e{}", ion), reversal);
+            }
+        }
+        globals.extend(states.iter().cloned());
+        globals
+    }
+
+    /// The steady state and time constant (ms) of one state variable at
+    /// membrane potential `v`. The derivative is assumed to be linear in the
+    /// state, as it is for both the alpha/beta and inf/tau forms, so two
+    /// evaluations recover both quantities.
+    pub fn steady_state_and_tau(&self, state: &str, v: f32, celsius: f32) -> Result<(f32, f32), String> {
+        let derivative_at = |x: f32| -> Result<f32, String> {
+            let states: Vec<(String, f32)> =
+                self.states.iter().map(|s| (s.clone(), if s == state { x } else { 0.5 })).collect();
+            let mut globals = self.globals(celsius, v, 0.0, &states);
+            self.exec(&self.derivative, &mut globals, &mut HashMap::new())?;
+            globals
+                .get(&format!("{}'", state))
+                .cloned()
+                .ok_or(format!("No derivative for state {}", state))
+        };
+        let at_zero = derivative_at(0.0)?;
+        let at_one = derivative_at(1.0)?;
+        let rate = at_zero - at_one;
+        Ok((at_zero / rate, 1.0 / rate))
+    }
+
+    /// The conductance (S/cm^2) behind `current` with the given states.
+    /// Currents are linear in the membrane potential, so the conductance is
+    /// the change in current (mA/cm^2) for a 1 mV step.
+    fn conductance(&self, current: &Current, states: &[(String, f32)], celsius: f32) -> Result<f32, String> {
+        let current_at = |v: f32| -> Result<f32, String> {
+            let mut globals = self.globals(celsius, v, 0.0, states);
+            self.exec(&self.breakpoint, &mut globals, &mut HashMap::new())?;
+            globals
+                .get(&current.variable)
+                .cloned()
+                .ok_or(format!("BREAKPOINT does not assign {}", current.variable))
+        };
+        Ok(current_at(1.0)? - current_at(0.0)?)
+    }
+
+    fn gating(&self, state: &str, celsius: f32) -> Result<(Magnitude, TimeConstant), String> {
+        let mut steady_states = vec![];
+        let mut taus = vec![];
+        for v in SAMPLE_VOLTAGES {
+            let (inf, tau) = self.steady_state_and_tau(state, v as f32, celsius)?;
+            steady_states.push((v as f32, inf));
+            taus.push((v as f32, tau * 0.001));
+        }
+        let magnitude = Magnitude::fit(&steady_states)
+            .ok_or(format!("Steady state of {} is not voltage dependent", state))?;
+        Ok((magnitude, TimeConstant::fit_gaussian(&taus)))
+    }
+
+    /// Fit every current of the mechanism as a [`ChannelBuilder`].
+    /// Nonspecific currents are imported as chloride currents, like the
+    /// built-in leak channel.
+    pub fn channels(&self, celsius: f32) -> Result<Vec<ImportedChannel>, String> {
+        let all_open: Vec<(String, f32)> = self.states.iter().map(|s| (s.clone(), 1.0)).collect();
+        self.currents
+            .iter()
+            .map(|current| {
+                let siemens_per_square_cm = self.conductance(current, &all_open, celsius)?;
+                if siemens_per_square_cm == 0.0 {
+                    return Err(format!("{} has zero conductance", current.variable));
+                }
+
+                let mut activation_parameters = None;
+                let mut inactivation_parameters = None;
+                for state in self.states.iter() {
+                    let half_open: Vec<(String, f32)> = all_open
+                        .iter()
+                        .map(|(s, x)| (s.clone(), if s == state { 0.5 } else { *x }))
+                        .collect();
+                    let ratio = self.conductance(current, &half_open, celsius)? / siemens_per_square_cm;
+                    let gates = (ratio.ln() / 0.5f32.ln()).round();
+                    if gates < 1.0 {
+                        continue;
+                    }
+                    let (steady_state_magnitude, time_constant) = self.gating(state, celsius)?;
+                    let gating = Gating { gates: gates as u8, steady_state_magnitude, time_constant };
+                    let slot = if gating.steady_state_magnitude.slope > 0.0 {
+                        &mut activation_parameters
+                    } else {
+                        &mut inactivation_parameters
+                    };
+                    if slot.is_some() {
+                        return Err(format!(
+                            "{} has more than one {} gate",
+                            current.variable,
+                            if gating.steady_state_magnitude.slope > 0.0 { "activation" } else { "inactivation" }
+                        ));
+                    }
+                    *slot = Some(gating);
+                }
+
+                let ion_selectivity: IonSelectivity = match current.ion.as_deref() {
+                    Some("na") => NA,
+                    Some("k") => K,
+                    Some("ca") => CA,
+                    Some("cl") => CL,
+                    None => CL,
+                    Some(ion) => return Err(format!("Unsupported ion {}", ion)),
+                };
+                Ok(ImportedChannel {
+                    name: format!("{} {}", self.suffix, current.variable),
+                    builder: ChannelBuilder {
+                        activation_parameters,
+                        inactivation_parameters,
+                        ion_selectivity,
+                    },
+                    siemens_per_square_cm,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimension::MilliVolts;
+
+    const HH_MOD: &str = r#"
+TITLE hh.mod   squid sodium, potassium, and leak channels
+
+COMMENT
+ This is the original Hodgkin-Huxley treatment for the set of sodium,
+ potassium, and leakage channels found in the squid giant axon membrane.
+ENDCOMMENT
+
+UNITS {
+        (mA) = (milliamp)
+        (mV) = (millivolt)
+        (S) = (siemens)
+}
+
+NEURON {
+        SUFFIX hh
+        USEION na READ ena WRITE ina
+        USEION k READ ek WRITE ik
+        NONSPECIFIC_CURRENT il
+        RANGE gnabar, gkbar, gl, el, gna, gk
+        THREADSAFE : assigned GLOBALs will be per thread
+}
+
+PARAMETER {
+        gnabar = .12 (S/cm2)    <0,1e9>
+        gkbar = .036 (S/cm2)    <0,1e9>
+        gl = .0003 (S/cm2)      <0,1e9>
+        el = -54.3 (mV)
+}
+
+STATE {
+        m h n
+}
+
+ASSIGNED {
+        v (mV)
+        celsius (degC)
+        ena (mV)
+        ek (mV)
+        gna (S/cm2)
+        gk (S/cm2)
+        ina (mA/cm2)
+        ik (mA/cm2)
+        il (mA/cm2)
+        minf hinf ninf
+        mtau (ms) htau (ms) ntau (ms)
+}
+
+BREAKPOINT {
+        SOLVE states METHOD cnexp
+        gna = gnabar*m*m*m*h
+        ina = gna*(v - ena)
+        gk = gkbar*n*n*n*n
+        ik = gk*(v - ek)
+        il = gl*(v - el)
+}
+
+INITIAL {
+        rates(v)
+        m = minf
+        h = hinf
+        n = ninf
+}
+
+DERIVATIVE states {
+        rates(v)
+        m' =  (minf-m)/mtau
+        h' = (hinf-h)/htau
+        n' = (ninf-n)/ntau
+}
+
+PROCEDURE rates(v(mV)) {
+        LOCAL  alpha, beta, sum, q10
+        TABLE minf, mtau, hinf, htau, ninf, ntau DEPEND celsius FROM -100 TO 100 WITH 200
+
+UNITSOFF
+        q10 = 3^((celsius - 6.3)/10)
+                :"m" sodium activation system
+        alpha = .1 * vtrap(-(v+40),10)
+        beta =  4 * exp(-(v+65)/18)
+        sum = alpha + beta
+        mtau = 1/(q10*sum)
+        minf = alpha/sum
+                :"h" sodium inactivation system
+        alpha = .07 * exp(-(v+65)/20)
+        beta = 1 / (exp(-(v+35)/10) + 1)
+        sum = alpha + beta
+        htau = 1/(q10*sum)
+        hinf = alpha/sum
+                :"n" potassium activation system
+        alpha = .01*vtrap(-(v+55),10)
+        beta = .125*exp(-(v+65)/80)
+        sum = alpha + beta
+        ntau = 1/(q10*sum)
+        ninf = alpha/sum
+}
+
+FUNCTION vtrap(x,y) {  :Traps for 0 in denominator of rate eqns.
+        if (fabs(x/y) < 1e-6) {
+                vtrap = y*(1 - x/y/2)
+        }else{
+                vtrap = x/(exp(x/y) - 1)
+        }
+}
+
+UNITSON
+"#;
+
+    #[test]
+    fn hh_mod_produces_three_channels() {
+        let channels = Mechanism::parse(HH_MOD).unwrap().channels(6.3).unwrap();
+        assert_eq!(channels.len(), 3);
+
+        let na = &channels[0];
+        assert!((na.siemens_per_square_cm - 0.12).abs() < 1e-4);
+        let m = na.builder.activation_parameters.as_ref().unwrap();
+        let h = na.builder.inactivation_parameters.as_ref().unwrap();
+        assert_eq!((m.gates, h.gates), (3, 1));
+        assert!(na.builder.ion_selectivity.na == 1.0);
+
+        let k = &channels[1];
+        assert_eq!(k.builder.activation_parameters.as_ref().unwrap().gates, 4);
+        assert!(k.builder.inactivation_parameters.is_none());
+
+        let leak = &channels[2];
+        assert!((leak.siemens_per_square_cm - 0.0003).abs() < 1e-6);
+        assert!(leak.builder.activation_parameters.is_none());
+    }
+
+    #[test]
+    fn fitted_steady_state_tracks_mod_file() {
+        let mechanism = Mechanism::parse(HH_MOD).unwrap();
+        let channels = mechanism.channels(6.3).unwrap();
+        let h = channels[0].builder.inactivation_parameters.as_ref().unwrap();
+        for v in [-80.0, -65.0, -50.0] {
+            let (expected, _) = mechanism.steady_state_and_tau("h", v, 6.3).unwrap();
+            let fitted = h.steady_state_magnitude.steady_state(&MilliVolts(v));
+            assert!((expected - fitted).abs() < 0.05);
+        }
+    }
+}
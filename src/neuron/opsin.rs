@@ -0,0 +1,323 @@
+//! Light-gated channels, for virtual optogenetics.
+//!
+//! An opsin gates its channel by the light reaching the segment rather
+//! than by voltage. Its state is the occupancy of a few kinetic states,
+//! with light-driven transitions scaled by a saturating function of the
+//! effective irradiance: the irradiance of each light weighted by the
+//! opsin's sensitivity at that light's wavelength.
+//!
+//! - ChR2 follows the four-state model of Nikolic et al. (2009): two
+//!   closed and two open states, the second open state conducting a tenth
+//!   as much. Under steady light the current peaks and then sags to a
+//!   plateau as the channels move into the second cycle, and the peak
+//!   recovers over seconds in the dark. Its channel passes Na⁺ and K⁺,
+//!   reversing near 0 mV.
+//! - NpHR has a closed, an open and a desensitized state. Halorhodopsin is
+//!   a chloride pump; here it is a light-gated Cl⁻ conductance, which
+//!   silences a neuron the same way as long as E_Cl is below threshold.
+
+use crate::dimension::Interval;
+use crate::neuron::channel::{Channel, IonSelectivity, CL};
+use crate::serialize;
+
+/// The largest change in any occupancy allowed in one integration step;
+/// longer steps are split.
+const MAX_STEP_FRACTION: f32 = 0.2;
+
+/// Relative conductance of ChR2's second open state.
+const CHR2_O2_CONDUCTANCE: f32 = 0.1;
+
+/// Width (standard deviation, nm) of the opsins' action spectra.
+const SPECTRUM_WIDTH_NM: f32 = 40.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opsin {
+    /// Channelrhodopsin-2, a blue-light-gated cation channel.
+    ChR2,
+    /// Halorhodopsin from Natronomonas, driven by yellow light.
+    NpHR,
+}
+
+impl Opsin {
+    pub const ALL: [Opsin; 2] = [Opsin::ChR2, Opsin::NpHR];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opsin::ChR2 => "ChR2",
+            Opsin::NpHR => "NpHR",
+        }
+    }
+
+    /// The wavelength (nm) the opsin responds to best.
+    pub fn peak_wavelength_nm(&self) -> f32 {
+        match self {
+            Opsin::ChR2 => 470.0,
+            Opsin::NpHR => 590.0,
+        }
+    }
+
+    /// The effective irradiance (mW/mm²) that drives the light-dependent
+    /// transitions at half their maximum rate.
+    pub fn half_saturation_mw_per_square_mm(&self) -> f32 {
+        match self {
+            Opsin::ChR2 => 1.0,
+            Opsin::NpHR => 2.0,
+        }
+    }
+
+    /// Response to light of `wavelength_nm` relative to the peak, from 0
+    /// to 1.
+    pub fn spectral_sensitivity(&self, wavelength_nm: f32) -> f32 {
+        let x = (wavelength_nm - self.peak_wavelength_nm()) / SPECTRUM_WIDTH_NM;
+        (-0.5 * x * x).exp()
+    }
+
+    pub fn ion_selectivity(&self) -> IonSelectivity {
+        match self {
+            // Weighted so that the reversal potential is near 0 mV.
+            Opsin::ChR2 => IonSelectivity { na: 0.6, k: 0.4, ca: 0.0, cl: 0.0 },
+            Opsin::NpHR => CL,
+        }
+    }
+
+    /// A channel gated by this opsin alone, in the dark.
+    pub fn channel(&self) -> Channel {
+        Channel {
+            activation: None,
+            inactivation: None,
+            ion_selectivity: self.ion_selectivity(),
+            custom: None,
+            opsin: Some(OpsinState::dark(*self)),
+            calcium: None,
+        }
+    }
+
+    pub fn serialize(&self) -> serialize::Opsin {
+        match self {
+            Opsin::ChR2 => serialize::Opsin::ChR2,
+            Opsin::NpHR => serialize::Opsin::NpHR,
+        }
+    }
+
+    pub fn deserialize(opsin: &serialize::Opsin) -> Self {
+        match opsin {
+            serialize::Opsin::ChR2 => Opsin::ChR2,
+            serialize::Opsin::NpHR => Opsin::NpHR,
+        }
+    }
+}
+
+/// Express `opsin` at `siemens_per_square_cm` in the membrane of the
+/// segment at `at`, and so in every segment of its type in that neuron,
+/// replacing its density if the membrane already has it. A density of zero
+/// removes it.
+pub fn express(
+    scene: &mut serialize::Scene,
+    at: serialize::SegmentRef,
+    opsin: Opsin,
+    siemens_per_square_cm: f32,
+) -> Result<(), String> {
+    if !(siemens_per_square_cm.is_finite() && siemens_per_square_cm >= 0.0) {
+        return Err(format!("Invalid density {}", siemens_per_square_cm));
+    }
+    let neuron = &mut scene.neurons.get_mut(at.neuron).ok_or(format!("No neuron {}", at.neuron))?.neuron;
+    let type_ = neuron
+        .segments
+        .iter()
+        .find(|s| s.id == at.segment)
+        .ok_or(format!("No segment {} in neuron {}", at.segment, at.neuron))?
+        .type_;
+    let membrane = type_
+        .checked_sub(1)
+        .and_then(|t| neuron.membranes.get_mut(t))
+        .ok_or(format!("No membrane for segment type {}", type_))?;
+    let serialized = opsin.serialize();
+    membrane.membrane_channels.retain(|c| c.channel.opsin != Some(serialized));
+    if siemens_per_square_cm > 0.0 {
+        membrane
+            .membrane_channels
+            .push(serialize::MembraneChannel { channel: opsin.channel().serialize(), siemens_per_square_cm, density: None });
+    }
+    Ok(())
+}
+
+/// Occupancy of each kinetic state but the first closed one, which holds
+/// the rest.
+#[derive(Clone, Debug, PartialEq)]
+enum Kinetics {
+    ChR2 { o1: f32, o2: f32, c2: f32 },
+    NpHR { open: f32, desensitized: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpsinState {
+    kinetics: Kinetics,
+    /// Effective irradiance (mW/mm²) reaching the channel, set before each
+    /// step; see `light::illuminate`.
+    pub irradiance: f32,
+}
+
+impl OpsinState {
+    /// Fully dark-adapted: every channel in the first closed state.
+    pub fn dark(opsin: Opsin) -> Self {
+        let kinetics = match opsin {
+            Opsin::ChR2 => Kinetics::ChR2 { o1: 0.0, o2: 0.0, c2: 0.0 },
+            Opsin::NpHR => Kinetics::NpHR { open: 0.0, desensitized: 0.0 },
+        };
+        OpsinState { kinetics, irradiance: 0.0 }
+    }
+
+    pub fn opsin(&self) -> Opsin {
+        match self.kinetics {
+            Kinetics::ChR2 { .. } => Opsin::ChR2,
+            Kinetics::NpHR { .. } => Opsin::NpHR,
+        }
+    }
+
+    /// The fraction of the peak conductance that is open.
+    pub fn conductance_coefficient(&self) -> f32 {
+        match self.kinetics {
+            Kinetics::ChR2 { o1, o2, .. } => o1 + CHR2_O2_CONDUCTANCE * o2,
+            Kinetics::NpHR { open, .. } => open,
+        }
+    }
+
+    /// The occupancies that make up the state, for checkpoints.
+    pub fn occupancies(&self) -> Vec<f32> {
+        match self.kinetics {
+            Kinetics::ChR2 { o1, o2, c2 } => vec![o1, o2, c2],
+            Kinetics::NpHR { open, desensitized } => vec![open, desensitized],
+        }
+    }
+
+    pub fn set_occupancies(&mut self, occupancies: &[f32]) -> Result<(), String> {
+        match (&mut self.kinetics, occupancies) {
+            (Kinetics::ChR2 { o1, o2, c2 }, [a, b, c]) => (*o1, *o2, *c2) = (*a, *b, *c),
+            (Kinetics::NpHR { open, desensitized }, [a, b]) => (*open, *desensitized) = (*a, *b),
+            _ => return Err(format!("{} occupancies saved for a {} channel", occupancies.len(), self.opsin().name())),
+        }
+        Ok(())
+    }
+
+    /// Advance the kinetics by `interval` at the current irradiance.
+    pub fn step(&mut self, interval: &Interval) {
+        let opsin = self.opsin();
+        let light = self.irradiance.max(0.0);
+        let s = light / (light + opsin.half_saturation_mw_per_square_mm());
+        // The fastest total rate (1/s) out of any state, in saturating light.
+        let fastest = match self.kinetics {
+            Kinetics::ChR2 { .. } => 650.0,
+            Kinetics::NpHR { .. } => 360.0,
+        };
+        let substeps = (interval.0 * fastest / MAX_STEP_FRACTION).ceil().max(1.0) as usize;
+        let dt = interval.0 / substeps as f32;
+        for _ in 0..substeps {
+            match &mut self.kinetics {
+                Kinetics::ChR2 { o1, o2, c2 } => {
+                    let c1 = 1.0 - *o1 - *o2 - *c2;
+                    let (ka1, ka2) = (500.0 * s, 100.0 * s);
+                    let (kd1, kd2, kr) = (100.0, 25.0, 0.4);
+                    let (e12, e21) = (10.0 + 40.0 * s, 15.0 + 10.0 * s);
+                    let do1 = ka1 * c1 - (kd1 + e12) * *o1 + e21 * *o2;
+                    let do2 = ka2 * *c2 + e12 * *o1 - (kd2 + e21) * *o2;
+                    let dc2 = kd2 * *o2 - (ka2 + kr) * *c2;
+                    *o1 += do1 * dt;
+                    *o2 += do2 * dt;
+                    *c2 += dc2 * dt;
+                },
+                Kinetics::NpHR { open, desensitized } => {
+                    let closed = 1.0 - *open - *desensitized;
+                    let (ka, kd, kdes, kr) = (300.0 * s, 60.0, 0.5 * s, 0.2);
+                    let dopen = ka * closed - (kd + kdes) * *open;
+                    let ddesensitized = kdes * *open - kr * *desensitized;
+                    *open += dopen * dt;
+                    *desensitized += ddesensitized * dt;
+                },
+            }
+        }
+        self.normalize();
+    }
+
+    /// Keep each occupancy in [0, 1] and their sum at most 1.
+    fn normalize(&mut self) {
+        let mut occupancies = self.occupancies();
+        occupancies.iter_mut().for_each(|o| *o = o.clamp(0.0, 1.0));
+        let total = occupancies.iter().sum::<f32>();
+        if total > 1.0 {
+            occupancies.iter_mut().for_each(|o| *o /= total);
+        }
+        self.set_occupancies(&occupancies).expect("occupancies come from this state");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::sample;
+
+    fn run(state: &mut OpsinState, irradiance: f32, seconds: f32) -> Vec<f32> {
+        state.irradiance = irradiance;
+        (0..(seconds / 1e-4) as usize)
+            .map(|_| {
+                state.step(&Interval(1e-4));
+                state.conductance_coefficient()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chr2_peaks_then_desensitizes() {
+        let mut state = OpsinState::dark(Opsin::ChR2);
+        let lit = run(&mut state, 5.0, 0.5);
+        let peak = lit.iter().cloned().fold(0.0, f32::max);
+        let plateau = *lit.last().unwrap();
+        assert!(peak > 0.3, "{}", peak);
+        assert!(plateau < 0.8 * peak && plateau > 0.05, "{} {}", peak, plateau);
+
+        // Off within a hundred milliseconds, with the peak not yet recovered.
+        let dark = run(&mut state, 0.0, 0.1);
+        assert!(*dark.last().unwrap() < 0.01);
+        let second = run(&mut state, 5.0, 0.05);
+        assert!(second.iter().cloned().fold(0.0, f32::max) < peak);
+
+        // Long steps stay bounded.
+        let mut long = OpsinState::dark(Opsin::ChR2);
+        long.irradiance = 100.0;
+        long.step(&Interval(0.5));
+        assert!(long.occupancies().iter().sum::<f32>() <= 1.0 + 1e-6);
+    }
+
+    #[test]
+    fn nphr_follows_yellow_light() {
+        assert!(Opsin::NpHR.spectral_sensitivity(590.0) > 0.99);
+        assert!(Opsin::NpHR.spectral_sensitivity(470.0) < 0.02);
+        let mut state = OpsinState::dark(Opsin::NpHR);
+        assert!(*run(&mut state, 10.0, 0.05).last().unwrap() > 0.5);
+        assert!(*run(&mut state, 0.0, 0.1).last().unwrap() < 0.01);
+        let saved = state.occupancies();
+        let mut restored = OpsinState::dark(Opsin::NpHR);
+        restored.set_occupancies(&saved).unwrap();
+        assert_eq!(restored, state);
+        assert!(restored.set_occupancies(&[0.0; 3]).is_err());
+    }
+
+    #[test]
+    fn expresses_in_a_membrane() {
+        let mut scene = sample::scene2();
+        let segment = &scene.neurons[0].neuron.segments[1];
+        let (at, membrane) = (serialize::SegmentRef { neuron: 0, segment: segment.id }, segment.type_ - 1);
+        let channels = |scene: &serialize::Scene| scene.neurons[0].neuron.membranes[membrane].membrane_channels.clone();
+        let before = channels(&scene).len();
+
+        express(&mut scene, at, Opsin::ChR2, 0.002).unwrap();
+        express(&mut scene, at, Opsin::ChR2, 0.004).unwrap();
+        let expressed = channels(&scene);
+        assert_eq!(expressed.len(), before + 1);
+        assert_eq!(expressed[before].siemens_per_square_cm, 0.004);
+        assert_eq!(Channel::deserialize(&expressed[before].channel).opsin, Some(OpsinState::dark(Opsin::ChR2)));
+
+        express(&mut scene, at, Opsin::ChR2, 0.0).unwrap();
+        assert_eq!(channels(&scene).len(), before);
+        assert!(express(&mut scene, serialize::SegmentRef { neuron: 0, segment: -5 }, Opsin::NpHR, 0.1).is_err());
+    }
+}
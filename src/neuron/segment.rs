@@ -1,19 +1,18 @@
 // use crate::constants::BODY_TEMPERATURE;
-use bevy::prelude::Component;
 use crate::dimension::{
-    Diameter, Farads, Interval, Kelvin, MicroAmps, MicroAmpsPerSquareCm, MilliVolts,
+    Farads, Interval, Kelvin, MicroAmps, MicroAmpsPerSquareCm, MilliVolts,
 };
 use crate::neuron::channel::{ca_reversal, cl_reversal, k_reversal, na_reversal};
 use crate::neuron::membrane::Membrane;
 use crate::neuron::solution::Solution;
 
-use std::f32::consts::PI;
+pub use crate::neuron::geometry::Geometry;
 
 #[derive(Clone, Debug)]
 pub struct Segment {
     /// The ion concentrations inside the segment.
     pub intracellular_solution: Solution,
-    /// The segment's shape (cylindrical radius and position).
+    /// The segment's shape.
     pub geometry: Geometry,
     /// The concentration of various channels.
     pub membrane: Membrane,
@@ -25,35 +24,13 @@ pub struct Segment {
 pub mod ecs {
     use crate::dimension::MicroAmpsPerSquareCm;
 
-    #[derive(bevy::ecs::component::Component)]
+    #[derive(bevy::ecs::component::Component, Clone)]
     pub struct Segment;
 
-    #[derive(bevy::ecs::component::Component)]
+    #[derive(bevy::ecs::component::Component, Clone)]
     pub struct InputCurrent(pub MicroAmpsPerSquareCm);
 }
 
-/// A cylindical neuron segment shape.
-#[derive(Clone, Component, Debug)]
-pub enum Geometry {
-    Cylinder {
-        diameter: Diameter,
-        length: f32,
-    },
-    Sphere {
-        diameter: Diameter,
-    }
-}
-
-impl Geometry {
-    pub fn surface_area(&self) -> f32 {
-        match self {
-            Geometry::Cylinder { diameter, length } => diameter.0 * PI * length,
-            Geometry::Sphere { diameter } => 4.0 * PI * (diameter.0 * 0.5).powi(2)
-        }
-
-    }
-}
-
 impl Segment {
     pub fn surface_area(&self) -> f32 {
         self.geometry.surface_area()
@@ -136,10 +113,7 @@ pub mod examples {
                 cl_concentration: Molar(4e-3),
                 ca_concentration: Molar(0.1e-6),
             },
-            geometry: Geometry::Cylinder {
-                diameter: Diameter(1.0),
-                length: 3.0,
-            },
+            geometry: Geometry::cylinder(Diameter(1.0), 3.0),
             input_current: MicroAmpsPerSquareCm(0.0),
             synaptic_current: MicroAmps(0.0),
             membrane_potential: initial_membrane_potential.clone(),
@@ -171,10 +145,7 @@ pub mod examples {
         Segment {
             intracellular_solution: EXAMPLE_CYTOPLASM,
             synaptic_current: MicroAmps(0.0),
-            geometry: Geometry::Cylinder {
-                diameter: Diameter(0.01),
-                length: 1000.0,
-            },
+            geometry: Geometry::cylinder(Diameter(0.01), 1000.0),
             input_current: MicroAmpsPerSquareCm(0.0),
             membrane_potential: initial_membrane_potential.clone(),
             membrane: Membrane {
@@ -199,10 +170,7 @@ pub mod examples {
                 cl_concentration: Molar(4e-3),
                 ca_concentration: Molar(0.1e-6),
             },
-            geometry: Geometry::Cylinder {
-                diameter: Diameter(1.0),
-                length: 3.0,
-            },
+            geometry: Geometry::cylinder(Diameter(1.0), 3.0),
             membrane_potential: initial_membrane_potential.clone(),
             membrane: Membrane {
                 membrane_channels: vec![MembraneChannel {
@@ -227,10 +195,7 @@ pub mod examples {
             intracellular_solution: EXAMPLE_CYTOPLASM,
             input_current: MicroAmpsPerSquareCm(0.0),
             synaptic_current: MicroAmps(0.0),
-            geometry: Geometry::Cylinder {
-                diameter: Diameter(2.0),
-                length: 2.0,
-            },
+            geometry: Geometry::cylinder(Diameter(2.0), 2.0),
             membrane_potential: initial_membrane_potential.clone(),
             membrane: Membrane {
                 membrane_channels: vec![
@@ -276,6 +241,7 @@ pub mod examples {
         // use crate::neuron::membrane::{Membrane, MembraneChannel};
         use crate::neuron::solution::{EXAMPLE_CYTOPLASM, INTERSTICIAL_FLUID};
         use crate::constants::BODY_TEMPERATURE;
+        use crate::recording::csv_recorder::{segment_columns, CsvRecorder, Quantity};
         // use std::io;
 
         #[test]
@@ -283,43 +249,21 @@ pub mod examples {
         // of -76 mV. (This is a smoke test - I didn't get this number from
         // a book, but should.
         pub fn giant_axon_steady_state() {
-            let mut wtr = csv::Writer::from_path("out.csv").unwrap();
-            wtr.write_record(&[
-                "t", "v_m", "i", "g_k", "g_na", "g_cl", "m_na", "h_na", "m_k",
-            ])
-            .unwrap();
+            let mut recorder = CsvRecorder::create("out.csv", 1).unwrap();
             let mut write_record = |t: f32, s: &Segment| {
-                let (k, na, cl, ca) = s.membrane.conductances();
-                wtr.write_record(&[
-                    format!("{0:.2}", t * 1000.0),
-                    s.membrane_potential.0.to_string(),
-                    s.input_current.0.to_string(),
-                    k.to_string(),
-                    na.to_string(),
-                    cl.to_string(),
-                    s.membrane.membrane_channels[1]
-                        .clone()
-                        .channel
-                        .activation
-                        .unwrap()
-                        .magnitude
-                        .to_string(),
-                    s.membrane.membrane_channels[1]
-                        .clone()
-                        .channel
-                        .inactivation
-                        .unwrap()
-                        .magnitude
-                        .to_string(),
-                    s.membrane.membrane_channels[0]
-                        .clone()
-                        .channel
-                        .activation
-                        .unwrap()
-                        .magnitude
-                        .to_string(),
-                ])
-                .unwrap();
+                let (k, na, cl, _ca) = s.membrane.conductances();
+                let mut columns = vec![
+                    ("i".to_string(), s.input_current.0),
+                    ("g_k".to_string(), k),
+                    ("g_na".to_string(), na),
+                    ("g_cl".to_string(), cl),
+                ];
+                columns.extend(segment_columns(
+                    &[Quantity::Voltage, Quantity::GateMagnitudes],
+                    &s.membrane_potential,
+                    &s.membrane,
+                ));
+                recorder.record(t, &columns).unwrap();
             };
             let mut t = 0.0;
 
@@ -588,10 +532,7 @@ pub mod examples {
         let mut ampa_segment = Segment {
             intracellular_solution: EXAMPLE_CYTOPLASM,
             synaptic_current: MicroAmps(0.0),
-            geometry: Geometry::Cylinder {
-                diameter: Diameter(1e-3),
-                length: 1e-3,
-            },
+            geometry: Geometry::cylinder(Diameter(1e-3), 1e-3),
             input_current: MicroAmpsPerSquareCm(0.0),
             membrane_potential: MilliVolts(-80.0),
             membrane: Membrane {
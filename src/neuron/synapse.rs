@@ -5,6 +5,7 @@ use bevy::prelude::Component;
 use crate::dimension::{
     AreaSquareMillimeters, Interval, Kelvin, MicroAmps, MilliVolts, Molar,
 };
+use crate::mechanism::CustomSynapse;
 use crate::neuron::channel::{ca_reversal, cl_reversal, k_reversal, na_reversal};
 use crate::neuron::membrane::MembraneChannel;
 use crate::neuron::Solution;
@@ -17,6 +18,11 @@ pub struct SynapseMembranes {
     pub presynaptic_pumps: Vec<TransmitterPump>,
     pub postsynaptic_receptors: Vec<Receptor>,
     pub surface_area: AreaSquareMillimeters,
+    /// A mechanism from another crate, alongside the receptors.
+    pub custom: Option<CustomSynapse>,
+    /// Whether, and how, the receptors' strength follows postsynaptic
+    /// calcium.
+    pub plasticity: Option<Plasticity>,
 }
 
 #[derive(Clone, Debug)]
@@ -80,6 +86,9 @@ impl SynapseMembranes {
                 .channel
                 .step(&postsynaptic_potential, interval)
         });
+        if let Some(mechanism) = self.custom.as_mut().and_then(|c| c.mechanism.as_mut()) {
+            mechanism.step(presynaptic_potential, postsynaptic_potential, interval);
+        }
     }
 
     pub fn apply_current(
@@ -103,6 +112,25 @@ impl SynapseMembranes {
             postsynaptic_potential.0 + delta_mv.0;
     }
 
+    /// The strength of the receptors, 1 unless the synapse is plastic.
+    pub fn weight(&self) -> f32 {
+        self.plasticity.as_ref().map_or(1.0, |p| p.weight)
+    }
+
+    /// The fraction of a receptor's conductance opened by the transmitter
+    /// and, for NMDA receptors, by relief of the magnesium block, scaled
+    /// by the synapse's weight.
+    fn receptor_coefficient(&self, receptor: &Receptor, postsynaptic_potential: &MilliVolts) -> f32 {
+        let gating_coefficient = receptor
+            .neurotransmitter_sensitivity
+            .gating_coefficient(&self.transmitter_concentrations);
+        let unblocked = receptor
+            .magnesium_block
+            .as_ref()
+            .map_or(1.0, |block| block.unblocked_fraction(postsynaptic_potential));
+        gating_coefficient * unblocked * self.weight()
+    }
+
     pub fn current(
         &self,
         temperature: &Kelvin,
@@ -136,15 +164,36 @@ impl SynapseMembranes {
                     ),
                     &postsynaptic_potential,
                 );
-                let gating_coefficient = receptor
-                    .neurotransmitter_sensitivity
-                    .gating_coefficient(&self.transmitter_concentrations);
-                // dbg!(channel_current_per_cm);
-                // dbg!(gating_coefficient);
-                channel_current_per_cm * gating_coefficient
+                channel_current_per_cm * self.receptor_coefficient(receptor, postsynaptic_potential)
             })
             .sum::<f32>();
 
+        let custom_current = self.custom.as_ref().map_or(0.0, |c| c.current(postsynaptic_potential).0);
+        MicroAmps(current_per_square_cm * self.surface_area.0 + custom_current)
+    }
+
+    /// The part of `current` carried by Ca2+ (outward positive), which
+    /// enters the postsynaptic segment mostly through NMDA receptors.
+    pub fn calcium_current(
+        &self,
+        temperature: &Kelvin,
+        postsynaptic_potential: &MilliVolts,
+        postsynaptic_solution: &Solution
+    ) -> MicroAmps {
+        let reversal = ca_reversal(postsynaptic_solution, &self.cleft_solution, temperature);
+        let current_per_square_cm = self
+            .postsynaptic_receptors
+            .iter()
+            .map(|receptor| {
+                let membrane_channel = &receptor.membrane_channel;
+                membrane_channel.channel.ion_selectivity.ca
+                    * membrane_channel.channel.conductance_coefficient()
+                    * membrane_channel.siemens_per_square_cm
+                    * (postsynaptic_potential.0 - reversal.0)
+                    * 0.001
+                    * self.receptor_coefficient(receptor, postsynaptic_potential)
+            })
+            .sum::<f32>();
         MicroAmps(current_per_square_cm * self.surface_area.0)
     }
 
@@ -155,6 +204,8 @@ impl SynapseMembranes {
             presynaptic_pumps: self.presynaptic_pumps.iter().map(|p| p.serialize()).collect(),
             postsynaptic_receptors: self.postsynaptic_receptors.iter().map(|r| r.serialize()).collect(),
             surface_area_square_mm: self.surface_area.0,
+            custom: self.custom.as_ref().map(|c| c.spec.clone()),
+            plasticity: self.plasticity.as_ref().map(|p| p.serialize()),
         }
     }
 
@@ -165,40 +216,113 @@ impl SynapseMembranes {
             presynaptic_pumps: s.presynaptic_pumps.iter().map(|p| TransmitterPump::deserialize(p)).collect::<Result<_,_>>()?,
             postsynaptic_receptors: s.postsynaptic_receptors.iter().map(|r| Receptor::deserialize(r)).collect::<Result<_,_>>()?,
             surface_area: AreaSquareMillimeters(s.surface_area_square_mm),
+            custom: s.custom.as_ref().map(CustomSynapse::new),
+            plasticity: s.plasticity.as_ref().map(Plasticity::deserialize),
         })
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Transmitter {
     Glutamate,
     Gaba,
 }
 
 impl Transmitter {
+    /// The name scene files use, which `from_str` reads back.
     pub fn to_string(&self) -> String {
         match self {
-            Transmitter::Glutamate => "glutamate".to_string(),
-            Transmitter::Gaba => "gaba".to_string(),
+            Transmitter::Glutamate => "Glutamate".to_string(),
+            Transmitter::Gaba => "GABA".to_string(),
         }
     }
 }
 
 impl FromStr for Transmitter {
     type Err = String;
+    /// Any case, since scenes were once saved in lower case.
     fn from_str(s: &str) -> Result<Self, String> {
-        match s {
-            "Glutamate" => Ok(Transmitter::Glutamate),
-            "GABA" => Ok(Transmitter::Gaba),
+        match s.to_lowercase().as_str() {
+            "glutamate" => Ok(Transmitter::Glutamate),
+            "gaba" => Ok(Transmitter::Gaba),
             _ => Err(format!("Unknown transmitter {s}")),
         }
     }
 }
 
+/// Calcium-controlled plasticity (Shouval, Bear and Cooper, 2002): while
+/// postsynaptic calcium is moderately raised the synapse weakens, and
+/// while it is high the synapse strengthens. Brief, weak activation leaves
+/// calcium below both thresholds; NMDA receptors let in enough for
+/// potentiation only when presynaptic release meets a depolarized
+/// postsynaptic segment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plasticity {
+    /// Multiplies the conductance of every receptor.
+    pub weight: f32,
+    /// Free Ca2+ above which the synapse depresses.
+    pub depression_threshold: Molar,
+    /// Free Ca2+ above which it potentiates instead.
+    pub potentiation_threshold: Molar,
+    /// Change in weight per second past either threshold.
+    pub rate: f32,
+    pub max_weight: f32,
+}
+
+impl Default for Plasticity {
+    fn default() -> Self {
+        Plasticity {
+            weight: 1.0,
+            depression_threshold: Molar(0.35e-6),
+            potentiation_threshold: Molar(0.55e-6),
+            rate: 1.0,
+            max_weight: 2.0,
+        }
+    }
+}
+
+impl Plasticity {
+    /// Move the weight for `interval` at a postsynaptic free calcium of
+    /// `calcium`.
+    pub fn step(&mut self, calcium: &Molar, interval: &Interval) {
+        let direction = if calcium.0 >= self.potentiation_threshold.0 {
+            1.0
+        } else if calcium.0 >= self.depression_threshold.0 {
+            -1.0
+        } else {
+            0.0
+        };
+        self.weight = (self.weight + direction * self.rate * interval.0).clamp(0.0, self.max_weight);
+    }
+
+    pub fn serialize(&self) -> serialize::Plasticity {
+        serialize::Plasticity {
+            weight: self.weight,
+            depression_threshold_molar: self.depression_threshold.0,
+            potentiation_threshold_molar: self.potentiation_threshold.0,
+            rate_per_second: self.rate,
+            max_weight: self.max_weight,
+        }
+    }
+
+    pub fn deserialize(s: &serialize::Plasticity) -> Self {
+        Plasticity {
+            weight: s.weight,
+            depression_threshold: Molar(s.depression_threshold_molar),
+            potentiation_threshold: Molar(s.potentiation_threshold_molar),
+            rate: s.rate_per_second,
+            max_weight: s.max_weight,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Receptor {
     pub membrane_channel: MembraneChannel,
     pub neurotransmitter_sensitivity: Sensitivity,
+    /// Voltage-dependent block by extracellular magnesium, as in NMDA
+    /// receptors.
+    pub magnesium_block: Option<MagnesiumBlock>,
 }
 
 impl Receptor {
@@ -206,6 +330,7 @@ impl Receptor {
         serialize::Receptor {
             membrane_channel: self.membrane_channel.serialize(),
             neurotransmitter_sensitivity: self.neurotransmitter_sensitivity.serialize(),
+            magnesium_block: self.magnesium_block.as_ref().map(|b| b.serialize()),
         }
     }
 
@@ -213,10 +338,40 @@ impl Receptor {
         Ok(Receptor {
             membrane_channel: MembraneChannel::deserialize(&s.membrane_channel),
             neurotransmitter_sensitivity: Sensitivity::deserialize(&s.neurotransmitter_sensitivity)?,
+            magnesium_block: s.magnesium_block.as_ref().map(MagnesiumBlock::deserialize),
         })
     }
 }
 
+/// The block of NMDA receptors by Mg2+ (Jahr and Stevens, 1990), relieved
+/// by depolarization.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MagnesiumBlock {
+    /// Extracellular Mg2+.
+    pub magnesium: Molar,
+}
+
+impl Default for MagnesiumBlock {
+    fn default() -> Self {
+        MagnesiumBlock { magnesium: Molar(1e-3) }
+    }
+}
+
+impl MagnesiumBlock {
+    /// The fraction of receptors not blocked at `v`.
+    pub fn unblocked_fraction(&self, v: &MilliVolts) -> f32 {
+        1.0 / (1.0 + (-0.062 * v.0).exp() * self.magnesium.0 / 3.57e-3)
+    }
+
+    pub fn serialize(&self) -> serialize::MagnesiumBlock {
+        serialize::MagnesiumBlock { magnesium_molar: self.magnesium.0 }
+    }
+
+    pub fn deserialize(s: &serialize::MagnesiumBlock) -> Self {
+        MagnesiumBlock { magnesium: Molar(s.magnesium_molar) }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Sensitivity {
     pub transmitter: Transmitter,
@@ -350,7 +505,7 @@ impl TransmitterPumpParams {
 pub mod examples {
     use super::*;
     use crate::dimension::{MilliVolts, Molar};
-    use crate::neuron::channel::common_channels::AMPA_CHANNEL;
+    use crate::neuron::channel::common_channels::{AMPA_CHANNEL, NMDA_CHANNEL};
     use crate::neuron::solution::INTERSTICIAL_FLUID;
 
     // Note: The numbers here are totally made up.
@@ -399,6 +554,23 @@ pub mod examples {
                 concentration_at_half_max: Molar(3e-3), // TODO: determine the right value.
                 slope: 10000.0,                            // TODO: determine the right value.
             },
+            magnesium_block: None,
+        }
+    }
+
+    // Note: The numbers here are totally made up, apart from the block.
+    pub fn nmda_receptor(initial_voltage: &MilliVolts) -> Receptor {
+        Receptor {
+            membrane_channel: MembraneChannel {
+                channel: NMDA_CHANNEL.build(initial_voltage),
+                siemens_per_square_cm: 5e6,
+            },
+            neurotransmitter_sensitivity: Sensitivity {
+                transmitter: Transmitter::Glutamate,
+                concentration_at_half_max: Molar(1e-3),
+                slope: 10000.0,
+            },
+            magnesium_block: Some(MagnesiumBlock::default()),
         }
     }
     pub fn excitatory_synapse(initial_voltage: &MilliVolts) -> SynapseMembranes {
@@ -411,6 +583,8 @@ pub mod examples {
             presynaptic_pumps: vec![glutamate_release()],
             postsynaptic_receptors: vec![ampa_receptor(initial_voltage)],
             surface_area: AreaSquareMillimeters(1e-6),
+            custom: None,
+            plasticity: None,
         }
     }
 }
@@ -499,8 +673,8 @@ mod tests {
 
         // Before glutamate builds up in the synapse, synaptic current should be
         // small.
-        dbg!(synapse.current(&BODY_TEMPERATURE, &segment_2));
-        assert!(synapse.current(&BODY_TEMPERATURE, &segment_2).0 < 1.0);
+        dbg!(synapse.current(&BODY_TEMPERATURE, &segment_2.membrane_potential, &segment_2.intracellular_solution));
+        assert!(synapse.current(&BODY_TEMPERATURE, &segment_2.membrane_potential, &segment_2.intracellular_solution).0 < 1.0);
 
         // Run forward by 1.5ms. This is enough time for segment_1 to spike,
         // which should push glutamate into the synapse and trigger some
@@ -509,12 +683,12 @@ mod tests {
         for n in 0..1500 {
             segment_1.step(&BODY_TEMPERATURE, &INTERSTICIAL_FLUID, &interval);
             segment_2.step(&BODY_TEMPERATURE, &INTERSTICIAL_FLUID, &interval);
-            synapse.step(&BODY_TEMPERATURE, &segment_1, &segment_2, &interval);
-            synapse.apply_current(&interval, &BODY_TEMPERATURE, &mut segment_2);
+            synapse.step(&BODY_TEMPERATURE, &segment_1.membrane_potential, &segment_2.membrane_potential, &interval);
+            synapse.apply_current(&interval, &BODY_TEMPERATURE, &mut segment_2.membrane_potential, &segment_2.intracellular_solution);
 
             // Pretend there are 1000 of these synapses behaving the same way.
             for n in 0..1000 {
-                synapse.apply_current(&interval, &BODY_TEMPERATURE, &mut segment_2);
+                synapse.apply_current(&interval, &BODY_TEMPERATURE, &mut segment_2.membrane_potential, &segment_2.intracellular_solution);
             }
 
             if n % 100 == 0 {
@@ -522,7 +696,7 @@ mod tests {
                 let coeff = &synapse.postsynaptic_receptors[0]
                     .neurotransmitter_sensitivity
                     .gating_coefficient(&synapse.transmitter_concentrations);
-                let i = synapse.current(&BODY_TEMPERATURE, &segment_2).0;
+                let i = synapse.current(&BODY_TEMPERATURE, &segment_2.membrane_potential, &segment_2.intracellular_solution).0;
                 let v_1 = &segment_1.membrane_potential;
                 let v_2 = &segment_2.membrane_potential;
                 dbg!(v_1.0);
@@ -530,16 +704,16 @@ mod tests {
                 dbg!(m_g);
                 dbg!(coeff);
                 dbg!(i);
-                dbg!(synapse.current(&BODY_TEMPERATURE, &segment_2).0);
+                dbg!(synapse.current(&BODY_TEMPERATURE, &segment_2.membrane_potential, &segment_2.intracellular_solution).0);
             }
         }
 
         let glu_pump = &synapse.presynaptic_pumps[0];
 
-        dbg!(synapse.current(&BODY_TEMPERATURE, &segment_2));
+        dbg!(synapse.current(&BODY_TEMPERATURE, &segment_2.membrane_potential, &segment_2.intracellular_solution));
         dbg!(glu_pump.target_concentration( &segment_1.membrane_potential ));
         // assert_eq!(synapse.transmitter_concentrations.glutamate, Molar(1.0));
         assert!( (synapse.transmitter_concentrations.glutamate.0 - 0.005202022).abs() < epsilon );
-        // assert_eq!(synapse.current(&BODY_TEMPERATURE, &segment_2).0, 1.0);
+        // assert_eq!(synapse.current(&BODY_TEMPERATURE, &segment_2.membrane_potential, &segment_2.intracellular_solution).0, 1.0);
     }
 }
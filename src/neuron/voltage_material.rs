@@ -0,0 +1,336 @@
+//! Segment coloring by membrane voltage.
+//!
+//! Each frame every segment's voltage is mapped through a `Colormap`,
+//! stretched over `voltage_range`, and written with its transform into the
+//! instance data of its batch (see `instancing`), so that recoloring the
+//! whole scene is one buffer upload per batch. Colors are quantized to the
+//! palette's levels, and frames on which no segment changed level upload
+//! nothing.
+//!
+//! Alongside the color goes an emissive glow picked up by bloom. Its
+//! drive follows the voltage or, in `EmissiveMode::Spiking`, a flash
+//! triggered by fast depolarization that decays over a configurable time.
+//! Segments beyond the `ClipPlane`, if any, are not drawn, for seeing into
+//! dense arbors.
+
+use bevy::prelude::*;
+
+use crate::dimension::{Interval, MilliVolts, SimulationStepSeconds, StepsPerFrame};
+use crate::instancing::{SegmentBatch, SegmentInstancingPlugin, SegmentInstances};
+use crate::lod::SegmentShape;
+use crate::neuron::membrane::MembraneVoltage;
+
+/// Colors a colormap is sampled at each frame.
+const PALETTE_LEN: usize = 256;
+
+/// The palettes segments can be colored with, from most hyperpolarized to
+/// most depolarized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colormap {
+    /// Black to teal, the original palette.
+    Teal,
+    /// Perceptually uniform dark purple to yellow.
+    Viridis,
+    /// Diverging blue to red through grey, centered on the middle of the
+    /// voltage range.
+    Coolwarm,
+}
+
+/// sRGB control points, evenly spaced.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+const COOLWARM: [[u8; 3]; 5] = [
+    [59, 76, 192],
+    [141, 176, 254],
+    [221, 221, 221],
+    [244, 154, 123],
+    [180, 4, 38],
+];
+
+fn interpolate(points: &[[u8; 3]], t: f32) -> [u8; 4] {
+    let x = t.clamp(0.0, 1.0) * (points.len() - 1) as f32;
+    let i = (x.floor() as usize).min(points.len() - 2);
+    let f = x - i as f32;
+    let channel = |c: usize| (points[i][c] as f32 * (1.0 - f) + points[i + 1][c] as f32 * f).round() as u8;
+    [channel(0), channel(1), channel(2), 255]
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 3] = [Colormap::Teal, Colormap::Viridis, Colormap::Coolwarm];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Colormap::Teal => "Teal",
+            Colormap::Viridis => "Viridis",
+            Colormap::Coolwarm => "Coolwarm",
+        }
+    }
+
+    /// The sRGBA color `t` of the way along the map, clamped to 0..1.
+    pub fn color(&self, t: f32) -> [u8; 4] {
+        match self {
+            Colormap::Teal => {
+                let t = t.clamp(0.0, 1.0);
+                Color::rgb(0.0, t * 0.83, t * 0.48).as_rgba_u8()
+            },
+            Colormap::Viridis => interpolate(&VIRIDIS, t),
+            Colormap::Coolwarm => interpolate(&COOLWARM, t),
+        }
+    }
+
+    /// The map sampled evenly, in linear RGBA for the shader.
+    fn linear_palette(&self) -> Vec<[f32; 4]> {
+        (0..PALETTE_LEN)
+            .map(|i| {
+                let [r, g, b, a] = self.color(i as f32 / (PALETTE_LEN - 1) as f32);
+                Color::rgba_u8(r, g, b, a).as_linear_rgba_f32()
+            })
+            .collect()
+    }
+}
+
+/// A plane cutting away the part of every segment on the side `normal`
+/// points to, where `normal · p > offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    /// Unit length.
+    pub normal: Vec3,
+    pub offset: f32,
+}
+
+impl ClipPlane {
+    /// Whether `point` is cut away. The shader makes the same test.
+    pub fn clips(&self, point: Vec3) -> bool {
+        self.normal.dot(point) > self.offset
+    }
+
+    /// The plane as the shader takes it.
+    fn shader_data(plane: &Option<ClipPlane>) -> Vec4 {
+        plane.map_or(Vec4::ZERO, |plane| plane.normal.extend(plane.offset))
+    }
+}
+
+/// What drives the emissive (bloom) intensity of segments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmissiveMode {
+    /// Glow in proportion to the membrane voltage.
+    Voltage,
+    /// Flash when the membrane depolarizes at `full_flash_dv_dt` (mV/ms) or
+    /// faster, fading with time constant `decay` (wall-clock seconds).
+    Spiking { decay: Interval, full_flash_dv_dt: f32 },
+}
+
+impl EmissiveMode {
+    pub fn spiking() -> Self {
+        EmissiveMode::Spiking { decay: Interval(0.3), full_flash_dv_dt: 50.0 }
+    }
+}
+
+/// Recent depolarization of a segment, for `EmissiveMode::Spiking`.
+#[derive(Component, Debug)]
+pub struct SpikeFlash {
+    pub last_voltage: MilliVolts,
+    pub intensity: f32,
+}
+
+impl SpikeFlash {
+    /// Decay the flash over `wall_interval`, and retrigger it from the rate
+    /// of depolarization over the simulated `simulation_interval`.
+    pub fn update(
+        &mut self,
+        v: &MilliVolts,
+        simulation_interval: &Interval,
+        wall_interval: &Interval,
+        decay: &Interval,
+        full_flash_dv_dt: f32,
+    ) -> f32 {
+        let dv_dt = (v.0 - self.last_voltage.0) / (simulation_interval.0 * 1000.0).max(f32::EPSILON);
+        let drive = (dv_dt / full_flash_dv_dt).clamp(0.0, 1.0);
+        let decayed = self.intensity * (-wall_interval.0 / decay.0.max(f32::EPSILON)).exp();
+        self.last_voltage = v.clone();
+        self.intensity = decayed.max(drive);
+        self.intensity
+    }
+}
+
+/// A segment's color and emissive drive as drawn, each quantized to one
+/// of `PALETTE_LEN` levels. Changes within a level are not visible, so do
+/// not rewrite the segment's instance.
+#[derive(Clone, Component, Copy, Debug, Default, PartialEq)]
+pub struct ColorBucket {
+    /// An index into the colormap's palette.
+    pub color: u8,
+    pub glow: u8,
+}
+
+impl ColorBucket {
+    /// The buckets of `t`, the way through the voltage range, and `drive`,
+    /// both clamped to 0..1.
+    pub fn new(t: f32, drive: f32) -> ColorBucket {
+        let level = |x: f32| (x.clamp(0.0, 1.0) * (PALETTE_LEN - 1) as f32).round() as u8;
+        ColorBucket { color: level(t), glow: level(drive) }
+    }
+
+    pub fn drive(&self) -> f32 {
+        self.glow as f32 / (PALETTE_LEN - 1) as f32
+    }
+}
+
+/// How segments are colored, and where they are clipped.
+#[derive(Resource)]
+pub struct MembraneMaterials {
+    pub colormap: Colormap,
+    pub voltage_range: (MilliVolts, MilliVolts),
+    pub emissive_gain: f32,
+    pub emissive_mode: EmissiveMode,
+    pub clip_plane: Option<ClipPlane>,
+}
+
+impl Default for MembraneMaterials {
+    fn default() -> Self {
+        MembraneMaterials {
+            colormap: Colormap::Teal,
+            voltage_range: (MilliVolts(-80.0), MilliVolts(50.0)),
+            emissive_gain: 100000.0,
+            emissive_mode: EmissiveMode::Voltage,
+            clip_plane: None,
+        }
+    }
+}
+
+pub struct VoltageMaterialPlugin;
+
+impl Plugin for VoltageMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(SegmentInstancingPlugin)
+            .init_resource::<MembraneMaterials>()
+            .add_systems(Update, (attach_spike_flashes, apply_voltage_to_materials).chain());
+    }
+}
+
+fn attach_spike_flashes(
+    mut commands: Commands,
+    new_segments: Query<(Entity, &MembraneVoltage), (With<SegmentShape>, Without<SpikeFlash>)>,
+) {
+    for (entity, v) in &new_segments {
+        commands.entity(entity).insert((SpikeFlash { last_voltage: v.0.clone(), intensity: 0.0 }, ColorBucket::default()));
+    }
+}
+
+/// Write every segment's transform, color and emissive drive into the
+/// instances of the batch for its mesh. The batches are only rewritten,
+/// and so uploaded to the GPU, on frames a segment moved, changed shape or
+/// changed `ColorBucket`, or the color settings changed.
+pub fn apply_voltage_to_materials(
+    membrane_materials: Res<MembraneMaterials>,
+    mut applied: Local<Option<(Colormap, (f32, f32), f32, Vec4)>>,
+    time: Res<Time>,
+    simulation_step: Res<SimulationStepSeconds>,
+    steps_per_frame: Res<StepsPerFrame>,
+    mut segments: Query<(Ref<GlobalTransform>, Ref<SegmentShape>, &MembraneVoltage, &mut SpikeFlash, &mut ColorBucket)>,
+    mut removed: RemovedComponents<SegmentShape>,
+    mut batches: Query<(&SegmentBatch, &mut SegmentInstances)>,
+) {
+    let simulation_interval = Interval(simulation_step.0 * steps_per_frame.0 as f32);
+    let wall_interval = Interval(time.delta_seconds());
+    let (v_min, v_max) = (membrane_materials.voltage_range.0.0, membrane_materials.voltage_range.1.0);
+    let clip_plane = ClipPlane::shader_data(&membrane_materials.clip_plane);
+    // Compared by value, since the GUI borrows the settings mutably every
+    // frame.
+    let settings = (membrane_materials.colormap, (v_min, v_max), membrane_materials.emissive_gain, clip_plane);
+    let mut stale = applied.replace(settings) != Some(settings);
+    stale |= removed.read().count() > 0;
+    for (transform, shape, v, mut flash, mut bucket) in &mut segments {
+        let t = ((v.0.0 - v_min) / (v_max - v_min)).clamp(0.0, 1.0);
+        let drive = match &membrane_materials.emissive_mode {
+            EmissiveMode::Voltage => t,
+            EmissiveMode::Spiking { decay, full_flash_dv_dt } =>
+                flash.update(&v.0, &simulation_interval, &wall_interval, decay, *full_flash_dv_dt),
+        };
+        let wanted = ColorBucket::new(t, drive);
+        if *bucket != wanted {
+            *bucket = wanted;
+            stale = true;
+        }
+        stale |= transform.is_changed() || shape.is_changed();
+    }
+    if !stale {
+        return;
+    }
+
+    let mut batches: Vec<_> = batches.iter_mut().collect();
+    for (_, instances) in batches.iter_mut() {
+        instances.0.clear();
+    }
+    let palette = membrane_materials.colormap.linear_palette();
+    let [r, g, b, _] = palette[PALETTE_LEN - 1];
+    for (transform, shape, _, _, bucket) in &segments {
+        let glow = bucket.drive() * membrane_materials.emissive_gain;
+        let model = transform.compute_matrix() * Mat4::from_scale(shape.scale);
+        let batch = shape.batch();
+        if let Some((_, instances)) = batches.iter_mut().find(|(kind, _)| **kind == batch) {
+            instances.push(model, palette[bucket.color as usize], [r * glow, g * glow, b * glow, 1.0], clip_plane);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spike_flash_triggers_then_decays() {
+        let mut flash = SpikeFlash { last_voltage: MilliVolts(-70.0), intensity: 0.0 };
+        let frame = Interval(0.1e-3);
+        let wall = Interval(0.1);
+        let decay = Interval(0.1);
+
+        // 10 mV in 0.1 ms is 100 mV/ms, twice the full-flash rate.
+        assert_eq!(flash.update(&MilliVolts(-60.0), &frame, &wall, &decay, 50.0), 1.0);
+        // Repolarizing does not retrigger; the flash fades by 1/e.
+        let faded = flash.update(&MilliVolts(-70.0), &frame, &wall, &decay, 50.0);
+        assert!((faded - (-1.0f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn colormaps_span_their_control_points() {
+        assert_eq!(Colormap::Viridis.color(0.0), [68, 1, 84, 255]);
+        assert_eq!(Colormap::Viridis.color(2.0), [253, 231, 37, 255]);
+        assert_eq!(Colormap::Coolwarm.color(0.5), [221, 221, 221, 255]);
+        assert_eq!(Colormap::Coolwarm.color(0.125), [100, 126, 223, 255]);
+        assert_eq!(Colormap::Teal.color(-1.0), [0, 0, 0, 255]);
+        for colormap in Colormap::ALL {
+            let palette = colormap.linear_palette();
+            assert_eq!(palette.len(), PALETTE_LEN);
+            assert!(palette.iter().flatten().all(|c| (0.0..=1.0).contains(c)));
+        }
+    }
+
+    #[test]
+    fn nearby_voltages_share_a_bucket() {
+        // A level is 1/255 of the range.
+        assert_eq!(ColorBucket::new(0.5, 0.0), ColorBucket::new(0.501, 0.0));
+        assert_ne!(ColorBucket::new(0.5, 0.0), ColorBucket::new(0.51, 0.0));
+        assert_eq!(ColorBucket::new(2.0, -1.0), ColorBucket { color: 255, glow: 0 });
+        assert_eq!(ColorBucket::new(0.0, 1.0).drive(), 1.0);
+    }
+
+    #[test]
+    fn clip_planes_cut_the_side_their_normal_faces() {
+        let plane = ClipPlane { normal: Vec3::X, offset: 2.0 };
+        assert!(plane.clips(Vec3::new(3.0, 0.0, 0.0)));
+        assert!(!plane.clips(Vec3::new(1.0, 5.0, 0.0)));
+        assert_eq!(ClipPlane::shader_data(&Some(plane)), Vec4::new(1.0, 0.0, 0.0, 2.0));
+        // No plane cuts nothing: 0 > 0 fails in the shader.
+        assert_eq!(ClipPlane::shader_data(&None), Vec4::ZERO);
+    }
+}
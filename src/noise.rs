@@ -0,0 +1,174 @@
+//! Intrinsic membrane noise, so that identical neurons in a network do not
+//! fire in perfect synchrony.
+//!
+//! Each noisy segment carries two Ornstein–Uhlenbeck currents: a slow one
+//! standing in for background synaptic bombardment, with a mean and a
+//! ~5 ms correlation time, and a fast, zero-mean one standing in for the
+//! stochastic gating of a finite number of channels. They are advanced
+//! with the exact OU update, so any step size is stable, and draw from the
+//! `SimulationRng` stream of their segment and step, so runs repeat from
+//! the scene's seed.
+//!
+//! Noise is set per neuron in the scene, usually from a `NoisePreset` for
+//! a whole population at once with `Command::SetNoise`.
+
+use bevy::prelude::*;
+
+use crate::command::Command;
+use crate::integrations::grace::{CurrentScene, SceneSegment};
+use crate::rng::{Rng, SimulationRng, NOISE_STREAM};
+use crate::serialize;
+
+/// Noise calibrated to give roughly a target voltage standard deviation
+/// at rest in a Hodgkin–Huxley membrane (about 3 kΩ·cm², τm ≈ 3 ms).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoisePreset {
+    /// A quiet slice recording: about 0.3 mV.
+    Slice,
+    /// Spontaneous background activity: about 1 mV.
+    Background,
+    /// The high-conductance state in vivo: about 3 mV, and a slight
+    /// depolarization.
+    InVivo,
+}
+
+impl NoisePreset {
+    pub const ALL: [NoisePreset; 3] = [NoisePreset::Slice, NoisePreset::Background, NoisePreset::InVivo];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoisePreset::Slice => "Slice",
+            NoisePreset::Background => "Background",
+            NoisePreset::InVivo => "In vivo",
+        }
+    }
+
+    pub fn noise(&self) -> serialize::MembraneNoise {
+        let (mean, background_sigma, channel_sigma) = match self {
+            NoisePreset::Slice => (0.0, 0.1, 0.05),
+            NoisePreset::Background => (0.0, 0.4, 0.1),
+            NoisePreset::InVivo => (0.5, 1.2, 0.2),
+        };
+        serialize::MembraneNoise {
+            mean_uamps_per_square_cm: mean,
+            background_sigma_uamps_per_square_cm: background_sigma,
+            background_tau_sec: 0.005,
+            channel_sigma_uamps_per_square_cm: channel_sigma,
+            channel_tau_sec: 0.0005,
+        }
+    }
+}
+
+/// One exact step of an Ornstein–Uhlenbeck process with stationary `mean`
+/// and standard deviation `sigma`, given a standard normal draw `z`.
+fn ou_step(x: f32, mean: f32, sigma: f32, tau: f32, interval: f32, z: f32) -> f32 {
+    let decay = if tau > 0.0 { (-interval / tau).exp() } else { 0.0 };
+    mean + (x - mean) * decay + sigma * (1.0 - decay * decay).sqrt() * z
+}
+
+#[derive(Clone, Component, Debug)]
+pub struct MembraneNoise {
+    pub spec: serialize::MembraneNoise,
+    /// The current densities (µA/cm²) of the background and channel
+    /// processes.
+    pub background: f32,
+    pub channel: f32,
+    /// The seed of the segment's stream; see `seed_noise`.
+    pub stream: u64,
+}
+
+impl MembraneNoise {
+    pub fn new(spec: serialize::MembraneNoise) -> Self {
+        let background = spec.mean_uamps_per_square_cm;
+        MembraneNoise { spec, background, channel: 0.0, stream: 0 }
+    }
+
+    /// Advance the noise by `interval` seconds for simulation step `step`,
+    /// returning the current density (µA/cm²) it injects.
+    pub fn step(&mut self, step: u64, interval: f32) -> f32 {
+        let spec = &self.spec;
+        let mut rng = Rng::keyed(self.stream, &[step]);
+        self.background = ou_step(
+            self.background,
+            spec.mean_uamps_per_square_cm,
+            spec.background_sigma_uamps_per_square_cm,
+            spec.background_tau_sec,
+            interval,
+            rng.normal() as f32,
+        );
+        self.channel = ou_step(
+            self.channel,
+            0.0,
+            spec.channel_sigma_uamps_per_square_cm,
+            spec.channel_tau_sec,
+            interval,
+            rng.normal() as f32,
+        );
+        self.background + self.channel
+    }
+}
+
+/// Give each segment's noise the stream of its segment under the scene's
+/// seed, whenever it or the seed changes.
+pub fn seed_noise(rng: Res<SimulationRng>, mut segments: Query<(&SceneSegment, &mut MembraneNoise)>) {
+    for (SceneSegment(at), mut noise) in &mut segments {
+        if rng.is_changed() || noise.is_changed() {
+            noise.bypass_change_detection().stream =
+                rng.stream_seed(&[NOISE_STREAM, at.neuron as u64, at.segment as u64]);
+        }
+    }
+}
+
+/// Apply `Command::SetNoise` to the segments of every neuron in the
+/// population, or every neuron, without restarting the run.
+pub fn noise_commands(
+    mut commands: Commands,
+    mut events: EventReader<Command>,
+    current_scene: Res<CurrentScene>,
+    segments: Query<(Entity, &SceneSegment)>,
+) {
+    for command in events.read() {
+        let Command::SetNoise { tag, noise } = command else {
+            continue;
+        };
+        let in_population = |neuron: usize| {
+            tag.is_none() || current_scene.0.neurons.get(neuron).is_some_and(|n| n.tag == *tag)
+        };
+        for (entity, SceneSegment(at)) in &segments {
+            if !in_population(at.neuron) {
+                continue;
+            }
+            match noise {
+                Some(noise) => {
+                    commands.entity(entity).insert(MembraneNoise::new(noise.clone()));
+                },
+                None => {
+                    commands.entity(entity).remove::<MembraneNoise>();
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_has_its_calibrated_spread_and_repeats() {
+        let spec = NoisePreset::Background.noise();
+        let mut noise = MembraneNoise { stream: 7, ..MembraneNoise::new(spec.clone()) };
+        let interval = 2.5e-5;
+        let samples = (0..200_000).map(|step| noise.step(step, interval)).collect::<Vec<_>>();
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        let sd = (samples.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / samples.len() as f32).sqrt();
+        let expected_sd = (spec.background_sigma_uamps_per_square_cm.powi(2) + spec.channel_sigma_uamps_per_square_cm.powi(2)).sqrt();
+        assert!(mean.abs() < 0.1, "{}", mean);
+        assert!((sd / expected_sd - 1.0).abs() < 0.15, "{} vs {}", sd, expected_sd);
+
+        let mut again = MembraneNoise { stream: 7, ..MembraneNoise::new(spec) };
+        assert_eq!(again.step(0, interval), samples[0]);
+        // A step longer than the correlation time is still stable.
+        assert!(again.step(1, 1.0).abs() < 10.0);
+    }
+}
@@ -0,0 +1,97 @@
+//! Webhook notifications for long unattended runs: a summary when the run
+//! reaches its simulated duration, and a warning when it becomes unstable.
+//! Alarms without a URL of their own also post to the URL here.
+//!
+//! Native builds read the URL from `NB_SIM_WEBHOOK_URL` and the duration
+//! (seconds of simulated time) from `NB_SIM_RUN_SECONDS`, falling back to
+//! the config file (see `config`).
+
+use bevy::prelude::*;
+
+use crate::clock::RunClock;
+use crate::command::Command;
+use crate::config::Config;
+use crate::dimension::{StepCount, Timestamp};
+use crate::neuron::membrane::MembraneVoltage;
+use crate::webhook;
+
+/// Membrane potentials beyond this (mV) mean the integration has
+/// diverged.
+pub const UNSTABLE_MV: f32 = 1000.0;
+
+#[derive(Debug, Default, Resource)]
+pub struct Notifications {
+    pub webhook: Option<String>,
+    /// Pause and report once this much time has been simulated (s).
+    pub run_seconds: Option<f32>,
+    finished: bool,
+    unstable: bool,
+    last_time: f32,
+}
+
+impl Notifications {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_env() -> Self {
+        Notifications {
+            webhook: std::env::var("NB_SIM_WEBHOOK_URL").ok().filter(|url| !url.is_empty()),
+            run_seconds: std::env::var("NB_SIM_RUN_SECONDS").ok().and_then(|s| s.parse().ok()),
+            ..default()
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_env() -> Self {
+        Notifications::default()
+    }
+
+    /// The environment's settings, falling back to the config file's.
+    pub fn from_config(config: &Config) -> Self {
+        let notifications = Notifications::from_env();
+        Notifications {
+            webhook: notifications.webhook.or(config.webhook_url.clone()),
+            run_seconds: notifications.run_seconds.or(config.run_seconds),
+            ..notifications
+        }
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.webhook.as_deref().filter(|url| !url.is_empty())
+    }
+}
+
+pub fn notify(
+    timestamp: Res<Timestamp>,
+    step_count: Res<StepCount>,
+    run_clock: Res<RunClock>,
+    mut notifications: ResMut<Notifications>,
+    mut command_events: EventWriter<Command>,
+    voltages: Query<&MembraneVoltage>,
+) {
+    // A reset starts a new run.
+    if timestamp.0 < notifications.last_time {
+        notifications.finished = false;
+        notifications.unstable = false;
+    }
+    notifications.last_time = timestamp.0;
+    let t_ms = timestamp.0 * 1000.0;
+
+    if let Some(run_seconds) = notifications.run_seconds {
+        if !notifications.finished && timestamp.0 >= run_seconds {
+            notifications.finished = true;
+            command_events.send(Command::Pause);
+            if let Some(url) = notifications.url() {
+                webhook::post_json(url, &webhook::run_finished_body(t_ms, step_count.0, run_clock.elapsed_seconds()));
+            }
+        }
+    }
+
+    if !notifications.unstable {
+        let unstable = voltages.iter().filter(|v| !v.0.0.is_finite() || v.0.0.abs() >= UNSTABLE_MV).count();
+        if unstable > 0 {
+            notifications.unstable = true;
+            if let Some(url) = notifications.url() {
+                webhook::post_json(url, &webhook::unstable_body(t_ms, unstable));
+            }
+        }
+    }
+}
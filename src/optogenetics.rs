@@ -0,0 +1,312 @@
+//! Lights for optogenetics: spots shining on the scene in pulses, driving
+//! the light-gated channels of `neuron::opsin`.
+//!
+//! Each light is a Gaussian spot around a point, with no attenuation by
+//! depth; `projector` adds patterned light. Once a frame, every segment
+//! with an opsin records which lights reach it and how brightly; every
+//! step, the lights that are on set the effective irradiance of the
+//! segment's opsins, each weighted by the opsin's sensitivity at the
+//! light's wavelength.
+
+use bevy::prelude::*;
+#[cfg(feature = "render")]
+use bevy::math::prelude::Sphere;
+
+use crate::dimension::{Interval, Timestamp};
+use crate::neuron::membrane::Membrane;
+use crate::neuron::opsin::Opsin;
+use crate::neuron::segment::ecs::Segment;
+use crate::projector::Projector;
+use crate::serialize;
+use crate::stimulator::Envelope;
+use crate::units::UnitScale;
+
+/// Irradiance (mW/mm²) below which a light is taken not to reach a segment.
+pub const MIN_IRRADIANCE: f32 = 1e-4;
+
+/// When a light is on: within its envelope, for the first `pulses`
+/// periods.
+#[derive(Clone, Debug)]
+pub struct PulseTrain {
+    pub envelope: Envelope,
+    pub pulses: Option<u32>,
+}
+
+impl PulseTrain {
+    pub fn is_on(&self, t: &Timestamp) -> bool {
+        let within_train = self.pulses.is_none_or(|pulses| t.0 < self.envelope.period.0 * pulses as f32);
+        within_train && self.envelope.completion(t).is_some()
+    }
+}
+
+#[derive(Clone, Component, Debug)]
+pub struct LightSource {
+    /// The center of the spot, in the scene frame; see `units`.
+    pub position_um: Vec3,
+    /// Irradiance (mW/mm²) at the center while a pulse is on.
+    pub irradiance: f32,
+    pub spread_um: f32,
+    pub wavelength_nm: f32,
+    pub train: PulseTrain,
+}
+
+impl Default for LightSource {
+    fn default() -> Self {
+        LightSource {
+            position_um: Vec3::ZERO,
+            irradiance: 5.0,
+            spread_um: 100.0,
+            wavelength_nm: Opsin::ChR2.peak_wavelength_nm(),
+            train: PulseTrain {
+                envelope: Envelope::deserialize(&serialize::Envelope { period_sec: 0.1, onset_sec: 0.01, offset_sec: 0.015 }),
+                pulses: None,
+            },
+        }
+    }
+}
+
+impl LightSource {
+    /// Irradiance (mW/mm²) at `point_um` while a pulse is on.
+    pub fn irradiance_at(&self, point_um: Vec3) -> f32 {
+        let d2 = point_um.distance_squared(self.position_um);
+        self.irradiance * (-0.5 * d2 / self.spread_um.powi(2)).exp()
+    }
+
+    pub fn serialize(&self) -> serialize::LightSource {
+        serialize::LightSource {
+            location: serialize::Location {
+                x_mm: self.position_um.x / UnitScale::mm_to_microns(1.0),
+                y_mm: self.position_um.y / UnitScale::mm_to_microns(1.0),
+                z_mm: self.position_um.z / UnitScale::mm_to_microns(1.0),
+            },
+            irradiance_mw_per_square_mm: self.irradiance,
+            spread_um: self.spread_um,
+            wavelength_nm: self.wavelength_nm,
+            envelope: self.train.envelope.serialize(),
+            pulses: self.train.pulses,
+        }
+    }
+
+    pub fn deserialize(light: &serialize::LightSource) -> Self {
+        let serialize::Location { x_mm, y_mm, z_mm } = &light.location;
+        LightSource {
+            position_um: Vec3::new(*x_mm, *y_mm, *z_mm) * UnitScale::mm_to_microns(1.0),
+            irradiance: light.irradiance_mw_per_square_mm,
+            spread_um: light.spread_um,
+            wavelength_nm: light.wavelength_nm,
+            train: PulseTrain { envelope: Envelope::deserialize(&light.envelope), pulses: light.pulses },
+        }
+    }
+}
+
+/// The frame of a movie of `frames` frames showing at `t`, or `None` once
+/// a movie that does not loop has ended. Without a frame interval, the
+/// first frame shows throughout.
+pub fn movie_frame(frames: usize, frame_interval: &Interval, looping: bool, t: &Timestamp) -> Option<usize> {
+    if frames <= 1 || frame_interval.0 <= 0.0 {
+        return (frames > 0).then_some(0);
+    }
+    let frame = (t.0 / frame_interval.0).max(0.0) as usize;
+    if looping { Some(frame % frames) } else { (frame < frames).then_some(frame) }
+}
+
+/// One light reaching a segment.
+#[derive(Clone, Debug)]
+pub struct Lit {
+    /// Irradiance (mW/mm²) at the segment while a pulse is on: one value
+    /// for a steady light, or one for each frame of a projected movie.
+    pub irradiance: Vec<f32>,
+    /// How long each frame of a movie shows.
+    pub frame_interval: Interval,
+    /// Whether a movie starts over after its last frame, rather than
+    /// going dark.
+    pub looping: bool,
+    pub wavelength_nm: f32,
+    pub train: PulseTrain,
+}
+
+impl Lit {
+    pub fn steady(irradiance: f32, wavelength_nm: f32, train: PulseTrain) -> Self {
+        Lit { irradiance: vec![irradiance], frame_interval: Interval(0.0), looping: false, wavelength_nm, train }
+    }
+
+    /// Irradiance (mW/mm²) at the segment at `t`, with the train on.
+    pub fn irradiance_at(&self, t: &Timestamp) -> f32 {
+        movie_frame(self.irradiance.len(), &self.frame_interval, self.looping, t)
+            .map_or(0.0, |frame| self.irradiance[frame])
+    }
+
+    /// Whether the light is bright enough, in any frame, to reach the
+    /// segment.
+    pub fn reaches(&self) -> bool {
+        self.irradiance.iter().any(|i| *i > MIN_IRRADIANCE)
+    }
+}
+
+/// The lights reaching a segment with an opsin.
+#[derive(Clone, Component, Debug, Default)]
+pub struct Illumination(pub Vec<Lit>);
+
+impl Illumination {
+    /// The irradiance `opsin` responds to at `t`, from the lights on.
+    pub fn effective_irradiance(&self, opsin: Opsin, t: &Timestamp) -> f32 {
+        self.0
+            .iter()
+            .filter(|lit| lit.train.is_on(t))
+            .map(|lit| lit.irradiance_at(t) * opsin.spectral_sensitivity(lit.wavelength_nm))
+            .sum()
+    }
+}
+
+/// Set the irradiance of the membrane's opsins for a step at `t`.
+pub fn illuminate(membrane: &mut Membrane, illumination: Option<&Illumination>, t: &Timestamp) {
+    for membrane_channel in membrane.membrane_channels.iter_mut() {
+        if let Some(opsin) = membrane_channel.channel.opsin.as_mut() {
+            opsin.irradiance = illumination.map_or(0.0, |i| i.effective_irradiance(opsin.opsin(), t));
+        }
+    }
+}
+
+/// Record, for each segment with an opsin, the lights and projectors that
+/// reach it.
+pub fn illuminate_segments(
+    mut commands: Commands,
+    scale: Res<UnitScale>,
+    lights: Query<&LightSource>,
+    projectors: Query<&Projector>,
+    mut segments: Query<(Entity, &Transform, &Membrane, Option<&mut Illumination>), With<Segment>>,
+) {
+    for (entity, transform, membrane, illumination) in &mut segments {
+        if !membrane.membrane_channels.iter().any(|c| c.channel.opsin.is_some()) {
+            continue;
+        }
+        let point_um = transform.translation / scale.screen_per_micron();
+        let lit = lights
+            .iter()
+            .map(|light| Lit::steady(light.irradiance_at(point_um), light.wavelength_nm, light.train.clone()))
+            .chain(projectors.iter().map(|projector| projector.lit_at(point_um)))
+            .filter(Lit::reaches)
+            .collect::<Vec<_>>();
+        match (illumination, lit.is_empty()) {
+            (Some(_), true) => {
+                commands.entity(entity).remove::<Illumination>();
+            },
+            (Some(mut illumination), false) => illumination.0 = lit,
+            (None, true) => {},
+            (None, false) => {
+                commands.entity(entity).insert(Illumination(lit));
+            },
+        }
+    }
+}
+
+/// An approximate color for light of `wavelength_nm`.
+#[cfg(feature = "render")]
+pub fn wavelength_color(wavelength_nm: f32) -> Color {
+    let w = wavelength_nm;
+    let (r, g, b) = if w < 440.0 {
+        ((440.0 - w) / 60.0, 0.0, 1.0)
+    } else if w < 490.0 {
+        (0.0, (w - 440.0) / 50.0, 1.0)
+    } else if w < 510.0 {
+        (0.0, 1.0, (510.0 - w) / 20.0)
+    } else if w < 580.0 {
+        ((w - 510.0) / 70.0, 1.0, 0.0)
+    } else if w < 645.0 {
+        (1.0, (645.0 - w) / 65.0, 0.0)
+    } else {
+        (1.0, 0.0, 0.0)
+    };
+    Color::rgb(r.clamp(0.0, 1.0), g, b)
+}
+
+/// The translucent sphere drawn at a light, one spread in radius, and
+/// whether it was last drawn on.
+#[cfg(feature = "render")]
+#[derive(Component)]
+pub struct LightMarker {
+    on: bool,
+}
+
+#[cfg(feature = "render")]
+fn marker_material(light: &LightSource, on: bool) -> StandardMaterial {
+    let color = wavelength_color(light.wavelength_nm);
+    StandardMaterial {
+        base_color: color.with_a(if on { 0.35 } else { 0.08 }),
+        emissive: if on { color * 2.0 } else { Color::BLACK },
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    }
+}
+
+/// Draw each new light.
+#[cfg(feature = "render")]
+pub fn spawn_light_markers(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    lights: Query<(Entity, &LightSource), Added<LightSource>>,
+) {
+    for (entity, light) in &lights {
+        commands.entity(entity).insert((
+            LightMarker { on: false },
+            PbrBundle {
+                mesh: meshes.add(Sphere { radius: 1.0 }),
+                material: materials.add(marker_material(light, false)),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Place and size each light's marker, lighting it up during pulses.
+#[cfg(feature = "render")]
+pub fn update_light_markers(
+    scale: Res<UnitScale>,
+    timestamp: Res<Timestamp>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut lights: Query<(Ref<LightSource>, &mut LightMarker, &mut Transform, &Handle<StandardMaterial>)>,
+) {
+    for (light, mut marker, mut transform, material) in &mut lights {
+        let on = light.train.is_on(&timestamp);
+        if light.is_changed() || marker.is_added() {
+            transform.translation = scale.point_to_screen(light.position_um);
+            transform.scale = Vec3::splat(scale.microns_to_screen(light.spread_um));
+        }
+        if light.is_changed() || marker.is_added() || on != marker.on {
+            marker.on = on;
+            if let Some(material) = materials.get_mut(material) {
+                *material = marker_material(&light, on);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lights_fall_off_and_pulse() {
+        let light = LightSource { spread_um: 50.0, ..LightSource::default() };
+        assert_eq!(light.irradiance_at(Vec3::ZERO), light.irradiance);
+        let one_spread = light.irradiance_at(Vec3::new(50.0, 0.0, 0.0));
+        assert!((one_spread / light.irradiance - (-0.5f32).exp()).abs() < 1e-6);
+
+        let train = PulseTrain { pulses: Some(2), ..light.train.clone() };
+        assert!(train.is_on(&Timestamp(0.012)));
+        assert!(!train.is_on(&Timestamp(0.02)));
+        assert!(train.is_on(&Timestamp(0.112)));
+        assert!(!train.is_on(&Timestamp(0.212)), "only two pulses");
+
+        let illumination = Illumination(vec![Lit::steady(2.0, 470.0, train)]);
+        assert!((illumination.effective_irradiance(Opsin::ChR2, &Timestamp(0.012)) - 2.0).abs() < 1e-6);
+        assert!(illumination.effective_irradiance(Opsin::NpHR, &Timestamp(0.012)) < 0.05);
+        assert_eq!(illumination.effective_irradiance(Opsin::ChR2, &Timestamp(0.05)), 0.0);
+
+        let round_trip = LightSource::deserialize(&light.serialize());
+        assert!((round_trip.position_um - light.position_um).length() < 1e-3);
+        assert_eq!(round_trip.irradiance, light.irradiance);
+    }
+}
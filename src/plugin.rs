@@ -1,133 +1,570 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+#[cfg(feature = "render")]
+use bevy_egui::EguiPlugin;
+#[cfg(feature = "render")]
+use bevy_mod_picking::DefaultPickingPlugins;
+#[cfg(feature = "render")]
+use bevy_mod_picking::debug::DebugPickingPlugin;
+#[cfg(feature = "render")]
+use bevy_panorbit_camera::PanOrbitCameraSystemSet;
+use std::collections::HashMap;
 use std::fmt::{self, Display};
+#[cfg(feature = "render")]
 use std::time::Duration;
 
 
 use crate::dimension::{
     Interval,
-    Kelvin,
+    MilliVolts,
+    StepCount,
     Timestamp,
     SimulationStepSeconds,
     StepsPerFrame,
 };
-use crate::constants::{BODY_TEMPERATURE, CONDUCTANCE_PER_SQUARE_CM};
-use crate::stimulator::{StimulatorMaterials, Stimulator, Stimulation};
-
+use crate::checkpoint::{SnapshotBuffer, checkpoint_commands};
+use crate::warm_restart::warm_restart_commands;
+use crate::config::{Config, load_configured_scene};
+#[cfg(feature = "render")]
+use crate::autosave::{Autosave, autosave_gui, autosave_system, load_autosave};
+use crate::background::{BackgroundSimulation, background_running, receive_voltage_snapshots, supervise_background_simulation};
+#[cfg(feature = "render")]
+use crate::camera::CameraPlugin;
+use crate::command::{Command, SimulationControl, apply_commands, release_scheduled_commands, simulation_running};
+use crate::clock::RunClock;
+use crate::notify::{Notifications, notify};
+use crate::constants::SimulationConstants;
+use crate::lfp::compute_lfp;
+#[cfg(feature = "render")]
+use crate::lfp::{spawn_electrode_markers, update_electrode_markers};
+use crate::probe::{accept_remote_subscriptions, sample_probes, ProbeRegistry};
+#[cfg(feature = "render")]
+use crate::probe::{despawn_probe_markers, spawn_probe_markers};
+use crate::recording::RecordingPlugin;
+use crate::replay::{SessionRecorder, step_session};
+use crate::serialize;
+use crate::spike_train::SpikeTrainInput;
+use crate::rng::SimulationRng;
+use crate::stimulator::{Stimulator, seed_stimulators};
+#[cfg(feature = "render")]
+use crate::stimulator::{StimulatorMaterials, Stimulation};
+use crate::extracellular::{ExtracellularField, extracellular_potential, polarize_segments};
+#[cfg(feature = "render")]
+use crate::extracellular::{spawn_stimulating_electrode_markers, update_stimulating_electrode_markers};
+use crate::optogenetics::{Illumination, LightSource, illuminate, illuminate_segments};
+#[cfg(feature = "render")]
+use crate::optogenetics::{spawn_light_markers, update_light_markers};
+use crate::projector::Projector;
+#[cfg(feature = "render")]
+use crate::projector::{spawn_projector_screens, update_projector_screens};
+use crate::noise::{MembraneNoise, noise_commands, seed_noise};
+use crate::thermal::ThermalStimulator;
+#[cfg(feature = "render")]
+use crate::thermal::{HeatMaterials, spawn_heat_overlays, update_heat_overlays};
+use crate::threads;
+use crate::units::UnitScale;
+
+#[cfg(feature = "render")]
 use crate::gui::oscilloscope::{Oscilloscope, step_oscilloscope_system};
+#[cfg(feature = "render")]
+use crate::gui::runs::RunRegistry;
+#[cfg(feature = "render")]
+use crate::gui::session_log::SessionLog;
+#[cfg(feature = "render")]
+use crate::gui::voltage_clamp::{VoltageClamp, voltage_clamp_gui};
+#[cfg(feature = "render")]
+use crate::gui::recording::recording_gui;
+#[cfg(feature = "render")]
+use crate::gui::layout::{LayoutFile, layout_gui};
+#[cfg(feature = "render")]
+use crate::gui::morphology::{MorphologyEditor, morphology_gui};
+#[cfg(feature = "render")]
+use crate::gui::thermal::{ThermalSettings, thermal_gui};
+#[cfg(feature = "render")]
+use crate::gui::noise::{NoiseSettings, noise_gui};
+#[cfg(feature = "render")]
+use crate::gui::probes::probes_gui;
+#[cfg(feature = "render")]
+use crate::gui::electrodes::electrodes_gui;
+#[cfg(feature = "render")]
+use crate::gui::ions::ions_gui;
+#[cfg(feature = "render")]
+use crate::gui::membrane::{MembraneEditor, membrane_gui};
+#[cfg(feature = "render")]
+use crate::gui::channel_library::{ChannelLibrary, channel_library_gui};
+#[cfg(feature = "render")]
+use crate::gui::synapses::{SynapseEditor, draw_synapses, synapses_gui};
+#[cfg(feature = "render")]
+use crate::gui::inspector::inspector_gui;
+#[cfg(feature = "render")]
+use crate::selection::box_select;
+#[cfg(feature = "render")]
+use crate::gui::bulk::{BulkActions, bulk_gui};
+#[cfg(feature = "render")]
+use crate::gui::palette::{CommandPalette, Shortcuts, palette_gui};
+#[cfg(feature = "render")]
+use crate::gui::tooltip::segment_tooltip;
+#[cfg(feature = "render")]
+use crate::gui::colorbar::{Colorbar, colorbar_gui};
+#[cfg(feature = "render")]
+use crate::gui::view::view_gui;
+#[cfg(feature = "render")]
+use crate::lod::{LodSettings, choose_segment_meshes};
+#[cfg(feature = "render")]
+use crate::gui::clipping::{clipping_gui, draw_clip_plane};
+#[cfg(feature = "render")]
+use crate::gui::rollback::rollback_gui;
+#[cfg(feature = "render")]
+use crate::gui::optogenetics::{OptogeneticsEditor, optogenetics_gui};
+#[cfg(feature = "render")]
+use crate::gui::replay::replay_gui;
+#[cfg(feature = "render")]
+use crate::gui::raster::{SpikeRaster, raster_gui, record_spikes};
+#[cfg(feature = "render")]
+use crate::gui::spike_train::{SpikeTrainReplay, spike_train_gui};
+#[cfg(feature = "render")]
+use crate::gui::scale::{ScaleBar, draw_scale_bar, scale_gui};
+#[cfg(feature = "render")]
+use crate::gui::scene_diff::{SceneDiffView, scene_diff_gui};
+#[cfg(feature = "render")]
+use crate::gui::integration::{IntegrationRates, integration_rates_gui};
+#[cfg(feature = "render")]
+use crate::gui::speed_test::{SpeedTest, run_speed_test, speed_test_gui};
+#[cfg(feature = "render")]
+use crate::gui::step_advice::{StepAdvice, advise_step, step_advice_gui};
+#[cfg(feature = "render")]
+use crate::gui::watch::{WatchList, sample_watches, watch_gui};
+#[cfg(feature = "render")]
 use crate::gui;
-use crate::neuron::Junction;
-use crate::integrations::grace::Synapse;
+use crate::neuron::{Junction, junction_charge};
+use crate::neuron::geometry::axial_conductance;
+use crate::integrations::grace::{self, CurrentScene, GraceSceneReceiver, GraceSceneSender, SceneSegment, Synapse, handle_loaded_neuron};
+#[cfg(feature = "render")]
+use crate::integrations::grace::HoveredSegment;
 use crate::neuron::segment::{Geometry, ecs::Segment, ecs::InputCurrent};
 use crate::neuron::solution::{Solution, INTERSTICIAL_FLUID};
-use crate::neuron::membrane::{Membrane, MembraneMaterials, MembraneVoltage};
-use crate::neuron::channel::{ca_reversal, cl_reversal, k_reversal, na_reversal};
+use crate::neuron::ion_dynamics::{IonDynamics, diffuse_along_junctions, update_intracellular};
+use crate::neuron::calcium::{CalciumShell, sense_calcium, track_calcium_shells};
+use crate::neuron::extracellular_space::{ExtracellularSpace, PotassiumAccumulation, clear_extracellular_potassium, local_solution, track_extracellular_spaces};
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::multirate::{IntegrationRate, couple, seen_voltage};
+#[cfg(feature = "render")]
+use crate::neuron::voltage_material::VoltageMaterialPlugin;
+
+/// The full NeuronBench app: simulation, 3D visualization, GUI and
+/// picking. Each part is its own plugin and can be disabled with the
+/// builder methods, so that downstream crates can embed just the
+/// simulation or just the viewer:
+///
+/// ```ignore
+/// app.add_plugins(NbSimPlugin::default().gui(false).picking(false));
+/// ```
+#[derive(Clone, Debug)]
+pub struct NbSimPlugin {
+    pub biophysics: bool,
+    pub visualization: bool,
+    pub gui: bool,
+    pub picking: bool,
+}
 
-pub struct NbSimPlugin;
+impl Default for NbSimPlugin {
+    fn default() -> Self {
+        NbSimPlugin {
+            biophysics: true,
+            visualization: true,
+            gui: true,
+            picking: true,
+        }
+    }
+}
+
+impl NbSimPlugin {
+    /// Only the biophysics, with no rendering, GUI or picking.
+    pub fn headless() -> Self {
+        NbSimPlugin {
+            biophysics: true,
+            visualization: false,
+            gui: false,
+            picking: false,
+        }
+    }
+
+    pub fn biophysics(mut self, enabled: bool) -> Self {
+        self.biophysics = enabled;
+        self
+    }
+
+    pub fn visualization(mut self, enabled: bool) -> Self {
+        self.visualization = enabled;
+        self
+    }
+
+    pub fn gui(mut self, enabled: bool) -> Self {
+        self.gui = enabled;
+        self
+    }
+
+    pub fn picking(mut self, enabled: bool) -> Self {
+        self.picking = enabled;
+        self
+    }
+}
 
 impl Plugin for NbSimPlugin {
     fn build(&self, app: &mut App) {
-            app.insert_resource(default_env())
+        if self.biophysics {
+            app.add_plugins(BiophysicsPlugin);
+        }
+        // Without the `render` feature there is nothing to draw, and only
+        // the biophysics is added.
+        #[cfg(feature = "render")]
+        if self.visualization {
+            app.add_plugins(VisualizationPlugin);
+        }
+        #[cfg(feature = "render")]
+        if self.gui {
+            app.add_plugins(GuiPlugin);
+        }
+        #[cfg(feature = "render")]
+        if self.picking {
+            app.add_plugins(PickingPlugin);
+        }
+    }
+}
+
+/// Simulation state, the integration step, and the scene loader: any
+/// number of neurons and synapses arrive as a `serialize::Scene` through
+/// `Command::LoadScene`. Needs nothing but the bevy core plugins.
+pub struct BiophysicsPlugin;
+
+impl Plugin for BiophysicsPlugin {
+    fn build(&self, app: &mut App) {
+        let (scene_sender, scene_receiver) = crossbeam::channel::unbounded();
+        if !app.world.contains_resource::<Config>() {
+            app.insert_resource(Config::load().unwrap_or_else(|e| {
+                eprintln!("{}; using the default settings", e);
+                Config::default()
+            }));
+        }
+        let config = app.world.resource::<Config>().clone();
+        app.insert_resource(default_env())
+            .init_resource::<SimulationConstants>()
+            .init_resource::<SimulationRng>()
+            .init_resource::<RunClock>()
+            .init_resource::<CurrentScene>()
+            .init_resource::<UnitScale>()
             .insert_resource(Timestamp(0.0))
-            .insert_resource(StepsPerFrame(100))
-            .init_resource::<gui::NextClickAction>()
-            .init_resource::<Oscilloscope>()
-            .insert_resource(Stimulator::default())
-            .insert_resource(SimulationStepSeconds(5e-7))
-            .init_resource::<MembraneMaterials>()
+            .init_resource::<StepCount>()
+            .init_resource::<SimulationControl>()
+            .init_resource::<SessionRecorder>()
+            .init_resource::<SnapshotBuffer>()
+            .init_resource::<ProbeRegistry>()
+            .init_resource::<IonDynamics>()
+            .insert_resource(Notifications::from_config(&config))
+            .init_resource::<BackgroundSimulation>()
+            .insert_resource(GraceSceneSender(scene_sender))
+            .insert_resource(GraceSceneReceiver(scene_receiver))
+            .add_event::<Command>()
+            .insert_resource(StepsPerFrame(config.steps_per_frame))
+            .insert_resource(SimulationStepSeconds(config.simulation_step_seconds))
+            .add_plugins(RecordingPlugin)
+            .add_systems(Startup, load_configured_scene)
+            .add_systems(PreUpdate, (supervise_background_simulation, receive_voltage_snapshots).chain())
+            .add_systems(Update, release_scheduled_commands.before(apply_commands))
+            .add_systems(Update, apply_commands)
+            .add_systems(Update, handle_loaded_neuron)
+            .add_systems(Update, checkpoint_commands.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, warm_restart_commands.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, illuminate_segments.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, polarize_segments.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, track_extracellular_spaces.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, track_calcium_shells.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, clear_extracellular_potassium.after(step_biophysics).run_if(not(background_running)))
+            .add_systems(Update, diffuse_along_junctions.after(step_biophysics).run_if(not(background_running)))
+            .add_systems(Update, seed_stimulators.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, noise_commands.after(apply_commands).before(seed_noise))
+            .add_systems(Update, seed_noise.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, step_session.after(apply_commands).before(step_biophysics))
+            .add_systems(Update, step_biophysics.run_if(simulation_running).run_if(not(background_running)).after(apply_commands))
+            .add_systems(Update, notify.after(step_biophysics))
+            .add_systems(Update, compute_lfp.after(step_biophysics))
+            .add_systems(Update, (accept_remote_subscriptions, sample_probes).chain().after(compute_lfp));
+    }
+}
+
+/// Colors segments by membrane voltage and stimulators by their current,
+/// and accepts programmatic camera commands.
+#[cfg(feature = "render")]
+pub struct VisualizationPlugin;
+
+#[cfg(feature = "render")]
+impl Plugin for VisualizationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(CameraPlugin)
+            .add_plugins(VoltageMaterialPlugin)
             .init_resource::<StimulatorMaterials>()
+            .init_resource::<ScaleBar>()
+            .init_resource::<HeatMaterials>()
+            .init_resource::<LodSettings>()
             .insert_resource(StdoutRenderTimer {
                 timer: Timer::new(Duration::from_millis(2000), TimerMode::Repeating)
-            });
-
-            app.add_systems(Update, step_biophysics);
-
-            app
-            .add_systems(Update, apply_voltage_to_materials)
+            })
             .add_systems(Update, apply_current_to_stimulator_material)
+            .add_systems(Update, print_voltages)
+            .add_systems(Update, (spawn_heat_overlays, update_heat_overlays))
+            .add_systems(Update, (spawn_light_markers, update_light_markers))
+            .add_systems(Update, (spawn_probe_markers, despawn_probe_markers))
+            .add_systems(Update, (spawn_electrode_markers, update_electrode_markers))
+            .add_systems(Update, (spawn_stimulating_electrode_markers, update_stimulating_electrode_markers))
+            .add_systems(Update, (spawn_projector_screens, update_projector_screens))
+            .add_systems(Update, draw_scale_bar)
+            .add_systems(Update, choose_segment_meshes);
+    }
+}
 
-            .add_systems(Update, step_oscilloscope_system)
+/// The egui panels and windows, and the scene loader they drive.
+#[cfg(feature = "render")]
+pub struct GuiPlugin;
+
+#[cfg(feature = "render")]
+impl Plugin for GuiPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app.init_resource::<gui::NextClickAction>()
+            .init_resource::<Oscilloscope>()
+            .init_resource::<RunRegistry>()
+            .init_resource::<SessionLog>()
+            .init_resource::<VoltageClamp>()
+            .init_resource::<LayoutFile>()
+            .init_resource::<SpikeRaster>()
+            .init_resource::<Autosave>()
+            .init_resource::<SpikeTrainReplay>()
+            .init_resource::<ScaleBar>()
+            .init_resource::<SceneDiffView>()
+            .init_resource::<IntegrationRates>()
+            .init_resource::<StepAdvice>()
+            .init_resource::<SpeedTest>()
+            .init_resource::<WatchList>()
+            .init_resource::<MorphologyEditor>()
+            .init_resource::<ThermalSettings>()
+            .init_resource::<OptogeneticsEditor>()
+            .init_resource::<NoiseSettings>()
+            .init_resource::<MembraneEditor>()
+            .init_resource::<ChannelLibrary>()
+            .init_resource::<SynapseEditor>()
+            .init_resource::<BulkActions>()
+            .init_resource::<Shortcuts>()
+            .init_resource::<CommandPalette>()
+            .init_resource::<HoveredSegment>()
+            .init_resource::<Colorbar>()
+            .insert_resource(Stimulator::default())
+            .add_systems(Update, step_oscilloscope_system.after(sample_probes))
             // .add_systems(Update, print_oscilloscope_system)
+            .add_systems(Update, gui::run_gui)
+            .add_systems(Update, voltage_clamp_gui)
+            .add_systems(Update, recording_gui)
+            .add_systems(Update, layout_gui)
+            .add_systems(Update, (record_spikes, raster_gui))
+            .add_systems(Startup, load_autosave)
+            .add_systems(Update, (autosave_system, autosave_gui))
+            .add_systems(Update, spike_train_gui)
+            .add_systems(Update, scale_gui.after(draw_scale_bar))
+            .add_systems(Update, scene_diff_gui)
+            .add_systems(Update, integration_rates_gui)
+            .add_systems(Update, (advise_step, step_advice_gui).chain())
+            .add_systems(Update, (speed_test_gui, run_speed_test).chain())
+            .add_systems(Update, (sample_watches, watch_gui).chain().before(sample_probes))
+            .add_systems(Update, morphology_gui)
+            .add_systems(Update, thermal_gui)
+            .add_systems(Update, optogenetics_gui)
+            .add_systems(Update, noise_gui)
+            .add_systems(Update, rollback_gui)
+            .add_systems(Update, probes_gui)
+            .add_systems(Update, electrodes_gui)
+            .add_systems(Update, ions_gui)
+            .add_systems(Update, membrane_gui)
+            .add_systems(Update, channel_library_gui)
+            .add_systems(Update, (synapses_gui, draw_synapses))
+            .add_systems(Update, inspector_gui)
+            .add_systems(Update, bulk_gui)
+            .add_systems(Update, palette_gui)
+            .add_systems(Update, segment_tooltip)
+            .add_systems(Update, colorbar_gui)
+            .add_systems(Update, view_gui)
+            .add_systems(Update, (clipping_gui, draw_clip_plane))
+            .add_systems(Update, box_select.before(PanOrbitCameraSystemSet))
+            .add_systems(Update, replay_gui);
+        gui::load::setup(app);
+    }
+}
+
+/// Mouse picking of segments and stimulators.
+#[cfg(feature = "render")]
+pub struct PickingPlugin;
 
-            .add_systems(Update, print_voltages);
-            gui::load::setup(app);
+#[cfg(feature = "render")]
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(DefaultPickingPlugins.build().disable::<DebugPickingPlugin>());
     }
 }
 
+#[cfg(feature = "render")]
 #[derive(Resource)]
 pub struct StdoutRenderTimer {
     timer: Timer,
 }
 
 
+/// What the integration step reads besides the segments and synapses.
+#[derive(SystemParam)]
+pub struct StepConditions<'w> {
+    pub env: Res<'w, Env>,
+    pub constants: Res<'w, SimulationConstants>,
+    pub ion_dynamics: Res<'w, IonDynamics>,
+}
 
-fn step_biophysics(
-  env: Res<Env>,
-  simulation_step: Res<SimulationStepSeconds>,
-  mut timestamp: ResMut<Timestamp>,
-  steps_per_frame: Res<StepsPerFrame>,
-  mut segments_query: Query<
-          (&Segment,
-           &Solution,
-           &Geometry,
-           &mut Membrane,
-           &mut MembraneVoltage,
-           Option<&InputCurrent>,
-           Option<&Stimulator>
-          )>,
+/// The step size and the clocks the integration step advances.
+#[derive(SystemParam)]
+pub struct StepClock<'w> {
+    pub simulation_step: Res<'w, SimulationStepSeconds>,
+    pub timestamp: ResMut<'w, Timestamp>,
+    pub step_count: ResMut<'w, StepCount>,
+    pub steps_per_frame: Res<'w, StepsPerFrame>,
+    pub control: ResMut<'w, SimulationControl>,
+}
+
+/// The segments the integration step advances, with what drives them.
+pub type StepSegments<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Segment,
+        &'static mut Solution,
+        &'static Geometry,
+        &'static mut Membrane,
+        &'static mut MembraneVoltage,
+        Option<&'static InputCurrent>,
+        Option<&'static Stimulator>,
+        Option<&'static SpikeTrainInput>,
+        Option<&'static mut IntegrationRate>,
+        Option<&'static ThermalStimulator>,
+        Option<&'static Illumination>,
+        Option<&'static mut MembraneNoise>,
+        Option<&'static mut ExtracellularSpace>,
+        Option<&'static mut CalciumShell>,
+    ),
+>;
+
+/// Integrate the frame's steps, up to `StepsPerFrame` as paced by
+/// `SimulationControl`, in one run of the system, so that the step count
+/// can be tuned while running.
+pub(crate) fn step_biophysics(
+  conditions: StepConditions,
+  clock: StepClock,
+  mut segments_query: StepSegments,
   junctions_query: Query<&Junction>,
-  mut synapses_query: Query<&mut Synapse>
+  fields_query: Query<&ExtracellularField>,
+  mut synapses_query: Query<(&mut Synapse, Option<&SpikeTrainInput>)>
 ){
-    for _ in 0..steps_per_frame.0 {
-    for (_,
-         solution,
+    let StepConditions { env, constants, ion_dynamics } = conditions;
+    let StepClock { simulation_step, mut timestamp, mut step_count, steps_per_frame, mut control } = clock;
+    for _ in 0..control.take_steps(steps_per_frame.0) {
+    let step = step_count.0;
+    let now = timestamp.clone();
+    // Segments are independent until the junctions couple them, so they
+    // step in parallel; see `threads::par_for_each`. On the web's main
+    // thread this is an ordinary loop.
+    threads::par_for_each(&mut segments_query, |(_,
+         mut solution,
          geometry,
          mut membrane,
          mut membrane_voltage,
          maybe_input_current,
-         maybe_stimulator
-        ) in &mut segments_query {
+         maybe_stimulator,
+         maybe_spike_train,
+         mut maybe_rate,
+         maybe_thermal,
+         maybe_illumination,
+         maybe_noise,
+         mut maybe_space,
+         mut maybe_shell,
+        )| {
+
+        // Slow segments take one long step every few simulation steps.
+        let interval = match maybe_rate.as_deref_mut() {
+            None => simulation_step.0,
+            Some(rate) if !rate.is_due(step) => return,
+            Some(rate) => {
+                rate.begin_step(&mut membrane_voltage.0.0);
+                rate.interval(simulation_step.0)
+            },
+        };
 
         // ***********************************
         // ***** Apply channel currents. *****
         // ***********************************
         let surface_area = geometry.surface_area();
 
+        let extracellular = local_solution(&env, maybe_space.as_deref());
+        let reversals = [
+            constants.k_reversal(&solution, extracellular),
+            constants.na_reversal(&solution, extracellular),
+            constants.cl_reversal(&solution, extracellular),
+            constants.ca_reversal(&solution, extracellular),
+        ];
+        let [k_reversal, na_reversal, cl_reversal, ca_reversal] = &reversals;
         let current = -1.0 * membrane.current_per_square_cm(
-                &k_reversal(
-                    &solution,
-                    &env.extracellular_solution,
-                    &env.temperature,
-                ),
-                &na_reversal(
-                    &solution,
-                    &env.extracellular_solution,
-                    &env.temperature,
-                ),
-                &cl_reversal(
-                    &solution,
-                    &env.extracellular_solution,
-                    &env.temperature,
-                ),
-                &ca_reversal(
-                    &solution,
-                    &env.extracellular_solution,
-                    &env.temperature,
-                ),
+                k_reversal,
+                na_reversal,
+                cl_reversal,
+                ca_reversal,
                 &membrane_voltage.0,
         ) * surface_area;
         let capacitance = membrane.capacitance.0 * surface_area;
         let dv_dt : f32 = current / capacitance;
 
-        membrane_voltage.0.0 += 1000.0 * dv_dt * simulation_step.0;
+        // *****************************************
+        // ***** Move ions across the membrane. ****
+        // *****************************************
+        if ion_dynamics.intracellular || env.potassium.is_some() || maybe_shell.is_some() {
+            let ion_currents = membrane.ion_currents_per_square_cm(
+                k_reversal,
+                na_reversal,
+                cl_reversal,
+                ca_reversal,
+                &membrane_voltage.0,
+            );
+            if ion_dynamics.intracellular {
+                update_intracellular(&mut solution, &ion_currents, geometry, interval, constants.faraday);
+            }
+            // K+ leaving the segment accumulates around it.
+            if let (Some(space), Some(potassium)) = (maybe_space.as_deref_mut(), env.potassium.as_ref()) {
+                let k = &mut space.0.k_concentration.0;
+                *k = (*k + potassium.efflux_rate(ion_currents.k, constants.faraday) * interval).max(0.0);
+            }
+            // Ca2+ entering lands in the shell beneath the membrane.
+            if let (Some(shell), Some(buffering)) = (maybe_shell.as_deref_mut(), ion_dynamics.calcium.as_ref()) {
+                shell.0.0 = buffering.step(shell.0.0, ion_currents.ca, interval, constants.faraday);
+            }
+        }
+
+        membrane_voltage.0.0 += 1000.0 * dv_dt * interval;
 
         // ***********************************
         // ***** Update membrane conductances.
         // ***********************************
+        illuminate(&mut membrane, maybe_illumination, &now);
+        sense_calcium(&mut membrane, maybe_shell.as_deref().map_or(&solution.ca_concentration, |shell| &shell.0));
+        // Heat speeds gating up, as if more time had passed.
+        let gating_interval = maybe_thermal.map_or(interval, |thermal| interval * thermal.kinetics_factor(&now));
         membrane
             .membrane_channels
             .iter_mut()
             .for_each(|membrane_channel| {
-            membrane_channel.channel.step(&membrane_voltage.0, &Interval(simulation_step.0))
+            membrane_channel.channel.step(&membrane_voltage.0, &Interval(gating_interval))
             });
 
         // ***************************************************
@@ -135,55 +572,86 @@ fn step_biophysics(
         // ***************************************************
         let input_current = maybe_input_current.map_or(0.0, |i| i.0.0);
         let stimulator_current = maybe_stimulator.map_or(0.0, |stimulator|
-                                    stimulator.current(timestamp.clone()
+                                    stimulator.current(now.clone()
                                     ).0);
-        let current_microamps = input_current + stimulator_current;
+        let spike_train_current = maybe_spike_train.map_or(0.0, |train| train.current(now.0).0);
+        // Stepping the noise is not an edit, for `seed_noise` to react to.
+        let noise_current = maybe_noise.map_or(0.0, |mut noise| noise.bypass_change_detection().step(step, interval));
+        let current_microamps = input_current + stimulator_current + spike_train_current + noise_current;
         let capacitance = membrane.capacitance.0 * surface_area;
         let current = current_microamps * 1e-6 * surface_area;
         let dv_dt = current / capacitance;
-        membrane_voltage.0.0 += 1000.0 * dv_dt * simulation_step.0;
+        membrane_voltage.0.0 += 1000.0 * dv_dt * interval;
 
 
-    }
+    });
 
-    for Junction {first_segment, second_segment, pore_diameter} in &junctions_query {
+    for Junction {first_segment, second_segment, ..} in &junctions_query {
         let interval_seconds = simulation_step.0;
 
         let results = segments_query.get_many_mut([first_segment.clone(), second_segment.clone()]);
         match results {
-            Ok([(_,_,geom1,membrane1, mut vm1,_,_), (_,_,geom2, membrane2, mut vm2,_,_)]) => {
+            Ok([(_,_,geom1,membrane1, mut vm1,_,_,_, mut rate1,_,_,_,_,_), (_,_,geom2, membrane2, mut vm2,_,_,_, mut rate2,_,_,_,_,_)]) => {
                 let capacitance1 = membrane1.capacitance.0 * geom1.surface_area();
                 let capacitance2 = membrane2.capacitance.0 * geom2.surface_area();
 
-                let mutual_conductance = pore_diameter.0 * std::f32::consts::PI * CONDUCTANCE_PER_SQUARE_CM;
-                let first_to_second_current = mutual_conductance * (vm1.0.0 - vm2.0.0) * 1e-3;
-
-                vm1.0.0 -= first_to_second_current / capacitance1 * interval_seconds;
-                vm2.0.0 += first_to_second_current / capacitance2 * interval_seconds;
+                let conductance = axial_conductance(geom1, geom2, constants.axial_resistivity_ohm_cm);
+                let v1 = seen_voltage(vm1.0.0, rate1.as_deref(), step);
+                let v2 = seen_voltage(vm2.0.0, rate2.as_deref(), step);
+                // Axial current flows between intracellular potentials,
+                // which an extracellular field shifts.
+                let ve1 = extracellular_potential(fields_query.get(*first_segment).ok(), &now);
+                let ve2 = extracellular_potential(fields_query.get(*second_segment).ok(), &now);
+                let charge = junction_charge(v1 + ve1 - v2 - ve2, conductance, capacitance1, capacitance2, interval_seconds);
+
+                couple(&mut vm1.0.0, rate1.as_deref_mut(), -charge / capacitance1);
+                couple(&mut vm2.0.0, rate2.as_deref_mut(), charge / capacitance2);
             },
             Err(e) => panic!("Other error {e}"),
 
         }
     }
 
-    for mut synapse in &mut synapses_query {
+    for (mut synapse, maybe_spike_train) in &mut synapses_query {
         // TODO: This fails if the source and target of the synapse are the same Entity.
         let interval_seconds = simulation_step.0;
         let results = segments_query.get_many_mut([synapse.pre_segment.clone(), synapse.post_segment.clone()]);
         match results {
-            Ok([(_,_,_,_,vm1,_,_), (_,solution,_,_,mut vm2,_,_)]) => {
+            Ok([(_,_,_,_,vm1,_,_,_,rate1,_,_,_,_,_), (_,solution,geom2,_,mut vm2,_,_,_,mut rate2,_,_,_,_,mut shell2)]) => {
+                // A replayed spike train stands in for the presynaptic voltage.
+                let presynaptic_potential = maybe_spike_train
+                    .and_then(|train| train.presynaptic_potential(timestamp.0))
+                    .unwrap_or(MilliVolts(seen_voltage(vm1.0.0, rate1.as_deref(), step)));
+                let mut postsynaptic_potential = MilliVolts(seen_voltage(vm2.0.0, rate2.as_deref(), step));
                 synapse.synapse_membranes.step(
-                    &BODY_TEMPERATURE,
-                    &vm1.0,
-                    &vm2.0,
+                    &constants.temperature,
+                    &presynaptic_potential,
+                    &postsynaptic_potential,
                     &Interval(interval_seconds)
                 );
+                // Calcium entering through the receptors loads the
+                // postsynaptic shell, and moves the synapse's weight.
+                let calcium = match (shell2.as_deref_mut(), ion_dynamics.calcium.as_ref()) {
+                    (Some(shell), Some(buffering)) => {
+                        let ca_current = synapse.synapse_membranes
+                            .calcium_current(&constants.temperature, &postsynaptic_potential, &solution).0
+                            * 1e-6 / geom2.surface_area();
+                        shell.0.0 = buffering.load(shell.0.0, ca_current, interval_seconds, constants.faraday);
+                        shell.0.clone()
+                    },
+                    _ => solution.ca_concentration.clone(),
+                };
+                if let Some(plasticity) = synapse.synapse_membranes.plasticity.as_mut() {
+                    plasticity.step(&calcium, &Interval(interval_seconds));
+                }
+                let before = postsynaptic_potential.0;
                 synapse.synapse_membranes.apply_current(
                     &Interval(interval_seconds),
-                    &BODY_TEMPERATURE,
-                    &mut vm2.0,
+                    &constants.temperature,
+                    &mut postsynaptic_potential,
                     &solution
                 );
+                couple(&mut vm2.0.0, rate2.as_deref_mut(), postsynaptic_potential.0 - before);
             }
             Err(e) => {
                 eprintln!("Synapse query error: {e}");
@@ -195,11 +663,13 @@ fn step_biophysics(
     // ***** Advance simulation time. *******
     // ***************************************
     timestamp.0 += simulation_step.0;
+    step_count.0 += 1;
 
 
     }
 }
 
+#[cfg(feature = "render")]
 #[derive(Bundle)]
 pub struct SegmentBundle {
     pub intracellular_solution: Solution,
@@ -219,28 +689,24 @@ impl Display for MembraneVoltage {
 #[derive(Component)]
 pub struct Neuron;
 
-#[derive(Resource)]
+#[derive(Clone, Resource)]
 pub struct Env {
-    pub temperature: Kelvin,
+    /// The bath, and the extracellular solution of every segment unless
+    /// potassium accumulates.
     pub extracellular_solution: Solution,
+    /// Whether, and how, K+ accumulates around each segment; see
+    /// `neuron::extracellular_space`.
+    pub potassium: Option<PotassiumAccumulation>,
 }
 
 fn default_env() -> Env {
     Env {
-        temperature: BODY_TEMPERATURE,
         extracellular_solution: INTERSTICIAL_FLUID,
+        potassium: None,
     }
 }
 
-fn apply_voltage_to_materials(
-    membrane_materials: Res<MembraneMaterials>,
-    mut query: Query<(&MembraneVoltage, &mut Handle<StandardMaterial>)>
-) {
-    for (v, mut material) in &mut query {
-        *material = membrane_materials.from_voltage(&v.0);
-    }
-}
-
+#[cfg(feature = "render")]
 fn apply_current_to_stimulator_material(
     stimulator_materials: Res<StimulatorMaterials>,
     segments_query: Query<(&Segment, &Stimulator)>,
@@ -257,6 +723,7 @@ fn apply_current_to_stimulator_material(
     }
 }
 
+#[cfg(feature = "render")]
 fn print_voltages(
     timestamp: Res<Timestamp>,
     mut stdout_render_timer: ResMut<StdoutRenderTimer>,
@@ -281,14 +748,129 @@ fn print_voltages(
 
 
 
-// pub fn serialize_simulation (
-//     extracellular_solution: &Solution,
-//     segments: &[(Membrane, MembraneVoltage, Stimulator)]
-// ) -> serialize::Scene {
-//     serialize::Scene {
-//         extracellular_solution: extracellular_solution.serialize(),
-//         membranes: unimplemented!(),
-//         neurons: unimplemented!(),
-//         synapses: vec![],
-//     }
-// }
+/// The loaded scene as it is now: its morphology with the current
+/// membranes, stimulators and synapses of the spawned entities, and the
+/// current constants. Membranes are stored per SWC type; see
+/// `grace::type_membranes` for how segments edited since loading are kept.
+pub fn serialize_simulation<'a>(
+    loaded: &serialize::Scene,
+    constants: &SimulationConstants,
+    segments: impl Iterator<Item = (Entity, &'a SceneSegment, &'a Membrane, Option<&'a Stimulator>, Option<&'a ThermalStimulator>, Option<&'a MembraneNoise>)>,
+    synapses: impl Iterator<Item = &'a Synapse>,
+    lights: impl Iterator<Item = &'a LightSource>,
+    projectors: impl Iterator<Item = &'a Projector>,
+) -> serialize::Scene {
+    let mut neurons = loaded.neurons.clone();
+    // For each neuron, each SWC id's index in its segment list and its type.
+    let positions = neurons
+        .iter()
+        .map(|n| n.neuron.segments.iter().enumerate().map(|(i, s)| (s.id, (i, s.type_))).collect::<HashMap<_, _>>())
+        .collect::<Vec<_>>();
+    for scene_neuron in neurons.iter_mut() {
+        scene_neuron.stimulator_segments.clear();
+        scene_neuron.thermal_segments.clear();
+        scene_neuron.noise = None;
+        scene_neuron.membrane_overrides.clear();
+    }
+
+    let mut entity_positions = HashMap::new();
+    // For each neuron and SWC type, its segments' membranes.
+    let mut membranes: HashMap<(usize, usize), Vec<(i32, serialize::Membrane)>> = HashMap::new();
+    for (entity, SceneSegment(at), membrane, stimulator, thermal, noise) in segments {
+        let (Some(scene_neuron), Some((index, type_))) =
+            (neurons.get_mut(at.neuron), positions.get(at.neuron).and_then(|p| p.get(&at.segment)))
+        else {
+            continue;
+        };
+        entity_positions.insert(entity, (at.neuron, *index));
+        membranes.entry((at.neuron, *type_)).or_default().push((at.segment, membrane.serialize()));
+        if let Some(stimulator) = stimulator {
+            scene_neuron.stimulator_segments.push(serialize::StimulatorSegment {
+                stimulator: stimulator.serialize(),
+                segment: at.segment as u32,
+            });
+        }
+        if let Some(thermal) = thermal {
+            scene_neuron.thermal_segments.push(thermal.serialize(at.segment as u32));
+        }
+        if let Some(noise) = noise {
+            scene_neuron.noise = Some(noise.spec.clone());
+        }
+    }
+    for ((neuron, type_), segments) in membranes {
+        let scene_neuron = &mut neurons[neuron];
+        let Some(t) = type_.checked_sub(1).filter(|t| *t < scene_neuron.neuron.membranes.len()) else {
+            continue;
+        };
+        let (type_membrane, overrides) =
+            grace::type_membranes(&scene_neuron.neuron, &scene_neuron.neuron.membranes[t], segments);
+        scene_neuron.neuron.membranes[t] = type_membrane;
+        scene_neuron.membrane_overrides.extend(overrides);
+    }
+    for scene_neuron in neurons.iter_mut() {
+        scene_neuron.stimulator_segments.sort_by_key(|s| s.segment);
+        scene_neuron.thermal_segments.sort_by_key(|s| s.segment);
+        scene_neuron.membrane_overrides.sort_by_key(|o| o.segment);
+    }
+
+    let synapses = synapses
+        .filter_map(|synapse| {
+            let (pre_neuron, pre_segment) = *entity_positions.get(&synapse.pre_segment)?;
+            let (post_neuron, post_segment) = *entity_positions.get(&synapse.post_segment)?;
+            Some(serialize::Synapse {
+                pre_neuron,
+                pre_segment,
+                post_neuron,
+                post_segment,
+                synapse_membranes: synapse.synapse_membranes.serialize(),
+            })
+        })
+        .collect();
+
+    serialize::Scene {
+        neurons,
+        synapses,
+        constants: Some(constants.serialize()),
+        lights: lights.map(LightSource::serialize).collect(),
+        projectors: projectors.map(Projector::serialize).collect(),
+        seed: loaded.seed,
+    }
+}
+
+/// The segment components a saved scene keeps.
+pub type SavedSegments<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static SceneSegment,
+        &'static Membrane,
+        Option<&'static Stimulator>,
+        Option<&'static ThermalStimulator>,
+        Option<&'static MembraneNoise>,
+    ),
+>;
+
+/// What `serialize_simulation` reads from the world, for systems that
+/// save the scene.
+#[derive(SystemParam)]
+pub struct SimulationState<'w, 's> {
+    pub current_scene: Res<'w, CurrentScene>,
+    pub segments: SavedSegments<'w, 's>,
+    pub synapses: Query<'w, 's, &'static Synapse>,
+    pub lights: Query<'w, 's, &'static LightSource>,
+    pub projectors: Query<'w, 's, &'static Projector>,
+}
+
+impl<'w, 's> SimulationState<'w, 's> {
+    pub fn serialize(&self, constants: &SimulationConstants) -> serialize::Scene {
+        serialize_simulation(
+            &self.current_scene.0,
+            constants,
+            self.segments.iter(),
+            self.synapses.iter(),
+            self.lights.iter(),
+            self.projectors.iter(),
+        )
+    }
+}
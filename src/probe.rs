@@ -0,0 +1,596 @@
+//! What probes read at a segment (`ProbeSource`), and every active probe
+//! in one place: segments with a `Probe`, the oscilloscope's sources,
+//! segments marked `Recorded`, CSV `Recorder`s of voltage, extracellular
+//! electrodes, and watch expressions.
+//!
+//! `sample_probes` refreshes the `ProbeRegistry` once per simulated frame
+//! and delivers a `ProbeFrame` of the samples of each subscriber's probes,
+//! so panels, exporters and streamers read one data path instead of each
+//! querying `MembraneVoltage`: the oscilloscope, continuous recordings and
+//! the gRPC voltage stream all subscribe. Subscribers hold the receiving
+//! end of a channel, which may live on another thread; dropping it
+//! unsubscribes. Threads without access to the app subscribe with
+//! `subscribe_remotely`.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use crossbeam::channel::{Receiver, Sender};
+use once_cell::sync::Lazy;
+
+use crate::analysis::Trace;
+use crate::command::SimulationControl;
+use crate::dimension::{StepCount, Timestamp};
+#[cfg(feature = "render")]
+use crate::gui::oscilloscope::Oscilloscope;
+#[cfg(feature = "render")]
+use crate::gui::watch::WatchList;
+use crate::integrations::grace::{SceneSegment, Synapse};
+use crate::lfp::ExtracellularElectrode;
+use crate::neuron::calcium::CalciumShell;
+use crate::neuron::extracellular_space::ExtracellularSpace;
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::synapse::{SynapseMembranes, Transmitter};
+use crate::recording::csv_recorder::{Quantity, Recorder};
+use crate::recording::Recorded;
+use crate::serialize::{self, SegmentRef};
+use crate::stimulator::Stimulator;
+
+/// A quantity read at a segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProbeSource {
+    /// Membrane voltage (mV).
+    Voltage,
+    /// Stimulator current density (uA/cm^2), zero without a stimulator.
+    StimulatorCurrent,
+    /// The open conductance (mS/cm^2) of the membrane channel at this
+    /// index.
+    Conductance(usize),
+    /// The cleft concentration (mM) of the first synapse onto the segment.
+    Transmitter(Transmitter),
+    /// The local field potential (uV) at an `ExtracellularElectrode`, whose
+    /// entity stands in for the segment.
+    Lfp,
+    /// The K+ concentration (mM) around the segment, while potassium
+    /// accumulates.
+    ExtracellularK,
+    /// The free Ca2+ (uM) beneath the segment's membrane, while calcium is
+    /// buffered.
+    ShellCalcium,
+}
+
+/// The components of a segment that probes read.
+pub type ProbedSegments<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static MembraneVoltage,
+        &'static Membrane,
+        Option<&'static Stimulator>,
+        Option<&'static ExtracellularSpace>,
+        Option<&'static CalciumShell>,
+    ),
+>;
+
+impl ProbeSource {
+    pub fn name(&self) -> String {
+        match self {
+            ProbeSource::Voltage => "Vm".to_string(),
+            ProbeSource::StimulatorCurrent => "I_stim".to_string(),
+            ProbeSource::Conductance(channel) => format!("g{}", channel),
+            ProbeSource::Transmitter(transmitter) => transmitter.to_string(),
+            ProbeSource::Lfp => "LFP".to_string(),
+            ProbeSource::ExtracellularK => "[K+]o".to_string(),
+            ProbeSource::ShellCalcium => "[Ca2+]".to_string(),
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            ProbeSource::Voltage => "mV",
+            ProbeSource::StimulatorCurrent => "uA/cm^2",
+            ProbeSource::Conductance(_) => "mS/cm^2",
+            ProbeSource::Transmitter(_) => "mM",
+            ProbeSource::Lfp => "uV",
+            ProbeSource::ExtracellularK => "mM",
+            ProbeSource::ShellCalcium => "uM",
+        }
+    }
+
+    /// The value at `segment`, or `None` if it is not a segment or lacks
+    /// the channel or synapse. `Lfp` reads an electrode instead.
+    pub fn read(
+        &self,
+        t: &Timestamp,
+        segment: Entity,
+        segments: &ProbedSegments,
+        synapses: &Query<&Synapse>,
+        electrodes: &Query<&ExtracellularElectrode>,
+    ) -> Option<f32> {
+        if let ProbeSource::Lfp = self {
+            return electrodes.get(segment).ok().map(|electrode| electrode.potential_uv);
+        }
+        let (voltage, membrane, stimulator, space, shell) = segments.get(segment).ok()?;
+        let synapse = || synapses.iter().find(|s| s.post_segment == segment).map(|s| &s.synapse_membranes);
+        match self {
+            ProbeSource::Transmitter(_) => self.value(t, voltage, membrane, stimulator, synapse()),
+            ProbeSource::ExtracellularK => space.map(|space| space.0.k_concentration.0 * 1000.0),
+            ProbeSource::ShellCalcium => shell.map(|shell| shell.0.0 * 1e6),
+            _ => self.value(t, voltage, membrane, stimulator, None),
+        }
+    }
+
+    /// The value given a segment's components and the synapse onto it.
+    pub fn value(
+        &self,
+        t: &Timestamp,
+        voltage: &MembraneVoltage,
+        membrane: &Membrane,
+        stimulator: Option<&Stimulator>,
+        synapse: Option<&SynapseMembranes>,
+    ) -> Option<f32> {
+        match self {
+            ProbeSource::Voltage => Some(voltage.0.0),
+            ProbeSource::StimulatorCurrent => Some(stimulator.map_or(0.0, |s| s.current(t.clone()).0)),
+            ProbeSource::Conductance(channel) => {
+                let membrane_channel = membrane.membrane_channels.get(*channel)?;
+                Some(membrane_channel.siemens_per_square_cm * membrane_channel.channel.conductance_coefficient() * 1000.0)
+            },
+            ProbeSource::Transmitter(transmitter) => {
+                let concentrations = &synapse?.transmitter_concentrations;
+                let molar = match transmitter {
+                    Transmitter::Glutamate => concentrations.glutamate.0,
+                    Transmitter::Gaba => concentrations.gaba.0,
+                };
+                Some(molar * 1000.0)
+            },
+            ProbeSource::Lfp | ProbeSource::ExtracellularK | ProbeSource::ShellCalcium => None,
+        }
+    }
+
+    pub fn serialize(&self) -> serialize::ProbedQuantity {
+        match self {
+            ProbeSource::Voltage => serialize::ProbedQuantity::Voltage,
+            ProbeSource::StimulatorCurrent => serialize::ProbedQuantity::StimulatorCurrent,
+            ProbeSource::Conductance(channel) => serialize::ProbedQuantity::Conductance { channel: *channel },
+            ProbeSource::Transmitter(Transmitter::Glutamate) => serialize::ProbedQuantity::Glutamate,
+            ProbeSource::Transmitter(Transmitter::Gaba) => serialize::ProbedQuantity::Gaba,
+            ProbeSource::Lfp => serialize::ProbedQuantity::Lfp,
+            ProbeSource::ExtracellularK => serialize::ProbedQuantity::ExtracellularK,
+            ProbeSource::ShellCalcium => serialize::ProbedQuantity::ShellCalcium,
+        }
+    }
+
+    pub fn deserialize(quantity: &serialize::ProbedQuantity) -> Self {
+        match quantity {
+            serialize::ProbedQuantity::Voltage => ProbeSource::Voltage,
+            serialize::ProbedQuantity::StimulatorCurrent => ProbeSource::StimulatorCurrent,
+            serialize::ProbedQuantity::Conductance { channel } => ProbeSource::Conductance(*channel),
+            serialize::ProbedQuantity::Glutamate => ProbeSource::Transmitter(Transmitter::Glutamate),
+            serialize::ProbedQuantity::Gaba => ProbeSource::Transmitter(Transmitter::Gaba),
+            serialize::ProbedQuantity::Lfp => ProbeSource::Lfp,
+            serialize::ProbedQuantity::ExtracellularK => ProbeSource::ExtracellularK,
+            serialize::ProbedQuantity::ShellCalcium => ProbeSource::ShellCalcium,
+        }
+    }
+}
+
+/// Samples each `Probe` keeps for analysis.
+pub const PROBE_HISTORY_LEN: usize = 2000;
+
+/// A probe placed on a segment by clicking it, apart from any stimulator
+/// there. It is listed in the registry, drawn with a marker, and keeps its
+/// recent samples for analysis.
+#[derive(Clone, Component, Debug)]
+pub struct Probe {
+    pub label: String,
+    pub quantity: ProbeSource,
+    /// `(t, value)` pairs, oldest first; times in seconds.
+    pub history: VecDeque<(f32, f32)>,
+}
+
+impl Probe {
+    pub fn new(label: String) -> Self {
+        Probe { label, quantity: ProbeSource::Voltage, history: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, t: f32, value: f32) {
+        if self.history.back().is_some_and(|(last, _)| t < *last) {
+            self.history.clear();
+        }
+        self.history.push_back((t, value));
+        if self.history.len() > PROBE_HISTORY_LEN {
+            self.history.pop_front();
+        }
+    }
+
+    /// The recent samples, for `crate::analysis`.
+    pub fn trace(&self) -> Trace {
+        let mut trace = Trace::new(self.label.clone());
+        for (t, value) in &self.history {
+            trace.push(*t, *value);
+        }
+        trace
+    }
+}
+
+/// The marker drawn at a probed segment.
+#[cfg(feature = "render")]
+#[derive(Component)]
+pub struct ProbeMarker {
+    pub segment: Entity,
+}
+
+/// What a probe is, which stays the same from frame to frame.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProbeKey {
+    /// A segment with a `Probe`.
+    Probe(Entity),
+    /// An oscilloscope source, numbered from 1 as in the oscilloscope.
+    Oscilloscope(usize),
+    /// The stimulator current at an oscilloscope source, which the
+    /// oscilloscope draws beneath it.
+    OscilloscopeCurrent(usize),
+    /// A segment marked `Recorded`.
+    Recorded(Entity),
+    /// A segment whose `Recorder` records its voltage.
+    Recorder(Entity),
+    /// An `ExtracellularElectrode`.
+    Electrode(Entity),
+    /// A watch expression, by name.
+    Watch(String),
+    /// A segment's voltage, sampled while a subscriber asks for it.
+    Segment(SegmentRef),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeEntry {
+    pub key: ProbeKey,
+    pub label: String,
+    /// The segment sampled, if the probe samples one.
+    pub segment: Option<Entity>,
+    pub unit: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeSample {
+    pub key: ProbeKey,
+    /// Simulation time (s).
+    pub t: f32,
+    pub value: f32,
+}
+
+/// One sampling of the probes a subscriber asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeFrame {
+    /// Simulation time (s).
+    pub t: f32,
+    pub step: u64,
+    pub paused: bool,
+    pub samples: Vec<ProbeSample>,
+}
+
+/// The probes a subscriber receives.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Subscription {
+    /// Every active probe, except segments sampled for other subscribers.
+    All,
+    /// These probes, whenever they are active. `ProbeKey::Segment`s are
+    /// sampled for as long as the subscriber is.
+    Probes(Vec<ProbeKey>),
+    /// The voltage of every segment.
+    EverySegment,
+}
+
+impl Subscription {
+    fn wants(&self, key: &ProbeKey) -> bool {
+        match self {
+            Subscription::All => !matches!(key, ProbeKey::Segment(_)),
+            Subscription::Probes(keys) => keys.contains(key),
+            Subscription::EverySegment => matches!(key, ProbeKey::Segment(_)),
+        }
+    }
+}
+
+struct Subscriber {
+    subscription: Subscription,
+    sender: Sender<ProbeFrame>,
+}
+
+/// Subscriptions from threads without access to the app, until
+/// `accept_remote_subscriptions` registers them.
+static REMOTE_SUBSCRIBERS: Lazy<(Sender<Subscriber>, Receiver<Subscriber>)> = Lazy::new(crossbeam::channel::unbounded);
+
+/// Subscribe from any thread. Frames arrive from the first one sampled
+/// once the app has registered the subscription, at most a frame later.
+pub fn subscribe_remotely(subscription: Subscription) -> Receiver<ProbeFrame> {
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    // The receiving end is static, so this cannot fail.
+    let _ = REMOTE_SUBSCRIBERS.0.send(Subscriber { subscription, sender });
+    receiver
+}
+
+#[derive(Default, Resource)]
+pub struct ProbeRegistry {
+    probes: Vec<ProbeEntry>,
+    latest: HashMap<ProbeKey, ProbeSample>,
+    subscribers: Vec<Subscriber>,
+    /// Simulation time of the last sample (s).
+    last_sampled: Option<f32>,
+}
+
+impl ProbeRegistry {
+    /// The active probes, in the order their samples are delivered.
+    pub fn probes(&self) -> &[ProbeEntry] {
+        &self.probes
+    }
+
+    /// The most recent sample of a probe.
+    pub fn latest(&self, key: &ProbeKey) -> Option<&ProbeSample> {
+        self.latest.get(key)
+    }
+
+    /// Receive a frame of the subscribed probes' samples each time they
+    /// are sampled, including probes that become active after
+    /// subscribing.
+    pub fn subscribe(&mut self, subscription: Subscription) -> Receiver<ProbeFrame> {
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        self.subscribers.push(Subscriber { subscription, sender });
+        receiver
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// The segments whose voltage subscribers asked for, or `None` for
+    /// every segment.
+    fn subscribed_segments(&self) -> Option<HashSet<SegmentRef>> {
+        let mut segments = HashSet::new();
+        for subscriber in &self.subscribers {
+            match &subscriber.subscription {
+                Subscription::EverySegment => return None,
+                Subscription::Probes(keys) => segments.extend(keys.iter().filter_map(|key| match key {
+                    ProbeKey::Segment(at) => Some(*at),
+                    _ => None,
+                })),
+                Subscription::All => {},
+            }
+        }
+        Some(segments)
+    }
+
+    /// Replace the active probes and deliver their samples, forgetting
+    /// subscribers that have dropped their receivers. Subscribers to none
+    /// of the probes get no frame.
+    pub fn publish(&mut self, probes: Vec<ProbeEntry>, frame: ProbeFrame) {
+        self.subscribers.retain(|subscriber| {
+            let samples = frame.samples.iter().filter(|sample| subscriber.subscription.wants(&sample.key)).cloned().collect::<Vec<_>>();
+            samples.is_empty() || subscriber.sender.send(ProbeFrame { samples, ..frame.clone() }).is_ok()
+        });
+        self.probes = probes;
+        self.latest = frame.samples.into_iter().map(|sample| (sample.key.clone(), sample)).collect();
+    }
+}
+
+/// Register the subscriptions made with `subscribe_remotely`.
+pub fn accept_remote_subscriptions(mut registry: ResMut<ProbeRegistry>) {
+    registry.subscribers.extend(REMOTE_SUBSCRIBERS.1.try_iter());
+}
+
+/// The time `sample_probes` stamps frames with.
+#[derive(SystemParam)]
+pub struct ProbeClock<'w> {
+    pub timestamp: Res<'w, Timestamp>,
+    pub step_count: Res<'w, StepCount>,
+    pub control: Res<'w, SimulationControl>,
+}
+
+/// What probes are placed on, besides the oscilloscope and watches.
+#[derive(SystemParam)]
+pub struct ProbeTargets<'w, 's> {
+    pub placed: Query<'w, 's, (Entity, &'static mut Probe)>,
+    pub recorded: Query<'w, 's, (Entity, &'static Recorded)>,
+    pub recorders: Query<'w, 's, (Entity, &'static Recorder), With<MembraneVoltage>>,
+    pub electrodes: Query<'w, 's, Entity, With<ExtracellularElectrode>>,
+    pub scene_segments: Query<'w, 's, (Entity, &'static SceneSegment)>,
+}
+
+/// What probes read.
+#[derive(SystemParam)]
+pub struct ProbeReads<'w, 's> {
+    pub segments: ProbedSegments<'w, 's>,
+    pub synapses: Query<'w, 's, &'static Synapse>,
+    pub electrodes: Query<'w, 's, &'static ExtracellularElectrode>,
+}
+
+/// Enumerate the probes and publish their samples, once per simulated
+/// frame. Runs after watches are sampled.
+pub fn sample_probes(
+    clock: ProbeClock,
+    mut registry: ResMut<ProbeRegistry>,
+    #[cfg(feature = "render")]
+    oscilloscope: Option<Res<Oscilloscope>>,
+    #[cfg(feature = "render")]
+    watch_list: Option<Res<WatchList>>,
+    mut targets: ProbeTargets,
+    reads: ProbeReads,
+) {
+    let timestamp = &*clock.timestamp;
+    if registry.last_sampled == Some(timestamp.0) {
+        return;
+    }
+    registry.last_sampled = Some(timestamp.0);
+
+    let mut probes = vec![];
+    let mut samples = vec![];
+    let mut segment_probe = |key: ProbeKey, label: String, segment: Entity, source: ProbeSource| {
+        let value = source.read(timestamp, segment, &reads.segments, &reads.synapses, &reads.electrodes);
+        if let Some(value) = value {
+            samples.push(ProbeSample { key: key.clone(), t: timestamp.0, value });
+        }
+        // An electrode is not a segment.
+        let segment = (source != ProbeSource::Lfp).then_some(segment);
+        probes.push(ProbeEntry { key, label, segment, unit: source.unit() });
+        value
+    };
+    for (segment, mut probe) in &mut targets.placed {
+        if let Some(value) = segment_probe(ProbeKey::Probe(segment), probe.label.clone(), segment, probe.quantity) {
+            probe.push(timestamp.0, value);
+        }
+    }
+    #[cfg(feature = "render")]
+    if let Some(oscilloscope) = &oscilloscope {
+        for (i, channel) in oscilloscope.channels.iter().enumerate() {
+            if let Some(segment) = channel.source {
+                segment_probe(ProbeKey::Oscilloscope(i + 1), format!("probe{}", i + 1), segment, channel.quantity);
+                let label = format!("probe{} {}", i + 1, ProbeSource::StimulatorCurrent.name());
+                segment_probe(ProbeKey::OscilloscopeCurrent(i + 1), label, segment, ProbeSource::StimulatorCurrent);
+            }
+        }
+    }
+    for (segment, Recorded { label }) in &targets.recorded {
+        segment_probe(ProbeKey::Recorded(segment), label.clone(), segment, ProbeSource::Voltage);
+    }
+    for (segment, recorder) in &targets.recorders {
+        if recorder.quantities.contains(&Quantity::Voltage) {
+            segment_probe(ProbeKey::Recorder(segment), recorder.path.clone(), segment, ProbeSource::Voltage);
+        }
+    }
+    let subscribed = registry.subscribed_segments();
+    for (segment, SceneSegment(at)) in &targets.scene_segments {
+        if subscribed.as_ref().is_none_or(|segments| segments.contains(at)) {
+            let label = format!("n{}_{}", at.neuron, at.segment);
+            segment_probe(ProbeKey::Segment(*at), label, segment, ProbeSource::Voltage);
+        }
+    }
+    for entity in &targets.electrodes {
+        let Ok(electrode) = reads.electrodes.get(entity) else { continue };
+        let key = ProbeKey::Electrode(entity);
+        probes.push(ProbeEntry { key: key.clone(), label: electrode.label.clone(), segment: None, unit: ProbeSource::Lfp.unit() });
+        samples.push(ProbeSample { key, t: timestamp.0, value: electrode.potential_uv });
+    }
+
+    #[cfg(feature = "render")]
+    if let Some(watch_list) = &watch_list {
+        for entry in &watch_list.entries {
+            let key = ProbeKey::Watch(entry.watch.name.clone());
+            probes.push(ProbeEntry { key: key.clone(), label: entry.watch.name.clone(), segment: None, unit: "" });
+            // Watch history is kept in ms.
+            if let Some((t_ms, value)) = entry.watch.history.back() {
+                samples.push(ProbeSample { key, t: t_ms / 1000.0, value: *value });
+            }
+        }
+    }
+    let frame = ProbeFrame { t: timestamp.0, step: clock.step_count.0, paused: clock.control.paused, samples };
+    registry.publish(probes, frame);
+}
+
+/// Draw a marker at each newly probed segment.
+#[cfg(feature = "render")]
+pub fn spawn_probe_markers(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    probed: Query<(Entity, &GlobalTransform), Added<Probe>>,
+) {
+    for (segment, transform) in &probed {
+        commands.spawn((
+            ProbeMarker { segment },
+            PbrBundle {
+                mesh: meshes.add(Cuboid::new(6.0, 6.0, 6.0)),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::CYAN,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_translation(transform.translation()),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Remove the markers of segments no longer probed.
+#[cfg(feature = "render")]
+pub fn despawn_probe_markers(
+    mut commands: Commands,
+    markers: Query<(Entity, &ProbeMarker)>,
+    probed: Query<(), With<Probe>>,
+) {
+    for (marker, ProbeMarker { segment }) in &markers {
+        if probed.get(*segment).is_err() {
+            commands.entity(marker).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimension::{FaradsPerSquareCm, MilliVolts};
+
+    #[test]
+    fn delivers_subscribed_samples_and_forgets_dropped_subscribers() {
+        let watch = |name: &str| ProbeKey::Watch(name.to_string());
+        let probe = |name: &str| ProbeEntry { key: watch(name), label: name.to_string(), segment: None, unit: "" };
+        let sample = |name: &str, value| ProbeSample { key: watch(name), t: 0.001, value };
+
+        let frame = |samples| ProbeFrame { t: 0.001, step: 10, paused: false, samples };
+
+        let mut registry = ProbeRegistry::default();
+        let all = registry.subscribe(Subscription::All);
+        let only_b = registry.subscribe(Subscription::Probes(vec![watch("b")]));
+        let only_c = registry.subscribe(Subscription::Probes(vec![watch("c")]));
+        let dropped = registry.subscribe(Subscription::All);
+        drop(dropped);
+        registry.publish(vec![probe("a"), probe("b")], frame(vec![sample("a", 1.0), sample("b", 2.0)]));
+
+        assert_eq!(all.try_iter().collect::<Vec<_>>(), vec![frame(vec![sample("a", 1.0), sample("b", 2.0)])]);
+        assert_eq!(only_b.try_iter().collect::<Vec<_>>(), vec![frame(vec![sample("b", 2.0)])]);
+        assert!(only_c.try_recv().is_err());
+        assert_eq!(registry.subscriber_count(), 3);
+        assert_eq!(registry.probes().len(), 2);
+        assert_eq!(registry.latest(&watch("b")).map(|s| s.value), Some(2.0));
+    }
+
+    #[test]
+    fn samples_the_segments_subscribers_ask_for() {
+        let at = |segment| SegmentRef { neuron: 0, segment };
+        let mut app = App::new();
+        app.init_resource::<ProbeRegistry>()
+            .insert_resource(Timestamp(0.0))
+            .init_resource::<StepCount>()
+            .init_resource::<SimulationControl>()
+            .add_systems(Update, (accept_remote_subscriptions, sample_probes).chain());
+        for (segment, voltage) in [(0, -65.0), (1, -40.0)] {
+            let membrane = Membrane { membrane_channels: vec![], capacitance: FaradsPerSquareCm(1e-6) };
+            app.world.spawn((SceneSegment(at(segment)), MembraneVoltage(MilliVolts(voltage)), membrane));
+        }
+
+        let everything = app.world.resource_mut::<ProbeRegistry>().subscribe(Subscription::All);
+        let one = subscribe_remotely(Subscription::Probes(vec![ProbeKey::Segment(at(1))]));
+        app.update();
+        let frame = one.try_recv().unwrap();
+        assert_eq!(frame.samples, vec![ProbeSample { key: ProbeKey::Segment(at(1)), t: 0.0, value: -40.0 }]);
+        assert!(everything.try_recv().is_err());
+
+        let every_segment = app.world.resource_mut::<ProbeRegistry>().subscribe(Subscription::EverySegment);
+        app.world.resource_mut::<Timestamp>().0 = 1e-4;
+        app.update();
+        assert_eq!(every_segment.try_recv().unwrap().samples.len(), 2);
+        assert_eq!(one.try_recv().unwrap().samples.len(), 1);
+    }
+
+    #[test]
+    fn probe_history_is_bounded_and_restarts_with_time() {
+        let mut probe = Probe::new("soma".to_string());
+        for i in 0..PROBE_HISTORY_LEN + 10 {
+            probe.push(i as f32 * 1e-4, -65.0);
+        }
+        assert_eq!(probe.trace().len(), PROBE_HISTORY_LEN);
+        probe.push(0.0, -70.0);
+        assert_eq!(probe.trace().values, vec![-70.0]);
+    }
+}
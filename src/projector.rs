@@ -0,0 +1,395 @@
+//! Patterned photostimulation: a projector, like a digital micromirror
+//! device behind an objective, casting a grayscale image or movie onto the
+//! scene to drive the light-gated channels of `neuron::opsin`.
+//!
+//! The projector is collimated: the image is cast along its direction
+//! without spreading or dimming with depth, so a segment is lit by the
+//! pixel it lies behind, whatever its distance. Movie frames advance from
+//! time zero, and the whole projector pulses with its train like a light.
+//!
+//! Patterns are read from PGM files (`P2` or `P5`). A movie is a file of
+//! several images one after another, as netpbm allows.
+//!
+//! Each projector is drawn as a translucent screen, at its center and
+//! facing its light, showing the current frame.
+
+use bevy::prelude::*;
+#[cfg(feature = "render")]
+use bevy::math::prelude::Rectangle;
+#[cfg(feature = "render")]
+use bevy::render::render_asset::RenderAssetUsages;
+#[cfg(feature = "render")]
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+#[cfg(feature = "render")]
+use bevy::render::texture::ImageSampler;
+
+use crate::dimension::{Interval, Timestamp};
+use crate::neuron::opsin::Opsin;
+use crate::optogenetics::{Lit, PulseTrain, movie_frame};
+#[cfg(feature = "render")]
+use crate::optogenetics::wavelength_color;
+use crate::serialize;
+use crate::stimulator::Envelope;
+use crate::units::UnitScale;
+
+/// The frames of a grayscale image or movie, each row by row from the top,
+/// from 0 for black to 255 for white.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl Pattern {
+    /// Read a PGM file of one or more images of the same size.
+    pub fn parse_pgm(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = PgmReader { bytes, at: 0 };
+        let mut pattern = Pattern { width: 0, height: 0, frames: vec![] };
+        while !reader.at_end() {
+            let binary = match reader.token() {
+                Some(b"P5") => true,
+                Some(b"P2") => false,
+                _ => return Err("Not a PGM image (P2 or P5)".to_string()),
+            };
+            let width = reader.number("width")?;
+            let height = reader.number("height")?;
+            let max_value = reader.number("maximum value")?;
+            if width == 0 || height == 0 || !(1..=65535).contains(&max_value) {
+                return Err(format!("Invalid PGM header: {}x{}, maximum value {}", width, height, max_value));
+            }
+            if !pattern.frames.is_empty() && (width, height) != (pattern.width, pattern.height) {
+                return Err(format!(
+                    "Frame {} is {}x{}, not {}x{} like the first",
+                    pattern.frames.len() + 1, width, height, pattern.width, pattern.height
+                ));
+            }
+            let samples = if binary {
+                reader.binary_samples(width * height, max_value)?
+            } else {
+                (0..width * height).map(|_| reader.number("sample")).collect::<Result<Vec<_>, _>>()?
+            };
+            pattern.width = width;
+            pattern.height = height;
+            pattern.frames.push(samples.iter().map(|s| ((s.min(&max_value) * 255 + max_value / 2) / max_value) as u8).collect());
+        }
+        if pattern.frames.is_empty() {
+            return Err("Empty PGM file".to_string());
+        }
+        Ok(pattern)
+    }
+
+    /// How bright `frame` is at `(u, v)`, from 0 to 1, where `u` runs from
+    /// the left edge to the right and `v` from the top to the bottom, each
+    /// from 0 to 1. Dark outside the image.
+    pub fn intensity(&self, frame: usize, u: f32, v: f32) -> f32 {
+        if !((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)) {
+            return 0.0;
+        }
+        let x = ((u * self.width as f32) as usize).min(self.width.saturating_sub(1));
+        let y = ((v * self.height as f32) as usize).min(self.height.saturating_sub(1));
+        self.frames
+            .get(frame)
+            .and_then(|pixels| pixels.get(y * self.width + x))
+            .map_or(0.0, |level| *level as f32 / 255.0)
+    }
+
+    pub fn serialize(&self) -> serialize::Pattern {
+        serialize::Pattern { width: self.width, height: self.height, frames: self.frames.clone() }
+    }
+
+    pub fn deserialize(pattern: &serialize::Pattern) -> Self {
+        Pattern { width: pattern.width, height: pattern.height, frames: pattern.frames.clone() }
+    }
+}
+
+struct PgmReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> PgmReader<'a> {
+    /// Skip whitespace and `#` comments.
+    fn skip_space(&mut self) {
+        while let Some(byte) = self.bytes.get(self.at) {
+            if *byte == b'#' {
+                while self.bytes.get(self.at).is_some_and(|b| *b != b'\n') {
+                    self.at += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.at += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_space();
+        self.at >= self.bytes.len()
+    }
+
+    fn token(&mut self) -> Option<&'a [u8]> {
+        self.skip_space();
+        let start = self.at;
+        while self.bytes.get(self.at).is_some_and(|b| !b.is_ascii_whitespace() && *b != b'#') {
+            self.at += 1;
+        }
+        (self.at > start).then(|| &self.bytes[start..self.at])
+    }
+
+    fn number(&mut self, what: &str) -> Result<usize, String> {
+        self.token()
+            .and_then(|token| std::str::from_utf8(token).ok())
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| format!("Expected a PGM {}", what))
+    }
+
+    /// The samples of a `P5` image, which follow a single whitespace byte
+    /// after the header, one byte each or, from a maximum value of 256,
+    /// two bytes, most significant first.
+    fn binary_samples(&mut self, count: usize, max_value: usize) -> Result<Vec<usize>, String> {
+        let sample_bytes = if max_value < 256 { 1 } else { 2 };
+        let start = self.at + 1;
+        let end = start + count * sample_bytes;
+        let data = self.bytes.get(start..end).ok_or("Truncated PGM image")?;
+        self.at = end;
+        Ok(data.chunks(sample_bytes).map(|c| c.iter().fold(0, |sample, b| sample << 8 | *b as usize)).collect())
+    }
+}
+
+#[derive(Clone, Component, Debug)]
+pub struct Projector {
+    /// Where the image is centered, in the scene frame.
+    pub center_um: Vec3,
+    /// The way the light travels.
+    pub direction: Vec3,
+    pub width_um: f32,
+    pub height_um: f32,
+    /// Irradiance (mW/mm²) where the image is white, while a pulse is on.
+    pub irradiance: f32,
+    pub wavelength_nm: f32,
+    pub pattern: Pattern,
+    pub frame_interval: Interval,
+    pub looping: bool,
+    pub train: PulseTrain,
+}
+
+impl Projector {
+    /// Project `pattern` down onto the scene, 200 µm wide, steadily and,
+    /// for a movie, at 20 frames a second over and over.
+    pub fn new(pattern: Pattern) -> Self {
+        let width_um = 200.0;
+        let height_um = width_um * pattern.height as f32 / pattern.width.max(1) as f32;
+        Projector {
+            center_um: Vec3::ZERO,
+            direction: Vec3::NEG_Y,
+            width_um,
+            height_um,
+            irradiance: 5.0,
+            wavelength_nm: Opsin::ChR2.peak_wavelength_nm(),
+            pattern,
+            frame_interval: Interval(0.05),
+            looping: true,
+            train: PulseTrain {
+                envelope: Envelope::deserialize(&serialize::Envelope { period_sec: 0.1, onset_sec: 0.0, offset_sec: 0.1 }),
+                pulses: None,
+            },
+        }
+    }
+
+    /// The directions of the image's right edge and top edge, across the
+    /// light.
+    pub fn axes(&self) -> (Vec3, Vec3) {
+        let forward = self.direction.normalize_or_zero();
+        let hint = if forward.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+        let right = forward.cross(hint).normalize_or_zero();
+        (right, right.cross(forward))
+    }
+
+    /// Where the light through `point_um` comes from in the image, as `u`
+    /// and `v` for `Pattern::intensity`.
+    pub fn image_coordinates(&self, point_um: Vec3) -> (f32, f32) {
+        let (right, up) = self.axes();
+        let offset = point_um - self.center_um;
+        (offset.dot(right) / self.width_um + 0.5, 0.5 - offset.dot(up) / self.height_um)
+    }
+
+    /// The light reaching `point_um`, frame by frame.
+    pub fn lit_at(&self, point_um: Vec3) -> Lit {
+        let (u, v) = self.image_coordinates(point_um);
+        Lit {
+            irradiance: (0..self.pattern.frames.len())
+                .map(|frame| self.irradiance * self.pattern.intensity(frame, u, v))
+                .collect(),
+            frame_interval: self.frame_interval.clone(),
+            looping: self.looping,
+            wavelength_nm: self.wavelength_nm,
+            train: self.train.clone(),
+        }
+    }
+
+    /// The frame showing at `t`, if the movie has not ended.
+    pub fn frame_at(&self, t: &Timestamp) -> Option<usize> {
+        movie_frame(self.pattern.frames.len(), &self.frame_interval, self.looping, t)
+    }
+
+    pub fn serialize(&self) -> serialize::Projector {
+        serialize::Projector {
+            location: serialize::Location {
+                x_mm: self.center_um.x / UnitScale::mm_to_microns(1.0),
+                y_mm: self.center_um.y / UnitScale::mm_to_microns(1.0),
+                z_mm: self.center_um.z / UnitScale::mm_to_microns(1.0),
+            },
+            direction: self.direction.to_array(),
+            width_um: self.width_um,
+            height_um: self.height_um,
+            irradiance_mw_per_square_mm: self.irradiance,
+            wavelength_nm: self.wavelength_nm,
+            pattern: self.pattern.serialize(),
+            frame_interval_sec: self.frame_interval.0,
+            looping: self.looping,
+            envelope: self.train.envelope.serialize(),
+            pulses: self.train.pulses,
+        }
+    }
+
+    pub fn deserialize(projector: &serialize::Projector) -> Self {
+        let serialize::Location { x_mm, y_mm, z_mm } = &projector.location;
+        Projector {
+            center_um: Vec3::new(*x_mm, *y_mm, *z_mm) * UnitScale::mm_to_microns(1.0),
+            direction: Vec3::from_array(projector.direction),
+            width_um: projector.width_um,
+            height_um: projector.height_um,
+            irradiance: projector.irradiance_mw_per_square_mm,
+            wavelength_nm: projector.wavelength_nm,
+            pattern: Pattern::deserialize(&projector.pattern),
+            frame_interval: Interval(projector.frame_interval_sec),
+            looping: projector.looping,
+            train: PulseTrain { envelope: Envelope::deserialize(&projector.envelope), pulses: projector.pulses },
+        }
+    }
+}
+
+/// The screen drawn for a projector, with the image it shows, and the
+/// frame it was last drawn showing and whether the projector was on.
+#[cfg(feature = "render")]
+#[derive(Component)]
+pub struct ProjectorScreen {
+    image: Handle<Image>,
+    shown: Option<(Option<usize>, bool)>,
+}
+
+/// The screen's image: the frame in the projector's color, brighter and
+/// more opaque while the projector is on.
+#[cfg(feature = "render")]
+fn screen_image(projector: &Projector, frame: Option<usize>, on: bool) -> Image {
+    let [r, g, b, _] = wavelength_color(projector.wavelength_nm).as_rgba_u8();
+    let pixels = projector.pattern.width * projector.pattern.height;
+    let levels = frame.and_then(|f| projector.pattern.frames.get(f));
+    let data = (0..pixels)
+        .flat_map(|i| {
+            let level = levels.and_then(|l| l.get(i)).copied().unwrap_or(0) as u32;
+            let alpha = if on { level * 3 / 4 } else { level / 6 };
+            [r, g, b, alpha as u8]
+        })
+        .collect::<Vec<_>>();
+    let mut image = Image::new(
+        Extent3d { width: projector.pattern.width as u32, height: projector.pattern.height as u32, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+/// Draw each new projector.
+#[cfg(feature = "render")]
+pub fn spawn_projector_screens(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    projectors: Query<(Entity, &Projector), Added<Projector>>,
+) {
+    for (entity, projector) in &projectors {
+        let image = images.add(screen_image(projector, None, false));
+        commands.entity(entity).insert((
+            ProjectorScreen { image: image.clone(), shown: None },
+            PbrBundle {
+                mesh: meshes.add(Rectangle::new(1.0, 1.0)),
+                material: materials.add(StandardMaterial {
+                    base_color_texture: Some(image),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    double_sided: true,
+                    cull_mode: None,
+                    ..default()
+                }),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Place each projector's screen, and redraw it as its frame changes and
+/// it pulses.
+#[cfg(feature = "render")]
+pub fn update_projector_screens(
+    scale: Res<UnitScale>,
+    timestamp: Res<Timestamp>,
+    mut images: ResMut<Assets<Image>>,
+    mut projectors: Query<(Ref<Projector>, &mut ProjectorScreen, &mut Transform)>,
+) {
+    for (projector, mut screen, mut transform) in &mut projectors {
+        if projector.is_changed() || screen.is_added() {
+            let (_, up) = projector.axes();
+            *transform = Transform::from_translation(scale.point_to_screen(projector.center_um))
+                .looking_to(projector.direction, up)
+                .with_scale(Vec3::new(scale.microns_to_screen(projector.width_um), scale.microns_to_screen(projector.height_um), 1.0));
+        }
+        let shown = (projector.frame_at(&timestamp), projector.train.is_on(&timestamp));
+        if projector.is_changed() || screen.shown != Some(shown) {
+            screen.shown = Some(shown);
+            if let Some(image) = images.get_mut(&screen.image) {
+                *image = screen_image(&projector, shown.0, shown.1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_a_movie_read_from_pgm() {
+        // Two 2x1 frames: white then black on the left, black then white
+        // on the right.
+        let mut pgm = b"P2 # a comment\n2 1\n4\n4 0\n".to_vec();
+        pgm.extend_from_slice(b"P5 2 1 255\n");
+        pgm.extend_from_slice(&[0, 255]);
+        let pattern = Pattern::parse_pgm(&pgm).unwrap();
+        assert_eq!((pattern.width, pattern.height), (2, 1));
+        assert_eq!(pattern.frames, vec![vec![255, 0], vec![0, 255]]);
+        assert!(Pattern::parse_pgm(b"P5 2 1 255\n\x00").is_err());
+        assert!(Pattern::parse_pgm(b"P6 1 1 255\n\x00\x00\x00").is_err());
+
+        let projector = Projector { center_um: Vec3::ZERO, direction: Vec3::NEG_Z, height_um: 100.0, ..Projector::new(pattern) };
+        let (right, up) = projector.axes();
+        assert!((right - Vec3::X).length() < 1e-6 && (up - Vec3::Y).length() < 1e-6);
+        // Collimated: depth along the light does not matter.
+        let left = projector.lit_at(Vec3::new(-50.0, 0.0, -300.0));
+        assert_eq!(left.irradiance, vec![projector.irradiance, 0.0]);
+        assert_eq!(left.irradiance_at(&Timestamp(0.01)), projector.irradiance);
+        assert_eq!(left.irradiance_at(&Timestamp(0.06)), 0.0);
+        assert_eq!(left.irradiance_at(&Timestamp(0.11)), projector.irradiance, "the movie loops");
+        assert!(!projector.lit_at(Vec3::new(150.0, 0.0, 0.0)).reaches(), "outside the image");
+
+        let round_trip = Projector::deserialize(&projector.serialize());
+        assert_eq!(round_trip.pattern, projector.pattern);
+        assert_eq!(round_trip.direction, projector.direction);
+    }
+}
@@ -0,0 +1,209 @@
+//! Continuous recording of membrane voltages to disk.
+//!
+//! `ContinuousRecorder` knows nothing about the ECS: it takes a time and a
+//! slice of channel values, so the headless runner can drive it directly.
+//! `RecordingPlugin` wires it up to the `ProbeRegistry`'s samples of every
+//! segment marked `Recorded`, taking one sample per frame. For per-entity CSV
+//! output of other quantities, see `csv_recorder::Recorder`.
+
+#[cfg(all(feature = "arrow", not(target_arch = "wasm32")))]
+pub mod arrow_file;
+pub mod csv_recorder;
+#[cfg(all(feature = "hdf5", not(target_arch = "wasm32")))]
+pub mod hdf5_file;
+
+use bevy::prelude::*;
+
+use crate::clock::RunClock;
+use crate::recording::csv_recorder::record_segments_and_synapses;
+use crate::dimension::Timestamp;
+use crate::probe::{sample_probes, ProbeKey, ProbeRegistry};
+
+/// Passes every `every`-th sample.
+#[derive(Clone, Debug)]
+pub struct Decimator {
+    pub every: usize,
+    count: usize,
+}
+
+impl Decimator {
+    pub fn new(every: usize) -> Self {
+        Decimator { every: every.max(1), count: 0 }
+    }
+
+    pub fn tick(&mut self) -> bool {
+        let pass = self.count == 0;
+        self.count = (self.count + 1) % self.every;
+        pass
+    }
+}
+
+/// The file a recording is written to.
+enum Writer {
+    #[cfg(all(feature = "hdf5", not(target_arch = "wasm32")))]
+    Hdf5(hdf5_file::Hdf5Writer),
+    #[cfg(all(feature = "arrow", not(target_arch = "wasm32")))]
+    Arrow(arrow_file::ArrowWriter),
+}
+
+/// Whether `path` names an Arrow IPC file rather than an HDF5 one.
+pub fn is_arrow_path(path: &str) -> bool {
+    path.ends_with(".arrow") || path.ends_with(".feather")
+}
+
+#[cfg(all(feature = "hdf5", not(target_arch = "wasm32")))]
+fn create_hdf5(path: &str, labels: &[String], decimation: usize, run_started_at: &str) -> Result<Writer, String> {
+    hdf5_file::Hdf5Writer::create(path, labels, decimation, run_started_at)
+        .map(Writer::Hdf5)
+        .map_err(|e| format!("Could not create recording {}: {}", path, e))
+}
+
+#[cfg(not(all(feature = "hdf5", not(target_arch = "wasm32"))))]
+fn create_hdf5(_path: &str, _labels: &[String], _decimation: usize, _run_started_at: &str) -> Result<Writer, String> {
+    Err("HDF5 recording requires a native build with the `hdf5` feature.".to_string())
+}
+
+#[cfg(all(feature = "arrow", not(target_arch = "wasm32")))]
+fn create_arrow(path: &str, labels: &[String], decimation: usize, run_started_at: &str) -> Result<Writer, String> {
+    arrow_file::ArrowWriter::create(path, labels, decimation, run_started_at)
+        .map(Writer::Arrow)
+        .map_err(|e| format!("Could not create recording {}: {}", path, e))
+}
+
+#[cfg(not(all(feature = "arrow", not(target_arch = "wasm32"))))]
+fn create_arrow(_path: &str, _labels: &[String], _decimation: usize, _run_started_at: &str) -> Result<Writer, String> {
+    Err("Arrow recording requires a native build with the `arrow` feature.".to_string())
+}
+
+/// A recording in progress.
+pub struct ContinuousRecorder {
+    decimator: Decimator,
+    writer: Writer,
+}
+
+impl ContinuousRecorder {
+    /// Start recording `labels.len()` channels to `path`, keeping every
+    /// `decimation`-th sample. Paths ending in `.arrow` or `.feather` are
+    /// written as Arrow IPC files, and others as HDF5.
+    pub fn create(path: &str, labels: &[String], decimation: usize, run_clock: &RunClock) -> Result<Self, String> {
+        let run_started_at = crate::clock::iso8601(run_clock.started_at_unix_seconds);
+        let writer = if is_arrow_path(path) {
+            create_arrow(path, labels, decimation, &run_started_at)?
+        } else {
+            create_hdf5(path, labels, decimation, &run_started_at)?
+        };
+        Ok(ContinuousRecorder { decimator: Decimator::new(decimation), writer })
+    }
+
+    pub fn record(&mut self, t: f32, values: &[f32]) -> Result<(), String> {
+        if !self.decimator.tick() {
+            return Ok(());
+        }
+        #[cfg(not(all(any(feature = "hdf5", feature = "arrow"), not(target_arch = "wasm32"))))]
+        let _ = (t, values);
+        match self.writer {
+            #[cfg(all(feature = "hdf5", not(target_arch = "wasm32")))]
+            Writer::Hdf5(ref mut writer) => writer.push(t, values).map_err(|e| e.to_string()),
+            #[cfg(all(feature = "arrow", not(target_arch = "wasm32")))]
+            Writer::Arrow(ref mut writer) => writer.push(t, values).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Write out buffered samples. Call before dropping the recorder.
+    pub fn finish(self) -> Result<(), String> {
+        match self.writer {
+            #[cfg(all(feature = "hdf5", not(target_arch = "wasm32")))]
+            Writer::Hdf5(mut writer) => writer.flush().map_err(|e| e.to_string()),
+            #[cfg(all(feature = "arrow", not(target_arch = "wasm32")))]
+            Writer::Arrow(mut writer) => writer.finish().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Marks a segment whose membrane voltage goes into recordings started
+/// while it is marked.
+#[derive(Component)]
+pub struct Recorded {
+    pub label: String,
+}
+
+#[derive(Resource)]
+pub struct Recording {
+    pub path: String,
+    pub decimation: usize,
+    pub active: Option<(ContinuousRecorder, Vec<Entity>)>,
+    pub error: Option<String>,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Recording {
+            path: "recording.h5".to_string(),
+            decimation: 1,
+            active: None,
+            error: None,
+        }
+    }
+}
+
+impl Recording {
+    pub fn start(&mut self, channels: Vec<(Entity, String)>, run_clock: &RunClock) {
+        let labels = channels.iter().map(|(_, label)| label.clone()).collect::<Vec<_>>();
+        match ContinuousRecorder::create(&self.path, &labels, self.decimation, run_clock) {
+            Ok(recorder) => {
+                self.active = Some((recorder, channels.into_iter().map(|(e, _)| e).collect()));
+                self.error = None;
+            },
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((recorder, _)) = self.active.take() {
+            self.error = recorder.finish().err();
+        }
+    }
+}
+
+pub struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Recording>()
+            .add_systems(Update, record_membrane_voltages.after(sample_probes))
+            .add_systems(Update, record_segments_and_synapses);
+    }
+}
+
+fn record_membrane_voltages(
+    timestamp: Res<Timestamp>,
+    mut recording: ResMut<Recording>,
+    probes: Res<ProbeRegistry>,
+) {
+    let result = match &mut recording.active {
+        Some((recorder, channels)) => {
+            let values = channels
+                .iter()
+                .map(|e| probes.latest(&ProbeKey::Recorded(*e)).map_or(f32::NAN, |s| s.value))
+                .collect::<Vec<_>>();
+            recorder.record(timestamp.0, &values)
+        },
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        recording.error = Some(e);
+        recording.active = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimator_keeps_every_nth_sample() {
+        let mut decimator = Decimator::new(3);
+        let kept = (0..7).filter(|_| decimator.tick()).count();
+        assert_eq!(kept, 3);
+    }
+}
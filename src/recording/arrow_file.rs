@@ -0,0 +1,100 @@
+//! Arrow IPC file layout for continuous recordings, which Python
+//! (`pyarrow.ipc.open_file`, `pandas.read_feather`) and R
+//! (`arrow::read_ipc_file`) read without conversion, memory-mapped if
+//! they like.
+//!
+//! A `time` column (seconds) is followed by one column per channel (mV),
+//! named by its label. The schema metadata records when the run started
+//! and the decimation. Samples are buffered and written a record batch at
+//! a time.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float32Array, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+const BATCH: usize = 1024;
+
+pub struct ArrowWriter {
+    writer: FileWriter<File>,
+    schema: Arc<Schema>,
+    time_buffer: Vec<f32>,
+    channel_buffers: Vec<Vec<f32>>,
+}
+
+impl ArrowWriter {
+    pub fn create(path: &str, labels: &[String], decimation: usize, run_started_at: &str) -> Result<Self, ArrowError> {
+        let fields = std::iter::once(Field::new("time", DataType::Float32, false))
+            .chain(labels.iter().map(|label| Field::new(label.as_str(), DataType::Float32, true)))
+            .collect::<Vec<_>>();
+        let metadata = HashMap::from([
+            ("run_started_at".to_string(), run_started_at.to_string()),
+            ("decimation".to_string(), decimation.to_string()),
+        ]);
+        let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+        let writer = FileWriter::try_new(File::create(path)?, &schema)?;
+        Ok(ArrowWriter {
+            writer,
+            schema,
+            time_buffer: Vec::with_capacity(BATCH),
+            channel_buffers: vec![Vec::with_capacity(BATCH); labels.len()],
+        })
+    }
+
+    pub fn push(&mut self, t: f32, values: &[f32]) -> Result<(), ArrowError> {
+        self.time_buffer.push(t);
+        for (buffer, v) in self.channel_buffers.iter_mut().zip(values.iter()) {
+            buffer.push(*v);
+        }
+        if self.time_buffer.len() >= BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), ArrowError> {
+        if self.time_buffer.is_empty() {
+            return Ok(());
+        }
+        let columns = std::iter::once(&mut self.time_buffer)
+            .chain(self.channel_buffers.iter_mut())
+            .map(|buffer| Arc::new(Float32Array::from(std::mem::take(buffer))) as ArrayRef)
+            .collect::<Vec<_>>();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)
+    }
+
+    /// Write out buffered samples and the file footer.
+    pub fn finish(&mut self) -> Result<(), ArrowError> {
+        self.flush()?;
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_ipc::reader::FileReader;
+
+    #[test]
+    fn writes_readable_batches() {
+        let path = std::env::temp_dir().join("nb_sim_arrow_file_test.arrow");
+        let path = path.to_str().unwrap();
+        let mut writer = ArrowWriter::create(path, &["soma".to_string(), "axon".to_string()], 2, "2024-01-01T00:00:00.000Z").unwrap();
+        for i in 0..(BATCH + 10) {
+            writer.push(i as f32 * 1e-5, &[-65.0, i as f32]).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = FileReader::try_new(File::open(path).unwrap(), None).unwrap();
+        assert_eq!(reader.schema().metadata()["decimation"], "2");
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), BATCH + 10);
+        let axon = batches[1].column_by_name("axon").unwrap().as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(axon.value(9), (BATCH + 9) as f32);
+        std::fs::remove_file(path).unwrap();
+    }
+}
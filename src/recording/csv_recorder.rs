@@ -0,0 +1,220 @@
+//! CSV recording of arbitrary quantities from segments and synapses.
+//!
+//! Attach a `Recorder` to a segment (an entity with a `Membrane`) or to a
+//! synapse, listing the quantities to record. Each recorder writes its own
+//! CSV file with a `t_ms` column followed by one column per recorded value.
+
+use std::fs::File;
+use std::io::Write;
+
+use bevy::prelude::*;
+
+use crate::dimension::{MilliVolts, Timestamp};
+use crate::integrations::grace::Synapse;
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::synapse::SynapseMembranes;
+use crate::recording::Decimator;
+use crate::serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    /// Membrane voltage (mV). Segments only.
+    Voltage,
+    /// Activation and inactivation magnitudes of every channel, or of every
+    /// postsynaptic receptor for synapses.
+    GateMagnitudes,
+    /// Cleft glutamate and GABA concentrations (M). Synapses only.
+    TransmitterConcentration,
+}
+
+impl Quantity {
+    pub fn serialize(&self) -> serialize::RecordedQuantity {
+        match self {
+            Quantity::Voltage => serialize::RecordedQuantity::Voltage,
+            Quantity::GateMagnitudes => serialize::RecordedQuantity::GateMagnitudes,
+            Quantity::TransmitterConcentration => serialize::RecordedQuantity::TransmitterConcentration,
+        }
+    }
+
+    pub fn deserialize(quantity: &serialize::RecordedQuantity) -> Self {
+        match quantity {
+            serialize::RecordedQuantity::Voltage => Quantity::Voltage,
+            serialize::RecordedQuantity::GateMagnitudes => Quantity::GateMagnitudes,
+            serialize::RecordedQuantity::TransmitterConcentration => Quantity::TransmitterConcentration,
+        }
+    }
+}
+
+/// Writes rows of named values, emitting the header from the names in the
+/// first row.
+pub struct CsvRecorder<W: Write> {
+    writer: csv::Writer<W>,
+    decimator: Decimator,
+    header_written: bool,
+}
+
+impl CsvRecorder<File> {
+    pub fn create(path: &str, decimation: usize) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+        Ok(CsvRecorder::from_writer(file, decimation))
+    }
+}
+
+impl<W: Write> CsvRecorder<W> {
+    pub fn from_writer(writer: W, decimation: usize) -> Self {
+        CsvRecorder {
+            writer: csv::Writer::from_writer(writer),
+            decimator: Decimator::new(decimation),
+            header_written: false,
+        }
+    }
+
+    /// Record one row at simulation time `t` (seconds).
+    pub fn record(&mut self, t: f32, columns: &[(String, f32)]) -> Result<(), String> {
+        if !self.decimator.tick() {
+            return Ok(());
+        }
+        if !self.header_written {
+            let header = std::iter::once("t_ms".to_string()).chain(columns.iter().map(|(name, _)| name.clone()));
+            self.writer.write_record(header).map_err(|e| e.to_string())?;
+            self.header_written = true;
+        }
+        let row = std::iter::once(format!("{:.4}", t * 1000.0)).chain(columns.iter().map(|(_, v)| v.to_string()));
+        self.writer.write_record(row).map_err(|e| e.to_string())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+
+    pub fn into_inner(self) -> Result<W, String> {
+        self.writer.into_inner().map_err(|e| e.to_string())
+    }
+}
+
+/// The recorded columns of a segment.
+pub fn segment_columns(quantities: &[Quantity], membrane_potential: &MilliVolts, membrane: &Membrane) -> Vec<(String, f32)> {
+    let mut columns = vec![];
+    for quantity in quantities {
+        match quantity {
+            Quantity::Voltage => columns.push(("v_mv".to_string(), membrane_potential.0)),
+            Quantity::GateMagnitudes => {
+                let channels = membrane.membrane_channels.iter().map(|c| &c.channel);
+                gate_columns(&mut columns, "channel", channels);
+            },
+            Quantity::TransmitterConcentration => {},
+        }
+    }
+    columns
+}
+
+/// The recorded columns of a synapse.
+pub fn synapse_columns(quantities: &[Quantity], synapse_membranes: &SynapseMembranes) -> Vec<(String, f32)> {
+    let mut columns = vec![];
+    for quantity in quantities {
+        match quantity {
+            Quantity::Voltage => {},
+            Quantity::GateMagnitudes => {
+                let channels = synapse_membranes
+                    .postsynaptic_receptors
+                    .iter()
+                    .map(|r| &r.membrane_channel.channel);
+                gate_columns(&mut columns, "receptor", channels);
+            },
+            Quantity::TransmitterConcentration => {
+                let concentrations = &synapse_membranes.transmitter_concentrations;
+                columns.push(("glutamate_molar".to_string(), concentrations.glutamate.0));
+                columns.push(("gaba_molar".to_string(), concentrations.gaba.0));
+            },
+        }
+    }
+    columns
+}
+
+fn gate_columns<'a>(
+    columns: &mut Vec<(String, f32)>,
+    prefix: &str,
+    channels: impl Iterator<Item = &'a crate::neuron::channel::Channel>,
+) {
+    for (i, channel) in channels.enumerate() {
+        if let Some(activation) = &channel.activation {
+            columns.push((format!("{}{}_activation", prefix, i), activation.magnitude));
+        }
+        if let Some(inactivation) = &channel.inactivation {
+            columns.push((format!("{}{}_inactivation", prefix, i), inactivation.magnitude));
+        }
+    }
+}
+
+/// Records the chosen quantities of the segment or synapse it is attached
+/// to. The file is opened on the first sample.
+#[derive(Component)]
+pub struct Recorder {
+    pub path: String,
+    pub quantities: Vec<Quantity>,
+    pub decimation: usize,
+    pub error: Option<String>,
+    csv: Option<CsvRecorder<File>>,
+}
+
+impl Recorder {
+    pub fn new(path: String, quantities: Vec<Quantity>, decimation: usize) -> Self {
+        Recorder { path, quantities, decimation, error: None, csv: None }
+    }
+
+    fn record(&mut self, t: f32, columns: &[(String, f32)]) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.csv.is_none() {
+            match CsvRecorder::create(&self.path, self.decimation) {
+                Ok(csv) => self.csv = Some(csv),
+                Err(e) => {
+                    self.error = Some(e);
+                    return;
+                },
+            }
+        }
+        if let Some(csv) = &mut self.csv {
+            self.error = csv.record(t, columns).err();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(csv) = &mut self.csv {
+            let _ = csv.flush();
+        }
+    }
+}
+
+pub fn record_segments_and_synapses(
+    timestamp: Res<Timestamp>,
+    mut segments: Query<(&mut Recorder, &MembraneVoltage, &Membrane), Without<Synapse>>,
+    mut synapses: Query<(&mut Recorder, &Synapse)>,
+) {
+    for (mut recorder, voltage, membrane) in &mut segments {
+        let columns = segment_columns(&recorder.quantities, &voltage.0, membrane);
+        recorder.record(timestamp.0, &columns);
+    }
+    for (mut recorder, synapse) in &mut synapses {
+        let columns = synapse_columns(&recorder.quantities, &synapse.synapse_membranes);
+        recorder.record(timestamp.0, &columns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_then_decimated_rows() {
+        let mut recorder = CsvRecorder::from_writer(vec![], 2);
+        for i in 0..4 {
+            recorder.record(i as f32 * 1e-3, &[("v_mv".to_string(), i as f32)]).unwrap();
+        }
+        let written = String::from_utf8(recorder.into_inner().unwrap()).unwrap();
+        assert_eq!(written, "t_ms,v_mv\n0.0000,0\n2.0000,2\n");
+    }
+}
@@ -0,0 +1,88 @@
+//! HDF5 file layout for continuous recordings.
+//!
+//! Every channel is a resizable one-dimensional dataset under
+//! `/membrane_voltage`, sharing the `/time` dataset. Samples are buffered
+//! and appended a chunk at a time, so long recordings need only constant
+//! memory.
+
+use hdf5::types::VarLenUnicode;
+use hdf5::{Dataset, File, Location, Result};
+
+const CHUNK: usize = 1024;
+
+pub struct Hdf5Writer {
+    file: File,
+    time: Dataset,
+    channels: Vec<Dataset>,
+    time_buffer: Vec<f32>,
+    channel_buffers: Vec<Vec<f32>>,
+    written: usize,
+}
+
+impl Hdf5Writer {
+    pub fn create(path: &str, labels: &[String], decimation: usize, run_started_at: &str) -> Result<Self> {
+        let file = File::create(path)?;
+        string_attr(&file, "run_started_at", run_started_at)?;
+        file.new_attr::<u64>().create("decimation")?.write_scalar(&(decimation as u64))?;
+
+        let time = file.new_dataset::<f32>().chunk(CHUNK).shape(0..).create("time")?;
+        string_attr(&time, "unit", "seconds")?;
+        string_attr(&time, "description", "Simulation time of each sample")?;
+
+        let group = file.create_group("membrane_voltage")?;
+        let channels = labels
+            .iter()
+            .map(|label| {
+                let dataset = group.new_dataset::<f32>().chunk(CHUNK).shape(0..).create(label.as_str())?;
+                string_attr(&dataset, "unit", "mV")?;
+                Ok(dataset)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Hdf5Writer {
+            file,
+            time,
+            channel_buffers: vec![Vec::with_capacity(CHUNK); channels.len()],
+            channels,
+            time_buffer: Vec::with_capacity(CHUNK),
+            written: 0,
+        })
+    }
+
+    pub fn push(&mut self, t: f32, values: &[f32]) -> Result<()> {
+        self.time_buffer.push(t);
+        for (buffer, v) in self.channel_buffers.iter_mut().zip(values.iter()) {
+            buffer.push(*v);
+        }
+        if self.time_buffer.len() >= CHUNK {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        let n = self.time_buffer.len();
+        if n == 0 {
+            return Ok(());
+        }
+        let range = self.written..self.written + n;
+        append(&self.time, &self.time_buffer, range.clone())?;
+        for (dataset, buffer) in self.channels.iter().zip(self.channel_buffers.iter_mut()) {
+            append(dataset, buffer, range.clone())?;
+            buffer.clear();
+        }
+        self.time_buffer.clear();
+        self.written += n;
+        self.file.flush()
+    }
+}
+
+fn append(dataset: &Dataset, values: &[f32], range: std::ops::Range<usize>) -> Result<()> {
+    dataset.resize(range.end)?;
+    dataset.write_slice(values, range)
+}
+
+fn string_attr(location: &Location, name: &str, value: &str) -> Result<()> {
+    let value = value.parse::<VarLenUnicode>().map_err(|e| e.to_string())?;
+    location.new_attr::<VarLenUnicode>().create(name)?.write_scalar(&value)
+}
@@ -0,0 +1,201 @@
+//! Repairing the defects common in reconstructed morphologies: segments
+//! that are their own parent or name a parent that is not in the file,
+//! loops of parents, pieces of the tree traced separately and never joined,
+//! and zero, negative or missing radii. Left alone, these make a neuron
+//! fail validation, fall apart into several cells, or divide by zero.
+//!
+//! `diagnose` reports what `repair` would change, without changing it.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::serialize;
+
+#[derive(Clone, Debug)]
+pub struct RepairOptions {
+    /// Join each disconnected piece to the nearest segment of the tree
+    /// holding the soma, at the closest pair of points.
+    pub reconnect: bool,
+    /// Radius (µm) given to segments whose radius is not positive.
+    pub min_radius_um: f32,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        RepairOptions { reconnect: true, min_radius_um: 0.1 }
+    }
+}
+
+/// What `repair` would do to `neuron`, one line per fix.
+pub fn diagnose(neuron: &serialize::Neuron) -> Vec<String> {
+    repair(&mut neuron.clone(), &RepairOptions::default())
+}
+
+fn distance_squared(a: &serialize::Segment, b: &serialize::Segment) -> f32 {
+    (a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)
+}
+
+/// The position of each segment's root in the segment list, or `None` for
+/// segments whose parents loop without reaching a root.
+fn roots(segments: &[serialize::Segment]) -> Vec<Option<usize>> {
+    let index = segments.iter().enumerate().map(|(i, s)| (s.id, i)).collect::<HashMap<_, _>>();
+    (0..segments.len())
+        .map(|i| {
+            let mut seen = HashSet::new();
+            let mut at = i;
+            while let Some(&parent) = index.get(&segments[at].parent) {
+                if !seen.insert(at) {
+                    return None;
+                }
+                at = parent;
+            }
+            Some(at)
+        })
+        .collect()
+}
+
+/// Fix `neuron` in place, returning a line for each fix made.
+pub fn repair(neuron: &mut serialize::Neuron, options: &RepairOptions) -> Vec<String> {
+    let mut report = vec![];
+    let segments = &mut neuron.segments;
+
+    let mut ids = HashSet::new();
+    let mut next_id = segments.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+    for segment in segments.iter_mut() {
+        if !ids.insert(segment.id) {
+            report.push(format!("Segment {} repeats an earlier id; renumbered {}", segment.id, next_id));
+            segment.id = next_id;
+            ids.insert(next_id);
+            next_id += 1;
+        }
+    }
+
+    for segment in segments.iter_mut() {
+        if segment.parent == segment.id {
+            report.push(format!("Segment {} is its own parent; made a root", segment.id));
+            segment.parent = -1;
+        } else if segment.parent != -1 && !ids.contains(&segment.parent) {
+            report.push(format!("Segment {} has missing parent {}; made a root", segment.id, segment.parent));
+            segment.parent = -1;
+        }
+    }
+
+    // Cutting a loop at any of its segments leaves the rest of the loop,
+    // and everything hanging from it, below that segment.
+    while let Some(mut i) = roots(segments).iter().position(Option::is_none) {
+        let index = segments.iter().enumerate().map(|(i, s)| (s.id, i)).collect::<HashMap<_, _>>();
+        let mut seen = HashSet::new();
+        while seen.insert(i) {
+            i = index[&segments[i].parent];
+        }
+        report.push(format!("Segment {} is in a loop of parents; made a root", segments[i].id));
+        segments[i].parent = -1;
+    }
+
+    if options.reconnect {
+        reconnect(segments, &mut report);
+    } else {
+        let mut pieces = roots(segments);
+        pieces.sort();
+        pieces.dedup();
+        if pieces.len() > 1 {
+            report.push(format!("{} disconnected pieces left as they are", pieces.len()));
+        }
+    }
+
+    for segment in segments.iter_mut() {
+        if !(segment.r.is_finite() && segment.r > 0.0) {
+            report.push(format!("Segment {} has radius {}; set to {} µm", segment.id, segment.r, options.min_radius_um));
+            segment.r = options.min_radius_um;
+        }
+    }
+    report
+}
+
+/// Join every piece to the main one, which holds the soma or, failing
+/// that, the most segments.
+fn reconnect(segments: &mut [serialize::Segment], report: &mut Vec<String>) {
+    loop {
+        let roots = roots(segments).into_iter().map(|r| r.expect("loops are cut first")).collect::<Vec<_>>();
+        let mut sizes = HashMap::<usize, usize>::new();
+        for root in roots.iter() {
+            *sizes.entry(*root).or_default() += 1;
+        }
+        if sizes.len() <= 1 {
+            return;
+        }
+        let main = *sizes
+            .keys()
+            .max_by_key(|root| (segments[**root].type_ == 1, sizes[*root], std::cmp::Reverse(**root)))
+            .expect("there are several pieces");
+
+        // The closest pair of points between the main piece and any other,
+        // or the first other piece's root if none have coordinates.
+        let first = roots.iter().position(|root| *root != main).expect("there are several pieces");
+        let (mut closest, mut attach, mut best) = (roots[first], main, f32::INFINITY);
+        let main_segments = (0..segments.len()).filter(|j| roots[*j] == main).collect::<Vec<_>>();
+        for i in (0..segments.len()).filter(|i| roots[*i] != main) {
+            for &j in main_segments.iter() {
+                let d = distance_squared(&segments[i], &segments[j]);
+                if d < best {
+                    (closest, attach, best) = (i, j, d);
+                }
+            }
+        }
+
+        // Re-root the piece at its closest point, reversing the parents on
+        // the way up to its old root, then hang it from the main piece.
+        let index = segments.iter().enumerate().map(|(i, s)| (s.id, i)).collect::<HashMap<_, _>>();
+        let (mut at, mut new_parent) = (closest, segments[attach].id);
+        loop {
+            let old_parent = segments[at].parent;
+            segments[at].parent = new_parent;
+            match index.get(&old_parent) {
+                Some(&next) => (at, new_parent) = (next, segments[at].id),
+                None => break,
+            }
+        }
+        report.push(format!(
+            "{} disconnected segments joined at segment {} to segment {}, {:.1} µm away",
+            sizes[&roots[closest]],
+            segments[closest].id,
+            segments[attach].id,
+            best.sqrt(),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::swc;
+
+    // A soma and dendrite, a self-parented segment, a two-segment loop, a
+    // piece traced from its far end, and a zero radius.
+    const SWC: &str = "
+1 1 0 0 0 5 -1
+2 3 10 0 0 0 1
+3 3 20 0 0 1 3
+4 3 100 0 0 1 5
+5 3 110 0 0 1 4
+6 3 40 0 0 1 -1
+7 3 30 0 0 1 6
+";
+
+    #[test]
+    fn joins_every_piece_to_the_soma() {
+        let mut neuron = serialize::Neuron { segments: swc::segments(SWC).unwrap(), membranes: vec![] };
+        assert_eq!(diagnose(&neuron).len(), 6);
+        let report = repair(&mut neuron, &RepairOptions::default());
+        assert_eq!(report, diagnose(&serialize::Neuron { segments: swc::segments(SWC).unwrap(), membranes: vec![] }));
+        assert!(report.iter().any(|line| line.contains("its own parent")), "{:?}", report);
+        assert!(report.iter().any(|line| line.contains("loop")), "{:?}", report);
+
+        let roots = roots(&neuron.segments);
+        assert!(roots.iter().all(|root| *root == Some(0)), "{:?}", neuron.segments);
+        assert!(neuron.segments.iter().all(|s| s.r > 0.0));
+        // The piece traced outwards from 6 hangs from its nearer end, 7.
+        let parent_of = |id: i32| neuron.segments.iter().find(|s| s.id == id).unwrap().parent;
+        assert_eq!(parent_of(6), 7);
+        assert!(diagnose(&neuron).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Recording a session, its scene and every intervention made in it, and
+//! replaying it exactly, for debugging and demos.
+//!
+//! Interventions are recorded as `Command`s, each with the frame it was
+//! applied on, counted from the start of the session. Commands from the
+//! GUI or remote controllers are recorded as `apply_commands` applies
+//! them. Stimulators and constants edited directly in the GUI are
+//! recorded as the `SetStimulator`, `RemoveStimulator` and `SetConstants`
+//! commands that make the same edits, on the frame whose steps first see
+//! them.
+//!
+//! Starting a recording restarts the scene at time zero. A replay loads
+//! the recorded scene with the recorded step size and steps per frame, and
+//! applies each command on the frame it was recorded on, so it integrates
+//! the same steps as the session did; random draws repeat from the scene's
+//! seed. Heat, lights, projectors, and changes to the step size or steps
+//! per frame during a session are not recorded yet.
+
+use std::collections::HashMap;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Command, SimulationControl};
+use crate::constants::SimulationConstants;
+use crate::dimension::{SimulationStepSeconds, StepsPerFrame, Timestamp};
+use crate::integrations::grace::SceneSegment;
+use crate::serialize;
+use crate::stimulator::Stimulator;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    /// Frames since the session began.
+    pub frame: u64,
+    /// Simulated time when the command was applied, for reading the log.
+    pub t_seconds: f32,
+    pub command: Command,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepSettings {
+    pub simulation_step_seconds: f32,
+    pub steps_per_frame: usize,
+    /// See `SimulationControl::speed`.
+    #[serde(default = "full_speed")]
+    pub speed: f32,
+}
+
+fn full_speed() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub scene: serialize::Scene,
+    /// Whether the simulation was paused when the session began.
+    pub paused: bool,
+    #[serde(default)]
+    pub steps: Option<StepSettings>,
+    pub commands: Vec<RecordedCommand>,
+}
+
+/// Read a session saved from the recorder, as JSON.
+pub fn read_session(path: &str) -> Result<Session, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+#[derive(Debug, Default)]
+enum Mode {
+    #[default]
+    Idle,
+    Recording {
+        session: Session,
+        frame: u64,
+        /// Stimulators and constants as last recorded, to tell edits from
+        /// widgets marking them changed.
+        stimulators: HashMap<serialize::SegmentRef, serialize::Stimulator>,
+        constants: Option<serialize::SimulationConstants>,
+    },
+    Replaying {
+        session: Session,
+        frame: u64,
+        /// The first command not yet sent.
+        next: usize,
+    },
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct SessionRecorder {
+    mode: Mode,
+    /// The last session recorded.
+    pub last: Option<Session>,
+}
+
+/// Whether `command` is recorded as `apply_commands` applies it. `At` is
+/// recorded as its command once that is released; stimulator and constants
+/// edits are recorded by `step_session` along with the GUI's; and the
+/// recorder's own commands are not interventions.
+fn recorded_as_applied(command: &Command) -> bool {
+    !matches!(
+        command,
+        Command::At { .. }
+            | Command::SetStimulator { .. }
+            | Command::RemoveStimulator { .. }
+            | Command::SetConstants { .. }
+            | Command::StartRecording
+            | Command::StopRecording
+            | Command::ReplaySession { .. }
+    )
+}
+
+impl SessionRecorder {
+    pub fn start_recording(&mut self, scene: serialize::Scene, paused: bool) {
+        let constants = scene.constants.clone();
+        self.mode = Mode::Recording {
+            session: Session { scene, paused, steps: None, commands: vec![] },
+            frame: 0,
+            stimulators: HashMap::new(),
+            constants,
+        };
+    }
+
+    /// End a recording, keeping it as `last`, or abandon a replay.
+    pub fn stop(&mut self) {
+        if let Mode::Recording { session, .. } = std::mem::take(&mut self.mode) {
+            self.last = Some(session);
+        }
+    }
+
+    pub fn replay(&mut self, session: Session) {
+        self.mode = Mode::Replaying { session, frame: 0, next: 0 };
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.mode, Mode::Recording { .. })
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replaying { .. })
+    }
+
+    pub fn status(&self) -> String {
+        match &self.mode {
+            Mode::Idle => "Not recording".to_string(),
+            Mode::Recording { session, frame, .. } =>
+                format!("Recording: frame {}, {} interventions", frame, session.commands.len()),
+            Mode::Replaying { session, next, .. } =>
+                format!("Replaying: {} of {} interventions", next, session.commands.len()),
+        }
+    }
+
+    /// Record `command`, applied at `t`, if recording.
+    pub fn record(&mut self, command: &Command, t: &Timestamp) {
+        if let Mode::Recording { session, frame, .. } = &mut self.mode {
+            if recorded_as_applied(command) {
+                session.commands.push(RecordedCommand { frame: *frame, t_seconds: t.0, command: command.clone() });
+            }
+        }
+    }
+
+    /// Move a replay on a frame, returning the commands recorded on it, to
+    /// send for that frame's `apply_commands`. Ends the replay once every
+    /// command is sent.
+    fn advance_replay(&mut self) -> Vec<Command> {
+        let Mode::Replaying { session, frame, next } = &mut self.mode else {
+            return vec![];
+        };
+        *frame += 1;
+        let due = session.commands[*next..]
+            .iter()
+            .take_while(|recorded| recorded.frame <= *frame)
+            .map(|recorded| recorded.command.clone())
+            .collect::<Vec<_>>();
+        *next += due.len();
+        if *next == session.commands.len() {
+            self.mode = Mode::Idle;
+        }
+        due
+    }
+}
+
+/// How fast the simulation runs, which a session records and a replay
+/// restores.
+#[derive(SystemParam)]
+pub struct Pacing<'w> {
+    pub control: ResMut<'w, SimulationControl>,
+    pub steps_per_frame: ResMut<'w, StepsPerFrame>,
+    pub simulation_step: ResMut<'w, SimulationStepSeconds>,
+}
+
+/// Once a frame, after commands are applied and before stepping: record
+/// the GUI's direct edits, or send the replayed commands due next frame.
+pub fn step_session(
+    mut recorder: ResMut<SessionRecorder>,
+    pacing: Pacing,
+    timestamp: Res<Timestamp>,
+    constants: Res<SimulationConstants>,
+    segments: Query<(Ref<SceneSegment>, Option<Ref<Stimulator>>)>,
+    mut removed: RemovedComponents<Stimulator>,
+    mut events: EventWriter<Command>,
+) {
+    let Pacing { mut control, mut steps_per_frame, mut simulation_step } = pacing;
+    let removed = removed.read().collect::<Vec<_>>();
+    match &mut recorder.mode {
+        Mode::Idle => {},
+        Mode::Recording { session, frame, stimulators: known, constants: known_constants } => {
+            session.steps.get_or_insert_with(|| StepSettings {
+                simulation_step_seconds: simulation_step.0,
+                steps_per_frame: steps_per_frame.0,
+                speed: control.speed,
+            });
+            // A respawned scene brings its own stimulators.
+            if segments.iter().any(|(segment, _)| segment.is_added()) {
+                known.clear();
+            }
+            let mut edits = vec![];
+            for (segment, stimulator) in &segments {
+                let Some(stimulator) = stimulator.filter(|s| s.is_changed()) else {
+                    continue;
+                };
+                let at = segment.0;
+                let serialized = stimulator.serialize();
+                if known.get(&at) != Some(&serialized) {
+                    if !segment.is_added() {
+                        edits.push(Command::SetStimulator { neuron: at.neuron, segment: at.segment, stimulator: serialized.clone() });
+                    }
+                    known.insert(at, serialized);
+                }
+            }
+            for entity in removed {
+                if let Ok((segment, None)) = segments.get(entity) {
+                    if known.remove(&segment.0).is_some() {
+                        edits.push(Command::RemoveStimulator { at: segment.0 });
+                    }
+                }
+            }
+            if constants.is_changed() {
+                let serialized = constants.serialize();
+                if known_constants.as_ref() != Some(&serialized) {
+                    edits.push(Command::SetConstants { constants: serialized.clone() });
+                    *known_constants = Some(serialized);
+                }
+            }
+            session.commands.extend(
+                edits.into_iter().map(|command| RecordedCommand { frame: *frame, t_seconds: timestamp.0, command }),
+            );
+            *frame += 1;
+        },
+        Mode::Replaying { session, frame, .. } => {
+            if *frame == 0 {
+                control.paused = session.paused;
+                if let Some(steps) = &session.steps {
+                    simulation_step.0 = steps.simulation_step_seconds;
+                    steps_per_frame.0 = steps.steps_per_frame;
+                    control.speed = steps.speed;
+                }
+            }
+            events.send_batch(recorder.advance_replay());
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_scene() -> serialize::Scene {
+        serialize::Scene { neurons: vec![], synapses: vec![], constants: None, lights: vec![], projectors: vec![], seed: None }
+    }
+
+    #[test]
+    fn records_and_replays_commands_on_their_frames() {
+        let mut recorder = SessionRecorder::default();
+        recorder.start_recording(empty_scene(), false);
+        recorder.record(&Command::Pause, &Timestamp(0.0));
+        recorder.record(&Command::At { t_seconds: 1.0, command: Box::new(Command::Reset) }, &Timestamp(0.0));
+        recorder.stop();
+        let recorded = recorder.last.clone().unwrap();
+        assert_eq!(recorded.commands.len(), 1, "At is recorded once it is released");
+
+        let at = |frame, command| RecordedCommand { frame, t_seconds: 0.0, command };
+        let session = Session {
+            commands: vec![at(1, Command::Pause), at(1, Command::Resume), at(3, Command::Reset)],
+            ..recorded
+        };
+        let round_trip: Session = serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+        recorder.replay(round_trip);
+        assert!(matches!(recorder.advance_replay()[..], [Command::Pause, Command::Resume]));
+        assert!(recorder.advance_replay().is_empty());
+        assert!(matches!(recorder.advance_replay()[..], [Command::Reset]));
+        assert!(!recorder.is_replaying());
+    }
+}
@@ -0,0 +1,109 @@
+//! A small seedable pseudorandom generator (SplitMix64), so that anything
+//! random in a run can be repeated from its seed, on every platform.
+//!
+//! The simulation draws from keyed streams of the scene's `SimulationRng`:
+//! each stochastic component names its stream by stable keys (its segment,
+//! the time bin of the draw), so its draws do not depend on the order
+//! segments are stepped in, and a run repeats bit for bit from its seed.
+
+use bevy::prelude::Resource;
+
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform on [0, 1).
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform on 0..n, for n > 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.uniform() * n as f64) as usize
+    }
+
+    /// Standard normal, by the Box–Muller transform.
+    pub fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * self.uniform()).cos()
+    }
+
+    /// A generator for a separate stream, e.g. one per thread, that does
+    /// not overlap this one in practice.
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+
+    /// The stream named by `keys` under `seed`. The same seed and keys
+    /// always give the same stream, however many others are made first.
+    pub fn keyed(seed: u64, keys: &[u64]) -> Rng {
+        keys.iter().fold(Rng::new(seed), |mut rng, key| Rng::new(rng.next_u64() ^ key))
+    }
+}
+
+/// Streams keyed by a stimulator's segment.
+pub const STIMULATOR_STREAM: u64 = 1;
+/// Streams keyed by a noisy segment; see `noise`.
+pub const NOISE_STREAM: u64 = 2;
+
+/// The seed of every random draw in the simulation, set from the scene
+/// file's `seed`.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct SimulationRng {
+    pub seed: u64,
+}
+
+impl SimulationRng {
+    /// The seed of the stream named by `keys`, for components that store
+    /// it and draw with `Rng::keyed` as they step.
+    pub fn stream_seed(&self, keys: &[u64]) -> u64 {
+        Rng::keyed(self.seed, keys).next_u64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_from_its_seed() {
+        let draws = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..5).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+
+        let mut rng = Rng::new(1);
+        let samples = (0..10000).map(|_| rng.normal()).collect::<Vec<_>>();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.05 && (variance - 1.0).abs() < 0.05, "{} {}", mean, variance);
+        assert!((0..1000).all(|_| rng.below(3) < 3));
+    }
+
+    #[test]
+    fn keyed_streams_are_independent_of_order() {
+        let simulation = SimulationRng { seed: 42 };
+        let first = simulation.stream_seed(&[STIMULATOR_STREAM, 0, 3]);
+        let _others = (0..10).map(|i| simulation.stream_seed(&[STIMULATOR_STREAM, 1, i])).collect::<Vec<_>>();
+        assert_eq!(simulation.stream_seed(&[STIMULATOR_STREAM, 0, 3]), first);
+        assert_ne!(simulation.stream_seed(&[STIMULATOR_STREAM, 3, 0]), first);
+        assert_ne!(SimulationRng { seed: 43 }.stream_seed(&[STIMULATOR_STREAM, 0, 3]), first);
+        assert_eq!(Rng::keyed(5, &[]).next_u64(), Rng::new(5).next_u64());
+    }
+}
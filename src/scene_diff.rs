@@ -0,0 +1,412 @@
+//! Differences between two scenes, for reviewing edits before sharing.
+//!
+//! Neurons are matched by their position in the scene, segments by SWC id,
+//! membranes by SWC type, channels by their position in the membrane,
+//! synapses by the segments they connect, and lights and projectors by
+//! their position in the scene.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+
+use crate::serialize::{self, SegmentRef};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    NeuronAdded { neuron: usize },
+    NeuronRemoved { neuron: usize },
+    NeuronMoved { neuron: usize },
+    NeuronRetagged { neuron: usize, from: Option<String>, to: Option<String> },
+    NoiseChanged { neuron: usize },
+    SegmentAdded { at: SegmentRef },
+    SegmentRemoved { at: SegmentRef },
+    /// The segment's position, radius, type or parent changed.
+    SegmentReshaped { at: SegmentRef },
+    ChannelAdded { neuron: usize, membrane: usize, channel: usize },
+    ChannelRemoved { neuron: usize, membrane: usize, channel: usize },
+    /// Gating or selectivity changed.
+    ChannelKineticsChanged { neuron: usize, membrane: usize, channel: usize },
+    ConductanceChanged { neuron: usize, membrane: usize, channel: usize, from: f32, to: f32 },
+    DensityRuleChanged { neuron: usize, membrane: usize, channel: usize, from: Option<String>, to: Option<String> },
+    CapacitanceChanged { neuron: usize, membrane: usize, from: f32, to: f32 },
+    StimulatorAdded { at: SegmentRef },
+    StimulatorRemoved { at: SegmentRef },
+    StimulatorChanged { at: SegmentRef },
+    ThermalAdded { at: SegmentRef },
+    ThermalRemoved { at: SegmentRef },
+    ThermalChanged { at: SegmentRef },
+    /// The segment's own membrane, in place of its type's.
+    MembraneOverrideAdded { at: SegmentRef },
+    MembraneOverrideRemoved { at: SegmentRef },
+    MembraneOverrideChanged { at: SegmentRef },
+    SynapseAdded { pre: SegmentRef, post: SegmentRef },
+    SynapseRemoved { pre: SegmentRef, post: SegmentRef },
+    SynapseChanged { pre: SegmentRef, post: SegmentRef },
+    LightAdded { light: usize },
+    LightRemoved { light: usize },
+    LightChanged { light: usize },
+    ProjectorAdded { projector: usize },
+    ProjectorRemoved { projector: usize },
+    ProjectorChanged { projector: usize },
+    ConstantsChanged,
+    SeedChanged { from: Option<u64>, to: Option<u64> },
+}
+
+fn or_none(s: &Option<String>) -> &str {
+    s.as_deref().unwrap_or("none")
+}
+
+fn segment_name(at: &SegmentRef) -> String {
+    format!("neuron {} segment {}", at.neuron + 1, at.segment)
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::NeuronAdded { neuron } => write!(f, "Added neuron {}", neuron + 1),
+            Change::NeuronRemoved { neuron } => write!(f, "Removed neuron {}", neuron + 1),
+            Change::NeuronMoved { neuron } => write!(f, "Moved neuron {}", neuron + 1),
+            Change::NeuronRetagged { neuron, from, to } =>
+                write!(f, "Neuron {} tag: {} → {}", neuron + 1, or_none(from), or_none(to)),
+            Change::NoiseChanged { neuron } => write!(f, "Changed the membrane noise of neuron {}", neuron + 1),
+            Change::SegmentAdded { at } => write!(f, "Added {}", segment_name(at)),
+            Change::SegmentRemoved { at } => write!(f, "Removed {}", segment_name(at)),
+            Change::SegmentReshaped { at } => write!(f, "Reshaped {}", segment_name(at)),
+            Change::ChannelAdded { neuron, membrane, channel } =>
+                write!(f, "Added channel {} to neuron {} membrane {}", channel + 1, neuron + 1, membrane + 1),
+            Change::ChannelRemoved { neuron, membrane, channel } =>
+                write!(f, "Removed channel {} from neuron {} membrane {}", channel + 1, neuron + 1, membrane + 1),
+            Change::ChannelKineticsChanged { neuron, membrane, channel } =>
+                write!(f, "Changed the kinetics of channel {} in neuron {} membrane {}", channel + 1, neuron + 1, membrane + 1),
+            Change::ConductanceChanged { neuron, membrane, channel, from, to } => write!(
+                f,
+                "Neuron {} membrane {} channel {}: {} → {} S/cm²",
+                neuron + 1, membrane + 1, channel + 1, from, to
+            ),
+            Change::DensityRuleChanged { neuron, membrane, channel, from, to } => write!(
+                f,
+                "Neuron {} membrane {} channel {} density rule: {} → {}",
+                neuron + 1, membrane + 1, channel + 1, or_none(from), or_none(to)
+            ),
+            Change::CapacitanceChanged { neuron, membrane, from, to } =>
+                write!(f, "Neuron {} membrane {} capacitance: {} → {} F/cm²", neuron + 1, membrane + 1, from, to),
+            Change::StimulatorAdded { at } => write!(f, "Added a stimulator on {}", segment_name(at)),
+            Change::StimulatorRemoved { at } => write!(f, "Removed the stimulator on {}", segment_name(at)),
+            Change::StimulatorChanged { at } => write!(f, "Changed the stimulator on {}", segment_name(at)),
+            Change::ThermalAdded { at } => write!(f, "Added a thermal stimulator on {}", segment_name(at)),
+            Change::ThermalRemoved { at } => write!(f, "Removed the thermal stimulator on {}", segment_name(at)),
+            Change::ThermalChanged { at } => write!(f, "Changed the thermal stimulator on {}", segment_name(at)),
+            Change::MembraneOverrideAdded { at } => write!(f, "Gave {} its own membrane", segment_name(at)),
+            Change::MembraneOverrideRemoved { at } => write!(f, "Returned {} to its type's membrane", segment_name(at)),
+            Change::MembraneOverrideChanged { at } => write!(f, "Changed the membrane of {}", segment_name(at)),
+            Change::SynapseAdded { pre, post } =>
+                write!(f, "Added a synapse from {} to {}", segment_name(pre), segment_name(post)),
+            Change::SynapseRemoved { pre, post } =>
+                write!(f, "Removed the synapse from {} to {}", segment_name(pre), segment_name(post)),
+            Change::SynapseChanged { pre, post } =>
+                write!(f, "Changed the synapse from {} to {}", segment_name(pre), segment_name(post)),
+            Change::LightAdded { light } => write!(f, "Added light {}", light + 1),
+            Change::LightRemoved { light } => write!(f, "Removed light {}", light + 1),
+            Change::LightChanged { light } => write!(f, "Changed light {}", light + 1),
+            Change::ProjectorAdded { projector } => write!(f, "Added projector {}", projector + 1),
+            Change::ProjectorRemoved { projector } => write!(f, "Removed projector {}", projector + 1),
+            Change::ProjectorChanged { projector } => write!(f, "Changed projector {}", projector + 1),
+            Change::ConstantsChanged => write!(f, "Changed the simulation constants"),
+            Change::SeedChanged { from, to } => {
+                let seed = |s: &Option<u64>| s.map_or("none".to_string(), |s| s.to_string());
+                write!(f, "Seed: {} → {}", seed(from), seed(to))
+            },
+        }
+    }
+}
+
+/// Whether two values serialize identically. The scene types don't
+/// implement `PartialEq`.
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Entries of both maps, by key, with `None` where a key is missing.
+fn pair_up<'a, K: Ord + Copy, V>(
+    before: &'a BTreeMap<K, V>,
+    after: &'a BTreeMap<K, V>,
+) -> impl Iterator<Item = (K, Option<&'a V>, Option<&'a V>)> + 'a {
+    let keys: BTreeSet<K> = before.keys().chain(after.keys()).copied().collect();
+    keys.into_iter().map(move |k| (k, before.get(&k), after.get(&k)))
+}
+
+fn diff_neuron(neuron: usize, before: &serialize::SceneNeuron, after: &serialize::SceneNeuron, changes: &mut Vec<Change>) {
+    if !same(&before.location, &after.location) {
+        changes.push(Change::NeuronMoved { neuron });
+    }
+    if before.tag != after.tag {
+        changes.push(Change::NeuronRetagged { neuron, from: before.tag.clone(), to: after.tag.clone() });
+    }
+    if before.noise != after.noise {
+        changes.push(Change::NoiseChanged { neuron });
+    }
+
+    let segments = |n: &serialize::SceneNeuron| n.neuron.segments.iter().map(|s| (s.id, s.clone())).collect::<BTreeMap<_, _>>();
+    for (segment, b, a) in pair_up(&segments(before), &segments(after)) {
+        let at = SegmentRef { neuron, segment };
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::SegmentRemoved { at }),
+            (None, Some(_)) => changes.push(Change::SegmentAdded { at }),
+            (Some(b), Some(a)) if !same(b, a) => changes.push(Change::SegmentReshaped { at }),
+            _ => {},
+        }
+    }
+
+    let membranes = |n: &serialize::SceneNeuron| n.neuron.membranes.iter().cloned().enumerate().collect::<BTreeMap<_, _>>();
+    for (membrane, b, a) in pair_up(&membranes(before), &membranes(after)) {
+        let empty = serialize::Membrane { membrane_channels: vec![], capacitance_farads_per_square_cm: 0.0 };
+        let (b, a) = (b.unwrap_or(&empty), a.unwrap_or(&empty));
+        if b.capacitance_farads_per_square_cm != a.capacitance_farads_per_square_cm {
+            changes.push(Change::CapacitanceChanged {
+                neuron,
+                membrane,
+                from: b.capacitance_farads_per_square_cm,
+                to: a.capacitance_farads_per_square_cm,
+            });
+        }
+        let channels = |m: &serialize::Membrane| m.membrane_channels.iter().cloned().enumerate().collect::<BTreeMap<_, _>>();
+        for (channel, b, a) in pair_up(&channels(b), &channels(a)) {
+            match (b, a) {
+                (Some(_), None) => changes.push(Change::ChannelRemoved { neuron, membrane, channel }),
+                (None, Some(_)) => changes.push(Change::ChannelAdded { neuron, membrane, channel }),
+                (Some(b), Some(a)) => {
+                    if !same(&b.channel, &a.channel) {
+                        changes.push(Change::ChannelKineticsChanged { neuron, membrane, channel });
+                    }
+                    if b.siemens_per_square_cm != a.siemens_per_square_cm {
+                        changes.push(Change::ConductanceChanged {
+                            neuron,
+                            membrane,
+                            channel,
+                            from: b.siemens_per_square_cm,
+                            to: a.siemens_per_square_cm,
+                        });
+                    }
+                    if b.density != a.density {
+                        changes.push(Change::DensityRuleChanged {
+                            neuron,
+                            membrane,
+                            channel,
+                            from: b.density.clone(),
+                            to: a.density.clone(),
+                        });
+                    }
+                },
+                (None, None) => {},
+            }
+        }
+    }
+
+    let stimulators = |n: &serialize::SceneNeuron| {
+        n.stimulator_segments.iter().map(|s| (s.segment as i32, s.stimulator.clone())).collect::<BTreeMap<_, _>>()
+    };
+    for (segment, b, a) in pair_up(&stimulators(before), &stimulators(after)) {
+        let at = SegmentRef { neuron, segment };
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::StimulatorRemoved { at }),
+            (None, Some(_)) => changes.push(Change::StimulatorAdded { at }),
+            (Some(b), Some(a)) if !same(b, a) => changes.push(Change::StimulatorChanged { at }),
+            _ => {},
+        }
+    }
+
+    let thermal = |n: &serialize::SceneNeuron| {
+        n.thermal_segments.iter().map(|s| (s.segment as i32, s.clone())).collect::<BTreeMap<_, _>>()
+    };
+    for (segment, b, a) in pair_up(&thermal(before), &thermal(after)) {
+        let at = SegmentRef { neuron, segment };
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::ThermalRemoved { at }),
+            (None, Some(_)) => changes.push(Change::ThermalAdded { at }),
+            (Some(b), Some(a)) if b != a => changes.push(Change::ThermalChanged { at }),
+            _ => {},
+        }
+    }
+
+    let overrides = |n: &serialize::SceneNeuron| {
+        n.membrane_overrides.iter().map(|o| (o.segment as i32, o.membrane.clone())).collect::<BTreeMap<_, _>>()
+    };
+    for (segment, b, a) in pair_up(&overrides(before), &overrides(after)) {
+        let at = SegmentRef { neuron, segment };
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::MembraneOverrideRemoved { at }),
+            (None, Some(_)) => changes.push(Change::MembraneOverrideAdded { at }),
+            (Some(b), Some(a)) if !same(b, a) => changes.push(Change::MembraneOverrideChanged { at }),
+            _ => {},
+        }
+    }
+}
+
+/// Pairs of the items of `before` and `after` at each position, with
+/// `None` past the end of the shorter.
+fn by_position<'a, T>(before: &'a [T], after: &'a [T]) -> impl Iterator<Item = (usize, Option<&'a T>, Option<&'a T>)> + 'a {
+    (0..before.len().max(after.len())).map(move |i| (i, before.get(i), after.get(i)))
+}
+
+/// The changes that turn `before` into `after`, neuron by neuron, then
+/// synapses, lights, projectors, constants and the seed.
+pub fn diff(before: &serialize::Scene, after: &serialize::Scene) -> Vec<Change> {
+    let mut changes = vec![];
+    let n = before.neurons.len().max(after.neurons.len());
+    for neuron in 0..n {
+        match (before.neurons.get(neuron), after.neurons.get(neuron)) {
+            (Some(b), Some(a)) => diff_neuron(neuron, b, a, &mut changes),
+            (Some(_), None) => changes.push(Change::NeuronRemoved { neuron }),
+            (None, Some(_)) => changes.push(Change::NeuronAdded { neuron }),
+            (None, None) => {},
+        }
+    }
+
+    let synapses = |scene: &serialize::Scene| {
+        scene
+            .synapses
+            .iter()
+            .map(|s| {
+                let pre = SegmentRef { neuron: s.pre_neuron, segment: s.pre_segment as i32 };
+                let post = SegmentRef { neuron: s.post_neuron, segment: s.post_segment as i32 };
+                ((pre.neuron, pre.segment, post.neuron, post.segment), s.synapse_membranes.clone())
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    for ((pre_neuron, pre_segment, post_neuron, post_segment), b, a) in pair_up(&synapses(before), &synapses(after)) {
+        let pre = SegmentRef { neuron: pre_neuron, segment: pre_segment };
+        let post = SegmentRef { neuron: post_neuron, segment: post_segment };
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::SynapseRemoved { pre, post }),
+            (None, Some(_)) => changes.push(Change::SynapseAdded { pre, post }),
+            (Some(b), Some(a)) if !same(b, a) => changes.push(Change::SynapseChanged { pre, post }),
+            _ => {},
+        }
+    }
+
+    for (light, b, a) in by_position(&before.lights, &after.lights) {
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::LightRemoved { light }),
+            (None, Some(_)) => changes.push(Change::LightAdded { light }),
+            (Some(b), Some(a)) if b != a => changes.push(Change::LightChanged { light }),
+            _ => {},
+        }
+    }
+    for (projector, b, a) in by_position(&before.projectors, &after.projectors) {
+        match (b, a) {
+            (Some(_), None) => changes.push(Change::ProjectorRemoved { projector }),
+            (None, Some(_)) => changes.push(Change::ProjectorAdded { projector }),
+            (Some(b), Some(a)) if b != a => changes.push(Change::ProjectorChanged { projector }),
+            _ => {},
+        }
+    }
+
+    if !same(&before.constants, &after.constants) {
+        changes.push(Change::ConstantsChanged);
+    }
+    if before.seed != after.seed {
+        changes.push(Change::SeedChanged { from: before.seed, to: after.seed });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::sample;
+    use crate::noise::NoisePreset;
+    use crate::optogenetics::LightSource;
+    use crate::projector::{Pattern, Projector};
+    use crate::thermal::ThermalStimulator;
+
+    /// An edit to a scene, for cases listed with the change they make.
+    type Edit = fn(&mut serialize::Scene);
+
+    #[test]
+    fn identical_scenes_have_no_changes() {
+        let scene = sample::scene2();
+        assert!(diff(&scene, &scene.clone()).is_empty());
+    }
+
+    #[test]
+    fn reports_segments_conductances_and_stimulators() {
+        let before = sample::scene2();
+        let mut after = before.clone();
+        let neuron = &mut after.neurons[0];
+        let removed = neuron.neuron.segments.pop().unwrap().id;
+        let old = neuron.neuron.membranes[0].membrane_channels[0].siemens_per_square_cm;
+        neuron.neuron.membranes[0].membrane_channels[0].siemens_per_square_cm = old * 2.0;
+        let stimulators = neuron.stimulator_segments.len();
+        neuron.stimulator_segments.clear();
+
+        let changes = diff(&before, &after);
+        assert!(changes.contains(&Change::SegmentRemoved { at: SegmentRef { neuron: 0, segment: removed } }));
+        assert!(changes.contains(&Change::ConductanceChanged { neuron: 0, membrane: 0, channel: 0, from: old, to: old * 2.0 }));
+        let removed_stimulators = changes.iter().filter(|c| matches!(c, Change::StimulatorRemoved { .. })).count();
+        assert_eq!(removed_stimulators, stimulators);
+    }
+
+    #[test]
+    fn reports_rules_heat_noise_tags_lights_projectors_and_seeds() {
+        let before = sample::scene2();
+        let at = SegmentRef { neuron: 0, segment: before.neurons[0].neuron.segments[0].id };
+        let rule = Some("1e-3 * exp(d / 100)".to_string());
+        let pattern = Pattern { width: 1, height: 1, frames: vec![vec![255]] };
+        let cases: [(Edit, Change); 7] = [
+            (
+                |s| s.neurons[0].neuron.membranes[0].membrane_channels[0].density = Some("1e-3 * exp(d / 100)".to_string()),
+                Change::DensityRuleChanged { neuron: 0, membrane: 0, channel: 0, from: None, to: rule },
+            ),
+            (
+                |s| {
+                    let segment = s.neurons[0].neuron.segments[0].id as u32;
+                    s.neurons[0].thermal_segments.push(ThermalStimulator::default().serialize(segment));
+                },
+                Change::ThermalAdded { at },
+            ),
+            (
+                |s| {
+                    let segment = s.neurons[0].neuron.segments[0].id as u32;
+                    let membrane = s.neurons[0].neuron.membranes[0].clone();
+                    s.neurons[0].membrane_overrides.push(serialize::MembraneOverride { segment, membrane });
+                },
+                Change::MembraneOverrideAdded { at },
+            ),
+            (|s| s.neurons[0].noise = Some(NoisePreset::Background.noise()), Change::NoiseChanged { neuron: 0 }),
+            (
+                |s| s.neurons[0].tag = Some("pyramidal".to_string()),
+                Change::NeuronRetagged { neuron: 0, from: None, to: Some("pyramidal".to_string()) },
+            ),
+            (|s| s.lights.push(LightSource::default().serialize()), Change::LightAdded { light: 0 }),
+            (|s| s.seed = Some(7), Change::SeedChanged { from: None, to: Some(7) }),
+        ];
+        for (edit, expected) in cases {
+            let mut after = before.clone();
+            edit(&mut after);
+            assert_eq!(diff(&before, &after), vec![expected]);
+        }
+
+        let mut heated = before.clone();
+        heated.neurons[0].thermal_segments.push(ThermalStimulator::default().serialize(at.segment as u32));
+        let mut hotter = heated.clone();
+        hotter.neurons[0].thermal_segments[0].rise_kelvin += 1.0;
+        assert_eq!(diff(&heated, &hotter), vec![Change::ThermalChanged { at }]);
+        assert_eq!(diff(&heated, &before), vec![Change::ThermalRemoved { at }]);
+
+        let mut lit = before.clone();
+        lit.lights.push(LightSource::default().serialize());
+        let mut brighter = lit.clone();
+        brighter.lights[0].irradiance_mw_per_square_mm *= 2.0;
+        assert_eq!(diff(&lit, &brighter), vec![Change::LightChanged { light: 0 }]);
+        assert_eq!(diff(&lit, &before), vec![Change::LightRemoved { light: 0 }]);
+
+        let mut projecting = before.clone();
+        projecting.projectors.push(Projector::new(pattern).serialize());
+        assert_eq!(diff(&before, &projecting), vec![Change::ProjectorAdded { projector: 0 }]);
+        let mut turned = projecting.clone();
+        turned.projectors[0].direction = [1.0, 0.0, 0.0];
+        assert_eq!(diff(&projecting, &turned), vec![Change::ProjectorChanged { projector: 0 }]);
+        assert_eq!(diff(&projecting, &before), vec![Change::ProjectorRemoved { projector: 0 }]);
+    }
+}
@@ -0,0 +1,60 @@
+// Draws every segment of a batch from one unit mesh. Each instance brings
+// its model matrix, color, emissive glow and clip plane; fragments beyond
+// the clip plane are discarded, and the rest are lit by a headlight.
+
+#import bevy_pbr::mesh_view_bindings::view
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+
+    @location(3) model_x: vec4<f32>,
+    @location(4) model_y: vec4<f32>,
+    @location(5) model_z: vec4<f32>,
+    @location(6) model_w: vec4<f32>,
+    @location(7) color: vec4<f32>,
+    @location(8) emissive: vec4<f32>,
+    // Unit normal and offset; all zero clips nothing.
+    @location(9) clip_plane: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+    @location(4) clip_plane: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let model = mat4x4<f32>(vertex.model_x, vertex.model_y, vertex.model_z, vertex.model_w);
+    let world_position = model * vec4<f32>(vertex.position, 1.0);
+    // The model is a rotation times a scale, so dividing by the squared
+    // scale gives the inverse transpose.
+    let scale_sq = vec3<f32>(
+        dot(vertex.model_x.xyz, vertex.model_x.xyz),
+        dot(vertex.model_y.xyz, vertex.model_y.xyz),
+        dot(vertex.model_z.xyz, vertex.model_z.xyz),
+    );
+
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.world_normal = normalize((model * vec4<f32>(vertex.normal / scale_sq, 0.0)).xyz);
+    out.color = vertex.color;
+    out.emissive = vertex.emissive;
+    out.clip_plane = vertex.clip_plane;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    if dot(in.clip_plane.xyz, in.world_position) > in.clip_plane.w {
+        discard;
+    }
+    let to_camera = normalize(view.world_position - in.world_position);
+    let light = 0.3 + 0.7 * abs(dot(normalize(in.world_normal), to_camera));
+    return vec4<f32>(in.color.rgb * light + in.emissive.rgb * view.exposure, 1.0);
+}
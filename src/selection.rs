@@ -1,5 +1,18 @@
+//! Which segments the GUI's panels act on.
+//!
+//! Clicking a stimulator selects its segment alone. Shift-clicking a
+//! segment adds it to the selection or takes it out, and dragging a
+//! rectangle with Ctrl held selects every segment whose centre falls
+//! inside it, adding to the selection if Shift is held too, so that a
+//! whole dendritic branch can be selected at once.
+
 use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::{egui, EguiContexts};
 use bevy_mod_picking::PickableBundle;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::neuron::segment::ecs::Segment;
 
 #[derive(Component)]
 pub struct Selection;
@@ -30,3 +43,115 @@ pub fn spawn_highlight(
     )).id();
     commands.entity(selected_entity).push_children(&[highlight_entity]);
 }
+
+/// Select `entity`, marking it with a highlight.
+pub fn select(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    entity: Entity,
+) {
+    spawn_highlight(commands, meshes, materials, entity);
+    commands.entity(entity).insert(Selection);
+}
+
+/// Take `entity` out of the selection, with its highlight.
+pub fn deselect(
+    commands: &mut Commands,
+    highlights: &Query<(Entity, &Parent), With<Highlight>>,
+    entity: Entity,
+) {
+    commands.entity(entity).remove::<Selection>();
+    for (highlight, parent) in highlights {
+        if parent.get() == entity {
+            commands.entity(highlight).despawn();
+        }
+    }
+}
+
+fn shift_held(keys: &ButtonInput<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+}
+
+/// Add a segment to the selection, or take it out, if Shift is held
+/// while it is clicked. Returns whether it was.
+pub fn toggle_on_shift_click(
+    commands: &mut Commands,
+    keys: &ButtonInput<KeyCode>,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    selections: &Query<Entity, With<Selection>>,
+    highlights: &Query<(Entity, &Parent), With<Highlight>>,
+    entity: Entity,
+) -> bool {
+    if !shift_held(keys) {
+        return false;
+    }
+    if selections.contains(entity) {
+        deselect(commands, highlights, entity);
+    } else {
+        select(commands, meshes, materials, entity);
+    }
+    true
+}
+
+/// Select the segments inside a rectangle dragged with Ctrl held, drawing
+/// the rectangle meanwhile. The orbit camera is held still during the
+/// drag.
+pub fn box_select(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&Camera, &GlobalTransform, Option<&mut PanOrbitCamera>), With<Camera3d>>,
+    segments: Query<(Entity, &GlobalTransform, Has<Selection>), With<Segment>>,
+    highlights: Query<(Entity, &Parent), With<Highlight>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut drag_start: Local<Option<Vec2>>,
+) {
+    let Some(cursor) = windows.get_single().ok().and_then(Window::cursor_position) else {
+        return;
+    };
+    let Some((camera, camera_transform, mut orbit)) = cameras.iter_mut().next() else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    if mouse.just_pressed(MouseButton::Left)
+        && keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+        && !ctx.wants_pointer_input()
+    {
+        *drag_start = Some(cursor);
+    }
+    let Some(start) = *drag_start else {
+        return;
+    };
+    if let Some(orbit) = orbit.as_mut() {
+        orbit.enabled = !mouse.pressed(MouseButton::Left);
+    }
+    let rect = Rect::from_corners(start, cursor);
+
+    if mouse.pressed(MouseButton::Left) {
+        ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("box_select")))
+            .rect_stroke(
+                egui::Rect::from_min_max(egui::pos2(rect.min.x, rect.min.y), egui::pos2(rect.max.x, rect.max.y)),
+                0.0,
+                egui::Stroke::new(1.0, egui::Color32::WHITE),
+            );
+        return;
+    }
+
+    *drag_start = None;
+    let adding = shift_held(&keys);
+    for (entity, transform, selected) in &segments {
+        let inside = camera
+            .world_to_viewport(camera_transform, transform.translation())
+            .is_some_and(|point| rect.contains(point));
+        if inside && !selected {
+            select(&mut commands, &mut meshes, &mut materials, entity);
+        } else if !inside && selected && !adding {
+            deselect(&mut commands, &highlights, entity);
+        }
+    }
+}
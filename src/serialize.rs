@@ -5,6 +5,41 @@ pub struct Scene {
     // pub extracellular_solution: Solution,
     pub neurons: Vec<SceneNeuron>,
     pub synapses: Vec<Synapse>,
+    #[serde(default)]
+    pub constants: Option<SimulationConstants>,
+    /// Lights for the scene's light-gated channels.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<LightSource>,
+    /// Projectors casting patterns onto the scene's light-gated channels.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub projectors: Vec<Projector>,
+    /// The seed of every random draw in a run, so that runs of the scene
+    /// repeat exactly. Scenes without one use seed 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// A scene saved automatically, with the simulation time it was saved at.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub saved_at_unix_seconds: f64,
+    pub simulation_time_seconds: f32,
+    pub scene: Scene,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationConstants {
+    pub temperature_kelvin: f32,
+    pub gas_constant: f32,
+    pub faraday: f32,
+    /// Scenes from before segments had real geometry carry a
+    /// `junction_conductance_per_square_cm` instead, which is ignored.
+    #[serde(default = "default_axial_resistivity")]
+    pub axial_resistivity_ohm_cm: f32,
+}
+
+fn default_axial_resistivity() -> f32 {
+    crate::constants::AXIAL_RESISTIVITY_OHM_CM
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -12,9 +47,22 @@ pub struct SceneNeuron {
     pub neuron: Neuron,
     pub location: Location,
     pub stimulator_segments: Vec<StimulatorSegment>,
+    /// The population the neuron belongs to, for grouping in the raster.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Segments heated by thermal stimulators.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thermal_segments: Vec<ThermalSegment>,
+    /// Membrane noise injected into every segment; see `noise`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<MembraneNoise>,
+    /// Segments whose membranes differ from the membrane of their type,
+    /// such as after editing one segment's channels.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub membrane_overrides: Vec<MembraneOverride>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Location {
     pub x_mm: f32,
     pub y_mm: f32,
@@ -27,20 +75,102 @@ pub struct StimulatorSegment {
     pub segment: u32,
 }
 
+/// The membrane of one segment, in place of the membrane of its type.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MembraneOverride {
+    pub segment: u32,
+    pub membrane: Membrane,
+}
+
+/// A segment heated over an envelope, as by an infrared laser.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThermalSegment {
+    pub segment: u32,
+    pub envelope: Envelope,
+    /// Rise above the scene's temperature during the envelope.
+    pub rise_kelvin: f32,
+}
+
+/// Ornstein–Uhlenbeck current noise: a background synaptic current and a
+/// fast, zero-mean surrogate for channel noise.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MembraneNoise {
+    pub mean_uamps_per_square_cm: f32,
+    pub background_sigma_uamps_per_square_cm: f32,
+    pub background_tau_sec: f32,
+    pub channel_sigma_uamps_per_square_cm: f32,
+    pub channel_tau_sec: f32,
+}
+
+/// A light spot, shining in pulses; see `optogenetics`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightSource {
+    /// The center of the spot, in the frame neurons are placed in.
+    pub location: Location,
+    /// Irradiance at the center of the spot while a pulse is on.
+    pub irradiance_mw_per_square_mm: f32,
+    /// Standard deviation of the spot's Gaussian profile.
+    pub spread_um: f32,
+    pub wavelength_nm: f32,
+    /// Each period, the light is on from onset to offset.
+    pub envelope: Envelope,
+    /// The number of pulses before the light stays off; unlimited if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pulses: Option<u32>,
+}
+
+/// A projector casting a grayscale image or movie onto the scene, in
+/// pulses; see `projector`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Projector {
+    /// The center of the image, in the frame neurons are placed in.
+    pub location: Location,
+    /// The way the light travels, as x, y and z; need not be normalized.
+    pub direction: [f32; 3],
+    pub width_um: f32,
+    pub height_um: f32,
+    /// Irradiance where the image is white, while a pulse is on.
+    pub irradiance_mw_per_square_mm: f32,
+    pub wavelength_nm: f32,
+    pub pattern: Pattern,
+    /// How long each frame of a movie shows.
+    #[serde(default)]
+    pub frame_interval_sec: f32,
+    /// Whether a movie starts over after its last frame, rather than going
+    /// dark.
+    #[serde(default)]
+    pub looping: bool,
+    /// Each period, the projector is on from onset to offset.
+    pub envelope: Envelope,
+    /// The number of pulses before the projector stays off; unlimited if
+    /// absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pulses: Option<u32>,
+}
+
+/// The frames of a grayscale image or movie, each row by row from the top,
+/// from 0 for black to 255 for white.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Stimulator {
     pub envelope: Envelope,
     pub current_shape: CurrentShape,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Envelope {
     pub period_sec: f32,
     pub onset_sec: f32,
     pub offset_sec: f32
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag="type")]
 pub enum CurrentShape {
     SquareWave {
@@ -57,10 +187,97 @@ pub enum CurrentShape {
         offset_current_uamps_per_square_cm: f32,
         start_frequency_hz: f32,
         end_frequency_hz: f32,
-    }
+    },
+    /// Pulses at random times, repeatable from the scene's `seed`.
+    Poisson {
+        rate_hz: f32,
+        pulse_current_uamps_per_square_cm: f32,
+        pulse_width_sec: f32,
+        off_current_uamps_per_square_cm: f32,
+    },
+    /// A stimulus source registered by another crate; see `mechanism`.
+    Custom(CustomMechanism),
+}
+
+/// A mechanism registered by another crate, by its registered name, with
+/// parameters it interprets itself. See `mechanism`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CustomMechanism {
+    pub name: String,
+    #[serde(default)]
+    pub parameters: serde_json::Value,
 }
 
 
+
+/// Stimulators, oscilloscope probes and recorders, located by segment
+/// rather than by entity, so that they can be applied to any scene whose
+/// neurons have segments with the same SWC ids.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Layout {
+    #[serde(default)]
+    pub stimulators: Vec<LayoutStimulator>,
+    #[serde(default)]
+    pub probes: Vec<LayoutProbe>,
+    #[serde(default)]
+    pub recorders: Vec<LayoutRecorder>,
+}
+
+/// A segment, by the index of its neuron in the scene and its SWC id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SegmentRef {
+    pub neuron: usize,
+    pub segment: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayoutStimulator {
+    pub at: SegmentRef,
+    pub stimulator: Stimulator,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayoutProbe {
+    /// Oscilloscope channel, from 0.
+    pub channel: usize,
+    pub at: SegmentRef,
+    #[serde(default)]
+    pub quantity: ProbedQuantity,
+}
+
+/// What an oscilloscope channel shows of its segment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ProbedQuantity {
+    #[default]
+    Voltage,
+    StimulatorCurrent,
+    /// The open conductance of the membrane channel at this index.
+    Conductance { channel: usize },
+    Glutamate,
+    Gaba,
+    /// The local field potential at an extracellular electrode.
+    Lfp,
+    /// The K+ concentration around the segment.
+    ExtracellularK,
+    /// The free Ca2+ beneath the segment's membrane.
+    ShellCalcium,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayoutRecorder {
+    pub at: SegmentRef,
+    pub path: String,
+    pub quantities: Vec<RecordedQuantity>,
+    pub decimation: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum RecordedQuantity {
+    Voltage,
+    GateMagnitudes,
+    TransmitterConcentration,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Neuron {
     pub segments: Vec<Segment>,
@@ -101,7 +318,11 @@ pub struct Membrane {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MembraneChannel {
     pub channel: Channel,
-    pub siemens_per_square_cm: f32
+    pub siemens_per_square_cm: f32,
+    // A formula for the conductance (S/cm²) in `d`, the segment's path
+    // distance (µm) from the soma, in place of `siemens_per_square_cm`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<String>,
 }
 
 
@@ -113,6 +334,29 @@ pub struct Channel {
   pub inactivation: Option<GatingParameters>,
   // Permiability of the channel to each ion, when activation magnitude is 1 and inactivation magnitude is 0.
   pub ion_selectivity: IonSelectivity,
+  // Gating by a model registered by another crate, on top of any gates above.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub custom: Option<CustomMechanism>,
+  // Gating by light, on top of any gates above.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub opsin: Option<Opsin>,
+  // Gating by submembrane calcium, on top of any gates above.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub calcium: Option<CalciumSensitivity>,
+}
+
+/// How a channel opens with calcium; see `neuron::calcium`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalciumSensitivity {
+    pub half_activation_molar: f32,
+    pub hill: f32,
+}
+
+/// A light-gated channel; see `neuron::opsin`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Opsin {
+    ChR2,
+    NpHR,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -134,10 +378,18 @@ pub struct GatingParameters {
   pub time_constant: TimeConstant,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Geometry {
-    pub diameter_cm: f32,
-    pub length_cm: f32,
+/// A segment's shape; see `neuron::geometry`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag="type")]
+pub enum Geometry {
+    Frustum {
+        start_diameter_cm: f32,
+        end_diameter_cm: f32,
+        length_cm: f32,
+    },
+    Sphere {
+        diameter_cm: f32,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -170,7 +422,23 @@ pub struct SynapseMembranes {
     pub transmitter_concentrations: TransmitterConcentrations,
     pub presynaptic_pumps: Vec<TransmitterPump>,
     pub postsynaptic_receptors: Vec<Receptor>,
-    pub surface_area_square_mm: f32
+    pub surface_area_square_mm: f32,
+    /// A mechanism registered by another crate, alongside the receptors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom: Option<CustomMechanism>,
+    /// Calcium-controlled plasticity, with the weight it has reached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plasticity: Option<Plasticity>,
+}
+
+/// See `neuron::synapse::Plasticity`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plasticity {
+    pub weight: f32,
+    pub depression_threshold_molar: f32,
+    pub potentiation_threshold_molar: f32,
+    pub rate_per_second: f32,
+    pub max_weight: f32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -213,6 +481,14 @@ pub struct TransmitterPumpParams {
 pub struct Receptor {
     pub membrane_channel: MembraneChannel,
     pub neurotransmitter_sensitivity: Sensitivity,
+    /// Voltage-dependent block by magnesium, for NMDA receptors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub magnesium_block: Option<MagnesiumBlock>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MagnesiumBlock {
+    pub magnesium_molar: f32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -222,7 +498,7 @@ pub struct Sensitivity {
     pub slope: f32,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Solution {
     // Na+ concentration (Molars).
     pub na: f32,
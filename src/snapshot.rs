@@ -0,0 +1,159 @@
+//! JSON snapshots of the running simulation, for dashboards that poll it.
+//!
+//! `publish_snapshot` publishes a `Snapshot` here once a frame, whenever
+//! the simulation has changed (see `gui::external_trigger`), and the query
+//! functions answer from the latest one without touching the ECS, so a
+//! poll sees the state of at most a frame ago. Their answers mirror REST
+//! routes: `time` for `/time`, `state` for `/state`, and `voltage` for
+//! `/voltage/:segment`. A `POST /scene` is `validate::checked_scene`
+//! followed by `Command::LoadScene`.
+
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::command::SimulationControl;
+use crate::dimension::{StepCount, Timestamp};
+use crate::integrations::grace::SceneSegment;
+use crate::neuron::membrane::MembraneVoltage;
+use crate::serialize::SegmentRef;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub t_ms: f32,
+    pub step: u64,
+    pub paused: bool,
+    pub voltages: Vec<SegmentVoltage>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SegmentVoltage {
+    pub neuron: usize,
+    /// SWC id.
+    pub segment: i32,
+    pub v_mv: f32,
+}
+
+static LATEST: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+pub fn publish(snapshot: Snapshot) {
+    *LATEST.lock().expect("Snapshot lock should not be poisoned") = Some(snapshot);
+}
+
+/// Publish the simulation's state whenever the time, the controls or a
+/// membrane potential has changed since the last frame.
+pub fn publish_snapshot(
+    timestamp: Res<Timestamp>,
+    step_count: Res<StepCount>,
+    control: Res<SimulationControl>,
+    segments: Query<(&SceneSegment, Ref<MembraneVoltage>)>,
+) {
+    let changed = timestamp.is_changed() || control.is_changed() || segments.iter().any(|(_, v)| v.is_changed());
+    if !changed {
+        return;
+    }
+    publish(Snapshot {
+        t_ms: timestamp.0 * 1000.0,
+        step: step_count.0,
+        paused: control.paused,
+        voltages: segments
+            .iter()
+            .map(|(SceneSegment(at), v)| SegmentVoltage { neuron: at.neuron, segment: at.segment, v_mv: v.0.0 })
+            .collect(),
+    });
+}
+
+fn with_latest<R>(f: impl FnOnce(&Snapshot) -> Result<R, String>) -> Result<R, String> {
+    let latest = LATEST.lock().map_err(|e| e.to_string())?;
+    f(latest.as_ref().ok_or("No simulation is running".to_string())?)
+}
+
+impl Snapshot {
+    pub fn time(&self) -> serde_json::Value {
+        json!({ "t_ms": self.t_ms, "step": self.step, "paused": self.paused })
+    }
+
+    pub fn voltage(&self, at: SegmentRef) -> Result<serde_json::Value, String> {
+        self.voltages
+            .iter()
+            .find(|v| v.neuron == at.neuron && v.segment == at.segment)
+            .map(|v| json!({ "t_ms": self.t_ms, "neuron": v.neuron, "segment": v.segment, "v_mv": v.v_mv }))
+            .ok_or(format!("No segment {} in neuron {}", at.segment, at.neuron))
+    }
+
+    pub fn state(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| e.to_string())
+    }
+}
+
+/// The latest snapshot itself, for `grpc` to translate.
+pub fn latest() -> Result<Snapshot, String> {
+    with_latest(|s| Ok(s.clone()))
+}
+
+/// `/time`: the simulation time and step.
+pub fn time() -> Result<String, String> {
+    with_latest(|s| Ok(s.time().to_string()))
+}
+
+/// `/state`: the time and every segment's membrane potential.
+pub fn state() -> Result<String, String> {
+    with_latest(Snapshot::state)
+}
+
+/// `/voltage/:segment`: one segment's membrane potential.
+pub fn voltage(at: SegmentRef) -> Result<String, String> {
+    with_latest(|s| Ok(s.voltage(at)?.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimension::MilliVolts;
+
+    #[test]
+    fn answers_from_a_snapshot() {
+        let snapshot = Snapshot {
+            t_ms: 1.5,
+            step: 60,
+            paused: false,
+            voltages: vec![SegmentVoltage { neuron: 0, segment: 1, v_mv: -65.0 }],
+        };
+        assert_eq!(snapshot.time()["step"], 60);
+        assert_eq!(snapshot.voltage(SegmentRef { neuron: 0, segment: 1 }).unwrap()["v_mv"], -65.0);
+        assert!(snapshot.voltage(SegmentRef { neuron: 1, segment: 1 }).is_err());
+        let state: Snapshot = serde_json::from_str(&snapshot.state().unwrap()).unwrap();
+        assert_eq!(state, snapshot);
+    }
+
+    #[test]
+    fn publishes_each_frame_the_simulation_changes() {
+        let mut app = App::new();
+        app.insert_resource(Timestamp(0.0))
+            .init_resource::<StepCount>()
+            .init_resource::<SimulationControl>()
+            .add_systems(Update, publish_snapshot);
+        let at = SegmentRef { neuron: 0, segment: 1 };
+        let segment = app.world.spawn((SceneSegment(at), MembraneVoltage(MilliVolts(-70.0)))).id();
+        app.update();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&time().unwrap()).unwrap()["t_ms"], 0.0);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&voltage(at).unwrap()).unwrap()["v_mv"], -70.0);
+        assert!(voltage(SegmentRef { neuron: 0, segment: 2 }).is_err());
+
+        // Unread snapshots are replaced all the same.
+        app.world.resource_mut::<Timestamp>().0 = 1e-3;
+        app.world.get_mut::<MembraneVoltage>(segment).unwrap().0 = MilliVolts(-60.0);
+        app.update();
+        app.world.resource_mut::<Timestamp>().0 = 2e-3;
+        app.update();
+        let state: Snapshot = serde_json::from_str(&state().unwrap()).unwrap();
+        assert_eq!((state.t_ms, state.voltages[0].v_mv), (2.0, -60.0));
+
+        // A frame in which nothing changed publishes nothing.
+        publish(Snapshot::default());
+        app.update();
+        assert_eq!(latest().unwrap(), Snapshot::default());
+    }
+}
@@ -0,0 +1,175 @@
+//! Replaying recorded spike trains as stimulation.
+//!
+//! A spike-times file lists one unit per row (whitespace- or
+//! comma-separated times) or one unit per column (CSV, with an optional
+//! header and blank cells where a unit has no more spikes). Each unit can
+//! drive a segment with a current pulse per spike, or a synapse, whose
+//! presynaptic terminal is held depolarized for the pulse so that it
+//! releases transmitter.
+
+use bevy::prelude::Component;
+
+use crate::dimension::{Interval, MicroAmpsPerSquareCm, MilliVolts};
+
+/// Presynaptic potential during a replayed spike, well above the release
+/// threshold of the transmitter pumps.
+pub const PRESYNAPTIC_SPIKE: MilliVolts = MilliVolts(30.0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrainLayout {
+    /// Each line is a unit.
+    Rows,
+    /// Each column is a unit.
+    Columns,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl TimeUnit {
+    fn to_seconds(self, t: f32) -> f32 {
+        match self {
+            TimeUnit::Seconds => t,
+            TimeUnit::Milliseconds => t * 1e-3,
+        }
+    }
+}
+
+/// Spike times of each unit, in seconds, sorted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpikeTrains {
+    pub units: Vec<Vec<f32>>,
+}
+
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+}
+
+pub fn parse(source: &str, layout: TrainLayout, unit: TimeUnit) -> Result<SpikeTrains, String> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    let parse_time = |i: usize, field: &str| {
+        field
+            .parse::<f32>()
+            .map(|t| unit.to_seconds(t))
+            .map_err(|e| format!("Line {}: {}: {}", i + 1, field, e))
+    };
+
+    let mut units: Vec<Vec<f32>> = vec![];
+    match layout {
+        TrainLayout::Rows => {
+            for (i, line) in lines {
+                let times = fields(line)
+                    .filter(|f| !f.is_empty())
+                    .map(|f| parse_time(i, f))
+                    .collect::<Result<Vec<_>, _>>()?;
+                units.push(times);
+            }
+        },
+        TrainLayout::Columns => {
+            for (row, (i, line)) in lines.enumerate() {
+                // Columns are separated by commas, so blank cells survive.
+                let cells = line.split(',').map(|f| f.trim()).collect::<Vec<_>>();
+                if units.len() < cells.len() {
+                    units.resize(cells.len(), vec![]);
+                }
+                let is_header = row == 0 && cells.iter().any(|c| !c.is_empty() && c.parse::<f32>().is_err());
+                if is_header {
+                    continue;
+                }
+                for (column, cell) in cells.iter().enumerate().filter(|(_, c)| !c.is_empty()) {
+                    units[column].push(parse_time(i, cell)?);
+                }
+            }
+        },
+    }
+    for times in units.iter_mut() {
+        times.sort_by(|a, b| a.total_cmp(b));
+    }
+    Ok(SpikeTrains { units })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Drive {
+    /// Inject `current` into the segment for `duration` after each spike.
+    Pulse { current: MicroAmpsPerSquareCm, duration: Interval },
+    /// Hold the synapse's presynaptic terminal at `PRESYNAPTIC_SPIKE` for
+    /// `duration` after each spike.
+    Presynaptic { duration: Interval },
+}
+
+/// Replays a spike train into the segment or synapse it is attached to.
+#[derive(Clone, Component, Debug)]
+pub struct SpikeTrainInput {
+    /// Spike times (s), sorted.
+    pub times: Vec<f32>,
+    pub drive: Drive,
+}
+
+impl SpikeTrainInput {
+    fn duration(&self) -> f32 {
+        match &self.drive {
+            Drive::Pulse { duration, .. } => duration.0,
+            Drive::Presynaptic { duration } => duration.0,
+        }
+    }
+
+    /// Whether a spike's pulse is under way at time `t` (s).
+    pub fn is_active(&self, t: f32) -> bool {
+        let i = self.times.partition_point(|s| *s <= t);
+        i > 0 && t - self.times[i - 1] < self.duration()
+    }
+
+    /// The current injected at time `t`, for `Drive::Pulse`.
+    pub fn current(&self, t: f32) -> MicroAmpsPerSquareCm {
+        match &self.drive {
+            Drive::Pulse { current, .. } if self.is_active(t) => current.clone(),
+            _ => MicroAmpsPerSquareCm(0.0),
+        }
+    }
+
+    /// The presynaptic potential at time `t`, for `Drive::Presynaptic`,
+    /// or `None` between spikes.
+    pub fn presynaptic_potential(&self, t: f32) -> Option<MilliVolts> {
+        match &self.drive {
+            Drive::Presynaptic { .. } if self.is_active(t) => Some(PRESYNAPTIC_SPIKE),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rows_and_columns() {
+        let rows = parse("# unit per row\n10 30 20\n\n5, 15\n", TrainLayout::Rows, TimeUnit::Milliseconds).unwrap();
+        assert_eq!(rows.units.len(), 2);
+        assert_eq!(rows.units[0].len(), 3);
+        assert!((rows.units[0][2] - 0.03).abs() < 1e-6);
+
+        let columns = parse("a,b\n0.1,0.2\n0.3,\n", TrainLayout::Columns, TimeUnit::Seconds).unwrap();
+        assert_eq!(columns.units, vec![vec![0.1, 0.3], vec![0.2]]);
+
+        assert!(parse("1 x", TrainLayout::Rows, TimeUnit::Seconds).is_err());
+    }
+
+    #[test]
+    fn pulses_follow_each_spike() {
+        let input = SpikeTrainInput {
+            times: vec![0.01, 0.02],
+            drive: Drive::Pulse { current: MicroAmpsPerSquareCm(5.0), duration: Interval(0.001) },
+        };
+        assert_eq!(input.current(0.0).0, 0.0);
+        assert_eq!(input.current(0.0105).0, 5.0);
+        assert_eq!(input.current(0.012).0, 0.0);
+        assert_eq!(input.current(0.0201).0, 5.0);
+        assert!(input.presynaptic_potential(0.0201).is_none());
+    }
+}
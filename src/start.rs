@@ -2,20 +2,16 @@ use bevy::prelude::*;
 use bevy::core_pipeline::bloom::{BloomPlugin, BloomSettings};
 use bevy::diagnostic::{LogDiagnosticsPlugin, FrameTimeDiagnosticsPlugin};
 use bevy::pbr::CascadeShadowConfigBuilder;
-use bevy_egui::EguiPlugin;
-use bevy_mod_picking::prelude::*;
 use bevy_panorbit_camera::{PanOrbitCameraPlugin, PanOrbitCamera};
 use std::f32::consts::PI;
 use wasm_bindgen::prelude::*;
 
 use crate::plugin::NbSimPlugin;
-use crate::gui::run_gui;
-use crate::gui::load::{handle_loaded_neuron, GraceSceneSource, InterpreterUrl};
-use crate::integrations::grace::{self, GraceScene};
-use crate::neuron::membrane::MembraneMaterials;
+use crate::gui::load::{GraceSceneSource, InterpreterUrl};
+use crate::integrations::grace::{self, CurrentScene, GraceScene, SceneVisuals};
 // use bevy_panorbit_camera::{PanOrbitCamera, pan_orbit_camera};
-use crate::selection::{Selection, Highlight};
 use crate::gui::external_trigger::ExternalTriggerPlugin;
+use crate::units::UnitScale;
 
 #[derive(Component)]
 struct MyCamera;
@@ -38,41 +34,39 @@ pub fn start(
       }),
       ..default()
     }))
-        .add_plugins(EguiPlugin)
         .add_plugins(LogDiagnosticsPlugin::default())
         .add_plugins(FrameTimeDiagnosticsPlugin::default())
-        .add_plugins(DefaultPickingPlugins.build().disable::<DebugPickingPlugin>())
         // .add_plugin(DebugCursorPickingPlugin)
         // .add_plugin(DebugEventsPickingPlugin)
-        .add_plugins(NbSimPlugin)
+        .add_plugins(NbSimPlugin::default())
         .add_plugins(ExternalTriggerPlugin)
         .add_plugins(PanOrbitCameraPlugin)
         .add_systems(Update, bevy::window::close_on_esc)
         .add_systems(Startup, setup_scene)
         .insert_resource(InterpreterUrl(interpreter_url))
-        .insert_resource(ClearColor(Color::hex("#0e0e1f").expect("valid hex")))
-        .add_systems(Update, run_gui)
-        .add_systems(Update, handle_loaded_neuron);
+        .insert_resource(ClearColor(Color::hex("#0e0e1f").expect("valid hex")));
 
         if demo {
           app.add_systems(Startup, setup_grace_neuron);
         }
 
+        #[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+        app.add_plugins(crate::grpc::GrpcPlugin);
+
         app.run();
 }
 
 fn setup_grace_neuron(
   commands: Commands,
-  mut meshes: ResMut<Assets<Mesh>>,
-  membrane_materials: Res<MembraneMaterials>,
-  mut materials: ResMut<Assets<StandardMaterial>>,
   grace_scene_source: Res<GraceSceneSource>,
-  selections: Query<Entity, With<Selection>>,
-  highlights: Query<Entity, With<Highlight>>,
+  mut current_scene: ResMut<CurrentScene>,
+  scale: Res<UnitScale>,
+  mut visuals: SceneVisuals,
 ) {
   if grace_scene_source.0.len() == 0 {
     let grace_scene = GraceScene ( grace::sample::scene2() );
-    grace_scene.spawn(Vec3::new(0.0,0.0,0.0), commands, &mut meshes, membrane_materials, &mut materials, selections, highlights);
+    let first_neuron = current_scene.append(&grace_scene.0);
+    grace_scene.spawn(Vec3::new(0.0,0.0,0.0), first_neuron, &scale, commands, &mut visuals);
   }
 }
 
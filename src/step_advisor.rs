@@ -0,0 +1,146 @@
+//! Recommends a simulation step the scene can be integrated at stably.
+//!
+//! Every state variable is integrated with explicit Euler steps, which are
+//! stable only for steps shorter than twice the variable's time constant.
+//! The fastest time constants in a scene come from two places: channel
+//! gates at their fastest voltage, and membranes with every channel fully
+//! open. The recommended step is `STEP_PER_TAU` of the faster of these,
+//! leaving a margin for accuracy as well as stability. Coupling between
+//! segments is solved exactly (`neuron::junction_charge`), so however
+//! fast it is it never limits the step.
+
+use std::fmt::{self, Display};
+
+use crate::dimension::MilliVolts;
+use crate::neuron::channel::{Channel, GateState};
+use crate::neuron::membrane::Membrane;
+
+/// The recommended step, as a fraction of the fastest time constant.
+pub const STEP_PER_TAU: f32 = 0.5;
+
+/// Voltages at which gating time constants are sampled (mV).
+const SAMPLED_VOLTAGES: std::ops::RangeInclusive<i32> = -100..=60;
+
+/// One segment, as the integration step sees it.
+pub struct Compartment<'a> {
+    pub membrane: &'a Membrane,
+    /// Simulation steps per step of this segment; see
+    /// `neuron::multirate`.
+    pub step_multiple: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    /// A channel gate, at the voltage where it is fastest.
+    Gating { at: f32 },
+    /// The membrane time constant with every channel fully open.
+    Membrane,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Advice {
+    /// The recommended maximum step (s).
+    pub max_step: f32,
+    /// The fastest time constant found (s), relative to the simulation
+    /// step: a segment stepping every `n` simulation steps counts `1/n` of
+    /// its time constant.
+    pub fastest_tau: f32,
+    pub limit: Limit,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Gating { at } => write!(f, "channel gating at {} mV", at),
+            Limit::Membrane => write!(f, "the membrane time constant with all channels open"),
+        }
+    }
+}
+
+/// The fastest time constant of a gate, and the voltage where it occurs.
+fn fastest_gate(gate: &GateState) -> Option<(f32, f32)> {
+    SAMPLED_VOLTAGES
+        .filter_map(|v| {
+            let tau = gate.parameters.time_constant.tau(&MilliVolts(v as f32))?;
+            (tau > 0.0).then_some((tau, v as f32))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+pub fn fastest_gating_tau(channel: &Channel) -> Option<(f32, f32)> {
+    channel
+        .activation
+        .iter()
+        .chain(channel.inactivation.iter())
+        .filter_map(fastest_gate)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// The membrane time constant with every channel's gates open (s), as the
+/// integration step applies channel currents.
+pub fn membrane_tau(membrane: &Membrane) -> Option<f32> {
+    let conductance_per_square_cm: f32 = membrane
+        .membrane_channels
+        .iter()
+        .map(|c| {
+            let s = &c.channel.ion_selectivity;
+            c.siemens_per_square_cm * (s.k + s.na + s.ca + s.cl)
+        })
+        .sum();
+    (conductance_per_square_cm > 0.0).then(|| membrane.capacitance.0 / conductance_per_square_cm)
+}
+
+pub fn advise<'a>(compartments: impl IntoIterator<Item = Compartment<'a>>) -> Option<Advice> {
+    let mut fastest: Option<(f32, Limit)> = None;
+    let mut consider = |tau: Option<f32>, limit: Limit| {
+        if let Some(tau) = tau.filter(|t| t.is_finite() && *t > 0.0) {
+            if fastest.is_none_or(|(f, _)| tau < f) {
+                fastest = Some((tau, limit));
+            }
+        }
+    };
+    for compartment in compartments {
+        let per_step = 1.0 / compartment.step_multiple.max(1) as f32;
+        for membrane_channel in &compartment.membrane.membrane_channels {
+            if let Some((tau, at)) = fastest_gating_tau(&membrane_channel.channel) {
+                consider(Some(tau * per_step), Limit::Gating { at });
+            }
+        }
+        consider(membrane_tau(compartment.membrane).map(|t| t * per_step), Limit::Membrane);
+    }
+    fastest.map(|(tau, limit)| Advice { max_step: tau * STEP_PER_TAU, fastest_tau: tau, limit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimension::FaradsPerSquareCm;
+    use crate::neuron::channel::common_channels::giant_squid;
+    use crate::neuron::membrane::MembraneChannel;
+
+    #[test]
+    fn fastest_process_limits_the_step() {
+        let v = MilliVolts(-70.0);
+        let membrane = Membrane {
+            membrane_channels: vec![
+                MembraneChannel { channel: giant_squid::NA_CHANNEL.build(&v), siemens_per_square_cm: 120e-3 },
+                MembraneChannel { channel: giant_squid::K_CHANNEL.build(&v), siemens_per_square_cm: 36e-3 },
+            ],
+            capacitance: FaradsPerSquareCm(1e-6),
+        };
+        let every_step = Compartment { membrane: &membrane, step_multiple: 1 };
+        let advice = advise([every_step]).unwrap();
+        assert!(matches!(advice.limit, Limit::Gating { .. } | Limit::Membrane));
+        assert!(advice.max_step > 0.0 && advice.max_step < 1e-3);
+
+        let slow = Compartment { membrane: &membrane, step_multiple: 10 };
+        assert!((advise([slow]).unwrap().max_step - advice.max_step / 10.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn membranes_without_channels_give_no_advice() {
+        let membrane = Membrane { membrane_channels: vec![], capacitance: FaradsPerSquareCm(1e-6) };
+        assert!(membrane_tau(&membrane).is_none());
+        assert!(advise([Compartment { membrane: &membrane, step_multiple: 1 }]).is_none());
+    }
+}
@@ -1,10 +1,17 @@
-use bevy::prelude::{Assets, Color, Component, Entity, FromWorld, Handle, Resource, StandardMaterial, World};
+use bevy::prelude::{Component, DetectChanges, DetectChangesMut, Entity, Query, Res, Resource};
+#[cfg(feature = "render")]
+use bevy::prelude::{Assets, Color, FromWorld, Handle, StandardMaterial, World};
 // use bevy_egui::egui::widgets::plot::{Plot, Line, PlotPoints};
+#[cfg(feature = "render")]
 use egui_plot::{Plot, Line, PlotPoints};
+#[cfg(feature = "render")]
 use bevy_egui::egui::{self, Ui};
 use std::default::Default;
 
 use crate::dimension::{Interval, Hz, MicroAmpsPerSquareCm, Timestamp};
+use crate::integrations::grace::SceneSegment;
+use crate::mechanism::CustomStimulus;
+use crate::rng::{Rng, SimulationRng, STIMULATOR_STREAM};
 use crate::serialize;
 
 #[derive(Debug, Clone, Component, Resource)]
@@ -39,6 +46,28 @@ pub struct Envelope {
     pub offset: Interval,
 }
 
+impl Envelope {
+    /// How far through its window the envelope is at `t`, from 0 to 1, or
+    /// `None` outside the window.
+    pub fn completion(&self, t: &Timestamp) -> Option<f32> {
+        let envelope_time = t.0.rem_euclid(self.period.0) - self.onset.0;
+        let completion = envelope_time / (self.offset.0 - self.onset.0);
+        (0.0..=1.0).contains(&completion).then_some(completion)
+    }
+
+    pub fn serialize(&self) -> serialize::Envelope {
+        serialize::Envelope { period_sec: self.period.0, onset_sec: self.onset.0, offset_sec: self.offset.0 }
+    }
+
+    pub fn deserialize(envelope: &serialize::Envelope) -> Self {
+        Envelope {
+            period: Interval(envelope.period_sec),
+            onset: Interval(envelope.onset_sec),
+            offset: Interval(envelope.offset_sec),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum CurrentShape {
     SquareWave {
@@ -55,8 +84,20 @@ pub enum CurrentShape {
         offset_current: MicroAmpsPerSquareCm,
         start_frequency: Hz,
         end_frequency: Hz,
-    }
-
+    },
+    /// Pulses at random times within the envelope, at an average `rate`.
+    /// Time is cut into bins one pulse long, and each bin holds a pulse
+    /// with the chance a Poisson process of `rate` has of firing in it,
+    /// drawn from `stream` (see `seed_stimulators`).
+    Poisson {
+        rate: Hz,
+        pulse_current: MicroAmpsPerSquareCm,
+        pulse_width: Interval,
+        off_current: MicroAmpsPerSquareCm,
+        stream: u64,
+    },
+    /// A source from another crate, which ignores the envelope.
+    Custom(CustomStimulus),
 }
 
 impl Stimulator {
@@ -89,16 +130,24 @@ impl Stimulator {
                 }
 
             }
+            CurrentShape::Poisson { rate, pulse_current, pulse_width, off_current, stream } => {
+                // Each bin's draw is keyed by the bin, so the current at
+                // `t` is the same however often and in whatever order it
+                // is sampled.
+                let bin = (t.0 / pulse_width.0).floor();
+                let p_pulse = 1.0 - (-rate.0 * pulse_width.0).exp();
+                let pulsing = in_envelope
+                    && pulse_width.0 > 0.0
+                    && bin >= 0.0
+                    && (Rng::keyed(*stream, &[bin as u64]).uniform() as f32) < p_pulse;
+                if pulsing { pulse_current.clone() } else { off_current.clone() }
+            }
+            CurrentShape::Custom(custom) => custom.current(&t),
         }
     }
 
     pub fn serialize(&self) -> serialize::Stimulator {
-        let Envelope {period, onset, offset} = self.envelope.clone();
-        let envelope = serialize::Envelope {
-            period_sec: period.0,
-            onset_sec: onset.0,
-            offset_sec: offset.0,
-        };
+        let envelope = self.envelope.serialize();
         let current_shape = match self.current_shape.clone() {
             CurrentShape::SquareWave { on_current, off_current } =>
                 serialize::CurrentShape::SquareWave {
@@ -118,17 +167,20 @@ impl Stimulator {
                     start_frequency_hz: start_frequency.0,
                     end_frequency_hz: end_frequency.0,
                 },
+            CurrentShape::Poisson { rate, pulse_current, pulse_width, off_current, stream: _ } =>
+                serialize::CurrentShape::Poisson {
+                    rate_hz: rate.0,
+                    pulse_current_uamps_per_square_cm: pulse_current.0,
+                    pulse_width_sec: pulse_width.0,
+                    off_current_uamps_per_square_cm: off_current.0,
+                },
+            CurrentShape::Custom(custom) => serialize::CurrentShape::Custom(custom.spec),
         };
         serialize::Stimulator { envelope, current_shape }
     }
 
     pub fn deserialize(stimulator: &serialize::Stimulator) -> Self {
-        let serialize::Envelope {period_sec, onset_sec, offset_sec} = stimulator.envelope.clone();
-        let envelope = Envelope {
-            period: Interval(period_sec),
-            onset: Interval(onset_sec),
-            offset: Interval(offset_sec),
-        };
+        let envelope = Envelope::deserialize(&stimulator.envelope);
         let current_shape = match stimulator.current_shape.clone() {
             serialize::CurrentShape::SquareWave {  on_current_uamps_per_square_cm,  off_current_uamps_per_square_cm  } =>
                 CurrentShape::SquareWave {
@@ -154,10 +206,25 @@ impl Stimulator {
                     start_frequency: Hz(start_frequency_hz),
                     end_frequency: Hz(end_frequency_hz),
                 },
+            serialize::CurrentShape::Poisson {
+                rate_hz,
+                pulse_current_uamps_per_square_cm,
+                pulse_width_sec,
+                off_current_uamps_per_square_cm,
+            } =>
+                CurrentShape::Poisson {
+                    rate: Hz(rate_hz),
+                    pulse_current: MicroAmpsPerSquareCm(pulse_current_uamps_per_square_cm),
+                    pulse_width: Interval(pulse_width_sec),
+                    off_current: MicroAmpsPerSquareCm(off_current_uamps_per_square_cm),
+                    stream: 0,
+                },
+            serialize::CurrentShape::Custom(spec) => CurrentShape::Custom(CustomStimulus::new(&spec)),
         };
         Stimulator { envelope, current_shape }
     }
 
+    #[cfg(feature = "render")]
     pub fn plot(&self, ui: &mut Ui) {
         let currents : PlotPoints = (0..2000).map(|t| {
             let timestamp = Timestamp(t.clone() as f32 * 0.0005);
@@ -171,6 +238,7 @@ impl Stimulator {
             .show(ui, |plot_ui| plot_ui.line(line));
     }
 
+    #[cfg(feature = "render")]
     pub fn widget(&mut self, ui: &mut Ui) {
         let Envelope { ref mut period, ref mut onset, ref mut offset } = &mut self.envelope;
         let mut current_shape = &mut self.current_shape;
@@ -225,11 +293,22 @@ impl Stimulator {
             }
         };
 
+        let default_poisson = match &mut current_shape {
+            c@CurrentShape::Poisson {..} => c.clone(),
+            _ => CurrentShape::Poisson {
+                rate: Hz(20.0),
+                pulse_current: MicroAmpsPerSquareCm(50.0),
+                pulse_width: Interval(0.002),
+                off_current: MicroAmpsPerSquareCm(0.0),
+                stream: 0,
+            }
+        };
 
         ui.horizontal(|ui| {
             ui.selectable_value(current_shape, default_square_wave, "Square");
             ui.selectable_value(current_shape, default_linear_ramp, "Linear Ramp");
             ui.selectable_value(current_shape, default_frequency_ramp, "Frequency Ramp");
+            ui.selectable_value(current_shape, default_poisson, "Poisson");
         });
 
         match &mut current_shape {
@@ -310,13 +389,71 @@ impl Stimulator {
                 }).logarithmic(false).text("End Frequency (Hz)"));
 
             },
+
+            CurrentShape::Poisson {
+                ref mut rate, ref mut pulse_current, ref mut pulse_width, ref mut off_current, ..
+            } => {
+
+                ui.add(egui::Slider::from_get_set(0.1..=1000.0, move |v: Option<f64>| {
+                    if let Some(v) = v {
+                        rate.0 = v as f32;
+                    }
+                    rate.0 as f64
+                }).logarithmic(true).text("Rate (Hz)"));
+
+                ui.add(egui::Slider::from_get_set(-500.0..=500.0, move |v: Option<f64>| {
+                    if let Some(v) = v {
+                        pulse_current.0 = v as f32;
+                    }
+                    pulse_current.0 as f64
+                }).logarithmic(false).text("Pulse Current (uAmps)"));
+
+                ui.add(egui::Slider::from_get_set(0.1..=50.0, move |v: Option<f64>| {
+                    if let Some(v) = v {
+                        pulse_width.0 = v as f32 * 0.001;
+                    }
+                    pulse_width.0 as f64 * 1000.0
+                }).logarithmic(true).text("Pulse Width (ms)"));
+
+                ui.add(egui::Slider::from_get_set(-500.0..=500.0, move |v: Option<f64>| {
+                    if let Some(v) = v {
+                        off_current.0 = v as f32;
+                    }
+                    off_current.0 as f64
+                }).logarithmic(false).text("Off Current (uAmps)"));
+
+            },
+
+            CurrentShape::Custom(custom) => {
+                ui.label(format!("{} {}", custom.spec.name, custom.spec.parameters));
+                if custom.source.is_none() {
+                    ui.colored_label(egui::Color32::GOLD, "Not registered; passes no current.");
+                }
+            },
         }
 
         self.plot(ui);
     }
 }
 
+/// Give each Poisson stimulator the stream of its segment under the
+/// scene's seed, whenever it or the seed changes.
+pub fn seed_stimulators(
+    rng: Res<SimulationRng>,
+    mut stimulators: Query<(&SceneSegment, &mut Stimulator)>,
+) {
+    for (SceneSegment(at), mut stimulator) in &mut stimulators {
+        if !(rng.is_changed() || stimulator.is_changed()) {
+            continue;
+        }
+        // Seeding is not an edit; leave the change ticks alone.
+        if let CurrentShape::Poisson { stream, .. } = &mut stimulator.bypass_change_detection().current_shape {
+            *stream = rng.stream_seed(&[STIMULATOR_STREAM, at.neuron as u64, at.segment as u64]);
+        }
+    }
+}
 
+#[cfg(feature = "render")]
 #[derive(Resource)]
 pub struct StimulatorMaterials {
     pub handles: Vec<Handle<StandardMaterial>>,
@@ -324,6 +461,7 @@ pub struct StimulatorMaterials {
     pub len: usize,
 }
 
+#[cfg(feature = "render")]
 impl FromWorld for StimulatorMaterials {
     fn from_world(world: &mut World) -> Self {
         let mut material_assets = world.get_resource_mut::<Assets<StandardMaterial>>()
@@ -363,6 +501,7 @@ impl FromWorld for StimulatorMaterials {
     }
 }
 
+#[cfg(feature = "render")]
 impl StimulatorMaterials {
     pub fn from_selected_and_current(
         &self,
@@ -0,0 +1,217 @@
+//! Running a scene over a grid of parameter values, and summarizing each
+//! neuron's response to every combination.
+//!
+//! Parameters are named by JSON pointers into the serialized scene, e.g.
+//! `/neurons/0/neuron/membranes/0/membrane_channels/1/siemens_per_square_cm`
+//! for a somatic Na conductance, or
+//! `/neurons/0/stimulator_segments/0/stimulator/current_shape/on_current_uamps_per_square_cm`
+//! for a stimulus amplitude. Each combination runs headless, in its own
+//! thread if the sweep is parallel.
+
+use crate::analysis::{Trace, SPIKE_THRESHOLD};
+use crate::headless::soma_traces;
+use crate::serialize::Scene;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    /// JSON pointer to a number in the scene.
+    pub path: String,
+    pub values: Vec<f64>,
+}
+
+impl Parameter {
+    /// Parse `PATH=VALUES`, where the values are either listed, as in
+    /// `0.1,0.2,0.5`, or spaced evenly, as in `0.1:0.5:5` for five values
+    /// from 0.1 to 0.5.
+    pub fn parse(spec: &str) -> Result<Parameter, String> {
+        let (path, values) = spec.split_once('=').ok_or(format!("Expected PATH=VALUES, found {}", spec))?;
+        if !path.starts_with('/') {
+            return Err(format!("{} should be a JSON pointer, starting with /", path));
+        }
+        let number = |s: &str| s.trim().parse::<f64>().map_err(|_| format!("{} is not a number", s));
+        let values = match values.split(':').collect::<Vec<_>>()[..] {
+            [start, end, count] => {
+                let (start, end) = (number(start)?, number(end)?);
+                let count = count.trim().parse::<usize>().map_err(|_| format!("{} is not a count", count))?;
+                let step = if count > 1 { (end - start) / (count - 1) as f64 } else { 0.0 };
+                (0..count).map(|i| start + step * i as f64).collect()
+            },
+            [list] => list.split(',').map(number).collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(format!("Expected a list or START:END:COUNT, found {}", values)),
+        };
+        if values.is_empty() {
+            return Err(format!("{} has no values", path));
+        }
+        Ok(Parameter { path: path.to_string(), values })
+    }
+}
+
+/// Every combination of the parameters' values, the last parameter
+/// varying fastest.
+pub fn combinations(parameters: &[Parameter]) -> Vec<Vec<f64>> {
+    parameters.iter().fold(vec![vec![]], |combinations, parameter| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                parameter.values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push(*value);
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+/// `scene` with each parameter set to the corresponding value.
+pub fn apply(scene: &Scene, parameters: &[Parameter], values: &[f64]) -> Result<Scene, String> {
+    let mut json = serde_json::to_value(scene).map_err(|e| e.to_string())?;
+    for (parameter, value) in parameters.iter().zip(values) {
+        match json.pointer_mut(&parameter.path) {
+            Some(target) if target.is_number() => *target = serde_json::json!(value),
+            Some(_) => return Err(format!("{} is not a number", parameter.path)),
+            None => return Err(format!("{} is not in the scene", parameter.path)),
+        }
+    }
+    serde_json::from_value(json).map_err(|e| e.to_string())
+}
+
+/// `f` of each item, in order, spread over the available cores if
+/// `parallel`.
+pub(crate) fn par_map<T: Sync, R: Send>(items: &[T], parallel: bool, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if !parallel || threads == 1 || cfg!(target_arch = "wasm32") {
+        return items.iter().map(f).collect();
+    }
+    let chunk = items.len().div_ceil(threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles = items
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Worker threads should not panic"))
+            .collect()
+    })
+}
+
+/// One neuron's response to one combination of values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub spike_count: usize,
+    /// The median somatic potential over the run (mV), which spikes,
+    /// being brief, leave at rest.
+    pub resting_mv: f32,
+}
+
+impl Summary {
+    fn of(trace: &Trace) -> Summary {
+        let mut values = trace.values.clone();
+        values.sort_by(f32::total_cmp);
+        Summary {
+            spike_count: trace.spike_times(&SPIKE_THRESHOLD).len(),
+            resting_mv: values.get(values.len() / 2).copied().unwrap_or(f32::NAN),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub values: Vec<f64>,
+    /// One summary per neuron, in scene order.
+    pub summaries: Result<Vec<Summary>, String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub parameters: Vec<Parameter>,
+    pub duration_seconds: f32,
+    pub step_seconds: f32,
+    /// Interval between samples of the somatic potential.
+    pub sample_seconds: f32,
+}
+
+impl Sweep {
+    /// Run `scene` at one combination of values, recording each neuron's
+    /// soma.
+    pub fn run_one(&self, scene: &Scene, values: &[f64]) -> Result<Vec<Summary>, String> {
+        let scene = apply(scene, &self.parameters, values)?;
+        let steps_per_sample = (self.sample_seconds / self.step_seconds).round() as usize;
+        let traces = soma_traces(scene, self.duration_seconds, self.step_seconds, steps_per_sample)?;
+        Ok(traces.iter().map(Summary::of).collect())
+    }
+
+    fn row(&self, scene: &Scene, values: &[f64]) -> Row {
+        Row { values: values.to_vec(), summaries: self.run_one(scene, values) }
+    }
+
+    /// Run every combination, spread over the available cores if
+    /// `parallel`, returning rows in the order of `combinations`.
+    pub fn run(&self, scene: &Scene, parallel: bool) -> Vec<Row> {
+        par_map(&combinations(&self.parameters), parallel, |values| self.row(scene, values))
+    }
+
+    /// The rows as CSV: a column per parameter, then the spike count and
+    /// resting potential of each neuron, then any error.
+    pub fn table(&self, rows: &[Row], neurons: usize) -> String {
+        let mut header = self.parameters.iter().map(|p| p.path.clone()).collect::<Vec<_>>();
+        for n in 0..neurons {
+            header.push(format!("n{}_spikes", n));
+            header.push(format!("n{}_resting_mv", n));
+        }
+        header.push("error".to_string());
+        let mut out = header.join(",") + "\n";
+        for row in rows {
+            let mut fields = row.values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+            match &row.summaries {
+                Ok(summaries) => {
+                    for summary in summaries {
+                        fields.push(summary.spike_count.to_string());
+                        fields.push(format!("{:.2}", summary.resting_mv));
+                    }
+                    fields.push(String::new());
+                },
+                Err(e) => {
+                    fields.resize(fields.len() + 2 * neurons, String::new());
+                    fields.push(format!("\"{}\"", e.replace('"', "'").replace('\n', "; ")));
+                },
+            }
+            out += &(fields.join(",") + "\n");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::sample;
+
+    const NA: &str = "/neurons/0/neuron/membranes/0/membrane_channels/1/siemens_per_square_cm";
+
+    #[test]
+    fn sets_every_combination() {
+        let conductance = Parameter::parse(&format!("{}=0.06:0.12:3", NA)).unwrap();
+        assert_eq!(conductance.values.len(), 3);
+        assert!((conductance.values[1] - 0.09).abs() < 1e-9);
+        let amplitude = Parameter::parse(
+            "/neurons/0/stimulator_segments/0/stimulator/current_shape/on_current_uamps_per_square_cm=5,10",
+        )
+        .unwrap();
+        let parameters = vec![conductance, amplitude];
+        let grid = combinations(&parameters);
+        assert_eq!(grid.len(), 6);
+        assert_eq!(grid[1], vec![0.06, 10.0]);
+
+        let scene = sample::scene2();
+        let swept = apply(&scene, &parameters, &grid[1]).unwrap();
+        let channel = &swept.neurons[0].neuron.membranes[0].membrane_channels[1];
+        assert!((channel.siemens_per_square_cm - 0.06).abs() < 1e-6);
+
+        let missing = Parameter { path: "/neurons/9/location/x_mm".to_string(), values: vec![1.0] };
+        assert!(apply(&scene, &[missing], &[1.0]).unwrap_err().contains("not in the scene"));
+        assert!(Parameter::parse("neurons/0=1").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Thermal stimulation: heating segments over an envelope, as an infrared
+//! laser does in infrared neural stimulation.
+//!
+//! A heated segment's channels gate faster by the Q10 rule, by a factor of
+//! `KINETICS_Q10` for every 10 K above the scene's temperature, which is
+//! taken to be the temperature the channel kinetics were measured at.
+//! Reversal potentials stay at the scene's temperature; a rise of a few
+//! kelvin moves them by about 1%.
+//!
+//! Heated segments carry a translucent overlay whose color follows the
+//! temperature rise.
+
+use bevy::prelude::*;
+#[cfg(feature = "render")]
+use bevy::math::prelude::Sphere;
+
+use crate::dimension::{Kelvin, Timestamp};
+use crate::serialize;
+use crate::stimulator::Envelope;
+
+/// Speed-up of gating for a 10 K rise, typical of mammalian Na and K
+/// channels.
+pub const KINETICS_Q10: f32 = 3.0;
+
+/// The rise shown by the hottest overlay color.
+#[cfg(feature = "render")]
+const OVERLAY_MAX_RISE: Kelvin = Kelvin(10.0);
+#[cfg(feature = "render")]
+const OVERLAY_LEVELS: usize = 16;
+
+/// How much faster gating runs `rise_kelvin` above the reference
+/// temperature.
+pub fn q10_factor(rise_kelvin: f32) -> f32 {
+    KINETICS_Q10.powf(rise_kelvin / 10.0)
+}
+
+#[derive(Clone, Component, Debug)]
+pub struct ThermalStimulator {
+    pub envelope: Envelope,
+    /// Rise above the scene's temperature during the envelope.
+    pub rise: Kelvin,
+}
+
+impl Default for ThermalStimulator {
+    fn default() -> Self {
+        ThermalStimulator {
+            envelope: Envelope::deserialize(&serialize::Envelope { period_sec: 0.1, onset_sec: 0.01, offset_sec: 0.03 }),
+            rise: Kelvin(5.0),
+        }
+    }
+}
+
+impl ThermalStimulator {
+    /// The rise (K) at `t`.
+    pub fn rise_at(&self, t: &Timestamp) -> f32 {
+        if self.envelope.completion(t).is_some() { self.rise.0 } else { 0.0 }
+    }
+
+    /// How much faster the segment's channels gate at `t`.
+    pub fn kinetics_factor(&self, t: &Timestamp) -> f32 {
+        q10_factor(self.rise_at(t))
+    }
+
+    pub fn serialize(&self, segment: u32) -> serialize::ThermalSegment {
+        serialize::ThermalSegment { segment, envelope: self.envelope.serialize(), rise_kelvin: self.rise.0 }
+    }
+
+    pub fn deserialize(thermal: &serialize::ThermalSegment) -> Self {
+        ThermalStimulator { envelope: Envelope::deserialize(&thermal.envelope), rise: Kelvin(thermal.rise_kelvin) }
+    }
+}
+
+/// The heat overlay on a heated segment, spawned as its child.
+#[derive(Component)]
+pub struct HeatOverlay;
+
+#[cfg(feature = "render")]
+#[derive(Resource)]
+pub struct HeatMaterials {
+    handles: Vec<Handle<StandardMaterial>>,
+}
+
+#[cfg(feature = "render")]
+impl FromWorld for HeatMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let handles = (0..OVERLAY_LEVELS)
+            .map(|i| {
+                let heat = (i + 1) as f32 / OVERLAY_LEVELS as f32;
+                materials.add(StandardMaterial {
+                    base_color: Color::rgba(1.0, 0.6 * (1.0 - heat), 0.0, 0.15 + 0.45 * heat),
+                    emissive: Color::rgb_linear(2.0 * heat, 0.5 * heat, 0.0),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                })
+            })
+            .collect();
+        HeatMaterials { handles }
+    }
+}
+
+#[cfg(feature = "render")]
+impl HeatMaterials {
+    fn for_rise(&self, rise: f32) -> &Handle<StandardMaterial> {
+        let level = (rise / OVERLAY_MAX_RISE.0 * OVERLAY_LEVELS as f32).ceil() as usize;
+        &self.handles[level.clamp(1, OVERLAY_LEVELS) - 1]
+    }
+}
+
+/// Give each newly heated segment an overlay.
+#[cfg(feature = "render")]
+pub fn spawn_heat_overlays(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    heat_materials: Res<HeatMaterials>,
+    heated: Query<Entity, Added<ThermalStimulator>>,
+    children: Query<&Children>,
+    overlays: Query<(), With<HeatOverlay>>,
+) {
+    for segment in &heated {
+        let has_overlay = children.get(segment).is_ok_and(|c| c.iter().any(|child| overlays.contains(*child)));
+        if has_overlay {
+            continue;
+        }
+        let overlay = commands
+            .spawn((
+                HeatOverlay,
+                PbrBundle {
+                    mesh: meshes.add(Sphere { radius: 10.0 }),
+                    material: heat_materials.handles[0].clone(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+            ))
+            .id();
+        commands.entity(segment).add_child(overlay);
+    }
+}
+
+/// Color each overlay by its segment's rise, hiding it while the segment
+/// is at the scene's temperature, and remove overlays from segments no
+/// longer heated.
+#[cfg(feature = "render")]
+pub fn update_heat_overlays(
+    mut commands: Commands,
+    timestamp: Res<Timestamp>,
+    heat_materials: Res<HeatMaterials>,
+    stimulators: Query<&ThermalStimulator>,
+    mut overlays: Query<(Entity, &Parent, &mut Handle<StandardMaterial>, &mut Visibility), With<HeatOverlay>>,
+) {
+    for (overlay, parent, mut material, mut visibility) in &mut overlays {
+        let Ok(stimulator) = stimulators.get(parent.get()) else {
+            commands.entity(overlay).despawn_recursive();
+            continue;
+        };
+        let rise = stimulator.rise_at(&timestamp);
+        *visibility = if rise > 0.0 { Visibility::Inherited } else { Visibility::Hidden };
+        let target = heat_materials.for_rise(rise);
+        if *material != *target {
+            *material = target.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heats_within_the_envelope() {
+        let stimulator = ThermalStimulator::default();
+        assert_eq!(stimulator.kinetics_factor(&Timestamp(0.0)), 1.0);
+        assert!((stimulator.kinetics_factor(&Timestamp(0.02)) - 3.0f32.sqrt()).abs() < 1e-5);
+        // The envelope repeats every period.
+        assert_eq!(stimulator.rise_at(&Timestamp(0.12)), 5.0);
+        assert_eq!(ThermalStimulator::deserialize(&stimulator.serialize(4)).rise.0, stimulator.rise.0);
+        assert!((q10_factor(10.0) - KINETICS_Q10).abs() < 1e-6);
+    }
+}
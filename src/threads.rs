@@ -0,0 +1,171 @@
+//! Threads for the biophysics: bevy's compute pool natively, and in
+//! browser builds with the `wasm-threads` feature, a pool of web workers.
+//!
+//! Browsers only share memory with workers on cross-origin isolated
+//! pages, served with `Cross-Origin-Opener-Policy: same-origin` and
+//! `Cross-Origin-Embedder-Policy: require-corp`. The page calls
+//! `startThreads` once the module is loaded. Where the page is not
+//! isolated, or the build lacks the feature, it resolves to false and the
+//! simulation keeps to the main thread, stepping once per frame.
+//!
+//! Once the pool is up the background simulation (see `background`) runs
+//! on one of its workers, and steps segments in parallel on the others.
+//! The main thread of a browser may not block, so nothing it runs waits
+//! on the pool. The feature needs a nightly toolchain and a standard
+//! library built with atomics:
+//!
+//! ```sh
+//! RUSTFLAGS='-C target-feature=+atomics,+bulk-memory,+mutable-globals' \
+//!   cargo +nightly build --target wasm32-unknown-unknown --features wasm-threads \
+//!   -Z build-std=panic_abort,std
+//! ```
+
+use bevy::ecs::query::{QueryData, QueryFilter, QueryItem};
+use bevy::prelude::*;
+
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+static POOL_READY: AtomicBool = AtomicBool::new(false);
+
+/// Whether work can be spawned off the main thread: always natively, and
+/// on the web once `startThreads` has started the workers.
+pub fn available() -> bool {
+    cfg!(not(target_arch = "wasm32")) || pool_ready()
+}
+
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+fn pool_ready() -> bool {
+    POOL_READY.load(Ordering::Acquire)
+}
+
+#[cfg(not(all(feature = "wasm-threads", target_arch = "wasm32")))]
+fn pool_ready() -> bool {
+    false
+}
+
+/// Whether the page may share memory with workers.
+#[cfg(target_arch = "wasm32")]
+pub fn cross_origin_isolated() -> bool {
+    js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("crossOriginIsolated"))
+        .is_ok_and(|isolated| isolated.is_truthy())
+}
+
+/// Start `threads` web workers, resolving to whether they are up. Pages
+/// that are not cross-origin isolated, and builds without the
+/// `wasm-threads` feature, resolve to false and stay single-threaded.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = startThreads)]
+pub async fn start_threads(threads: usize) -> bool {
+    if !cross_origin_isolated() {
+        web_sys::console::warn_1(&"The page is not cross-origin isolated; simulating on the main thread.".into());
+        return false;
+    }
+    init_pool(threads).await
+}
+
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+async fn init_pool(threads: usize) -> bool {
+    // One worker runs the simulation, and waits on the others.
+    let started = wasm_bindgen_futures::JsFuture::from(wasm_bindgen_rayon::init_thread_pool(threads.max(2))).await;
+    if let Err(e) = &started {
+        web_sys::console::warn_2(&"Could not start the simulation workers:".into(), e);
+    }
+    POOL_READY.store(started.is_ok(), Ordering::Release);
+    started.is_ok()
+}
+
+#[cfg(all(not(feature = "wasm-threads"), target_arch = "wasm32"))]
+async fn init_pool(_threads: usize) -> bool {
+    web_sys::console::warn_1(&"Built without the wasm-threads feature; simulating on the main thread.".into());
+    false
+}
+
+/// Run `f` off the main thread, as `name`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> Result<(), String> {
+    std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(f)
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+/// Run `f` off the main thread, on one of the web workers.
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+pub fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> Result<(), String> {
+    if !pool_ready() {
+        return Err(format!("No worker for {}: the page has not started any", name));
+    }
+    rayon::spawn(f);
+    Ok(())
+}
+
+#[cfg(all(not(feature = "wasm-threads"), target_arch = "wasm32"))]
+pub fn spawn(name: &str, _f: impl FnOnce() + Send + 'static) -> Result<(), String> {
+    Err(format!("No worker for {}: built without the wasm-threads feature", name))
+}
+
+/// Run `f` on every item of `query`, in parallel where that is possible:
+/// on bevy's compute pool natively, on the web workers when called from
+/// one of them, and otherwise in turn.
+#[cfg(not(all(feature = "wasm-threads", target_arch = "wasm32")))]
+pub fn par_for_each<'a, D, F, Func>(query: &'a mut Query<'_, '_, D, F>, f: Func)
+where
+    D: QueryData,
+    F: QueryFilter,
+    Func: Fn(QueryItem<'a, D>) + Send + Sync + Clone,
+{
+    query.par_iter_mut().for_each(f);
+}
+
+/// Run `f` on every item of `query`, in parallel where that is possible:
+/// on bevy's compute pool natively, on the web workers when called from
+/// one of them, and otherwise in turn.
+#[cfg(all(feature = "wasm-threads", target_arch = "wasm32"))]
+pub fn par_for_each<'a, D, F, Func>(query: &'a mut Query<'_, '_, D, F>, f: Func)
+where
+    D: QueryData,
+    F: QueryFilter,
+    Func: Fn(QueryItem<'a, D>) + Send + Sync + Clone,
+    QueryItem<'a, D>: Send,
+{
+    // Waiting on the pool from the main thread would block it.
+    if rayon::current_thread_index().is_some() {
+        query.iter_mut().collect::<Vec<_>>().into_par_iter().for_each(f);
+    } else {
+        query.iter_mut().for_each(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+    use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+    #[derive(Component)]
+    struct Level(u32);
+
+    #[test]
+    fn steps_every_item_and_spawns() {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        let mut world = World::new();
+        world.spawn_batch((0..100).map(Level));
+        let mut state = SystemState::<Query<&mut Level>>::new(&mut world);
+        par_for_each(&mut state.get_mut(&mut world), |mut level| level.0 += 1);
+        let mut levels = world.query::<&Level>().iter(&world).map(|l| l.0).collect::<Vec<_>>();
+        levels.sort();
+        assert_eq!(levels, (1..=100).collect::<Vec<_>>());
+
+        assert!(available());
+        let (sender, receiver) = crossbeam::channel::bounded(1);
+        spawn("test", move || sender.send(7).unwrap()).unwrap();
+        assert_eq!(receiver.recv(), Ok(7));
+    }
+}
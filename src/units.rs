@@ -0,0 +1,133 @@
+//! Conversions between the units scenes are written in and the units the
+//! simulation and the 3D view use.
+//!
+//! SWC coordinates and radii are in microns and scene locations in
+//! millimeters, the cable equations work in centimeters, and the 3D view
+//! works in screen units. The micron, millimeter and centimeter ratios are
+//! fixed; how many screen units a micron spans is the one free choice, and
+//! lives in the `UnitScale` resource.
+//!
+//! Positions in the scene, such as those of electrodes and lights, are in
+//! the scene frame: microns, with each neuron's soma at its `Location`
+//! and its other segments offset from the soma as in its SWC.
+
+use bevy::prelude::{Resource, Vec3};
+
+pub const CM_PER_MICRON: f32 = 1e-4;
+pub const MICRONS_PER_MM: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Resource)]
+pub struct UnitScale {
+    screen_per_micron: f32,
+    radius_exaggeration: f32,
+}
+
+impl Default for UnitScale {
+    fn default() -> Self {
+        UnitScale { screen_per_micron: 1.0, radius_exaggeration: 5.0 }
+    }
+}
+
+fn positive(name: &str, value: f32) -> Result<f32, String> {
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} must be a positive number, got {}", name, value))
+    }
+}
+
+impl UnitScale {
+    pub fn new(screen_per_micron: f32, radius_exaggeration: f32) -> Result<Self, String> {
+        Ok(UnitScale {
+            screen_per_micron: positive("Screen units per micron", screen_per_micron)?,
+            radius_exaggeration: positive("Radius exaggeration", radius_exaggeration)?,
+        })
+    }
+
+    /// Screen units spanned by one micron.
+    pub fn screen_per_micron(&self) -> f32 {
+        self.screen_per_micron
+    }
+
+    /// How many times thicker than life neurites are drawn, so that thin
+    /// dendrites stay visible. Somata are drawn true to size.
+    pub fn radius_exaggeration(&self) -> f32 {
+        self.radius_exaggeration
+    }
+
+    pub fn microns_to_cm(microns: f32) -> f32 {
+        microns * CM_PER_MICRON
+    }
+
+    pub fn cm_to_microns(cm: f32) -> f32 {
+        cm / CM_PER_MICRON
+    }
+
+    pub fn mm_to_microns(mm: f32) -> f32 {
+        mm * MICRONS_PER_MM
+    }
+
+    pub fn microns_to_screen(&self, microns: f32) -> f32 {
+        microns * self.screen_per_micron
+    }
+
+    pub fn screen_to_microns(&self, screen: f32) -> f32 {
+        screen / self.screen_per_micron
+    }
+
+    pub fn cm_to_screen(&self, cm: f32) -> f32 {
+        self.microns_to_screen(Self::cm_to_microns(cm))
+    }
+
+    /// A point given in microns, placed in the view.
+    pub fn point_to_screen(&self, microns: Vec3) -> Vec3 {
+        microns * self.screen_per_micron
+    }
+}
+
+/// The longest 1, 2 or 5 times a power of ten microns that fits in
+/// `target` microns.
+pub fn scale_bar_microns(target: f32) -> f32 {
+    if !(target.is_finite() && target > 0.0) {
+        return 1.0;
+    }
+    let magnitude = 10f32.powf(target.log10().floor());
+    let step = [5.0, 2.0, 1.0].into_iter().find(|s| s * magnitude <= target * 1.0001).unwrap_or(1.0);
+    step * magnitude
+}
+
+/// A length in the largest unit that keeps it at least one.
+pub fn format_microns(microns: f32) -> String {
+    if microns >= 10_000.0 {
+        format!("{} cm", microns * CM_PER_MICRON)
+    } else if microns >= MICRONS_PER_MM {
+        format!("{} mm", microns / MICRONS_PER_MM)
+    } else {
+        format!("{} µm", microns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_and_validates() {
+        assert!(UnitScale::new(0.0, 5.0).is_err());
+        assert!(UnitScale::new(1.0, f32::NAN).is_err());
+        let scale = UnitScale::new(2.0, 5.0).unwrap();
+        assert_eq!(scale.microns_to_screen(10.0), 20.0);
+        assert_eq!(scale.screen_to_microns(20.0), 10.0);
+        assert!((scale.cm_to_screen(0.01) - 200.0).abs() < 1e-3);
+        assert!((UnitScale::microns_to_cm(UnitScale::mm_to_microns(1.0)) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn scale_bars_are_round_lengths() {
+        assert_eq!(scale_bar_microns(730.0), 500.0);
+        assert_eq!(scale_bar_microns(300.0), 200.0);
+        assert_eq!(scale_bar_microns(100.0), 100.0);
+        assert_eq!(format_microns(500.0), "500 µm");
+        assert_eq!(format_microns(2000.0), "2 mm");
+    }
+}
@@ -0,0 +1,203 @@
+//! Structural checks on a serialized scene, so that a scene uploaded to a
+//! running simulation is rejected with a list of its problems rather than
+//! panicking halfway through spawning.
+
+use std::collections::HashSet;
+
+use crate::integrations::dhall::parse_scene;
+use crate::neuron::density::DensityRule;
+use crate::serialize::{CurrentShape, Scene, SceneNeuron};
+
+fn positive(x: f32) -> bool {
+    x.is_finite() && x > 0.0
+}
+
+fn check_neuron(n: usize, scene_neuron: &SceneNeuron, errors: &mut Vec<String>) {
+    let neuron = &scene_neuron.neuron;
+    if neuron.segments.is_empty() {
+        errors.push(format!("Neuron {} has no segments", n));
+    }
+    let mut ids = HashSet::new();
+    for segment in &neuron.segments {
+        if !ids.insert(segment.id) {
+            errors.push(format!("Neuron {}: duplicate segment id {}", n, segment.id));
+        }
+    }
+    for segment in &neuron.segments {
+        if segment.parent != -1 && !ids.contains(&segment.parent) {
+            errors.push(format!("Neuron {}: segment {} has missing parent {}", n, segment.id, segment.parent));
+        }
+        if !positive(segment.r) {
+            errors.push(format!("Neuron {}: segment {} has radius {}", n, segment.id, segment.r));
+        }
+        // Membranes are indexed by SWC type, from 1.
+        if segment.type_ == 0 || segment.type_ > neuron.membranes.len() {
+            errors.push(format!("Neuron {}: segment {} has type {} but there are {} membranes", n, segment.id, segment.type_, neuron.membranes.len()));
+        }
+    }
+    for (i, membrane) in neuron.membranes.iter().enumerate() {
+        if !positive(membrane.capacitance_farads_per_square_cm) {
+            errors.push(format!("Neuron {}: membrane {} has capacitance {}", n, i + 1, membrane.capacitance_farads_per_square_cm));
+        }
+        for channel in &membrane.membrane_channels {
+            if !(channel.siemens_per_square_cm.is_finite() && channel.siemens_per_square_cm >= 0.0) {
+                errors.push(format!("Neuron {}: membrane {} has a channel with conductance {}", n, i + 1, channel.siemens_per_square_cm));
+            }
+            if let Some(Err(e)) = channel.density.as_deref().map(DensityRule::parse) {
+                errors.push(format!("Neuron {}: membrane {}: {}", n, i + 1, e));
+            }
+        }
+    }
+    for stimulator in &scene_neuron.stimulator_segments {
+        if !ids.contains(&(stimulator.segment as i32)) {
+            errors.push(format!("Neuron {}: stimulator on missing segment {}", n, stimulator.segment));
+        }
+        if let CurrentShape::Poisson { rate_hz, pulse_width_sec, .. } = stimulator.stimulator.current_shape {
+            if !(rate_hz.is_finite() && rate_hz >= 0.0 && pulse_width_sec.is_finite() && pulse_width_sec > 0.0) {
+                errors.push(format!(
+                    "Neuron {}: Poisson stimulator on segment {} has rate {} Hz and pulse width {} s",
+                    n, stimulator.segment, rate_hz, pulse_width_sec
+                ));
+            }
+        }
+    }
+    for thermal in &scene_neuron.thermal_segments {
+        if !ids.contains(&(thermal.segment as i32)) {
+            errors.push(format!("Neuron {}: thermal stimulator on missing segment {}", n, thermal.segment));
+        }
+    }
+    for over in &scene_neuron.membrane_overrides {
+        if !ids.contains(&(over.segment as i32)) {
+            errors.push(format!("Neuron {}: membrane override on missing segment {}", n, over.segment));
+        }
+        if !positive(over.membrane.capacitance_farads_per_square_cm) {
+            errors.push(format!(
+                "Neuron {}: membrane override on segment {} has capacitance {}",
+                n, over.segment, over.membrane.capacitance_farads_per_square_cm
+            ));
+        }
+    }
+    if let Some(noise) = &scene_neuron.noise {
+        let non_negative = [
+            noise.background_sigma_uamps_per_square_cm,
+            noise.background_tau_sec,
+            noise.channel_sigma_uamps_per_square_cm,
+            noise.channel_tau_sec,
+        ];
+        if !(noise.mean_uamps_per_square_cm.is_finite() && non_negative.iter().all(|x| x.is_finite() && *x >= 0.0)) {
+            errors.push(format!("Neuron {}: membrane noise has negative or non-finite settings: {:?}", n, noise));
+        }
+    }
+}
+
+/// Every problem found with `scene`; empty if it can be spawned.
+pub fn validate_scene(scene: &Scene) -> Vec<String> {
+    let mut errors = vec![];
+    for (n, scene_neuron) in scene.neurons.iter().enumerate() {
+        check_neuron(n, scene_neuron, &mut errors);
+    }
+    // Synapses refer to segments by their position in the segment list.
+    let segment_counts = scene.neurons.iter().map(|n| n.neuron.segments.len()).collect::<Vec<_>>();
+    for (i, synapse) in scene.synapses.iter().enumerate() {
+        for (role, neuron, segment) in [("pre", synapse.pre_neuron, synapse.pre_segment), ("post", synapse.post_neuron, synapse.post_segment)] {
+            match segment_counts.get(neuron) {
+                None => errors.push(format!("Synapse {}: missing {}synaptic neuron {}", i, role, neuron)),
+                Some(count) if segment >= *count => {
+                    errors.push(format!("Synapse {}: {}synaptic neuron {} has no segment at {}", i, role, neuron, segment))
+                },
+                Some(_) => {},
+            }
+        }
+        if (synapse.pre_neuron, synapse.pre_segment) == (synapse.post_neuron, synapse.post_segment) {
+            errors.push(format!("Synapse {}: segment {} of neuron {} synapses onto itself", i, synapse.pre_segment, synapse.pre_neuron));
+        }
+    }
+    for (i, light) in scene.lights.iter().enumerate() {
+        if !(light.irradiance_mw_per_square_mm.is_finite() && light.irradiance_mw_per_square_mm >= 0.0) {
+            errors.push(format!("Light {}: irradiance must not be negative", i));
+        }
+        if !positive(light.spread_um) || !positive(light.wavelength_nm) {
+            errors.push(format!("Light {}: spread and wavelength must be positive", i));
+        }
+    }
+    for (i, projector) in scene.projectors.iter().enumerate() {
+        if !(projector.irradiance_mw_per_square_mm.is_finite() && projector.irradiance_mw_per_square_mm >= 0.0) {
+            errors.push(format!("Projector {}: irradiance must not be negative", i));
+        }
+        if ![projector.width_um, projector.height_um, projector.wavelength_nm].into_iter().all(positive) {
+            errors.push(format!("Projector {}: width, height and wavelength must be positive", i));
+        }
+        if !(projector.direction.iter().all(|d| d.is_finite()) && projector.direction.iter().any(|d| *d != 0.0)) {
+            errors.push(format!("Projector {}: direction must not be zero", i));
+        }
+        if !(projector.frame_interval_sec.is_finite() && projector.frame_interval_sec >= 0.0) {
+            errors.push(format!("Projector {}: frame interval must not be negative", i));
+        }
+        let pattern = &projector.pattern;
+        let pixels = pattern.width * pattern.height;
+        if pixels == 0 || pattern.frames.is_empty() || pattern.frames.iter().any(|f| f.len() != pixels) {
+            errors.push(format!(
+                "Projector {}: pattern needs at least one frame of {}x{} pixels",
+                i, pattern.width, pattern.height
+            ));
+        }
+    }
+    if let Some(constants) = &scene.constants {
+        let values = [constants.temperature_kelvin, constants.gas_constant, constants.faraday, constants.axial_resistivity_ohm_cm];
+        if !values.into_iter().all(positive) {
+            errors.push("Constants must all be positive".to_string());
+        }
+    }
+    errors
+}
+
+/// Parse a scene given as JSON or Dhall, the way a `/scene` upload
+/// arrives, rejecting it with every problem found.
+pub fn checked_scene(source: &str) -> Result<Scene, String> {
+    let scene = parse_scene(source)?;
+    let errors = validate_scene(&scene);
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::grace::sample;
+    use crate::serialize;
+
+    #[test]
+    fn finds_dangling_references() {
+        let mut scene = sample::scene2();
+        assert_eq!(validate_scene(&scene), Vec::<String>::new());
+
+        scene.neurons[0].neuron.segments[1].parent = 9999;
+        scene.synapses[0].post_neuron = 7;
+        let membrane = scene.neurons[0].neuron.membranes[0].clone();
+        scene.neurons[0].membrane_overrides.push(serialize::MembraneOverride { segment: 99999, membrane });
+        let errors = validate_scene(&scene);
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("missing parent 9999"));
+        assert!(errors[1].contains("override on missing segment 99999"));
+        assert!(errors[2].contains("missing postsynaptic neuron 7"));
+    }
+
+    #[test]
+    fn rejects_self_synapses() {
+        let mut scene = sample::scene2();
+        let synapse = &mut scene.synapses[0];
+        (synapse.post_neuron, synapse.post_segment) = (synapse.pre_neuron, synapse.pre_segment);
+        let errors = validate_scene(&scene);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("onto itself"));
+    }
+
+    #[test]
+    fn checks_uploaded_sources() {
+        assert!(checked_scene(include_str!("../sample_data/sample_scene.json")).is_ok());
+        let invalid = include_str!("../sample_data/sample_scene.json").replacen("\"parent\": -1", "\"parent\": 9999", 1);
+        assert!(checked_scene(&invalid).unwrap_err().contains("missing parent 9999"));
+    }
+}
@@ -0,0 +1,189 @@
+//! A compact per-frame encoding of every segment's voltage, for remote
+//! viewers of scenes with tens of thousands of segments.
+//!
+//! Each voltage is quantized to a byte against the colormap's range, which
+//! is all the precision a viewer can show. A key frame carries the bytes
+//! themselves; the frames after it carry the wrapping difference from the
+//! previous frame, with runs of zeros (segments whose color did not
+//! change) collapsed. Voltages must come in the same segment order every
+//! frame; the gRPC voltage stream sends them ordered by neuron, then
+//! segment, when asked for `StreamRequest.quantized`.
+//!
+//! Every frame starts with a header: the frame kind (`KEY` or `DELTA`),
+//! the number of segments as a little-endian u32, then the range's ends
+//! as little-endian f32s.
+
+const KEY: u8 = 0;
+const DELTA: u8 = 1;
+const HEADER_BYTES: usize = 13;
+
+/// Frames between key frames, so that a viewer joining late, or one that
+/// dropped a frame, recovers within a second or two.
+pub const KEY_FRAME_INTERVAL: usize = 60;
+
+pub fn quantize(v_mv: f32, (v_min, v_max): (f32, f32)) -> u8 {
+    let fraction = ((v_mv - v_min) / (v_max - v_min)).clamp(0.0, 1.0);
+    // NaN clamps to NaN, which casts to 0.
+    (fraction * 255.0).round() as u8
+}
+
+pub fn dequantize(q: u8, (v_min, v_max): (f32, f32)) -> f32 {
+    v_min + q as f32 / 255.0 * (v_max - v_min)
+}
+
+fn header(kind: u8, count: usize, (v_min, v_max): (f32, f32)) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_BYTES + count);
+    bytes.push(kind);
+    bytes.extend_from_slice(&(count as u32).to_le_bytes());
+    bytes.extend_from_slice(&v_min.to_le_bytes());
+    bytes.extend_from_slice(&v_max.to_le_bytes());
+    bytes
+}
+
+#[derive(Debug)]
+pub struct Encoder {
+    pub range: (f32, f32),
+    previous: Vec<u8>,
+    since_key_frame: usize,
+}
+
+impl Encoder {
+    pub fn new(range: (f32, f32)) -> Self {
+        Encoder { range, previous: vec![], since_key_frame: 0 }
+    }
+
+    /// Encode one frame. It is a key frame if it is the first, if the
+    /// number of segments or the range changed, or if one is due.
+    pub fn encode(&mut self, voltages: &[f32]) -> Vec<u8> {
+        let quantized = voltages.iter().map(|v| quantize(*v, self.range)).collect::<Vec<_>>();
+        let key = self.previous.len() != quantized.len() || self.since_key_frame + 1 >= KEY_FRAME_INTERVAL;
+        let bytes = if key {
+            self.since_key_frame = 0;
+            let mut bytes = header(KEY, quantized.len(), self.range);
+            bytes.extend_from_slice(&quantized);
+            bytes
+        } else {
+            self.since_key_frame += 1;
+            let mut bytes = header(DELTA, quantized.len(), self.range);
+            let deltas = quantized.iter().zip(&self.previous).map(|(q, p)| q.wrapping_sub(*p));
+            run_length_encode(deltas, &mut bytes);
+            bytes
+        };
+        self.previous = quantized;
+        bytes
+    }
+
+    /// Start a new key frame with a new range, e.g. when the colormap
+    /// changes.
+    pub fn set_range(&mut self, range: (f32, f32)) {
+        if range != self.range {
+            self.range = range;
+            self.previous.clear();
+        }
+    }
+}
+
+/// Zero is written as a zero byte followed by the length of the run of
+/// zeros, up to 255; every other byte is written as is.
+fn run_length_encode(bytes: impl Iterator<Item = u8>, out: &mut Vec<u8>) {
+    let mut zeros: u8 = 0;
+    for byte in bytes {
+        if byte == 0 {
+            if zeros == u8::MAX {
+                out.extend_from_slice(&[0, zeros]);
+                zeros = 0;
+            }
+            zeros += 1;
+            continue;
+        }
+        if zeros > 0 {
+            out.extend_from_slice(&[0, zeros]);
+            zeros = 0;
+        }
+        out.push(byte);
+    }
+    if zeros > 0 {
+        out.extend_from_slice(&[0, zeros]);
+    }
+}
+
+fn run_length_decode(bytes: &[u8], count: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(count);
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0 {
+            let run = *bytes.next().ok_or("Truncated run of zeros".to_string())?;
+            out.resize(out.len() + run as usize, 0);
+        } else {
+            out.push(byte);
+        }
+    }
+    if out.len() != count {
+        return Err(format!("Frame has {} segments, not {}", out.len(), count));
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    previous: Vec<u8>,
+}
+
+impl Decoder {
+    /// Decode one frame into voltages (mV). Delta frames need the frame
+    /// before them; until the next key frame arrives they are an error.
+    pub fn decode(&mut self, frame: &[u8]) -> Result<Vec<f32>, String> {
+        if frame.len() < HEADER_BYTES {
+            return Err(format!("Frame of {} bytes is shorter than its header", frame.len()));
+        }
+        let word = |i: usize| [frame[i], frame[i + 1], frame[i + 2], frame[i + 3]];
+        let count = u32::from_le_bytes(word(1)) as usize;
+        let range = (f32::from_le_bytes(word(5)), f32::from_le_bytes(word(9)));
+        let body = &frame[HEADER_BYTES..];
+        let quantized = match frame[0] {
+            KEY if body.len() == count => body.to_vec(),
+            KEY => return Err(format!("Key frame has {} segments, not {}", body.len(), count)),
+            DELTA if self.previous.len() != count => {
+                return Err("Delta frame without the frame before it".to_string());
+            },
+            DELTA => run_length_decode(body, count)?
+                .iter()
+                .zip(&self.previous)
+                .map(|(delta, p)| p.wrapping_add(*delta))
+                .collect(),
+            kind => return Err(format!("Unknown frame kind {}", kind)),
+        };
+        let voltages = quantized.iter().map(|q| dequantize(*q, range)).collect();
+        self.previous = quantized;
+        Ok(voltages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_within_one_level() {
+        let range = (-80.0, 50.0);
+        let mut encoder = Encoder::new(range);
+        let mut decoder = Decoder::default();
+        let mut voltages = vec![-70.0; 1000];
+        let key = encoder.encode(&voltages);
+        assert_eq!(key.len(), HEADER_BYTES + 1000);
+        decoder.decode(&key).unwrap();
+
+        // One segment spikes; the rest are runs of zeros.
+        voltages[500] = 30.0;
+        let delta = encoder.encode(&voltages);
+        assert!(delta.len() < HEADER_BYTES + 10, "{} bytes", delta.len());
+        let decoded = decoder.decode(&delta).unwrap();
+        let level = (range.1 - range.0) / 255.0;
+        assert!(decoded.iter().zip(&voltages).all(|(d, v)| (d - v).abs() <= level / 2.0));
+
+        // A viewer that missed the key frame waits for the next one.
+        assert!(Decoder::default().decode(&delta).is_err());
+        assert_eq!(quantize(f32::NAN, range), 0);
+        assert_eq!(quantize(100.0, range), 255);
+    }
+}
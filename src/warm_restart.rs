@@ -0,0 +1,200 @@
+//! Warm restarts: new membrane and synapse parameters applied to the
+//! running scene in place, without despawning thousands of segment meshes
+//! and spawning them again.
+//!
+//! `Command::WarmRestart` carries a whole scene, which must have the same
+//! neurons, segments and synapses as the running one; anything else needs
+//! `Command::LoadScene`. Each segment's membrane is rebuilt from the
+//! membrane of its type, with any density rules evaluated at the segment,
+//! or from its own membrane if the scene overrides it, and each synapse
+//! from its new parameters. State carries over where the channels and
+//! receptors still line up, unless the restart resets it, along with time,
+//! as `Command::Reset` would. Stimulators, noise, lights and constants are
+//! left as they are.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::checkpoint::{apply_all, GateMagnitudes, SegmentState};
+use crate::clock::RunClock;
+use crate::command::Command;
+use crate::dimension::{StepCount, Timestamp};
+use crate::integrations::grace::{paths_to_soma_microns, CurrentScene, SceneSegment, Synapse, INITIAL_VOLTAGE};
+use crate::neuron::density;
+use crate::neuron::membrane::{Membrane, MembraneVoltage};
+use crate::neuron::synapse::SynapseMembranes;
+use crate::serialize::{Scene, SegmentRef};
+use crate::validate::validate_scene;
+
+/// Check that `new` places the same segments as `current`, so its
+/// parameters can be applied without respawning.
+pub fn check_same_topology(current: &Scene, new: &Scene) -> Result<(), String> {
+    if current.neurons.len() != new.neurons.len() {
+        return Err(format!("{} neurons running but {} in the new scene", current.neurons.len(), new.neurons.len()));
+    }
+    for (n, (running, replacement)) in current.neurons.iter().zip(new.neurons.iter()).enumerate() {
+        if running.location != replacement.location {
+            return Err(format!("Neuron {} has moved", n));
+        }
+        let (running, replacement) = (&running.neuron.segments, &replacement.neuron.segments);
+        if running.len() != replacement.len() {
+            return Err(format!("Neuron {} has {} segments running but {} in the new scene", n, running.len(), replacement.len()));
+        }
+        for (a, b) in running.iter().zip(replacement.iter()) {
+            if (a.id, a.parent, a.type_) != (b.id, b.parent, b.type_) || (a.x, a.y, a.z, a.r) != (b.x, b.y, b.z, b.r) {
+                return Err(format!("Neuron {}: segment {} differs in its place, shape or type", n, a.id));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// For each running synapse, given by its segments, the index of the
+/// synapse in `new` that replaces it. Synapses between the same segments
+/// are paired in order.
+pub fn pair_synapses(running: &[(SegmentRef, SegmentRef)], new: &[(SegmentRef, SegmentRef)]) -> Result<Vec<usize>, String> {
+    if running.len() != new.len() {
+        return Err(format!("{} synapses running but {} in the new scene", running.len(), new.len()));
+    }
+    let mut unpaired: HashMap<(SegmentRef, SegmentRef), Vec<usize>> = HashMap::new();
+    for (i, pair) in new.iter().enumerate().rev() {
+        unpaired.entry(*pair).or_default().push(i);
+    }
+    running
+        .iter()
+        .map(|pair| {
+            unpaired
+                .get_mut(pair)
+                .and_then(|indices| indices.pop())
+                .ok_or(format!("No synapse from {:?} to {:?} in the new scene", pair.0, pair.1))
+        })
+        .collect()
+}
+
+/// Apply `Command::WarmRestart`.
+pub fn warm_restart_commands(
+    mut events: EventReader<Command>,
+    mut current_scene: ResMut<CurrentScene>,
+    mut timestamp: ResMut<Timestamp>,
+    mut step_count: ResMut<StepCount>,
+    mut run_clock: ResMut<RunClock>,
+    mut segments: Query<(&SceneSegment, &mut Membrane, &mut MembraneVoltage)>,
+    mut synapses: Query<&mut Synapse>,
+) {
+    for command in events.read() {
+        let Command::WarmRestart { scene, reset_state } = command else {
+            continue;
+        };
+        let errors = validate_scene(scene);
+        if !errors.is_empty() {
+            eprintln!("WarmRestart: invalid scene: {}", errors.join("; "));
+            continue;
+        }
+        if let Err(e) = check_same_topology(&current_scene.0, scene) {
+            eprintln!("WarmRestart: {}; load the scene instead", e);
+            continue;
+        }
+        let segment_ref = |neuron: usize, index: usize| SegmentRef { neuron, segment: scene.neurons[neuron].neuron.segments[index].id };
+        let new_pairs = scene
+            .synapses
+            .iter()
+            .map(|s| (segment_ref(s.pre_neuron, s.pre_segment), segment_ref(s.post_neuron, s.post_segment)))
+            .collect::<Vec<_>>();
+        let running_pairs = synapses
+            .iter()
+            .map(|s| Some((segments.get(s.pre_segment).ok()?.0 .0, segments.get(s.post_segment).ok()?.0 .0)))
+            .collect::<Option<Vec<_>>>();
+        let pairing = running_pairs
+            .ok_or("a synapse is on a despawned segment".to_string())
+            .and_then(|running| pair_synapses(&running, &new_pairs));
+        let new_synapses = scene
+            .synapses
+            .iter()
+            .map(|s| SynapseMembranes::deserialize(&s.synapse_membranes))
+            .collect::<Result<Vec<_>, _>>();
+        let (pairing, new_synapses) = match (pairing, new_synapses) {
+            (Ok(pairing), Ok(new_synapses)) => (pairing, new_synapses),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("WarmRestart: {}", e);
+                continue;
+            },
+        };
+
+        let types = scene
+            .neurons
+            .iter()
+            .map(|n| n.neuron.segments.iter().map(|s| (s.id, s.type_)).collect::<HashMap<_, _>>())
+            .collect::<Vec<_>>();
+        let distances = scene
+            .neurons
+            .iter()
+            .map(|n| {
+                if n.neuron.membranes.iter().any(density::has_rules) {
+                    paths_to_soma_microns(&n.neuron)
+                } else {
+                    HashMap::new()
+                }
+            })
+            .collect::<Vec<_>>();
+        for (SceneSegment(at), mut membrane, mut voltage) in &mut segments {
+            let Some(type_) = types.get(at.neuron).and_then(|t| t.get(&at.segment)) else {
+                continue;
+            };
+            let scene_neuron = &scene.neurons[at.neuron];
+            let serialized = &scene_neuron.neuron.membranes[type_ - 1];
+            let distance = distances[at.neuron].get(&at.segment).copied().unwrap_or(0.0);
+            let overridden = scene_neuron.membrane_overrides.iter().find(|o| o.segment as i32 == at.segment);
+            let mut rebuilt = match overridden {
+                Some(o) => Membrane::deserialize(&o.membrane),
+                None => density::membrane_at(serialized, distance).unwrap_or_else(|e| {
+                    eprintln!("WarmRestart: segment {:?}: {}", at, e);
+                    Membrane::deserialize(serialized)
+                }),
+            };
+            if *reset_state {
+                voltage.0 = INITIAL_VOLTAGE;
+            } else if let Err(e) = SegmentState::capture(*at, &membrane, &voltage, None).restore(&mut rebuilt, &mut voltage, None) {
+                eprintln!("WarmRestart: segment {:?} starts from fresh gates: {}", at, e);
+            }
+            *membrane = rebuilt;
+        }
+        for (mut synapse, index) in synapses.iter_mut().zip(pairing) {
+            let mut rebuilt = new_synapses[index].clone();
+            if !*reset_state {
+                let old = &synapse.synapse_membranes;
+                rebuilt.transmitter_concentrations = old.transmitter_concentrations.clone();
+                let gates = old.postsynaptic_receptors.iter().map(|r| GateMagnitudes::of(&r.membrane_channel.channel)).collect::<Vec<_>>();
+                let receptors = rebuilt.postsynaptic_receptors.iter_mut().map(|r| &mut r.membrane_channel.channel);
+                if let Err(e) = apply_all(&gates, receptors) {
+                    eprintln!("WarmRestart: a synapse's receptors start from fresh gates: {}", e);
+                }
+            }
+            synapse.synapse_membranes = rebuilt;
+        }
+
+        for (running, replacement) in current_scene.0.neurons.iter_mut().zip(scene.neurons.iter()) {
+            running.neuron.membranes = replacement.neuron.membranes.clone();
+        }
+        current_scene.0.synapses = scene.synapses.clone();
+        if *reset_state {
+            timestamp.0 = 0.0;
+            step_count.0 = 0;
+            run_clock.restart();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_synapses_between_the_same_segments_in_order() {
+        let at = |neuron, segment| SegmentRef { neuron, segment };
+        let (a, b, c) = (at(0, 1), at(1, 1), at(1, 2));
+        assert_eq!(pair_synapses(&[(a, b), (a, c), (a, b)], &[(a, c), (a, b), (a, b)]), Ok(vec![1, 0, 2]));
+        assert!(pair_synapses(&[(a, b)], &[(a, c)]).is_err());
+        assert!(pair_synapses(&[(a, b)], &[]).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! Watch expressions: quantities derived from the simulation by a
+//! user-entered formula, such as `g_Na(soma) * (Vm - E_Na)` or
+//! `Vm(probe2) - Vm(probe1)`.
+//!
+//! Formulas use the syntax of `crate::expr`. A quantity is sampled at a
+//! location by calling it with the location's name; a bare quantity is
+//! sampled at the one location the formula names, or at the first
+//! oscilloscope probe if it names none. `t` is the simulation time (ms).
+//!
+//! Quantities: `Vm` (mV), `g_K`, `g_Na`, `g_Ca`, `g_Cl` (open conductance,
+//! S/cm^2), `E_K`, `E_Na`, `E_Ca`, `E_Cl` (reversal potential, mV), and
+//! `K_i`, `Na_i`, ... and `K_o`, `Na_o`, ... (concentration inside and
+//! around the segment, mM).
+//!
+//! Locations: `probe1` to `probeN` (oscilloscope sources), `soma` (the
+//! soma of the first neuron), `selected` (the first selected segment), and
+//! `n<neuron>_<id>`, a segment by its neuron's index in the scene and its
+//! SWC id.
+//!
+//! Alarms are conditions in the same language that can also refer to
+//! watches by name, and to `rate(name)`, the rate (Hz) at which a watch
+//! rose through `SPIKE_THRESHOLD` over the last `RATE_WINDOW_MS`. An alarm
+//! fires when its condition becomes true, e.g. `Vm(n0_42) > -20` or
+//! `rate(v1) > 50`.
+
+use std::collections::VecDeque;
+
+use crate::analysis::SPIKE_THRESHOLD;
+use crate::expr::{self, Expr};
+use crate::serialize::SegmentRef;
+
+/// Samples kept for plotting.
+pub const HISTORY_LEN: usize = 2000;
+
+/// The window over which `rate(name)` counts spikes (ms).
+pub const RATE_WINDOW_MS: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ion {
+    K,
+    Na,
+    Ca,
+    Cl,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Quantity {
+    Voltage,
+    Conductance(Ion),
+    Reversal(Ion),
+    Intracellular(Ion),
+    Extracellular(Ion),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    /// An oscilloscope source, numbered from 1 as in the oscilloscope.
+    Probe(usize),
+    Soma,
+    Selected,
+    Segment(SegmentRef),
+}
+
+impl Ion {
+    fn parse(name: &str) -> Option<Ion> {
+        match name {
+            "K" => Some(Ion::K),
+            "Na" => Some(Ion::Na),
+            "Ca" => Some(Ion::Ca),
+            "Cl" => Some(Ion::Cl),
+            _ => None,
+        }
+    }
+}
+
+impl Quantity {
+    pub fn parse(name: &str) -> Option<Quantity> {
+        match name {
+            "Vm" | "V" => Some(Quantity::Voltage),
+            _ => {
+                if let Some(ion) = name.strip_prefix("g_") {
+                    Ion::parse(ion).map(Quantity::Conductance)
+                } else if let Some(ion) = name.strip_suffix("_i") {
+                    Ion::parse(ion).map(Quantity::Intracellular)
+                } else if let Some(ion) = name.strip_suffix("_o") {
+                    Ion::parse(ion).map(Quantity::Extracellular)
+                } else {
+                    Ion::parse(name.strip_prefix("E_")?).map(Quantity::Reversal)
+                }
+            },
+        }
+    }
+}
+
+impl Location {
+    pub fn parse(name: &str) -> Option<Location> {
+        match name {
+            "soma" => Some(Location::Soma),
+            "selected" => Some(Location::Selected),
+            _ => {
+                if let Some(n) = name.strip_prefix("probe") {
+                    return n.parse().ok().filter(|n| *n > 0).map(Location::Probe);
+                }
+                let (neuron, segment) = name.strip_prefix('n')?.split_once('_')?;
+                Some(Location::Segment(SegmentRef { neuron: neuron.parse().ok()?, segment: segment.parse().ok()? }))
+            },
+        }
+    }
+}
+
+/// A compiled watch expression and its recent values.
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub name: String,
+    pub source: String,
+    expr: Expr,
+    /// The sampled quantities, indexed by the variables of `expr`.
+    samples: Vec<(Quantity, Location)>,
+    /// `(t, value)` pairs, oldest first; times in ms.
+    pub history: VecDeque<(f32, f32)>,
+    /// Fire when the value rises through this.
+    pub threshold: Option<f32>,
+}
+
+fn sample_variable(index: usize) -> String {
+    format!("#{}", index)
+}
+
+impl Watch {
+    pub fn new(name: &str, source: &str) -> Result<Watch, String> {
+        Watch::with_references(name, source, &[])
+    }
+
+    /// A formula that may also refer to the watches named in `references`,
+    /// and to their firing rates; see `Alarm`.
+    pub fn with_references(name: &str, source: &str, references: &[String]) -> Result<Watch, String> {
+        let expr = expr::parse(source)?;
+        let mut locations = vec![];
+        collect_locations(&expr, &mut locations)?;
+        // Two or more named locations leave bare quantities ambiguous.
+        let default_location = match locations.as_slice() {
+            [] => Some(Location::Probe(1)),
+            [location] => Some(*location),
+            _ => None,
+        };
+        let mut samples = vec![];
+        let expr = rewrite(expr, &Scope { default_location, references }, &mut samples)?;
+        Ok(Watch {
+            name: name.to_string(),
+            source: source.to_string(),
+            expr,
+            samples,
+            history: VecDeque::new(),
+            threshold: None,
+        })
+    }
+
+    /// The quantities to sample before calling `eval`.
+    pub fn samples(&self) -> &[(Quantity, Location)] {
+        &self.samples
+    }
+
+    /// Evaluate at time `t_ms`, given the values of `samples()` in order,
+    /// and the referenced watches.
+    pub fn eval(&self, t_ms: f32, values: &[f32], watches: &[&Watch]) -> Result<f32, String> {
+        let lookup = |name: &str| match name {
+            "t" => Some(t_ms),
+            _ => {
+                if let Some(i) = name.strip_prefix('#') {
+                    return i.parse::<usize>().ok().and_then(|i| values.get(i).copied());
+                }
+                if let Some(watch) = name.strip_prefix("rate ") {
+                    return watches.iter().find(|w| w.name == watch).map(|w| w.firing_rate(t_ms));
+                }
+                watches.iter().find(|w| w.name == name)?.latest()
+            },
+        };
+        self.expr.eval(&lookup)
+    }
+
+    pub fn latest(&self) -> Option<f32> {
+        self.history.back().map(|(_, v)| *v)
+    }
+
+    /// Upward crossings of `SPIKE_THRESHOLD` per second over the
+    /// `RATE_WINDOW_MS` before `t_ms`.
+    pub fn firing_rate(&self, t_ms: f32) -> f32 {
+        let recent = self.history.iter().filter(|(t, _)| *t > t_ms - RATE_WINDOW_MS).map(|(_, v)| *v).collect::<Vec<_>>();
+        let spikes = recent.windows(2).filter(|w| w[0] < SPIKE_THRESHOLD.0 && w[1] >= SPIKE_THRESHOLD.0).count();
+        spikes as f32 * 1000.0 / RATE_WINDOW_MS
+    }
+
+    /// Append a value, returning whether it rose through the threshold.
+    pub fn push(&mut self, t_ms: f32, value: f32) -> bool {
+        let previous = self.latest();
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back((t_ms, value));
+        match (self.threshold, previous) {
+            (Some(threshold), Some(previous)) => previous < threshold && value >= threshold,
+            _ => false,
+        }
+    }
+}
+
+/// A condition over watches and sampled quantities.
+#[derive(Clone, Debug)]
+pub struct Alarm {
+    pub condition: Watch,
+    pub pause: bool,
+    pub log: bool,
+    /// URL to POST a JSON description of the alarm to.
+    pub webhook: Option<String>,
+    /// Times (ms) the alarm fired.
+    pub fired: Vec<f32>,
+    active: bool,
+}
+
+impl Alarm {
+    /// An alarm on `condition`, which may refer to the watches named in
+    /// `watch_names`.
+    pub fn new(name: &str, condition: &str, watch_names: &[String]) -> Result<Alarm, String> {
+        Ok(Alarm {
+            condition: Watch::with_references(name, condition, watch_names)?,
+            pause: true,
+            log: true,
+            webhook: None,
+            fired: vec![],
+            active: false,
+        })
+    }
+
+    /// Record the condition's value at `t_ms`, returning whether the alarm
+    /// fires: it does when the condition becomes true.
+    pub fn update(&mut self, t_ms: f32, value: f32) -> bool {
+        let was_active = std::mem::replace(&mut self.active, value != 0.0);
+        let fires = self.active && !was_active;
+        if fires {
+            self.fired.push(t_ms);
+        }
+        fires
+    }
+}
+
+fn collect_locations(expr: &Expr, locations: &mut Vec<Location>) -> Result<(), String> {
+    match expr {
+        Expr::Call(name, args) if Quantity::parse(name).is_some() => {
+            let location = match args.as_slice() {
+                [Expr::Variable(location)] => Location::parse(location).ok_or(format!("Unknown location {}", location))?,
+                _ => return Err(format!("{} takes a location, e.g. {}(soma)", name, name)),
+            };
+            if !locations.contains(&location) {
+                locations.push(location);
+            }
+        },
+        Expr::Call(_, args) => {
+            for arg in args {
+                collect_locations(arg, locations)?;
+            }
+        },
+        Expr::Neg(e) | Expr::Not(e) => collect_locations(e, locations)?,
+        Expr::Binary(_, a, b) => {
+            collect_locations(a, locations)?;
+            collect_locations(b, locations)?;
+        },
+        Expr::Number(_) | Expr::Variable(_) => {},
+    }
+    Ok(())
+}
+
+struct Scope<'a> {
+    /// Where bare quantities are sampled, unless that is ambiguous.
+    default_location: Option<Location>,
+    /// Watches that may be referred to by name.
+    references: &'a [String],
+}
+
+/// Replace sampled quantities with variables indexing `samples`, and
+/// firing rates with variables naming their watch.
+fn rewrite(expr: Expr, scope: &Scope, samples: &mut Vec<(Quantity, Location)>) -> Result<Expr, String> {
+    let mut sample = |quantity: Quantity, location: Location| {
+        let index = samples.iter().position(|s| *s == (quantity, location)).unwrap_or_else(|| {
+            samples.push((quantity, location));
+            samples.len() - 1
+        });
+        Expr::Variable(sample_variable(index))
+    };
+    Ok(match expr {
+        Expr::Variable(name) if name == "t" || scope.references.contains(&name) => Expr::Variable(name),
+        Expr::Variable(name) => match (Quantity::parse(&name), scope.default_location) {
+            (Some(quantity), Some(location)) => sample(quantity, location),
+            (Some(_), None) => return Err(format!("{} needs a location, e.g. {}(soma)", name, name)),
+            (None, _) => return Err(format!("Unknown variable {}", name)),
+        },
+        Expr::Call(name, args) => match (Quantity::parse(&name), args.as_slice()) {
+            (Some(quantity), [Expr::Variable(location)]) => {
+                sample(quantity, Location::parse(location).ok_or(format!("Unknown location {}", location))?)
+            },
+            (None, [Expr::Variable(watch)]) if name == "rate" && scope.references.contains(watch) => {
+                Expr::Variable(format!("rate {}", watch))
+            },
+            _ => Expr::Call(name, args.into_iter().map(|a| rewrite(a, scope, samples)).collect::<Result<_, _>>()?),
+        },
+        Expr::Neg(e) => Expr::Neg(Box::new(rewrite(*e, scope, samples)?)),
+        Expr::Not(e) => Expr::Not(Box::new(rewrite(*e, scope, samples)?)),
+        Expr::Binary(op, a, b) => Expr::Binary(
+            op,
+            Box::new(rewrite(*a, scope, samples)?),
+            Box::new(rewrite(*b, scope, samples)?),
+        ),
+        Expr::Number(n) => Expr::Number(n),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_quantities_take_the_named_location() {
+        let watch = Watch::new("I_Na", "g_Na(soma) * (Vm - E_Na)").unwrap();
+        assert_eq!(
+            watch.samples(),
+            &[
+                (Quantity::Conductance(Ion::Na), Location::Soma),
+                (Quantity::Voltage, Location::Soma),
+                (Quantity::Reversal(Ion::Na), Location::Soma),
+            ]
+        );
+        assert_eq!(watch.eval(0.0, &[0.1, -20.0, 50.0], &[]), Ok(-7.0));
+
+        let difference = Watch::new("dV", "Vm(probe2) - Vm(n0_12)").unwrap();
+        let at = SegmentRef { neuron: 0, segment: 12 };
+        assert_eq!(difference.samples()[1], (Quantity::Voltage, Location::Segment(at)));
+        assert!(Watch::new("bad", "Vm(probe2) - Vm(probe1) + E_K").is_err());
+        assert!(Watch::new("bad", "Vm(axon)").is_err());
+        assert!(Watch::new("bad", "x + 1").is_err());
+        assert_eq!(Watch::new("v", "Vm + t").unwrap().samples(), &[(Quantity::Voltage, Location::Probe(1))]);
+    }
+
+    #[test]
+    fn concentrations_are_named_by_side() {
+        assert_eq!(Quantity::parse("Na_i"), Some(Quantity::Intracellular(Ion::Na)));
+        assert_eq!(Quantity::parse("K_o"), Some(Quantity::Extracellular(Ion::K)));
+        assert_eq!(Quantity::parse("X_i"), None);
+    }
+
+    #[test]
+    fn fires_on_rising_threshold_crossings() {
+        let mut watch = Watch::new("v", "Vm").unwrap();
+        watch.threshold = Some(0.0);
+        let fired = [-10.0, 5.0, 10.0, -5.0, 0.0].map(|v| watch.push(0.0, v));
+        assert_eq!(fired, [false, true, false, false, true]);
+    }
+
+    #[test]
+    fn alarms_fire_when_their_condition_becomes_true() {
+        let mut v = Watch::new("v", "Vm").unwrap();
+        for (i, value) in [-70.0, 10.0, -70.0, 10.0, -70.0].iter().enumerate() {
+            v.push(500.0 + i as f32, *value);
+        }
+        let names = ["v".to_string()];
+        let mut alarm = Alarm::new("fast", "rate(v) > 1 || v > 0", &names).unwrap();
+        assert!(alarm.condition.samples().is_empty());
+        assert_eq!(v.firing_rate(504.0), 2.0);
+
+        let value = alarm.condition.eval(504.0, &[], &[&v]).unwrap();
+        assert!(alarm.update(504.0, value));
+        assert!(!alarm.update(505.0, value));
+        assert!(!alarm.update(506.0, 0.0) && alarm.update(507.0, 1.0));
+        assert_eq!(alarm.fired.len(), 2);
+        assert!(Alarm::new("bad", "rate(w) > 1", &names).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Notifications POSTed as JSON to a user-configured URL, such as a Slack
+//! incoming webhook, for runs nobody is watching.
+
+use ehttp::{fetch, Request};
+use serde_json::json;
+
+/// POST `body` to `url`, reporting failures on stderr.
+pub fn post_json(url: &str, body: &serde_json::Value) {
+    let mut request = Request::post(url, body.to_string().into_bytes());
+    request.headers.insert("Content-Type", "application/json");
+    let url = url.to_string();
+    fetch(request, move |response| match response {
+        Ok(r) if r.ok => {},
+        Ok(r) => eprintln!("Webhook {} returned {} {}", url, r.status, r.status_text),
+        Err(e) => eprintln!("Webhook {} failed: {}", url, e),
+    });
+}
+
+/// An alarm that fired at simulation time `t_ms`. `text` makes it readable
+/// in Slack.
+pub fn alarm_body(name: &str, condition: &str, t_ms: f32) -> serde_json::Value {
+    json!({
+        "text": format!("Alarm {} ({}) fired at {:.2} ms", name, condition, t_ms),
+        "alarm": name,
+        "condition": condition,
+        "t_ms": t_ms,
+    })
+}
+
+/// A run that reached its simulated duration.
+pub fn run_finished_body(t_ms: f32, steps: u64, wall_seconds: f64) -> serde_json::Value {
+    json!({
+        "text": format!(
+            "Run finished: {:.1} ms simulated in {} steps, {:.1} s wall clock ({:.3}x real time)",
+            t_ms, steps, wall_seconds, t_ms as f64 / 1000.0 / wall_seconds.max(f64::EPSILON),
+        ),
+        "t_ms": t_ms,
+        "steps": steps,
+        "wall_seconds": wall_seconds,
+    })
+}
+
+/// A run whose membrane potentials left the plausible range.
+pub fn unstable_body(t_ms: f32, unstable_segments: usize) -> serde_json::Value {
+    json!({
+        "text": format!(
+            "Simulation unstable at {:.2} ms: {} segments out of range; try a shorter step",
+            t_ms, unstable_segments,
+        ),
+        "t_ms": t_ms,
+        "unstable_segments": unstable_segments,
+    })
+}
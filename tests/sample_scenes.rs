@@ -0,0 +1,184 @@
+//! Every bundled sample scene, run headless for 100 ms at the step the
+//! step advisor recommends: membrane potentials stay finite and within
+//! physiological bounds, and somas and stimulated segments fire as often
+//! as they were measured to. Catches regressions in spawning and numerics
+//! with a plain `cargo test`.
+//!
+//! Spike counts are checked against narrow ranges around the expected
+//! count rather than exact counts, so that changes to the numerics that
+//! keep the dynamics intact pass while a neuron that stops firing, or
+//! fires twice as often, does not.
+
+use std::ops::RangeInclusive;
+
+use nb_sim::analysis::Trace;
+use nb_sim::dimension::{MilliVolts, SimulationStepSeconds, StepsPerFrame};
+use nb_sim::headless::HeadlessSimulation;
+use nb_sim::integrations::grace::{sample, soma};
+use nb_sim::integrations::swc;
+use nb_sim::serialize::{self, Scene, SegmentRef};
+
+const DURATION_SECONDS: f32 = 0.1;
+/// How often the somas are sampled, often enough to see every spike.
+const SAMPLE_SECONDS: f32 = 1e-4;
+const VOLTAGE_BOUNDS_MV: RangeInclusive<f32> = -150.0..=100.0;
+const SPIKE_THRESHOLD: MilliVolts = MilliVolts(0.0);
+/// The axon segment `sample::scene` stimulates, and the segment further
+/// along the same axon its synapse leaves from (SWC ids).
+const STIMULATED_AXON: i32 = 100;
+const PRESYNAPTIC_TERMINAL: i32 = 1050;
+
+/// Where spikes are counted: a neuron's soma, or one of its segments by
+/// SWC id.
+#[derive(Clone, Copy, Debug)]
+enum Site {
+    Soma(usize),
+    Segment(usize, i32),
+}
+
+/// Run `scene` and check it, expecting each site to fire a number of
+/// times in its range.
+fn check(name: &str, scene: Scene, expected_spikes: &[(Site, RangeInclusive<usize>)]) {
+    let sites = expected_spikes
+        .iter()
+        .map(|(site, _)| match *site {
+            Site::Soma(n) => SegmentRef {
+                neuron: n,
+                segment: soma(&scene.neurons[n].neuron).unwrap_or_else(|| panic!("{}: neuron {} has no soma", name, n)).id,
+            },
+            Site::Segment(neuron, segment) => SegmentRef { neuron, segment },
+        })
+        .collect::<Vec<_>>();
+    let mut simulation = HeadlessSimulation::new(scene, 1e-6, 1).unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+    let advice = simulation.step_advice().unwrap_or_else(|| panic!("{}: no step advice", name));
+    let steps_per_frame = (SAMPLE_SECONDS / advice.max_step).ceil() as usize;
+    simulation.world_mut().insert_resource(SimulationStepSeconds(advice.max_step));
+    simulation.world_mut().insert_resource(StepsPerFrame(steps_per_frame));
+
+    let mut traces = sites.iter().map(|at| Trace::new(format!("n{}_{}", at.neuron, at.segment))).collect::<Vec<_>>();
+    loop {
+        let t = simulation.time();
+        for (at, v) in simulation.voltages() {
+            assert!(
+                v.is_finite() && VOLTAGE_BOUNDS_MV.contains(&v),
+                "{}: segment {:?} is at {} mV at {:.2} ms",
+                name,
+                at,
+                v,
+                t * 1000.0,
+            );
+            for (site, trace) in sites.iter().zip(traces.iter_mut()) {
+                if *site == at {
+                    trace.push(t, v);
+                }
+            }
+        }
+        if t >= DURATION_SECONDS {
+            break;
+        }
+        simulation.step();
+    }
+
+    for ((trace, (site, expected)), at) in traces.iter().zip(expected_spikes).zip(&sites) {
+        assert!(!trace.is_empty(), "{}: no segment {:?}", name, at);
+        let spikes = trace.spike_times(&SPIKE_THRESHOLD).len();
+        assert!(expected.contains(&spikes), "{}: {:?} fired {} times, expected {:?}", name, site, spikes, expected);
+    }
+}
+
+/// The sample neuron's soma alone, as one compartment, driven well above
+/// threshold for the first half of the run.
+fn stimulated_soma() -> Scene {
+    let neuron = sample::neuron();
+    let soma = soma(&neuron).expect("The sample neuron should have a soma").clone();
+    Scene {
+        neurons: vec![serialize::SceneNeuron {
+            neuron: serialize::Neuron { segments: vec![soma.clone()], membranes: neuron.membranes.clone() },
+            location: serialize::Location { x_mm: 0.0, y_mm: 0.0, z_mm: 0.0 },
+            stimulator_segments: vec![serialize::StimulatorSegment {
+                stimulator: serialize::Stimulator {
+                    envelope: serialize::Envelope { period_sec: 0.1, onset_sec: 0.0, offset_sec: 0.05 },
+                    current_shape: serialize::CurrentShape::SquareWave {
+                        on_current_uamps_per_square_cm: 20.0,
+                        off_current_uamps_per_square_cm: 0.0,
+                    },
+                },
+                segment: soma.id as u32,
+            }],
+            tag: None,
+            thermal_segments: vec![],
+            noise: None,
+            membrane_overrides: vec![],
+        }],
+        synapses: vec![],
+        constants: None,
+        lights: vec![],
+        projectors: vec![],
+        seed: None,
+    }
+}
+
+/// The whole sample neuron with no stimulation, which should stay at rest.
+fn resting_neuron() -> Scene {
+    let mut scene = stimulated_soma();
+    scene.neurons[0].neuron = sample::neuron();
+    scene.neurons[0].stimulator_segments.clear();
+    scene
+}
+
+/// The bundled SWC reconstruction, read as a user's file would be, with
+/// the sample neuron's membranes and no stimulation.
+fn resting_swc_neuron() -> Scene {
+    let segments = swc::segments(include_str!("../sample_data/H17.03.010.11.13.01_656411100_m.swc"))
+        .expect("The bundled SWC file should parse");
+    let mut scene = resting_neuron();
+    scene.neurons[0].neuron.segments = segments;
+    scene
+}
+
+/// 10 µA/cm² on one thin axon segment barely moves it from rest, so
+/// neither neuron fires.
+#[test]
+fn sample_scene_json() {
+    check("sample_scene.json", sample::scene2(), &[(Site::Soma(0), 0..=0), (Site::Soma(1), 0..=0)]);
+}
+
+/// 200 µA/cm² for 69 ms drives the stimulated axon segment to fire every
+/// 23 ms or so. The spikes reach the synapse's presynaptic terminal
+/// further along the axon, but the axon is too thin to charge the soma
+/// past threshold. The second neuron fires only on the input of its one
+/// synapse, onto a dendrite wide enough to carry each spike into its soma.
+#[test]
+fn sample_scene() {
+    check(
+        "sample::scene",
+        sample::scene(),
+        &[
+            (Site::Segment(0, STIMULATED_AXON), 2..=4),
+            (Site::Segment(0, PRESYNAPTIC_TERMINAL), 2..=4),
+            (Site::Soma(0), 0..=0),
+            (Site::Soma(1), 2..=4),
+        ],
+    );
+}
+
+#[test]
+fn swc_neuron_json_rests() {
+    check("swc_neuron.json", resting_neuron(), &[(Site::Soma(0), 0..=0)]);
+}
+
+/// Every segment starts at the same potential, away from where the
+/// reconstruction's membranes rest, and the soma fires once as the tree
+/// settles, at about 47 ms; it then stays quiet.
+#[test]
+fn swc_file_settles() {
+    check("H17.03.010.11.13.01_656411100_m.swc", resting_swc_neuron(), &[(Site::Soma(0), 1..=1)]);
+}
+
+/// Squid-axon densities at 20 µA/cm² fire at about 120 Hz, so 6 spikes
+/// over the 50 ms of current.
+#[test]
+fn stimulated_soma_fires() {
+    check("stimulated soma", stimulated_soma(), &[(Site::Soma(0), 5..=7)]);
+}